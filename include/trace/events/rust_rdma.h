@@ -0,0 +1,99 @@
+/* SPDX-License-Identifier: GPL-2.0 */
+/*
+ * Tracepoints of the Rust RDMA abstractions.
+ *
+ * The events are created by rust/helpers_rdma.c and declared on the Rust side with
+ * `kernel::declare_trace!`, see rust/kernel/ib/trace.rs.
+ */
+
+#undef TRACE_SYSTEM
+#define TRACE_SYSTEM rust_rdma
+
+#if !defined(_TRACE_RUST_RDMA_H) || defined(TRACE_HEADER_MULTI_READ)
+#define _TRACE_RUST_RDMA_H
+
+#include <linux/tracepoint.h>
+#include <rdma/ib_verbs.h>
+
+TRACE_DEFINE_ENUM(IB_QPS_RESET);
+TRACE_DEFINE_ENUM(IB_QPS_INIT);
+TRACE_DEFINE_ENUM(IB_QPS_RTR);
+TRACE_DEFINE_ENUM(IB_QPS_RTS);
+TRACE_DEFINE_ENUM(IB_QPS_SQD);
+TRACE_DEFINE_ENUM(IB_QPS_SQE);
+TRACE_DEFINE_ENUM(IB_QPS_ERR);
+
+#define show_qp_state(state)				\
+	__print_symbolic(state,				\
+		{ IB_QPS_RESET,	"RESET" },		\
+		{ IB_QPS_INIT,	"INIT" },		\
+		{ IB_QPS_RTR,	"RTR" },		\
+		{ IB_QPS_RTS,	"RTS" },		\
+		{ IB_QPS_SQD,	"SQD" },		\
+		{ IB_QPS_SQE,	"SQE" },		\
+		{ IB_QPS_ERR,	"ERR" })
+
+DECLARE_EVENT_CLASS(rxe_pkt,
+	TP_PROTO(int ifindex, u32 qpn, u8 opcode, u32 psn, u32 len),
+
+	TP_ARGS(ifindex, qpn, opcode, psn, len),
+
+	TP_STRUCT__entry(
+		__field(int, ifindex)
+		__field(u32, qpn)
+		__field(u8, opcode)
+		__field(u32, psn)
+		__field(u32, len)
+	),
+
+	TP_fast_assign(
+		__entry->ifindex = ifindex;
+		__entry->qpn = qpn;
+		__entry->opcode = opcode;
+		__entry->psn = psn;
+		__entry->len = len;
+	),
+
+	TP_printk("ifindex=%d qpn=%#x opcode=%#x psn=%u len=%u",
+		  __entry->ifindex, __entry->qpn, __entry->opcode, __entry->psn,
+		  __entry->len)
+);
+
+DEFINE_EVENT(rxe_pkt, rxe_send_pkt,
+	TP_PROTO(int ifindex, u32 qpn, u8 opcode, u32 psn, u32 len),
+	TP_ARGS(ifindex, qpn, opcode, psn, len)
+);
+
+DEFINE_EVENT(rxe_pkt, rxe_recv_pkt,
+	TP_PROTO(int ifindex, u32 qpn, u8 opcode, u32 psn, u32 len),
+	TP_ARGS(ifindex, qpn, opcode, psn, len)
+);
+
+TRACE_EVENT(qp_state_change,
+	TP_PROTO(const struct ib_qp *qp, int old_state, int new_state),
+
+	TP_ARGS(qp, old_state, new_state),
+
+	TP_STRUCT__entry(
+		__array(char, dev, IB_DEVICE_NAME_MAX)
+		__field(u32, qpn)
+		__field(int, old_state)
+		__field(int, new_state)
+	),
+
+	TP_fast_assign(
+		strscpy(__entry->dev, qp->device->name, IB_DEVICE_NAME_MAX);
+		__entry->qpn = qp->qp_num;
+		__entry->old_state = old_state;
+		__entry->new_state = new_state;
+	),
+
+	TP_printk("dev=%s qpn=%#x %s -> %s",
+		  __entry->dev, __entry->qpn, show_qp_state(__entry->old_state),
+		  show_qp_state(__entry->new_state))
+);
+
+#endif /* _TRACE_RUST_RDMA_H */
+
+/* This part must be outside protection */
+#include <trace/define_trace.h>
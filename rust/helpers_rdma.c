@@ -0,0 +1,585 @@
+// SPDX-License-Identifier: GPL-2.0
+/*
+ * Non-trivial C macros and inline functions used by the RDMA abstractions.
+ *
+ * The content of this file must be appended to rust/helpers.c; as for the other helpers the
+ * `rust_helper_` prefix is stripped when generating the Rust bindings.
+ *
+ * Sorted alphabetically. Only built with CONFIG_RUST_RDMA, see rust/Kconfig.rdma.
+ */
+
+#ifdef CONFIG_RUST_RDMA
+
+#include <linux/auxiliary_bus.h>
+#include <linux/completion.h>
+#include <linux/cpumask.h>
+#include <linux/crc32.h>
+#include <linux/dma-mapping.h>
+#include <linux/dma-resv.h>
+#include <linux/highmem.h>
+#include <linux/igmp.h>
+#include <linux/inetdevice.h>
+#include <linux/ip.h>
+#include <linux/ipv6.h>
+#include <linux/jiffies.h>
+#include <linux/mlx5/driver.h>
+#include <linux/mutex.h>
+#include <linux/netdevice.h>
+#include <linux/pci.h>
+#include <linux/percpu.h>
+#include <linux/rcupdate.h>
+#include <linux/scatterlist.h>
+#include <linux/sched.h>
+#include <linux/skbuff.h>
+#include <linux/timer.h>
+#include <linux/udp.h>
+#include <linux/workqueue.h>
+#include <net/dst.h>
+#include <net/ipv6_stubs.h>
+#include <net/net_namespace.h>
+#include <net/netlink.h>
+#include <net/netns/generic.h>
+#include <rdma/ib_sa.h>
+#include <rdma/ib_umem.h>
+#include <rdma/ib_verbs.h>
+#include <rdma/restrack.h>
+
+#define CREATE_TRACE_POINTS
+#include <trace/events/rust_rdma.h>
+
+struct sk_buff *rust_helper_alloc_skb(unsigned int size, gfp_t priority)
+{
+	return alloc_skb(size, priority);
+}
+EXPORT_SYMBOL_GPL(rust_helper_alloc_skb);
+
+void *rust_helper_auxiliary_get_drvdata(struct auxiliary_device *auxdev)
+{
+	return auxiliary_get_drvdata(auxdev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_auxiliary_get_drvdata);
+
+void rust_helper_auxiliary_set_drvdata(struct auxiliary_device *auxdev, void *data)
+{
+	auxiliary_set_drvdata(auxdev, data);
+}
+EXPORT_SYMBOL_GPL(rust_helper_auxiliary_set_drvdata);
+
+void rust_helper_cond_resched(void)
+{
+	cond_resched();
+}
+EXPORT_SYMBOL_GPL(rust_helper_cond_resched);
+
+bool rust_helper_cpu_possible(unsigned int cpu)
+{
+	return cpu_possible(cpu);
+}
+EXPORT_SYMBOL_GPL(rust_helper_cpu_possible);
+
+u32 rust_helper_crc32_le(u32 crc, const unsigned char *p, size_t len)
+{
+	return crc32_le(crc, p, len);
+}
+EXPORT_SYMBOL_GPL(rust_helper_crc32_le);
+
+void rust_helper_dev_hold(struct net_device *dev)
+{
+	dev_hold(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dev_hold);
+
+const char *rust_helper_dev_name(const struct device *dev)
+{
+	return dev_name(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dev_name);
+
+struct net *rust_helper_dev_net(const struct net_device *dev)
+{
+	return dev_net(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dev_net);
+
+void rust_helper_dev_put(struct net_device *dev)
+{
+	dev_put(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dev_put);
+
+int rust_helper_dma_resv_lock(struct dma_resv *obj, struct ww_acquire_ctx *ctx)
+{
+	return dma_resv_lock(obj, ctx);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dma_resv_lock);
+
+void rust_helper_dma_resv_unlock(struct dma_resv *obj)
+{
+	dma_resv_unlock(obj);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dma_resv_unlock);
+
+int rust_helper_dma_set_mask_and_coherent(struct device *dev, u64 mask)
+{
+	return dma_set_mask_and_coherent(dev, mask);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dma_set_mask_and_coherent);
+
+struct dst_entry *rust_helper_dst_clone(struct dst_entry *dst)
+{
+	return dst_clone(dst);
+}
+EXPORT_SYMBOL_GPL(rust_helper_dst_clone);
+
+u64 rust_helper_get_jiffies_64(void)
+{
+	return get_jiffies_64();
+}
+EXPORT_SYMBOL_GPL(rust_helper_get_jiffies_64);
+
+struct net *rust_helper_genl_info_net(const struct genl_info *info)
+{
+	return genl_info_net(info);
+}
+EXPORT_SYMBOL_GPL(rust_helper_genl_info_net);
+
+bool rust_helper_ib_device_try_get(struct ib_device *dev)
+{
+	return ib_device_try_get(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_device_try_get);
+
+int rust_helper_ib_dma_map_sg(struct ib_device *dev, struct scatterlist *sg, int nents,
+			      enum dma_data_direction direction)
+{
+	return ib_dma_map_sg(dev, sg, nents, direction);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_dma_map_sg);
+
+u64 rust_helper_ib_dma_map_single(struct ib_device *dev, void *cpu_addr, size_t size,
+				  enum dma_data_direction direction)
+{
+	return ib_dma_map_single(dev, cpu_addr, size, direction);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_dma_map_single);
+
+int rust_helper_ib_dma_mapping_error(struct ib_device *dev, u64 dma_addr)
+{
+	return ib_dma_mapping_error(dev, dma_addr);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_dma_mapping_error);
+
+void rust_helper_ib_dma_sync_single_for_cpu(struct ib_device *dev, u64 addr, size_t size,
+					     enum dma_data_direction dir)
+{
+	ib_dma_sync_single_for_cpu(dev, addr, size, dir);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_dma_sync_single_for_cpu);
+
+void rust_helper_ib_dma_sync_single_for_device(struct ib_device *dev, u64 addr, size_t size,
+						enum dma_data_direction dir)
+{
+	ib_dma_sync_single_for_device(dev, addr, size, dir);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_dma_sync_single_for_device);
+
+void rust_helper_ib_dma_unmap_sg(struct ib_device *dev, struct scatterlist *sg, int nents,
+				 enum dma_data_direction direction)
+{
+	ib_dma_unmap_sg(dev, sg, nents, direction);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_dma_unmap_sg);
+
+void rust_helper_ib_dma_unmap_single(struct ib_device *dev, u64 addr, size_t size,
+				     enum dma_data_direction direction)
+{
+	ib_dma_unmap_single(dev, addr, size, direction);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_dma_unmap_single);
+
+size_t rust_helper_ib_umem_num_dma_blocks(struct ib_umem *umem, unsigned long pgsz)
+{
+	return ib_umem_num_dma_blocks(umem, pgsz);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_umem_num_dma_blocks);
+
+int rust_helper_ib_umem_offset(struct ib_umem *umem)
+{
+	return ib_umem_offset(umem);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_umem_offset);
+
+bool rust_helper_ib_uses_virt_dma(struct ib_device *dev)
+{
+	return ib_uses_virt_dma(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ib_uses_virt_dma);
+
+struct in_device *rust_helper_in_dev_get(const struct net_device *dev)
+{
+	return in_dev_get(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_in_dev_get);
+
+void rust_helper_in_dev_put(struct in_device *idev)
+{
+	in_dev_put(idev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_in_dev_put);
+
+void rust_helper_init_completion(struct completion *x)
+{
+	init_completion(x);
+}
+EXPORT_SYMBOL_GPL(rust_helper_init_completion);
+
+void rust_helper_init_delayed_work(struct delayed_work *dwork, work_func_t func)
+{
+	INIT_DELAYED_WORK(dwork, func);
+}
+EXPORT_SYMBOL_GPL(rust_helper_init_delayed_work);
+
+void rust_helper_init_work(struct work_struct *work, work_func_t func)
+{
+	INIT_WORK(work, func);
+}
+EXPORT_SYMBOL_GPL(rust_helper_init_work);
+
+struct iphdr *rust_helper_ip_hdr(const struct sk_buff *skb)
+{
+	return ip_hdr(skb);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ip_hdr);
+
+void rust_helper_ip_mc_dec_group(struct in_device *in_dev, __be32 addr)
+{
+	ip_mc_dec_group(in_dev, addr);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ip_mc_dec_group);
+
+struct dst_entry *rust_helper_ipv6_dst_lookup_flow(struct net *net, const struct sock *sk,
+						   struct flowi6 *fl6)
+{
+	return ipv6_stub->ipv6_dst_lookup_flow(net, sk, fl6, NULL);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ipv6_dst_lookup_flow);
+
+struct ipv6hdr *rust_helper_ipv6_hdr(const struct sk_buff *skb)
+{
+	return ipv6_hdr(skb);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ipv6_hdr);
+
+void *rust_helper_kmalloc(size_t size, gfp_t flags)
+{
+	return kmalloc(size, flags);
+}
+EXPORT_SYMBOL_GPL(rust_helper_kmalloc);
+
+void *rust_helper_kmap_local_page(struct page *page)
+{
+	return kmap_local_page(page);
+}
+EXPORT_SYMBOL_GPL(rust_helper_kmap_local_page);
+
+void rust_helper_kunmap_local(const void *addr)
+{
+	kunmap_local(addr);
+}
+EXPORT_SYMBOL_GPL(rust_helper_kunmap_local);
+
+unsigned int rust_helper_ll_reserved_space(const struct net_device *dev)
+{
+	return LL_RESERVED_SPACE(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_ll_reserved_space);
+
+#ifdef CONFIG_RUST_RDMA_MLX5
+int rust_helper_mlx5_db_alloc(struct mlx5_core_dev *dev, struct mlx5_db *db)
+{
+	return mlx5_db_alloc(dev, db);
+}
+EXPORT_SYMBOL_GPL(rust_helper_mlx5_db_alloc);
+#endif
+
+unsigned long rust_helper_msecs_to_jiffies(const unsigned int m)
+{
+	return msecs_to_jiffies(m);
+}
+EXPORT_SYMBOL_GPL(rust_helper_msecs_to_jiffies);
+
+void rust_helper_mutex_lock(struct mutex *lock)
+{
+	mutex_lock(lock);
+}
+EXPORT_SYMBOL_GPL(rust_helper_mutex_lock);
+
+void *rust_helper_net_generic(const struct net *net, unsigned int id)
+{
+	return net_generic(net, id);
+}
+EXPORT_SYMBOL_GPL(rust_helper_net_generic);
+
+struct net_device *
+rust_helper_netdev_notifier_info_to_dev(const struct netdev_notifier_info *info)
+{
+	return netdev_notifier_info_to_dev(info);
+}
+EXPORT_SYMBOL_GPL(rust_helper_netdev_notifier_info_to_dev);
+
+bool rust_helper_netif_carrier_ok(const struct net_device *dev)
+{
+	return netif_carrier_ok(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_netif_carrier_ok);
+
+bool rust_helper_netif_device_present(const struct net_device *dev)
+{
+	return netif_device_present(dev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_netif_device_present);
+
+void rust_helper_nla_nest_cancel(struct sk_buff *skb, struct nlattr *start)
+{
+	nla_nest_cancel(skb, start);
+}
+EXPORT_SYMBOL_GPL(rust_helper_nla_nest_cancel);
+
+int rust_helper_nla_nest_end(struct sk_buff *skb, struct nlattr *start)
+{
+	return nla_nest_end(skb, start);
+}
+EXPORT_SYMBOL_GPL(rust_helper_nla_nest_end);
+
+struct nlattr *rust_helper_nla_nest_start_noflag(struct sk_buff *skb, int attrtype)
+{
+	return nla_nest_start_noflag(skb, attrtype);
+}
+EXPORT_SYMBOL_GPL(rust_helper_nla_nest_start_noflag);
+
+void *rust_helper_nla_data(const struct nlattr *nla)
+{
+	return nla_data(nla);
+}
+EXPORT_SYMBOL_GPL(rust_helper_nla_data);
+
+int rust_helper_nla_len(const struct nlattr *nla)
+{
+	return nla_len(nla);
+}
+EXPORT_SYMBOL_GPL(rust_helper_nla_len);
+
+void *rust_helper_pci_get_drvdata(struct pci_dev *pdev)
+{
+	return pci_get_drvdata(pdev);
+}
+EXPORT_SYMBOL_GPL(rust_helper_pci_get_drvdata);
+
+void rust_helper_pci_set_drvdata(struct pci_dev *pdev, void *data)
+{
+	pci_set_drvdata(pdev, data);
+}
+EXPORT_SYMBOL_GPL(rust_helper_pci_set_drvdata);
+
+void *rust_helper_per_cpu_ptr(void __percpu *ptr, unsigned int cpu)
+{
+	return per_cpu_ptr(ptr, cpu);
+}
+EXPORT_SYMBOL_GPL(rust_helper_per_cpu_ptr);
+
+void rust_helper_rcu_read_lock(void)
+{
+	rcu_read_lock();
+}
+EXPORT_SYMBOL_GPL(rust_helper_rcu_read_lock);
+
+void rust_helper_rcu_read_unlock(void)
+{
+	rcu_read_unlock();
+}
+EXPORT_SYMBOL_GPL(rust_helper_rcu_read_unlock);
+
+dma_addr_t rust_helper_rdma_block_iter_dma_address(struct ib_block_iter *biter)
+{
+	return rdma_block_iter_dma_address(biter);
+}
+EXPORT_SYMBOL_GPL(rust_helper_rdma_block_iter_dma_address);
+
+bool rust_helper_rdma_is_kernel_res(const struct rdma_restrack_entry *res)
+{
+	return rdma_is_kernel_res(res);
+}
+EXPORT_SYMBOL_GPL(rust_helper_rdma_is_kernel_res);
+
+struct ib_device *rust_helper_rdma_device_to_ibdev(struct device *device)
+{
+	return rdma_device_to_ibdev(device);
+}
+EXPORT_SYMBOL_GPL(rust_helper_rdma_device_to_ibdev);
+
+void rust_helper_rdma_set_device_sysfs_group(struct ib_device *dev,
+					     const struct attribute_group *group)
+{
+	rdma_set_device_sysfs_group(dev, group);
+}
+EXPORT_SYMBOL_GPL(rust_helper_rdma_set_device_sysfs_group);
+
+void rust_helper_rdma_umem_block_iter_start(struct ib_block_iter *biter, struct ib_umem *umem,
+					    unsigned long pgsz)
+{
+	__rdma_umem_block_iter_start(biter, umem, pgsz);
+}
+EXPORT_SYMBOL_GPL(rust_helper_rdma_umem_block_iter_start);
+
+__be32 rust_helper_sa_path_get_dlid(struct sa_path_rec *rec)
+{
+	return sa_path_get_dlid(rec);
+}
+EXPORT_SYMBOL_GPL(rust_helper_sa_path_get_dlid);
+
+__be32 rust_helper_sa_path_get_slid(struct sa_path_rec *rec)
+{
+	return sa_path_get_slid(rec);
+}
+EXPORT_SYMBOL_GPL(rust_helper_sa_path_get_slid);
+
+dma_addr_t rust_helper_sg_dma_address(struct scatterlist *sg)
+{
+	return sg_dma_address(sg);
+}
+EXPORT_SYMBOL_GPL(rust_helper_sg_dma_address);
+
+unsigned int rust_helper_sg_dma_len(struct scatterlist *sg)
+{
+	return sg_dma_len(sg);
+}
+EXPORT_SYMBOL_GPL(rust_helper_sg_dma_len);
+
+struct page *rust_helper_sg_page_iter_page(struct sg_page_iter *piter)
+{
+	return sg_page_iter_page(piter);
+}
+EXPORT_SYMBOL_GPL(rust_helper_sg_page_iter_page);
+
+void rust_helper_skb_clear_hash(struct sk_buff *skb)
+{
+	skb_clear_hash(skb);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_clear_hash);
+
+void rust_helper_skb_dst_set(struct sk_buff *skb, struct dst_entry *dst)
+{
+	skb_dst_set(skb, dst);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_dst_set);
+
+unsigned char *rust_helper_skb_network_header(const struct sk_buff *skb)
+{
+	return skb_network_header(skb);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_network_header);
+
+void *rust_helper_skb_put_zero(struct sk_buff *skb, unsigned int len)
+{
+	return skb_put_zero(skb, len);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_put_zero);
+
+void rust_helper_skb_reserve(struct sk_buff *skb, int len)
+{
+	skb_reserve(skb, len);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_reserve);
+
+void rust_helper_skb_reset_network_header(struct sk_buff *skb)
+{
+	skb_reset_network_header(skb);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_reset_network_header);
+
+void rust_helper_skb_reset_transport_header(struct sk_buff *skb)
+{
+	skb_reset_transport_header(skb);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_reset_transport_header);
+
+int rust_helper_skb_tailroom(const struct sk_buff *skb)
+{
+	return skb_tailroom(skb);
+}
+EXPORT_SYMBOL_GPL(rust_helper_skb_tailroom);
+
+pid_t rust_helper_task_pid_vnr(struct task_struct *tsk)
+{
+	return task_pid_vnr(tsk);
+}
+EXPORT_SYMBOL_GPL(rust_helper_task_pid_vnr);
+
+void rust_helper_this_cpu_add_u64(u64 __percpu *ptr, u64 val)
+{
+	this_cpu_add(*ptr, val);
+}
+EXPORT_SYMBOL_GPL(rust_helper_this_cpu_add_u64);
+
+void rust_helper_timer_setup(struct timer_list *timer,
+			     void (*func)(struct timer_list *), unsigned int flags)
+{
+	timer_setup(timer, func, flags);
+}
+EXPORT_SYMBOL_GPL(rust_helper_timer_setup);
+
+void rust_helper_trace_qp_state_change(const struct ib_qp *qp, int old_state, int new_state)
+{
+	trace_qp_state_change(qp, old_state, new_state);
+}
+EXPORT_SYMBOL_GPL(rust_helper_trace_qp_state_change);
+
+bool rust_helper_trace_qp_state_change_enabled(void)
+{
+	return trace_qp_state_change_enabled();
+}
+EXPORT_SYMBOL_GPL(rust_helper_trace_qp_state_change_enabled);
+
+void rust_helper_trace_rxe_recv_pkt(int ifindex, u32 qpn, u8 opcode, u32 psn, u32 len)
+{
+	trace_rxe_recv_pkt(ifindex, qpn, opcode, psn, len);
+}
+EXPORT_SYMBOL_GPL(rust_helper_trace_rxe_recv_pkt);
+
+bool rust_helper_trace_rxe_recv_pkt_enabled(void)
+{
+	return trace_rxe_recv_pkt_enabled();
+}
+EXPORT_SYMBOL_GPL(rust_helper_trace_rxe_recv_pkt_enabled);
+
+void rust_helper_trace_rxe_send_pkt(int ifindex, u32 qpn, u8 opcode, u32 psn, u32 len)
+{
+	trace_rxe_send_pkt(ifindex, qpn, opcode, psn, len);
+}
+EXPORT_SYMBOL_GPL(rust_helper_trace_rxe_send_pkt);
+
+bool rust_helper_trace_rxe_send_pkt_enabled(void)
+{
+	return trace_rxe_send_pkt_enabled();
+}
+EXPORT_SYMBOL_GPL(rust_helper_trace_rxe_send_pkt_enabled);
+
+struct udp_sock *rust_helper_udp_sk(const struct sock *sk)
+{
+	return udp_sk(sk);
+}
+EXPORT_SYMBOL_GPL(rust_helper_udp_sk);
+
+unsigned long rust_helper_usecs_to_jiffies(const unsigned int u)
+{
+	return usecs_to_jiffies(u);
+}
+EXPORT_SYMBOL_GPL(rust_helper_usecs_to_jiffies);
+
+void rust_helper_wmb(void)
+{
+	wmb();
+}
+EXPORT_SYMBOL_GPL(rust_helper_wmb);
+
+#endif /* CONFIG_RUST_RDMA */
@@ -0,0 +1,346 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Auxiliary bus drivers.
+//!
+//! mlx5 and the newer RDMA drivers do not attach to their core driver through a bespoke interface
+//! like `mlx4_interface`: the core driver creates one auxiliary device per function it splits
+//! out, e.g. `mlx5_core.rdma.0`, and the RDMA driver binds to those devices by name like to any
+//! other bus. A driver implements [`Driver`], lists the devices it handles in
+//! [`Driver::ID_TABLE`] and keeps a [`Registration`] alive while it is loaded.
+//!
+//! The core driver usually embeds the [`Device`] in a structure carrying its own handles;
+//! [`Device::container`] gets from one to the other.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::pin::Pin;
+use core::{marker, ptr};
+use macros::vtable;
+
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::ib::compat::try_pin;
+use crate::str::CStr;
+use crate::ThisModule;
+
+/// Longest match name of a device, `AUXILIARY_NAME_SIZE` minus the terminating NUL.
+pub const MAX_NAME_LEN: usize = bindings::AUXILIARY_NAME_SIZE as usize - 1;
+
+/// A device matched by a driver, wraps `struct auxiliary_device_id`.
+///
+/// The name is the one of the core driver's module and the device's, e.g. `mlx5_core.rdma`.
+#[derive(Clone, Copy)]
+pub struct DeviceId {
+    name: [u8; MAX_NAME_LEN],
+    len: usize,
+    data: usize,
+}
+
+impl DeviceId {
+    /// Creates an ID matching the devices named `name`, handing `data` to [`Driver::probe`].
+    ///
+    /// Names longer than [`MAX_NAME_LEN`] fail the build when used in a constant.
+    pub const fn new(name: &str, data: usize) -> Self {
+        let bytes = name.as_bytes();
+        assert!(bytes.len() <= MAX_NAME_LEN);
+        let mut id = Self {
+            name: [0; MAX_NAME_LEN],
+            len: bytes.len(),
+            data,
+        };
+        let mut i = 0;
+        while i < bytes.len() {
+            id.name[i] = bytes[i];
+            i += 1;
+        }
+        id
+    }
+
+    /// Returns the name matched.
+    pub fn name(&self) -> &[u8] {
+        &self.name[..self.len]
+    }
+
+    /// Returns the driver data of the ID.
+    pub const fn data(&self) -> usize {
+        self.data
+    }
+
+    fn to_raw(self) -> bindings::auxiliary_device_id {
+        let mut raw = bindings::auxiliary_device_id::default();
+        for (dst, src) in raw.name.iter_mut().zip(self.name()) {
+            *dst = *src as _;
+        }
+        raw.driver_data = self.data as _;
+        raw
+    }
+}
+
+/// An auxiliary device, wraps `struct auxiliary_device`.
+#[repr(transparent)]
+pub struct Device(UnsafeCell<bindings::auxiliary_device>);
+
+impl Device {
+    /// Creates a reference to a [`Device`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::auxiliary_device) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `Device` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct auxiliary_device` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::auxiliary_device {
+        self.0.get()
+    }
+
+    /// Returns the embedded `struct device`, e.g. for DMA mappings.
+    pub fn raw_device(&self) -> *mut bindings::device {
+        // SAFETY: The device is valid.
+        unsafe { ptr::addr_of_mut!((*self.as_ptr()).dev) }
+    }
+
+    /// Returns the full name of the device, e.g. `mlx5_core.rdma.0`.
+    pub fn name(&self) -> &CStr {
+        // SAFETY: The device is valid and was named when it was added to the bus.
+        unsafe { CStr::from_char_ptr(bindings::dev_name(self.raw_device())) }
+    }
+
+    /// Returns the instance number of the device among the ones of the same name.
+    pub fn id(&self) -> u32 {
+        // SAFETY: The device is valid and its ID does not change once added.
+        unsafe { (*self.as_ptr()).id }
+    }
+
+    /// Returns the structure of the core driver the device is embedded in, at `offset` bytes.
+    ///
+    /// # Safety
+    ///
+    /// The device must be embedded in a `P` at `offset`, e.g. the `adev` field of
+    /// `struct mlx5_adev` for `mlx5_core` devices, which the caller knows from the name matched.
+    pub unsafe fn container<P>(&self, offset: usize) -> &P {
+        // SAFETY: Guaranteed by the safety requirements; the container lives as long as the
+        // device.
+        unsafe { &*self.as_ptr().cast::<u8>().sub(offset).cast::<P>() }
+    }
+}
+
+/// Corresponds to the kernel's `struct auxiliary_driver`.
+///
+/// You implement this trait whenever you would create a `struct auxiliary_driver`.
+#[vtable]
+pub trait Driver {
+    /// Driver state of one device, created by [`Driver::probe`].
+    type Context: Send + Sync;
+
+    /// Devices the driver binds to.
+    const ID_TABLE: &'static [DeviceId];
+
+    /// Binds to `dev`, which matched `id`.
+    ///
+    /// The returned context is handed back to [`Driver::remove`]; on error the driver does not
+    /// bind to the device.
+    fn probe(dev: &Device, id: &DeviceId) -> Result<Pin<Box<Self::Context>>>;
+
+    /// Unbinds from `dev`, dropping the context returned by [`Driver::probe`].
+    fn remove(dev: &Device, context: Pin<Box<Self::Context>>);
+
+    /// Quiesces `dev` on reboot or kexec; the device is not removed afterwards.
+    fn shutdown(_dev: &Device, _context: Pin<&Self::Context>) {}
+}
+
+/// Auxiliary driver registration.
+pub struct Registration<T: Driver> {
+    registered: bool,
+    name: &'static CStr,
+    module: &'static ThisModule,
+    driver: bindings::auxiliary_driver,
+    ids: Vec<bindings::auxiliary_device_id>,
+    phantom: marker::PhantomData<T>,
+    _pin: marker::PhantomPinned,
+}
+
+impl<T: Driver> Registration<T> {
+    /// Creates a new [`Registration`] but does not register it yet.
+    ///
+    /// It is allowed to move until it is pinned to be registered. The driver is named
+    /// `<module>.<name>` in sysfs.
+    pub fn new(name: &'static CStr, module: &'static ThisModule) -> Self {
+        // INVARIANT: `registered` is `false`
+        Self {
+            registered: false,
+            name,
+            module,
+            driver: bindings::auxiliary_driver::default(),
+            ids: Vec::new(),
+            phantom: marker::PhantomData,
+            _pin: marker::PhantomPinned,
+        }
+    }
+
+    /// Registers an auxiliary driver.
+    ///
+    /// Returns a pinned heap-allocated representation of the registration.
+    pub fn new_pinned(name: &'static CStr, module: &'static ThisModule) -> Result<Pin<Box<Self>>> {
+        let mut r = try_pin(Self::new(name, module))?;
+        r.as_mut().register()?;
+        Ok(r)
+    }
+
+    /// Registers an auxiliary driver with the rest of the kernel; the devices already on the bus
+    /// are probed before this returns.
+    ///
+    /// It must be pinned because the memory block that represents the registration is
+    /// self-referential.
+    pub fn register(self: Pin<&mut Self>) -> Result {
+        // SAFETY: We must ensure that we never move out of `this`.
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.registered {
+            // Already registered.
+            return Err(EINVAL);
+        }
+        if T::ID_TABLE.is_empty() {
+            return Err(EINVAL);
+        }
+
+        // The bus walks the table up to an empty name.
+        let mut ids = Vec::try_with_capacity(T::ID_TABLE.len() + 1)?;
+        for id in T::ID_TABLE {
+            ids.try_push(id.to_raw())?;
+        }
+        ids.try_push(bindings::auxiliary_device_id::default())?;
+        this.ids = ids;
+
+        this.driver = DriverTable::<T>::build(this.name, &this.ids);
+        // SAFETY: `this.driver` and the ID table it points to are pinned and only unregistered
+        // in `drop`; the module and name are static.
+        let ret = unsafe {
+            bindings::__auxiliary_driver_register(
+                &mut this.driver,
+                this.module.as_ptr(),
+                this.module_name(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+
+        this.registered = true;
+        Ok(())
+    }
+
+    /// Returns the name of the module, which prefixes the name of the driver.
+    fn module_name(&self) -> *const core::ffi::c_char {
+        // SAFETY: The module is static, and so is its name.
+        unsafe { (*self.module.as_ptr()).name.as_ptr() }
+    }
+}
+
+impl<T: Driver> Drop for Registration<T> {
+    /// Removes the registration from the kernel if it has completed successfully before.
+    fn drop(&mut self) {
+        if self.registered {
+            // SAFETY: `self.driver` was registered in `register` and has not moved since. The
+            // bus calls `remove` for every bound device before this returns.
+            unsafe { bindings::auxiliary_driver_unregister(&mut self.driver) };
+        }
+    }
+}
+
+// SAFETY: `Registration` does not expose any of its state across threads; the driver it owns is
+// only touched by the driver core, under its own locks.
+unsafe impl<T: Driver> Sync for Registration<T> {}
+
+/// Build kernel's `struct auxiliary_driver` type with the operations of a [`Driver`].
+pub struct DriverTable<T>(marker::PhantomData<T>);
+
+impl<T: Driver> DriverTable<T> {
+    /// Builds an instance of [`struct auxiliary_driver`] matching the devices of `ids`, which
+    /// must end with an empty entry.
+    ///
+    /// The driver and `ids` must not move once it is registered; [`Registration`] keeps them
+    /// pinned until it unregisters it.
+    pub fn build(
+        name: &'static CStr,
+        ids: &[bindings::auxiliary_device_id],
+    ) -> bindings::auxiliary_driver {
+        bindings::auxiliary_driver {
+            probe: Some(Self::probe_callback),
+            remove: Some(Self::remove_callback),
+            shutdown: if T::HAS_SHUTDOWN {
+                Some(Self::shutdown_callback)
+            } else {
+                None
+            },
+            name: name.as_char_ptr(),
+            id_table: ids.as_ptr(),
+            ..Default::default()
+        }
+    }
+
+    /// Looks up the entry of [`Driver::ID_TABLE`] the bus matched, from its copy in the table.
+    fn find_id(id: *const bindings::auxiliary_device_id) -> Option<&'static DeviceId> {
+        if id.is_null() {
+            return None;
+        }
+        // SAFETY: The bus passes an entry of the table built from `T::ID_TABLE`, in the same
+        // order.
+        let data = unsafe { (*id).driver_data } as usize;
+        // SAFETY: As above.
+        let name = unsafe { CStr::from_char_ptr((*id).name.as_ptr()) };
+        T::ID_TABLE
+            .iter()
+            .find(|i| i.name() == name.as_bytes() && i.data() == data)
+    }
+
+    unsafe extern "C" fn probe_callback(
+        adev: *mut bindings::auxiliary_device,
+        id: *const bindings::auxiliary_device_id,
+    ) -> core::ffi::c_int {
+        let id = match Self::find_id(id) {
+            Some(id) => id,
+            None => return ENODEV.to_kernel_errno(),
+        };
+        // SAFETY: The bus passes a valid device that outlives the binding.
+        let dev = unsafe { Device::from_ptr(adev) };
+        match T::probe(dev, id) {
+            Ok(context) => {
+                // SAFETY: The context is turned back into a pinned box by `remove_callback`
+                // only.
+                let context = Box::into_raw(unsafe { Pin::into_inner_unchecked(context) });
+                // SAFETY: The device is valid and bound to us, so its driver data is ours.
+                unsafe { bindings::auxiliary_set_drvdata(adev, context.cast()) };
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn remove_callback(adev: *mut bindings::auxiliary_device) {
+        // SAFETY: The bus only removes devices whose probe succeeded, so the driver data is the
+        // context `probe_callback` stored, which is not used anymore once this returns.
+        let (dev, context) = unsafe {
+            let context = bindings::auxiliary_get_drvdata(adev).cast::<T::Context>();
+            bindings::auxiliary_set_drvdata(adev, ptr::null_mut());
+            (
+                Device::from_ptr(adev),
+                Pin::new_unchecked(Box::from_raw(context)),
+            )
+        };
+        T::remove(dev, context);
+    }
+
+    unsafe extern "C" fn shutdown_callback(adev: *mut bindings::auxiliary_device) {
+        // SAFETY: The bus only shuts down devices whose probe succeeded, whose context lives
+        // until `remove_callback`.
+        let (dev, context) = unsafe {
+            let context = bindings::auxiliary_get_drvdata(adev).cast::<T::Context>();
+            (Device::from_ptr(adev), Pin::new_unchecked(&*context))
+        };
+        T::shutdown(dev, context);
+    }
+}
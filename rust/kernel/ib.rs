@@ -0,0 +1,117 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! InfiniBand verbs provider abstractions.
+//!
+//! A provider implements [`IbDeviceOperations`] and creates [`Device`] instances, typically from
+//! the `newlink` callback of a soft transport or the probe of a hardware driver. ib_core allocates
+//! the verbs objects (PDs, CQs, QPs, ...) itself with room for the provider's per-object data,
+//! which the abstractions here initialise and drop around the provider's callbacks. Memory regions
+//! are the exception: providers allocate them, so [`MemoryRegion`] boxes them itself.
+
+use core::mem::MaybeUninit;
+
+pub mod ah;
+#[cfg(CONFIG_RUST_RDMA_CM)]
+pub mod cm;
+#[cfg(CONFIG_RUST_RDMA_CM)]
+mod cm_context;
+pub mod compat;
+pub mod cq;
+pub mod device;
+pub mod dma;
+pub mod gid;
+pub mod hw_stats;
+#[cfg(CONFIG_RUST_RDMA_KUNIT_TEST)]
+pub(crate) mod kunit;
+pub mod mad;
+pub mod mr;
+pub mod mw;
+pub mod netdev;
+pub mod netlink;
+pub mod observer;
+#[cfg(CONFIG_RUST_RDMA_PCI)]
+pub mod pci;
+pub mod pd;
+pub mod qp;
+#[cfg(CONFIG_RUST_RDMA_CM)]
+pub mod rdma_cm;
+pub mod restrack;
+pub mod sa;
+pub mod srq;
+pub mod sysfs;
+pub mod trace;
+pub mod umem;
+pub mod umem_dmabuf;
+#[cfg(CONFIG_INFINIBAND_ON_DEMAND_PAGING)]
+pub mod umem_odp;
+pub mod wr;
+
+pub use ah::{AddressHandle, RdmaAhAttr};
+pub use cq::{CompletionQueue, WorkCompletion};
+pub use device::{Device, DeviceRef, DriverId, IbDeviceOperations, MadRequest, MadSource};
+pub use gid::{GidAttr, GidTable};
+pub use hw_stats::{HwCounter, HwStats};
+pub use mr::MemoryRegion;
+pub use mw::MemoryWindow;
+pub use netdev::{Namespace, NetDevice};
+pub use netlink::{DriverAttrs, StatCounters};
+pub use observer::{DeviceObserver, ObservedDevice, ObserverRegistration};
+#[cfg(CONFIG_RUST_RDMA_PCI)]
+pub use pci::{PciDevice, PciDeviceId, PciRdmaAdapter, PciRdmaDriver};
+pub use pd::ProtectionDomain;
+pub use qp::{QpAsyncEvent, QpAttr, QpState, QueuePair};
+pub use restrack::{ResourceEntry, ResourceOwner, ResourceRef};
+pub use srq::SharedReceiveQueue;
+pub use sysfs::{AttributeGroup, DeviceAttribute};
+pub use umem::Umem;
+pub use wr::{PostRecvWr, PostSendWr, RecvWr, SendWr, Sge};
+
+pub use crate::rdma::fw_str::FwStr;
+pub use crate::rdma::mad::MadResult;
+
+/// A verbs object allocated by ib_core followed by the provider's data.
+///
+/// ib_core allocates `size_ib_*` bytes (see `INIT_RDMA_OBJ_SIZE`) and only initialises the leading
+/// `struct ib_*`, so `data` is written by the create callback and dropped by the destroy callback.
+#[repr(C)]
+pub(crate) struct Object<R, D> {
+    pub(crate) raw: R,
+    pub(crate) data: MaybeUninit<D>,
+}
+
+impl<R, D> Object<R, D> {
+    /// Returns the object containing `raw`.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must have been allocated by ib_core with the size of `Object<R, D>`.
+    pub(crate) unsafe fn from_raw<'a>(raw: *mut R) -> &'a mut Self {
+        // SAFETY: `raw` is the first field of the `repr(C)` struct, so the cast is ok.
+        unsafe { &mut *raw.cast() }
+    }
+
+    /// Stores the provider's data.
+    pub(crate) fn init(&mut self, data: D) {
+        self.data.write(data);
+    }
+
+    /// Returns the provider's data.
+    ///
+    /// # Safety
+    ///
+    /// `init` must have been called and `take` not yet.
+    pub(crate) unsafe fn data(&self) -> &D {
+        // SAFETY: Guaranteed by the safety requirements.
+        unsafe { self.data.assume_init_ref() }
+    }
+
+    /// Moves the provider's data out.
+    ///
+    /// # Safety
+    ///
+    /// `init` must have been called and `take` not yet; the data must not be used afterwards.
+    pub(crate) unsafe fn take(&mut self) -> D {
+        // SAFETY: Guaranteed by the safety requirements.
+        unsafe { self.data.assume_init_read() }
+    }
+}
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Address handles.
+
+use core::cell::UnsafeCell;
+use core::marker;
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::pd::ProtectionDomain;
+use super::Object;
+use crate::bindings;
+use crate::rdma::gid::Gid;
+
+/// Kind of address vector, `enum rdma_ah_attr_type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AhAttrType {
+    /// Not set.
+    Undefined,
+    /// InfiniBand, addressed by LID.
+    Ib,
+    /// RoCE, addressed by GID and destination MAC.
+    Roce,
+    /// Omni-Path.
+    Opa,
+}
+
+impl AhAttrType {
+    fn from_raw(raw: bindings::rdma_ah_attr_type) -> Self {
+        match raw {
+            bindings::rdma_ah_attr_type_RDMA_AH_ATTR_TYPE_IB => AhAttrType::Ib,
+            bindings::rdma_ah_attr_type_RDMA_AH_ATTR_TYPE_ROCE => AhAttrType::Roce,
+            bindings::rdma_ah_attr_type_RDMA_AH_ATTR_TYPE_OPA => AhAttrType::Opa,
+            _ => AhAttrType::Undefined,
+        }
+    }
+
+    fn to_raw(self) -> bindings::rdma_ah_attr_type {
+        match self {
+            AhAttrType::Undefined => bindings::rdma_ah_attr_type_RDMA_AH_ATTR_TYPE_UNDEFINED,
+            AhAttrType::Ib => bindings::rdma_ah_attr_type_RDMA_AH_ATTR_TYPE_IB,
+            AhAttrType::Roce => bindings::rdma_ah_attr_type_RDMA_AH_ATTR_TYPE_ROCE,
+            AhAttrType::Opa => bindings::rdma_ah_attr_type_RDMA_AH_ATTR_TYPE_OPA,
+        }
+    }
+}
+
+/// Global routing information, from `struct ib_global_route`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GlobalRoute {
+    /// Destination GID.
+    pub dgid: Gid,
+    /// Flow label, 20 bits.
+    pub flow_label: u32,
+    /// Index of the source GID in the port's GID table; for RoCE it selects the RoCE version and
+    /// the source IP address.
+    pub sgid_index: u8,
+    /// Hop limit.
+    pub hop_limit: u8,
+    /// Traffic class.
+    pub traffic_class: u8,
+}
+
+/// An address vector, the typed equivalent of `struct rdma_ah_attr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RdmaAhAttr {
+    /// Kind of address.
+    pub ty: AhAttrType,
+    /// Local port the traffic leaves from.
+    pub port_num: u32,
+    /// Service level.
+    pub sl: u8,
+    /// Static rate, `enum ib_rate`.
+    pub static_rate: u8,
+    /// Global routing header, mandatory for RoCE.
+    pub grh: Option<GlobalRoute>,
+    /// Destination LID, InfiniBand only.
+    pub dlid: u16,
+    /// Source path bits, InfiniBand only.
+    pub src_path_bits: u8,
+    /// Destination MAC address, RoCE only.
+    pub dmac: [u8; 6],
+}
+
+impl RdmaAhAttr {
+    /// Creates an address vector of kind `ty` leaving from `port_num`, with everything else zero.
+    pub const fn new(ty: AhAttrType, port_num: u32) -> Self {
+        Self {
+            ty,
+            port_num,
+            sl: 0,
+            static_rate: 0,
+            grh: None,
+            dlid: 0,
+            src_path_bits: 0,
+            dmac: [0; 6],
+        }
+    }
+
+    /// Sets the global routing header.
+    pub const fn with_grh(mut self, grh: GlobalRoute) -> Self {
+        self.grh = Some(grh);
+        self
+    }
+
+    /// Sets the service level.
+    pub const fn with_sl(mut self, sl: u8) -> Self {
+        self.sl = sl;
+        self
+    }
+
+    /// Sets the destination LID.
+    pub const fn with_dlid(mut self, dlid: u16) -> Self {
+        self.dlid = dlid;
+        self
+    }
+
+    /// Sets the destination MAC address.
+    pub const fn with_dmac(mut self, dmac: [u8; 6]) -> Self {
+        self.dmac = dmac;
+        self
+    }
+
+    /// Copies an address vector passed by ib_core.
+    pub(crate) fn from_raw(raw: &bindings::rdma_ah_attr) -> Self {
+        let ty = AhAttrType::from_raw(raw.type_);
+        let grh = if raw.ah_flags & bindings::ib_ah_flags_IB_AH_GRH as u8 != 0 {
+            Some(GlobalRoute {
+                // SAFETY: Every bit pattern is a valid `raw` view of the GID.
+                dgid: Gid::from_raw(unsafe { raw.grh.dgid.raw }),
+                flow_label: raw.grh.flow_label,
+                sgid_index: raw.grh.sgid_index,
+                hop_limit: raw.grh.hop_limit,
+                traffic_class: raw.grh.traffic_class,
+            })
+        } else {
+            None
+        };
+        let mut attr = Self {
+            ty,
+            port_num: raw.port_num,
+            sl: raw.sl,
+            static_rate: raw.static_rate,
+            grh,
+            dlid: 0,
+            src_path_bits: 0,
+            dmac: [0; 6],
+        };
+        // SAFETY: The union member read is the one selected by `type_`.
+        unsafe {
+            match ty {
+                AhAttrType::Ib => {
+                    attr.dlid = raw.__bindgen_anon_1.ib.dlid;
+                    attr.src_path_bits = raw.__bindgen_anon_1.ib.src_path_bits;
+                }
+                AhAttrType::Roce => attr.dmac = raw.__bindgen_anon_1.roce.dmac,
+                AhAttrType::Opa | AhAttrType::Undefined => {}
+            }
+        }
+        attr
+    }
+
+    /// Fills `raw`, e.g. for `query_ah`. The source GID attribute is left untouched.
+    pub(crate) fn write_to(&self, raw: &mut bindings::rdma_ah_attr) {
+        raw.type_ = self.ty.to_raw();
+        raw.port_num = self.port_num;
+        raw.sl = self.sl;
+        raw.static_rate = self.static_rate;
+        raw.ah_flags = 0;
+        if let Some(grh) = &self.grh {
+            raw.ah_flags |= bindings::ib_ah_flags_IB_AH_GRH as u8;
+            raw.grh.dgid.raw = grh.dgid.raw();
+            raw.grh.flow_label = grh.flow_label;
+            raw.grh.sgid_index = grh.sgid_index;
+            raw.grh.hop_limit = grh.hop_limit;
+            raw.grh.traffic_class = grh.traffic_class;
+        }
+        match self.ty {
+            AhAttrType::Ib => {
+                raw.__bindgen_anon_1.ib.dlid = self.dlid;
+                raw.__bindgen_anon_1.ib.src_path_bits = self.src_path_bits;
+            }
+            AhAttrType::Roce => raw.__bindgen_anon_1.roce.dmac = self.dmac,
+            AhAttrType::Opa | AhAttrType::Undefined => {}
+        }
+    }
+}
+
+/// Provider data of an AH together with the address vector it was created from.
+pub(crate) struct AhObject<D> {
+    pub(crate) attr: RdmaAhAttr,
+    pub(crate) data: D,
+}
+
+/// An address handle of a device provided by `T`, wraps `struct ib_ah`.
+///
+/// The abstraction keeps the address vector the AH was created from, so `query_ah` needs no
+/// provider support and UD senders can read it back with [`AddressHandle::attr`].
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_ah` belongs to a device provided by `T` and is followed by an
+/// initialised `AhObject<T::AhData>`.
+#[repr(transparent)]
+pub struct AddressHandle<T: IbDeviceOperations>(
+    UnsafeCell<bindings::ib_ah>,
+    marker::PhantomData<T>,
+);
+
+impl<T: IbDeviceOperations> AddressHandle<T> {
+    /// Creates a reference to an [`AddressHandle`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an AH of a device provided by `T` whose `create_ah` succeeded and which is
+    /// not destroyed for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_ah) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `AddressHandle` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_ah` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_ah {
+        self.0.get()
+    }
+
+    fn object(&self) -> &AhObject<T::AhData> {
+        // SAFETY: By the type invariants the object is initialised.
+        unsafe { Object::<bindings::ib_ah, AhObject<T::AhData>>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns the device the AH belongs to.
+    pub fn device(&self) -> &DeviceRef<T> {
+        // SAFETY: By the type invariants the AH belongs to a device provided by `T`, which
+        // outlives all of its AHs.
+        unsafe { DeviceRef::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the protection domain of the AH.
+    pub fn pd(&self) -> &ProtectionDomain<T> {
+        // SAFETY: ib_core sets the PD before calling `create_ah`, and it outlives the AH.
+        unsafe { ProtectionDomain::from_ptr((*self.as_ptr()).pd) }
+    }
+
+    /// Returns the address vector of the AH.
+    pub fn attr(&self) -> &RdmaAhAttr {
+        &self.object().attr
+    }
+
+    /// Returns the provider data of the AH.
+    pub fn data(&self) -> &T::AhData {
+        &self.object().data
+    }
+}
@@ -0,0 +1,677 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! InfiniBand connection manager consumers.
+//!
+//! C header: [`include/rdma/ib_cm.h`](../../../../include/rdma/ib_cm.h)
+//!
+//! Kernel ULPs establish their RC connections through ib_cm: the target side creates a
+//! [`Listener`] for its service ID, the initiator a [`Connection`] on which it sends a REQ, and
+//! both are told about the messages of the handshake by their [`CmEventHandler`]. A listener
+//! hands every REQ it receives to its handler as an [`Incoming`] ID, which the handler turns into
+//! a [`Connection`] with its own handler before answering with a REP, or rejects.
+//!
+//! The consumer owns its QP and moves it through the states with the attributes of
+//! [`CmId::init_qp_attr`]: to RTR and RTS before sending a REP or an RTU.
+//!
+//! The event callbacks of an ID run one at a time, in process context. Dropping a [`Connection`]
+//! waits for the callback of its ID in progress, unless it is dropped from that very callback: its
+//! ID is then destroyed by ib_cm once the callback returns. A [`Listener`] must not be dropped from
+//! the callbacks of the REQs it received, which hold it; the ULPs defer its teardown to a work item
+//! instead.
+
+use alloc::boxed::Box;
+use core::cell::{Cell, UnsafeCell};
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::{marker, slice};
+use macros::vtable;
+
+use super::cm_context::IdContext;
+use super::observer::ObservedDevice;
+use super::qp::QpAttr;
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Error, Result};
+use crate::rdma::cm::{cm_timeout, CmMessage, RejReason};
+use crate::rdma::psn::Psn;
+use crate::rdma::qp_state::QpState;
+
+/// CM response timeout of the REQs, in milliseconds, like `CMA_CM_RESPONSE_TIMEOUT`.
+pub const CM_RESPONSE_TIMEOUT_MS: u32 = 4_000;
+
+/// Retries of an unanswered REQ, REP or DREQ, like `CMA_MAX_CM_RETRIES`.
+pub const MAX_CM_RETRIES: u8 = 15;
+
+/// Returns the `private_data_len` of `data` sent in `message`.
+fn private_data_len(message: CmMessage, data: &[u8]) -> Result<u8> {
+    message.check_private_data(data).map_err(|_| EINVAL)
+}
+
+/// Returns the private data of a received `message`, which ib_cm hands over without its length.
+///
+/// # Safety
+///
+/// `data` must be null or point to the private data area of a `message` that outlives `'a`.
+unsafe fn private_data<'a>(data: *mut core::ffi::c_void, message: CmMessage) -> &'a [u8] {
+    if data.is_null() {
+        return &[];
+    }
+    // SAFETY: Guaranteed by the safety requirements.
+    unsafe { slice::from_raw_parts(data.cast(), message.private_data_size()) }
+}
+
+fn to_result(ret: core::ffi::c_int) -> Result {
+    if ret < 0 {
+        return Err(Error::from_kernel_errno(ret));
+    }
+    Ok(())
+}
+
+/// A communication ID, wraps `struct ib_cm_id`.
+///
+/// Owned by a [`Connection`] or a [`Listener`], or lent to the callbacks of the handler.
+#[repr(transparent)]
+pub struct CmId(UnsafeCell<bindings::ib_cm_id>);
+
+impl CmId {
+    /// Creates a reference to a [`CmId`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_cm_id) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `CmId` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_cm_id` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_cm_id {
+        self.0.get()
+    }
+
+    /// Returns the device the ID was created on.
+    pub fn device(&self) -> &ObservedDevice {
+        // SAFETY: The ID is valid, and holds its device until it is destroyed.
+        unsafe { ObservedDevice::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the service ID listened to or connected to.
+    pub fn service_id(&self) -> u64 {
+        // SAFETY: The ID is valid.
+        u64::from_be(unsafe { (*self.as_ptr()).service_id })
+    }
+
+    /// Returns the local communication ID.
+    pub fn local_id(&self) -> u32 {
+        // SAFETY: The ID is valid.
+        u32::from_be(unsafe { (*self.as_ptr()).local_id })
+    }
+
+    /// Returns the communication ID of the peer, 0 until it is known.
+    pub fn remote_id(&self) -> u32 {
+        // SAFETY: The ID is valid.
+        u32::from_be(unsafe { (*self.as_ptr()).remote_id })
+    }
+
+    /// Sends a REQ, starting the handshake of an active connection.
+    pub fn send_req(&self, params: &ReqParams<'_>) -> Result {
+        let mut raw = params.raw;
+        // SAFETY: The ID is valid, and the path and the private data outlive the call, which
+        // copies them.
+        to_result(unsafe { bindings::ib_send_cm_req(self.as_ptr(), &mut raw) })
+    }
+
+    /// Answers a REQ with a REP; the QP must be in RTR already.
+    pub fn send_rep(&self, params: &RepParams<'_>) -> Result {
+        let mut raw = params.raw;
+        // SAFETY: The ID is valid, and the private data outlives the call, which copies it.
+        to_result(unsafe { bindings::ib_send_cm_rep(self.as_ptr(), &mut raw) })
+    }
+
+    /// Answers a REP with an RTU, completing the handshake of an active connection.
+    pub fn send_rtu(&self, private_data: &[u8]) -> Result {
+        let len = private_data_len(CmMessage::Rtu, private_data)?;
+        // SAFETY: The ID is valid, and the private data outlives the call, which copies it.
+        to_result(unsafe {
+            bindings::ib_send_cm_rtu(self.as_ptr(), private_data.as_ptr().cast(), len)
+        })
+    }
+
+    /// Sends a DREQ, starting the teardown of an established connection.
+    pub fn send_dreq(&self, private_data: &[u8]) -> Result {
+        let len = private_data_len(CmMessage::Dreq, private_data)?;
+        // SAFETY: The ID is valid, and the private data outlives the call, which copies it.
+        to_result(unsafe {
+            bindings::ib_send_cm_dreq(self.as_ptr(), private_data.as_ptr().cast(), len)
+        })
+    }
+
+    /// Answers a DREQ with a DREP.
+    pub fn send_drep(&self, private_data: &[u8]) -> Result {
+        let len = private_data_len(CmMessage::Drep, private_data)?;
+        // SAFETY: The ID is valid, and the private data outlives the call, which copies it.
+        to_result(unsafe {
+            bindings::ib_send_cm_drep(self.as_ptr(), private_data.as_ptr().cast(), len)
+        })
+    }
+
+    /// Rejects a REQ or a REP.
+    pub fn send_rej(&self, reason: RejReason, private_data: &[u8]) -> Result {
+        let len = private_data_len(CmMessage::Rej, private_data)?;
+        // SAFETY: The ID is valid, and the private data outlives the call, which copies it.
+        to_result(unsafe {
+            bindings::ib_send_cm_rej(
+                self.as_ptr(),
+                reason.code() as _,
+                core::ptr::null_mut(),
+                0,
+                private_data.as_ptr().cast(),
+                len,
+            )
+        })
+    }
+
+    /// Tells ib_cm that the connection is established before the RTU arrived, when the QP
+    /// receives its first packet.
+    pub fn notify_established(&self) -> Result {
+        // SAFETY: The ID is valid.
+        to_result(unsafe {
+            bindings::ib_cm_notify(self.as_ptr(), bindings::ib_event_type_IB_EVENT_COMM_EST)
+        })
+    }
+
+    /// Returns the attributes moving the QP of the connection to `state`, negotiated by the
+    /// handshake so far.
+    pub fn init_qp_attr(&self, state: QpState) -> Result<QpAttr> {
+        let mut raw = bindings::ib_qp_attr {
+            qp_state: state.to_raw(),
+            ..Default::default()
+        };
+        let mut mask = 0;
+        // SAFETY: The ID is valid and the attributes are written to locals.
+        to_result(unsafe { bindings::ib_cm_init_qp_attr(self.as_ptr(), &mut raw, &mut mask) })?;
+        // SAFETY: `raw` is a valid, initialised attribute set.
+        Ok(unsafe { QpAttr::from_raw(&raw, mask as u32) })
+    }
+}
+
+/// Parameters of a REQ, see [`CmId::send_req`].
+pub struct ReqParams<'a> {
+    raw: bindings::ib_cm_req_param,
+    phantom: marker::PhantomData<&'a [u8]>,
+}
+
+impl<'a> ReqParams<'a> {
+    /// Returns the parameters connecting the RC QP `qp_num`, whose send queue starts at
+    /// `starting_psn`, to `service_id` over `path`.
+    ///
+    /// The REQ asks for no RDMA read resources, waits [`CM_RESPONSE_TIMEOUT_MS`] for the REP and
+    /// retries the transport and RNR errors 7 times.
+    pub fn new(
+        path: &'a bindings::sa_path_rec,
+        service_id: u64,
+        qp_num: u32,
+        starting_psn: Psn,
+    ) -> Self {
+        let timeout = cm_timeout(CM_RESPONSE_TIMEOUT_MS);
+        Self {
+            raw: bindings::ib_cm_req_param {
+                primary_path: path as *const _ as *mut _,
+                service_id: service_id.to_be(),
+                qp_num,
+                qp_type: bindings::ib_qp_type_IB_QPT_RC,
+                starting_psn: starting_psn.value(),
+                remote_cm_response_timeout: timeout,
+                local_cm_response_timeout: timeout,
+                flow_control: 1,
+                retry_count: 7,
+                rnr_retry_count: 7,
+                max_cm_retries: MAX_CM_RETRIES,
+                ..Default::default()
+            },
+            phantom: marker::PhantomData,
+        }
+    }
+
+    /// Sets the private data, at most 92 bytes.
+    pub fn with_private_data(mut self, data: &'a [u8]) -> Result<Self> {
+        self.raw.private_data_len = private_data_len(CmMessage::Req, data)?;
+        self.raw.private_data = data.as_ptr().cast();
+        Ok(self)
+    }
+
+    /// Sets the RDMA reads and atomics the QP accepts and sends at once.
+    pub fn with_rdma_depth(mut self, responder_resources: u8, initiator_depth: u8) -> Self {
+        self.raw.responder_resources = responder_resources;
+        self.raw.initiator_depth = initiator_depth;
+        self
+    }
+
+    /// Sets how long to wait for the answers of the peer, in milliseconds.
+    pub fn with_response_timeout(mut self, ms: u32) -> Self {
+        let timeout = cm_timeout(ms);
+        self.raw.remote_cm_response_timeout = timeout;
+        self.raw.local_cm_response_timeout = timeout;
+        self
+    }
+
+    /// Sets the retries of the transport and RNR errors, 7 retrying RNR NAKs forever.
+    pub fn with_retries(mut self, retry_count: u8, rnr_retry_count: u8) -> Self {
+        self.raw.retry_count = retry_count.min(7);
+        self.raw.rnr_retry_count = rnr_retry_count.min(7);
+        self
+    }
+
+    /// Tells the peer that the QP receives from an SRQ.
+    pub fn with_srq(mut self, srq: bool) -> Self {
+        self.raw.srq = srq as u8;
+        self
+    }
+}
+
+/// Parameters of a REP, see [`CmId::send_rep`].
+pub struct RepParams<'a> {
+    raw: bindings::ib_cm_rep_param,
+    phantom: marker::PhantomData<&'a [u8]>,
+}
+
+impl<'a> RepParams<'a> {
+    /// Returns the parameters accepting a REQ with the QP `qp_num`, whose send queue starts at
+    /// `starting_psn`.
+    pub fn new(qp_num: u32, starting_psn: Psn) -> Self {
+        Self {
+            raw: bindings::ib_cm_rep_param {
+                qp_num,
+                starting_psn: starting_psn.value(),
+                flow_control: 1,
+                rnr_retry_count: 7,
+                ..Default::default()
+            },
+            phantom: marker::PhantomData,
+        }
+    }
+
+    /// Sets the private data, at most 196 bytes.
+    pub fn with_private_data(mut self, data: &'a [u8]) -> Result<Self> {
+        self.raw.private_data_len = private_data_len(CmMessage::Rep, data)?;
+        self.raw.private_data = data.as_ptr().cast();
+        Ok(self)
+    }
+
+    /// Sets the RDMA reads and atomics the QP accepts and sends at once, at most what the REQ
+    /// asked for.
+    pub fn with_rdma_depth(mut self, responder_resources: u8, initiator_depth: u8) -> Self {
+        self.raw.responder_resources = responder_resources;
+        self.raw.initiator_depth = initiator_depth;
+        self
+    }
+
+    /// Sets the retries of the RNR errors, 7 retrying forever.
+    pub fn with_rnr_retry(mut self, rnr_retry_count: u8) -> Self {
+        self.raw.rnr_retry_count = rnr_retry_count.min(7);
+        self
+    }
+
+    /// Tells the peer that the QP receives from an SRQ.
+    pub fn with_srq(mut self, srq: bool) -> Self {
+        self.raw.srq = srq as u8;
+        self
+    }
+}
+
+/// A received REQ.
+pub struct ReqEvent<'a> {
+    param: &'a bindings::ib_cm_req_event_param,
+    private_data: &'a [u8],
+}
+
+impl ReqEvent<'_> {
+    /// Returns the port the REQ was received on.
+    pub fn port(&self) -> u32 {
+        self.param.port
+    }
+
+    /// Returns the GUID of the peer's channel adapter.
+    pub fn remote_ca_guid(&self) -> u64 {
+        u64::from_be(self.param.remote_ca_guid)
+    }
+
+    /// Returns the QP number of the peer.
+    pub fn remote_qpn(&self) -> u32 {
+        self.param.remote_qpn
+    }
+
+    /// Returns the first PSN the peer sends with, where the receive queue of the QP starts.
+    pub fn starting_psn(&self) -> Psn {
+        Psn::new(self.param.starting_psn)
+    }
+
+    /// Returns the RDMA reads and atomics the peer accepts at once.
+    pub fn responder_resources(&self) -> u8 {
+        self.param.responder_resources
+    }
+
+    /// Returns the RDMA reads and atomics the peer sends at once.
+    pub fn initiator_depth(&self) -> u8 {
+        self.param.initiator_depth
+    }
+
+    /// Returns the private data of the REQ.
+    pub fn private_data(&self) -> &[u8] {
+        self.private_data
+    }
+}
+
+/// A received REP.
+pub struct RepEvent<'a> {
+    param: &'a bindings::ib_cm_rep_event_param,
+    private_data: &'a [u8],
+}
+
+impl RepEvent<'_> {
+    /// Returns the GUID of the peer's channel adapter.
+    pub fn remote_ca_guid(&self) -> u64 {
+        u64::from_be(self.param.remote_ca_guid)
+    }
+
+    /// Returns the QP number of the peer.
+    pub fn remote_qpn(&self) -> u32 {
+        self.param.remote_qpn
+    }
+
+    /// Returns the first PSN the peer sends with.
+    pub fn starting_psn(&self) -> Psn {
+        Psn::new(self.param.starting_psn)
+    }
+
+    /// Returns the RDMA reads and atomics the peer accepts at once.
+    pub fn responder_resources(&self) -> u8 {
+        self.param.responder_resources
+    }
+
+    /// Returns the RDMA reads and atomics the peer sends at once.
+    pub fn initiator_depth(&self) -> u8 {
+        self.param.initiator_depth
+    }
+
+    /// Returns the private data of the REP.
+    pub fn private_data(&self) -> &[u8] {
+        self.private_data
+    }
+}
+
+/// A received REJ.
+pub struct RejEvent<'a> {
+    reason: RejReason,
+    private_data: &'a [u8],
+}
+
+impl RejEvent<'_> {
+    /// Returns why the peer rejected the connection.
+    pub fn reason(&self) -> RejReason {
+        self.reason
+    }
+
+    /// Returns the private data of the REJ.
+    pub fn private_data(&self) -> &[u8] {
+        self.private_data
+    }
+}
+
+/// Handles the events of the communication IDs of a [`Connection`] or a [`Listener`].
+///
+/// Each callback runs for one ID at a time, in process context, and may sleep.
+#[vtable]
+pub trait CmEventHandler: Send + Sync + Sized {
+    /// Called on a listener for every REQ to its service ID.
+    ///
+    /// The handler calls [`Incoming::accept`] to keep the new ID, and then sends a REP or a REJ
+    /// on it. The REQ is rejected if the handler returns an error, or does not accept the ID.
+    fn req_received(&self, _id: Incoming<'_, Self>, _req: &ReqEvent<'_>) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Called when the peer accepted the REQ. The handler moves the QP to RTS and sends an RTU;
+    /// the connection is rejected if it returns an error.
+    fn rep_received(&self, _id: &CmId, _rep: &RepEvent<'_>) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Called when the connection is established: an RTU arrived, or
+    /// [`CmId::notify_established`] was called.
+    fn established(&self, _id: &CmId) {}
+
+    /// Called when the peer rejected the connection.
+    fn rej_received(&self, _id: &CmId, _rej: &RejEvent<'_>) {}
+
+    /// Called when the peer tears the connection down; by default a DREP is sent at once.
+    fn dreq_received(&self, id: &CmId, _private_data: &[u8]) {
+        let _ = id.send_drep(&[]);
+    }
+
+    /// Called when the peer answered the DREQ sent by [`CmId::send_dreq`].
+    fn drep_received(&self, _id: &CmId, _private_data: &[u8]) {}
+
+    /// Called when `message`, a REQ, REP or DREQ, was not answered in time.
+    fn timed_out(&self, _id: &CmId, _message: CmMessage) {}
+
+    /// Called once the QP of a torn down connection may be reused.
+    fn timewait_exit(&self, _id: &CmId) {}
+}
+
+/// A communication ID created for a REQ received by a [`Listener`].
+///
+/// Only lent to [`CmEventHandler::req_received`]; the ID is destroyed, rejecting the REQ, unless
+/// the handler accepts it.
+pub struct Incoming<'a, T: CmEventHandler> {
+    id: &'a CmId,
+    accepted: &'a Cell<bool>,
+    phantom: marker::PhantomData<T>,
+}
+
+impl<T: CmEventHandler> Incoming<'_, T> {
+    /// Keeps the ID as a connection whose events go to `handler`.
+    ///
+    /// The connection may be dropped before the callback returns, e.g. if the REP cannot be sent:
+    /// ib_cm then destroys the ID, rejecting the REQ. On error the ID is not kept, and ib_cm
+    /// destroys it once the callback returns.
+    pub fn accept(self, handler: Pin<Box<T>>) -> Result<Connection<T>> {
+        let context = IdContext::try_new(handler)?;
+        // The callback of the REQ is the first one of the new ID.
+        context.enter();
+        // SAFETY: The ID is valid, and its callbacks do not run while this one does, so the next
+        // one sees the new context. The context lives until the connection destroys the ID.
+        unsafe { (*self.id.as_ptr()).context = &*context as *const IdContext<T> as *mut _ };
+        self.accepted.set(true);
+        // INVARIANT: The ID was created by ib_cm for us and its context is `context`.
+        Ok(Connection {
+            ptr: self.id.as_ptr(),
+            context: ManuallyDrop::new(context),
+        })
+    }
+}
+
+impl<T: CmEventHandler> Deref for Incoming<'_, T> {
+    type Target = CmId;
+
+    fn deref(&self) -> &CmId {
+        self.id
+    }
+}
+
+/// A communication ID of a connection, destroyed on drop.
+///
+/// If it is dropped from a callback of the ID, the destruction is left to ib_cm once the callback
+/// returns.
+///
+/// # Invariants
+///
+/// `ptr` is a live ID whose context is `context`, both owned by the instance.
+pub struct Connection<T: CmEventHandler> {
+    ptr: *mut bindings::ib_cm_id,
+    context: ManuallyDrop<Box<IdContext<T>>>,
+}
+
+// SAFETY: The ID may be used and destroyed from any thread, and the handler is `Send`.
+unsafe impl<T: CmEventHandler> Send for Connection<T> {}
+// SAFETY: The verbs of the ID serialise on its lock, and the handler is `Sync`.
+unsafe impl<T: CmEventHandler> Sync for Connection<T> {}
+
+impl<T: CmEventHandler> Connection<T> {
+    /// Creates an ID on `dev` whose events go to `handler`, e.g. to send a REQ.
+    pub fn new(dev: &ObservedDevice, handler: Pin<Box<T>>) -> Result<Self> {
+        let context = IdContext::try_new(handler)?;
+        // SAFETY: The device is valid, and the context outlives the ID, which `drop` destroys.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::ib_create_cm_id(
+                dev.as_ptr(),
+                Some(cm_handler::<T>),
+                &*context as *const IdContext<T> as *mut _,
+            )
+        })?;
+        // INVARIANT: The ID was just created with `context` as context.
+        Ok(Self {
+            ptr,
+            context: ManuallyDrop::new(context),
+        })
+    }
+
+    /// Returns the handler of the connection.
+    pub fn handler(&self) -> Pin<&T> {
+        self.context.handler.as_ref()
+    }
+}
+
+impl<T: CmEventHandler> Deref for Connection<T> {
+    type Target = CmId;
+
+    fn deref(&self) -> &CmId {
+        // SAFETY: By the type invariants the ID is live.
+        unsafe { CmId::from_ptr(self.ptr) }
+    }
+}
+
+impl<T: CmEventHandler> Drop for Connection<T> {
+    fn drop(&mut self) {
+        if self.context.doom_from_callback() {
+            // Dropped from a callback of the ID, which `ib_destroy_cm_id` would wait for: the
+            // callback returns nonzero for ib_cm to destroy the ID, and frees the context.
+            return;
+        }
+        // SAFETY: By the type invariants we own the ID. Destroying it waits for its callback in
+        // progress, so the context is not used once this returns.
+        unsafe { bindings::ib_destroy_cm_id(self.ptr) };
+        // SAFETY: The context is not used anymore, and dropped only here.
+        unsafe { ManuallyDrop::drop(&mut self.context) };
+    }
+}
+
+/// A communication ID listening for the REQs to a service ID, destroyed on drop.
+///
+/// The REQs go to [`CmEventHandler::req_received`] of the handler; the connections accepted stay
+/// up when the listener is dropped. It must not be dropped from the callback of a REQ, which
+/// holds it until it returns.
+pub struct Listener<T: CmEventHandler>(Connection<T>);
+
+impl<T: CmEventHandler> Listener<T> {
+    /// Listens for the REQs to `service_id` on `dev`.
+    pub fn new(dev: &ObservedDevice, service_id: u64, handler: Pin<Box<T>>) -> Result<Self> {
+        if !T::HAS_REQ_RECEIVED {
+            return Err(EINVAL);
+        }
+        let id = Connection::new(dev, handler)?;
+        // SAFETY: The ID is valid and was just created.
+        to_result(unsafe { bindings::ib_cm_listen(id.as_ptr(), service_id.to_be()) })?;
+        Ok(Self(id))
+    }
+
+    /// Returns the service ID listened to.
+    pub fn service_id(&self) -> u64 {
+        self.0.service_id()
+    }
+
+    /// Returns the handler of the listener.
+    pub fn handler(&self) -> Pin<&T> {
+        self.0.handler()
+    }
+}
+
+unsafe extern "C" fn cm_handler<T: CmEventHandler>(
+    cm_id: *mut bindings::ib_cm_id,
+    event: *const bindings::ib_cm_event,
+) -> core::ffi::c_int {
+    // SAFETY: ib_cm passes a live ID, whose context is the context of the `Connection` or the
+    // `Listener` owning it; the ID of a REQ inherits the context of its listener.
+    let (id, context, event) = unsafe {
+        (
+            CmId::from_ptr(cm_id),
+            &*(*cm_id).context.cast::<IdContext<T>>(),
+            &*event,
+        )
+    };
+    let handler = &*context.handler;
+    // SAFETY: The private data of a received message lives as long as its event.
+    let data = |message| unsafe { private_data(event.private_data, message) };
+    if event.event != bindings::ib_cm_event_type_IB_CM_REQ_RECEIVED {
+        context.enter();
+    }
+    match event.event {
+        bindings::ib_cm_event_type_IB_CM_REQ_RECEIVED => {
+            let accepted = Cell::new(false);
+            let req = ReqEvent {
+                // SAFETY: The event is a REQ, so the union holds its parameters.
+                param: unsafe { &event.param.req_rcvd },
+                private_data: data(CmMessage::Req),
+            };
+            let incoming = Incoming {
+                id,
+                accepted: &accepted,
+                phantom: marker::PhantomData,
+            };
+            let ret = handler.req_received(incoming, &req);
+            if accepted.get() {
+                // The connection owns the ID now, even if the handler failed, unless it was
+                // dropped already.
+                // SAFETY: `accept` set the context of the connection, and the callback ends.
+                return unsafe { IdContext::<T>::leave((*cm_id).context.cast()) };
+            }
+            // ib_cm destroys the ID, which rejects the REQ.
+            return ret.err().unwrap_or(EINVAL).to_kernel_errno();
+        }
+        bindings::ib_cm_event_type_IB_CM_REP_RECEIVED => {
+            let rep = RepEvent {
+                // SAFETY: The event is a REP, so the union holds its parameters.
+                param: unsafe { &event.param.rep_rcvd },
+                private_data: data(CmMessage::Rep),
+            };
+            if handler.rep_received(id, &rep).is_err() {
+                let _ = id.send_rej(RejReason::ConsumerDefined, &[]);
+            }
+        }
+        bindings::ib_cm_event_type_IB_CM_RTU_RECEIVED
+        | bindings::ib_cm_event_type_IB_CM_USER_ESTABLISHED => handler.established(id),
+        bindings::ib_cm_event_type_IB_CM_REJ_RECEIVED => {
+            let rej = RejEvent {
+                // SAFETY: The event is a REJ, so the union holds its parameters.
+                reason: RejReason::from_code(unsafe { event.param.rej_rcvd.reason } as u16),
+                private_data: data(CmMessage::Rej),
+            };
+            handler.rej_received(id, &rej);
+        }
+        bindings::ib_cm_event_type_IB_CM_DREQ_RECEIVED => {
+            handler.dreq_received(id, data(CmMessage::Dreq))
+        }
+        bindings::ib_cm_event_type_IB_CM_DREP_RECEIVED => {
+            handler.drep_received(id, data(CmMessage::Drep))
+        }
+        bindings::ib_cm_event_type_IB_CM_REQ_ERROR => handler.timed_out(id, CmMessage::Req),
+        bindings::ib_cm_event_type_IB_CM_REP_ERROR => handler.timed_out(id, CmMessage::Rep),
+        bindings::ib_cm_event_type_IB_CM_DREQ_ERROR => handler.timed_out(id, CmMessage::Dreq),
+        bindings::ib_cm_event_type_IB_CM_TIMEWAIT_EXIT => handler.timewait_exit(id),
+        // MRAs are handled by ib_cm, and path migration and SIDR are not supported.
+        _ => {}
+    }
+    // SAFETY: The context is the one of the ID, not used after the callback ends.
+    unsafe { IdContext::leave(context as *const IdContext<T> as *mut IdContext<T>) }
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! The context of the IDs of ib_cm and rdma_cm.
+//!
+//! Both connection managers call the handler of an ID one event at a time, and destroy the ID
+//! when its callback returns nonzero. Destroying the ID otherwise waits for the callback in
+//! progress, so an ID dropped from its own callback is left to the connection manager instead:
+//! the callback then returns nonzero and frees the context.
+
+use alloc::boxed::Box;
+use core::pin::Pin;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::bindings;
+use crate::error::{code::*, Result};
+
+/// The context of an ID: its handler, and the state of the callback in progress.
+pub(crate) struct IdContext<H> {
+    pub(crate) handler: Pin<Box<H>>,
+    /// The task running a callback of the ID, null if none.
+    running: AtomicPtr<bindings::task_struct>,
+    /// Set when the owner of the ID was dropped from a callback of the ID, which then returns
+    /// nonzero for the connection manager to destroy the ID, and frees the context.
+    doomed: AtomicBool,
+}
+
+impl<H> IdContext<H> {
+    pub(crate) fn try_new(handler: Pin<Box<H>>) -> Result<Box<Self>> {
+        Ok(Box::try_new(Self {
+            handler,
+            running: AtomicPtr::new(ptr::null_mut()),
+            doomed: AtomicBool::new(false),
+        })?)
+    }
+
+    /// Marks the callback of the ID in progress as run by the current task.
+    pub(crate) fn enter(&self) {
+        // SAFETY: Reading the current task has no requirements.
+        self.running
+            .store(unsafe { bindings::get_current() }, Ordering::Relaxed);
+    }
+
+    /// Leaves the ID to the connection manager if the current task runs its callback.
+    ///
+    /// Returns `false` if the caller must destroy the ID itself, which waits for the callback in
+    /// progress.
+    pub(crate) fn doom_from_callback(&self) -> bool {
+        // SAFETY: Reading the current task has no requirements.
+        let current = unsafe { bindings::get_current() };
+        if self.running.load(Ordering::Relaxed) != current {
+            return false;
+        }
+        self.doomed.store(true, Ordering::Relaxed);
+        true
+    }
+
+    /// Ends the callback in progress, returning nonzero for the connection manager to destroy the
+    /// ID if its owner was dropped.
+    ///
+    /// # Safety
+    ///
+    /// `this` must be the context of the ID whose callback is ending, and not be used afterwards
+    /// if nonzero is returned.
+    pub(crate) unsafe fn leave(this: *mut Self) -> core::ffi::c_int {
+        // SAFETY: The context lives until the owner of the ID is dropped, which either waits for
+        // the callback to return or leaves the context to it.
+        if unsafe { (*this).doomed.load(Ordering::Relaxed) } {
+            // SAFETY: The dropped owner leaked the context, which no one else uses now.
+            drop(unsafe { Box::from_raw(this) });
+            return EINVAL.to_kernel_errno();
+        }
+        // SAFETY: As above.
+        unsafe { (*this).running.store(ptr::null_mut(), Ordering::Relaxed) };
+        0
+    }
+}
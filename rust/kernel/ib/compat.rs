@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Adapters over the kernel crate's generic abstractions.
+//!
+//! The RDMA modules were written against the `rust` development branch, while upstream
+//! Rust-for-Linux grew its own versions of several building blocks: reference-counted C objects
+//! (`kernel::types::{ARef, AlwaysRefCounted}`), owned workqueues and pinned heap allocation
+//! (`Box::pin` and pin-init). The providers use the adapters of this module instead of the branch
+//! APIs, with the upstream names and signatures, so that moving to upstream only touches this file.
+//!
+//! [`ARef`] and [`AlwaysRefCounted`] are a copy of the upstream types, which the branch lacks.
+
+use alloc::boxed::Box;
+use core::fmt;
+use core::marker;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr::NonNull;
+
+use crate::bindings;
+use crate::error::Result;
+use crate::workqueue::{BoxedQueue, Queue};
+
+/// Types whose instances are reference counted by C code.
+///
+/// # Safety
+///
+/// Implementers must ensure that `inc_ref` keeps the object alive until the matching `dec_ref`,
+/// and that shared references to it are only handed out while it is alive.
+pub unsafe trait AlwaysRefCounted {
+    /// Takes a reference on the object.
+    fn inc_ref(&self);
+
+    /// Releases a reference on the object, which may free it.
+    ///
+    /// # Safety
+    ///
+    /// The caller must own a reference on `obj`, and must not use `obj` afterwards.
+    unsafe fn dec_ref(obj: NonNull<Self>);
+}
+
+/// An owned reference to an [`AlwaysRefCounted`] object.
+///
+/// # Invariants
+///
+/// `ptr` is valid and `self` owns one reference on it.
+pub struct ARef<T: AlwaysRefCounted> {
+    ptr: NonNull<T>,
+    _p: marker::PhantomData<T>,
+}
+
+impl<T: AlwaysRefCounted> ARef<T> {
+    /// Takes over a reference the caller owns.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid, and the caller must own a reference on it that is released by the
+    /// returned [`ARef`] instead.
+    pub unsafe fn from_raw(ptr: NonNull<T>) -> Self {
+        // INVARIANT: Guaranteed by the safety requirements.
+        Self {
+            ptr,
+            _p: marker::PhantomData,
+        }
+    }
+}
+
+impl<T: AlwaysRefCounted> Clone for ARef<T> {
+    fn clone(&self) -> Self {
+        self.inc_ref();
+        // SAFETY: We just took the reference the new instance owns.
+        unsafe { Self::from_raw(self.ptr) }
+    }
+}
+
+impl<T: AlwaysRefCounted> From<&T> for ARef<T> {
+    fn from(obj: &T) -> Self {
+        obj.inc_ref();
+        // SAFETY: We just took the reference the new instance owns.
+        unsafe { Self::from_raw(NonNull::from(obj)) }
+    }
+}
+
+impl<T: AlwaysRefCounted> Deref for ARef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: By the type invariants the object is alive while we own a reference.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T: AlwaysRefCounted> Drop for ARef<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants we own a reference, which is never used again.
+        unsafe { T::dec_ref(self.ptr) };
+    }
+}
+
+// SAFETY: References can be released from any thread when `T` can be shared across threads.
+unsafe impl<T: AlwaysRefCounted + Sync + Send> Send for ARef<T> {}
+
+// SAFETY: `ARef<T>` only hands out `&T`.
+unsafe impl<T: AlwaysRefCounted + Sync + Send> Sync for ARef<T> {}
+
+/// A workqueue allocated by the provider and destroyed on drop.
+pub struct OwnedQueue {
+    queue: BoxedQueue,
+}
+
+impl OwnedQueue {
+    /// Allocates a workqueue named after `name`, with `WQ_*` `flags` and at most `max_active`
+    /// work items running at once, 0 meaning the default.
+    pub fn try_new(name: fmt::Arguments<'_>, flags: u32, max_active: i32) -> Result<Self> {
+        Ok(Self {
+            queue: Queue::try_new(name, flags, max_active)?,
+        })
+    }
+
+    /// Returns the raw `struct workqueue_struct` pointer.
+    ///
+    /// Gap in both APIs: needed to queue a work item embedded in a C structure or a `static`.
+    pub fn as_raw(&self) -> *mut bindings::workqueue_struct {
+        // `Queue` wraps the `struct workqueue_struct` it was created from.
+        &*self.queue as *const Queue as *mut bindings::workqueue_struct
+    }
+}
+
+impl Deref for OwnedQueue {
+    type Target = Queue;
+
+    fn deref(&self) -> &Queue {
+        &self.queue
+    }
+}
+
+/// Moves `value` to a pinned heap allocation, upstream's `Box::pin(value, GFP_KERNEL)`.
+pub fn try_pin<T>(value: T) -> Result<Pin<Box<T>>> {
+    Ok(Pin::from(Box::try_new(value)?))
+}
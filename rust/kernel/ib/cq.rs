@@ -0,0 +1,505 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Completion queues.
+
+use core::cell::UnsafeCell;
+use core::marker;
+use core::ptr;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::restrack::ResourceEntry;
+use super::Object;
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::rdma::cq_mode::CqModeState;
+
+pub use crate::rdma::cq_mode::CqMode;
+
+/// Status of a completed work request, `enum ib_wc_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WcStatus {
+    /// The work request completed successfully.
+    Success,
+    /// Local length error.
+    LocLenErr,
+    /// Local QP operation error.
+    LocQpOpErr,
+    /// Local protection error.
+    LocProtErr,
+    /// The work request was flushed because the QP entered the error state.
+    WrFlushErr,
+    /// Memory window bind error.
+    MwBindErr,
+    /// Bad response error.
+    BadRespErr,
+    /// Local access error.
+    LocAccessErr,
+    /// Remote invalid request error.
+    RemInvReqErr,
+    /// Remote access error.
+    RemAccessErr,
+    /// Remote operation error.
+    RemOpErr,
+    /// Transport retry counter exceeded.
+    RetryExcErr,
+    /// RNR retry counter exceeded.
+    RnrRetryExcErr,
+    /// Remote aborted error.
+    RemAbortErr,
+    /// Fatal error.
+    FatalErr,
+    /// Response timeout error.
+    RespTimeoutErr,
+    /// General error.
+    GeneralErr,
+}
+
+impl WcStatus {
+    fn to_raw(self) -> bindings::ib_wc_status {
+        match self {
+            WcStatus::Success => bindings::ib_wc_status_IB_WC_SUCCESS,
+            WcStatus::LocLenErr => bindings::ib_wc_status_IB_WC_LOC_LEN_ERR,
+            WcStatus::LocQpOpErr => bindings::ib_wc_status_IB_WC_LOC_QP_OP_ERR,
+            WcStatus::LocProtErr => bindings::ib_wc_status_IB_WC_LOC_PROT_ERR,
+            WcStatus::WrFlushErr => bindings::ib_wc_status_IB_WC_WR_FLUSH_ERR,
+            WcStatus::MwBindErr => bindings::ib_wc_status_IB_WC_MW_BIND_ERR,
+            WcStatus::BadRespErr => bindings::ib_wc_status_IB_WC_BAD_RESP_ERR,
+            WcStatus::LocAccessErr => bindings::ib_wc_status_IB_WC_LOC_ACCESS_ERR,
+            WcStatus::RemInvReqErr => bindings::ib_wc_status_IB_WC_REM_INV_REQ_ERR,
+            WcStatus::RemAccessErr => bindings::ib_wc_status_IB_WC_REM_ACCESS_ERR,
+            WcStatus::RemOpErr => bindings::ib_wc_status_IB_WC_REM_OP_ERR,
+            WcStatus::RetryExcErr => bindings::ib_wc_status_IB_WC_RETRY_EXC_ERR,
+            WcStatus::RnrRetryExcErr => bindings::ib_wc_status_IB_WC_RNR_RETRY_EXC_ERR,
+            WcStatus::RemAbortErr => bindings::ib_wc_status_IB_WC_REM_ABORT_ERR,
+            WcStatus::FatalErr => bindings::ib_wc_status_IB_WC_FATAL_ERR,
+            WcStatus::RespTimeoutErr => bindings::ib_wc_status_IB_WC_RESP_TIMEOUT_ERR,
+            WcStatus::GeneralErr => bindings::ib_wc_status_IB_WC_GENERAL_ERR,
+        }
+    }
+}
+
+/// Operation of a completed work request, `enum ib_wc_opcode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WcOpcode {
+    /// SEND.
+    Send,
+    /// RDMA WRITE.
+    RdmaWrite,
+    /// RDMA READ.
+    RdmaRead,
+    /// Atomic compare and swap.
+    CompSwap,
+    /// Atomic fetch and add.
+    FetchAdd,
+    /// Memory window bind.
+    BindMw,
+    /// Local invalidate.
+    LocalInv,
+    /// Fast memory registration.
+    RegMr,
+    /// Incoming SEND.
+    Recv,
+    /// Incoming RDMA WRITE with immediate data.
+    RecvRdmaWithImm,
+}
+
+impl WcOpcode {
+    fn to_raw(self) -> bindings::ib_wc_opcode {
+        match self {
+            WcOpcode::Send => bindings::ib_wc_opcode_IB_WC_SEND,
+            WcOpcode::RdmaWrite => bindings::ib_wc_opcode_IB_WC_RDMA_WRITE,
+            WcOpcode::RdmaRead => bindings::ib_wc_opcode_IB_WC_RDMA_READ,
+            WcOpcode::CompSwap => bindings::ib_wc_opcode_IB_WC_COMP_SWAP,
+            WcOpcode::FetchAdd => bindings::ib_wc_opcode_IB_WC_FETCH_ADD,
+            WcOpcode::BindMw => bindings::ib_wc_opcode_IB_WC_BIND_MW,
+            WcOpcode::LocalInv => bindings::ib_wc_opcode_IB_WC_LOCAL_INV,
+            WcOpcode::RegMr => bindings::ib_wc_opcode_IB_WC_REG_MR,
+            WcOpcode::Recv => bindings::ib_wc_opcode_IB_WC_RECV,
+            WcOpcode::RecvRdmaWithImm => bindings::ib_wc_opcode_IB_WC_RECV_RDMA_WITH_IMM,
+        }
+    }
+}
+
+/// Extra data carried by a completion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WcEx {
+    /// Nothing.
+    None,
+    /// Immediate data, in network byte order as received.
+    Imm(u32),
+    /// R_Key invalidated by a SEND with invalidate.
+    Invalidate(u32),
+}
+
+/// A work completion, the typed equivalent of `struct ib_wc`.
+#[derive(Clone, Copy, Debug)]
+pub struct WorkCompletion {
+    /// Identifier of the completed work request.
+    pub wr_id: u64,
+    /// Completion status.
+    pub status: WcStatus,
+    /// Completed operation.
+    pub opcode: WcOpcode,
+    /// Provider specific error syndrome.
+    pub vendor_err: u32,
+    /// Number of bytes transferred, for receives and RDMA READs.
+    pub byte_len: u32,
+    /// Immediate data or invalidated key.
+    pub ex: WcEx,
+    /// QP the work request was posted to.
+    pub qp: *mut bindings::ib_qp,
+    /// Source QP number, for UD receives.
+    pub src_qp: u32,
+    /// Source LID, for UD receives on InfiniBand.
+    pub slid: u32,
+    /// P_Key index, for UD receives on GSI QPs.
+    pub pkey_index: u16,
+    /// Service level.
+    pub sl: u8,
+    /// Port the packet was received on.
+    pub port_num: u32,
+    /// `IB_WC_*` flags besides the ones implied by `ex`.
+    pub wc_flags: u32,
+}
+
+impl WorkCompletion {
+    /// Creates a completion of `opcode` for `wr_id` posted on `qp`, with every other field zero.
+    pub fn new(wr_id: u64, status: WcStatus, opcode: WcOpcode, qp: *mut bindings::ib_qp) -> Self {
+        Self {
+            wr_id,
+            status,
+            opcode,
+            vendor_err: 0,
+            byte_len: 0,
+            ex: WcEx::None,
+            qp,
+            src_qp: 0,
+            slid: 0,
+            pkey_index: 0,
+            sl: 0,
+            port_num: 0,
+            wc_flags: 0,
+        }
+    }
+
+    pub(crate) fn write_to(&self, raw: &mut bindings::ib_wc) {
+        *raw = bindings::ib_wc::default();
+        raw.__bindgen_anon_1.wr_id = self.wr_id;
+        raw.status = self.status.to_raw();
+        raw.opcode = self.opcode.to_raw();
+        raw.vendor_err = self.vendor_err;
+        raw.byte_len = self.byte_len;
+        raw.qp = self.qp;
+        raw.src_qp = self.src_qp;
+        raw.slid = self.slid;
+        raw.pkey_index = self.pkey_index;
+        raw.sl = self.sl;
+        raw.port_num = self.port_num;
+        raw.wc_flags = self.wc_flags as _;
+        match self.ex {
+            WcEx::None => {}
+            WcEx::Imm(imm) => {
+                raw.ex.imm_data = imm;
+                raw.wc_flags |= bindings::ib_wc_flags_IB_WC_WITH_IMM as core::ffi::c_int;
+            }
+            WcEx::Invalidate(rkey) => {
+                raw.ex.invalidate_rkey = rkey;
+                raw.wc_flags |= bindings::ib_wc_flags_IB_WC_WITH_INVALIDATE as core::ffi::c_int;
+            }
+        }
+    }
+}
+
+/// Creation attributes of a completion queue, from `struct ib_cq_init_attr`.
+#[derive(Clone, Copy, Debug)]
+pub struct CqInitAttr {
+    /// Number of entries; the provider may round it up, the final value is reported back to the
+    /// consumer.
+    pub cqe: u32,
+    /// Completion vector the consumer asked for.
+    pub comp_vector: u32,
+    /// `IB_UVERBS_CQ_FLAGS_*` creation flags.
+    pub flags: u32,
+}
+
+/// Which completions trigger the next notification, from `enum ib_cq_notify_flags`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CqNotify {
+    /// Only solicited completions, or any error completion.
+    Solicited,
+    /// Any completion.
+    NextComp,
+}
+
+const NOTIFY_NONE: u8 = 0;
+const NOTIFY_SOLICITED: u8 = 1;
+const NOTIFY_NEXT_COMP: u8 = 2;
+
+/// Arm state of a completion queue, kept in the provider's CQ data.
+///
+/// A consumer arms the CQ through `req_notify_cq`; the next matching completion disarms it and
+/// calls the consumer's completion handler, see [`CompletionQueue::complete`].
+///
+/// A kernel consumer may switch the CQ to [`CqMode::Poll`] at runtime, e.g. for benchmarks:
+/// arming is then ignored and it polls with [`CompletionQueue::poll`] instead. Providers hand the
+/// notifier to [`IbDeviceOperations::cq_notifier`] so that consumers reach it through
+/// [`CompletionQueue::set_mode`].
+pub struct CqNotifier {
+    armed: AtomicU8,
+    mode: CqModeState,
+}
+
+impl CqNotifier {
+    /// Creates a disarmed notifier, in event mode.
+    pub const fn new() -> Self {
+        Self {
+            armed: AtomicU8::new(NOTIFY_NONE),
+            mode: CqModeState::new(),
+        }
+    }
+
+    /// Returns the current mode.
+    pub fn mode(&self) -> CqMode {
+        self.mode.mode()
+    }
+
+    /// Switches to `mode`.
+    ///
+    /// Switching to polling disarms the notifier; after switching back, the consumer must arm it
+    /// again before it is notified.
+    pub fn set_mode(&self, mode: CqMode) {
+        self.mode.set_mode(mode);
+        if mode == CqMode::Poll {
+            self.armed.store(NOTIFY_NONE, Ordering::Release);
+        }
+    }
+
+    /// Sets the largest number of completions returned by one [`CompletionQueue::poll`], 0
+    /// meaning unlimited.
+    pub fn set_poll_budget(&self, budget: u32) {
+        self.mode.set_budget(budget);
+    }
+
+    /// Returns the poll budget, 0 meaning unlimited.
+    pub fn poll_budget(&self) -> u32 {
+        self.mode.budget()
+    }
+
+    /// Returns the number of arm requests ignored in poll mode.
+    pub fn suppressed(&self) -> u64 {
+        self.mode.suppressed()
+    }
+
+    /// Arms the notifier; a pending `NextComp` request is not downgraded to `Solicited`.
+    ///
+    /// Does nothing in poll mode.
+    pub fn arm(&self, notify: CqNotify) {
+        if self.mode.suppress() {
+            return;
+        }
+        match notify {
+            CqNotify::Solicited => {
+                let _ = self.armed.compare_exchange(
+                    NOTIFY_NONE,
+                    NOTIFY_SOLICITED,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+            }
+            CqNotify::NextComp => self.armed.store(NOTIFY_NEXT_COMP, Ordering::Release),
+        }
+    }
+
+    /// Disarms the notifier and returns `true` if a completion with the given properties must
+    /// trigger the completion handler.
+    fn fire(&self, solicited: bool, error: bool) -> bool {
+        // An arm racing with the switch to polling may have slipped through.
+        if self.mode() == CqMode::Poll {
+            return false;
+        }
+        let armed = self.armed.load(Ordering::Acquire);
+        let matches =
+            armed == NOTIFY_NEXT_COMP || (armed == NOTIFY_SOLICITED && (solicited || error));
+        matches
+            && self
+                .armed
+                .compare_exchange(armed, NOTIFY_NONE, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+    }
+}
+
+impl Default for CqNotifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A completion queue of a device provided by `T`, wraps `struct ib_cq`.
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_cq` belongs to a device provided by `T` and is followed by an
+/// initialised `T::CqData`.
+#[repr(transparent)]
+pub struct CompletionQueue<T: IbDeviceOperations>(
+    UnsafeCell<bindings::ib_cq>,
+    marker::PhantomData<T>,
+);
+
+impl<T: IbDeviceOperations> CompletionQueue<T> {
+    /// Creates a reference to a [`CompletionQueue`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a CQ of a device provided by `T` whose `create_cq` succeeded and which is not
+    /// destroyed for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_cq) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `CompletionQueue` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_cq` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_cq {
+        self.0.get()
+    }
+
+    /// Returns the restrack record of the CQ, with its ID and creator.
+    pub fn restrack(&self) -> &ResourceEntry {
+        // SAFETY: The CQ is valid and embeds its entry.
+        unsafe { ResourceEntry::from_ptr(ptr::addr_of_mut!((*self.as_ptr()).res)) }
+    }
+
+    /// Returns the device the CQ belongs to.
+    pub fn device(&self) -> &DeviceRef<T> {
+        // SAFETY: By the type invariants the CQ belongs to a device provided by `T`, which
+        // outlives all of its CQs.
+        unsafe { DeviceRef::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the provider data of the CQ.
+    pub fn data(&self) -> &T::CqData {
+        // SAFETY: By the type invariants the data is initialised.
+        unsafe { Object::<bindings::ib_cq, T::CqData>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns the number of entries of the CQ.
+    pub fn cqe(&self) -> u32 {
+        // SAFETY: The CQ is valid.
+        unsafe { (*self.as_ptr()).cqe as u32 }
+    }
+
+    /// Tells the consumer about a newly posted completion.
+    ///
+    /// Calls the consumer's completion handler if `notifier` was armed for a completion that is
+    /// `solicited` or an `error`, disarming it.
+    pub fn complete(&self, notifier: &CqNotifier, solicited: bool, error: bool) {
+        if !notifier.fire(solicited, error) {
+            return;
+        }
+        // SAFETY: The CQ is valid; the handler and its context are set by ib_core at creation
+        // and stay valid until the CQ is destroyed.
+        unsafe {
+            let cq = self.as_ptr();
+            if let Some(handler) = (*cq).comp_handler {
+                handler(cq, (*cq).cq_context);
+            }
+        }
+    }
+
+    /// Polls up to the poll budget of `notifier` completions, passing each of them to `f`.
+    ///
+    /// Returns the number of completions polled. This is the polling path of a kernel consumer
+    /// that put the CQ in [`CqMode::Poll`], but works in either mode.
+    pub fn poll(&self, notifier: &CqNotifier, f: impl FnMut(WorkCompletion)) -> usize {
+        self.process_direct(notifier.mode.entries(self.cqe() as usize), f)
+    }
+
+    /// Switches the CQ to `mode`, e.g. for a benchmark turning notifications off to busy-poll.
+    ///
+    /// Fails with `EOPNOTSUPP` if the provider does not expose the notifier of its CQs, see
+    /// [`IbDeviceOperations::cq_notifier`].
+    pub fn set_mode(&self, mode: CqMode) -> Result {
+        T::cq_notifier(self).ok_or(EOPNOTSUPP)?.set_mode(mode);
+        Ok(())
+    }
+
+    /// Sets the largest number of completions returned by one poll, 0 meaning unlimited.
+    ///
+    /// Fails with `EOPNOTSUPP` like [`CompletionQueue::set_mode`].
+    pub fn set_poll_budget(&self, budget: u32) -> Result {
+        T::cq_notifier(self)
+            .ok_or(EOPNOTSUPP)?
+            .set_poll_budget(budget);
+        Ok(())
+    }
+
+    /// Returns how many of the `requested` completions a poll may return under the poll budget.
+    pub(crate) fn poll_entries(&self, requested: usize) -> usize {
+        match T::cq_notifier(self) {
+            Some(notifier) => notifier.mode.entries(requested),
+            None => requested,
+        }
+    }
+
+    /// Passes up to `budget` completions to `f`, in order, and returns how many it got.
+    ///
+    /// This is `ib_process_cq_direct` for Rust consumers: every completion goes straight from
+    /// the provider to `f`, without an intermediate array of `struct ib_wc`, and the CQ is not
+    /// rearmed. A consumer driving the CQ from its own context, e.g. a polling thread, calls it
+    /// until it returns less than `budget`.
+    pub fn process_direct(&self, budget: usize, mut f: impl FnMut(WorkCompletion)) -> usize {
+        let mut done = 0;
+        while done < budget {
+            match T::poll_cq(self) {
+                Some(wc) => f(wc),
+                None => break,
+            }
+            done += 1;
+        }
+        done
+    }
+
+    /// Passes up to `budget` completions to `f` like [`CompletionQueue::process_direct`], and
+    /// rearms `notifier` for `notify` if the CQ was drained within the budget.
+    ///
+    /// Completions that arrive between the last poll and the arming do not fire the handler, so
+    /// the CQ is polled once more after arming, as ib_core does with
+    /// `IB_CQ_REPORT_MISSED_EVENTS`. This is the body of a completion handler or of the work it
+    /// schedules: when this returns `budget`, more completions may be waiting and the caller
+    /// reschedules itself rather than waiting for the handler.
+    pub fn process(
+        &self,
+        notifier: &CqNotifier,
+        budget: usize,
+        notify: CqNotify,
+        mut f: impl FnMut(WorkCompletion),
+    ) -> usize {
+        let mut done = self.process_direct(budget, &mut f);
+        while done < budget {
+            notifier.arm(notify);
+            let missed = self.process_direct(budget - done, &mut f);
+            if missed == 0 {
+                break;
+            }
+            done += missed;
+        }
+        done
+    }
+
+    /// Reports an asynchronous CQ error (overrun or access error) to the consumer.
+    pub fn report_error(&self) {
+        // SAFETY: The CQ is valid; the handler and its context are set by ib_core at creation.
+        unsafe {
+            let cq = self.as_ptr();
+            if let Some(handler) = (*cq).event_handler {
+                let mut event = bindings::ib_event::default();
+                event.device = (*cq).device;
+                event.event = bindings::ib_event_type_IB_EVENT_CQ_ERR;
+                event.element.cq = cq;
+                handler(&mut event, (*cq).cq_context);
+            }
+        }
+    }
+}
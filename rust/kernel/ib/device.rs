@@ -0,0 +1,2174 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! InfiniBand devices.
+//!
+//! C header: [`include/rdma/ib_verbs.h`](../../../../include/rdma/ib_verbs.h)
+
+use core::cell::UnsafeCell;
+use core::fmt::Write;
+use core::marker;
+use core::mem;
+use core::ops::Deref;
+use core::pin::Pin;
+use macros::vtable;
+
+use super::ah::{AddressHandle, AhObject, RdmaAhAttr};
+use super::cq::{CompletionQueue, CqInitAttr, CqNotifier, CqNotify, WorkCompletion};
+use super::gid::{GidAttr, GidTable};
+use super::hw_stats::HwStats;
+use super::mr::{MemoryRegion, NewMr};
+use super::mw::{MemoryWindow, MwObject, MwType, NewMw};
+use super::netdev::NetDevice;
+use super::netlink::{DriverAttrs, StatCounters};
+use super::observer;
+use super::pd::ProtectionDomain;
+use super::qp::{self, QpAttr, QpInitAttr, QpObject, QpState, QueuePair};
+use super::restrack::{self, ResourceRef};
+use super::srq::{SharedReceiveQueue, SrqAttr, SrqAttrMask, SrqObject};
+use super::sysfs::AttributeGroup;
+use super::umem::Umem;
+use super::wr::{RecvWr, SendWr};
+use super::Object;
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Error, Result};
+use crate::rdma::flap::LinkEvent;
+use crate::rdma::fw_str::FwStr;
+use crate::rdma::gid::Gid;
+use crate::rdma::link_params::LinkParams;
+use crate::rdma::mad::{MadResult, GRH_SIZE, MAD_SIZE};
+use crate::rdma::mr_cache::MrGeneration;
+use crate::rdma::odp::OdpCaps;
+use crate::rdma::page_size::MrLimits;
+use crate::rdma::qp_fault::QpFaultInjector;
+use crate::rdma::tracker::ResourceKind;
+use crate::rdma::tunables::QpLimits;
+use crate::str::CStr;
+use crate::ThisModule;
+
+/// State of a port, `enum ib_port_state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PortState {
+    /// Reserved.
+    Nop = 0,
+    /// The link is down.
+    Down = 1,
+    /// The link is up, the port is not configured yet.
+    Init = 2,
+    /// The port is configured but not forwarding yet.
+    Armed = 3,
+    /// The port is fully operational.
+    Active = 4,
+    /// The port is operational but deferring errors.
+    ActiveDefer = 5,
+}
+
+impl PortState {
+    /// Converts a raw `enum ib_port_state` value.
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        Some(match raw {
+            0 => PortState::Nop,
+            1 => PortState::Down,
+            2 => PortState::Init,
+            3 => PortState::Armed,
+            4 => PortState::Active,
+            5 => PortState::ActiveDefer,
+            _ => return None,
+        })
+    }
+}
+
+/// Path MTU, `enum ib_mtu`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Mtu {
+    /// 256 bytes.
+    Mtu256 = 1,
+    /// 512 bytes.
+    Mtu512 = 2,
+    /// 1024 bytes.
+    Mtu1024 = 3,
+    /// 2048 bytes.
+    Mtu2048 = 4,
+    /// 4096 bytes.
+    Mtu4096 = 5,
+}
+
+impl Mtu {
+    /// Converts a raw `enum ib_mtu` value.
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        Some(match raw {
+            1 => Mtu::Mtu256,
+            2 => Mtu::Mtu512,
+            3 => Mtu::Mtu1024,
+            4 => Mtu::Mtu2048,
+            5 => Mtu::Mtu4096,
+            _ => return None,
+        })
+    }
+
+    /// Returns the MTU in bytes.
+    pub const fn bytes(self) -> u32 {
+        128 << (self as u32)
+    }
+
+    /// Returns the largest MTU that fits in `bytes`, e.g. to derive the IB MTU from an Ethernet
+    /// MTU once the RoCE headers have been accounted for.
+    pub const fn floor(bytes: u32) -> Option<Self> {
+        Some(if bytes >= 4096 {
+            Mtu::Mtu4096
+        } else if bytes >= 2048 {
+            Mtu::Mtu2048
+        } else if bytes >= 1024 {
+            Mtu::Mtu1024
+        } else if bytes >= 512 {
+            Mtu::Mtu512
+        } else if bytes >= 256 {
+            Mtu::Mtu256
+        } else {
+            return None;
+        })
+    }
+}
+
+/// Driver a device belongs to, `enum rdma_driver_id`.
+///
+/// ib_core uses it to match devices with their driver, e.g. in `ib_unregister_driver` and for the
+/// uverbs driver bindings of rdma-core.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DriverId {
+    /// A driver without an ID of its own.
+    Unknown,
+    /// ConnectX-3, mlx4_ib.
+    Mlx4,
+    /// ConnectX-4 and later, mlx5_ib.
+    Mlx5,
+    /// Soft-RoCE.
+    Rxe,
+    /// Soft-iWARP.
+    Siw,
+}
+
+impl DriverId {
+    /// Returns the raw `RDMA_DRIVER_*` value.
+    pub const fn to_raw(self) -> bindings::rdma_driver_id {
+        match self {
+            DriverId::Unknown => bindings::rdma_driver_id_RDMA_DRIVER_UNKNOWN,
+            DriverId::Mlx4 => bindings::rdma_driver_id_RDMA_DRIVER_MLX4,
+            DriverId::Mlx5 => bindings::rdma_driver_id_RDMA_DRIVER_MLX5,
+            DriverId::Rxe => bindings::rdma_driver_id_RDMA_DRIVER_RXE,
+            DriverId::Siw => bindings::rdma_driver_id_RDMA_DRIVER_SIW,
+        }
+    }
+
+    /// Returns the link type of `rdma link add` for the soft transports, `None` for the drivers
+    /// of real hardware.
+    pub fn link_type(self) -> Option<&'static CStr> {
+        match self {
+            DriverId::Rxe => Some(crate::c_str!("rxe")),
+            DriverId::Siw => Some(crate::c_str!("siw")),
+            DriverId::Unknown | DriverId::Mlx4 | DriverId::Mlx5 => None,
+        }
+    }
+}
+
+/// Link layer of a port, `enum rdma_link_layer`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum LinkLayer {
+    /// Derived from the core capabilities.
+    Unspecified = 0,
+    /// Native InfiniBand.
+    Infiniband = 1,
+    /// Ethernet, i.e. RoCE or iWARP.
+    Ethernet = 2,
+}
+
+/// Transport protocol of a port, selects the `RDMA_CORE_PORT_*` capability set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortProtocol {
+    /// Native InfiniBand.
+    Ib,
+    /// RoCE v1.
+    Roce,
+    /// RoCE v2, i.e. InfiniBand transport over UDP.
+    RoceUdpEncap,
+    /// iWARP.
+    Iwarp,
+}
+
+impl PortProtocol {
+    fn core_cap_flags(self) -> u32 {
+        (match self {
+            PortProtocol::Ib => bindings::RDMA_CORE_PORT_IBA_IB,
+            PortProtocol::Roce => bindings::RDMA_CORE_PORT_IBA_ROCE,
+            PortProtocol::RoceUdpEncap => bindings::RDMA_CORE_PORT_IBA_ROCE_UDP_ENCAP,
+            PortProtocol::Iwarp => bindings::RDMA_CORE_PORT_IWARP,
+        }) as u32
+    }
+}
+
+/// Atomic operation support of a device, `enum ib_atomic_cap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AtomicCap {
+    /// Atomic operations are not supported.
+    None = 0,
+    /// Atomic operations are atomic with respect to other atomics of the same device only.
+    Hca = 1,
+    /// Atomic operations are atomic with respect to the CPUs and other devices too.
+    Global = 2,
+}
+
+macro_rules! attr_setters {
+    ($($(#[$doc:meta])* $setter:ident => $field:ident: $ty:ty;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $setter(&mut self, value: $ty) -> &mut Self {
+                self.0.$field = value as _;
+                self
+            }
+        )*
+    };
+}
+
+macro_rules! attr_getters {
+    ($($(#[$doc:meta])* $getter:ident => $field:ident: $ty:ty;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $getter(&self) -> $ty {
+                self.0.$field as _
+            }
+        )*
+    };
+}
+
+/// Device attributes reported by `query_device`, wraps `struct ib_device_attr`.
+#[repr(transparent)]
+pub struct DeviceAttr(bindings::ib_device_attr);
+
+impl DeviceAttr {
+    /// # Safety
+    ///
+    /// `ptr` must be valid and exclusively ours for the lifetime of the returned reference.
+    unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_device_attr) -> &'a mut Self {
+        // SAFETY: Guaranteed by the safety requirements and `repr(transparent)`.
+        unsafe { &mut *ptr.cast() }
+    }
+
+    /// Reports the memory registration limits of the device.
+    pub fn set_mr_limits(&mut self, limits: &MrLimits) -> &mut Self {
+        self.set_max_mr_size(limits.max_mr_size)
+            .set_page_size_cap(limits.page_size_cap.bits())
+    }
+
+    /// Reports the QP limits of the device, e.g. a [`TunableLimits`] snapshot.
+    ///
+    /// [`TunableLimits`]: crate::rdma::tunables::TunableLimits
+    pub fn set_qp_limits(&mut self, limits: &QpLimits) -> &mut Self {
+        self.set_max_qp_wr(limits.max_qp_wr)
+    }
+
+    /// Replaces the limits overridden in `params` for this device, see
+    /// [`crate::rdma::link_params`]. Call it last, after the provider's defaults.
+    pub fn set_link_params(&mut self, params: &LinkParams) -> &mut Self {
+        if let Some(max_qp) = params.max_qp {
+            self.set_max_qp(max_qp);
+        }
+        if let Some(max_qp_wr) = params.max_qp_wr {
+            self.set_max_qp_wr(max_qp_wr);
+        }
+        if let Some(max_cq) = params.max_cq {
+            self.set_max_cq(max_cq);
+        }
+        if let Some(max_mr) = params.max_mr {
+            self.set_max_mr(max_mr);
+        }
+        self
+    }
+
+    /// Reports the support of CMP&SWP and FETCH&ADD by the QPs of the device.
+    ///
+    /// Soft providers executing atomics with CPU instructions are [`AtomicCap::Global`].
+    pub fn set_atomic_cap(&mut self, cap: AtomicCap) -> &mut Self {
+        self.0.atomic_cap = cap as _;
+        self
+    }
+
+    /// Reports the on-demand paging support of the device, see [`crate::rdma::odp`].
+    ///
+    /// Devices supporting some faults also get `IBK_ON_DEMAND_PAGING`, which lets consumers
+    /// register ODP memory regions.
+    pub fn set_odp_caps(&mut self, caps: &OdpCaps) -> &mut Self {
+        let odp = &mut self.0.odp_caps;
+        odp.general_caps = caps.general_caps();
+        odp.per_transport_caps.rc_odp_caps = caps.rc.bits();
+        odp.per_transport_caps.uc_odp_caps = caps.uc.bits();
+        odp.per_transport_caps.ud_odp_caps = caps.ud.bits();
+        odp.per_transport_caps.xrc_odp_caps = caps.xrc.bits();
+        if caps.is_supported() {
+            self.0.kernel_cap_flags |= bindings::ib_kernel_cap_flags_IBK_ON_DEMAND_PAGING as u64;
+        }
+        self
+    }
+
+    /// Sets the system image GUID, in host byte order.
+    pub fn set_sys_image_guid(&mut self, guid: u64) -> &mut Self {
+        self.0.sys_image_guid = guid.to_be();
+        self
+    }
+
+    attr_setters! {
+        /// Sets the firmware version.
+        set_fw_ver => fw_ver: u64;
+        /// Sets the largest registrable memory region.
+        set_max_mr_size => max_mr_size: u64;
+        /// Sets the supported page sizes, as a bitmap of sizes.
+        set_page_size_cap => page_size_cap: u64;
+        /// Sets the IEEE vendor ID.
+        set_vendor_id => vendor_id: u32;
+        /// Sets the vendor part ID.
+        set_vendor_part_id => vendor_part_id: u32;
+        /// Sets the hardware version.
+        set_hw_ver => hw_ver: u32;
+        /// Sets the `IB_DEVICE_*` capability flags.
+        set_device_cap_flags => device_cap_flags: u64;
+        /// Sets the maximum number of QPs.
+        set_max_qp => max_qp: u32;
+        /// Sets the maximum number of work requests per queue.
+        set_max_qp_wr => max_qp_wr: u32;
+        /// Sets the maximum number of scatter/gather entries per send work request.
+        set_max_send_sge => max_send_sge: u32;
+        /// Sets the maximum number of scatter/gather entries per receive work request.
+        set_max_recv_sge => max_recv_sge: u32;
+        /// Sets the maximum number of scatter/gather entries per RDMA READ.
+        set_max_sge_rd => max_sge_rd: u32;
+        /// Sets the maximum number of CQs.
+        set_max_cq => max_cq: u32;
+        /// Sets the maximum number of entries per CQ.
+        set_max_cqe => max_cqe: u32;
+        /// Sets the maximum number of MRs.
+        set_max_mr => max_mr: u32;
+        /// Sets the maximum number of PDs.
+        set_max_pd => max_pd: u32;
+        /// Sets the maximum number of outstanding RDMA READs and atomics as a responder.
+        set_max_qp_rd_atom => max_qp_rd_atom: u32;
+        /// Sets the maximum number of outstanding RDMA READs and atomics as a requester.
+        set_max_qp_init_rd_atom => max_qp_init_rd_atom: u32;
+        /// Sets the maximum number of SRQs.
+        set_max_srq => max_srq: u32;
+        /// Sets the maximum number of work requests per SRQ.
+        set_max_srq_wr => max_srq_wr: u32;
+        /// Sets the maximum number of scatter/gather entries per SRQ work request.
+        set_max_srq_sge => max_srq_sge: u32;
+        /// Sets the maximum number of address handles.
+        set_max_ah => max_ah: u32;
+        /// Sets the maximum number of memory windows.
+        set_max_mw => max_mw: u32;
+        /// Sets the maximum number of multicast groups.
+        set_max_mcast_grp => max_mcast_grp: u32;
+        /// Sets the maximum number of QPs attached to one multicast group.
+        set_max_mcast_qp_attach => max_mcast_qp_attach: u32;
+        /// Sets the maximum number of QP attachments over all multicast groups.
+        set_max_total_mcast_qp_attach => max_total_mcast_qp_attach: u32;
+        /// Sets the number of partition keys.
+        set_max_pkeys => max_pkeys: u16;
+        /// Sets the local CA ACK delay.
+        set_local_ca_ack_delay => local_ca_ack_delay: u8;
+    }
+
+    attr_getters! {
+        /// Returns the firmware version.
+        fw_ver => fw_ver: u64;
+        /// Returns the largest registrable memory region.
+        max_mr_size => max_mr_size: u64;
+        /// Returns the maximum number of QPs.
+        max_qp => max_qp: u32;
+        /// Returns the maximum number of work requests per queue.
+        max_qp_wr => max_qp_wr: u32;
+        /// Returns the maximum number of CQs.
+        max_cq => max_cq: u32;
+        /// Returns the maximum number of entries per CQ.
+        max_cqe => max_cqe: u32;
+        /// Returns the maximum number of MRs.
+        max_mr => max_mr: u32;
+        /// Returns the maximum number of PDs.
+        max_pd => max_pd: u32;
+        /// Returns the maximum number of SRQs.
+        max_srq => max_srq: u32;
+        /// Returns the maximum number of work requests per SRQ.
+        max_srq_wr => max_srq_wr: u32;
+        /// Returns the maximum number of scatter/gather entries per SRQ work request.
+        max_srq_sge => max_srq_sge: u32;
+    }
+}
+
+/// Port attributes reported by `query_port`, wraps `struct ib_port_attr`.
+#[repr(transparent)]
+pub struct PortAttr(bindings::ib_port_attr);
+
+impl PortAttr {
+    /// # Safety
+    ///
+    /// `ptr` must be valid and exclusively ours for the lifetime of the returned reference.
+    unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_port_attr) -> &'a mut Self {
+        // SAFETY: Guaranteed by the safety requirements and `repr(transparent)`.
+        unsafe { &mut *ptr.cast() }
+    }
+
+    /// Queries the attributes of `port` of `ibdev` with `ib_query_port`.
+    ///
+    /// # Safety
+    ///
+    /// `ibdev` must be a valid device whose ops are set.
+    pub(crate) unsafe fn query(ibdev: *mut bindings::ib_device, port: u32) -> Result<Self> {
+        let mut attr = Self(bindings::ib_port_attr::default());
+        // SAFETY: The device is valid by the safety requirements and `attr` a valid out pointer;
+        // ib_core checks the port number.
+        let err = unsafe { bindings::ib_query_port(ibdev, port, &mut attr.0) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        Ok(attr)
+    }
+
+    /// Returns the logical port state, `None` if it is not a known one.
+    pub fn state(&self) -> Option<PortState> {
+        PortState::from_raw(self.0.state as u32)
+    }
+
+    /// Returns the currently active MTU, `None` if it is not a known one.
+    pub fn active_mtu(&self) -> Option<Mtu> {
+        Mtu::from_raw(self.0.active_mtu as u32)
+    }
+
+    /// Sets the logical port state.
+    pub fn set_state(&mut self, state: PortState) -> &mut Self {
+        self.0.state = state as _;
+        self
+    }
+
+    /// Sets the largest supported MTU.
+    pub fn set_max_mtu(&mut self, mtu: Mtu) -> &mut Self {
+        self.0.max_mtu = mtu as _;
+        self
+    }
+
+    /// Sets the currently active MTU.
+    pub fn set_active_mtu(&mut self, mtu: Mtu) -> &mut Self {
+        self.0.active_mtu = mtu as _;
+        self
+    }
+
+    attr_setters! {
+        /// Sets the physical port state, e.g. 5 for `LinkUp` and 3 for `Disabled`.
+        set_phys_state => phys_state: u8;
+        /// Sets the number of GID table entries.
+        set_gid_tbl_len => gid_tbl_len: u32;
+        /// Sets the `IB_PORT_*` capability flags.
+        set_port_cap_flags => port_cap_flags: u32;
+        /// Sets the largest message size.
+        set_max_msg_sz => max_msg_sz: u32;
+        /// Sets the number of P_Key table entries.
+        set_pkey_tbl_len => pkey_tbl_len: u16;
+        /// Sets the bad P_Key counter.
+        set_bad_pkey_cntr => bad_pkey_cntr: u32;
+        /// Sets the Q_Key violation counter.
+        set_qkey_viol_cntr => qkey_viol_cntr: u32;
+        /// Sets the active link width, `enum ib_port_width`.
+        set_active_width => active_width: u8;
+        /// Sets the active link speed, `enum ib_port_speed`.
+        set_active_speed => active_speed: u16;
+        /// Sets the number of supported virtual lanes.
+        set_max_vl_num => max_vl_num: u8;
+    }
+
+    attr_getters! {
+        /// Returns the physical port state.
+        phys_state => phys_state: u8;
+        /// Returns the number of GID table entries.
+        gid_tbl_len => gid_tbl_len: u32;
+        /// Returns the active link width, `enum ib_port_width`.
+        active_width => active_width: u8;
+        /// Returns the active link speed, `enum ib_port_speed`.
+        active_speed => active_speed: u16;
+    }
+}
+
+/// Port attributes that never change once registered, wraps `struct ib_port_immutable`.
+#[repr(transparent)]
+pub struct PortImmutable(bindings::ib_port_immutable);
+
+impl PortImmutable {
+    /// # Safety
+    ///
+    /// `ptr` must be valid and exclusively ours for the lifetime of the returned reference.
+    unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_port_immutable) -> &'a mut Self {
+        // SAFETY: Guaranteed by the safety requirements and `repr(transparent)`.
+        unsafe { &mut *ptr.cast() }
+    }
+
+    /// Sets the core capabilities from the port protocol.
+    pub fn set_protocol(&mut self, protocol: PortProtocol) -> &mut Self {
+        self.0.core_cap_flags = protocol.core_cap_flags();
+        self
+    }
+
+    attr_setters! {
+        /// Sets the number of P_Key table entries.
+        set_pkey_tbl_len => pkey_tbl_len: u32;
+        /// Sets the number of GID table entries.
+        set_gid_tbl_len => gid_tbl_len: u32;
+        /// Sets the largest MAD size, `IB_MGMT_MAD_SIZE` unless OPA MADs are supported.
+        set_max_mad_size => max_mad_size: u32;
+    }
+}
+
+/// Where a MAD passed to [`IbDeviceOperations::process_mad`] comes from, read from its `ib_wc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MadSource {
+    /// LID of the sender.
+    pub slid: u32,
+    /// QP of the sender.
+    pub src_qp: u32,
+    /// QP the MAD was received on.
+    pub qp_num: u32,
+    /// Index of the P_Key the MAD was received with.
+    pub pkey_index: u16,
+    /// Service level.
+    pub sl: u8,
+    /// Low bits of the destination LID, selecting the path of a port with an LMC.
+    pub dlid_path_bits: u8,
+    /// The global route header, if the MAD was received with one.
+    pub grh: Option<[u8; GRH_SIZE]>,
+}
+
+impl MadSource {
+    /// Reads the sender of a MAD from its completion `wc`, and from `grh` if `wc` says the MAD
+    /// came with a GRH.
+    ///
+    /// # Safety
+    ///
+    /// `wc.qp` must point to a valid QP, and `grh` to a GRH if `wc` has `IB_WC_GRH` set.
+    pub(crate) unsafe fn from_wc(wc: &bindings::ib_wc, grh: *const bindings::ib_grh) -> Self {
+        let has_grh = wc.wc_flags & bindings::ib_wc_flags_IB_WC_GRH as core::ffi::c_int != 0;
+        let grh = if has_grh && !grh.is_null() {
+            // SAFETY: The GRH is valid by the safety requirements, and `GRH_SIZE` bytes long.
+            Some(unsafe { *grh.cast::<[u8; GRH_SIZE]>() })
+        } else {
+            None
+        };
+        Self {
+            slid: wc.slid,
+            src_qp: wc.src_qp,
+            // SAFETY: The QP is valid by the safety requirements.
+            qp_num: unsafe { (*wc.qp).qp_num },
+            pkey_index: wc.pkey_index,
+            sl: wc.sl,
+            dlid_path_bits: wc.dlid_path_bits,
+            grh,
+        }
+    }
+}
+
+/// A MAD handed to [`IbDeviceOperations::process_mad`].
+#[derive(Clone, Copy, Debug)]
+pub struct MadRequest<'a> {
+    /// `IB_MAD_IGNORE_*` flags, telling which keys are not to be checked.
+    pub flags: u32,
+    /// The sender, `None` for MADs generated locally, e.g. by the SMA of ib_core.
+    pub source: Option<MadSource>,
+    /// The MAD, [`MAD_SIZE`] bytes; parse it with [`crate::rdma::mad`].
+    pub mad: &'a [u8],
+}
+
+/// Implement this trait to provide an InfiniBand device.
+///
+/// Corresponds to the kernel's `struct ib_device_ops`; the callbacks ib_core requires at
+/// registration time are the non-optional methods.
+#[vtable]
+pub trait IbDeviceOperations: Sized {
+    /// Data stored alongside each `struct ib_device`.
+    type Data: Send + Sync;
+    /// Data stored alongside each protection domain.
+    type PdData: Send + Sync = ();
+    /// Data stored alongside each address handle.
+    type AhData: Send + Sync = ();
+    /// Data stored alongside each completion queue.
+    type CqData: Send + Sync = ();
+    /// Data stored alongside each memory region.
+    type MrData: Send + Sync = ();
+    /// Data stored alongside each memory window.
+    type MwData: Send + Sync = ();
+    /// Data stored alongside each shared receive queue.
+    type SrqData: Send + Sync = ();
+    /// Data stored alongside each queue pair.
+    type QpData: Send + Sync = ();
+
+    /// Value of `ib_device_ops::driver_id`.
+    const DRIVER_ID: DriverId = DriverId::Unknown;
+    /// Value of `ib_device_ops::uverbs_abi_ver`.
+    const UVERBS_ABI_VER: u32 = 0;
+    /// Driver version reported in place of a firmware version, after the module name, when
+    /// [`IbDeviceOperations::get_dev_fw_str`] is not implemented. Empty reports nothing.
+    const DRIVER_VERSION: &'static str = "";
+
+    /// Reports the device attributes.
+    fn query_device(dev: &DeviceRef<Self>, attr: &mut DeviceAttr) -> Result;
+
+    /// Reports the attributes of `port`.
+    fn query_port(dev: &DeviceRef<Self>, port: u32, attr: &mut PortAttr) -> Result;
+
+    /// Reports the immutable attributes of `port`, called once at registration.
+    fn get_port_immutable(dev: &DeviceRef<Self>, port: u32, imm: &mut PortImmutable) -> Result;
+
+    /// Returns the link layer of `port`.
+    fn get_link_layer(_dev: &DeviceRef<Self>, _port: u32) -> LinkLayer {
+        LinkLayer::Unspecified
+    }
+
+    /// Called when ib_core adds an entry to the GID table of a port.
+    fn add_gid(_dev: &DeviceRef<Self>, _attr: &GidAttr) -> Result {
+        Ok(())
+    }
+
+    /// Called when ib_core removes an entry from the GID table of a port.
+    fn del_gid(_dev: &DeviceRef<Self>, _attr: &GidAttr) -> Result {
+        Ok(())
+    }
+
+    /// Describes the firmware of the device, as shown by `ibv_devinfo` and `rdma dev show`.
+    fn get_dev_fw_str(_dev: &DeviceRef<Self>, _fw: &mut FwStr<'_>) {}
+
+    /// Creates the provider data of a new protection domain.
+    fn alloc_pd(dev: &DeviceRef<Self>) -> Result<Self::PdData>;
+
+    /// Releases a protection domain, its data is dropped once this returns.
+    fn dealloc_pd(_pd: &ProtectionDomain<Self>) {}
+
+    /// Creates the provider data of a new address handle for `attr`.
+    ///
+    /// `sleepable` is `false` when the consumer runs in atomic context, in which case the
+    /// provider must not sleep (e.g. to resolve a neighbour).
+    fn create_ah(
+        _pd: &ProtectionDomain<Self>,
+        _attr: &RdmaAhAttr,
+        _sleepable: bool,
+    ) -> Result<Self::AhData> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Releases an address handle, its data is dropped once this returns.
+    fn destroy_ah(_ah: &AddressHandle<Self>, _sleepable: bool) {}
+
+    /// Registers a memory region covering all of memory for kernel consumers.
+    fn get_dma_mr(_pd: &ProtectionDomain<Self>, _access: u32) -> Result<NewMr<Self::MrData>> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Registers the userspace memory pinned in `umem` as a memory region starting at `iova`.
+    fn reg_user_mr(
+        _pd: &ProtectionDomain<Self>,
+        _umem: Umem,
+        _iova: u64,
+        _access: u32,
+    ) -> Result<NewMr<Self::MrData>> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Registers `length` bytes of userspace memory at `start` as an on-demand paging memory
+    /// region starting at `iova`.
+    ///
+    /// Called in place of [`IbDeviceOperations::reg_user_mr`] when `access` holds
+    /// `IB_ACCESS_ON_DEMAND`, which consumers only ask for if the device reports ODP support with
+    /// [`DeviceAttr::set_odp_caps`]. Providers typically keep a
+    /// [`crate::ib::umem_odp::UmemOdp`] of the range in the region's data.
+    fn reg_user_mr_odp(
+        _pd: &ProtectionDomain<Self>,
+        _start: u64,
+        _length: u64,
+        _iova: u64,
+        _access: u32,
+    ) -> Result<NewMr<Self::MrData>> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Registers `length` bytes at `offset` in the DMA-buf of the file descriptor `fd` as a memory
+    /// region starting at `iova`.
+    ///
+    /// Providers attach to the buffer with a [`crate::ib::umem_dmabuf::UmemDmabuf`], pinned unless
+    /// the device can fault, and keep it in the region's data.
+    fn reg_user_mr_dmabuf(
+        _pd: &ProtectionDomain<Self>,
+        _offset: u64,
+        _length: u64,
+        _iova: u64,
+        _fd: i32,
+        _access: u32,
+    ) -> Result<NewMr<Self::MrData>> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Deregisters a memory region, its data is dropped once this returns.
+    ///
+    /// Not called, and `EINVAL` returned to the consumer, while memory windows are bound to it.
+    /// The [`IbDeviceOperations::mr_generation`] of the device is bumped before.
+    fn dereg_mr(_mr: &MemoryRegion<Self>) {}
+
+    /// Returns the invalidation counter of the per-QP [`MrCache`]s of `dev`, if its responder
+    /// caches its rkey translations.
+    ///
+    /// The abstraction bumps it before a region is deregistered and when a memory window is
+    /// bound, invalidated or deallocated, so that no cache hands out a stale translation. New
+    /// registrations need nothing: a key only comes back after the deregistration that bumped
+    /// the counter. Providers invalidating the keys of their fast registration MRs bump it
+    /// themselves.
+    ///
+    /// [`MrCache`]: crate::rdma::mr_cache::MrCache
+    fn mr_generation(_dev: &DeviceRef<Self>) -> Option<&MrGeneration> {
+        None
+    }
+
+    /// Allocates a memory window of `mw_type` in `pd`, for userspace consumers.
+    fn alloc_mw(_pd: &ProtectionDomain<Self>, _mw_type: MwType) -> Result<NewMw<Self::MwData>> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Deallocates a memory window, its data is dropped once this returns.
+    ///
+    /// The window is unbound from its region first.
+    fn dealloc_mw(_mw: &MemoryWindow<Self>) {}
+
+    /// Creates the provider data of a new completion queue.
+    ///
+    /// The provider may round `attr.cqe` up; the final value is reported to the consumer.
+    fn create_cq(dev: &DeviceRef<Self>, attr: &mut CqInitAttr) -> Result<Self::CqData>;
+
+    /// Releases a completion queue, its data is dropped once this returns.
+    fn destroy_cq(_cq: &CompletionQueue<Self>) {}
+
+    /// Removes the oldest completion from `cq`, or returns `None` if it is empty.
+    fn poll_cq(cq: &CompletionQueue<Self>) -> Option<WorkCompletion>;
+
+    /// Arms `cq` for the next completion matching `notify`.
+    ///
+    /// Returns `true` if completions are already queued, which is reported to consumers that ask
+    /// for missed events.
+    fn req_notify_cq(cq: &CompletionQueue<Self>, notify: CqNotify) -> Result<bool>;
+
+    /// Returns the notifier `cq` keeps in its data, if any.
+    ///
+    /// Consumers may then switch the CQ between event and polling mode with
+    /// [`CompletionQueue::set_mode`], and the polls coming through ib_core, e.g. `ib_poll_cq`,
+    /// are held to its poll budget.
+    fn cq_notifier(_cq: &CompletionQueue<Self>) -> Option<&CqNotifier> {
+        None
+    }
+
+    /// Creates the provider data of a new shared receive queue.
+    ///
+    /// Only basic SRQs are created, and `attr` is within the `max_srq_wr` and `max_srq_sge` the
+    /// device reported, with at least one work request: the abstraction refuses the others. The
+    /// provider may round `attr.max_wr` and `attr.max_sge` up; the final values are reported to
+    /// the consumer. A non-zero `attr.srq_limit` arms the limit event.
+    fn create_srq(_pd: &ProtectionDomain<Self>, _attr: &mut SrqAttr) -> Result<Self::SrqData> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Applies the attributes of `attr` selected by `mask` to `srq`.
+    ///
+    /// The abstraction arms the limit event once this succeeds, so providers that cannot resize
+    /// their queues can keep the default, which only accepts limit changes.
+    fn modify_srq(_srq: &SharedReceiveQueue<Self>, _attr: &SrqAttr, mask: SrqAttrMask) -> Result {
+        if mask.contains(SrqAttrMask::MAX_WR) {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+
+    /// Reports the size of `srq`; `srq_limit` is filled in by the abstraction.
+    fn query_srq(_srq: &SharedReceiveQueue<Self>, _attr: &mut SrqAttr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Releases a shared receive queue, its data is dropped once this returns.
+    fn destroy_srq(_srq: &SharedReceiveQueue<Self>) {}
+
+    /// Queues one receive work request on `srq`.
+    fn post_srq_recv(_srq: &SharedReceiveQueue<Self>, _wr: &RecvWr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Creates a new queue pair, returning its QP number and provider data.
+    ///
+    /// The QP starts in [`qp::QpState::Reset`].
+    fn create_qp(dev: &DeviceRef<Self>, init: &QpInitAttr) -> Result<(u32, Self::QpData)>;
+
+    /// Applies `attr` to `qp`.
+    ///
+    /// The modification was already validated by [`qp::check_modify`]; the QP moves to the
+    /// requested state if this succeeds, `qp.state()` still returns the old one meanwhile.
+    fn modify_qp(qp: &QueuePair<Self>, attr: &QpAttr) -> Result;
+
+    /// Returns the injector whose rules may fail `modify_qp` on `dev` or move its QPs to the
+    /// error state, see [`crate::rdma::qp_fault`].
+    ///
+    /// Only consulted with `CONFIG_RUST_RDMA_FAULT_INJECTION`. Injected `modify_qp` failures
+    /// return `EIO` without calling [`IbDeviceOperations::modify_qp`].
+    fn qp_faults(_dev: &DeviceRef<Self>) -> Option<&QpFaultInjector> {
+        None
+    }
+
+    /// Releases a queue pair, its data is dropped once this returns.
+    fn destroy_qp(_qp: &QueuePair<Self>) {}
+
+    /// Queues one send work request on `qp`.
+    ///
+    /// Called for each request of a posted chain, while the QP is in RTS or a later state; the
+    /// first failure stops the chain and is reported back to the consumer.
+    fn post_send(qp: &QueuePair<Self>, wr: &SendWr) -> Result;
+
+    /// Queues one receive work request on `qp`.
+    ///
+    /// Called for each request of a posted chain, while the QP is out of RESET and has no SRQ;
+    /// the first failure stops the chain and is reported back to the consumer.
+    fn post_recv(qp: &QueuePair<Self>, wr: &RecvWr) -> Result;
+
+    /// Attaches the UD QP `qp` to the multicast group `mgid`, whose LID is `mlid` on InfiniBand.
+    ///
+    /// Providers implementing this also implement [`IbDeviceOperations::detach_mcast`]; soft
+    /// RoCE ones typically keep their groups in a [`crate::rxe::mcast::McastTable`].
+    fn attach_mcast(_qp: &QueuePair<Self>, _mgid: &Gid, _mlid: u16) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Detaches `qp` from the multicast group `mgid`.
+    fn detach_mcast(_qp: &QueuePair<Self>, _mgid: &Gid, _mlid: u16) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Handles a MAD for `port` in place of the MAD layer's agents, e.g. by forwarding it to
+    /// firmware.
+    ///
+    /// A reply is written to `out_mad` and flagged with [`MadResult::REPLY`]. Errors are reported
+    /// to the MAD layer as `IB_MAD_RESULT_FAILURE`. Called in process context.
+    fn process_mad(
+        _dev: &DeviceRef<Self>,
+        _port: u32,
+        _req: &MadRequest<'_>,
+        _out_mad: &mut [u8],
+    ) -> Result<MadResult> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Adds the provider's attributes of `qp` to its `rdma resource show qp` entry, e.g. its
+    /// queue indices and PSNs.
+    ///
+    /// An `EMSGSIZE` from the writer must be returned as is: ib_core then retries with a larger
+    /// message. Called in process context.
+    fn fill_res_qp_entry(_qp: &QueuePair<Self>, _attrs: &mut DriverAttrs) -> Result {
+        Ok(())
+    }
+
+    /// Adds the provider's attributes of `cq` to its `rdma resource show cq` entry.
+    fn fill_res_cq_entry(_cq: &CompletionQueue<Self>, _attrs: &mut DriverAttrs) -> Result {
+        Ok(())
+    }
+
+    /// Adds the provider's attributes of `mr` to its `rdma resource show mr` entry.
+    fn fill_res_mr_entry(_mr: &MemoryRegion<Self>, _attrs: &mut DriverAttrs) -> Result {
+        Ok(())
+    }
+
+    /// Adds the provider's attributes of `srq` to its `rdma resource show srq` entry.
+    fn fill_res_srq_entry(_srq: &SharedReceiveQueue<Self>, _attrs: &mut DriverAttrs) -> Result {
+        Ok(())
+    }
+
+    /// Adds the hardware counters of `mr` to its `rdma statistic mr` entry, e.g. page faults.
+    fn fill_stat_mr_entry(_mr: &MemoryRegion<Self>, _counters: &mut StatCounters) -> Result {
+        Ok(())
+    }
+
+    /// Describes the hardware counters of `port`, shown by `rdma statistic show`.
+    ///
+    /// Called once per port at registration; the values are then read with
+    /// [`IbDeviceOperations::get_hw_stats`].
+    fn alloc_hw_port_stats(_dev: &DeviceRef<Self>, _port: u32) -> Result<HwStats> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Writes the current values of the counters of `port` to `values`, in the order of the
+    /// [`HwStats`] of the port, and returns how many it updated.
+    ///
+    /// Called in process context, at most once per lifespan of the counters.
+    fn get_hw_stats(_dev: &DeviceRef<Self>, _port: u32, _values: &mut [u64]) -> Result<usize> {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Called when ib_core releases the device, right before its data is dropped.
+    ///
+    /// For registered devices this runs at the end of every unregistration path: `rdma link
+    /// delete`, [`DeviceRef::unregister_queued`] from a netdev notifier, or `ib_unregister_driver`
+    /// when the provider's registration goes away.
+    fn dealloc_driver(_dev: &DeviceRef<Self>) {}
+}
+
+/// A borrowed InfiniBand device, as seen from provider callbacks.
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_device` was allocated by [`Device::try_new`] with the same `T`, so it
+/// is followed by an initialised `T::Data`.
+#[repr(transparent)]
+pub struct DeviceRef<T: IbDeviceOperations>(
+    UnsafeCell<bindings::ib_device>,
+    marker::PhantomData<T>,
+);
+
+impl<T: IbDeviceOperations> DeviceRef<T> {
+    /// Creates a reference to a [`DeviceRef`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by [`Device::try_new`] with the same `T`, and must remain
+    /// valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_device) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `DeviceRef` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_device` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_device {
+        self.0.get()
+    }
+
+    /// Runs `f` on the device of `T` bound to `ndev`, e.g. the device a received packet is for.
+    ///
+    /// Returns `None` if `ndev` has no such device. The device cannot be freed while `f` runs.
+    /// Does not sleep, so it can be called from the receive softirq.
+    pub fn with_netdev<R>(ndev: &NetDevice, f: impl FnOnce(&Self) -> R) -> Option<R> {
+        // SAFETY: By the type invariants of `NetDevice` the network device is valid.
+        let ptr =
+            unsafe { bindings::ib_device_get_by_netdev(ndev.as_ptr(), T::DRIVER_ID.to_raw()) };
+        if ptr.is_null() {
+            return None;
+        }
+        // Other providers, e.g. the C driver, may use the same driver ID: only the devices using
+        // the operations of `T` are followed by a `T::Data`.
+        // SAFETY: `ib_device_get_by_netdev` returned a valid device and took a reference on it.
+        let ours = unsafe { (*ptr).ops.dealloc_driver }
+            == Some(DeviceOperationsTable::<T>::dealloc_driver_callback as _);
+        // SAFETY: The device was allocated by `Device::try_new` with `T`, and the reference
+        // keeps it alive until it is put below.
+        let ret = ours.then(|| f(unsafe { Self::from_ptr(ptr) }));
+        // SAFETY: Drops the reference taken by `ib_device_get_by_netdev`.
+        unsafe { bindings::ib_device_put(ptr) };
+        ret
+    }
+
+    /// Returns the provider data of the device.
+    pub fn data(&self) -> &T::Data {
+        // SAFETY: By the type invariants the device is followed by an initialised `T::Data`.
+        unsafe { Object::<bindings::ib_device, T::Data>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns the device name, e.g. `rxe0`.
+    pub fn name(&self) -> &CStr {
+        // SAFETY: `name` is a NUL-terminated array inside the valid device.
+        unsafe { CStr::from_char_ptr((*self.as_ptr()).name.as_ptr()) }
+    }
+
+    /// Returns the attributes the provider reported in `query_device` when it was registered.
+    ///
+    /// They are zeroed until the device is registered.
+    pub fn attrs(&self) -> &DeviceAttr {
+        // SAFETY: The device is valid and ib_core only fills `attrs` while registering it, before
+        // any verb runs; they do not change afterwards. `DeviceAttr` is transparent.
+        unsafe { &*core::ptr::addr_of!((*self.as_ptr()).attrs).cast() }
+    }
+
+    /// Returns the name of the module providing the device, `None` if it is built in.
+    pub fn driver_name(&self) -> Option<&CStr> {
+        // SAFETY: The device is valid and its ops were copied from the table built for it.
+        let owner = unsafe { (*self.as_ptr()).ops.owner };
+        if owner.is_null() {
+            return None;
+        }
+        // SAFETY: The module owns the device's ops, so it outlives the device, and its name is a
+        // NUL-terminated array.
+        Some(unsafe { CStr::from_char_ptr((*owner).name.as_ptr()) })
+    }
+
+    /// Returns the number of physical ports.
+    pub fn phys_port_cnt(&self) -> u32 {
+        // SAFETY: The device is valid.
+        unsafe { (*self.as_ptr()).phys_port_cnt }
+    }
+
+    /// Returns the GID table of `port`.
+    pub fn gid_table(&self, port: u32) -> GidTable<'_, T> {
+        GidTable::new(self, port)
+    }
+
+    /// Looks the object of `kind` with restrack ID `id` up, the ID `rdma res show` prints.
+    ///
+    /// Fails with `EINVAL` for the kinds restrack does not list, and `ENOENT` if there is no such
+    /// object or it is being destroyed.
+    pub fn find_resource(&self, kind: ResourceKind, id: u32) -> Result<ResourceRef> {
+        let ty = restrack::restrack_type(kind).ok_or(EINVAL)?;
+        // SAFETY: The device is valid.
+        let res = unsafe { bindings::rdma_restrack_get_byid(self.as_ptr(), ty, id) };
+        let res = from_kernel_err_ptr(res)?;
+        // SAFETY: `rdma_restrack_get_byid` returned a tracked entry and took a reference on it.
+        Ok(unsafe { ResourceRef::from_raw(res) })
+    }
+
+    /// Reports to ib_core and the consumers that the link of `port` went up or down.
+    ///
+    /// This dispatches `IB_EVENT_PORT_ACTIVE` or `IB_EVENT_PORT_ERR`; the port attributes
+    /// returned by `query_port` must already reflect the new state.
+    pub fn dispatch_port_event(&self, port: u32, event: LinkEvent) {
+        // SAFETY: The device is valid.
+        unsafe { dispatch_port_event(self.as_ptr(), port, event) };
+    }
+
+    /// Asks ib_core to unregister the device from a work item.
+    ///
+    /// This is safe to call from atomic context and from netdev notifiers, where unregistering
+    /// synchronously would deadlock. The device is freed once unregistration completes.
+    pub fn unregister_queued(&self) {
+        // SAFETY: The device is valid and was registered, ib_core ignores repeated requests.
+        unsafe { bindings::ib_unregister_device_queued(self.as_ptr()) };
+    }
+}
+
+/// Dispatches `IB_EVENT_PORT_ACTIVE` or `IB_EVENT_PORT_ERR` for `port` of `ibdev`.
+///
+/// # Safety
+///
+/// `ibdev` must be a valid device.
+unsafe fn dispatch_port_event(ibdev: *mut bindings::ib_device, port: u32, event: LinkEvent) {
+    let mut ev = bindings::ib_event::default();
+    ev.device = ibdev;
+    ev.event = match event {
+        LinkEvent::Up => bindings::ib_event_type_IB_EVENT_PORT_ACTIVE,
+        LinkEvent::Down => bindings::ib_event_type_IB_EVENT_PORT_ERR,
+    };
+    ev.element.port_num = port;
+    // SAFETY: The device is valid by the safety requirements, and `ev` lives for the duration of
+    // the call.
+    unsafe { bindings::ib_dispatch_event(&ev) };
+}
+
+/// Reports to ib_core and the consumers that the link of `ndev` went up or down, for every port
+/// of the device of `driver` bound to it, if there is one.
+///
+/// Soft transports bind all the ports of their devices to the same interface. The port attributes
+/// returned by `query_port` must already reflect the new state.
+pub(crate) fn dispatch_netdev_event(ndev: &NetDevice, driver: DriverId, event: LinkEvent) {
+    // SAFETY: By the type invariants of `NetDevice` the network device is valid.
+    let ibdev = unsafe { bindings::ib_device_get_by_netdev(ndev.as_ptr(), driver.to_raw()) };
+    if ibdev.is_null() {
+        return;
+    }
+    // SAFETY: `ib_device_get_by_netdev` returned a valid device and took a reference on it.
+    let ports = unsafe { (*ibdev).phys_port_cnt };
+    for port in 1..=ports {
+        // SAFETY: The reference keeps the device valid.
+        unsafe { dispatch_port_event(ibdev, port, event) };
+    }
+    // SAFETY: Drops the reference taken by `ib_device_get_by_netdev`.
+    unsafe { bindings::ib_device_put(ibdev) };
+}
+
+/// An InfiniBand device that is not registered yet.
+///
+/// Wraps `ib_alloc_device` and `ib_register_device`. The device uses the `dealloc_driver` flow:
+/// once [`Device::register`] succeeds, ib_core owns it and frees it at the end of whichever
+/// unregistration path runs first, calling [`IbDeviceOperations::dealloc_driver`] on the way.
+/// Dropping an unregistered [`Device`] frees it right away through `ib_dealloc_device`.
+///
+/// # Invariants
+///
+/// `ptr` was returned by `_ib_alloc_device` with room for `T::Data`, which is initialised, and
+/// the device is not registered.
+pub struct Device<T: IbDeviceOperations> {
+    ptr: *mut bindings::ib_device,
+    phantom: marker::PhantomData<T>,
+}
+
+impl<T: IbDeviceOperations> Device<T> {
+    /// Allocates a new device holding `data`, but does not register it yet.
+    pub fn try_new(module: &'static ThisModule, data: T::Data) -> Result<Self> {
+        let size = mem::size_of::<Object<bindings::ib_device, T::Data>>();
+        // SAFETY: `size` covers a `struct ib_device` at offset 0, as `_ib_alloc_device` requires.
+        let ptr = unsafe { bindings::_ib_alloc_device(size) };
+        if ptr.is_null() {
+            return Err(ENOMEM);
+        }
+        // SAFETY: The allocation has the size of the `Object`, and nothing reads the data yet.
+        unsafe { Object::<bindings::ib_device, T::Data>::from_raw(ptr) }.init(data);
+
+        let ops = DeviceOperationsTable::<T>::build(module);
+        // SAFETY: `ptr` is a freshly allocated device and `ib_set_device_ops` copies the table.
+        unsafe { bindings::ib_set_device_ops(ptr, &ops) };
+
+        // INVARIANT: The device was allocated with room for `T::Data`, which was just written.
+        Ok(Self {
+            ptr,
+            phantom: marker::PhantomData,
+        })
+    }
+
+    /// Sets the node type, `RDMA_NODE_IB_CA` for regular channel adapters.
+    pub fn set_node_type(&mut self, node_type: bindings::rdma_node_type) -> &mut Self {
+        // SAFETY: The device is valid and not registered yet, so nobody else reads it.
+        unsafe { (*self.ptr).node_type = node_type as _ };
+        self
+    }
+
+    /// Sets the number of physical ports.
+    pub fn set_phys_port_cnt(&mut self, cnt: u32) -> &mut Self {
+        // SAFETY: The device is valid and not registered yet, so nobody else reads it.
+        unsafe { (*self.ptr).phys_port_cnt = cnt };
+        self
+    }
+
+    /// Sets the number of completion vectors.
+    pub fn set_num_comp_vectors(&mut self, count: u32) -> &mut Self {
+        // SAFETY: The device is valid and not registered yet, so nobody else reads it.
+        unsafe { (*self.ptr).num_comp_vectors = count as _ };
+        self
+    }
+
+    /// Sets the node GUID, in host byte order.
+    pub fn set_node_guid(&mut self, guid: u64) -> &mut Self {
+        // SAFETY: The device is valid and not registered yet, so nobody else reads it.
+        unsafe { (*self.ptr).node_guid = guid.to_be() };
+        self
+    }
+
+    /// Sets the node description, truncated to `IB_DEVICE_NODE_DESC_MAX` bytes.
+    pub fn set_node_desc(&mut self, desc: &[u8]) -> &mut Self {
+        // SAFETY: The device is valid and not registered yet, so nobody else reads it.
+        let node_desc = unsafe { &mut (*self.ptr).node_desc };
+        let len = desc.len().min(node_desc.len());
+        for (dst, src) in node_desc.iter_mut().zip(&desc[..len]) {
+            *dst = *src as _;
+        }
+        self
+    }
+
+    /// Binds `port` of the device to the network interface `ndev`.
+    pub fn set_netdev(&mut self, ndev: &NetDevice, port: u32) -> Result {
+        // SAFETY: The device is valid, `ndev` is a valid network device which ib_core takes its
+        // own reference on.
+        let err = unsafe { bindings::ib_device_set_netdev(self.ptr, ndev.as_ptr(), port) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        Ok(())
+    }
+
+    /// Adds the provider attributes of `group` to the sysfs directory of the device.
+    ///
+    /// A device has a single provider group; setting another one replaces it.
+    pub fn set_sysfs_group<const N: usize>(
+        &mut self,
+        group: &'static AttributeGroup<T, N>,
+    ) -> &mut Self {
+        // SAFETY: The device is valid and not registered yet, and the group is static, so it
+        // outlives the sysfs directory created at registration.
+        unsafe { bindings::rdma_set_device_sysfs_group(self.ptr, group.as_raw()) };
+        self
+    }
+
+    /// Registers the device with ib_core under `name`, which may contain a `%d` pattern.
+    ///
+    /// On success ownership passes to ib_core, see the type documentation, and the device is
+    /// reported to the [`DeviceObserver`]s. On failure the device is freed.
+    ///
+    /// [`DeviceObserver`]: super::observer::DeviceObserver
+    pub fn register(self, name: &CStr) -> Result {
+        // Soft devices have no DMA device.
+        self.register_with_dma(name, core::ptr::null_mut())
+            .map(|_| ())
+    }
+
+    /// Registers the device like [`Device::register`], with `dma_device` doing the DMA of the
+    /// hardware behind it, and returns it.
+    ///
+    /// The caller must unregister the device with `ib_unregister_device` before `dma_device`
+    /// goes away.
+    pub(crate) fn register_with_dma(
+        self,
+        name: &CStr,
+        dma_device: *mut bindings::device,
+    ) -> Result<*mut bindings::ib_device> {
+        let err = observer::announce(self.ptr, || {
+            // SAFETY: The device is valid and fully initialised, and `dma_device` is null or
+            // outlives the registration.
+            unsafe { bindings::ib_register_device(self.ptr, name.as_char_ptr(), dma_device) }
+        });
+        if err != 0 {
+            // Dropping `self` deallocates the device, as required after a failed registration.
+            return Err(Error::from_kernel_errno(err));
+        }
+        let ptr = self.ptr;
+        mem::forget(self);
+        Ok(ptr)
+    }
+}
+
+impl<T: IbDeviceOperations> Deref for Device<T> {
+    type Target = DeviceRef<T>;
+
+    fn deref(&self) -> &DeviceRef<T> {
+        // SAFETY: By the type invariants `ptr` was allocated by `try_new` with the same `T`, and
+        // lives as long as `self`.
+        unsafe { DeviceRef::from_ptr(self.ptr) }
+    }
+}
+
+impl<T: IbDeviceOperations> Drop for Device<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the device was allocated by `_ib_alloc_device` and is
+        // not registered. This calls `dealloc_driver`, which drops the data.
+        unsafe { bindings::ib_dealloc_device(self.ptr) };
+    }
+}
+
+// SAFETY: The device can be dropped from any thread, and `T::Data` is `Send`.
+unsafe impl<T: IbDeviceOperations> Send for Device<T> {}
+
+// SAFETY: Shared references only give access to `T::Data`, which is `Sync`.
+unsafe impl<T: IbDeviceOperations> Sync for Device<T> {}
+
+/// Build kernel's `struct ib_device_ops` type with ib device operation.
+pub(crate) struct DeviceOperationsTable<T>(marker::PhantomData<T>);
+
+impl<T: IbDeviceOperations> DeviceOperationsTable<T> {
+    /// Builds an instance of [`struct ib_device_ops`].
+    ///
+    /// The table is copied into the device by `ib_set_device_ops`, it does not need to outlive
+    /// the call.
+    pub(crate) fn build(module: &'static ThisModule) -> bindings::ib_device_ops {
+        let mut ops = bindings::ib_device_ops::default();
+        ops.owner = module.as_ptr();
+        ops.driver_id = T::DRIVER_ID.to_raw();
+        ops.uverbs_abi_ver = T::UVERBS_ABI_VER;
+
+        ops.query_device = Some(Self::query_device_callback);
+        ops.query_port = Some(Self::query_port_callback);
+        ops.get_port_immutable = Some(Self::get_port_immutable_callback);
+        if T::HAS_GET_LINK_LAYER {
+            ops.get_link_layer = Some(Self::get_link_layer_callback);
+        }
+        if T::HAS_ADD_GID {
+            ops.add_gid = Some(Self::add_gid_callback);
+        }
+        if T::HAS_DEL_GID {
+            ops.del_gid = Some(Self::del_gid_callback);
+        }
+        if T::HAS_GET_DEV_FW_STR || !T::DRIVER_VERSION.is_empty() {
+            ops.get_dev_fw_str = Some(Self::get_dev_fw_str_callback);
+        }
+        ops.alloc_pd = Some(Self::alloc_pd_callback);
+        ops.dealloc_pd = Some(Self::dealloc_pd_callback);
+        if T::HAS_CREATE_AH {
+            ops.create_ah = Some(Self::create_ah_callback);
+            ops.query_ah = Some(Self::query_ah_callback);
+            ops.destroy_ah = Some(Self::destroy_ah_callback);
+        }
+        if T::HAS_GET_DMA_MR {
+            ops.get_dma_mr = Some(Self::get_dma_mr_callback);
+        }
+        if T::HAS_REG_USER_MR {
+            ops.reg_user_mr = Some(Self::reg_user_mr_callback);
+        }
+        if T::HAS_REG_USER_MR_DMABUF {
+            ops.reg_user_mr_dmabuf = Some(Self::reg_user_mr_dmabuf_callback);
+        }
+        ops.dereg_mr = Some(Self::dereg_mr_callback);
+        if T::HAS_ALLOC_MW {
+            ops.alloc_mw = Some(Self::alloc_mw_callback);
+            ops.dealloc_mw = Some(Self::dealloc_mw_callback);
+        }
+        ops.create_cq = Some(Self::create_cq_callback);
+        ops.destroy_cq = Some(Self::destroy_cq_callback);
+        ops.poll_cq = Some(Self::poll_cq_callback);
+        ops.req_notify_cq = Some(Self::req_notify_cq_callback);
+        if T::HAS_CREATE_SRQ {
+            ops.create_srq = Some(Self::create_srq_callback);
+            ops.modify_srq = Some(Self::modify_srq_callback);
+            ops.query_srq = Some(Self::query_srq_callback);
+            ops.destroy_srq = Some(Self::destroy_srq_callback);
+            ops.post_srq_recv = Some(Self::post_srq_recv_callback);
+        }
+        ops.create_qp = Some(Self::create_qp_callback);
+        ops.modify_qp = Some(Self::modify_qp_callback);
+        ops.destroy_qp = Some(Self::destroy_qp_callback);
+        ops.post_send = Some(Self::post_send_callback);
+        ops.post_recv = Some(Self::post_recv_callback);
+        if T::HAS_ATTACH_MCAST {
+            ops.attach_mcast = Some(Self::attach_mcast_callback);
+            ops.detach_mcast = Some(Self::detach_mcast_callback);
+        }
+        if T::HAS_PROCESS_MAD {
+            ops.process_mad = Some(Self::process_mad_callback);
+        }
+        if T::HAS_FILL_RES_QP_ENTRY {
+            ops.fill_res_qp_entry = Some(Self::fill_res_qp_entry_callback);
+        }
+        if T::HAS_FILL_RES_CQ_ENTRY {
+            ops.fill_res_cq_entry = Some(Self::fill_res_cq_entry_callback);
+        }
+        if T::HAS_FILL_RES_MR_ENTRY {
+            ops.fill_res_mr_entry = Some(Self::fill_res_mr_entry_callback);
+        }
+        if T::HAS_FILL_RES_SRQ_ENTRY {
+            ops.fill_res_srq_entry = Some(Self::fill_res_srq_entry_callback);
+        }
+        if T::HAS_FILL_STAT_MR_ENTRY {
+            ops.fill_stat_mr_entry = Some(Self::fill_stat_mr_entry_callback);
+        }
+        if T::HAS_ALLOC_HW_PORT_STATS {
+            ops.alloc_hw_port_stats = Some(Self::alloc_hw_port_stats_callback);
+            ops.get_hw_stats = Some(Self::get_hw_stats_callback);
+        }
+        ops.dealloc_driver = Some(Self::dealloc_driver_callback);
+
+        ops.size_ib_pd = mem::size_of::<Object<bindings::ib_pd, T::PdData>>();
+        ops.size_ib_ah = mem::size_of::<Object<bindings::ib_ah, AhObject<T::AhData>>>();
+        ops.size_ib_mw = mem::size_of::<Object<bindings::ib_mw, MwObject<T::MwData>>>();
+        ops.size_ib_cq = mem::size_of::<Object<bindings::ib_cq, T::CqData>>();
+        ops.size_ib_srq = mem::size_of::<Object<bindings::ib_srq, SrqObject<T::SrqData>>>();
+        ops.size_ib_qp = mem::size_of::<Object<bindings::ib_qp, QpObject<T::QpData>>>();
+        ops
+    }
+
+    /// # Safety
+    ///
+    /// `ibdev` must be a device allocated by [`Device::try_new`] with the same `T`.
+    unsafe fn dev<'a>(ibdev: *mut bindings::ib_device) -> &'a DeviceRef<T> {
+        // SAFETY: Guaranteed by the safety requirements; ib_core keeps the device alive while
+        // calling into the provider.
+        unsafe { DeviceRef::from_ptr(ibdev) }
+    }
+
+    unsafe extern "C" fn query_device_callback(
+        ibdev: *mut bindings::ib_device,
+        attr: *mut bindings::ib_device_attr,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only calls us on devices we allocated, with a valid attribute buffer.
+        let (dev, attr) = unsafe { (Self::dev(ibdev), DeviceAttr::from_ptr(attr)) };
+        match T::query_device(dev, attr) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn query_port_callback(
+        ibdev: *mut bindings::ib_device,
+        port: u32,
+        attr: *mut bindings::ib_port_attr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only calls us on devices we allocated, with a valid attribute buffer.
+        let (dev, attr) = unsafe { (Self::dev(ibdev), PortAttr::from_ptr(attr)) };
+        match T::query_port(dev, port, attr) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn get_port_immutable_callback(
+        ibdev: *mut bindings::ib_device,
+        port: u32,
+        imm: *mut bindings::ib_port_immutable,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only calls us on devices we allocated, with a valid attribute buffer.
+        let (dev, imm) = unsafe { (Self::dev(ibdev), PortImmutable::from_ptr(imm)) };
+        match T::get_port_immutable(dev, port, imm) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn get_link_layer_callback(
+        ibdev: *mut bindings::ib_device,
+        port: u32,
+    ) -> bindings::rdma_link_layer {
+        // SAFETY: ib_core only calls us on devices we allocated.
+        let dev = unsafe { Self::dev(ibdev) };
+        T::get_link_layer(dev, port) as _
+    }
+
+    unsafe extern "C" fn get_dev_fw_str_callback(
+        ibdev: *mut bindings::ib_device,
+        str_: *mut core::ffi::c_char,
+    ) {
+        // SAFETY: ib_core passes a buffer of `IB_FW_VERSION_NAME_MAX` bytes.
+        let buf = unsafe {
+            core::slice::from_raw_parts_mut(
+                str_.cast::<u8>(),
+                bindings::IB_FW_VERSION_NAME_MAX as usize,
+            )
+        };
+        let mut fw = match FwStr::new(buf) {
+            Some(fw) => fw,
+            None => return,
+        };
+        // SAFETY: ib_core only calls us on devices we allocated.
+        let dev = unsafe { Self::dev(ibdev) };
+        if T::HAS_GET_DEV_FW_STR {
+            T::get_dev_fw_str(dev, &mut fw);
+        } else {
+            // Truncation is the only possible failure, and `FwStr` hides it.
+            let _ = match dev.driver_name() {
+                Some(name) => write!(fw, "{} {}", name, T::DRIVER_VERSION),
+                None => write!(fw, "{}", T::DRIVER_VERSION),
+            };
+        }
+    }
+
+    unsafe extern "C" fn add_gid_callback(
+        attr: *const bindings::ib_gid_attr,
+        _context: *mut *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core passes a valid entry of one of our devices.
+        let (dev, attr) = unsafe { (Self::dev((*attr).device), GidAttr::from_ptr(attr)) };
+        match T::add_gid(dev, attr) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn del_gid_callback(
+        attr: *const bindings::ib_gid_attr,
+        _context: *mut *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core passes a valid entry of one of our devices.
+        let (dev, attr) = unsafe { (Self::dev((*attr).device), GidAttr::from_ptr(attr)) };
+        match T::del_gid(dev, attr) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn alloc_pd_callback(
+        ibpd: *mut bindings::ib_pd,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core allocated `ibpd` with `size_ib_pd` bytes and set its device.
+        let (dev, pd) = unsafe {
+            (
+                Self::dev((*ibpd).device),
+                Object::<bindings::ib_pd, T::PdData>::from_raw(ibpd),
+            )
+        };
+        match T::alloc_pd(dev) {
+            Ok(data) => {
+                pd.init(data);
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn dealloc_pd_callback(
+        ibpd: *mut bindings::ib_pd,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only releases PDs whose `alloc_pd` succeeded, and never uses them
+        // after this callback.
+        let pd = unsafe { ProtectionDomain::<T>::from_ptr(ibpd) };
+        T::dealloc_pd(pd);
+        // SAFETY: The data was initialised by `alloc_pd_callback` and is never used again.
+        drop(unsafe { Object::<bindings::ib_pd, T::PdData>::from_raw(ibpd).take() });
+        0
+    }
+
+    unsafe extern "C" fn create_ah_callback(
+        ibah: *mut bindings::ib_ah,
+        init: *mut bindings::rdma_ah_init_attr,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core allocated `ibah` with `size_ib_ah` bytes, set its device and PD, and
+        // passes valid creation attributes.
+        let (pd, ah, attr, flags) = unsafe {
+            (
+                ProtectionDomain::<T>::from_ptr((*ibah).pd),
+                Object::<bindings::ib_ah, AhObject<T::AhData>>::from_raw(ibah),
+                RdmaAhAttr::from_raw(&*(*init).ah_attr),
+                (*init).flags,
+            )
+        };
+        let sleepable = flags & bindings::rdma_create_ah_flags_RDMA_CREATE_AH_SLEEPABLE as u32 != 0;
+        match T::create_ah(pd, &attr, sleepable) {
+            Ok(data) => {
+                ah.init(AhObject { attr, data });
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn query_ah_callback(
+        ibah: *mut bindings::ib_ah,
+        attr: *mut bindings::rdma_ah_attr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only queries AHs whose `create_ah` succeeded, with a valid buffer.
+        let (ah, attr) = unsafe { (AddressHandle::<T>::from_ptr(ibah), &mut *attr) };
+        ah.attr().write_to(attr);
+        0
+    }
+
+    unsafe extern "C" fn destroy_ah_callback(
+        ibah: *mut bindings::ib_ah,
+        flags: u32,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only destroys AHs whose `create_ah` succeeded, and never uses them
+        // after this callback.
+        let ah = unsafe { AddressHandle::<T>::from_ptr(ibah) };
+        T::destroy_ah(
+            ah,
+            flags & bindings::rdma_destroy_ah_flags_RDMA_DESTROY_AH_SLEEPABLE as u32 != 0,
+        );
+        // SAFETY: The data was initialised by `create_ah_callback` and is never used again.
+        drop(unsafe { Object::<bindings::ib_ah, AhObject<T::AhData>>::from_raw(ibah).take() });
+        0
+    }
+
+    unsafe extern "C" fn get_dma_mr_callback(
+        ibpd: *mut bindings::ib_pd,
+        access: core::ffi::c_int,
+    ) -> *mut bindings::ib_mr {
+        // SAFETY: ib_core only passes PDs whose `alloc_pd` succeeded.
+        let pd = unsafe { ProtectionDomain::<T>::from_ptr(ibpd) };
+        let access = access as u32;
+        match T::get_dma_mr(pd, access).and_then(|mr| MemoryRegion::<T>::into_raw(mr, access)) {
+            Ok(mr) => mr,
+            // SAFETY: `ERR_PTR` only encodes the error number.
+            Err(e) => unsafe { bindings::ERR_PTR(e.to_kernel_errno() as _).cast() },
+        }
+    }
+
+    unsafe extern "C" fn reg_user_mr_callback(
+        ibpd: *mut bindings::ib_pd,
+        start: u64,
+        length: u64,
+        iova: u64,
+        access: core::ffi::c_int,
+        _udata: *mut bindings::ib_udata,
+    ) -> *mut bindings::ib_mr {
+        // SAFETY: ib_core only passes PDs whose `alloc_pd` succeeded.
+        let pd = unsafe { ProtectionDomain::<T>::from_ptr(ibpd) };
+        let access = access as u32;
+        let mr = if access & bindings::ib_access_flags_IB_ACCESS_ON_DEMAND as u32 != 0 {
+            T::reg_user_mr_odp(pd, start, length, iova, access)
+        } else {
+            Umem::get(pd.device(), start, length, access)
+                .and_then(|umem| T::reg_user_mr(pd, umem, iova, access))
+        }
+        .and_then(|mr| MemoryRegion::<T>::into_raw(mr, access));
+        match mr {
+            Ok(mr) => mr,
+            // SAFETY: `ERR_PTR` only encodes the error number.
+            Err(e) => unsafe { bindings::ERR_PTR(e.to_kernel_errno() as _).cast() },
+        }
+    }
+
+    unsafe extern "C" fn reg_user_mr_dmabuf_callback(
+        ibpd: *mut bindings::ib_pd,
+        offset: u64,
+        length: u64,
+        iova: u64,
+        fd: core::ffi::c_int,
+        access: core::ffi::c_int,
+        _udata: *mut bindings::ib_udata,
+    ) -> *mut bindings::ib_mr {
+        // SAFETY: ib_core only passes PDs whose `alloc_pd` succeeded.
+        let pd = unsafe { ProtectionDomain::<T>::from_ptr(ibpd) };
+        let access = access as u32;
+        let mr = T::reg_user_mr_dmabuf(pd, offset, length, iova, fd, access)
+            .and_then(|mr| MemoryRegion::<T>::into_raw(mr, access));
+        match mr {
+            Ok(mr) => mr,
+            // SAFETY: `ERR_PTR` only encodes the error number.
+            Err(e) => unsafe { bindings::ERR_PTR(e.to_kernel_errno() as _).cast() },
+        }
+    }
+
+    unsafe extern "C" fn dereg_mr_callback(
+        ibmr: *mut bindings::ib_mr,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only deregisters MRs returned by our registration callbacks, and never
+        // uses them after this callback.
+        let mr = unsafe { MemoryRegion::<T>::from_ptr(ibmr) };
+        // Refuse the deregistration while windows hold the region, and keep them from binding
+        // to it from now on.
+        if !mr.windows().retire() {
+            return EINVAL.to_kernel_errno();
+        }
+        // The cached translations must go before the region does.
+        if let Some(generation) = T::mr_generation(mr.device()) {
+            generation.bump();
+        }
+        T::dereg_mr(mr);
+        // SAFETY: The MR was boxed by `MemoryRegion::into_raw` and is never used again.
+        unsafe { MemoryRegion::<T>::drop_raw(ibmr) };
+        0
+    }
+
+    unsafe extern "C" fn alloc_mw_callback(
+        ibmw: *mut bindings::ib_mw,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core allocated `ibmw` with `size_ib_mw` bytes and set its device, PD and
+        // type.
+        let (pd, mw, raw_type) = unsafe {
+            (
+                ProtectionDomain::<T>::from_ptr((*ibmw).pd),
+                Object::<bindings::ib_mw, MwObject<T::MwData>>::from_raw(ibmw),
+                (*ibmw).type_,
+            )
+        };
+        let mw_type = match MwType::from_raw(raw_type as u32) {
+            Some(mw_type) => mw_type,
+            None => return EINVAL.to_kernel_errno(),
+        };
+        match T::alloc_mw(pd, mw_type) {
+            Ok(new) => {
+                mw.raw.rkey = new.rkey.raw();
+                // SAFETY: The object is initialised in the `struct ib_mw`, which does not move
+                // until `dealloc_mw_callback`, and its lock right below.
+                mw.init(unsafe { MwObject::new(mw_type, pd.as_ptr(), new.rkey, new.data) });
+                // SAFETY: The data was just initialised, see above.
+                MwObject::init_lock(unsafe { Pin::new_unchecked(mw.data.assume_init_mut()) });
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn dealloc_mw_callback(ibmw: *mut bindings::ib_mw) -> core::ffi::c_int {
+        // SAFETY: ib_core only deallocates MWs whose `alloc_mw` succeeded, and never uses them
+        // after this callback.
+        let mw = unsafe { MemoryWindow::<T>::from_ptr(ibmw) };
+        mw.release();
+        T::dealloc_mw(mw);
+        // SAFETY: The data was initialised by `alloc_mw_callback` and is never used again.
+        drop(unsafe { Object::<bindings::ib_mw, MwObject<T::MwData>>::from_raw(ibmw).take() });
+        0
+    }
+
+    unsafe extern "C" fn create_cq_callback(
+        ibcq: *mut bindings::ib_cq,
+        attr: *const bindings::ib_cq_init_attr,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core allocated `ibcq` with `size_ib_cq` bytes, set its device, and passes
+        // valid creation attributes.
+        let (dev, cq, mut attr) = unsafe {
+            (
+                Self::dev((*ibcq).device),
+                Object::<bindings::ib_cq, T::CqData>::from_raw(ibcq),
+                CqInitAttr {
+                    cqe: (*attr).cqe,
+                    comp_vector: (*attr).comp_vector,
+                    flags: (*attr).flags,
+                },
+            )
+        };
+        match T::create_cq(dev, &mut attr) {
+            Ok(data) => {
+                cq.raw.cqe = attr.cqe as _;
+                cq.init(data);
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn destroy_cq_callback(
+        ibcq: *mut bindings::ib_cq,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only destroys CQs whose `create_cq` succeeded, and never uses them
+        // after this callback.
+        let cq = unsafe { CompletionQueue::<T>::from_ptr(ibcq) };
+        T::destroy_cq(cq);
+        // SAFETY: The data was initialised by `create_cq_callback` and is never used again.
+        drop(unsafe { Object::<bindings::ib_cq, T::CqData>::from_raw(ibcq).take() });
+        0
+    }
+
+    unsafe extern "C" fn poll_cq_callback(
+        ibcq: *mut bindings::ib_cq,
+        num_entries: core::ffi::c_int,
+        wc: *mut bindings::ib_wc,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only polls CQs whose `create_cq` succeeded, and `wc` has room for
+        // `num_entries` completions.
+        let cq = unsafe { CompletionQueue::<T>::from_ptr(ibcq) };
+        let num_entries = cq.poll_entries(num_entries as usize) as core::ffi::c_int;
+        let mut polled = 0;
+        while polled < num_entries {
+            let completion = match T::poll_cq(cq) {
+                Some(completion) => completion,
+                None => break,
+            };
+            // SAFETY: `polled` is below `num_entries`, so the entry is inside the array.
+            completion.write_to(unsafe { &mut *wc.add(polled as usize) });
+            polled += 1;
+        }
+        polled
+    }
+
+    unsafe extern "C" fn req_notify_cq_callback(
+        ibcq: *mut bindings::ib_cq,
+        flags: bindings::ib_cq_notify_flags,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only arms CQs whose `create_cq` succeeded.
+        let cq = unsafe { CompletionQueue::<T>::from_ptr(ibcq) };
+        let notify = if flags & bindings::ib_cq_notify_flags_IB_CQ_SOLICITED_MASK
+            == bindings::ib_cq_notify_flags_IB_CQ_SOLICITED
+        {
+            CqNotify::Solicited
+        } else {
+            CqNotify::NextComp
+        };
+        match T::req_notify_cq(cq, notify) {
+            Ok(true) if flags & bindings::ib_cq_notify_flags_IB_CQ_REPORT_MISSED_EVENTS != 0 => 1,
+            Ok(_) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn create_srq_callback(
+        ibsrq: *mut bindings::ib_srq,
+        init: *mut bindings::ib_srq_init_attr,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core allocated `ibsrq` with `size_ib_srq` bytes, set its device and PD, and
+        // passes valid creation attributes.
+        let (pd, srq, init) = unsafe {
+            (
+                ProtectionDomain::<T>::from_ptr((*ibsrq).pd),
+                Object::<bindings::ib_srq, SrqObject<T::SrqData>>::from_raw(ibsrq),
+                &mut *init,
+            )
+        };
+        // Like the C driver's `rxe_srq_chk_init`.
+        if init.srq_type != bindings::ib_srq_type_IB_SRQT_BASIC {
+            return EOPNOTSUPP.to_kernel_errno();
+        }
+        let mut attr = SrqAttr::from_raw(&init.attr);
+        let limits = pd.device().attrs();
+        if attr.max_wr == 0
+            || attr.max_wr > limits.max_srq_wr()
+            || attr.max_sge > limits.max_srq_sge()
+        {
+            return EINVAL.to_kernel_errno();
+        }
+        match T::create_srq(pd, &mut attr) {
+            Ok(data) => {
+                attr.write_to(&mut init.attr);
+                let object = SrqObject::new(data);
+                object
+                    .limit
+                    .store(attr.srq_limit, core::sync::atomic::Ordering::Relaxed);
+                srq.init(object);
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn modify_srq_callback(
+        ibsrq: *mut bindings::ib_srq,
+        attr: *mut bindings::ib_srq_attr,
+        mask: bindings::ib_srq_attr_mask,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only modifies SRQs whose `create_srq` succeeded, with valid attributes.
+        let (srq, attr) = unsafe {
+            (
+                SharedReceiveQueue::<T>::from_ptr(ibsrq),
+                SrqAttr::from_raw(&*attr),
+            )
+        };
+        let mask = SrqAttrMask::from_bits(mask as u32);
+        match T::modify_srq(srq, &attr, mask) {
+            Ok(()) => {
+                if mask.contains(SrqAttrMask::LIMIT) {
+                    srq.set_limit(attr.srq_limit);
+                }
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn query_srq_callback(
+        ibsrq: *mut bindings::ib_srq,
+        raw: *mut bindings::ib_srq_attr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only queries SRQs whose `create_srq` succeeded, with a valid buffer.
+        let srq = unsafe { SharedReceiveQueue::<T>::from_ptr(ibsrq) };
+        let mut attr = SrqAttr::default();
+        match T::query_srq(srq, &mut attr) {
+            Ok(()) => {
+                attr.srq_limit = srq.limit();
+                // SAFETY: `raw` is valid per the above.
+                attr.write_to(unsafe { &mut *raw });
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn destroy_srq_callback(
+        ibsrq: *mut bindings::ib_srq,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only destroys SRQs whose `create_srq` succeeded, and never uses them
+        // after this callback.
+        let srq = unsafe { SharedReceiveQueue::<T>::from_ptr(ibsrq) };
+        T::destroy_srq(srq);
+        // SAFETY: The data was initialised by `create_srq_callback` and is never used again.
+        drop(unsafe { Object::<bindings::ib_srq, SrqObject<T::SrqData>>::from_raw(ibsrq).take() });
+        0
+    }
+
+    unsafe extern "C" fn post_srq_recv_callback(
+        ibsrq: *mut bindings::ib_srq,
+        wr: *const bindings::ib_recv_wr,
+        bad_wr: *mut *const bindings::ib_recv_wr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only posts to SRQs whose `create_srq` succeeded, with a valid chain of
+        // work requests that the caller does not touch until we return.
+        let (srq, wr) = unsafe {
+            (
+                SharedReceiveQueue::<T>::from_ptr(ibsrq),
+                RecvWr::from_ptr(wr),
+            )
+        };
+        for wr in wr.iter() {
+            if let Err(e) = T::post_srq_recv(srq, wr) {
+                // SAFETY: `bad_wr` is a valid pointer provided by the caller.
+                unsafe { *bad_wr = wr.as_ptr() };
+                return e.to_kernel_errno();
+            }
+        }
+        0
+    }
+
+    unsafe extern "C" fn create_qp_callback(
+        ibqp: *mut bindings::ib_qp,
+        init: *mut bindings::ib_qp_init_attr,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core allocated `ibqp` with `size_ib_qp` bytes, set its device, and passes
+        // valid creation attributes.
+        let (dev, qp, init) = unsafe {
+            (
+                Self::dev((*ibqp).device),
+                Object::<bindings::ib_qp, QpObject<T::QpData>>::from_raw(ibqp),
+                QpInitAttr::from_ptr(init),
+            )
+        };
+        match T::create_qp(dev, init) {
+            Ok((qp_num, data)) => {
+                qp.raw.qp_num = qp_num;
+                qp.init(QpObject::new(data));
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn modify_qp_callback(
+        ibqp: *mut bindings::ib_qp,
+        attr: *mut bindings::ib_qp_attr,
+        mask: core::ffi::c_int,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only modifies QPs whose `create_qp` succeeded, with valid attributes.
+        let (qp, attr) = unsafe {
+            (
+                QueuePair::<T>::from_ptr(ibqp),
+                QpAttr::from_raw(attr, mask as u32),
+            )
+        };
+        let faults = if cfg!(CONFIG_RUST_RDMA_FAULT_INJECTION) {
+            T::qp_faults(qp.device()).filter(|f| f.is_active())
+        } else {
+            None
+        };
+        if let Some(faults) = faults {
+            qp.take_injected_error(faults);
+        }
+        let next = match qp::check_modify(qp.qp_type(), qp.state(), &attr) {
+            Ok(next) => next,
+            Err(e) => return e.to_kernel_errno(),
+        };
+        if let Some(faults) = faults {
+            if faults.fail_modify(qp.qp_num(), qp.state(), next) {
+                return EIO.to_kernel_errno();
+            }
+        }
+        match T::modify_qp(qp, &attr) {
+            Ok(()) => {
+                qp.set_state(next);
+                0
+            }
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn destroy_qp_callback(
+        ibqp: *mut bindings::ib_qp,
+        _udata: *mut bindings::ib_udata,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only destroys QPs whose `create_qp` succeeded, and never uses them
+        // after this callback.
+        let qp = unsafe { QueuePair::<T>::from_ptr(ibqp) };
+        T::destroy_qp(qp);
+        // SAFETY: The data was initialised by `create_qp_callback` and is never used again.
+        drop(unsafe { Object::<bindings::ib_qp, QpObject<T::QpData>>::from_raw(ibqp).take() });
+        0
+    }
+
+    unsafe extern "C" fn post_send_callback(
+        ibqp: *mut bindings::ib_qp,
+        wr: *const bindings::ib_send_wr,
+        bad_wr: *mut *const bindings::ib_send_wr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only posts to QPs whose `create_qp` succeeded, with a valid chain of
+        // work requests that the caller does not touch until we return.
+        let (qp, wr) = unsafe { (QueuePair::<T>::from_ptr(ibqp), SendWr::from_ptr(wr)) };
+        if cfg!(CONFIG_RUST_RDMA_FAULT_INJECTION) {
+            if let Some(faults) = T::qp_faults(qp.device()) {
+                qp.take_injected_error(faults);
+            }
+        }
+        let ready = matches!(
+            qp.state(),
+            QpState::Rts | QpState::Sqd | QpState::Sqe | QpState::Err
+        );
+        if !ready {
+            // SAFETY: `bad_wr` is a valid pointer provided by the caller.
+            unsafe { *bad_wr = wr.as_ptr() };
+            return EINVAL.to_kernel_errno();
+        }
+        for wr in wr.iter() {
+            if let Err(e) = T::post_send(qp, wr) {
+                // SAFETY: `bad_wr` is a valid pointer provided by the caller.
+                unsafe { *bad_wr = wr.as_ptr() };
+                return e.to_kernel_errno();
+            }
+        }
+        0
+    }
+
+    unsafe extern "C" fn post_recv_callback(
+        ibqp: *mut bindings::ib_qp,
+        wr: *const bindings::ib_recv_wr,
+        bad_wr: *mut *const bindings::ib_recv_wr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only posts to QPs whose `create_qp` succeeded, with a valid chain of
+        // work requests that the caller does not touch until we return.
+        let (qp, wr) = unsafe { (QueuePair::<T>::from_ptr(ibqp), RecvWr::from_ptr(wr)) };
+        let ready = qp.state() != QpState::Reset && qp.srq().is_none();
+        if !ready {
+            // SAFETY: `bad_wr` is a valid pointer provided by the caller.
+            unsafe { *bad_wr = wr.as_ptr() };
+            return EINVAL.to_kernel_errno();
+        }
+        for wr in wr.iter() {
+            if let Err(e) = T::post_recv(qp, wr) {
+                // SAFETY: `bad_wr` is a valid pointer provided by the caller.
+                unsafe { *bad_wr = wr.as_ptr() };
+                return e.to_kernel_errno();
+            }
+        }
+        0
+    }
+
+    unsafe extern "C" fn attach_mcast_callback(
+        ibqp: *mut bindings::ib_qp,
+        gid: *mut bindings::ib_gid,
+        lid: u16,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only attaches QPs whose `create_qp` succeeded, with a valid GID.
+        let (qp, mgid) = unsafe { (QueuePair::<T>::from_ptr(ibqp), Gid::from_raw((*gid).raw)) };
+        match T::attach_mcast(qp, &mgid, lid) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn detach_mcast_callback(
+        ibqp: *mut bindings::ib_qp,
+        gid: *mut bindings::ib_gid,
+        lid: u16,
+    ) -> core::ffi::c_int {
+        // SAFETY: As in `attach_mcast_callback`.
+        let (qp, mgid) = unsafe { (QueuePair::<T>::from_ptr(ibqp), Gid::from_raw((*gid).raw)) };
+        match T::detach_mcast(qp, &mgid, lid) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    unsafe extern "C" fn fill_res_qp_entry_callback(
+        msg: *mut bindings::sk_buff,
+        ibqp: *mut bindings::ib_qp,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core holds a reference on the QP, one of ours, while it fills the message,
+        // which only this dump writes to.
+        unsafe {
+            let qp = QueuePair::<T>::from_ptr(ibqp);
+            DriverAttrs::fill(msg, |attrs| T::fill_res_qp_entry(qp, attrs))
+        }
+    }
+
+    unsafe extern "C" fn fill_res_cq_entry_callback(
+        msg: *mut bindings::sk_buff,
+        ibcq: *mut bindings::ib_cq,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core holds a reference on the CQ, one of ours, while it fills the message,
+        // which only this dump writes to.
+        unsafe {
+            let cq = CompletionQueue::<T>::from_ptr(ibcq);
+            DriverAttrs::fill(msg, |attrs| T::fill_res_cq_entry(cq, attrs))
+        }
+    }
+
+    unsafe extern "C" fn fill_res_mr_entry_callback(
+        msg: *mut bindings::sk_buff,
+        ibmr: *mut bindings::ib_mr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core holds a reference on the MR, one of ours, while it fills the message,
+        // which only this dump writes to.
+        unsafe {
+            let mr = MemoryRegion::<T>::from_ptr(ibmr);
+            DriverAttrs::fill(msg, |attrs| T::fill_res_mr_entry(mr, attrs))
+        }
+    }
+
+    unsafe extern "C" fn fill_res_srq_entry_callback(
+        msg: *mut bindings::sk_buff,
+        ibsrq: *mut bindings::ib_srq,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core holds a reference on the SRQ, one of ours, while it fills the message,
+        // which only this dump writes to.
+        unsafe {
+            let srq = SharedReceiveQueue::<T>::from_ptr(ibsrq);
+            DriverAttrs::fill(msg, |attrs| T::fill_res_srq_entry(srq, attrs))
+        }
+    }
+
+    unsafe extern "C" fn fill_stat_mr_entry_callback(
+        msg: *mut bindings::sk_buff,
+        ibmr: *mut bindings::ib_mr,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core holds a reference on the MR, one of ours, while it fills the message,
+        // which only this dump writes to.
+        unsafe {
+            let mr = MemoryRegion::<T>::from_ptr(ibmr);
+            StatCounters::fill(msg, |counters| T::fill_stat_mr_entry(mr, counters))
+        }
+    }
+
+    unsafe extern "C" fn alloc_hw_port_stats_callback(
+        ibdev: *mut bindings::ib_device,
+        port: u32,
+    ) -> *mut bindings::rdma_hw_stats {
+        // SAFETY: ib_core only calls us for our devices, during registration.
+        let dev = unsafe { DeviceRef::<T>::from_ptr(ibdev) };
+        // ib_core fails the registration when no statistics are returned.
+        T::alloc_hw_port_stats(dev, port)
+            .and_then(|stats| stats.alloc())
+            .unwrap_or(core::ptr::null_mut())
+    }
+
+    unsafe extern "C" fn get_hw_stats_callback(
+        ibdev: *mut bindings::ib_device,
+        stats: *mut bindings::rdma_hw_stats,
+        port: u32,
+        _index: core::ffi::c_int,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only calls us for our devices, with the statistics allocated by
+        // `alloc_hw_port_stats_callback`, whose lock it holds.
+        let (dev, values) = unsafe {
+            (
+                DeviceRef::<T>::from_ptr(ibdev),
+                (*stats).value.as_mut_slice((*stats).num_counters as usize),
+            )
+        };
+        // All counters are refreshed, whichever one is read.
+        match T::get_hw_stats(dev, port, values) {
+            Ok(n) => n.min(values.len()) as _,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe extern "C" fn process_mad_callback(
+        ibdev: *mut bindings::ib_device,
+        flags: core::ffi::c_int,
+        port: u32,
+        in_wc: *const bindings::ib_wc,
+        in_grh: *const bindings::ib_grh,
+        in_mad: *const bindings::ib_mad,
+        out_mad: *mut bindings::ib_mad,
+        out_mad_size: *mut usize,
+        out_mad_pkey_index: *mut u16,
+    ) -> core::ffi::c_int {
+        // SAFETY: ib_core only calls us on devices we allocated.
+        let dev = unsafe { Self::dev(ibdev) };
+        // SAFETY: `in_wc` is null for local MADs and valid otherwise, with the QP the MAD was
+        // received on. The MAD layer passes the receive buffer's GRH along with it.
+        let source = unsafe { in_wc.as_ref() }.map(|wc| unsafe { MadSource::from_wc(wc, in_grh) });
+        // SAFETY: The MAD layer passes a full IB MAD, and an output buffer of `*out_mad_size`
+        // bytes that nothing else accesses during the call.
+        let (mad, out) = unsafe {
+            (
+                core::slice::from_raw_parts(in_mad.cast::<u8>(), MAD_SIZE),
+                core::slice::from_raw_parts_mut(out_mad.cast::<u8>(), *out_mad_size),
+            )
+        };
+        let req = MadRequest {
+            flags: flags as u32,
+            source,
+            mad,
+        };
+        match T::process_mad(dev, port, &req, out) {
+            Ok(result) => {
+                // Replies go out with the P_Key the request came with.
+                if let (Some(source), false) = (source, out_mad_pkey_index.is_null()) {
+                    // SAFETY: The MAD layer passes a valid index to write, or null.
+                    unsafe { *out_mad_pkey_index = source.pkey_index };
+                }
+                result.bits() as _
+            }
+            Err(_) => MadResult::FAILURE.bits() as _,
+        }
+    }
+
+    unsafe extern "C" fn dealloc_driver_callback(ibdev: *mut bindings::ib_device) {
+        // SAFETY: ib_core calls this exactly once per device, from `ib_dealloc_device`, when no
+        // other callback can run anymore.
+        let dev = unsafe { Self::dev(ibdev) };
+        observer::retire(ibdev);
+        T::dealloc_driver(dev);
+        // SAFETY: The data was initialised in `Device::try_new` and is never used again.
+        drop(unsafe { Object::<bindings::ib_device, T::Data>::from_raw(ibdev).take() });
+    }
+}
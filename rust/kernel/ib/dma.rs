@@ -0,0 +1,406 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! DMA mappings of the memory a device accesses.
+//!
+//! C header: [`include/rdma/ib_verbs.h`](../../../../include/rdma/ib_verbs.h)
+//!
+//! A [`DmaMap`] maps memory for the `dma_device` of an InfiniBand device: streaming mappings of
+//! kmalloc buffers ([`DmaMap::map_single`]) and scatter-gather lists ([`DmaMap::map_sg`]), which
+//! are unmapped on drop, and coherent allocations ([`DmaMap::alloc_coherent`]). Soft providers
+//! register their device without a `dma_device`; their "DMA addresses" are then kernel virtual
+//! addresses, which the CPU copies from and to, see [`DmaMap::uses_virt_dma`].
+
+use alloc::boxed::Box;
+use core::mem::ManuallyDrop;
+use core::{marker, ptr, slice};
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use crate::bindings;
+use crate::error::{code::*, Result};
+
+/// Direction of the data of a DMA mapping, `enum dma_data_direction`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum DmaDirection {
+    /// The device reads and writes the memory.
+    Bidirectional = bindings::dma_data_direction_DMA_BIDIRECTIONAL,
+    /// The device reads the memory.
+    ToDevice = bindings::dma_data_direction_DMA_TO_DEVICE,
+    /// The device writes the memory.
+    FromDevice = bindings::dma_data_direction_DMA_FROM_DEVICE,
+}
+
+impl DmaDirection {
+    fn as_raw(self) -> bindings::dma_data_direction {
+        self as _
+    }
+}
+
+/// The DMA mapping operations of an InfiniBand device.
+///
+/// # Invariants
+///
+/// `dev` is a valid `struct ib_device` for the lifetime `'a`.
+#[derive(Clone, Copy)]
+pub struct DmaMap<'a> {
+    dev: *mut bindings::ib_device,
+    phantom: marker::PhantomData<&'a ()>,
+}
+
+impl<'a> DmaMap<'a> {
+    /// Returns the DMA mapping operations of `dev`.
+    pub fn new<T: IbDeviceOperations>(dev: &'a DeviceRef<T>) -> Self {
+        // INVARIANT: `dev` is borrowed for `'a`.
+        Self {
+            dev: dev.as_ptr(),
+            phantom: marker::PhantomData,
+        }
+    }
+
+    /// Returns `true` if the device has no `dma_device`, i.e. DMA addresses are kernel virtual
+    /// addresses.
+    pub fn uses_virt_dma(&self) -> bool {
+        // SAFETY: The device is valid by the type invariants.
+        unsafe { bindings::ib_uses_virt_dma(self.dev) }
+    }
+
+    /// Maps `buf` for streaming DMA in direction `dir`, until the returned mapping is dropped or
+    /// hands the buffer back with [`DmaSingle::unmap`].
+    ///
+    /// The buffer is taken over so that it is kmalloc memory, which can be mapped unlike stack or
+    /// vmalloc memory, and is not freed while mapped. The CPU gets it back between transfers with
+    /// [`DmaSingle::sync_for_cpu`]. Fails with `EINVAL` if it is empty, and with `ENOMEM` if the
+    /// mapping fails, e.g. because the IOMMU is out of space.
+    pub fn map_single(&self, mut buf: Box<[u8]>, dir: DmaDirection) -> Result<DmaSingle<'a>> {
+        if buf.is_empty() {
+            return Err(EINVAL);
+        }
+        // SAFETY: The device is valid by the type invariants, and `buf` is a kmalloc buffer that
+        // the mapping owns until it is unmapped.
+        let addr = unsafe {
+            bindings::ib_dma_map_single(self.dev, buf.as_mut_ptr().cast(), buf.len(), dir.as_raw())
+        };
+        // SAFETY: `addr` was just returned by `ib_dma_map_single`.
+        if unsafe { bindings::ib_dma_mapping_error(self.dev, addr) } != 0 {
+            return Err(ENOMEM);
+        }
+        // INVARIANT: The buffer was just mapped.
+        Ok(DmaSingle {
+            dev: self.dev,
+            buf,
+            addr,
+            dir,
+            phantom: marker::PhantomData,
+        })
+    }
+
+    /// Maps the `nents` entries of the scatter-gather list `sgl` for streaming DMA in direction
+    /// `dir`, until the returned mapping is dropped.
+    ///
+    /// The IOMMU may merge entries; the mapping lists the resulting DMA segments. Fails with
+    /// `ENOMEM` if the mapping fails.
+    ///
+    /// # Safety
+    ///
+    /// `sgl` must be a valid list of `nents` entries, whose pages the CPU does not access, and
+    /// which outlive the mapping.
+    pub unsafe fn map_sg<'b>(
+        &self,
+        sgl: *mut bindings::scatterlist,
+        nents: u32,
+        dir: DmaDirection,
+    ) -> Result<DmaSg<'b>>
+    where
+        'a: 'b,
+    {
+        // SAFETY: The device is valid by the type invariants, and the list by the safety
+        // requirements.
+        let mapped = unsafe { bindings::ib_dma_map_sg(self.dev, sgl, nents as _, dir.as_raw()) };
+        if mapped <= 0 {
+            return Err(ENOMEM);
+        }
+        // INVARIANT: The list was just mapped into `mapped` segments.
+        Ok(DmaSg {
+            dev: self.dev,
+            sgl,
+            nents,
+            mapped: mapped as u32,
+            dir,
+            phantom: marker::PhantomData,
+        })
+    }
+
+    /// Allocates `size` bytes of zeroed memory that the device and the CPU access coherently, until
+    /// the returned allocation is dropped.
+    ///
+    /// Fails with `EOPNOTSUPP` on devices without a `dma_device`, and with `ENOMEM` if the
+    /// allocation fails.
+    pub fn alloc_coherent(&self, size: usize) -> Result<DmaCoherent<'a>> {
+        if self.uses_virt_dma() {
+            return Err(EOPNOTSUPP);
+        }
+        let mut addr = 0;
+        // SAFETY: The device is valid by the type invariants, and has a `dma_device`.
+        let cpu = unsafe {
+            bindings::dma_alloc_attrs(
+                (*self.dev).dma_device,
+                size,
+                &mut addr,
+                bindings::GFP_KERNEL | bindings::__GFP_ZERO,
+                0,
+            )
+        };
+        if cpu.is_null() {
+            return Err(ENOMEM);
+        }
+        // INVARIANT: The memory was just allocated.
+        Ok(DmaCoherent {
+            dev: self.dev,
+            cpu: cpu.cast(),
+            size,
+            addr,
+            phantom: marker::PhantomData,
+        })
+    }
+}
+
+/// A kmalloc buffer mapped for streaming DMA, see [`DmaMap::map_single`].
+///
+/// # Invariants
+///
+/// `buf` is mapped at `addr` for `dev`, which is valid for `'a`, in direction `dir`.
+pub struct DmaSingle<'a> {
+    dev: *mut bindings::ib_device,
+    buf: Box<[u8]>,
+    addr: u64,
+    dir: DmaDirection,
+    phantom: marker::PhantomData<&'a ()>,
+}
+
+impl DmaSingle<'_> {
+    /// Returns the DMA address of the buffer.
+    pub fn dma_addr(&self) -> u64 {
+        self.addr
+    }
+
+    /// Returns the length of the buffer.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns `true` if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Returns the buffer to the CPU, after the device is done with it.
+    ///
+    /// The device must not access it again before [`DmaSingle::sync_for_device`].
+    pub fn sync_for_cpu(&mut self) -> &mut [u8] {
+        // SAFETY: By the type invariants the buffer is mapped.
+        unsafe {
+            bindings::ib_dma_sync_single_for_cpu(
+                self.dev,
+                self.addr,
+                self.buf.len(),
+                self.dir.as_raw(),
+            )
+        };
+        // The returned slice borrows `self`, so it is gone before the next sync for the device.
+        &mut self.buf
+    }
+
+    /// Gives the buffer back to the device, after the CPU is done with it.
+    pub fn sync_for_device(&mut self) {
+        // SAFETY: By the type invariants the buffer is mapped.
+        unsafe {
+            bindings::ib_dma_sync_single_for_device(
+                self.dev,
+                self.addr,
+                self.buf.len(),
+                self.dir.as_raw(),
+            )
+        };
+    }
+
+    /// Unmaps the buffer and hands it back to the CPU.
+    pub fn unmap(self) -> Box<[u8]> {
+        let mut this = ManuallyDrop::new(self);
+        this.unmap_buf();
+        // SAFETY: `this` is not dropped, so the buffer is moved out only once.
+        unsafe { ptr::read(&this.buf) }
+    }
+
+    fn unmap_buf(&mut self) {
+        // SAFETY: By the type invariants the buffer is mapped.
+        unsafe {
+            bindings::ib_dma_unmap_single(self.dev, self.addr, self.buf.len(), self.dir.as_raw())
+        };
+    }
+}
+
+impl Drop for DmaSingle<'_> {
+    fn drop(&mut self) {
+        // The buffer is freed once unmapped.
+        self.unmap_buf();
+    }
+}
+
+// SAFETY: The mapping can be synced and unmapped from any thread.
+unsafe impl Send for DmaSingle<'_> {}
+
+/// A scatter-gather list mapped for streaming DMA, see [`DmaMap::map_sg`].
+///
+/// # Invariants
+///
+/// The `nents` entries of `sgl` are mapped for `dev` into `mapped` segments, in direction `dir`,
+/// and valid for `'b`.
+pub struct DmaSg<'b> {
+    dev: *mut bindings::ib_device,
+    sgl: *mut bindings::scatterlist,
+    nents: u32,
+    mapped: u32,
+    dir: DmaDirection,
+    phantom: marker::PhantomData<&'b ()>,
+}
+
+impl DmaSg<'_> {
+    /// Returns the number of DMA segments.
+    pub fn segments(&self) -> u32 {
+        self.mapped
+    }
+
+    /// Returns an iterator over the DMA address and length of the segments.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u32)> + '_ {
+        let mut sg = self.sgl;
+        (0..self.mapped).map(move |_| {
+            // SAFETY: The first `mapped` entries hold the segments by the type invariants.
+            let seg = unsafe { (bindings::sg_dma_address(sg), bindings::sg_dma_len(sg)) };
+            // SAFETY: As above; the last `sg_next` may return null, which is never read.
+            sg = unsafe { bindings::sg_next(sg) };
+            seg
+        })
+    }
+
+    /// Hands the pages to the CPU, after the device is done with them.
+    pub fn sync_for_cpu(&mut self) {
+        if self.uses_virt_dma() {
+            return;
+        }
+        // SAFETY: By the type invariants the list is mapped, for the `dma_device` of `dev`.
+        unsafe {
+            bindings::dma_sync_sg_for_cpu(
+                (*self.dev).dma_device,
+                self.sgl,
+                self.nents as _,
+                self.dir.as_raw(),
+            )
+        };
+    }
+
+    /// Gives the pages back to the device, after the CPU is done with them.
+    pub fn sync_for_device(&mut self) {
+        if self.uses_virt_dma() {
+            return;
+        }
+        // SAFETY: As in `sync_for_cpu`.
+        unsafe {
+            bindings::dma_sync_sg_for_device(
+                (*self.dev).dma_device,
+                self.sgl,
+                self.nents as _,
+                self.dir.as_raw(),
+            )
+        };
+    }
+
+    fn uses_virt_dma(&self) -> bool {
+        // SAFETY: The device is valid by the type invariants.
+        unsafe { bindings::ib_uses_virt_dma(self.dev) }
+    }
+}
+
+impl Drop for DmaSg<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the list is mapped; unmapping takes the number of entries
+        // passed to `ib_dma_map_sg`.
+        unsafe {
+            bindings::ib_dma_unmap_sg(self.dev, self.sgl, self.nents as _, self.dir.as_raw())
+        };
+    }
+}
+
+// SAFETY: The mapping can be synced and unmapped from any thread.
+unsafe impl Send for DmaSg<'_> {}
+
+/// Memory allocated for coherent DMA, see [`DmaMap::alloc_coherent`].
+///
+/// # Invariants
+///
+/// `size` bytes at `cpu` were allocated with `dma_alloc_attrs` for the `dma_device` of `dev`, at
+/// the DMA address `addr`.
+pub struct DmaCoherent<'a> {
+    dev: *mut bindings::ib_device,
+    cpu: *mut u8,
+    size: usize,
+    addr: u64,
+    phantom: marker::PhantomData<&'a ()>,
+}
+
+impl DmaCoherent<'_> {
+    /// Returns the DMA address of the memory.
+    pub fn dma_addr(&self) -> u64 {
+        self.addr
+    }
+
+    /// Returns the size of the memory.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Returns `true` if the memory is empty.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns the memory as seen by the CPU.
+    ///
+    /// The device may access it at any time; callers order their accesses with it with barriers.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: The memory is allocated by the type invariants.
+        unsafe { slice::from_raw_parts(self.cpu, self.size) }
+    }
+
+    /// Returns the memory as seen by the CPU, mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: The memory is allocated by the type invariants, and `self` is borrowed mutably.
+        unsafe { slice::from_raw_parts_mut(self.cpu, self.size) }
+    }
+}
+
+impl Drop for DmaCoherent<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the memory was allocated for this `dma_device`.
+        unsafe {
+            bindings::dma_free_attrs(
+                (*self.dev).dma_device,
+                self.size,
+                self.cpu.cast(),
+                self.addr,
+                0,
+            )
+        };
+    }
+}
+
+// SAFETY: The memory can be freed from any thread.
+unsafe impl Send for DmaCoherent<'_> {}
+// SAFETY: Shared references only read the memory.
+unsafe impl Sync for DmaCoherent<'_> {}
+
+impl<T: IbDeviceOperations> DeviceRef<T> {
+    /// Returns the DMA mapping operations of the device.
+    pub fn dma(&self) -> DmaMap<'_> {
+        DmaMap::new(self)
+    }
+}
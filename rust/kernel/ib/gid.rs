@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! GID tables.
+//!
+//! ib_core owns the GID table of every port and, on RoCE ports, fills it from the IP addresses of
+//! the bound network device. Providers see entries come and go through the `add_gid` and
+//! `del_gid` hooks, and look entries up through a [`GidTable`].
+
+use core::cell::UnsafeCell;
+use core::ptr::NonNull;
+
+use super::compat::{ARef, AlwaysRefCounted};
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::netdev::NetDevice;
+use crate::bindings;
+use crate::error::{from_kernel_err_ptr, Result};
+use crate::rdma::gid::Gid;
+
+/// Type of a GID table entry, `enum ib_gid_type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GidType {
+    /// InfiniBand.
+    Ib,
+    /// RoCE v1.
+    Roce,
+    /// RoCE v2.
+    RoceUdpEncap,
+}
+
+impl GidType {
+    fn from_raw(raw: bindings::ib_gid_type) -> Self {
+        match raw {
+            bindings::ib_gid_type_IB_GID_TYPE_ROCE => GidType::Roce,
+            bindings::ib_gid_type_IB_GID_TYPE_ROCE_UDP_ENCAP => GidType::RoceUdpEncap,
+            _ => GidType::Ib,
+        }
+    }
+
+    fn to_raw(self) -> bindings::ib_gid_type {
+        match self {
+            GidType::Ib => bindings::ib_gid_type_IB_GID_TYPE_IB,
+            GidType::Roce => bindings::ib_gid_type_IB_GID_TYPE_ROCE,
+            GidType::RoceUdpEncap => bindings::ib_gid_type_IB_GID_TYPE_ROCE_UDP_ENCAP,
+        }
+    }
+}
+
+/// An entry of a GID table, wraps `struct ib_gid_attr`.
+#[repr(transparent)]
+pub struct GidAttr(UnsafeCell<bindings::ib_gid_attr>);
+
+impl GidAttr {
+    /// Creates a reference to a [`GidAttr`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid and the entry must stay in use for the lifetime of the returned
+    /// instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::ib_gid_attr) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `GidAttr` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    fn raw(&self) -> &bindings::ib_gid_attr {
+        // SAFETY: The entry is valid, and immutable while in use.
+        unsafe { &*self.0.get() }
+    }
+
+    /// Returns the raw `struct ib_gid_attr` pointer.
+    pub fn as_ptr(&self) -> *const bindings::ib_gid_attr {
+        self.0.get()
+    }
+
+    /// Returns the GID.
+    pub fn gid(&self) -> Gid {
+        // SAFETY: Every bit pattern is a valid `raw` view of the GID.
+        Gid::from_raw(unsafe { self.raw().gid.raw })
+    }
+
+    /// Returns the type of the entry.
+    pub fn gid_type(&self) -> GidType {
+        GidType::from_raw(self.raw().gid_type)
+    }
+
+    /// Returns the index of the entry in its table.
+    pub fn index(&self) -> u16 {
+        self.raw().index
+    }
+
+    /// Returns the port of the table.
+    pub fn port(&self) -> u32 {
+        self.raw().port_num
+    }
+
+    /// Returns the network device the entry was derived from, if any.
+    pub fn ndev(&self) -> Option<&NetDevice> {
+        let ndev = self.raw().ndev;
+        if ndev.is_null() {
+            return None;
+        }
+        // SAFETY: An entry in use holds a reference on its network device.
+        Some(unsafe { NetDevice::from_ptr(ndev) })
+    }
+}
+
+// SAFETY: `rdma_hold_gid_attr` keeps the entry in use until the matching `rdma_put_gid_attr`.
+unsafe impl AlwaysRefCounted for GidAttr {
+    fn inc_ref(&self) {
+        // SAFETY: The entry is in use while we have a reference to it.
+        unsafe { bindings::rdma_hold_gid_attr(self.as_ptr()) };
+    }
+
+    unsafe fn dec_ref(obj: NonNull<Self>) {
+        // SAFETY: The caller owns a reference on the entry.
+        unsafe { bindings::rdma_put_gid_attr(obj.as_ref().as_ptr()) };
+    }
+}
+
+// SAFETY: Entries are reference counted by ib_core and can be released from any thread.
+unsafe impl Send for GidAttr {}
+
+// SAFETY: Entries are immutable while in use.
+unsafe impl Sync for GidAttr {}
+
+/// The GID table of a port.
+pub struct GidTable<'a, T: IbDeviceOperations> {
+    dev: &'a DeviceRef<T>,
+    port: u32,
+}
+
+impl<'a, T: IbDeviceOperations> GidTable<'a, T> {
+    pub(crate) fn new(dev: &'a DeviceRef<T>, port: u32) -> Self {
+        Self { dev, port }
+    }
+
+    /// Returns the entry at `index`, `ENOENT` if it is empty.
+    pub fn get(&self, index: u32) -> Result<ARef<GidAttr>> {
+        // SAFETY: The device is valid; ib_core checks the port and index.
+        let ptr = unsafe { bindings::rdma_get_gid_attr(self.dev.as_ptr(), self.port, index) };
+        let ptr = from_kernel_err_ptr(ptr as *mut bindings::ib_gid_attr)?;
+        // SAFETY: `rdma_get_gid_attr` succeeded and returned a reference we own; `GidAttr` is
+        // transparent over `ib_gid_attr`.
+        Ok(unsafe { ARef::from_raw(NonNull::new_unchecked(ptr.cast())) })
+    }
+
+    /// Looks up the entry holding `gid` with type `gid_type`, optionally restricted to `ndev`.
+    pub fn find(
+        &self,
+        gid: &Gid,
+        gid_type: GidType,
+        ndev: Option<&NetDevice>,
+    ) -> Result<ARef<GidAttr>> {
+        let mut raw = bindings::ib_gid::default();
+        raw.raw = gid.raw();
+        let ndev = ndev.map_or(core::ptr::null_mut(), |n| n.as_ptr());
+        // SAFETY: The device is valid, `raw` lives across the call and `ndev` is valid or null.
+        let ptr = unsafe {
+            bindings::rdma_find_gid_by_port(
+                self.dev.as_ptr(),
+                &raw,
+                gid_type.to_raw(),
+                self.port,
+                ndev,
+            )
+        };
+        let ptr = from_kernel_err_ptr(ptr as *mut bindings::ib_gid_attr)?;
+        // SAFETY: `rdma_find_gid_by_port` succeeded and returned a reference we own; `GidAttr` is
+        // transparent over `ib_gid_attr`.
+        Ok(unsafe { ARef::from_raw(NonNull::new_unchecked(ptr.cast())) })
+    }
+}
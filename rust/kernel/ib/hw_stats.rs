@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Hardware counters of InfiniBand ports.
+//!
+//! `rdma statistic show` and the `hw_counters` directory of each port list counters the provider
+//! names, e.g. sent and received packets, retransmissions or errors. The provider describes them
+//! once, as a `static` array of [`HwCounter`] wrapped in an [`HwStats`] returned by
+//! [`super::IbDeviceOperations::alloc_hw_port_stats`], and ib_core asks for their values through
+//! [`super::IbDeviceOperations::get_hw_stats`], at most once per lifespan: reads in between are
+//! answered from the values of the previous call.
+
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::str::CStr;
+
+/// How long ib_core keeps the counter values before asking again, by default.
+pub const DEFAULT_LIFESPAN_MS: u32 = 10;
+
+/// A named hardware counter, wraps `struct rdma_stat_desc`.
+#[repr(transparent)]
+pub struct HwCounter(bindings::rdma_stat_desc);
+
+// SAFETY: The descriptor only points to static strings and is never written after construction.
+unsafe impl Sync for HwCounter {}
+
+impl HwCounter {
+    /// Creates a counter shown as `name`, e.g. `sent_pkts`.
+    pub const fn new(name: &'static CStr) -> Self {
+        Self(bindings::rdma_stat_desc {
+            name: name.as_char_ptr(),
+            flags: 0,
+            priv_: core::ptr::null(),
+        })
+    }
+
+    /// Marks the counter optional: it is disabled until enabled with `rdma statistic set`, e.g.
+    /// for counters that are costly to maintain.
+    pub const fn optional(mut self) -> Self {
+        self.0.flags |= bindings::IB_STAT_FLAG_OPTIONAL;
+        self
+    }
+}
+
+/// The hardware counters of a port, in the order of the values reported for them.
+#[derive(Clone, Copy)]
+pub struct HwStats {
+    counters: &'static [HwCounter],
+    lifespan_ms: u32,
+}
+
+impl HwStats {
+    /// Creates the description of `counters`, with the default lifespan.
+    pub const fn new(counters: &'static [HwCounter]) -> Self {
+        Self {
+            counters,
+            lifespan_ms: DEFAULT_LIFESPAN_MS,
+        }
+    }
+
+    /// Sets how long ib_core reuses the values before asking for new ones.
+    pub const fn with_lifespan(mut self, ms: u32) -> Self {
+        self.lifespan_ms = ms;
+        self
+    }
+
+    /// Returns the number of counters.
+    pub const fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    /// Returns `true` if there is no counter.
+    pub const fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    /// Allocates the `struct rdma_hw_stats` holding the values, which ib_core frees.
+    pub(crate) fn alloc(&self) -> Result<*mut bindings::rdma_hw_stats> {
+        if self.counters.is_empty() {
+            return Err(EINVAL);
+        }
+        // SAFETY: FFI call without preconditions.
+        let lifespan = unsafe { bindings::msecs_to_jiffies(self.lifespan_ms) };
+        // SAFETY: `HwCounter` is a transparent wrapper around `struct rdma_stat_desc`, and the
+        // counters are static, so they outlive the allocation pointing to them.
+        let stats = unsafe {
+            bindings::rdma_alloc_hw_stats_struct(
+                self.counters.as_ptr().cast(),
+                self.counters.len() as _,
+                lifespan,
+            )
+        };
+        if stats.is_null() {
+            return Err(ENOMEM);
+        }
+        Ok(stats)
+    }
+}
@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! KUnit suite `rust_rdma` of the example configurations.
+//!
+//! The samples and the smoke test register with the `example_*` configurations of
+//! [`rxe::Registration`] and [`mlx4::Registration`], which go through every setter of the
+//! configuration structs. The cases live next to the configurations, in the `kunit` modules of
+//! [`crate::rxe`] and [`crate::mlx4`], and check that each setter still lands in the value the
+//! matching getter, or the raw `struct mlx4_interface`, reports, so that a setter left behind as
+//! the configurations grow fails at boot rather than in a registration. This module only gathers
+//! them into the suite.
+//!
+//! The suite is put in `.kunit_test_suites` like `kunit_test_suite()` does, and runs when KUnit
+//! runs the built-in suites, e.g. with `kunit.filter_glob=rust_rdma`.
+//!
+//! [`rxe::Registration`]: crate::rxe::Registration
+//! [`mlx4::Registration`]: crate::mlx4::Registration
+
+use core::{mem, ptr};
+
+use crate::bindings;
+use crate::pr_err;
+
+/// Marks the test as failed and logs `what`, like a failed `KUNIT_EXPECT_*`.
+///
+/// # Safety
+///
+/// `test` must be the test passed to the running case.
+pub(crate) unsafe fn expect(test: *mut bindings::kunit, ok: bool, what: &str) {
+    if !ok {
+        pr_err!("rust_rdma: expectation failed: {}\n", what);
+        // SAFETY: The test is valid by the safety requirements; this is `kunit_set_failure`.
+        unsafe { ptr::write_volatile(&mut (*test).status, bindings::kunit_status_KUNIT_FAILURE) };
+    }
+}
+
+macro_rules! expect {
+    ($test:expr, $cond:expr) => {
+        // SAFETY: `$test` is the test of the running case.
+        unsafe { $crate::ib::kunit::expect($test, $cond, stringify!($cond)) }
+    };
+}
+pub(crate) use expect;
+
+/// Returns the terminating case of a case table.
+const fn last_case() -> bindings::kunit_case {
+    // SAFETY: `struct kunit_case` is plain C data, for which zeroes are the terminator.
+    unsafe { mem::transmute([0u8; mem::size_of::<bindings::kunit_case>()]) }
+}
+
+/// Builds the case `name` running `run`.
+const fn case(
+    name: &'static [u8],
+    run: unsafe extern "C" fn(*mut bindings::kunit),
+) -> bindings::kunit_case {
+    // KUnit fills in the status and the log, starting from `KUNIT_SUCCESS`, which is zero.
+    let mut case = last_case();
+    case.name = name.as_ptr().cast();
+    case.run_case = Some(run);
+    case
+}
+
+/// Number of entries of [`CASES`], the terminator included.
+const NUM_CASES: usize =
+    cfg!(CONFIG_RUST_RDMA_RXE) as usize + 2 * cfg!(CONFIG_RUST_RDMA_MLX4) as usize + 1;
+
+/// The cases of the suite, for the providers enabled in Kconfig.
+static mut CASES: [bindings::kunit_case; NUM_CASES] = [
+    #[cfg(CONFIG_RUST_RDMA_RXE)]
+    case(b"rxe_example_config\0", crate::rxe::kunit::socket_config),
+    #[cfg(CONFIG_RUST_RDMA_MLX4)]
+    case(
+        b"mlx4_example_interface_config\0",
+        crate::mlx4::kunit::interface_config,
+    ),
+    #[cfg(CONFIG_RUST_RDMA_MLX4)]
+    case(b"mlx4_example_config\0", crate::mlx4::kunit::event_mask),
+    last_case(),
+];
+
+/// Builds the suite `name` running `cases`.
+const fn suite(name: &[u8], cases: *mut bindings::kunit_case) -> bindings::kunit_suite {
+    // SAFETY: `struct kunit_suite` is plain C data, KUnit fills in the rest when running it.
+    let mut suite: bindings::kunit_suite =
+        unsafe { mem::transmute([0u8; mem::size_of::<bindings::kunit_suite>()]) };
+    let mut i = 0;
+    // The name stays NUL-terminated.
+    while i < name.len() && i < suite.name.len() - 1 {
+        suite.name[i] = name[i] as _;
+        i += 1;
+    }
+    suite.test_cases = cases;
+    suite
+}
+
+// SAFETY: Only KUnit accesses the cases, once it runs the suite.
+static mut SUITE: bindings::kunit_suite =
+    suite(b"rust_rdma", unsafe { ptr::addr_of_mut!(CASES) }.cast());
+
+/// The entry of the suite in the table of the built-in suites KUnit runs.
+#[used]
+#[link_section = ".kunit_test_suites"]
+// SAFETY: Only KUnit accesses the suite, once it runs it.
+static mut SUITE_ENTRY: *mut bindings::kunit_suite = unsafe { ptr::addr_of_mut!(SUITE) };
@@ -0,0 +1,532 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! MAD agents.
+//!
+//! C header: [`include/rdma/ib_mad.h`](../../../../include/rdma/ib_mad.h)
+//!
+//! A [`MadAgent`] registers with the MAD layer of ib_core on one port of a device, on the QP0 of
+//! subnet management or the QP1 of the general services, and receives the MADs of the
+//! management class and methods it registered for, like the agents of `/dev/umad*` do for
+//! userspace. It sends MADs through [`SendMad`] buffers; requests are retried until their
+//! response arrives, which the MAD layer matches by transaction ID. The headers are built and
+//! parsed with [`crate::rdma::mad`].
+//!
+//! The classes whose messages span several MADs use RMPP, which the MAD layer runs for the agents
+//! registered with [`Rmpp::Kernel`]: they send and receive whole messages. Agents registered with
+//! [`Rmpp::User`] see the segments instead.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::mem::ManuallyDrop;
+use core::pin::Pin;
+use core::{marker, ptr, slice};
+use macros::vtable;
+
+use super::device::MadSource;
+use super::observer::ObservedDevice;
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Error, Result};
+use crate::rdma::mad::{MadHdr, MAD_SIZE, RMPP_VERSION};
+
+/// `IB_MAD_USER_RMPP`, registers an agent that runs RMPP itself.
+const MAD_USER_RMPP: u32 = 1 << 0;
+
+/// The management QP of an agent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MadQpType {
+    /// QP0, subnet management packets. Only on InfiniBand link layer ports.
+    Smi,
+    /// QP1, general services.
+    Gsi,
+}
+
+impl MadQpType {
+    fn to_raw(self) -> bindings::ib_qp_type {
+        match self {
+            MadQpType::Smi => bindings::ib_qp_type_IB_QPT_SMI,
+            MadQpType::Gsi => bindings::ib_qp_type_IB_QPT_GSI,
+        }
+    }
+}
+
+/// Who runs RMPP for an agent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rmpp {
+    /// The class of the agent does not use RMPP.
+    None,
+    /// The MAD layer segments and reassembles the messages of the agent.
+    Kernel,
+    /// The agent sends and receives the segments itself.
+    User,
+}
+
+impl Rmpp {
+    fn version(self) -> u8 {
+        match self {
+            Rmpp::None => 0,
+            Rmpp::Kernel | Rmpp::User => RMPP_VERSION,
+        }
+    }
+
+    fn flags(self) -> u32 {
+        match self {
+            Rmpp::User => MAD_USER_RMPP,
+            Rmpp::None | Rmpp::Kernel => 0,
+        }
+    }
+}
+
+/// The unsolicited MADs an agent receives, `struct ib_mad_reg_req`.
+///
+/// Agents without one only receive the responses to their requests.
+#[derive(Clone, Copy)]
+pub struct MadRegistration {
+    raw: bindings::ib_mad_reg_req,
+}
+
+impl MadRegistration {
+    /// Returns a registration for `mgmt_class` at `class_version`, for no method yet.
+    pub fn new(mgmt_class: u8, class_version: u8) -> Self {
+        Self {
+            raw: bindings::ib_mad_reg_req {
+                mgmt_class,
+                mgmt_class_version: class_version,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Adds `method`, see [`crate::rdma::mad::method`], to the methods received.
+    pub fn with_method(mut self, method: u8) -> Self {
+        let (word, bit) = (method as usize / 64, method as usize % 64);
+        if let Some(word) = self.raw.method_mask.get_mut(word) {
+            *word |= 1 << bit;
+        }
+        self
+    }
+
+    /// Sets the OUI of a vendor class with OUI.
+    pub fn with_oui(mut self, oui: [u8; 3]) -> Self {
+        self.raw.oui = oui;
+        self
+    }
+}
+
+/// How a MAD sent by an agent completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendStatus {
+    /// The MAD was sent, and its response received if it expected one.
+    Success,
+    /// No response arrived within the timeout and retries of the request.
+    TimedOut,
+    /// The send was canceled, e.g. because the agent is unregistered.
+    Canceled,
+    /// The send failed with this `enum ib_wc_status`.
+    Failed(u32),
+}
+
+impl SendStatus {
+    fn from_raw(status: bindings::ib_wc_status) -> Self {
+        match status {
+            bindings::ib_wc_status_IB_WC_SUCCESS => SendStatus::Success,
+            bindings::ib_wc_status_IB_WC_RESP_TIMEOUT_ERR => SendStatus::TimedOut,
+            bindings::ib_wc_status_IB_WC_WR_FLUSH_ERR => SendStatus::Canceled,
+            status => SendStatus::Failed(status as u32),
+        }
+    }
+}
+
+/// A registered MAD agent, wraps `struct ib_mad_agent`.
+///
+/// Owned by a [`MadAgent`], or lent to the callbacks of its handler.
+#[repr(transparent)]
+pub struct MadAgentRef(UnsafeCell<bindings::ib_mad_agent>);
+
+impl MadAgentRef {
+    /// Creates a reference to a [`MadAgentRef`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_mad_agent) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `MadAgentRef` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_mad_agent` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_mad_agent {
+        self.0.get()
+    }
+
+    /// Returns the device of the agent.
+    pub fn device(&self) -> &ObservedDevice {
+        // SAFETY: The agent is valid and holds its device until it is unregistered.
+        unsafe { ObservedDevice::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the port of the agent.
+    pub fn port_num(&self) -> u32 {
+        // SAFETY: The agent is valid.
+        unsafe { (*self.as_ptr()).port_num }
+    }
+
+    /// Returns the ID the MAD layer puts in the high 32 bits of the transaction IDs of the
+    /// agent's requests, to route their responses back to it.
+    pub fn hi_tid(&self) -> u32 {
+        // SAFETY: The agent is valid.
+        unsafe { (*self.as_ptr()).hi_tid }
+    }
+
+    /// Allocates a MAD to send to `remote_qpn`, made of `hdr_len` bytes of headers and
+    /// `data_len` bytes of data.
+    ///
+    /// With `rmpp_active`, the data is sent in as many segments as needed by an agent registered
+    /// with [`Rmpp::Kernel`]; otherwise the headers and data must fit in one MAD.
+    pub fn create_send(
+        &self,
+        remote_qpn: u32,
+        pkey_index: u16,
+        rmpp_active: bool,
+        hdr_len: usize,
+        data_len: usize,
+    ) -> Result<SendMad<'_>> {
+        if !rmpp_active && hdr_len + data_len > MAD_SIZE {
+            return Err(EINVAL);
+        }
+        // SAFETY: The agent is valid.
+        let buf = from_kernel_err_ptr(unsafe {
+            bindings::ib_create_send_mad(
+                self.as_ptr(),
+                remote_qpn,
+                pkey_index,
+                rmpp_active as _,
+                hdr_len as _,
+                data_len as _,
+                bindings::GFP_KERNEL,
+                bindings::IB_MGMT_BASE_VERSION as _,
+            )
+        })?;
+        // INVARIANT: The buffer was just created on this agent and is not posted.
+        Ok(SendMad {
+            buf,
+            phantom: marker::PhantomData,
+        })
+    }
+}
+
+/// A MAD being built, allocated by [`MadAgentRef::create_send`].
+///
+/// # Invariants
+///
+/// `buf` was created on the agent `'a` borrows, is not posted, and is freed on drop along with
+/// its address handle, if any.
+pub struct SendMad<'a> {
+    buf: *mut bindings::ib_mad_send_buf,
+    phantom: marker::PhantomData<&'a MadAgentRef>,
+}
+
+impl SendMad<'_> {
+    /// Returns the raw `struct ib_mad_send_buf` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_mad_send_buf {
+        self.buf
+    }
+
+    fn is_rmpp(&self) -> bool {
+        // SAFETY: By the type invariants the buffer is valid.
+        unsafe { (*self.buf).seg_count != 0 }
+    }
+
+    /// Returns the MAD to fill in: the headers and data of a single MAD, only the headers of an
+    /// RMPP message, whose data goes to [`SendMad::segment_mut`].
+    pub fn mad_mut(&mut self) -> &mut [u8] {
+        // SAFETY: By the type invariants the buffer is valid, and the MAD layer allocated the
+        // headers followed by the data of single MADs.
+        unsafe {
+            let buf = &*self.buf;
+            let len = if self.is_rmpp() {
+                buf.hdr_len
+            } else {
+                buf.hdr_len + buf.data_len
+            };
+            slice::from_raw_parts_mut(buf.mad.cast(), len as usize)
+        }
+    }
+
+    /// Returns the number of data segments of an RMPP message, 0 for single MADs.
+    pub fn segments(&self) -> u32 {
+        // SAFETY: By the type invariants the buffer is valid.
+        unsafe { (*self.buf).seg_count as u32 }
+    }
+
+    /// Returns the data of segment `seg_num` of an RMPP message, counting from 1.
+    pub fn segment_mut(&mut self, seg_num: u32) -> Option<&mut [u8]> {
+        if seg_num == 0 || seg_num > self.segments() {
+            return None;
+        }
+        // SAFETY: By the type invariants the buffer is valid, and the segment exists.
+        unsafe {
+            let data = bindings::ib_get_rmpp_segment(self.buf, seg_num as _);
+            Some(slice::from_raw_parts_mut(
+                data.cast(),
+                (*self.buf).seg_size as usize,
+            ))
+        }
+    }
+
+    /// Addresses the MAD to the sender of `recv`, e.g. to answer it.
+    pub fn reply_to(&mut self, recv: &RecvMad<'_>) -> Result {
+        // SAFETY: By the type invariants the buffer is valid, and so are its agent, the QP of the
+        // agent and the completion of the received MAD.
+        let ah = from_kernel_err_ptr(unsafe {
+            let agent = &*(*self.buf).mad_agent;
+            let wc = &*recv.wc;
+            bindings::ib_create_ah_from_wc((*agent.qp).pd, wc.wc, wc.recv_buf.grh, agent.port_num)
+        })?;
+        // SAFETY: By the type invariants the buffer is valid and not posted.
+        unsafe {
+            destroy_ah((*self.buf).ah);
+            (*self.buf).ah = ah;
+        }
+        Ok(())
+    }
+
+    /// Posts the MAD; a request is resent `retries` times until its response arrives, after
+    /// `timeout_ms` each. [`MadHandler::send_done`] is called once it completes.
+    pub fn post(self, timeout_ms: u32, retries: u32) -> Result {
+        // SAFETY: By the type invariants the buffer is valid and not posted.
+        let ret = unsafe {
+            (*self.buf).timeout_ms = timeout_ms as _;
+            (*self.buf).retries = retries as _;
+            bindings::ib_post_send_mad(self.buf, ptr::null_mut())
+        };
+        if ret < 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+        // The send handler frees the buffer.
+        let _ = ManuallyDrop::new(self);
+        Ok(())
+    }
+}
+
+impl Drop for SendMad<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the buffer is not posted.
+        unsafe { free_send(self.buf) };
+    }
+}
+
+/// Destroys the address handle `ah` of a MAD, if any.
+///
+/// # Safety
+///
+/// `ah` must be null, or created by [`SendMad::reply_to`] and not used anymore.
+unsafe fn destroy_ah(ah: *mut bindings::ib_ah) {
+    if !ah.is_null() {
+        // SAFETY: Guaranteed by the safety requirements; the MAD layer calls us in process
+        // context.
+        unsafe {
+            bindings::rdma_destroy_ah_user(
+                ah,
+                bindings::rdma_destroy_ah_flags_RDMA_DESTROY_AH_SLEEPABLE as _,
+                ptr::null_mut(),
+            )
+        };
+    }
+}
+
+/// Frees `buf` and its address handle.
+///
+/// # Safety
+///
+/// `buf` must be a send buffer of ours that is not posted, or whose send completed.
+unsafe fn free_send(buf: *mut bindings::ib_mad_send_buf) {
+    // SAFETY: Guaranteed by the safety requirements.
+    unsafe {
+        destroy_ah((*buf).ah);
+        bindings::ib_free_send_mad(buf);
+    }
+}
+
+/// A MAD received by an agent, lent to [`MadHandler::recv`].
+pub struct RecvMad<'a> {
+    wc: *mut bindings::ib_mad_recv_wc,
+    phantom: marker::PhantomData<&'a bindings::ib_mad_recv_wc>,
+}
+
+impl RecvMad<'_> {
+    /// Returns the sender of the MAD.
+    pub fn source(&self) -> MadSource {
+        // SAFETY: The MAD layer passes the completion of the MAD, which outlives `self`, with
+        // the QP it was received on and the GRH of its receive buffer.
+        unsafe { MadSource::from_wc(&*(*self.wc).wc, (*self.wc).recv_buf.grh) }
+    }
+
+    /// Returns the length of the message, the data of every segment of an RMPP message.
+    pub fn len(&self) -> usize {
+        // SAFETY: The completion is valid.
+        unsafe { (*self.wc).mad_len as usize }
+    }
+
+    /// Returns `true` if the message is empty, which the MAD layer never delivers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the first MAD of the message, the whole message unless it is an RMPP one.
+    pub fn mad(&self) -> &[u8] {
+        // SAFETY: The completion is valid, and its first buffer holds a MAD of `mad_seg_size`
+        // bytes.
+        unsafe {
+            let wc = &*self.wc;
+            let len = (wc.mad_seg_size as usize).min(wc.mad_len as usize);
+            slice::from_raw_parts(wc.recv_buf.__bindgen_anon_1.mad.cast(), len)
+        }
+    }
+
+    /// Returns the common header of the MAD.
+    pub fn hdr(&self) -> Option<MadHdr> {
+        MadHdr::parse(self.mad())
+    }
+
+    /// Copies the whole message to `buf`, the data of the segments of an RMPP message following
+    /// the headers of the first one, and returns its length.
+    pub fn copy_to(&self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.len();
+        if buf.len() < len {
+            return Err(EINVAL);
+        }
+        // SAFETY: The completion is valid and `buf` holds the whole message.
+        unsafe { bindings::ib_coalesce_recv_mad(self.wc, buf.as_mut_ptr().cast()) };
+        Ok(len)
+    }
+}
+
+/// Handles the MADs of a [`MadAgent`].
+///
+/// The callbacks run in process context and may sleep.
+#[vtable]
+pub trait MadHandler: Send + Sync + Sized {
+    /// Called for every MAD received by the agent, unsolicited ones and responses to its
+    /// requests. Agents that do not implement it only send.
+    fn recv(&self, _agent: &MadAgentRef, _mad: &RecvMad<'_>) {}
+
+    /// Called once a MAD posted by [`SendMad::post`] completed; `tid` is its transaction ID.
+    fn send_done(&self, _tid: u64, _status: SendStatus) {}
+}
+
+/// A MAD agent, unregistered on drop.
+///
+/// # Invariants
+///
+/// `ptr` is a registered agent whose context is `handler`, owned by the instance.
+pub struct MadAgent<T: MadHandler> {
+    ptr: *mut bindings::ib_mad_agent,
+    handler: Pin<Box<T>>,
+}
+
+// SAFETY: The agent may be used and unregistered from any thread, and the handler is `Send`.
+unsafe impl<T: MadHandler> Send for MadAgent<T> {}
+// SAFETY: The MAD layer serialises the calls on the agent, and the handler is `Sync`.
+unsafe impl<T: MadHandler> Sync for MadAgent<T> {}
+
+impl<T: MadHandler> MadAgent<T> {
+    /// Registers an agent on `port` of `dev` and `qp`, receiving the MADs of `reg`.
+    pub fn register(
+        dev: &ObservedDevice,
+        port: u32,
+        qp: MadQpType,
+        reg: Option<MadRegistration>,
+        rmpp: Rmpp,
+        handler: Pin<Box<T>>,
+    ) -> Result<Self> {
+        if reg.is_some() && !T::HAS_RECV {
+            return Err(EINVAL);
+        }
+        let mut reg = reg.map(|reg| reg.raw);
+        let reg = reg.as_mut().map_or(ptr::null_mut(), |reg| reg as *mut _);
+        let context = &*handler as *const T as *mut core::ffi::c_void;
+        // SAFETY: The device is valid, the registration request outlives the call, which copies
+        // it, and the handler outlives the agent, which `drop` unregisters.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::ib_register_mad_agent(
+                dev.as_ptr(),
+                port,
+                qp.to_raw(),
+                reg,
+                rmpp.version(),
+                Some(send_handler::<T>),
+                if T::HAS_RECV {
+                    Some(recv_handler::<T>)
+                } else {
+                    None
+                },
+                context,
+                rmpp.flags(),
+            )
+        })?;
+        // INVARIANT: The agent was just registered with `handler` as context.
+        Ok(Self { ptr, handler })
+    }
+
+    /// Returns the handler of the agent.
+    pub fn handler(&self) -> Pin<&T> {
+        self.handler.as_ref()
+    }
+}
+
+impl<T: MadHandler> core::ops::Deref for MadAgent<T> {
+    type Target = MadAgentRef;
+
+    fn deref(&self) -> &MadAgentRef {
+        // SAFETY: By the type invariants the agent is registered.
+        unsafe { MadAgentRef::from_ptr(self.ptr) }
+    }
+}
+
+impl<T: MadHandler> Drop for MadAgent<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants we own the agent. Unregistering it cancels its sends and
+        // waits for its callbacks, so the handler is not used once this returns.
+        unsafe { bindings::ib_unregister_mad_agent(self.ptr) };
+    }
+}
+
+unsafe extern "C" fn send_handler<T: MadHandler>(
+    agent: *mut bindings::ib_mad_agent,
+    send_wc: *mut bindings::ib_mad_send_wc,
+) {
+    // SAFETY: The MAD layer passes a registered agent, whose context is the handler of the
+    // `MadAgent` owning it, and the completion of a buffer we posted.
+    let (handler, buf, status) = unsafe {
+        (
+            &*(*agent).context.cast::<T>(),
+            (*send_wc).send_buf,
+            (*send_wc).status,
+        )
+    };
+    // SAFETY: The buffer is valid and holds at least the common header.
+    let mad = unsafe { slice::from_raw_parts((*buf).mad.cast::<u8>(), (*buf).hdr_len as usize) };
+    let tid = MadHdr::parse(mad).map_or(0, |hdr| hdr.tid);
+    handler.send_done(tid, SendStatus::from_raw(status));
+    // SAFETY: The send completed, so the buffer is ours again.
+    unsafe { free_send(buf) };
+}
+
+unsafe extern "C" fn recv_handler<T: MadHandler>(
+    agent: *mut bindings::ib_mad_agent,
+    _send_buf: *mut bindings::ib_mad_send_buf,
+    recv_wc: *mut bindings::ib_mad_recv_wc,
+) {
+    // SAFETY: The MAD layer passes a registered agent, whose context is the handler of the
+    // `MadAgent` owning it.
+    let (agent_ref, handler) =
+        unsafe { (MadAgentRef::from_ptr(agent), &*(*agent).context.cast::<T>()) };
+    let mad = RecvMad {
+        wc: recv_wc,
+        phantom: marker::PhantomData,
+    };
+    handler.recv(agent_ref, &mad);
+    // SAFETY: The completion is ours to free, and `mad` is not used anymore.
+    unsafe { bindings::ib_free_recv_mad(recv_wc) };
+}
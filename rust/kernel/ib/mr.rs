@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory regions.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::marker;
+use core::ptr;
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::pd::ProtectionDomain;
+use super::restrack::ResourceEntry;
+use super::Object;
+use crate::bindings;
+use crate::rdma::mr_key::MrKey;
+use crate::rdma::mw::{BoundWindows, MrView};
+
+/// `IB_ACCESS_*` flags that let remote peers use a memory region.
+pub const ACCESS_REMOTE: u32 = (bindings::ib_access_flags_IB_ACCESS_REMOTE_WRITE
+    | bindings::ib_access_flags_IB_ACCESS_REMOTE_READ
+    | bindings::ib_access_flags_IB_ACCESS_REMOTE_ATOMIC) as u32;
+
+/// A memory region being registered, returned by the provider's registration hooks.
+pub struct NewMr<D> {
+    /// Key of the region. It becomes the lkey, and the rkey too if remote access is granted.
+    pub key: MrKey,
+    /// First I/O virtual address of the region.
+    pub iova: u64,
+    /// Length of the region in bytes, `u64::MAX` for DMA MRs.
+    pub length: u64,
+    /// Provider data of the region.
+    pub data: D,
+}
+
+/// Provider data of an MR together with the number of memory windows bound to it.
+pub(crate) struct MrObject<D> {
+    pub(crate) access: u32,
+    pub(crate) windows: BoundWindows,
+    pub(crate) data: D,
+}
+
+/// A memory region of a device provided by `T`, wraps `struct ib_mr`.
+///
+/// Unlike PDs and QPs, MRs are allocated by the provider: the abstraction boxes the `struct ib_mr`
+/// together with `T::MrData` when a registration hook succeeds and frees both on `dereg_mr`.
+/// Deregistration is refused while memory windows are bound to the region.
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_mr` is the `raw` field of a boxed `Object<ib_mr, MrObject<T::MrData>>`
+/// with initialised data, created by a registration hook of `T`.
+#[repr(transparent)]
+pub struct MemoryRegion<T: IbDeviceOperations>(UnsafeCell<bindings::ib_mr>, marker::PhantomData<T>);
+
+impl<T: IbDeviceOperations> MemoryRegion<T> {
+    /// Creates a reference to a [`MemoryRegion`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an MR returned by [`MemoryRegion::into_raw`] with the same `T`, not
+    /// deregistered for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_mr) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `MemoryRegion` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Boxes a new MR and returns the `struct ib_mr` to hand to ib_core.
+    pub(crate) fn into_raw(
+        mr: NewMr<T::MrData>,
+        access: u32,
+    ) -> crate::error::Result<*mut bindings::ib_mr> {
+        let mut object = Box::try_new(Object {
+            raw: bindings::ib_mr::default(),
+            data: core::mem::MaybeUninit::uninit(),
+        })?;
+        object.raw.lkey = mr.key.raw();
+        object.raw.rkey = if access & ACCESS_REMOTE != 0 {
+            mr.key.raw()
+        } else {
+            0
+        };
+        object.raw.iova = mr.iova;
+        object.raw.length = mr.length;
+        object.init(MrObject {
+            access,
+            windows: BoundWindows::new(),
+            data: mr.data,
+        });
+        // INVARIANT: The object is boxed and its data initialised.
+        Ok(Box::into_raw(object).cast())
+    }
+
+    /// Drops an MR created by [`MemoryRegion::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from [`MemoryRegion::into_raw`] with the same `T` and must not be used
+    /// afterwards.
+    pub(crate) unsafe fn drop_raw(ptr: *mut bindings::ib_mr) {
+        // SAFETY: Guaranteed by the safety requirements, the object was boxed by `into_raw`.
+        let mut object =
+            unsafe { Box::from_raw(ptr.cast::<Object<bindings::ib_mr, MrObject<T::MrData>>>()) };
+        // SAFETY: The data was initialised by `into_raw` and is not used after this.
+        drop(unsafe { object.take() });
+    }
+
+    /// Returns the raw `struct ib_mr` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_mr {
+        self.0.get()
+    }
+
+    /// Returns the restrack record of the MR, with its ID and creator.
+    pub fn restrack(&self) -> &ResourceEntry {
+        // SAFETY: The MR is valid and embeds its entry.
+        unsafe { ResourceEntry::from_ptr(ptr::addr_of_mut!((*self.as_ptr()).res)) }
+    }
+
+    /// Returns the device the MR belongs to.
+    pub fn device(&self) -> &DeviceRef<T> {
+        // SAFETY: ib_core sets the device right after registration, before the MR can be used.
+        unsafe { DeviceRef::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the protection domain of the MR.
+    pub fn pd(&self) -> &ProtectionDomain<T> {
+        // SAFETY: ib_core sets the PD right after registration, and the PD outlives its MRs.
+        unsafe { ProtectionDomain::from_ptr((*self.as_ptr()).pd) }
+    }
+
+    pub(crate) fn object(&self) -> &MrObject<T::MrData> {
+        // SAFETY: By the type invariants the object is initialised.
+        unsafe { Object::<bindings::ib_mr, MrObject<T::MrData>>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns the provider data of the MR.
+    pub fn data(&self) -> &T::MrData {
+        &self.object().data
+    }
+
+    /// Returns the `IB_ACCESS_*` flags the MR was registered with.
+    pub fn access(&self) -> u32 {
+        self.object().access
+    }
+
+    /// Returns the number of memory windows bound to the MR.
+    ///
+    /// Providers must not invalidate a region while windows are bound to it.
+    pub fn windows(&self) -> &BoundWindows {
+        &self.object().windows
+    }
+
+    /// Returns the region as seen by the memory window bind checks.
+    pub fn view(&self) -> MrView {
+        MrView {
+            lkey: self.lkey(),
+            pd: self.pd().as_ptr() as usize,
+            iova: self.iova(),
+            length: self.length(),
+            access: self.access(),
+        }
+    }
+
+    /// Returns the local key.
+    pub fn lkey(&self) -> MrKey {
+        // SAFETY: The MR is valid.
+        MrKey::from_raw(unsafe { (*self.as_ptr()).lkey })
+    }
+
+    /// Returns the remote key, `None` if the MR grants no remote access.
+    pub fn rkey(&self) -> Option<MrKey> {
+        // SAFETY: The MR is valid.
+        match unsafe { (*self.as_ptr()).rkey } {
+            0 => None,
+            rkey => Some(MrKey::from_raw(rkey)),
+        }
+    }
+
+    /// Returns the first I/O virtual address of the region.
+    pub fn iova(&self) -> u64 {
+        // SAFETY: The MR is valid.
+        unsafe { (*self.as_ptr()).iova }
+    }
+
+    /// Returns the length of the region in bytes.
+    pub fn length(&self) -> u64 {
+        // SAFETY: The MR is valid.
+        unsafe { (*self.as_ptr()).length }
+    }
+}
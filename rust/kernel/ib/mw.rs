@@ -0,0 +1,232 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory windows.
+//!
+//! The bind rules live in [`crate::rdma::mw`]; this wraps `struct ib_mw` and keeps the
+//! [`MemoryRegion::windows`] count of the region a window is bound to up to date.
+
+use core::cell::UnsafeCell;
+use core::marker;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::mr::MemoryRegion;
+use super::pd::ProtectionDomain;
+use super::Object;
+use crate::bindings;
+use crate::rdma::mr_key::MrKey;
+use crate::sync::SpinLock;
+
+pub use crate::rdma::mw::{BindError, BindMw, InvalidateError, MwState, MwType, Window};
+
+/// A memory window being allocated, returned by [`IbDeviceOperations::alloc_mw`].
+pub struct NewMw<D> {
+    /// Initial rkey of the window, from the same key space as the MRs.
+    pub rkey: MrKey,
+    /// Provider data of the window.
+    pub data: D,
+}
+
+/// Provider data of a window together with its bind state.
+///
+/// The window is checked from the receive path in softirq context, so its lock is taken with
+/// interrupts, and bottom halves with them, disabled.
+pub(crate) struct MwObject<D> {
+    pub(crate) window: SpinLock<Window>,
+    /// The `struct ib_mr` the window is bound to, changed with the window locked.
+    pub(crate) mr: AtomicPtr<bindings::ib_mr>,
+    pub(crate) data: D,
+}
+
+/// A memory window of a device provided by `T`, wraps `struct ib_mw`.
+///
+/// ib_core allocates the `struct ib_mw` for userspace consumers; binds are posted to the provider's
+/// send queues and applied with [`MemoryWindow::bind`].
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_mw` belongs to a device provided by `T` and is followed by an
+/// initialised `MwObject<T::MwData>`.
+#[repr(transparent)]
+pub struct MemoryWindow<T: IbDeviceOperations>(UnsafeCell<bindings::ib_mw>, marker::PhantomData<T>);
+
+impl<T: IbDeviceOperations> MemoryWindow<T> {
+    /// Creates a reference to a [`MemoryWindow`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an MW of a device provided by `T` whose `alloc_mw` succeeded and which is not
+    /// deallocated for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_mw) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `MemoryWindow` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_mw` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_mw {
+        self.0.get()
+    }
+
+    fn object(&self) -> &MwObject<T::MwData> {
+        // SAFETY: By the type invariants the object is initialised.
+        unsafe { Object::<bindings::ib_mw, MwObject<T::MwData>>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns the device the MW belongs to.
+    pub fn device(&self) -> &DeviceRef<T> {
+        // SAFETY: By the type invariants the MW belongs to a device provided by `T`, which
+        // outlives all of its MWs.
+        unsafe { DeviceRef::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the protection domain of the MW.
+    pub fn pd(&self) -> &ProtectionDomain<T> {
+        // SAFETY: ib_core sets the PD before calling `alloc_mw`, and it outlives the MW.
+        unsafe { ProtectionDomain::from_ptr((*self.as_ptr()).pd) }
+    }
+
+    /// Returns the provider data of the MW.
+    pub fn data(&self) -> &T::MwData {
+        &self.object().data
+    }
+
+    /// Returns the type of the window.
+    pub fn mw_type(&self) -> MwType {
+        self.object().window.lock_irqdisable().mw_type()
+    }
+
+    /// Returns the current rkey.
+    pub fn rkey(&self) -> MrKey {
+        // SAFETY: The MW is valid; the rkey is only changed with the window locked.
+        MrKey::from_raw(unsafe { (*self.as_ptr()).rkey })
+    }
+
+    /// Returns a copy of the bind state.
+    pub fn state(&self) -> Window {
+        *self.object().window.lock_irqdisable()
+    }
+
+    /// Binds the window to `mr`, or unbinds it if `mr` is `None`, on behalf of `qp_pd` and QP
+    /// `qpn`, e.g. for a bind work request.
+    ///
+    /// `req.mr` is filled from `mr`. On failure the window is unchanged and the error is reported
+    /// as an `IB_WC_MW_BIND_ERR` completion.
+    pub fn bind(
+        &self,
+        qp_pd: &ProtectionDomain<T>,
+        qpn: u32,
+        mut req: BindMw,
+        mr: Option<&MemoryRegion<T>>,
+    ) -> Result<(), BindError> {
+        req.mr = mr.map(MemoryRegion::view);
+        // Hold the region before checking the request, so that it cannot be deregistered from
+        // under the window once bound.
+        if let Some(mr) = mr {
+            if !mr.windows().bind() {
+                return Err(BindError::Deregistered);
+            }
+        }
+        let mut window = self.object().window.lock_irqdisable();
+        self.bump_generation();
+        if let Err(e) = window.bind(qp_pd.as_ptr() as usize, qpn, &req) {
+            if let Some(mr) = mr {
+                mr.windows().unbind();
+            }
+            return Err(e);
+        }
+        self.rebind(mr.map_or(core::ptr::null_mut(), MemoryRegion::as_ptr));
+        // SAFETY: The MW is valid and the rkey is changed with the window locked.
+        unsafe { (*self.as_ptr()).rkey = window.rkey().raw() };
+        Ok(())
+    }
+
+    /// Invalidates the window bound under `rkey`, for LOCAL_INV and SEND_WITH_INV.
+    pub fn invalidate(&self, rkey: MrKey) -> Result<(), InvalidateError> {
+        let mut window = self.object().window.lock_irqdisable();
+        self.bump_generation();
+        window.invalidate(rkey)?;
+        self.rebind(core::ptr::null_mut());
+        Ok(())
+    }
+
+    /// Resolves a remote access through the window, see [`Window::check_access`].
+    ///
+    /// Returns the region and the I/O virtual address to access in it.
+    pub fn check_access(
+        &self,
+        rkey: MrKey,
+        qpn: u32,
+        access: u32,
+        addr: u64,
+        length: u64,
+    ) -> Option<(&MemoryRegion<T>, u64)> {
+        let window = self.object().window.lock_irqdisable();
+        let (_, iova) = window.check_access(rkey, qpn, access, addr, length)?;
+        let mr = self.object().mr.load(Ordering::Relaxed);
+        // SAFETY: The window holds `mr`, whose deregistration is refused until the window is
+        // unbound.
+        Some((unsafe { MemoryRegion::from_ptr(mr) }, iova))
+    }
+
+    /// Records the window bound to `mr`, which the caller holds for it, and releases the region
+    /// it was bound to before, with the window locked.
+    fn rebind(&self, mr: *mut bindings::ib_mr) {
+        let old = self.object().mr.swap(mr, Ordering::Relaxed);
+        if !old.is_null() {
+            // SAFETY: The window held `old`, which could not be deregistered since.
+            unsafe { MemoryRegion::<T>::from_ptr(old) }
+                .windows()
+                .unbind();
+        }
+    }
+
+    /// Makes the cached rkey translations of the device stale, see
+    /// [`IbDeviceOperations::mr_generation`].
+    ///
+    /// Called with the window locked, before its binding changes: lookups resolving the rkey
+    /// through the window wait for the lock, so they cannot cache the old binding afterwards.
+    fn bump_generation(&self) {
+        if let Some(generation) = T::mr_generation(self.device()) {
+            generation.bump();
+        }
+    }
+
+    /// Unbinds the window before it is deallocated.
+    pub(crate) fn release(&self) {
+        let mut window = self.object().window.lock_irqdisable();
+        self.bump_generation();
+        window.dealloc();
+        self.rebind(core::ptr::null_mut());
+    }
+}
+
+impl<D> MwObject<D> {
+    /// Creates the object of a window of `mw_type` in `pd`.
+    ///
+    /// # Safety
+    ///
+    /// The object must be pinned and [`MwObject::init_lock`] called before it is used.
+    pub(crate) unsafe fn new(
+        mw_type: MwType,
+        pd: *mut bindings::ib_pd,
+        rkey: MrKey,
+        data: D,
+    ) -> Self {
+        Self {
+            // SAFETY: The lock is initialised by `init_lock` before use, per the safety
+            // requirements.
+            window: unsafe { SpinLock::new(Window::new(mw_type, rkey, pd as usize)) },
+            mr: AtomicPtr::new(core::ptr::null_mut()),
+            data,
+        }
+    }
+
+    /// Initialises the window lock.
+    pub(crate) fn init_lock(self: Pin<&mut Self>) {
+        // SAFETY: `window` is pinned along with the object.
+        let window = unsafe { self.map_unchecked_mut(|object| &mut object.window) };
+        crate::spinlock_init!(window, "MwObject::window");
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Network devices.
+//!
+//! RoCE ports are bound to a network device, which the verbs core reports for their GIDs and the
+//! soft-RoCE transport sends through.
+
+use core::cell::UnsafeCell;
+use core::{ptr, slice};
+
+use crate::bindings;
+use crate::rdma::gid::{self, Gid};
+use crate::str::CStr;
+
+/// Wraps the kernel's `struct net_device`.
+///
+/// Instances are only ever handed out by reference from callbacks where the kernel guarantees the
+/// device stays alive (e.g. `rdma_link_ops::newlink`, which runs under RTNL).
+///
+/// # Invariants
+///
+/// The wrapped `struct net_device` is valid for the lifetime of the reference.
+#[repr(transparent)]
+pub struct NetDevice(UnsafeCell<bindings::net_device>);
+
+impl NetDevice {
+    /// Creates a reference to a [`NetDevice`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` is valid and remains valid for the lifetime of the
+    /// returned [`NetDevice`] instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::net_device) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `NetDevice` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct net_device` pointer.
+    pub(crate) fn as_ptr(&self) -> *mut bindings::net_device {
+        self.0.get()
+    }
+
+    /// Returns the interface name, e.g. `eth0`.
+    pub fn name(&self) -> &CStr {
+        // SAFETY: By the type invariants the device is valid, and `name` is always a
+        // NUL-terminated array inside it.
+        unsafe { CStr::from_char_ptr((*self.as_ptr()).name.as_ptr()) }
+    }
+
+    /// Returns the interface index.
+    pub fn ifindex(&self) -> i32 {
+        // SAFETY: By the type invariants the device is valid.
+        unsafe { (*self.as_ptr()).ifindex }
+    }
+
+    /// Returns the current MTU of the interface.
+    pub fn mtu(&self) -> u32 {
+        // SAFETY: By the type invariants the device is valid.
+        unsafe { (*self.as_ptr()).mtu }
+    }
+
+    /// Returns `true` if the interface is administratively up.
+    pub fn is_up(&self) -> bool {
+        // SAFETY: By the type invariants the device is valid.
+        let flags = unsafe { (*self.as_ptr()).flags };
+        flags & bindings::net_device_flags_IFF_UP != 0
+    }
+
+    /// Returns `true` if the interface has a carrier, i.e. its link is up.
+    pub fn has_carrier(&self) -> bool {
+        // SAFETY: By the type invariants the device is valid.
+        unsafe { bindings::netif_carrier_ok(self.as_ptr()) }
+    }
+
+    /// Returns `true` unless the device was detached, e.g. hot-unplugged or suspended.
+    pub fn is_present(&self) -> bool {
+        // SAFETY: By the type invariants the device is valid.
+        unsafe { bindings::netif_device_present(self.as_ptr()) }
+    }
+
+    /// Returns the network namespace the interface belongs to.
+    pub fn namespace(&self) -> &Namespace {
+        // SAFETY: By the type invariants the device is valid, and it holds a reference on its
+        // namespace for as long as it lives.
+        unsafe { Namespace::from_ptr(bindings::dev_net(self.as_ptr())) }
+    }
+
+    /// Returns the hardware address of the interface.
+    pub fn dev_addr(&self) -> &[u8] {
+        // SAFETY: By the type invariants the device is valid, `dev_addr` points to at least
+        // `addr_len` bytes for as long as the device lives.
+        unsafe {
+            let dev = &*self.as_ptr();
+            if dev.dev_addr.is_null() {
+                return &[];
+            }
+            slice::from_raw_parts(dev.dev_addr, dev.addr_len as usize)
+        }
+    }
+
+    /// Returns the MAC address of an Ethernet interface.
+    pub fn mac(&self) -> Option<[u8; 6]> {
+        self.dev_addr().try_into().ok()
+    }
+
+    /// Returns the default GID of a RoCE port bound to this interface, the link-local address
+    /// derived from its MAC address.
+    pub fn default_gid(&self) -> Option<Gid> {
+        self.mac().map(Gid::link_local)
+    }
+
+    /// Returns the node GUID of a soft RoCE device bound to this interface, the modified EUI-64
+    /// of its MAC address.
+    pub fn node_guid(&self) -> Option<u64> {
+        self.mac().map(|mac| u64::from_be_bytes(gid::eui64(mac)))
+    }
+}
+
+/// Wraps the kernel's `struct net`.
+///
+/// # Invariants
+///
+/// The wrapped `struct net` is valid for the lifetime of the reference.
+#[repr(transparent)]
+pub struct Namespace(UnsafeCell<bindings::net>);
+
+impl Namespace {
+    /// Creates a reference to a [`Namespace`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` is valid and remains valid for the lifetime of the
+    /// returned [`Namespace`] instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::net) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `Namespace` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct net` pointer.
+    pub(crate) fn as_ptr(&self) -> *mut bindings::net {
+        self.0.get()
+    }
+
+    /// Returns `true` if this is the initial namespace.
+    pub fn is_init(&self) -> bool {
+        ptr::eq(self, init_ns())
+    }
+}
+
+/// Returns the network namespace of the `init` process.
+pub fn init_ns() -> &'static Namespace {
+    // SAFETY: `init_net` lives as long as the kernel.
+    unsafe { Namespace::from_ptr(ptr::addr_of!(bindings::init_net)) }
+}
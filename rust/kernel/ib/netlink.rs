@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Provider attributes of RDMA netlink resource dumps.
+//!
+//! The `RDMA_NL_NLDEV` netlink client belongs to ib_core, which answers `rdma resource show` and
+//! `rdma statistic` itself and lets the provider of each object add its own attributes: the
+//! `fill_res_*_entry` and `fill_stat_mr_entry` callbacks of `struct ib_device_ops`, see
+//! [`super::IbDeviceOperations::fill_res_qp_entry`] and its siblings. The abstraction opens the
+//! nested table the attributes go to, hands it to the provider as a [`DriverAttrs`] or
+//! [`StatCounters`] writer, and closes it, or drops it whole if the message ran out of room, in
+//! which case ib_core retries the dump with a larger one.
+//!
+//! Attributes are typed like those of C drivers: `rdma` prints them as `name value`, in decimal
+//! or, for the `_hex` variants, in hexadecimal.
+
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::str::CStr;
+
+/// The netlink message of a resource dump entry.
+///
+/// # Invariants
+///
+/// `skb` is a valid message that nothing else writes to for the lifetime of the writer.
+struct Msg {
+    skb: *mut bindings::sk_buff,
+}
+
+impl Msg {
+    fn check(ret: core::ffi::c_int) -> Result {
+        // The `rdma_nl_put_*` and `nla_put_*` helpers only fail on a full message.
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(EMSGSIZE)
+        }
+    }
+
+    /// Runs `f` with a nested table of type `attr` opened, and closes it if `f` succeeds.
+    ///
+    /// On failure the table is removed from the message, along with whatever `f` put in it.
+    ///
+    /// # Safety
+    ///
+    /// `skb` must be a valid message that nothing else writes to during the call.
+    unsafe fn nested(
+        skb: *mut bindings::sk_buff,
+        attr: u32,
+        f: impl FnOnce(&mut Self) -> Result,
+    ) -> Result {
+        // SAFETY: `skb` is valid per the safety requirements.
+        let nest = unsafe { bindings::nla_nest_start_noflag(skb, attr as _) };
+        if nest.is_null() {
+            return Err(EMSGSIZE);
+        }
+        // INVARIANT: The safety requirements make `skb` exclusive to us.
+        let mut msg = Self { skb };
+        match f(&mut msg) {
+            Ok(()) => {
+                // SAFETY: `nest` was opened on `skb` above and is still the innermost table.
+                unsafe { bindings::nla_nest_end(skb, nest) };
+                Ok(())
+            }
+            Err(e) => {
+                // SAFETY: As above; cancelling trims the message back to before the table.
+                unsafe { bindings::nla_nest_cancel(skb, nest) };
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Writes the provider attributes of a resource, the `RDMA_NLDEV_ATTR_DRIVER` table.
+#[repr(transparent)]
+pub struct DriverAttrs(Msg);
+
+impl DriverAttrs {
+    /// Runs `f` on the driver table of `skb`.
+    ///
+    /// # Safety
+    ///
+    /// `skb` must be a valid message that nothing else writes to during the call.
+    pub(crate) unsafe fn fill(
+        skb: *mut bindings::sk_buff,
+        f: impl FnOnce(&mut Self) -> Result,
+    ) -> core::ffi::c_int {
+        // SAFETY: Guaranteed by the safety requirements.
+        let ret = unsafe {
+            Msg::nested(
+                skb,
+                bindings::rdma_nldev_attr_RDMA_NLDEV_ATTR_DRIVER,
+                |msg| {
+                    // SAFETY: `DriverAttrs` is a transparent wrapper around `Msg`, which we own
+                    // exclusively for the duration of `f`.
+                    f(unsafe { &mut *(msg as *mut Msg).cast::<Self>() })
+                },
+            )
+        };
+        match ret {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    /// Adds `name` with the value `value`, printed in decimal.
+    pub fn put_u32(&mut self, name: &CStr, value: u32) -> Result {
+        // SAFETY: By the type invariants the message is valid and ours, `name` is NUL-terminated.
+        Msg::check(unsafe {
+            bindings::rdma_nl_put_driver_u32(self.0.skb, name.as_char_ptr(), value)
+        })
+    }
+
+    /// Adds `name` with the value `value`, printed in hexadecimal, e.g. for keys and PSNs.
+    pub fn put_u32_hex(&mut self, name: &CStr, value: u32) -> Result {
+        // SAFETY: By the type invariants the message is valid and ours, `name` is NUL-terminated.
+        Msg::check(unsafe {
+            bindings::rdma_nl_put_driver_u32_hex(self.0.skb, name.as_char_ptr(), value)
+        })
+    }
+
+    /// Adds `name` with the value `value`, printed in decimal.
+    pub fn put_u64(&mut self, name: &CStr, value: u64) -> Result {
+        // SAFETY: By the type invariants the message is valid and ours, `name` is NUL-terminated.
+        Msg::check(unsafe {
+            bindings::rdma_nl_put_driver_u64(self.0.skb, name.as_char_ptr(), value)
+        })
+    }
+
+    /// Adds `name` with the value `value`, printed in hexadecimal, e.g. for addresses.
+    pub fn put_u64_hex(&mut self, name: &CStr, value: u64) -> Result {
+        // SAFETY: By the type invariants the message is valid and ours, `name` is NUL-terminated.
+        Msg::check(unsafe {
+            bindings::rdma_nl_put_driver_u64_hex(self.0.skb, name.as_char_ptr(), value)
+        })
+    }
+
+    /// Adds `name` with the string `value`.
+    pub fn put_str(&mut self, name: &CStr, value: &CStr) -> Result {
+        // SAFETY: By the type invariants the message is valid and ours, both strings are
+        // NUL-terminated.
+        Msg::check(unsafe {
+            bindings::rdma_nl_put_driver_string(self.0.skb, name.as_char_ptr(), value.as_char_ptr())
+        })
+    }
+}
+
+/// Writes the hardware counters of a resource, the `RDMA_NLDEV_ATTR_STAT_HWCOUNTERS` table.
+#[repr(transparent)]
+pub struct StatCounters(Msg);
+
+impl StatCounters {
+    /// Runs `f` on the counters table of `skb`.
+    ///
+    /// # Safety
+    ///
+    /// `skb` must be a valid message that nothing else writes to during the call.
+    pub(crate) unsafe fn fill(
+        skb: *mut bindings::sk_buff,
+        f: impl FnOnce(&mut Self) -> Result,
+    ) -> core::ffi::c_int {
+        // SAFETY: Guaranteed by the safety requirements.
+        let ret = unsafe {
+            Msg::nested(
+                skb,
+                bindings::rdma_nldev_attr_RDMA_NLDEV_ATTR_STAT_HWCOUNTERS,
+                |msg| {
+                    // SAFETY: `StatCounters` is a transparent wrapper around `Msg`, which we own
+                    // exclusively for the duration of `f`.
+                    f(unsafe { &mut *(msg as *mut Msg).cast::<Self>() })
+                },
+            )
+        };
+        match ret {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+
+    /// Adds the counter `name` with the value `value`.
+    pub fn put(&mut self, name: &CStr, value: u64) -> Result {
+        // SAFETY: By the type invariants the message is valid and ours, `name` is NUL-terminated.
+        Msg::check(unsafe {
+            bindings::rdma_nl_stat_hwcounter_entry(self.0.skb, name.as_char_ptr(), value)
+        })
+    }
+}
@@ -0,0 +1,331 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Notifications of the devices created by Rust providers.
+//!
+//! Upper-layer modules written in Rust bind to the devices of the providers in this crate by
+//! registering a [`DeviceObserver`]: it is told about every device once [`Device::register`]
+//! succeeded, including the ones that already exist when it is registered, and about every device
+//! being unregistered, when ib_core removes its clients. This is the Rust-only counterpart of
+//! `ib_register_client`, without polling `ib_device_get_by_name`; the removals are reported by an
+//! ib_client of the registry, registered along with the first device.
+//!
+//! Notifications are serialised: an observer never sees a device removed while it is still being
+//! told about it, and unregistering an observer waits for the callback it is in, if any.
+//!
+//! [`Device::register`]: super::Device::register
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::ptr;
+
+use super::device::{DeviceAttr, DriverId, PortAttr};
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::str::CStr;
+use crate::sync::smutex::Mutex;
+
+/// Largest number of observers registered at once.
+pub const MAX_OBSERVERS: usize = 8;
+
+/// Largest number of devices tracked at once; registering more fails.
+pub const MAX_DEVICES: usize = 64;
+
+/// A device created by a Rust provider, as seen by observers that do not know its provider.
+#[repr(transparent)]
+pub struct ObservedDevice(UnsafeCell<bindings::ib_device>);
+
+impl ObservedDevice {
+    /// Creates a reference to an [`ObservedDevice`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_device) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `ObservedDevice` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_device` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_device {
+        self.0.get()
+    }
+
+    /// Returns the device name, e.g. `rxe0`.
+    pub fn name(&self) -> &CStr {
+        // SAFETY: `name` is a NUL-terminated array inside the valid device.
+        unsafe { CStr::from_char_ptr((*self.as_ptr()).name.as_ptr()) }
+    }
+
+    /// Returns the name of the module providing the device, `None` if it is built in.
+    pub fn driver_name(&self) -> Option<&CStr> {
+        // SAFETY: The device is valid and its ops were copied from the table built for it.
+        let owner = unsafe { (*self.as_ptr()).ops.owner };
+        if owner.is_null() {
+            return None;
+        }
+        // SAFETY: The module owns the device's ops, so it outlives the device, and its name is a
+        // NUL-terminated array.
+        Some(unsafe { CStr::from_char_ptr((*owner).name.as_ptr()) })
+    }
+
+    /// Returns `true` if the device belongs to `driver`, e.g. to only handle the devices of one
+    /// soft transport.
+    pub fn belongs_to(&self, driver: DriverId) -> bool {
+        // SAFETY: The device is valid.
+        unsafe { (*self.as_ptr()).ops.driver_id == driver.to_raw() }
+    }
+
+    /// Returns the number of physical ports.
+    pub fn phys_port_cnt(&self) -> u32 {
+        // SAFETY: The device is valid.
+        unsafe { (*self.as_ptr()).phys_port_cnt }
+    }
+
+    /// Returns the attributes the provider reported in `query_device` when it was registered.
+    pub fn attrs(&self) -> &DeviceAttr {
+        // SAFETY: The device is valid and ib_core filled `attrs` before registering it; they do
+        // not change afterwards. `DeviceAttr` is transparent.
+        unsafe { &*ptr::addr_of!((*self.as_ptr()).attrs).cast() }
+    }
+
+    /// Queries the current attributes of `port`, numbered from 1.
+    pub fn query_port(&self, port: u32) -> Result<PortAttr> {
+        // SAFETY: The device is valid and its ops are set for as long as observers see it.
+        unsafe { PortAttr::query(self.as_ptr(), port) }
+    }
+
+    /// Reads the provider sysfs attribute `name` of the device into `buf`, see
+    /// [`super::Device::set_sysfs_group`], and returns its value without the trailing newline.
+    ///
+    /// Fails with `ENOENT` if the provider has no such attribute, and with `EINVAL` if the value
+    /// is not UTF-8. Values longer than `buf` are truncated.
+    pub fn show_attr<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<&'a str> {
+        // SAFETY: The device is valid, and its groups are set before it is registered.
+        let group = unsafe { (*self.as_ptr()).groups[1] };
+        if group.is_null() {
+            return Err(ENOENT);
+        }
+        // SAFETY: The provider group is valid while the device is, with a NULL-terminated array
+        // of attributes.
+        let mut attrs = unsafe { (*group).attrs };
+        let attr = loop {
+            // SAFETY: `attrs` points into the array, up to its NULL terminator.
+            let attr = unsafe { *attrs };
+            if attr.is_null() {
+                return Err(ENOENT);
+            }
+            // SAFETY: The attribute is valid, with a NUL-terminated name.
+            if unsafe { CStr::from_char_ptr((*attr).name) }.as_bytes() == name.as_bytes() {
+                break attr;
+            }
+            // SAFETY: The terminator was not reached, so the next entry is in the array.
+            attrs = unsafe { attrs.add(1) };
+        };
+        // SAFETY: The attributes of the device group are `struct device_attribute`s.
+        let attr = unsafe { crate::container_of!(attr, bindings::device_attribute, attr) };
+        // SAFETY: The attribute is valid.
+        let show = unsafe { (*attr).show }.ok_or(ENOENT)?;
+        // SAFETY: FFI call without preconditions. `show` gets a page like from sysfs, which
+        // `sysfs_emit` checks.
+        let page = unsafe { bindings::get_zeroed_page(bindings::GFP_KERNEL) };
+        if page == 0 {
+            return Err(ENOMEM);
+        }
+        // SAFETY: The device and its attribute are valid, and `page` is a page we own.
+        let len = unsafe {
+            show(
+                ptr::addr_of_mut!((*self.as_ptr()).dev),
+                attr as *mut _,
+                page as _,
+            )
+        };
+        let n = usize::try_from(len).map_or(0, |len| {
+            len.min(bindings::PAGE_SIZE as usize).min(buf.len())
+        });
+        // SAFETY: `show` wrote the first `n` bytes of the page.
+        buf[..n].copy_from_slice(unsafe { core::slice::from_raw_parts(page as *const u8, n) });
+        // SAFETY: The page was allocated above, and `show` does not keep it.
+        unsafe { bindings::free_pages(page, 0) };
+        if len < 0 {
+            return Err(Error::from_kernel_errno(len as _));
+        }
+        let text = core::str::from_utf8(&buf[..n]).map_err(|_| EINVAL)?;
+        Ok(text.trim_end())
+    }
+}
+
+/// Implement this trait to be told about the devices of the Rust providers.
+///
+/// The callbacks run in process context, one at a time across all observers. They may sleep, but
+/// must not register or unregister observers nor register devices.
+pub trait DeviceObserver: Send + Sync {
+    /// Called once `dev` is registered with ib_core, or when the observer is registered for the
+    /// devices that already are.
+    fn added(&self, dev: &ObservedDevice);
+
+    /// Called while `dev` is unregistered, once ib_core stopped reporting it but before its
+    /// resources are torn down, or when the observer is unregistered for the devices that still
+    /// exist.
+    fn removed(&self, dev: &ObservedDevice);
+}
+
+struct Inner {
+    devices: [*mut bindings::ib_device; MAX_DEVICES],
+    observers: [Option<*const dyn DeviceObserver>; MAX_OBSERVERS],
+    /// Whether [`CLIENT`] is registered.
+    client: bool,
+}
+
+// SAFETY: The devices are only dereferenced while they are registered, and the observers are
+// `Sync`.
+unsafe impl Send for Inner {}
+
+impl Inner {
+    const NO_OBSERVER: Option<*const dyn DeviceObserver> = None;
+
+    fn for_each_device(&self, f: impl Fn(&ObservedDevice)) {
+        for dev in self.devices.iter().filter(|dev| !dev.is_null()) {
+            // SAFETY: Devices are removed from the table before they are freed.
+            f(unsafe { ObservedDevice::from_ptr(*dev) });
+        }
+    }
+
+    fn for_each_observer(&self, f: impl Fn(&dyn DeviceObserver)) {
+        for observer in self.observers.iter().flatten() {
+            // SAFETY: Observers are removed from the table before they are freed.
+            f(unsafe { &**observer });
+        }
+    }
+}
+
+/// The devices and observers.
+///
+/// The lock is held across the callbacks, which may sleep.
+static REGISTRY: Mutex<Inner> = Mutex::new(Inner {
+    devices: [ptr::null_mut(); MAX_DEVICES],
+    observers: [Inner::NO_OBSERVER; MAX_OBSERVERS],
+    client: false,
+});
+
+/// The ib_client reporting the removal of the devices, registered once and never unregistered.
+struct Client(UnsafeCell<bindings::ib_client>);
+
+// SAFETY: The client is only handed to ib_core, which synchronises its accesses, and only while
+// holding the registry lock.
+unsafe impl Sync for Client {}
+
+static CLIENT: Client = Client(UnsafeCell::new(client()));
+
+/// Builds the client, whose `add` and `remove` are [`client_add`] and [`client_remove`].
+const fn client() -> bindings::ib_client {
+    // SAFETY: `struct ib_client` is plain C data, for which zeroes are the initial state ib_core
+    // expects of the fields it fills in.
+    let mut client: bindings::ib_client =
+        unsafe { core::mem::transmute([0u8; core::mem::size_of::<bindings::ib_client>()]) };
+    client.name = b"rust_rdma_observer\0".as_ptr().cast();
+    client.add = Some(client_add);
+    client.remove = Some(client_remove);
+    client
+}
+
+unsafe extern "C" fn client_add(_ibdev: *mut bindings::ib_device) -> core::ffi::c_int {
+    // Every device gets a context, so that `client_remove` is called for the announced ones. The
+    // registry is not locked here, as ib_core adds the clients from within `announce`.
+    0
+}
+
+unsafe extern "C" fn client_remove(ibdev: *mut bindings::ib_device, _data: *mut core::ffi::c_void) {
+    retire(ibdev);
+}
+
+/// Runs `register`, which registers `dev` with ib_core, and reports `dev` to the observers if it
+/// returns 0.
+///
+/// Fails with `ENOSPC` without calling `register` if [`MAX_DEVICES`] devices are registered.
+/// Holding the registry across the registration keeps a concurrent removal of `dev` from being
+/// reported before its addition.
+pub(crate) fn announce(dev: *mut bindings::ib_device, register: impl FnOnce() -> i32) -> i32 {
+    let mut inner = REGISTRY.lock();
+    let i = match inner.devices.iter().position(|d| d.is_null()) {
+        Some(i) => i,
+        None => return ENOSPC.to_kernel_errno(),
+    };
+    if !inner.client {
+        // SAFETY: The client is static and never unregistered.
+        let err = unsafe { bindings::ib_register_client(CLIENT.0.get()) };
+        if err != 0 {
+            return err;
+        }
+        inner.client = true;
+    }
+    let err = register();
+    if err != 0 {
+        return err;
+    }
+    inner.devices[i] = dev;
+    // SAFETY: `dev` was just registered, and is only freed after `retire` removed it.
+    let observed = unsafe { ObservedDevice::from_ptr(dev) };
+    inner.for_each_observer(|observer| observer.added(observed));
+    0
+}
+
+/// Reports `dev` as gone to the observers, if it was announced.
+///
+/// ib_core calls this through [`CLIENT`] when it unregisters `dev`; the provider calls it again
+/// before freeing `dev`, which only matters if the removal of the clients was missed.
+pub(crate) fn retire(dev: *mut bindings::ib_device) {
+    let mut inner = REGISTRY.lock();
+    if let Some(i) = inner.devices.iter().position(|d| *d == dev) {
+        inner.devices[i] = ptr::null_mut();
+        // SAFETY: `dev` is being unregistered or freed, and still valid.
+        let observed = unsafe { ObservedDevice::from_ptr(dev) };
+        inner.for_each_observer(|observer| observer.removed(observed));
+    }
+}
+
+/// A registered [`DeviceObserver`], unregistered when dropped.
+pub struct ObserverRegistration<O: DeviceObserver + 'static> {
+    observer: Box<O>,
+}
+
+impl<O: DeviceObserver + 'static> ObserverRegistration<O> {
+    /// Registers `observer` and calls [`DeviceObserver::added`] for the existing devices.
+    ///
+    /// Fails with `EBUSY` if [`MAX_OBSERVERS`] observers are registered already.
+    pub fn try_new(observer: O) -> Result<Self> {
+        let observer = Box::try_new(observer)?;
+        let raw = &*observer as &dyn DeviceObserver as *const dyn DeviceObserver;
+        let mut inner = REGISTRY.lock();
+        let i = inner
+            .observers
+            .iter()
+            .position(Option::is_none)
+            .ok_or(EBUSY)?;
+        inner.observers[i] = Some(raw);
+        inner.for_each_device(|dev| observer.added(dev));
+        drop(inner);
+        Ok(Self { observer })
+    }
+
+    /// Returns the observer.
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+}
+
+impl<O: DeviceObserver + 'static> Drop for ObserverRegistration<O> {
+    /// Calls [`DeviceObserver::removed`] for the existing devices and unregisters the observer.
+    fn drop(&mut self) {
+        let raw = &*self.observer as &dyn DeviceObserver as *const dyn DeviceObserver;
+        let mut inner = REGISTRY.lock();
+        if let Some(slot) = inner
+            .observers
+            .iter_mut()
+            .find(|o| matches!(o, Some(o) if o.cast::<u8>() == raw.cast::<u8>()))
+        {
+            *slot = None;
+            inner.for_each_device(|dev| self.observer.removed(dev));
+        }
+    }
+}
@@ -0,0 +1,418 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! PCI hardware providers.
+//!
+//! Providers like EFA or a virtio RDMA device are plain PCI drivers: the device is found by the
+//! PCI core, and its InfiniBand device lives from `probe` to `remove`. [`PciRdmaAdapter`] is the
+//! PCI driver registration of such a provider, which implements [`PciRdmaDriver`] and only deals
+//! with its hardware. The adapter owns the ordering around it:
+//!
+//! - on probe, the PCI device is enabled, made bus master and given the DMA mask of the driver
+//!   before [`PciRdmaDriver::probe`] runs, and the [`Device`] it returns is registered with the
+//!   PCI device as DMA device;
+//! - on remove, the InfiniBand device is unregistered first, which waits for its users and runs
+//!   [`IbDeviceOperations::dealloc_driver`], then [`PciRdmaDriver::remove`] tears the hardware
+//!   down and the PCI device is disabled.
+//!
+//! A failed registration unwinds the same way, so [`PciRdmaDriver::remove`] always pairs with a
+//! successful [`PciRdmaDriver::probe`].
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::pin::Pin;
+use core::{marker, ptr};
+use macros::vtable;
+
+use super::device::{Device, IbDeviceOperations};
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::ib::compat::try_pin;
+use crate::str::CStr;
+use crate::ThisModule;
+
+/// A device matched by a driver, wraps `struct pci_device_id`.
+///
+/// Subsystem IDs and classes are not matched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciDeviceId {
+    /// PCI vendor ID.
+    pub vendor: u16,
+    /// PCI device ID.
+    pub device: u16,
+    /// Driver data handed to [`PciRdmaDriver::probe`], e.g. the generation of the device.
+    pub data: usize,
+}
+
+impl PciDeviceId {
+    /// Creates an ID matching the devices `vendor:device`.
+    pub const fn new(vendor: u16, device: u16, data: usize) -> Self {
+        Self {
+            vendor,
+            device,
+            data,
+        }
+    }
+
+    fn to_raw(self) -> bindings::pci_device_id {
+        bindings::pci_device_id {
+            vendor: self.vendor as _,
+            device: self.device as _,
+            subvendor: !0,
+            subdevice: !0,
+            driver_data: self.data as _,
+            ..Default::default()
+        }
+    }
+}
+
+/// A PCI device, wraps `struct pci_dev`.
+#[repr(transparent)]
+pub struct PciDevice(UnsafeCell<bindings::pci_dev>);
+
+impl PciDevice {
+    /// Creates a reference to a [`PciDevice`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::pci_dev) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `PciDevice` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct pci_dev` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::pci_dev {
+        self.0.get()
+    }
+
+    /// Returns the embedded `struct device`, which does the DMA of the device.
+    pub fn raw_device(&self) -> *mut bindings::device {
+        // SAFETY: The device is valid.
+        unsafe { ptr::addr_of_mut!((*self.as_ptr()).dev) }
+    }
+
+    /// Returns the bus address of the device, e.g. `0000:3b:00.0`.
+    pub fn name(&self) -> &CStr {
+        // SAFETY: The device is valid and was named when it was added to the bus.
+        unsafe { CStr::from_char_ptr(bindings::dev_name(self.raw_device())) }
+    }
+
+    /// Returns the vendor ID.
+    pub fn vendor_id(&self) -> u16 {
+        // SAFETY: The device is valid; the ID is read at enumeration.
+        unsafe { (*self.as_ptr()).vendor }
+    }
+
+    /// Returns the device ID.
+    pub fn device_id(&self) -> u16 {
+        // SAFETY: The device is valid; the ID is read at enumeration.
+        unsafe { (*self.as_ptr()).device }
+    }
+
+    /// Returns the revision ID.
+    pub fn revision(&self) -> u8 {
+        // SAFETY: The device is valid; the revision is read at enumeration.
+        unsafe { (*self.as_ptr()).revision }
+    }
+
+    /// Returns the bus address and length of BAR `bar`, or `None` if it is not implemented.
+    pub fn bar(&self, bar: usize) -> Option<(u64, u64)> {
+        // SAFETY: The device is valid; the resources are set up at enumeration.
+        let res = unsafe { (*self.as_ptr()).resource.get(bar)? };
+        if res.start == 0 && res.end == 0 {
+            return None;
+        }
+        Some((res.start as u64, (res.end - res.start + 1) as u64))
+    }
+}
+
+/// A PCI RDMA provider, driven by a [`PciRdmaAdapter`].
+#[vtable]
+pub trait PciRdmaDriver {
+    /// Verbs operations of the InfiniBand devices created by [`PciRdmaDriver::probe`].
+    type Ops: IbDeviceOperations;
+
+    /// Hardware state of one PCI device, e.g. its mapped BARs and interrupt vectors.
+    type Context: Send + Sync;
+
+    /// Devices the driver binds to.
+    const ID_TABLE: &'static [PciDeviceId];
+
+    /// Name of the InfiniBand devices, which may contain a `%d` pattern, e.g. `efa_%d`.
+    const IB_NAME: &'static CStr;
+
+    /// Width in bits of the DMA addresses of the device.
+    const DMA_BITS: u32 = 64;
+
+    /// Brings up the hardware of `pdev`, which matched `id`, and returns it along with the
+    /// InfiniBand device to register for it.
+    ///
+    /// The PCI device is already enabled, bus master and has its DMA mask set. The returned
+    /// [`Device`] is registered once this returns; if that fails, [`PciRdmaDriver::remove`] is
+    /// called right away.
+    fn probe(
+        pdev: &PciDevice,
+        id: &PciDeviceId,
+    ) -> Result<(Pin<Box<Self::Context>>, Device<Self::Ops>)>;
+
+    /// Tears the hardware of `pdev` down, once its InfiniBand device is unregistered and freed.
+    fn remove(pdev: &PciDevice, context: Pin<Box<Self::Context>>);
+
+    /// Quiesces the hardware on reboot or kexec; the device is not removed afterwards.
+    fn shutdown(_pdev: &PciDevice, _context: Pin<&Self::Context>) {}
+}
+
+/// What the adapter keeps for a bound PCI device, in its driver data.
+///
+/// It is allocated before [`PciRdmaDriver::probe`] runs, so that nothing can fail between a
+/// successful probe and the registration of the InfiniBand device.
+struct Bound<T: PciRdmaDriver> {
+    context: Option<Pin<Box<T::Context>>>,
+    ibdev: *mut bindings::ib_device,
+}
+
+/// PCI driver registration of a [`PciRdmaDriver`].
+pub struct PciRdmaAdapter<T: PciRdmaDriver> {
+    registered: bool,
+    name: &'static CStr,
+    module: &'static ThisModule,
+    driver: bindings::pci_driver,
+    ids: Vec<bindings::pci_device_id>,
+    phantom: marker::PhantomData<T>,
+    _pin: marker::PhantomPinned,
+}
+
+impl<T: PciRdmaDriver> PciRdmaAdapter<T> {
+    /// Creates a new [`PciRdmaAdapter`] but does not register it yet.
+    ///
+    /// It is allowed to move until it is pinned to be registered. `name` is the name of the PCI
+    /// driver.
+    pub fn new(name: &'static CStr, module: &'static ThisModule) -> Self {
+        // INVARIANT: `registered` is `false`
+        Self {
+            registered: false,
+            name,
+            module,
+            driver: bindings::pci_driver::default(),
+            ids: Vec::new(),
+            phantom: marker::PhantomData,
+            _pin: marker::PhantomPinned,
+        }
+    }
+
+    /// Registers a PCI RDMA driver.
+    ///
+    /// Returns a pinned heap-allocated representation of the registration.
+    pub fn new_pinned(name: &'static CStr, module: &'static ThisModule) -> Result<Pin<Box<Self>>> {
+        let mut r = try_pin(Self::new(name, module))?;
+        r.as_mut().register()?;
+        Ok(r)
+    }
+
+    /// Registers a PCI RDMA driver with the rest of the kernel; the devices already enumerated
+    /// are probed before this returns.
+    ///
+    /// It must be pinned because the memory block that represents the registration is
+    /// self-referential.
+    pub fn register(self: Pin<&mut Self>) -> Result {
+        // SAFETY: We must ensure that we never move out of `this`.
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.registered {
+            // Already registered.
+            return Err(EINVAL);
+        }
+        if T::ID_TABLE.is_empty() || T::DMA_BITS == 0 || T::DMA_BITS > 64 {
+            return Err(EINVAL);
+        }
+
+        // The PCI core walks the table up to an entry with a zero vendor.
+        let mut ids = Vec::try_with_capacity(T::ID_TABLE.len() + 1)?;
+        for id in T::ID_TABLE {
+            ids.try_push(id.to_raw())?;
+        }
+        ids.try_push(bindings::pci_device_id::default())?;
+        this.ids = ids;
+
+        this.driver = PciRdmaTable::<T>::build(this.name, &this.ids);
+        // SAFETY: `this.driver` and the ID table it points to are pinned and only unregistered
+        // in `drop`; the module and name are static.
+        let ret = unsafe {
+            bindings::__pci_register_driver(
+                &mut this.driver,
+                this.module.as_ptr(),
+                (*this.module.as_ptr()).name.as_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+
+        this.registered = true;
+        Ok(())
+    }
+}
+
+impl<T: PciRdmaDriver> Drop for PciRdmaAdapter<T> {
+    /// Removes the registration from the kernel if it has completed successfully before.
+    fn drop(&mut self) {
+        if self.registered {
+            // SAFETY: `self.driver` was registered in `register` and has not moved since. The
+            // PCI core calls `remove` for every bound device before this returns.
+            unsafe { bindings::pci_unregister_driver(&mut self.driver) };
+        }
+    }
+}
+
+// SAFETY: `PciRdmaAdapter` does not expose any of its state across threads; the driver it owns
+// is only touched by the PCI core, under its own locks.
+unsafe impl<T: PciRdmaDriver> Sync for PciRdmaAdapter<T> {}
+
+/// Build kernel's `struct pci_driver` type with the operations of a [`PciRdmaDriver`].
+pub struct PciRdmaTable<T>(marker::PhantomData<T>);
+
+impl<T: PciRdmaDriver> PciRdmaTable<T> {
+    /// Builds an instance of [`struct pci_driver`] matching the devices of `ids`, which must end
+    /// with an all-zero entry.
+    ///
+    /// The driver and `ids` must not move once it is registered; [`PciRdmaAdapter`] keeps them
+    /// pinned until it unregisters it.
+    pub fn build(name: &'static CStr, ids: &[bindings::pci_device_id]) -> bindings::pci_driver {
+        bindings::pci_driver {
+            name: name.as_char_ptr(),
+            id_table: ids.as_ptr(),
+            probe: Some(Self::probe_callback),
+            remove: Some(Self::remove_callback),
+            shutdown: if T::HAS_SHUTDOWN {
+                Some(Self::shutdown_callback)
+            } else {
+                None
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Looks up the entry of [`PciRdmaDriver::ID_TABLE`] the PCI core matched.
+    fn find_id(id: *const bindings::pci_device_id) -> Option<&'static PciDeviceId> {
+        if id.is_null() {
+            return None;
+        }
+        // SAFETY: The PCI core passes an entry of the table built from `T::ID_TABLE`.
+        let (vendor, device, data) = unsafe { ((*id).vendor, (*id).device, (*id).driver_data) };
+        T::ID_TABLE.iter().find(|i| {
+            i.vendor as u32 == vendor && i.device as u32 == device && i.data == data as usize
+        })
+    }
+
+    /// Makes `pdev`, which is enabled, ready for DMA, runs [`PciRdmaDriver::probe`] and
+    /// registers the InfiniBand device it returns.
+    fn bind(pdev: &PciDevice, id: &PciDeviceId) -> Result<Box<Bound<T>>> {
+        let mask = if T::DMA_BITS == 64 {
+            u64::MAX
+        } else {
+            (1 << T::DMA_BITS) - 1
+        };
+        // SAFETY: The device is valid and being probed.
+        let err = unsafe { bindings::dma_set_mask_and_coherent(pdev.raw_device(), mask) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        let mut bound = Box::try_new(Bound::<T> {
+            context: None,
+            ibdev: ptr::null_mut(),
+        })?;
+
+        // SAFETY: The device is valid and enabled.
+        unsafe { bindings::pci_set_master(pdev.as_ptr()) };
+        let (context, dev) = match T::probe(pdev, id) {
+            Ok(probed) => probed,
+            Err(e) => {
+                // SAFETY: The device is valid and was made bus master above.
+                unsafe { bindings::pci_clear_master(pdev.as_ptr()) };
+                return Err(e);
+            }
+        };
+        bound.context = Some(context);
+        match dev.register_with_dma(T::IB_NAME, pdev.raw_device()) {
+            Ok(ibdev) => {
+                bound.ibdev = ibdev;
+                Ok(bound)
+            }
+            Err(e) => {
+                Self::teardown(pdev, bound);
+                Err(e)
+            }
+        }
+    }
+
+    /// Runs [`PciRdmaDriver::remove`] and stops bus mastering, once the InfiniBand device is
+    /// gone.
+    fn teardown(pdev: &PciDevice, mut bound: Box<Bound<T>>) {
+        if let Some(context) = bound.context.take() {
+            T::remove(pdev, context);
+        }
+        // SAFETY: The device is valid and was made bus master by `bind`.
+        unsafe { bindings::pci_clear_master(pdev.as_ptr()) };
+    }
+
+    unsafe extern "C" fn probe_callback(
+        pdev: *mut bindings::pci_dev,
+        id: *const bindings::pci_device_id,
+    ) -> core::ffi::c_int {
+        let id = match Self::find_id(id) {
+            Some(id) => id,
+            None => return ENODEV.to_kernel_errno(),
+        };
+        // SAFETY: The PCI core passes a valid device that outlives the binding.
+        let err = unsafe { bindings::pci_enable_device_mem(pdev) };
+        if err != 0 {
+            return err;
+        }
+        // SAFETY: As above.
+        let dev = unsafe { PciDevice::from_ptr(pdev) };
+        match Self::bind(dev, id) {
+            Ok(bound) => {
+                // SAFETY: The device is bound to us, so its driver data is ours; it is turned
+                // back into a box by `remove_callback` only.
+                unsafe { bindings::pci_set_drvdata(pdev, Box::into_raw(bound).cast()) };
+                0
+            }
+            Err(e) => {
+                // SAFETY: The device was enabled above.
+                unsafe { bindings::pci_disable_device(pdev) };
+                e.to_kernel_errno()
+            }
+        }
+    }
+
+    unsafe extern "C" fn remove_callback(pdev: *mut bindings::pci_dev) {
+        // SAFETY: The PCI core only removes devices whose probe succeeded, so the driver data is
+        // the box `probe_callback` stored, which is not used anymore once this returns.
+        let (dev, bound) = unsafe {
+            let bound = Box::from_raw(bindings::pci_get_drvdata(pdev).cast::<Bound<T>>());
+            bindings::pci_set_drvdata(pdev, ptr::null_mut());
+            (PciDevice::from_ptr(pdev), bound)
+        };
+        // SAFETY: The InfiniBand device was registered by `bind` and only unregistered here.
+        // This waits for its users and frees it through `dealloc_driver`.
+        unsafe { bindings::ib_unregister_device(bound.ibdev) };
+        Self::teardown(dev, bound);
+        // SAFETY: The device was enabled by `probe_callback`.
+        unsafe { bindings::pci_disable_device(pdev) };
+    }
+
+    unsafe extern "C" fn shutdown_callback(pdev: *mut bindings::pci_dev) {
+        // SAFETY: The PCI core only shuts down bound devices, whose driver data is the box
+        // `probe_callback` stored, which lives until `remove_callback`.
+        let (dev, bound) = unsafe {
+            (
+                PciDevice::from_ptr(pdev),
+                &*bindings::pci_get_drvdata(pdev).cast::<Bound<T>>(),
+            )
+        };
+        if let Some(context) = &bound.context {
+            T::shutdown(dev, context.as_ref());
+        }
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Protection domains.
+
+use core::cell::UnsafeCell;
+use core::marker;
+use core::ptr;
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::restrack::ResourceEntry;
+use super::Object;
+use crate::bindings;
+
+/// A protection domain of a device provided by `T`, wraps `struct ib_pd`.
+///
+/// ib_core allocates and frees the `struct ib_pd`; the provider only sees it between a successful
+/// [`IbDeviceOperations::alloc_pd`] and the matching [`IbDeviceOperations::dealloc_pd`], after
+/// which the abstraction drops `T::PdData`.
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_pd` belongs to a device provided by `T` and is followed by an
+/// initialised `T::PdData`.
+#[repr(transparent)]
+pub struct ProtectionDomain<T: IbDeviceOperations>(
+    UnsafeCell<bindings::ib_pd>,
+    marker::PhantomData<T>,
+);
+
+impl<T: IbDeviceOperations> ProtectionDomain<T> {
+    /// Creates a reference to a [`ProtectionDomain`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a PD of a device provided by `T` whose `alloc_pd` succeeded and which is not
+    /// released for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_pd) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `ProtectionDomain` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_pd` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_pd {
+        self.0.get()
+    }
+
+    /// Returns the restrack record of the PD, with its ID and creator.
+    pub fn restrack(&self) -> &ResourceEntry {
+        // SAFETY: The PD is valid and embeds its entry.
+        unsafe { ResourceEntry::from_ptr(ptr::addr_of_mut!((*self.as_ptr()).res)) }
+    }
+
+    /// Returns the device the PD belongs to.
+    pub fn device(&self) -> &DeviceRef<T> {
+        // SAFETY: By the type invariants the PD belongs to a device provided by `T`, which
+        // outlives all of its PDs.
+        unsafe { DeviceRef::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the provider data of the PD.
+    pub fn data(&self) -> &T::PdData {
+        // SAFETY: By the type invariants the data is initialised.
+        unsafe { Object::<bindings::ib_pd, T::PdData>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns `true` if the PD was allocated by a userspace process through uverbs.
+    pub fn is_user(&self) -> bool {
+        // SAFETY: The PD is valid.
+        unsafe { !(*self.as_ptr()).uobject.is_null() }
+    }
+}
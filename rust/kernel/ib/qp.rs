@@ -0,0 +1,617 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Queue pairs.
+
+use core::cell::UnsafeCell;
+use core::marker;
+use core::ops::BitOr;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::cq::CompletionQueue;
+use super::device::{DeviceRef, IbDeviceOperations, Mtu};
+use super::pd::ProtectionDomain;
+use super::restrack::ResourceEntry;
+use super::srq::SharedReceiveQueue;
+use super::trace;
+use super::Object;
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::rdma::psn::Psn;
+use crate::rdma::qp_fault::QpFaultInjector;
+use crate::rdma::tunables::QpLimits;
+
+pub use crate::rdma::qp_state::QpState;
+
+/// Transport service type of a queue pair.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QpType {
+    /// Subnet management QP0.
+    Smi,
+    /// General services QP1.
+    Gsi,
+    /// Reliable connection.
+    Rc,
+    /// Unreliable connection.
+    Uc,
+    /// Unreliable datagram.
+    Ud,
+    /// Any other type, e.g. raw packet or XRC.
+    Other(u32),
+}
+
+impl QpType {
+    /// Converts a raw `enum ib_qp_type` value.
+    pub fn from_raw(raw: bindings::ib_qp_type) -> Self {
+        match raw {
+            bindings::ib_qp_type_IB_QPT_SMI => QpType::Smi,
+            bindings::ib_qp_type_IB_QPT_GSI => QpType::Gsi,
+            bindings::ib_qp_type_IB_QPT_RC => QpType::Rc,
+            bindings::ib_qp_type_IB_QPT_UC => QpType::Uc,
+            bindings::ib_qp_type_IB_QPT_UD => QpType::Ud,
+            other => QpType::Other(other as u32),
+        }
+    }
+
+    /// Returns the raw `enum ib_qp_type` value.
+    pub fn to_raw(self) -> bindings::ib_qp_type {
+        match self {
+            QpType::Smi => bindings::ib_qp_type_IB_QPT_SMI,
+            QpType::Gsi => bindings::ib_qp_type_IB_QPT_GSI,
+            QpType::Rc => bindings::ib_qp_type_IB_QPT_RC,
+            QpType::Uc => bindings::ib_qp_type_IB_QPT_UC,
+            QpType::Ud => bindings::ib_qp_type_IB_QPT_UD,
+            QpType::Other(other) => other as _,
+        }
+    }
+}
+
+/// Creation attributes of a queue pair, wraps `struct ib_qp_init_attr`.
+#[repr(transparent)]
+pub struct QpInitAttr(UnsafeCell<bindings::ib_qp_init_attr>);
+
+impl QpInitAttr {
+    /// Creates a reference to a [`QpInitAttr`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `ptr` is valid and remains valid for the lifetime of the
+    /// returned [`QpInitAttr`] instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_qp_init_attr) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `QpInitAttr` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    fn raw(&self) -> &bindings::ib_qp_init_attr {
+        // SAFETY: The attributes are valid per the `from_ptr` requirements and only ib_core,
+        // which is waiting for us, writes to them.
+        unsafe { &*self.0.get() }
+    }
+
+    /// Returns the requested QP type.
+    pub fn qp_type(&self) -> QpType {
+        QpType::from_raw(self.raw().qp_type)
+    }
+
+    /// Returns the requested number of send work requests.
+    pub fn max_send_wr(&self) -> u32 {
+        self.raw().cap.max_send_wr
+    }
+
+    /// Returns the requested number of receive work requests.
+    pub fn max_recv_wr(&self) -> u32 {
+        self.raw().cap.max_recv_wr
+    }
+
+    /// Returns the requested number of scatter/gather entries per send work request.
+    pub fn max_send_sge(&self) -> u32 {
+        self.raw().cap.max_send_sge
+    }
+
+    /// Returns the requested number of scatter/gather entries per receive work request.
+    pub fn max_recv_sge(&self) -> u32 {
+        self.raw().cap.max_recv_sge
+    }
+
+    /// Returns the requested amount of inline data per send work request.
+    pub fn max_inline_data(&self) -> u32 {
+        self.raw().cap.max_inline_data
+    }
+
+    /// Returns `true` if every send work request must generate a completion.
+    pub fn sq_sig_all(&self) -> bool {
+        self.raw().sq_sig_type == bindings::ib_sig_type_IB_SIGNAL_ALL_WR
+    }
+
+    /// Checks the requested queue sizes and inline data against `limits`, which should be a
+    /// single snapshot taken for this QP.
+    ///
+    /// Returns `EINVAL` if the request does not fit.
+    pub fn check_limits(&self, limits: &QpLimits) -> Result {
+        if !limits.allows(self.max_send_wr(), self.max_recv_wr(), self.max_inline_data()) {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+
+    /// Reports the capabilities actually granted back to the caller.
+    pub fn set_granted_caps(&self, send_wr: u32, recv_wr: u32, inline_data: u32) {
+        // SAFETY: The attributes are valid per the `from_ptr` requirements, ib_core reads the
+        // capabilities back once the callback returns.
+        let cap = unsafe { &mut (*self.0.get()).cap };
+        cap.max_send_wr = send_wr;
+        cap.max_recv_wr = recv_wr;
+        cap.max_inline_data = inline_data;
+    }
+}
+
+/// Set of attributes present in a [`QpAttr`], `enum ib_qp_attr_mask`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QpAttrMask(u32);
+
+impl QpAttrMask {
+    /// No attribute.
+    pub const NONE: Self = Self(0);
+    /// `qp_state`.
+    pub const STATE: Self = Self(bindings::ib_qp_attr_mask_IB_QP_STATE as u32);
+    /// `cur_qp_state`.
+    pub const CUR_STATE: Self = Self(bindings::ib_qp_attr_mask_IB_QP_CUR_STATE as u32);
+    /// `en_sqd_async_notify`.
+    pub const EN_SQD_ASYNC_NOTIFY: Self =
+        Self(bindings::ib_qp_attr_mask_IB_QP_EN_SQD_ASYNC_NOTIFY as u32);
+    /// `qp_access_flags`.
+    pub const ACCESS_FLAGS: Self = Self(bindings::ib_qp_attr_mask_IB_QP_ACCESS_FLAGS as u32);
+    /// `pkey_index`.
+    pub const PKEY_INDEX: Self = Self(bindings::ib_qp_attr_mask_IB_QP_PKEY_INDEX as u32);
+    /// `port_num`.
+    pub const PORT: Self = Self(bindings::ib_qp_attr_mask_IB_QP_PORT as u32);
+    /// `qkey`.
+    pub const QKEY: Self = Self(bindings::ib_qp_attr_mask_IB_QP_QKEY as u32);
+    /// `ah_attr`, the primary path.
+    pub const AV: Self = Self(bindings::ib_qp_attr_mask_IB_QP_AV as u32);
+    /// `path_mtu`.
+    pub const PATH_MTU: Self = Self(bindings::ib_qp_attr_mask_IB_QP_PATH_MTU as u32);
+    /// `timeout`.
+    pub const TIMEOUT: Self = Self(bindings::ib_qp_attr_mask_IB_QP_TIMEOUT as u32);
+    /// `retry_cnt`.
+    pub const RETRY_CNT: Self = Self(bindings::ib_qp_attr_mask_IB_QP_RETRY_CNT as u32);
+    /// `rnr_retry`.
+    pub const RNR_RETRY: Self = Self(bindings::ib_qp_attr_mask_IB_QP_RNR_RETRY as u32);
+    /// `rq_psn`.
+    pub const RQ_PSN: Self = Self(bindings::ib_qp_attr_mask_IB_QP_RQ_PSN as u32);
+    /// `max_rd_atomic`.
+    pub const MAX_QP_RD_ATOMIC: Self =
+        Self(bindings::ib_qp_attr_mask_IB_QP_MAX_QP_RD_ATOMIC as u32);
+    /// `min_rnr_timer`.
+    pub const MIN_RNR_TIMER: Self = Self(bindings::ib_qp_attr_mask_IB_QP_MIN_RNR_TIMER as u32);
+    /// `sq_psn`.
+    pub const SQ_PSN: Self = Self(bindings::ib_qp_attr_mask_IB_QP_SQ_PSN as u32);
+    /// `max_dest_rd_atomic`.
+    pub const MAX_DEST_RD_ATOMIC: Self =
+        Self(bindings::ib_qp_attr_mask_IB_QP_MAX_DEST_RD_ATOMIC as u32);
+    /// `cap`.
+    pub const CAP: Self = Self(bindings::ib_qp_attr_mask_IB_QP_CAP as u32);
+    /// `dest_qp_num`.
+    pub const DEST_QPN: Self = Self(bindings::ib_qp_attr_mask_IB_QP_DEST_QPN as u32);
+
+    /// Creates a mask from its raw value.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw value.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if every attribute of `other` is present.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for QpAttrMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Attributes of a queue pair together with the mask of the valid ones, wraps
+/// `struct ib_qp_attr`.
+///
+/// Getters return `None` for attributes outside the mask; the `with_*` builders set an attribute
+/// and add it to the mask.
+#[derive(Clone)]
+pub struct QpAttr {
+    raw: bindings::ib_qp_attr,
+    mask: QpAttrMask,
+}
+
+macro_rules! qp_attr_fields {
+    ($($(#[$doc:meta])* $getter:ident, $builder:ident => $field:ident: $ty:ty, $mask:ident;)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $getter(&self) -> Option<$ty> {
+                self.get(QpAttrMask::$mask, self.raw.$field as $ty)
+            }
+
+            $(#[$doc])*
+            pub fn $builder(mut self, value: $ty) -> Self {
+                self.raw.$field = value as _;
+                self.mask = self.mask | QpAttrMask::$mask;
+                self
+            }
+        )*
+    };
+}
+
+impl QpAttr {
+    /// Creates an empty set of attributes.
+    pub fn new() -> Self {
+        Self {
+            raw: bindings::ib_qp_attr::default(),
+            mask: QpAttrMask::NONE,
+        }
+    }
+
+    /// Copies attributes passed by ib_core.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads.
+    pub(crate) unsafe fn from_raw(ptr: *const bindings::ib_qp_attr, mask: u32) -> Self {
+        Self {
+            // SAFETY: Guaranteed by the safety requirements. The struct is plain data, the copy
+            // does not take ownership of anything.
+            raw: unsafe { ptr.read() },
+            mask: QpAttrMask::from_bits(mask),
+        }
+    }
+
+    /// Returns the raw attributes, including the ones outside the mask.
+    pub fn as_raw(&self) -> &bindings::ib_qp_attr {
+        &self.raw
+    }
+
+    /// Returns the mask of the valid attributes.
+    pub fn mask(&self) -> QpAttrMask {
+        self.mask
+    }
+
+    fn get<V>(&self, mask: QpAttrMask, value: V) -> Option<V> {
+        if self.mask.contains(mask) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Requested state.
+    pub fn state(&self) -> Option<QpState> {
+        self.get(QpAttrMask::STATE, ())
+            .and_then(|()| QpState::from_raw(self.raw.qp_state))
+    }
+
+    /// Requests a state.
+    pub fn with_state(mut self, state: QpState) -> Self {
+        self.raw.qp_state = state.to_raw();
+        self.mask = self.mask | QpAttrMask::STATE;
+        self
+    }
+
+    /// State the consumer believes the QP is in.
+    pub fn cur_state(&self) -> Option<QpState> {
+        self.get(QpAttrMask::CUR_STATE, ())
+            .and_then(|()| QpState::from_raw(self.raw.cur_qp_state))
+    }
+
+    /// Path MTU.
+    pub fn path_mtu(&self) -> Option<Mtu> {
+        self.get(QpAttrMask::PATH_MTU, ())
+            .and_then(|()| Mtu::from_raw(self.raw.path_mtu))
+    }
+
+    /// Sets the path MTU.
+    pub fn with_path_mtu(mut self, mtu: Mtu) -> Self {
+        self.raw.path_mtu = mtu as _;
+        self.mask = self.mask | QpAttrMask::PATH_MTU;
+        self
+    }
+
+    /// First PSN of the send queue.
+    pub fn sq_psn(&self) -> Option<Psn> {
+        self.get(QpAttrMask::SQ_PSN, Psn::new(self.raw.sq_psn))
+    }
+
+    /// Sets the first PSN of the send queue.
+    pub fn with_sq_psn(mut self, psn: Psn) -> Self {
+        self.raw.sq_psn = psn.value();
+        self.mask = self.mask | QpAttrMask::SQ_PSN;
+        self
+    }
+
+    /// Expected PSN of the receive queue.
+    pub fn rq_psn(&self) -> Option<Psn> {
+        self.get(QpAttrMask::RQ_PSN, Psn::new(self.raw.rq_psn))
+    }
+
+    /// Sets the expected PSN of the receive queue.
+    pub fn with_rq_psn(mut self, psn: Psn) -> Self {
+        self.raw.rq_psn = psn.value();
+        self.mask = self.mask | QpAttrMask::RQ_PSN;
+        self
+    }
+
+    qp_attr_fields! {
+        /// `IB_ACCESS_*` flags granted to incoming requests.
+        access_flags, with_access_flags => qp_access_flags: u32, ACCESS_FLAGS;
+        /// P_Key index.
+        pkey_index, with_pkey_index => pkey_index: u16, PKEY_INDEX;
+        /// Port number.
+        port, with_port => port_num: u32, PORT;
+        /// Q_Key of a datagram QP.
+        qkey, with_qkey => qkey: u32, QKEY;
+        /// Destination QP number of a connected QP.
+        dest_qp_num, with_dest_qp_num => dest_qp_num: u32, DEST_QPN;
+        /// Local ACK timeout exponent, `4.096 us << timeout`.
+        timeout, with_timeout => timeout: u8, TIMEOUT;
+        /// Transport retry count.
+        retry_cnt, with_retry_cnt => retry_cnt: u8, RETRY_CNT;
+        /// RNR retry count, 7 meaning infinite.
+        rnr_retry, with_rnr_retry => rnr_retry: u8, RNR_RETRY;
+        /// Minimum RNR NAK timer code.
+        min_rnr_timer, with_min_rnr_timer => min_rnr_timer: u8, MIN_RNR_TIMER;
+        /// Outstanding RDMA READ and atomic requests as initiator.
+        max_rd_atomic, with_max_rd_atomic => max_rd_atomic: u8, MAX_QP_RD_ATOMIC;
+        /// Outstanding RDMA READ and atomic requests as responder.
+        max_dest_rd_atomic, with_max_dest_rd_atomic => max_dest_rd_atomic: u8, MAX_DEST_RD_ATOMIC;
+    }
+}
+
+impl Default for QpAttr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Checks a modification of a QP of type `qp_type` currently in `cur`, and returns the state it
+/// moves to.
+///
+/// Follows the IBTA state machine, honours the consumer's `cur_qp_state`, and requires exactly the
+/// attributes ib_core expects for the transition (see `ib_modify_qp_is_ok()`).
+pub fn check_modify(qp_type: QpType, cur: QpState, attr: &QpAttr) -> Result<QpState> {
+    let cur = attr.cur_state().unwrap_or(cur);
+    let next = attr.state().unwrap_or(cur);
+    if attr.mask().contains(QpAttrMask::STATE) && attr.state().is_none() {
+        return Err(EINVAL);
+    }
+    if !cur.can_transition_to(next) {
+        return Err(EINVAL);
+    }
+    // SAFETY: The function only inspects its arguments.
+    let ok = unsafe {
+        bindings::ib_modify_qp_is_ok(cur.to_raw(), next.to_raw(), qp_type.to_raw(), attr.mask().0)
+    };
+    if !ok {
+        return Err(EINVAL);
+    }
+    Ok(next)
+}
+
+/// An asynchronous event of a QP, reported to its consumer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QpAsyncEvent {
+    /// The connection is established, `IB_EVENT_COMM_EST`.
+    CommEstablished,
+    /// The last WQE of a QP attached to an SRQ was consumed, `IB_EVENT_QP_LAST_WQE_REACHED`.
+    LastWqeReached,
+    /// The QP moved to the error state on its own, e.g. because its peer stopped answering,
+    /// `IB_EVENT_QP_FATAL`.
+    Fatal,
+    /// A request was invalid, `IB_EVENT_QP_REQ_ERR`.
+    RequestError,
+    /// A request violated the access rights of its target, `IB_EVENT_QP_ACCESS_ERR`.
+    AccessError,
+}
+
+impl QpAsyncEvent {
+    fn to_raw(self) -> bindings::ib_event_type {
+        match self {
+            Self::CommEstablished => bindings::ib_event_type_IB_EVENT_COMM_EST,
+            Self::LastWqeReached => bindings::ib_event_type_IB_EVENT_QP_LAST_WQE_REACHED,
+            Self::Fatal => bindings::ib_event_type_IB_EVENT_QP_FATAL,
+            Self::RequestError => bindings::ib_event_type_IB_EVENT_QP_REQ_ERR,
+            Self::AccessError => bindings::ib_event_type_IB_EVENT_QP_ACCESS_ERR,
+        }
+    }
+}
+
+/// Provider data of a QP together with the state tracked by the abstraction.
+pub(crate) struct QpObject<D> {
+    pub(crate) state: AtomicU32,
+    pub(crate) data: D,
+}
+
+impl<D> QpObject<D> {
+    pub(crate) fn new(data: D) -> Self {
+        Self {
+            state: AtomicU32::new(QpState::Reset.to_raw()),
+            data,
+        }
+    }
+}
+
+/// A queue pair of a device provided by `T`, wraps `struct ib_qp`.
+///
+/// The abstraction tracks the QP state: [`IbDeviceOperations::modify_qp`] only sees modifications
+/// that passed [`check_modify`], and the new state is recorded once it succeeds.
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_qp` belongs to a device provided by `T` and is followed by an
+/// initialised `QpObject<T::QpData>`.
+#[repr(transparent)]
+pub struct QueuePair<T: IbDeviceOperations>(UnsafeCell<bindings::ib_qp>, marker::PhantomData<T>);
+
+impl<T: IbDeviceOperations> QueuePair<T> {
+    /// Creates a reference to a [`QueuePair`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a QP of a device provided by `T` whose `create_qp` succeeded and which is not
+    /// destroyed for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_qp) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `QueuePair` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_qp` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_qp {
+        self.0.get()
+    }
+
+    /// Returns the restrack record of the QP, with its ID and creator.
+    pub fn restrack(&self) -> &ResourceEntry {
+        // SAFETY: The QP is valid and embeds its entry.
+        unsafe { ResourceEntry::from_ptr(ptr::addr_of_mut!((*self.as_ptr()).res)) }
+    }
+
+    fn object(&self) -> &QpObject<T::QpData> {
+        // SAFETY: By the type invariants the object is initialised.
+        unsafe { Object::<bindings::ib_qp, QpObject<T::QpData>>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns the device the QP belongs to.
+    pub fn device(&self) -> &DeviceRef<T> {
+        // SAFETY: By the type invariants the QP belongs to a device provided by `T`, which
+        // outlives all of its QPs.
+        unsafe { DeviceRef::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the provider data of the QP.
+    pub fn data(&self) -> &T::QpData {
+        &self.object().data
+    }
+
+    /// Returns the QP number.
+    pub fn qp_num(&self) -> u32 {
+        // SAFETY: The QP is valid.
+        unsafe { (*self.as_ptr()).qp_num }
+    }
+
+    /// Returns the QP type.
+    pub fn qp_type(&self) -> QpType {
+        // SAFETY: The QP is valid.
+        QpType::from_raw(unsafe { (*self.as_ptr()).qp_type })
+    }
+
+    /// Returns the current state.
+    pub fn state(&self) -> QpState {
+        QpState::from_raw(self.object().state.load(Ordering::Acquire)).unwrap_or(QpState::Err)
+    }
+
+    /// Moves the QP to the error state on the provider's own initiative, e.g. when the retry
+    /// count is exhausted.
+    pub fn set_error(&self) {
+        self.set_state(QpState::Err);
+    }
+
+    /// Records the new state, firing the `qp_state_change` tracepoint if it changed.
+    pub(crate) fn set_state(&self, state: QpState) {
+        let old = self.object().state.swap(state.to_raw(), Ordering::AcqRel);
+        if old != state.to_raw() && trace::qp_state_change::enabled() {
+            // SAFETY: The QP is valid.
+            let qp = unsafe { &*self.as_ptr() };
+            trace::qp_state_change::emit(qp, old as i32, state.to_raw() as i32);
+        }
+    }
+
+    /// Reports `event` to the consumer of the QP.
+    ///
+    /// A provider detecting a dead peer, e.g. once its retries are exhausted, calls
+    /// [`QueuePair::set_error`] first and then reports [`QpAsyncEvent::Fatal`].
+    pub fn report_event(&self, event: QpAsyncEvent) {
+        // SAFETY: The QP is valid; the handler and its context are set by ib_core at creation
+        // and stay valid until the QP is destroyed.
+        unsafe {
+            let qp = self.as_ptr();
+            if let Some(handler) = (*qp).event_handler {
+                let mut ev = bindings::ib_event::default();
+                ev.device = (*qp).device;
+                ev.event = event.to_raw();
+                ev.element.qp = qp;
+                handler(&mut ev, (*qp).qp_context);
+            }
+        }
+    }
+
+    /// Moves the QP to the error state if `faults` queued it, and reports it like a provider
+    /// detected failure. Returns `true` if it did.
+    ///
+    /// `modify_qp` and `post_send` check this before anything else; providers call it from the
+    /// places where their QPs may fail on their own, e.g. the requester and responder tasks.
+    pub fn take_injected_error(&self, faults: &QpFaultInjector) -> bool {
+        if !faults.take_error(self.qp_num()) {
+            return false;
+        }
+        self.set_error();
+        self.report_event(QpAsyncEvent::Fatal);
+        true
+    }
+
+    /// Returns the protection domain of the QP, `None` for XRC targets.
+    pub fn pd(&self) -> Option<&ProtectionDomain<T>> {
+        // SAFETY: The QP is valid; its PD belongs to the same device and outlives it.
+        unsafe {
+            let pd = (*self.as_ptr()).pd;
+            if pd.is_null() {
+                None
+            } else {
+                Some(ProtectionDomain::from_ptr(pd))
+            }
+        }
+    }
+
+    /// Returns the CQ of the send queue.
+    pub fn send_cq(&self) -> Option<&CompletionQueue<T>> {
+        // SAFETY: The QP is valid; its CQs belong to the same device and outlive it.
+        unsafe {
+            let cq = (*self.as_ptr()).send_cq;
+            if cq.is_null() {
+                None
+            } else {
+                Some(CompletionQueue::from_ptr(cq))
+            }
+        }
+    }
+
+    /// Returns the CQ of the receive queue.
+    pub fn recv_cq(&self) -> Option<&CompletionQueue<T>> {
+        // SAFETY: The QP is valid; its CQs belong to the same device and outlive it.
+        unsafe {
+            let cq = (*self.as_ptr()).recv_cq;
+            if cq.is_null() {
+                None
+            } else {
+                Some(CompletionQueue::from_ptr(cq))
+            }
+        }
+    }
+
+    /// Returns the shared receive queue the QP takes its receive work requests from, if any.
+    pub fn srq(&self) -> Option<&SharedReceiveQueue<T>> {
+        // SAFETY: The QP is valid; its SRQ belongs to the same device and outlives it.
+        unsafe {
+            let srq = (*self.as_ptr()).srq;
+            if srq.is_null() {
+                None
+            } else {
+                Some(SharedReceiveQueue::from_ptr(srq))
+            }
+        }
+    }
+}
@@ -0,0 +1,631 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! RDMA connection manager consumers.
+//!
+//! C header: [`include/rdma/rdma_cm.h`](../../../../include/rdma/rdma_cm.h)
+//!
+//! rdma_cm connects kernel ULPs by IP address, over any transport: it resolves the address of
+//! the peer to a local device and port, the route to the peer, and then runs the handshake of the
+//! transport, ib_cm for InfiniBand and RoCE devices such as rxe. Every step completes with an
+//! event to the [`RdmaCmEventHandler`] of the [`Endpoint`], which starts the next one:
+//!
+//! - the active side calls [`RdmaCmId::resolve_addr`], then [`RdmaCmId::resolve_route`] from
+//!   [`RdmaCmEventHandler::addr_resolved`], then [`LockedRdmaCmId::connect`] from
+//!   [`RdmaCmEventHandler::route_resolved`];
+//! - the passive side binds and listens, and gets an [`Incoming`] ID per connection request,
+//!   which it accepts with a handler of its own.
+//!
+//! The consumer owns its QP, created on [`RdmaCmId::device`] once the address is resolved, and
+//! moves it through the states with the attributes of [`RdmaCmId::init_qp_attr`].
+//!
+//! The event callbacks of an ID run one at a time with its handler lock held, in process context.
+//! Dropping an [`Endpoint`] waits for the callback in progress, unless it is dropped from that very
+//! callback, e.g. on [`RdmaCmEventHandler::device_removal`]: rdma_cm then destroys the ID once the
+//! callback returns. A listening endpoint must not be dropped from the callback of a connection
+//! request, which holds its handler lock; the ULPs defer its teardown to a work item instead.
+
+use alloc::boxed::Box;
+use core::cell::{Cell, UnsafeCell};
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::{marker, ptr, slice};
+use macros::vtable;
+
+use super::cm_context::IdContext;
+use super::observer::ObservedDevice;
+use super::qp::QpAttr;
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Error, Result};
+use crate::rdma::cm::CmMessage;
+use crate::rdma::ip_filter::PeerAddr;
+use crate::rdma::qp_state::QpState;
+use crate::ThisModule;
+
+fn to_result(ret: core::ffi::c_int) -> Result {
+    if ret < 0 {
+        return Err(Error::from_kernel_errno(ret));
+    }
+    Ok(())
+}
+
+/// The port spaces of rdma_cm, `enum rdma_ucm_port_space`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortSpace {
+    /// Reliable connections, sharing the port numbers of TCP.
+    Tcp,
+    /// Unreliable datagrams, sharing the port numbers of UDP.
+    Udp,
+    /// Either, in the InfiniBand port space.
+    Ib,
+    /// The port space of IPoIB.
+    Ipoib,
+}
+
+impl PortSpace {
+    fn to_raw(self) -> bindings::rdma_ucm_port_space {
+        match self {
+            PortSpace::Tcp => bindings::rdma_ucm_port_space_RDMA_PS_TCP,
+            PortSpace::Udp => bindings::rdma_ucm_port_space_RDMA_PS_UDP,
+            PortSpace::Ib => bindings::rdma_ucm_port_space_RDMA_PS_IB,
+            PortSpace::Ipoib => bindings::rdma_ucm_port_space_RDMA_PS_IPOIB,
+        }
+    }
+}
+
+/// A socket address, as rdma_cm takes them.
+#[repr(C)]
+union SockAddr {
+    v4: bindings::sockaddr_in,
+    v6: bindings::sockaddr_in6,
+}
+
+impl SockAddr {
+    fn new(addr: PeerAddr, port: u16) -> Self {
+        let octets = addr.octets();
+        if addr.is_v4() {
+            let mut v4 = bindings::sockaddr_in {
+                sin_family: bindings::AF_INET as _,
+                sin_port: port.to_be(),
+                ..Default::default()
+            };
+            v4.sin_addr.s_addr =
+                u32::from_ne_bytes([octets[12], octets[13], octets[14], octets[15]]);
+            return Self { v4 };
+        }
+        let mut v6 = bindings::sockaddr_in6 {
+            sin6_family: bindings::AF_INET6 as _,
+            sin6_port: port.to_be(),
+            ..Default::default()
+        };
+        v6.sin6_addr.in6_u.u6_addr8 = octets;
+        Self { v6 }
+    }
+
+    fn as_ptr(&mut self) -> *mut bindings::sockaddr {
+        (self as *mut Self).cast()
+    }
+}
+
+/// An rdma_cm ID, wraps `struct rdma_cm_id`.
+///
+/// Owned by an [`Endpoint`], or lent to the callbacks of its handler.
+#[repr(transparent)]
+pub struct RdmaCmId(UnsafeCell<bindings::rdma_cm_id>);
+
+impl RdmaCmId {
+    /// Creates a reference to an [`RdmaCmId`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::rdma_cm_id) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `RdmaCmId` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct rdma_cm_id` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::rdma_cm_id {
+        self.0.get()
+    }
+
+    /// Returns the device the ID is bound to, `None` until its address is resolved or bound.
+    pub fn device(&self) -> Option<&ObservedDevice> {
+        // SAFETY: The ID is valid.
+        let dev = unsafe { (*self.as_ptr()).device };
+        if dev.is_null() {
+            return None;
+        }
+        // SAFETY: The ID holds its device until it is destroyed or the device is removed, which
+        // the handler is told about first.
+        Some(unsafe { ObservedDevice::from_ptr(dev) })
+    }
+
+    /// Returns the port of [`RdmaCmId::device`] the ID is bound to.
+    pub fn port_num(&self) -> u32 {
+        // SAFETY: The ID is valid.
+        unsafe { (*self.as_ptr()).port_num as u32 }
+    }
+
+    /// Binds the ID to a local address, e.g. before listening. Port 0 picks a free port.
+    pub fn bind_addr(&self, addr: PeerAddr, port: u16) -> Result {
+        let mut src = SockAddr::new(addr, port);
+        // SAFETY: The ID is valid and the address outlives the call, which copies it.
+        to_result(unsafe { bindings::rdma_bind_addr(self.as_ptr(), src.as_ptr()) })
+    }
+
+    /// Listens for connection requests to the bound address, queueing up to `backlog` of them.
+    pub fn listen(&self, backlog: i32) -> Result {
+        // SAFETY: The ID is valid.
+        to_result(unsafe { bindings::rdma_listen(self.as_ptr(), backlog) })
+    }
+
+    /// Starts resolving `dst` to a local device and port, from `src` if given; completes with
+    /// [`RdmaCmEventHandler::addr_resolved`] within `timeout_ms`.
+    pub fn resolve_addr(
+        &self,
+        src: Option<PeerAddr>,
+        dst: (PeerAddr, u16),
+        timeout_ms: u32,
+    ) -> Result {
+        let mut src = src.map(|addr| SockAddr::new(addr, 0));
+        let src = src.as_mut().map_or(ptr::null_mut(), SockAddr::as_ptr);
+        let mut dst = SockAddr::new(dst.0, dst.1);
+        // SAFETY: The ID is valid and the addresses outlive the call, which copies them.
+        to_result(unsafe {
+            bindings::rdma_resolve_addr(self.as_ptr(), src, dst.as_ptr(), timeout_ms as _)
+        })
+    }
+
+    /// Starts resolving the route to the peer of a resolved address; completes with
+    /// [`RdmaCmEventHandler::route_resolved`] within `timeout_ms`.
+    pub fn resolve_route(&self, timeout_ms: u32) -> Result {
+        // SAFETY: The ID is valid.
+        to_result(unsafe { bindings::rdma_resolve_route(self.as_ptr(), timeout_ms as _) })
+    }
+
+    /// Returns the attributes moving the QP of the connection to `state`, negotiated so far.
+    pub fn init_qp_attr(&self, state: QpState) -> Result<QpAttr> {
+        let mut raw = bindings::ib_qp_attr {
+            qp_state: state.to_raw(),
+            ..Default::default()
+        };
+        let mut mask = 0;
+        // SAFETY: The ID is valid and the attributes are written to locals.
+        to_result(unsafe { bindings::rdma_init_qp_attr(self.as_ptr(), &mut raw, &mut mask) })?;
+        // SAFETY: `raw` is a valid, initialised attribute set.
+        Ok(unsafe { QpAttr::from_raw(&raw, mask as u32) })
+    }
+
+    /// Tears the connection down; both sides are then told by
+    /// [`RdmaCmEventHandler::disconnected`].
+    pub fn disconnect(&self) -> Result {
+        // SAFETY: The ID is valid.
+        to_result(unsafe { bindings::rdma_disconnect(self.as_ptr()) })
+    }
+}
+
+/// An [`RdmaCmId`] lent to a callback of its handler, whose lock is held.
+#[repr(transparent)]
+pub struct LockedRdmaCmId(RdmaCmId);
+
+impl LockedRdmaCmId {
+    /// Creates a reference to a [`LockedRdmaCmId`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid, and its handler lock held, for the lifetime of the returned instance.
+    unsafe fn from_ptr<'a>(ptr: *mut bindings::rdma_cm_id) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Starts the handshake with the peer of a resolved route, usually from
+    /// [`RdmaCmEventHandler::route_resolved`]; the QP of `params` must be in INIT.
+    pub fn connect(&self, params: &ConnParams<'_>) -> Result {
+        let mut raw = params.raw;
+        // SAFETY: The ID is valid and its handler lock is held; the private data outlives the
+        // call, which copies it.
+        to_result(unsafe { bindings::rdma_connect_locked(self.as_ptr(), &mut raw) })
+    }
+
+    /// Rejects a connection request, or a reply answering [`LockedRdmaCmId::connect`].
+    pub fn reject(&self, private_data: &[u8]) -> Result {
+        let len = CmMessage::Rej
+            .check_private_data(private_data)
+            .map_err(|_| EINVAL)?;
+        // SAFETY: The ID is valid and the private data outlives the call, which copies it.
+        to_result(unsafe {
+            bindings::rdma_reject(
+                self.as_ptr(),
+                private_data.as_ptr().cast(),
+                len,
+                bindings::ib_cm_rej_reason_IB_CM_REJ_CONSUMER_DEFINED as _,
+            )
+        })
+    }
+}
+
+impl Deref for LockedRdmaCmId {
+    type Target = RdmaCmId;
+
+    fn deref(&self) -> &RdmaCmId {
+        &self.0
+    }
+}
+
+/// Parameters of a connection, see [`LockedRdmaCmId::connect`] and [`Incoming::accept`].
+pub struct ConnParams<'a> {
+    raw: bindings::rdma_conn_param,
+    phantom: marker::PhantomData<&'a [u8]>,
+}
+
+impl<'a> ConnParams<'a> {
+    /// Returns the parameters connecting the QP `qp_num`, with no RDMA read resources, retrying
+    /// the transport and RNR errors 7 times.
+    pub fn new(qp_num: u32) -> Self {
+        Self {
+            raw: bindings::rdma_conn_param {
+                qp_num,
+                flow_control: 1,
+                retry_count: 7,
+                rnr_retry_count: 7,
+                ..Default::default()
+            },
+            phantom: marker::PhantomData,
+        }
+    }
+
+    /// Sets the private data, at most the 196 bytes of a REP; rdma_cm prepends a header of its
+    /// own to that of a connection request in the TCP port space, which leaves 56 bytes there.
+    pub fn with_private_data(mut self, data: &'a [u8]) -> Result<Self> {
+        self.raw.private_data_len = CmMessage::Rep
+            .check_private_data(data)
+            .map_err(|_| EINVAL)?;
+        self.raw.private_data = data.as_ptr().cast();
+        Ok(self)
+    }
+
+    /// Sets the RDMA reads and atomics the QP accepts and sends at once.
+    pub fn with_rdma_depth(mut self, responder_resources: u8, initiator_depth: u8) -> Self {
+        self.raw.responder_resources = responder_resources;
+        self.raw.initiator_depth = initiator_depth;
+        self
+    }
+
+    /// Sets the retries of the transport and RNR errors, 7 retrying RNR NAKs forever.
+    pub fn with_retries(mut self, retry_count: u8, rnr_retry_count: u8) -> Self {
+        self.raw.retry_count = retry_count.min(7);
+        self.raw.rnr_retry_count = rnr_retry_count.min(7);
+        self
+    }
+
+    /// Tells the peer that the QP receives from an SRQ.
+    pub fn with_srq(mut self, srq: bool) -> Self {
+        self.raw.srq = srq as u8;
+        self
+    }
+}
+
+/// The connection parameters of the peer, reported with the connection events.
+pub struct ConnEvent<'a> {
+    param: &'a bindings::rdma_conn_param,
+    status: i32,
+}
+
+impl ConnEvent<'_> {
+    /// Returns the status of the event: the reason of a reject, 0 otherwise.
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    /// Returns the QP number of the peer.
+    pub fn qp_num(&self) -> u32 {
+        self.param.qp_num
+    }
+
+    /// Returns the RDMA reads and atomics the peer accepts at once.
+    pub fn responder_resources(&self) -> u8 {
+        self.param.responder_resources
+    }
+
+    /// Returns the RDMA reads and atomics the peer sends at once.
+    pub fn initiator_depth(&self) -> u8 {
+        self.param.initiator_depth
+    }
+
+    /// Returns `true` if the QP of the peer receives from an SRQ.
+    pub fn srq(&self) -> bool {
+        self.param.srq != 0
+    }
+
+    /// Returns the private data sent by the peer.
+    pub fn private_data(&self) -> &[u8] {
+        if self.param.private_data.is_null() {
+            return &[];
+        }
+        // SAFETY: The private data lives as long as the event, with the length it was sent with.
+        unsafe {
+            slice::from_raw_parts(
+                self.param.private_data.cast(),
+                self.param.private_data_len as usize,
+            )
+        }
+    }
+}
+
+/// Handles the events of the rdma_cm IDs of an [`Endpoint`].
+///
+/// Each callback runs for one ID at a time, with its handler lock held, in process context, and
+/// may sleep.
+#[vtable]
+pub trait RdmaCmEventHandler: Send + Sync + Sized {
+    /// Called when [`RdmaCmId::resolve_addr`] completes; [`RdmaCmId::device`] is known on
+    /// success.
+    fn addr_resolved(&self, _id: &LockedRdmaCmId, _status: Result) {}
+
+    /// Called when [`RdmaCmId::resolve_route`] completes.
+    fn route_resolved(&self, _id: &LockedRdmaCmId, _status: Result) {}
+
+    /// Called on a listening endpoint for every connection request.
+    ///
+    /// The handler moves its QP to RTR and calls [`Incoming::accept`]; the request is rejected
+    /// if it returns an error, or does not accept it.
+    fn connect_request(&self, _id: Incoming<'_, Self>, _conn: &ConnEvent<'_>) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Called when the peer accepted the request of [`LockedRdmaCmId::connect`].
+    ///
+    /// The handler moves its QP to RTR and RTS, and the handshake is completed once it returns;
+    /// the connection is rejected if it returns an error.
+    fn connect_response(&self, _id: &LockedRdmaCmId, _conn: &ConnEvent<'_>) -> Result {
+        Err(EOPNOTSUPP)
+    }
+
+    /// Called when the connection is established.
+    fn established(&self, _id: &LockedRdmaCmId, _conn: &ConnEvent<'_>) {}
+
+    /// Called when the peer rejected the connection.
+    fn rejected(&self, _id: &LockedRdmaCmId, _conn: &ConnEvent<'_>) {}
+
+    /// Called when the handshake failed, or the peer could not be reached.
+    fn connect_error(&self, _id: &LockedRdmaCmId, _err: Error) {}
+
+    /// Called when the connection is torn down, by either side.
+    fn disconnected(&self, _id: &LockedRdmaCmId) {}
+
+    /// Called when the device of the ID is being removed; the handler must destroy its QP and
+    /// drop the endpoint, which the removal waits for.
+    fn device_removal(&self, _id: &LockedRdmaCmId) {}
+
+    /// Called when the address the ID is bound to moved to another device.
+    fn addr_change(&self, _id: &LockedRdmaCmId) {}
+
+    /// Called once the QP of a torn down connection may be reused.
+    fn timewait_exit(&self, _id: &LockedRdmaCmId) {}
+}
+
+/// An rdma_cm ID created for a connection request to a listening [`Endpoint`].
+///
+/// Only lent to [`RdmaCmEventHandler::connect_request`]; the ID is destroyed, rejecting the
+/// request, unless the handler accepts it.
+pub struct Incoming<'a, T: RdmaCmEventHandler> {
+    id: &'a LockedRdmaCmId,
+    accepted: &'a Cell<bool>,
+    phantom: marker::PhantomData<T>,
+}
+
+impl<T: RdmaCmEventHandler> Incoming<'_, T> {
+    /// Accepts the request, with the QP of `params` in RTR, and keeps the ID as an endpoint whose
+    /// events go to `handler`.
+    ///
+    /// The endpoint may be dropped before the callback returns: rdma_cm then destroys the ID.
+    pub fn accept(self, handler: Pin<Box<T>>, params: &ConnParams<'_>) -> Result<Endpoint<T>> {
+        let context = IdContext::try_new(handler)?;
+        let mut raw = params.raw;
+        // SAFETY: The ID is valid and its handler lock is held; the private data outlives the
+        // call, which copies it.
+        to_result(unsafe { bindings::rdma_accept(self.id.as_ptr(), &mut raw) })?;
+        // The callback of the request is the first one of the new ID.
+        context.enter();
+        // SAFETY: The ID is valid, and its callbacks do not run while this one does, so the next
+        // one sees the new context. The context lives until the endpoint destroys the ID.
+        unsafe { (*self.id.as_ptr()).context = &*context as *const IdContext<T> as *mut _ };
+        self.accepted.set(true);
+        // INVARIANT: The ID was created by rdma_cm for us and its context is `context`.
+        Ok(Endpoint {
+            ptr: self.id.as_ptr(),
+            context: ManuallyDrop::new(context),
+        })
+    }
+}
+
+impl<T: RdmaCmEventHandler> Deref for Incoming<'_, T> {
+    type Target = LockedRdmaCmId;
+
+    fn deref(&self) -> &LockedRdmaCmId {
+        self.id
+    }
+}
+
+/// An rdma_cm ID, destroyed on drop.
+///
+/// If it is dropped from a callback of the ID, the destruction is left to rdma_cm once the
+/// callback returns.
+///
+/// # Invariants
+///
+/// `ptr` is a live ID whose context is `context`, both owned by the instance.
+pub struct Endpoint<T: RdmaCmEventHandler> {
+    ptr: *mut bindings::rdma_cm_id,
+    context: ManuallyDrop<Box<IdContext<T>>>,
+}
+
+// SAFETY: The ID may be used and destroyed from any thread, and the handler is `Send`.
+unsafe impl<T: RdmaCmEventHandler> Send for Endpoint<T> {}
+// SAFETY: The calls on the ID serialise on its locks, and the handler is `Sync`.
+unsafe impl<T: RdmaCmEventHandler> Sync for Endpoint<T> {}
+
+impl<T: RdmaCmEventHandler> Endpoint<T> {
+    /// Creates an ID in `init_net` for RC connections in `ps`, or UD QPs in [`PortSpace::Udp`],
+    /// whose events go to `handler`. `module` is reported as the owner of the ID by restrack.
+    pub fn new(module: &'static ThisModule, ps: PortSpace, handler: Pin<Box<T>>) -> Result<Self> {
+        let qp_type = match ps {
+            PortSpace::Udp => bindings::ib_qp_type_IB_QPT_UD,
+            _ => bindings::ib_qp_type_IB_QPT_RC,
+        };
+        let context = IdContext::try_new(handler)?;
+        // SAFETY: `init_net` lives as long as the kernel, the context outlives the ID, which
+        // `drop` destroys, and the name of the module is static.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::__rdma_create_kernel_id(
+                ptr::addr_of!(bindings::init_net) as *mut _,
+                Some(event_handler::<T>),
+                &*context as *const IdContext<T> as *mut _,
+                ps.to_raw(),
+                qp_type,
+                (*module.as_ptr()).name.as_ptr(),
+            )
+        })?;
+        // INVARIANT: The ID was just created with `context` as context.
+        Ok(Self {
+            ptr,
+            context: ManuallyDrop::new(context),
+        })
+    }
+
+    /// Listens on `addr` and `port` for connection requests, which go to
+    /// [`RdmaCmEventHandler::connect_request`].
+    pub fn listen_on(&self, addr: PeerAddr, port: u16, backlog: i32) -> Result {
+        if !T::HAS_CONNECT_REQUEST {
+            return Err(EINVAL);
+        }
+        self.bind_addr(addr, port)?;
+        self.listen(backlog)
+    }
+
+    /// Returns the handler of the endpoint.
+    pub fn handler(&self) -> Pin<&T> {
+        self.context.handler.as_ref()
+    }
+}
+
+impl<T: RdmaCmEventHandler> Deref for Endpoint<T> {
+    type Target = RdmaCmId;
+
+    fn deref(&self) -> &RdmaCmId {
+        // SAFETY: By the type invariants the ID is live.
+        unsafe { RdmaCmId::from_ptr(self.ptr) }
+    }
+}
+
+impl<T: RdmaCmEventHandler> Drop for Endpoint<T> {
+    fn drop(&mut self) {
+        if self.context.doom_from_callback() {
+            // Dropped from a callback of the ID, whose handler lock `rdma_destroy_id` would take:
+            // the callback returns nonzero for rdma_cm to destroy the ID, and frees the context.
+            return;
+        }
+        // SAFETY: By the type invariants we own the ID. Destroying it waits for its callback in
+        // progress, so the context is not used once this returns.
+        unsafe { bindings::rdma_destroy_id(self.ptr) };
+        // SAFETY: The context is not used anymore, and dropped only here.
+        unsafe { ManuallyDrop::drop(&mut self.context) };
+    }
+}
+
+/// Returns the error of a failure event, whose status is a negative errno.
+fn event_error(status: i32) -> Error {
+    if status < 0 {
+        return Error::from_kernel_errno(status);
+    }
+    EINVAL
+}
+
+unsafe extern "C" fn event_handler<T: RdmaCmEventHandler>(
+    cm_id: *mut bindings::rdma_cm_id,
+    event: *mut bindings::rdma_cm_event,
+) -> core::ffi::c_int {
+    // SAFETY: rdma_cm passes a live ID with its handler lock held, whose context is the context
+    // of the `Endpoint` owning it; the ID of a connection request inherits the context of its
+    // listener.
+    let (id, context, event) = unsafe {
+        (
+            LockedRdmaCmId::from_ptr(cm_id),
+            &*(*cm_id).context.cast::<IdContext<T>>(),
+            &*event,
+        )
+    };
+    let handler = &*context.handler;
+    let conn = || ConnEvent {
+        // SAFETY: The connection events carry the connection parameters of the peer.
+        param: unsafe { &event.param.conn },
+        status: event.status,
+    };
+    if event.event != bindings::rdma_cm_event_type_RDMA_CM_EVENT_CONNECT_REQUEST {
+        context.enter();
+    }
+    match event.event {
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_ADDR_RESOLVED => {
+            handler.addr_resolved(id, Ok(()))
+        }
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_ADDR_ERROR => {
+            handler.addr_resolved(id, Err(event_error(event.status)))
+        }
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_ROUTE_RESOLVED => {
+            handler.route_resolved(id, Ok(()))
+        }
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_ROUTE_ERROR => {
+            handler.route_resolved(id, Err(event_error(event.status)))
+        }
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_CONNECT_REQUEST => {
+            let accepted = Cell::new(false);
+            let incoming = Incoming {
+                id,
+                accepted: &accepted,
+                phantom: marker::PhantomData,
+            };
+            let ret = handler.connect_request(incoming, &conn());
+            if accepted.get() {
+                // The endpoint owns the ID now, even if the handler failed, unless it was dropped
+                // already.
+                // SAFETY: `accept` set the context of the endpoint, and the callback ends.
+                return unsafe { IdContext::<T>::leave((*cm_id).context.cast()) };
+            }
+            // rdma_cm destroys the ID, which rejects the request.
+            return ret.err().unwrap_or(EINVAL).to_kernel_errno();
+        }
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_CONNECT_RESPONSE => {
+            if handler.connect_response(id, &conn()).is_err() {
+                let _ = id.reject(&[]);
+            } else {
+                // SAFETY: The ID is valid and its handler lock is held. Accepting a reply sends
+                // the RTU.
+                let ret = unsafe { bindings::rdma_accept(id.as_ptr(), ptr::null_mut()) };
+                match to_result(ret) {
+                    // rdma_cm reports no ESTABLISHED event on the active side of IB and RoCE
+                    // connections, the RTU completes the handshake.
+                    Ok(()) => handler.established(id, &conn()),
+                    Err(err) => handler.connect_error(id, err),
+                }
+            }
+        }
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_ESTABLISHED => handler.established(id, &conn()),
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_REJECTED => handler.rejected(id, &conn()),
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_CONNECT_ERROR
+        | bindings::rdma_cm_event_type_RDMA_CM_EVENT_UNREACHABLE => {
+            handler.connect_error(id, event_error(event.status))
+        }
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_DISCONNECTED => handler.disconnected(id),
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_DEVICE_REMOVAL => handler.device_removal(id),
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_ADDR_CHANGE => handler.addr_change(id),
+        bindings::rdma_cm_event_type_RDMA_CM_EVENT_TIMEWAIT_EXIT => handler.timewait_exit(id),
+        // Multicast is not supported.
+        _ => {}
+    }
+    // SAFETY: The context is the one of the ID, not used after the callback ends.
+    unsafe { IdContext::leave(context as *const IdContext<T> as *mut IdContext<T>) }
+}
@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Resource tracking entries, what `rdma res show` lists.
+//!
+//! ib_core adds every PD, CQ, QP, SRQ and MR of a device to its restrack database when it is
+//! created, MRs allocated by the provider included, and records who created it: the module of a
+//! kernel consumer, or the task of a userspace one. [`ResourceEntry`] is the provider's view of
+//! that record, reached through the `restrack` accessor of each verbs object, e.g. to attribute
+//! leaks to their owner. [`ResourceRef`] holds an object found by its restrack ID, the ID
+//! `rdma res show` prints, keeping it from being destroyed while the reference lives.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+
+use crate::bindings;
+use crate::rdma::tracker::ResourceKind;
+use crate::str::CStr;
+
+/// Returns the `RDMA_RESTRACK_*` type of `kind`, `None` for the kinds restrack does not list.
+pub const fn restrack_type(kind: ResourceKind) -> Option<bindings::rdma_restrack_type> {
+    Some(match kind {
+        ResourceKind::Pd => bindings::rdma_restrack_type_RDMA_RESTRACK_PD,
+        ResourceKind::Cq => bindings::rdma_restrack_type_RDMA_RESTRACK_CQ,
+        ResourceKind::Qp => bindings::rdma_restrack_type_RDMA_RESTRACK_QP,
+        ResourceKind::Srq => bindings::rdma_restrack_type_RDMA_RESTRACK_SRQ,
+        ResourceKind::Mr => bindings::rdma_restrack_type_RDMA_RESTRACK_MR,
+        ResourceKind::Mw | ResourceKind::Ah => return None,
+    })
+}
+
+/// Who created a tracked object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceOwner<'a> {
+    /// A kernel consumer, named after its module, e.g. `ib_ipoib`.
+    Kernel(&'a CStr),
+    /// A userspace process, by PID.
+    User(u32),
+}
+
+impl fmt::Display for ResourceOwner<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResourceOwner::Kernel(name) => write!(f, "[{}]", name),
+            ResourceOwner::User(pid) => write!(f, "pid {}", pid),
+        }
+    }
+}
+
+/// The restrack record embedded in a verbs object, wraps `struct rdma_restrack_entry`.
+///
+/// # Invariants
+///
+/// The wrapped entry is embedded in a live verbs object of the type it records.
+#[repr(transparent)]
+pub struct ResourceEntry(UnsafeCell<bindings::rdma_restrack_entry>);
+
+impl ResourceEntry {
+    /// Creates a reference to a [`ResourceEntry`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the entry of a verbs object that is not destroyed for the lifetime of the
+    /// returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::rdma_restrack_entry) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `ResourceEntry` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct rdma_restrack_entry` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::rdma_restrack_entry {
+        self.0.get()
+    }
+
+    /// Returns `true` once ib_core added the object to the database, i.e. after its create
+    /// callback returned, and until it starts destroying it.
+    pub fn is_tracked(&self) -> bool {
+        // SAFETY: By the type invariants the entry is valid.
+        unsafe { (*self.as_ptr()).valid }
+    }
+
+    /// Returns the ID of the object among those of its kind on the device.
+    pub fn id(&self) -> u32 {
+        // SAFETY: By the type invariants the entry is valid.
+        unsafe { (*self.as_ptr()).id }
+    }
+
+    /// Returns `true` if a kernel consumer created the object.
+    pub fn is_kernel(&self) -> bool {
+        // SAFETY: By the type invariants the entry is valid.
+        unsafe { bindings::rdma_is_kernel_res(self.as_ptr()) }
+    }
+
+    /// Returns who created the object.
+    ///
+    /// Kernel objects whose creator did not give its name are owned by an empty name.
+    pub fn owner(&self) -> ResourceOwner<'_> {
+        let res = self.as_ptr();
+        if self.is_kernel() {
+            // SAFETY: By the type invariants the entry is valid; `kern_name` points to the static
+            // `KBUILD_MODNAME` of the creator, or is null.
+            let name = unsafe { (*res).kern_name };
+            if name.is_null() {
+                return ResourceOwner::Kernel(crate::c_str!(""));
+            }
+            // SAFETY: `name` is a non-null, NUL-terminated string that outlives the entry.
+            return ResourceOwner::Kernel(unsafe { CStr::from_char_ptr(name) });
+        }
+        // SAFETY: By the type invariants the entry is valid, and user entries hold a reference
+        // on their task until the entry is deleted.
+        let pid = unsafe { bindings::task_pid_vnr((*res).task) };
+        ResourceOwner::User(pid as u32)
+    }
+
+    /// Returns the PID of the creator, 0 for kernel consumers, as reported by
+    /// [`crate::rdma::tracker::LiveResource::owner`].
+    pub fn owner_pid(&self) -> u32 {
+        match self.owner() {
+            ResourceOwner::Kernel(_) => 0,
+            ResourceOwner::User(pid) => pid,
+        }
+    }
+}
+
+/// A reference on a tracked object, found by [`crate::ib::DeviceRef::find_resource`].
+///
+/// # Invariants
+///
+/// `ptr` is the entry of a tracked object on which the instance holds a reference.
+pub struct ResourceRef {
+    ptr: *mut bindings::rdma_restrack_entry,
+}
+
+// SAFETY: The reference may be dropped from any thread.
+unsafe impl Send for ResourceRef {}
+// SAFETY: The entry is only read.
+unsafe impl Sync for ResourceRef {}
+
+impl ResourceRef {
+    /// Takes over the reference returned by `rdma_restrack_get_byid`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a tracked entry on which the caller holds a reference.
+    pub(crate) unsafe fn from_raw(ptr: *mut bindings::rdma_restrack_entry) -> Self {
+        // INVARIANT: Guaranteed by the safety requirements.
+        Self { ptr }
+    }
+}
+
+impl core::ops::Deref for ResourceRef {
+    type Target = ResourceEntry;
+
+    fn deref(&self) -> &ResourceEntry {
+        // SAFETY: By the type invariants the reference keeps the object alive.
+        unsafe { ResourceEntry::from_ptr(self.ptr) }
+    }
+}
+
+impl Drop for ResourceRef {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants we hold a reference, which destroying the object waits
+        // for.
+        unsafe { bindings::rdma_restrack_put(self.ptr) };
+    }
+}
@@ -0,0 +1,435 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Subnet administrator queries.
+//!
+//! C header: [`include/rdma/ib_sa.h`](../../../../include/rdma/ib_sa.h)
+//!
+//! Connections over InfiniBand link layer ports, such as those of mlx4 in IB mode, need a path
+//! record from the SA before a REQ can be sent, and UD multicast needs the group to be joined
+//! through it. The queries go through an [`SaClient`], which the ULP registers once, and complete
+//! asynchronously: the callback runs in process context once the SA answered, or the query timed
+//! out or was canceled. RoCE ports have no SA; rdma_cm resolves their paths locally.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::pin::Pin;
+use core::{ptr, slice};
+
+use super::observer::ObservedDevice;
+use crate::bindings;
+use crate::error::{from_kernel_err_ptr, Error, Result};
+use crate::ib::compat::try_pin;
+use crate::rdma::gid::Gid;
+use crate::rdma::sa::SaCompMask;
+
+fn to_result(status: core::ffi::c_int) -> Result {
+    if status < 0 {
+        return Err(Error::from_kernel_errno(status));
+    }
+    Ok(())
+}
+
+/// A path record, `struct sa_path_rec`.
+#[repr(transparent)]
+pub struct PathRecord(bindings::sa_path_rec);
+
+impl PathRecord {
+    /// Returns the raw record, e.g. for [`crate::ib::cm::ReqParams::new`].
+    pub fn as_raw(&self) -> &bindings::sa_path_rec {
+        &self.0
+    }
+
+    /// Returns the destination GID.
+    pub fn dgid(&self) -> Gid {
+        // SAFETY: Every bit pattern of the union is a valid GID.
+        Gid::from_raw(unsafe { self.0.dgid.raw })
+    }
+
+    /// Returns the source GID.
+    pub fn sgid(&self) -> Gid {
+        // SAFETY: Every bit pattern of the union is a valid GID.
+        Gid::from_raw(unsafe { self.0.sgid.raw })
+    }
+
+    /// Returns the destination LID.
+    pub fn dlid(&self) -> u32 {
+        // SAFETY: The record is valid; the helper reads the LID of its type.
+        u32::from_be(unsafe { bindings::sa_path_get_dlid(&self.0 as *const _ as *mut _) })
+    }
+
+    /// Returns the source LID.
+    pub fn slid(&self) -> u32 {
+        // SAFETY: The record is valid; the helper reads the LID of its type.
+        u32::from_be(unsafe { bindings::sa_path_get_slid(&self.0 as *const _ as *mut _) })
+    }
+
+    /// Returns the P_Key of the path.
+    pub fn pkey(&self) -> u16 {
+        u16::from_be(self.0.pkey)
+    }
+
+    /// Returns the service level of the path.
+    pub fn sl(&self) -> u8 {
+        self.0.sl
+    }
+
+    /// Returns the MTU of the path, `enum ib_mtu`.
+    pub fn mtu(&self) -> u8 {
+        self.0.mtu
+    }
+
+    /// Returns the static rate of the path, `enum ib_rate`.
+    pub fn rate(&self) -> u8 {
+        self.0.rate
+    }
+
+    /// Returns the packet lifetime exponent of the path.
+    pub fn packet_life_time(&self) -> u8 {
+        self.0.packet_life_time
+    }
+}
+
+/// A path record query, see [`SaClient::path_rec_get`].
+pub struct PathQuery {
+    rec: bindings::sa_path_rec,
+    mask: SaCompMask,
+}
+
+impl PathQuery {
+    /// Returns a query for one reversible path from `sgid` to `dgid` in the partition of `pkey`.
+    pub fn new(sgid: Gid, dgid: Gid, pkey: u16) -> Self {
+        let mut rec = bindings::sa_path_rec {
+            rec_type: bindings::sa_path_rec_type_SA_PATH_REC_TYPE_IB,
+            pkey: pkey.to_be(),
+            reversible: 1u32.to_be(),
+            numb_path: 1,
+            ..Default::default()
+        };
+        rec.sgid.raw = sgid.raw();
+        rec.dgid.raw = dgid.raw();
+        Self {
+            rec,
+            mask: SaCompMask::PATH_SGID
+                | SaCompMask::PATH_DGID
+                | SaCompMask::PATH_PKEY
+                | SaCompMask::PATH_REVERSIBLE
+                | SaCompMask::PATH_NUMB_PATH,
+        }
+    }
+
+    /// Restricts the query to the paths of `service_id`, whose QoS policy the SA applies.
+    pub fn with_service_id(mut self, service_id: u64) -> Self {
+        self.rec.service_id = service_id.to_be();
+        self.mask = self.mask | SaCompMask::PATH_SERVICE_ID;
+        self
+    }
+
+    /// Restricts the query to the paths of service level `sl`.
+    pub fn with_sl(mut self, sl: u8) -> Self {
+        self.rec.sl = sl;
+        self.mask = self.mask | SaCompMask::PATH_SL;
+        self
+    }
+
+    /// Asks for up to `paths` paths instead of one.
+    pub fn with_numb_path(mut self, paths: u8) -> Self {
+        self.rec.numb_path = paths;
+        self
+    }
+}
+
+/// A multicast member record, `struct ib_sa_mcmember_rec`.
+#[repr(transparent)]
+pub struct McMemberRecord(bindings::ib_sa_mcmember_rec);
+
+impl McMemberRecord {
+    /// Returns the raw record.
+    pub fn as_raw(&self) -> &bindings::ib_sa_mcmember_rec {
+        &self.0
+    }
+
+    /// Returns the multicast GID of the group.
+    pub fn mgid(&self) -> Gid {
+        // SAFETY: Every bit pattern of the union is a valid GID.
+        Gid::from_raw(unsafe { self.0.mgid.raw })
+    }
+
+    /// Returns the multicast LID of the group.
+    pub fn mlid(&self) -> u16 {
+        u16::from_be(self.0.mlid)
+    }
+
+    /// Returns the Q_Key of the group.
+    pub fn qkey(&self) -> u32 {
+        u32::from_be(self.0.qkey)
+    }
+
+    /// Returns the P_Key of the group.
+    pub fn pkey(&self) -> u16 {
+        u16::from_be(self.0.pkey)
+    }
+
+    /// Returns the MTU of the group, `enum ib_mtu`.
+    pub fn mtu(&self) -> u8 {
+        self.0.mtu
+    }
+
+    /// Returns the static rate of the group, `enum ib_rate`.
+    pub fn rate(&self) -> u8 {
+        self.0.rate
+    }
+
+    /// Returns the service level of the group.
+    pub fn sl(&self) -> u8 {
+        self.0.sl
+    }
+
+    /// Returns the join state of the member, see [`crate::rdma::mcg::JOIN_FULL`].
+    pub fn join_state(&self) -> u8 {
+        self.0.join_state
+    }
+}
+
+/// A multicast join, see [`SaClient::join_multicast`].
+pub struct McMemberQuery {
+    rec: bindings::ib_sa_mcmember_rec,
+    mask: SaCompMask,
+}
+
+impl McMemberQuery {
+    /// Returns a join of the port of `port_gid` to the existing group `mgid` in the partition of
+    /// `pkey`, with the join states of `join_state`.
+    pub fn new(mgid: Gid, port_gid: Gid, pkey: u16, join_state: u8) -> Self {
+        let mut rec = bindings::ib_sa_mcmember_rec {
+            pkey: pkey.to_be(),
+            join_state,
+            ..Default::default()
+        };
+        rec.mgid.raw = mgid.raw();
+        rec.port_gid.raw = port_gid.raw();
+        Self {
+            rec,
+            mask: SaCompMask::MCMEMBER_JOIN,
+        }
+    }
+
+    /// Sets the Q_Key, which creates the group if it does not exist yet.
+    pub fn with_qkey(mut self, qkey: u32) -> Self {
+        self.rec.qkey = qkey.to_be();
+        self.mask = self.mask | SaCompMask::MCMEMBER_QKEY;
+        self
+    }
+
+    /// Sets the service level, traffic class and flow label of a group to create.
+    pub fn with_qos(mut self, sl: u8, traffic_class: u8, flow_label: u32) -> Self {
+        self.rec.sl = sl;
+        self.rec.traffic_class = traffic_class;
+        self.rec.flow_label = flow_label.to_be();
+        self.mask = self.mask
+            | SaCompMask::MCMEMBER_SL
+            | SaCompMask::MCMEMBER_TRAFFIC_CLASS
+            | SaCompMask::MCMEMBER_FLOW_LABEL;
+        self
+    }
+}
+
+/// An SA query in flight, returned by [`SaClient::path_rec_get`].
+pub struct SaQuery {
+    id: core::ffi::c_int,
+    query: *mut bindings::ib_sa_query,
+}
+
+impl SaQuery {
+    /// Returns the ID of the query.
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// Cancels the query; its callback runs with an error if it did not run yet.
+    ///
+    /// # Safety
+    ///
+    /// The callback of the query must not have returned yet, as the query is freed once it did.
+    pub unsafe fn cancel(self) {
+        // SAFETY: Guaranteed by the safety requirements.
+        unsafe { bindings::ib_sa_cancel_query(self.id, self.query) };
+    }
+}
+
+/// A multicast group joined through the SA, left on drop.
+///
+/// # Invariants
+///
+/// `ptr` is a live join whose context is `callback`, owned by the instance.
+pub struct Multicast<F: Fn(Result<&McMemberRecord>) + Send + Sync + 'static> {
+    ptr: *mut bindings::ib_sa_multicast,
+    callback: Pin<Box<F>>,
+}
+
+// SAFETY: The join may be left from any thread, and the callback is `Send`.
+unsafe impl<F: Fn(Result<&McMemberRecord>) + Send + Sync + 'static> Send for Multicast<F> {}
+// SAFETY: The instance only hands out the callback, which is `Sync`.
+unsafe impl<F: Fn(Result<&McMemberRecord>) + Send + Sync + 'static> Sync for Multicast<F> {}
+
+impl<F: Fn(Result<&McMemberRecord>) + Send + Sync + 'static> Multicast<F> {
+    /// Returns the callback of the join.
+    pub fn callback(&self) -> Pin<&F> {
+        self.callback.as_ref()
+    }
+}
+
+impl<F: Fn(Result<&McMemberRecord>) + Send + Sync + 'static> Drop for Multicast<F> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants we own the join. Freeing it waits for its callback in
+        // progress and leaves the group if it was joined.
+        unsafe { bindings::ib_sa_free_multicast(self.ptr) };
+    }
+}
+
+/// A registered SA client, wraps `struct ib_sa_client`.
+///
+/// # Invariants
+///
+/// The client is registered while the instance lives, and does not move.
+pub struct SaClient {
+    raw: UnsafeCell<bindings::ib_sa_client>,
+}
+
+// SAFETY: The client is only handed to the SA, which synchronises its accesses.
+unsafe impl Send for SaClient {}
+// SAFETY: As above.
+unsafe impl Sync for SaClient {}
+
+impl SaClient {
+    /// Registers a client.
+    pub fn new() -> Result<Pin<Box<Self>>> {
+        let client = try_pin(Self {
+            raw: UnsafeCell::new(bindings::ib_sa_client::default()),
+        })?;
+        // SAFETY: The client is pinned and unregistered in `drop`.
+        unsafe { bindings::ib_sa_register_client(client.raw.get()) };
+        // INVARIANT: The client was just registered.
+        Ok(client)
+    }
+
+    /// Queries the paths of `query` on `port` of `dev`. `callback` gets the paths found, or the
+    /// error of the query, e.g. `ETIMEDOUT` once `timeout_ms` elapsed without answer.
+    pub fn path_rec_get<F>(
+        &self,
+        dev: &ObservedDevice,
+        port: u32,
+        query: &PathQuery,
+        timeout_ms: u32,
+        callback: F,
+    ) -> Result<SaQuery>
+    where
+        F: FnOnce(Result<&[PathRecord]>) + Send + 'static,
+    {
+        let context = Box::into_raw(Box::try_new(callback)?);
+        let mut rec = query.rec;
+        let mut sa_query = ptr::null_mut();
+        // SAFETY: The client is registered, the device is valid and the record outlives the
+        // call, which copies it. The callback box is reclaimed by `path_callback`.
+        let id = unsafe {
+            bindings::ib_sa_path_rec_get(
+                self.raw.get(),
+                dev.as_ptr(),
+                port,
+                &mut rec,
+                query.mask.to_be(),
+                timeout_ms as _,
+                bindings::GFP_KERNEL,
+                Some(path_callback::<F>),
+                context.cast(),
+                &mut sa_query,
+            )
+        };
+        if id < 0 {
+            // SAFETY: The query was not sent, so the callback will not run.
+            drop(unsafe { Box::from_raw(context) });
+            return Err(Error::from_kernel_errno(id));
+        }
+        Ok(SaQuery {
+            id,
+            query: sa_query,
+        })
+    }
+
+    /// Joins `port` of `dev` to the group of `query`. `callback` gets the record of the group once
+    /// joined, or the error of the join; it is called again with an error if the group is lost,
+    /// e.g. when the port goes down, and with the new record once it is joined again.
+    pub fn join_multicast<F>(
+        &self,
+        dev: &ObservedDevice,
+        port: u32,
+        query: &McMemberQuery,
+        callback: F,
+    ) -> Result<Multicast<F>>
+    where
+        F: Fn(Result<&McMemberRecord>) + Send + Sync + 'static,
+    {
+        let callback = try_pin(callback)?;
+        let context = &*callback as *const F as *mut core::ffi::c_void;
+        let mut rec = query.rec;
+        // SAFETY: The client is registered, the device is valid and the record outlives the
+        // call, which copies it. The callback outlives the join, which `Multicast` frees.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::ib_sa_join_multicast(
+                self.raw.get(),
+                dev.as_ptr(),
+                port,
+                &mut rec,
+                query.mask.to_be(),
+                bindings::GFP_KERNEL,
+                Some(multicast_callback::<F>),
+                context,
+            )
+        })?;
+        // INVARIANT: The join was just created with `callback` as context.
+        Ok(Multicast { ptr, callback })
+    }
+}
+
+impl Drop for SaClient {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the client is registered. Unregistering it waits for the
+        // callbacks of its queries.
+        unsafe { bindings::ib_sa_unregister_client(self.raw.get()) };
+    }
+}
+
+unsafe extern "C" fn path_callback<F: FnOnce(Result<&[PathRecord]>) + Send + 'static>(
+    ret: core::ffi::c_int,
+    resp: *mut bindings::sa_path_rec,
+    num_prs: core::ffi::c_uint,
+    context: *mut core::ffi::c_void,
+) {
+    // SAFETY: The context is the callback boxed by `path_rec_get`, and the SA calls us once.
+    let callback = unsafe { Box::from_raw(context.cast::<F>()) };
+    let paths = to_result(ret).map(|()| {
+        if resp.is_null() {
+            return &[][..];
+        }
+        // SAFETY: On success the SA passes `num_prs` records, which live during the call, and
+        // `PathRecord` is transparent.
+        unsafe { slice::from_raw_parts(resp.cast::<PathRecord>(), num_prs as usize) }
+    });
+    callback(paths);
+}
+
+unsafe extern "C" fn multicast_callback<F: Fn(Result<&McMemberRecord>) + Send + Sync + 'static>(
+    ret: core::ffi::c_int,
+    multicast: *mut bindings::ib_sa_multicast,
+) -> core::ffi::c_int {
+    // SAFETY: The SA passes a live join, whose context is the callback of the `Multicast`
+    // owning it, and `McMemberRecord` is transparent.
+    let (callback, rec) = unsafe {
+        (
+            &*(*multicast).context.cast::<F>(),
+            &*ptr::addr_of!((*multicast).rec).cast::<McMemberRecord>(),
+        )
+    };
+    callback(to_result(ret).map(|()| rec));
+    // The `Multicast` frees the join.
+    0
+}
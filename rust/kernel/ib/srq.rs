@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Shared receive queues.
+
+use core::cell::UnsafeCell;
+use core::marker;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::pd::ProtectionDomain;
+use super::restrack::ResourceEntry;
+use super::Object;
+use crate::bindings;
+
+/// Attributes of a shared receive queue, from `struct ib_srq_attr`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SrqAttr {
+    /// Number of outstanding receive work requests.
+    pub max_wr: u32,
+    /// Number of scatter/gather entries per work request.
+    pub max_sge: u32,
+    /// Limit below which the consumer wants an `IB_EVENT_SRQ_LIMIT_REACHED` event, 0 for none.
+    pub srq_limit: u32,
+}
+
+impl SrqAttr {
+    pub(crate) fn from_raw(raw: &bindings::ib_srq_attr) -> Self {
+        Self {
+            max_wr: raw.max_wr,
+            max_sge: raw.max_sge,
+            srq_limit: raw.srq_limit,
+        }
+    }
+
+    pub(crate) fn write_to(&self, raw: &mut bindings::ib_srq_attr) {
+        raw.max_wr = self.max_wr;
+        raw.max_sge = self.max_sge;
+        raw.srq_limit = self.srq_limit;
+    }
+}
+
+/// Attributes changed by a `modify_srq` call, from `enum ib_srq_attr_mask`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SrqAttrMask(u32);
+
+impl SrqAttrMask {
+    /// Resize the queue to `max_wr` entries.
+    pub const MAX_WR: Self = Self(bindings::ib_srq_attr_mask_IB_SRQ_MAX_WR as u32);
+    /// Arm the limit event at `srq_limit`.
+    pub const LIMIT: Self = Self(bindings::ib_srq_attr_mask_IB_SRQ_LIMIT as u32);
+
+    /// Creates a mask from its raw value.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns `true` if every attribute of `other` is present.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// Provider data of an SRQ together with the limit tracked by the abstraction.
+pub(crate) struct SrqObject<D> {
+    pub(crate) limit: AtomicU32,
+    pub(crate) data: D,
+}
+
+impl<D> SrqObject<D> {
+    pub(crate) fn new(data: D) -> Self {
+        Self {
+            limit: AtomicU32::new(0),
+            data,
+        }
+    }
+}
+
+/// A shared receive queue of a device provided by `T`, wraps `struct ib_srq`.
+///
+/// The abstraction keeps the armed limit: `modify_srq` arms it once the provider accepted the
+/// change, `query_srq` reports it, and [`SharedReceiveQueue::check_limit`] fires the event.
+///
+/// # Invariants
+///
+/// The wrapped `struct ib_srq` belongs to a device provided by `T` and is followed by an
+/// initialised `SrqObject<T::SrqData>`.
+#[repr(transparent)]
+pub struct SharedReceiveQueue<T: IbDeviceOperations>(
+    UnsafeCell<bindings::ib_srq>,
+    marker::PhantomData<T>,
+);
+
+impl<T: IbDeviceOperations> SharedReceiveQueue<T> {
+    /// Creates a reference to a [`SharedReceiveQueue`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an SRQ of a device provided by `T` whose `create_srq` succeeded and which is
+    /// not destroyed for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_srq) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `SharedReceiveQueue` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_srq` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_srq {
+        self.0.get()
+    }
+
+    /// Returns the restrack record of the SRQ, with its ID and creator.
+    pub fn restrack(&self) -> &ResourceEntry {
+        // SAFETY: The SRQ is valid and embeds its entry.
+        unsafe { ResourceEntry::from_ptr(ptr::addr_of_mut!((*self.as_ptr()).res)) }
+    }
+
+    fn object(&self) -> &SrqObject<T::SrqData> {
+        // SAFETY: By the type invariants the object is initialised.
+        unsafe { Object::<bindings::ib_srq, SrqObject<T::SrqData>>::from_raw(self.as_ptr()).data() }
+    }
+
+    /// Returns the device the SRQ belongs to.
+    pub fn device(&self) -> &DeviceRef<T> {
+        // SAFETY: By the type invariants the SRQ belongs to a device provided by `T`, which
+        // outlives all of its SRQs.
+        unsafe { DeviceRef::from_ptr((*self.as_ptr()).device) }
+    }
+
+    /// Returns the protection domain of the SRQ.
+    pub fn pd(&self) -> &ProtectionDomain<T> {
+        // SAFETY: ib_core sets the PD before calling `create_srq`, and it outlives the SRQ.
+        unsafe { ProtectionDomain::from_ptr((*self.as_ptr()).pd) }
+    }
+
+    /// Returns the provider data of the SRQ.
+    pub fn data(&self) -> &T::SrqData {
+        &self.object().data
+    }
+
+    /// Returns the armed limit, 0 if the limit event is not armed.
+    pub fn limit(&self) -> u32 {
+        self.object().limit.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn set_limit(&self, limit: u32) {
+        self.object().limit.store(limit, Ordering::Release);
+    }
+
+    /// Tells the consumer the queue runs low, to be called after a work request is consumed.
+    ///
+    /// If the limit is armed and fewer than `limit` work requests are left in the queue, the limit
+    /// is disarmed and `IB_EVENT_SRQ_LIMIT_REACHED` is delivered, as the IBTA requires.
+    pub fn check_limit(&self, available: u32) {
+        let limit = self.limit();
+        if limit == 0 || available >= limit {
+            return;
+        }
+        if self
+            .object()
+            .limit
+            .compare_exchange(limit, 0, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return;
+        }
+        // SAFETY: The SRQ is valid; the handler and its context are set by ib_core at creation
+        // and stay valid until the SRQ is destroyed.
+        unsafe {
+            let srq = self.as_ptr();
+            if let Some(handler) = (*srq).event_handler {
+                let mut event = bindings::ib_event::default();
+                event.device = (*srq).device;
+                event.event = bindings::ib_event_type_IB_EVENT_SRQ_LIMIT_REACHED;
+                event.element.srq = srq;
+                handler(&mut event, (*srq).srq_context);
+            }
+        }
+    }
+}
@@ -0,0 +1,228 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Provider sysfs attributes of InfiniBand devices.
+//!
+//! Besides the attributes ib_core creates under `/sys/class/infiniband/<dev>`, C drivers expose
+//! their own, e.g. `board_id` or `hw_rev`, with one attribute group passed to
+//! `rdma_set_device_sysfs_group` before registration. An [`AttributeGroup`] is that group for a
+//! Rust provider: a `static` listing [`DeviceAttribute`]s, each with a typed `show` function and
+//! optionally a `store` function, attached with [`super::Device::set_sysfs_group`].
+//!
+//! The group is static, so it outlives every device using it, and only turned into the C
+//! structures the first time it is attached.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::rdma::init_once::InitOnce;
+use crate::str::CStr;
+
+/// Writes the value of an attribute, e.g. with `writeln!`.
+pub type ShowFn<T> = fn(&DeviceRef<T>, &mut dyn fmt::Write) -> fmt::Result;
+
+/// Parses and applies a value written to an attribute, as sent by userspace.
+pub type StoreFn<T> = fn(&DeviceRef<T>, &str) -> Result;
+
+/// A sysfs attribute of the devices of `T`, wraps `struct device_attribute`.
+///
+/// # Invariants
+///
+/// `raw` is initialised once the group holding the attribute was attached to a device, and never
+/// changes afterwards.
+#[repr(C)]
+pub struct DeviceAttribute<T: IbDeviceOperations> {
+    raw: UnsafeCell<MaybeUninit<bindings::device_attribute>>,
+    name: &'static CStr,
+    mode: u16,
+    show: ShowFn<T>,
+    store: Option<StoreFn<T>>,
+}
+
+impl<T: IbDeviceOperations> DeviceAttribute<T> {
+    /// Creates a read-only attribute `name`, readable by everyone.
+    pub const fn new(name: &'static CStr, show: ShowFn<T>) -> Self {
+        Self {
+            raw: UnsafeCell::new(MaybeUninit::uninit()),
+            name,
+            mode: 0o444,
+            show,
+            store: None,
+        }
+    }
+
+    /// Makes the attribute writable by root, with `store` handling the writes.
+    pub const fn with_store(mut self, store: StoreFn<T>) -> Self {
+        self.store = Some(store);
+        self.mode |= 0o200;
+        self
+    }
+
+    /// Sets the permissions of the attribute, e.g. 0o400 for values only root may read.
+    pub const fn with_mode(mut self, mode: u16) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    fn init(&self) {
+        let mut raw = bindings::device_attribute::default();
+        raw.attr.name = self.name.as_char_ptr();
+        raw.attr.mode = self.mode as _;
+        raw.show = Some(Self::show_callback);
+        if self.store.is_some() {
+            raw.store = Some(Self::store_callback);
+        }
+        // SAFETY: Only the group's `InitOnce` initialiser writes the attribute, before sysfs can
+        // see it.
+        unsafe { (*self.raw.get()).write(raw) };
+    }
+
+    fn as_raw(&self) -> *mut bindings::attribute {
+        // SAFETY: `attr` is a field of the attribute, no reference is created.
+        unsafe { ptr::addr_of_mut!((*self.raw.get().cast::<bindings::device_attribute>()).attr) }
+    }
+
+    /// # Safety
+    ///
+    /// `dev` must be the device of an ib device of `T`, and `attr` one of its attributes.
+    unsafe fn get<'a>(
+        dev: *mut bindings::device,
+        attr: *mut bindings::device_attribute,
+    ) -> (&'a DeviceRef<T>, &'a Self) {
+        // SAFETY: Per the safety requirements the device is embedded in an ib device of `T`,
+        // which sysfs keeps alive during the callbacks. `raw` is the first field of the
+        // `repr(C)` attribute, and `UnsafeCell<MaybeUninit<_>>` has the layout of its content.
+        unsafe {
+            (
+                DeviceRef::from_ptr(bindings::rdma_device_to_ibdev(dev)),
+                &*attr.cast::<Self>(),
+            )
+        }
+    }
+
+    unsafe extern "C" fn show_callback(
+        dev: *mut bindings::device,
+        attr: *mut bindings::device_attribute,
+        buf: *mut core::ffi::c_char,
+    ) -> isize {
+        // SAFETY: sysfs only calls us for the attributes of our group, attached to our devices.
+        let (dev, attr) = unsafe { Self::get(dev, attr) };
+        // SAFETY: sysfs passes a buffer of one page, which nothing else accesses meanwhile.
+        let buf =
+            unsafe { core::slice::from_raw_parts_mut(buf.cast(), bindings::PAGE_SIZE as usize) };
+        let mut w = PageWriter { buf, len: 0 };
+        // Values longer than a page are truncated, like with `sysfs_emit`.
+        let _ = (attr.show)(dev, &mut w);
+        w.len as isize
+    }
+
+    unsafe extern "C" fn store_callback(
+        dev: *mut bindings::device,
+        attr: *mut bindings::device_attribute,
+        buf: *const core::ffi::c_char,
+        count: usize,
+    ) -> isize {
+        // SAFETY: sysfs only calls us for the attributes of our group, attached to our devices.
+        let (dev, attr) = unsafe { Self::get(dev, attr) };
+        // SAFETY: sysfs passes the `count` bytes written, in a buffer it owns during the call.
+        let buf = unsafe { core::slice::from_raw_parts(buf.cast::<u8>(), count) };
+        let store = match attr.store {
+            Some(store) => store,
+            None => return EACCES.to_kernel_errno() as isize,
+        };
+        let ret = core::str::from_utf8(buf)
+            .map_err(|_| EINVAL)
+            .and_then(|text| store(dev, text));
+        match ret {
+            Ok(()) => count as isize,
+            Err(e) => e.to_kernel_errno() as isize,
+        }
+    }
+}
+
+/// Writes into the page of a `show` callback, dropping what does not fit.
+struct PageWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for PageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.buf.len() - self.len;
+        let n = s.len().min(room);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// The NULL-terminated attribute array of a group.
+#[repr(C)]
+struct AttrPtrs<const N: usize> {
+    ptrs: [*mut bindings::attribute; N],
+    end: *mut bindings::attribute,
+}
+
+/// A group of `N` sysfs attributes of the devices of `T`, wraps `struct attribute_group`.
+///
+/// # Invariants
+///
+/// `ptrs` and `raw` are initialised once `init` is done, and never change afterwards.
+pub struct AttributeGroup<T: IbDeviceOperations, const N: usize> {
+    name: Option<&'static CStr>,
+    attrs: [DeviceAttribute<T>; N],
+    ptrs: UnsafeCell<AttrPtrs<N>>,
+    raw: UnsafeCell<MaybeUninit<bindings::attribute_group>>,
+    init: InitOnce,
+}
+
+// SAFETY: The C structures are only written once, by the `InitOnce` initialiser, before they are
+// shared with sysfs; the callbacks are plain functions.
+unsafe impl<T: IbDeviceOperations, const N: usize> Sync for AttributeGroup<T, N> {}
+
+impl<T: IbDeviceOperations, const N: usize> AttributeGroup<T, N> {
+    /// Creates a group of `attrs`, in a subdirectory `name` of the device if it is given.
+    pub const fn new(name: Option<&'static CStr>, attrs: [DeviceAttribute<T>; N]) -> Self {
+        Self {
+            name,
+            attrs,
+            ptrs: UnsafeCell::new(AttrPtrs {
+                ptrs: [ptr::null_mut(); N],
+                end: ptr::null_mut(),
+            }),
+            raw: UnsafeCell::new(MaybeUninit::uninit()),
+            init: InitOnce::new(),
+        }
+    }
+
+    /// Returns the `struct attribute_group`, building it on first use.
+    pub(crate) fn as_raw(&'static self) -> *const bindings::attribute_group {
+        let _ = self.init.call(
+            || -> Result {
+                // SAFETY: Only this initialiser writes `ptrs` and `raw`, before anybody reads
+                // them.
+                let ptrs = unsafe { &mut *self.ptrs.get() };
+                for (ptr, attr) in ptrs.ptrs.iter_mut().zip(&self.attrs) {
+                    attr.init();
+                    *ptr = attr.as_raw();
+                }
+                let mut raw = bindings::attribute_group::default();
+                raw.name = self.name.map_or(ptr::null(), |name| name.as_char_ptr());
+                raw.attrs = ptrs.ptrs.as_mut_ptr();
+                // SAFETY: As above.
+                unsafe { (*self.raw.get()).write(raw) };
+                Ok(())
+            },
+            core::hint::spin_loop,
+        );
+        // INVARIANT: The initialiser cannot fail, so the group is initialised now.
+        self.raw.get().cast()
+    }
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Static tracepoints.
+//!
+//! The events are defined by `include/trace/events/rust_rdma.h`, like those of the C drivers, so
+//! they show up under `events/rust_rdma` in tracefs and can be recorded with ftrace or
+//! `perf record -e rust_rdma:*`. [`declare_trace!`] binds an event to Rust through the helpers
+//! created with it: a module with an `emit` function firing the event, and an `enabled`
+//! function for callers that only want to decode a packet when someone is listening.
+//!
+//! [`declare_trace!`]: crate::declare_trace
+
+/// Declares tracepoints, e.g.
+///
+/// ```ignore
+/// kernel::declare_trace! {
+///     /// A packet was handed to the IP layer.
+///     pub rxe_send_pkt(ifindex: i32, qpn: u32, opcode: u8, psn: u32, len: u32)
+///         => trace_rxe_send_pkt, trace_rxe_send_pkt_enabled;
+/// }
+/// ```
+///
+/// declares `rxe_send_pkt::emit` and `rxe_send_pkt::enabled` over the `bindings` functions
+/// named after the arrow, which `rust/helpers_rdma.c` defines around `trace_rxe_send_pkt()` and
+/// `trace_rxe_send_pkt_enabled()`.
+#[macro_export]
+macro_rules! declare_trace {
+    ($(
+        $(#[$meta:meta])*
+        $vis:vis $name:ident($($arg:ident: $ty:ty),* $(,)?) => $emit:ident, $enabled:ident;
+    )*) => {$(
+        $(#[$meta])*
+        $vis mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            /// Returns `true` if the event is enabled, e.g. through tracefs.
+            #[inline]
+            pub fn enabled() -> bool {
+                // SAFETY: FFI call without preconditions.
+                unsafe { $crate::bindings::$enabled() }
+            }
+
+            /// Fires the event, which costs a call and a static branch while it is disabled.
+            #[inline]
+            pub fn emit($($arg: $ty),*) {
+                // SAFETY: Events take plain values and references, which are valid for the
+                // duration of the call, and may fire in any context.
+                unsafe { $crate::bindings::$emit($($arg),*) }
+            }
+        }
+    )*};
+}
+
+crate::declare_trace! {
+    /// A QP moved to another state, through `modify_qp` or on the provider's initiative.
+    pub(crate) qp_state_change(
+        qp: &crate::bindings::ib_qp,
+        old_state: i32,
+        new_state: i32,
+    ) => trace_qp_state_change, trace_qp_state_change_enabled;
+}
@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Pinned userspace memory.
+//!
+//! C header: [`include/rdma/ib_umem.h`](../../../../include/rdma/ib_umem.h)
+//!
+//! Hardware providers hand the DMA addresses of a [`Umem`] to the device, in blocks of the
+//! largest size it supports, see [`Umem::find_best_pgsz`] and [`Umem::dma_blocks`]. Soft
+//! providers access the memory with the CPU instead, through the pages of [`Umem::sg_pages`].
+
+use core::{marker, ptr};
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Result};
+
+/// A range of userspace memory pinned for DMA, wraps `struct ib_umem`.
+///
+/// # Invariants
+///
+/// `ptr` was returned by a successful `ib_umem_get` and is released on drop.
+pub struct Umem {
+    ptr: *mut bindings::ib_umem,
+}
+
+impl Umem {
+    /// Pins `length` bytes of the current process starting at `addr` for DMA by `dev`.
+    ///
+    /// `access` holds the `IB_ACCESS_*` flags of the registration; pages are pinned writable when
+    /// any write access is requested.
+    pub fn get<T: IbDeviceOperations>(
+        dev: &DeviceRef<T>,
+        addr: u64,
+        length: u64,
+        access: u32,
+    ) -> Result<Self> {
+        // SAFETY: The device is valid; `ib_umem_get` checks the range against the caller's
+        // address space.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::ib_umem_get(dev.as_ptr(), addr as _, length as _, access as _)
+        })?;
+        // INVARIANT: `ib_umem_get` succeeded.
+        Ok(Self { ptr })
+    }
+
+    /// Returns the raw `struct ib_umem` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_umem {
+        self.ptr
+    }
+
+    /// Returns the userspace address of the range.
+    pub fn address(&self) -> u64 {
+        // SAFETY: The umem is valid by the type invariants.
+        unsafe { (*self.ptr).address as u64 }
+    }
+
+    /// Returns the length of the range in bytes.
+    pub fn length(&self) -> u64 {
+        // SAFETY: The umem is valid by the type invariants.
+        unsafe { (*self.ptr).length as u64 }
+    }
+
+    /// Returns the device the range was mapped for, the DMA device of its `ib_device`.
+    pub fn dma_device(&self) -> *mut bindings::device {
+        // SAFETY: The umem is valid by the type invariants, and holds its `ib_device`.
+        unsafe { (*(*self.ptr).ibdev).dma_device }
+    }
+
+    /// Returns the offset of the range in its first page.
+    pub fn offset(&self) -> u64 {
+        // SAFETY: The umem is valid by the type invariants.
+        unsafe { bindings::ib_umem_offset(self.ptr) as u64 }
+    }
+
+    /// Returns `true` if the pages were pinned writable, i.e. the registration allows writes.
+    pub fn is_writable(&self) -> bool {
+        // SAFETY: The umem is valid by the type invariants.
+        unsafe { (*self.ptr).writable() != 0 }
+    }
+
+    /// Returns the largest block size of `pgsz_bitmap` the range can be mapped with at the device
+    /// address `virt`, or `None` if none of them fits, e.g. because `virt` and the range are
+    /// misaligned.
+    pub fn find_best_pgsz(&self, pgsz_bitmap: u64, virt: u64) -> Option<u64> {
+        // SAFETY: The umem is valid by the type invariants.
+        let pgsz = unsafe { bindings::ib_umem_find_best_pgsz(self.ptr, pgsz_bitmap as _, virt) };
+        if pgsz == 0 {
+            return None;
+        }
+        Some(pgsz as u64)
+    }
+
+    /// Returns the number of blocks of `pgsz` bytes covering the range.
+    pub fn num_dma_blocks(&self, pgsz: u64) -> usize {
+        // SAFETY: The umem is valid by the type invariants.
+        unsafe { bindings::ib_umem_num_dma_blocks(self.ptr, pgsz as _) as usize }
+    }
+
+    /// Returns an iterator over the DMA addresses of the blocks of `pgsz` bytes backing the range.
+    ///
+    /// `pgsz` must be a power of two no smaller than `PAGE_SIZE`.
+    pub fn dma_blocks(&self, pgsz: u64) -> DmaBlocks<'_> {
+        // SAFETY: The umem is valid by the type invariants, and pinned while `self` is borrowed.
+        unsafe { DmaBlocks::start(self.ptr, pgsz) }
+    }
+
+    /// Returns an iterator over the DMA addresses of the pages backing the range.
+    pub fn pages(&self) -> DmaBlocks<'_> {
+        self.dma_blocks(bindings::PAGE_SIZE as u64)
+    }
+
+    /// Returns an iterator over the pages backing the range, walking its scatter-gather list.
+    ///
+    /// The range starts at [`Umem::offset`] in the first page and ends within the last one.
+    pub fn sg_pages(&self) -> SgPages<'_> {
+        let mut iter = bindings::sg_page_iter::default();
+        // SAFETY: The umem is valid by the type invariants, and its table lists the pinned pages
+        // until it is released.
+        unsafe {
+            let sgt = &(*self.ptr).sgt_append.sgt;
+            bindings::__sg_page_iter_start(&mut iter, sgt.sgl, sgt.orig_nents, 0);
+        }
+        SgPages {
+            iter,
+            writable: self.is_writable(),
+            phantom: marker::PhantomData,
+        }
+    }
+}
+
+impl Drop for Umem {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the umem is still pinned.
+        unsafe { bindings::ib_umem_release(self.ptr) };
+    }
+}
+
+// SAFETY: A pinned range can be released from any thread.
+unsafe impl Send for Umem {}
+
+// SAFETY: Shared references only read the immutable description of the range.
+unsafe impl Sync for Umem {}
+
+/// Iterator over the DMA blocks of a [`Umem`], see [`Umem::dma_blocks`], or of a mapped
+/// [`super::umem_dmabuf::UmemDmabuf`].
+pub struct DmaBlocks<'a> {
+    iter: bindings::ib_block_iter,
+    phantom: marker::PhantomData<&'a Umem>,
+}
+
+impl DmaBlocks<'_> {
+    /// Starts iterating over the blocks of `pgsz` bytes listed by the table of `umem`.
+    ///
+    /// # Safety
+    ///
+    /// `umem` must be valid, and its table must not change, for the lifetime of the iterator.
+    pub(super) unsafe fn start(umem: *mut bindings::ib_umem, pgsz: u64) -> Self {
+        let mut iter = bindings::ib_block_iter::default();
+        // SAFETY: Guaranteed by the safety requirements, and `iter` is a fresh iterator.
+        unsafe { bindings::rdma_umem_block_iter_start(&mut iter, umem, pgsz as _) };
+        DmaBlocks {
+            iter,
+            phantom: marker::PhantomData,
+        }
+    }
+}
+
+impl Iterator for DmaBlocks<'_> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        // SAFETY: The iterator was started on a umem that outlives `self`.
+        unsafe {
+            if !bindings::__rdma_block_iter_next(&mut self.iter) {
+                return None;
+            }
+            Some(bindings::rdma_block_iter_dma_address(&mut self.iter))
+        }
+    }
+}
+
+/// Iterator over the pages of a [`Umem`], see [`Umem::sg_pages`].
+pub struct SgPages<'a> {
+    iter: bindings::sg_page_iter,
+    writable: bool,
+    phantom: marker::PhantomData<&'a Umem>,
+}
+
+impl<'a> Iterator for SgPages<'a> {
+    type Item = UmemPage<'a>;
+
+    fn next(&mut self) -> Option<UmemPage<'a>> {
+        // SAFETY: The iterator was started on the table of a umem that outlives `self`.
+        unsafe {
+            if !bindings::__sg_page_iter_next(&mut self.iter) {
+                return None;
+            }
+            Some(UmemPage {
+                page: bindings::sg_page_iter_page(&mut self.iter),
+                writable: self.writable,
+                phantom: marker::PhantomData,
+            })
+        }
+    }
+}
+
+/// A page pinned by a [`Umem`].
+///
+/// # Invariants
+///
+/// `page` is pinned by the umem for the lifetime `'a`, writable if `writable` is set.
+pub struct UmemPage<'a> {
+    page: *mut bindings::page,
+    writable: bool,
+    phantom: marker::PhantomData<&'a Umem>,
+}
+
+impl UmemPage<'_> {
+    /// Returns the raw `struct page` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::page {
+        self.page
+    }
+
+    /// Copies the bytes at `offset` in the page to `buf`.
+    ///
+    /// Fails with `EINVAL` if they do not fit in the page.
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> Result {
+        check_range(offset, buf.len())?;
+        // SAFETY: The range was checked to be in the page, which the mapping covers, and `buf`
+        // cannot overlap user memory we only reach through the mapping.
+        self.with_mapped(|addr| unsafe {
+            ptr::copy_nonoverlapping(addr.add(offset), buf.as_mut_ptr(), buf.len())
+        });
+        Ok(())
+    }
+
+    /// Copies `data` to `offset` in the page.
+    ///
+    /// Fails with `EINVAL` if it does not fit in the page, and with `EACCES` if the umem was not
+    /// pinned writable.
+    pub fn write(&self, offset: usize, data: &[u8]) -> Result {
+        if !self.writable {
+            return Err(EACCES);
+        }
+        check_range(offset, data.len())?;
+        // SAFETY: As in `read`.
+        self.with_mapped(|addr| unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), addr.add(offset), data.len())
+        });
+        Ok(())
+    }
+
+    /// Runs `f` with the page mapped at the address it gets.
+    ///
+    /// The mapping is local to the thread and only valid within `f`.
+    fn with_mapped<R>(&self, f: impl FnOnce(*mut u8) -> R) -> R {
+        // SAFETY: By the type invariants the page is pinned.
+        let addr = unsafe { bindings::kmap_local_page(self.page) };
+        let ret = f(addr.cast());
+        // SAFETY: Paired with the `kmap_local_page` above.
+        unsafe { bindings::kunmap_local(addr) };
+        ret
+    }
+}
+
+fn check_range(offset: usize, len: usize) -> Result {
+    match offset.checked_add(len) {
+        Some(end) if end <= bindings::PAGE_SIZE as usize => Ok(()),
+        _ => Err(EINVAL),
+    }
+}
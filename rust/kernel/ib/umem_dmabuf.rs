@@ -0,0 +1,306 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! DMA-buf backed memory.
+//!
+//! C header: [`include/rdma/ib_umem.h`](../../../../include/rdma/ib_umem.h)
+//!
+//! An [`UmemDmabuf`] attaches the device to a DMA-buf exported by another driver, typically the
+//! VRAM of a GPU, so that the device reaches it peer-to-peer. Exporters may move the buffer:
+//! dynamic attachments hear about it through [`DmabufHandler::move_notify`], after which the
+//! abstraction unmaps the buffer until the provider maps it again, while [`Pinned`] attachments
+//! keep it in place. Providers register such ranges from
+//! [`super::IbDeviceOperations::reg_user_mr_dmabuf`].
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::marker;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr;
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use super::umem::DmaBlocks;
+use crate::bindings;
+use crate::error::{from_kernel_err_ptr, Error, Result};
+
+/// The provider's side of an [`UmemDmabuf`].
+pub trait DmabufHandler: Send + Sync + Sized {
+    /// Whether the exporter may place the buffer in the memory of another device.
+    const ALLOW_PEER2PEER: bool = true;
+
+    /// Stops the device from accessing the buffer of `umem`, which the exporter is about to move.
+    ///
+    /// Called with the reservation lock of the buffer held. The buffer is unmapped once this
+    /// returns, so the provider must have zapped its translation of it; it maps it again from
+    /// its next page fault, see [`DmabufGuard::map_pages`].
+    fn move_notify(&self, umem: &UmemDmabufRef);
+}
+
+/// The handler of attachments pinning their buffer, see [`UmemDmabuf::get_pinned`].
+pub struct Pinned;
+
+impl DmabufHandler for Pinned {
+    fn move_notify(&self, _umem: &UmemDmabufRef) {}
+}
+
+/// A range of a DMA-buf attached to a device, wraps `struct ib_umem_dmabuf`.
+///
+/// # Invariants
+///
+/// The wrapped range is valid for the lifetime of the reference.
+#[repr(transparent)]
+pub struct UmemDmabufRef(UnsafeCell<bindings::ib_umem_dmabuf>);
+
+impl UmemDmabufRef {
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned reference.
+    unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_umem_dmabuf) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements and `repr(transparent)`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_umem_dmabuf` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_umem_dmabuf {
+        self.0.get()
+    }
+
+    /// Returns the offset of the range in the buffer.
+    pub fn offset(&self) -> u64 {
+        // SAFETY: The range is valid by the type invariants.
+        unsafe { (*self.as_ptr()).umem.address as u64 }
+    }
+
+    /// Returns the length of the range in bytes.
+    pub fn length(&self) -> u64 {
+        // SAFETY: The range is valid by the type invariants.
+        unsafe { (*self.as_ptr()).umem.length as u64 }
+    }
+
+    /// Returns `true` if the pages were attached writable, i.e. the registration allows writes.
+    pub fn is_writable(&self) -> bool {
+        // SAFETY: The range is valid by the type invariants.
+        unsafe { (*self.as_ptr()).umem.writable() != 0 }
+    }
+
+    fn resv(&self) -> *mut bindings::dma_resv {
+        // SAFETY: The range is valid by the type invariants, and so is its attachment until it is
+        // released.
+        unsafe { (*(*(*self.as_ptr()).attach).dmabuf).resv }
+    }
+}
+
+/// A range of a DMA-buf attached to a device, released on drop.
+///
+/// # Invariants
+///
+/// `ptr` was returned by a successful `ib_umem_dmabuf_get` with the attachment operations of `T`,
+/// or by `ib_umem_dmabuf_get_pinned` if `T` is [`Pinned`]. Its `private` field is null or points
+/// to `handler` while the range is attached.
+pub struct UmemDmabuf<T: DmabufHandler = Pinned> {
+    ptr: *mut bindings::ib_umem_dmabuf,
+    handler: Pin<Box<T>>,
+}
+
+// SAFETY: The range can be released from any thread, and the handler is `Send`.
+unsafe impl<T: DmabufHandler> Send for UmemDmabuf<T> {}
+// SAFETY: Shared references only read the range, or map it under its reservation lock.
+unsafe impl<T: DmabufHandler> Sync for UmemDmabuf<T> {}
+
+impl<T: DmabufHandler> UmemDmabuf<T> {
+    /// Attaches `dev` to `size` bytes at `offset` in the DMA-buf of the file descriptor `fd`, with
+    /// the `IB_ACCESS_*` flags `access`.
+    ///
+    /// Nothing is mapped yet; the provider maps the buffer with [`DmabufGuard::map_pages`], and
+    /// `handler` is told when the exporter moves it.
+    pub fn get<D: IbDeviceOperations>(
+        dev: &DeviceRef<D>,
+        offset: u64,
+        size: u64,
+        fd: i32,
+        access: u32,
+        handler: Pin<Box<T>>,
+    ) -> Result<Self> {
+        // SAFETY: The device is valid, `ib_umem_dmabuf_get` looks `fd` up in the caller's file
+        // table, and the operations live as long as the module.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::ib_umem_dmabuf_get(
+                dev.as_ptr(),
+                offset as _,
+                size as _,
+                fd,
+                access as _,
+                &DmabufTable::<T>::OPS,
+            )
+        })?;
+        // SAFETY: `ptr` is valid. Moves may already be notified, they find the handler once it is
+        // published under the reservation lock they hold.
+        let umem = unsafe { UmemDmabufRef::from_ptr(ptr) };
+        // SAFETY: The buffer of an attached range is valid.
+        unsafe {
+            bindings::dma_resv_lock(umem.resv(), ptr::null_mut());
+            (*ptr).private = &*handler as *const T as *mut core::ffi::c_void;
+            bindings::dma_resv_unlock(umem.resv());
+        }
+        // INVARIANT: The range was just attached, with `private` pointing to `handler`.
+        Ok(Self { ptr, handler })
+    }
+
+    /// Returns the handler of the range.
+    pub fn handler(&self) -> Pin<&T> {
+        self.handler.as_ref()
+    }
+
+    /// Takes the reservation lock of the buffer, keeping the exporter from moving it.
+    pub fn lock(&self) -> DmabufGuard<'_> {
+        // SAFETY: The buffer of an attached range is valid, and `dma_resv_lock` only fails with an
+        // acquire context.
+        unsafe { bindings::dma_resv_lock(self.resv(), ptr::null_mut()) };
+        // INVARIANT: The lock was just taken.
+        DmabufGuard { umem: self }
+    }
+}
+
+impl UmemDmabuf<Pinned> {
+    /// Attaches `dev` to `size` bytes at `offset` in the DMA-buf of the file descriptor `fd`, with
+    /// the `IB_ACCESS_*` flags `access`, and pins and maps the buffer.
+    ///
+    /// For devices that cannot fault; fails if the exporter cannot pin the buffer.
+    pub fn get_pinned<D: IbDeviceOperations>(
+        dev: &DeviceRef<D>,
+        offset: u64,
+        size: u64,
+        fd: i32,
+        access: u32,
+    ) -> Result<Self> {
+        let handler = Pin::new(Box::try_new(Pinned)?);
+        // SAFETY: As in `get`.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::ib_umem_dmabuf_get_pinned(
+                dev.as_ptr(),
+                offset as _,
+                size as _,
+                fd,
+                access as _,
+            )
+        })?;
+        // INVARIANT: The range was just attached and pinned, and `private` is null.
+        Ok(Self { ptr, handler })
+    }
+}
+
+impl<T: DmabufHandler> Deref for UmemDmabuf<T> {
+    type Target = UmemDmabufRef;
+
+    fn deref(&self) -> &UmemDmabufRef {
+        // SAFETY: By the type invariants the range is valid while `self` lives.
+        unsafe { UmemDmabufRef::from_ptr(self.ptr) }
+    }
+}
+
+impl<T: DmabufHandler> Drop for UmemDmabuf<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the range is attached. Releasing it unmaps the buffer
+        // under its reservation lock and detaches it, so the handler is no longer used once this
+        // returns.
+        unsafe { bindings::ib_umem_dmabuf_release(self.ptr) };
+    }
+}
+
+/// The reservation lock of the buffer of an [`UmemDmabuf`], see [`UmemDmabuf::lock`].
+///
+/// # Invariants
+///
+/// The reservation lock of the buffer of `umem` is held.
+pub struct DmabufGuard<'a> {
+    umem: &'a UmemDmabufRef,
+}
+
+impl DmabufGuard<'_> {
+    /// Maps the buffer for DMA by the device, if it is not already.
+    ///
+    /// The mapping lasts until the exporter moves the buffer, after the lock is dropped.
+    pub fn map_pages(&mut self) -> Result {
+        // SAFETY: The lock is held by the type invariants.
+        let ret = unsafe { bindings::ib_umem_dmabuf_map_pages(self.umem.as_ptr()) };
+        if ret < 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+        Ok(())
+    }
+
+    /// Unmaps the buffer, if it is mapped.
+    pub fn unmap_pages(&mut self) {
+        // SAFETY: The lock is held by the type invariants.
+        unsafe { bindings::ib_umem_dmabuf_unmap_pages(self.umem.as_ptr()) };
+    }
+
+    /// Returns `true` if the buffer is mapped.
+    pub fn is_mapped(&self) -> bool {
+        // SAFETY: The range is valid, and the lock serialises the changes of its table.
+        unsafe { !(*self.umem.as_ptr()).sgt.is_null() }
+    }
+
+    /// Returns the largest block size of `pgsz_bitmap` the mapped buffer can be mapped with at the
+    /// device address `virt`, or `None` if none fits or the buffer is not mapped.
+    pub fn find_best_pgsz(&self, pgsz_bitmap: u64, virt: u64) -> Option<u64> {
+        if !self.is_mapped() {
+            return None;
+        }
+        // SAFETY: The buffer is mapped, and the lock keeps it so.
+        let pgsz = unsafe {
+            bindings::ib_umem_find_best_pgsz(
+                ptr::addr_of_mut!((*self.umem.as_ptr()).umem),
+                pgsz_bitmap as _,
+                virt,
+            )
+        };
+        if pgsz == 0 {
+            return None;
+        }
+        Some(pgsz as u64)
+    }
+
+    /// Returns an iterator over the DMA addresses of the blocks of `pgsz` bytes backing the mapped
+    /// range, or `None` if the buffer is not mapped.
+    ///
+    /// `pgsz` must be a power of two no smaller than `PAGE_SIZE`.
+    pub fn dma_blocks(&self, pgsz: u64) -> Option<DmaBlocks<'_>> {
+        if !self.is_mapped() {
+            return None;
+        }
+        // SAFETY: The buffer is mapped, so the table of the umem lists it, and the lock keeps it
+        // from changing while the iterator borrows `self`.
+        Some(unsafe { DmaBlocks::start(ptr::addr_of_mut!((*self.umem.as_ptr()).umem), pgsz) })
+    }
+}
+
+impl Drop for DmabufGuard<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the lock is held.
+        unsafe { bindings::dma_resv_unlock(self.umem.resv()) };
+    }
+}
+
+struct DmabufTable<T>(marker::PhantomData<T>);
+
+impl<T: DmabufHandler> DmabufTable<T> {
+    const OPS: bindings::dma_buf_attach_ops = bindings::dma_buf_attach_ops {
+        allow_peer2peer: T::ALLOW_PEER2PEER,
+        move_notify: Some(Self::move_notify_callback),
+    };
+
+    unsafe extern "C" fn move_notify_callback(attach: *mut bindings::dma_buf_attachment) {
+        // SAFETY: The exporter only notifies attachments made by `UmemDmabuf::get`, whose
+        // importer data is the range, with the reservation lock held.
+        let umem = unsafe { UmemDmabufRef::from_ptr((*attach).importer_priv.cast()) };
+        // SAFETY: `private` is written under the reservation lock; by the type invariants of
+        // `UmemDmabuf` it points to the handler, which outlives the attachment.
+        let handler = unsafe { (*umem.as_ptr()).private.cast::<T>().as_ref() };
+        if let Some(handler) = handler {
+            handler.move_notify(umem);
+        }
+        // SAFETY: The reservation lock is held, as unmapping requires.
+        unsafe { bindings::ib_umem_dmabuf_unmap_pages(umem.as_ptr()) };
+    }
+}
@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! On-demand paging userspace memory.
+//!
+//! C header: [`include/rdma/ib_umem_odp.h`](../../../../include/rdma/ib_umem_odp.h)
+//!
+//! An [`UmemOdp`] describes a range of userspace memory like a [`super::Umem`], but pins nothing:
+//! the provider maps the pages the device faults on with [`UmemOdp::map_and_lock`], and the MMU
+//! notifier of the range calls [`OdpHandler::invalidate`] when the CPU mapping of some of them
+//! changes, after which the abstraction unmaps them again. Providers register such ranges from
+//! [`super::IbDeviceOperations::reg_user_mr_odp`] and advertise the faults they handle with
+//! [`super::device::DeviceAttr::set_odp_caps`].
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::marker;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr;
+
+use super::device::{DeviceRef, IbDeviceOperations};
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Error, Result};
+use crate::rdma::odp::OdpSpan;
+
+pub use crate::rdma::odp::{OdpCaps, OdpTransportCaps};
+
+/// Access bit of the device addresses of mapped pages, `ODP_READ_ALLOWED_BIT`.
+const ODP_READ_ALLOWED: u64 = 1 << 0;
+/// Access bit of the device addresses of writable pages, `ODP_WRITE_ALLOWED_BIT`.
+const ODP_WRITE_ALLOWED: u64 = 1 << 1;
+/// Mask of the DMA address in an entry of `dma_list`, `ODP_DMA_ADDR_MASK`.
+const ODP_DMA_ADDR_MASK: u64 = !(ODP_READ_ALLOWED | ODP_WRITE_ALLOWED);
+
+/// The provider's side of an [`UmemOdp`].
+pub trait OdpHandler: Send + Sync + Sized {
+    /// Stops the device from accessing the pages of `span`, whose CPU mapping is about to change.
+    ///
+    /// Called by the MMU notifier with the mutex of `odp` held, so faults of the same range wait
+    /// for it. The pages are unmapped for DMA once this returns, so the provider must have
+    /// zapped its translation of them, and waited for the device to drop any cached copy.
+    fn invalidate(&self, odp: &UmemOdpRef, span: &OdpSpan);
+}
+
+/// A range of userspace memory mapped on demand, wraps `struct ib_umem_odp`.
+///
+/// # Invariants
+///
+/// The wrapped range is valid for the lifetime of the reference.
+#[repr(transparent)]
+pub struct UmemOdpRef(UnsafeCell<bindings::ib_umem_odp>);
+
+impl UmemOdpRef {
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned reference.
+    unsafe fn from_ptr<'a>(ptr: *mut bindings::ib_umem_odp) -> &'a Self {
+        // SAFETY: Guaranteed by the safety requirements and `repr(transparent)`.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_umem_odp` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_umem_odp {
+        self.0.get()
+    }
+
+    /// Returns the userspace address of the range.
+    pub fn address(&self) -> u64 {
+        // SAFETY: The range is valid by the type invariants.
+        unsafe { (*self.as_ptr()).umem.address as u64 }
+    }
+
+    /// Returns the length of the range in bytes.
+    pub fn length(&self) -> u64 {
+        // SAFETY: The range is valid by the type invariants.
+        unsafe { (*self.as_ptr()).umem.length as u64 }
+    }
+
+    /// Returns the start of the first page of the range, `ib_umem_start()`.
+    pub fn start(&self) -> u64 {
+        // SAFETY: The range is valid by the type invariants, and the interval of its notifier is
+        // never changed.
+        unsafe { (*self.as_ptr()).notifier.interval_tree.start as u64 }
+    }
+
+    /// Returns the end of the last page of the range, `ib_umem_end()`.
+    pub fn end(&self) -> u64 {
+        // SAFETY: As in `start`.
+        unsafe { (*self.as_ptr()).notifier.interval_tree.last as u64 + 1 }
+    }
+
+    /// Returns the page shift of the range.
+    pub fn page_shift(&self) -> u32 {
+        // SAFETY: The range is valid by the type invariants.
+        unsafe { (*self.as_ptr()).page_shift }
+    }
+
+    /// Returns the span of the range overlapped by `[start, end)`.
+    pub fn span(&self, start: u64, end: u64) -> Option<OdpSpan> {
+        OdpSpan::of(self.start(), self.end(), self.page_shift(), start, end)
+    }
+
+    fn mutex(&self) -> *mut bindings::mutex {
+        // SAFETY: The range is valid by the type invariants.
+        unsafe { ptr::addr_of_mut!((*self.as_ptr()).umem_mutex) }
+    }
+}
+
+/// A range of userspace memory mapped on demand, released on drop.
+///
+/// # Invariants
+///
+/// `ptr` was returned by a successful `ib_umem_odp_get` with the notifier operations of `T`, and
+/// its `private` field points to `handler` while the range is registered.
+pub struct UmemOdp<T: OdpHandler> {
+    ptr: *mut bindings::ib_umem_odp,
+    handler: Pin<Box<T>>,
+}
+
+// SAFETY: The range can be released from any thread, and the handler is `Send`.
+unsafe impl<T: OdpHandler> Send for UmemOdp<T> {}
+// SAFETY: Shared references only read the range, or map it under its mutex.
+unsafe impl<T: OdpHandler> Sync for UmemOdp<T> {}
+
+impl<T: OdpHandler> UmemOdp<T> {
+    /// Registers `length` bytes of the current process starting at `addr` for on-demand DMA by
+    /// `dev`, with the `IB_ACCESS_*` flags `access`, which include `IB_ACCESS_ON_DEMAND`.
+    ///
+    /// Nothing is mapped yet; `handler` is told about the invalidations of the pages mapped later.
+    pub fn get<D: IbDeviceOperations>(
+        dev: &DeviceRef<D>,
+        addr: u64,
+        length: u64,
+        access: u32,
+        handler: Pin<Box<T>>,
+    ) -> Result<Self> {
+        // SAFETY: The device is valid, `ib_umem_odp_get` checks the range against the caller's
+        // address space, and the operations live as long as the module.
+        let ptr = from_kernel_err_ptr(unsafe {
+            bindings::ib_umem_odp_get(
+                dev.as_ptr(),
+                addr as _,
+                length as _,
+                access as _,
+                &OdpTable::<T>::OPS,
+            )
+        })?;
+        // SAFETY: `ptr` is valid. Invalidations may already run, they find the handler once it is
+        // published under the mutex they take.
+        unsafe {
+            bindings::mutex_lock(ptr::addr_of_mut!((*ptr).umem_mutex));
+            (*ptr).private = &*handler as *const T as *mut core::ffi::c_void;
+            bindings::mutex_unlock(ptr::addr_of_mut!((*ptr).umem_mutex));
+        }
+        // INVARIANT: The range was just registered, with `private` pointing to `handler`.
+        Ok(Self { ptr, handler })
+    }
+
+    /// Returns the handler of the range.
+    pub fn handler(&self) -> Pin<&T> {
+        self.handler.as_ref()
+    }
+
+    /// Maps the pages backing `[va, va + len)` for DMA, faulting them in if `fault` is set, and
+    /// locks the range against invalidations.
+    ///
+    /// Only the pages already present are mapped when `fault` is unset, e.g. to prefetch. This is
+    /// how providers service the page faults of their device: they update its translation of the
+    /// returned mapping, then drop it to let invalidations in again. Fails with `EINVAL` if the
+    /// range is not covered, and with `EFAULT` if some page cannot be faulted in.
+    pub fn map_and_lock(
+        &self,
+        va: u64,
+        len: u64,
+        write: bool,
+        fault: bool,
+    ) -> Result<OdpMapping<'_>> {
+        let span = self.span(va, va.saturating_add(len)).ok_or(EINVAL)?;
+        let mut access = ODP_READ_ALLOWED;
+        if write {
+            access |= ODP_WRITE_ALLOWED;
+        }
+        // SAFETY: By the type invariants the range is registered.
+        let ret =
+            unsafe { bindings::ib_umem_odp_map_dma_and_lock(self.ptr, va, len, access, fault) };
+        if ret < 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+        // INVARIANT: `ib_umem_odp_map_dma_and_lock` returns with the mutex held on success.
+        Ok(OdpMapping {
+            odp: self,
+            first_page: span.first_page,
+            npages: ret as usize,
+        })
+    }
+}
+
+impl<T: OdpHandler> Deref for UmemOdp<T> {
+    type Target = UmemOdpRef;
+
+    fn deref(&self) -> &UmemOdpRef {
+        // SAFETY: By the type invariants the range is valid while `self` lives.
+        unsafe { UmemOdpRef::from_ptr(self.ptr) }
+    }
+}
+
+impl<T: OdpHandler> Drop for UmemOdp<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the range is registered. Releasing it removes the
+        // notifier, waiting for the invalidations in progress, and unmaps what is left, so the
+        // handler is no longer used once this returns.
+        unsafe { bindings::ib_umem_odp_release(self.ptr) };
+    }
+}
+
+/// Pages of an [`UmemOdp`] mapped for DMA, see [`UmemOdp::map_and_lock`].
+///
+/// The range is locked against invalidations until the mapping is dropped.
+///
+/// # Invariants
+///
+/// The mutex of `odp` is held, and its pages `first_page..first_page + npages` are mapped.
+pub struct OdpMapping<'a> {
+    odp: &'a UmemOdpRef,
+    first_page: usize,
+    npages: usize,
+}
+
+impl OdpMapping<'_> {
+    /// Returns the index of the first mapped page in the range.
+    pub fn first_page(&self) -> usize {
+        self.first_page
+    }
+
+    /// Returns the number of mapped pages.
+    pub fn npages(&self) -> usize {
+        self.npages
+    }
+
+    /// Returns an iterator over the DMA addresses of the mapped pages.
+    pub fn dma_addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        // SAFETY: The pages are mapped by the type invariants, and `dma_list` holds an entry for
+        // each page of the range; the mutex keeps them from changing.
+        let list = unsafe { (*self.odp.as_ptr()).dma_list };
+        (self.first_page..self.first_page + self.npages)
+            // SAFETY: As above.
+            .map(move |i| unsafe { *list.add(i) } as u64 & ODP_DMA_ADDR_MASK)
+    }
+}
+
+impl Drop for OdpMapping<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the mutex is held.
+        unsafe { bindings::mutex_unlock(self.odp.mutex()) };
+    }
+}
+
+/// Returns the range whose notifier is `mni`.
+///
+/// # Safety
+///
+/// `mni` must be the `notifier` field of a valid `struct ib_umem_odp`.
+unsafe fn odp_of(mni: *mut bindings::mmu_interval_notifier) -> *mut bindings::ib_umem_odp {
+    let base = MaybeUninit::<bindings::ib_umem_odp>::uninit();
+    let base = base.as_ptr();
+    // SAFETY: Only the address of the field is computed, nothing is read.
+    let offset = unsafe { ptr::addr_of!((*base).notifier) as usize - base as usize };
+    // SAFETY: Guaranteed by the safety requirements.
+    unsafe { mni.cast::<u8>().sub(offset).cast() }
+}
+
+struct OdpTable<T>(marker::PhantomData<T>);
+
+impl<T: OdpHandler> OdpTable<T> {
+    const OPS: bindings::mmu_interval_notifier_ops = bindings::mmu_interval_notifier_ops {
+        invalidate: Some(Self::invalidate_callback),
+    };
+
+    unsafe extern "C" fn invalidate_callback(
+        mni: *mut bindings::mmu_interval_notifier,
+        range: *const bindings::mmu_notifier_range,
+        cur_seq: core::ffi::c_ulong,
+    ) -> bool {
+        // SAFETY: The MMU notifier passes a valid range, and only calls us for the notifier of a
+        // registered `ib_umem_odp`, see `UmemOdp::get`.
+        let (odp, flags, start, end) = unsafe {
+            (
+                UmemOdpRef::from_ptr(odp_of(mni)),
+                (*range).flags,
+                (*range).start as u64,
+                (*range).end as u64,
+            )
+        };
+        if flags & bindings::MMU_NOTIFIER_RANGE_BLOCKABLE == 0 {
+            // Only the OOM reaper invalidates without sleeping; it skips the ranges refusing.
+            return false;
+        }
+        // SAFETY: The mutex is initialised while the range is registered.
+        unsafe { bindings::mutex_lock(odp.mutex()) };
+        // SAFETY: `mni` is valid, and the mutex serialises the writers of the sequence, as
+        // `mmu_interval_set_seq()` requires; readers load it with `READ_ONCE`.
+        unsafe { ptr::write_volatile(ptr::addr_of_mut!((*mni).invalidate_seq), cur_seq) };
+        // SAFETY: The range is valid, and `npages` only changes under the mutex.
+        let mapped = unsafe { (*odp.as_ptr()).npages } > 0;
+        if let Some(span) = odp.span(start, end).filter(|_| mapped) {
+            // SAFETY: `private` is written under the mutex; by the type invariants of `UmemOdp`
+            // it points to the handler, which outlives the registration.
+            let handler = unsafe { (*odp.as_ptr()).private.cast::<T>().as_ref() };
+            if let Some(handler) = handler {
+                handler.invalidate(odp, &span);
+            }
+            // SAFETY: The mutex is held, as unmapping requires.
+            unsafe { bindings::ib_umem_odp_unmap_dma_pages(odp.as_ptr(), span.start, span.end) };
+        }
+        // SAFETY: Paired with the `mutex_lock` above.
+        unsafe { bindings::mutex_unlock(odp.mutex()) };
+        true
+    }
+}
@@ -0,0 +1,794 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Work requests.
+//!
+//! Providers see the chains posted by consumers through the borrowed [`SendWr`] and [`RecvWr`]
+//! views, which decode the opcode-specific structure a send request is embedded in and validate
+//! it. In-kernel consumers build requests with [`PostSendWr`] and [`PostRecvWr`], which borrow
+//! their scatter/gather lists so that a chain cannot outlive the buffers it describes.
+
+use core::cell::UnsafeCell;
+use core::marker;
+
+use super::device::IbDeviceOperations;
+use super::mr::MemoryRegion;
+use super::mw::MemoryWindow;
+use super::qp::{QpType, QueuePair};
+use crate::bindings;
+use crate::error::{code::*, Result};
+
+/// A scatter/gather entry, wraps `struct ib_sge`.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Sge(bindings::ib_sge);
+
+impl Sge {
+    /// Creates an entry for `length` bytes at `addr` in the MR of `lkey`.
+    pub const fn new(addr: u64, length: u32, lkey: u32) -> Self {
+        Self(bindings::ib_sge { addr, length, lkey })
+    }
+
+    /// Returns the address of the buffer, an I/O virtual address of the MR of `lkey`.
+    pub fn addr(&self) -> u64 {
+        self.0.addr
+    }
+
+    /// Returns the length of the buffer in bytes.
+    pub fn length(&self) -> u32 {
+        self.0.length
+    }
+
+    /// Returns the local key of the MR covering the buffer.
+    pub fn lkey(&self) -> u32 {
+        self.0.lkey
+    }
+}
+
+fn sg_slice<'a>(sg_list: *const bindings::ib_sge, num_sge: core::ffi::c_int) -> &'a [Sge] {
+    if num_sge <= 0 || sg_list.is_null() {
+        return &[];
+    }
+    // SAFETY: Callers pass the list of a request valid for `'a`, which holds `num_sge` entries,
+    // and `Sge` is transparent over `ib_sge`.
+    unsafe { core::slice::from_raw_parts(sg_list.cast(), num_sge as usize) }
+}
+
+/// Operation of a send work request, decoded from `struct ib_send_wr` and the opcode-specific
+/// structure embedding it.
+///
+/// Immediate data is in network byte order, as posted. The MRs and windows of REG_MR and BIND_MW
+/// are borrowed from the request, which keeps them alive while it is being posted.
+pub enum SendOp<'a, T: IbDeviceOperations> {
+    /// SEND, optionally with immediate data.
+    Send {
+        /// Immediate data.
+        imm: Option<u32>,
+    },
+    /// SEND that invalidates `rkey` at the responder.
+    SendWithInv {
+        /// R_Key to invalidate.
+        rkey: u32,
+    },
+    /// RDMA WRITE, optionally with immediate data.
+    RdmaWrite {
+        /// Remote I/O virtual address.
+        remote_addr: u64,
+        /// Remote key of the target MR.
+        rkey: u32,
+        /// Immediate data.
+        imm: Option<u32>,
+    },
+    /// RDMA READ.
+    RdmaRead {
+        /// Remote I/O virtual address.
+        remote_addr: u64,
+        /// Remote key of the source MR.
+        rkey: u32,
+    },
+    /// Atomic compare and swap of the 8 bytes at `remote_addr`.
+    CompSwap {
+        /// Remote I/O virtual address, 8-byte aligned.
+        remote_addr: u64,
+        /// Remote key of the target MR.
+        rkey: u32,
+        /// Value to compare with.
+        compare: u64,
+        /// Value stored if the comparison succeeds.
+        swap: u64,
+    },
+    /// Atomic fetch and add of the 8 bytes at `remote_addr`.
+    FetchAdd {
+        /// Remote I/O virtual address, 8-byte aligned.
+        remote_addr: u64,
+        /// Remote key of the target MR.
+        rkey: u32,
+        /// Value to add.
+        add: u64,
+    },
+    /// Local invalidation of `rkey`.
+    LocalInv {
+        /// Key to invalidate.
+        rkey: u32,
+    },
+    /// Fast registration of `mr` under `key`.
+    RegMr {
+        /// The MR, of the device of the QP.
+        mr: &'a MemoryRegion<T>,
+        /// New key of the MR.
+        key: u32,
+        /// `IB_ACCESS_*` flags of the registration.
+        access: u32,
+    },
+    /// Bind of the type 2 window `mw` to `mr`, see [`MemoryWindow::bind`].
+    BindMw {
+        /// The window, of the device of the QP.
+        mw: &'a MemoryWindow<T>,
+        /// The MR to bind to, of the device of the QP, or `None` to unbind.
+        mr: Option<&'a MemoryRegion<T>>,
+        /// New rkey of the window, only its variant is taken.
+        rkey: u32,
+        /// Start of the window.
+        addr: u64,
+        /// Length of the window.
+        length: u64,
+        /// `IB_ACCESS_*` flags granted through the window.
+        access: u32,
+    },
+}
+
+impl<T: IbDeviceOperations> Clone for SendOp<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: IbDeviceOperations> Copy for SendOp<'_, T> {}
+
+/// A BIND_MW request, laid out as the `struct ib_bind_mw_wr` that ib_core used to define.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawBindMwWr {
+    wr: bindings::ib_send_wr,
+    mw: *mut bindings::ib_mw,
+    rkey: u32,
+    mr: *mut bindings::ib_mr,
+    addr: u64,
+    length: u64,
+    access: core::ffi::c_int,
+}
+
+/// Destination of a send work request on a datagram QP, from `struct ib_ud_wr`.
+#[derive(Clone, Copy, Debug)]
+pub struct UdDest {
+    /// Address handle of the destination port, created on the same device.
+    pub ah: *mut bindings::ib_ah,
+    /// Destination QP number.
+    pub remote_qpn: u32,
+    /// Q_Key of the destination QP.
+    pub remote_qkey: u32,
+    /// P_Key index, for GSI QPs.
+    pub pkey_index: u16,
+    /// Port to send from, for SMI and GSI QPs.
+    pub port_num: u32,
+}
+
+/// A send work request posted by a consumer, wraps `struct ib_send_wr`.
+///
+/// Consumers post chains of requests; [`SendWr::iter`] walks the chain and [`SendWr::op`] decodes
+/// each request.
+#[repr(transparent)]
+pub struct SendWr(UnsafeCell<bindings::ib_send_wr>);
+
+impl SendWr {
+    /// Creates a reference to a [`SendWr`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid chain of send work requests, each embedded in the structure
+    /// its opcode and the QP type call for, with valid scatter/gather lists, which stays
+    /// unchanged for the lifetime of the returned instance. The MRs and windows of its REG_MR and
+    /// BIND_MW requests must stay valid for that lifetime too.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::ib_send_wr) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `SendWr` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_send_wr` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_send_wr {
+        self.0.get()
+    }
+
+    fn raw(&self) -> &bindings::ib_send_wr {
+        // SAFETY: The request is valid and unchanged per the `from_ptr` requirements.
+        unsafe { &*self.0.get() }
+    }
+
+    /// Returns the identifier reported in the completion.
+    pub fn wr_id(&self) -> u64 {
+        // SAFETY: Providers that use completion entries read them through `wr_cqe`; the bits are
+        // reported back unchanged either way.
+        unsafe { self.raw().__bindgen_anon_1.wr_id }
+    }
+
+    fn has_flag(&self, flag: bindings::ib_send_flags) -> bool {
+        self.raw().send_flags & flag as core::ffi::c_int != 0
+    }
+
+    /// Returns `true` if the request generates a completion even on QPs created with
+    /// `IB_SIGNAL_REQ_WR`.
+    pub fn signaled(&self) -> bool {
+        self.has_flag(bindings::ib_send_flags_IB_SEND_SIGNALED)
+    }
+
+    /// Returns `true` if the responder should raise a solicited event.
+    pub fn solicited(&self) -> bool {
+        self.has_flag(bindings::ib_send_flags_IB_SEND_SOLICITED)
+    }
+
+    /// Returns `true` if the request waits for prior RDMA READs and atomics to complete.
+    pub fn fence(&self) -> bool {
+        self.has_flag(bindings::ib_send_flags_IB_SEND_FENCE)
+    }
+
+    /// Returns `true` if the payload is copied from the scatter/gather list at post time, in
+    /// which case its keys are not checked.
+    pub fn inline(&self) -> bool {
+        self.has_flag(bindings::ib_send_flags_IB_SEND_INLINE)
+    }
+
+    /// Returns the scatter/gather list the payload is read from, or written to for RDMA READs
+    /// and atomics.
+    pub fn sg_list(&self) -> &[Sge] {
+        let raw = self.raw();
+        sg_slice(raw.sg_list, raw.num_sge)
+    }
+
+    /// Returns the total length of the scatter/gather list, `None` if it overflows.
+    pub fn total_length(&self) -> Option<u32> {
+        self.sg_list()
+            .iter()
+            .try_fold(0u32, |len, sge| len.checked_add(sge.length()))
+    }
+
+    /// Decodes and validates the operation, for the request posted to `qp`.
+    ///
+    /// Fails with `EINVAL` for opcodes not covered by [`SendOp`], for atomics whose
+    /// scatter/gather list is not a single 8-byte entry or whose address is unaligned, for
+    /// inline requests other than SEND and RDMA WRITE, and for REG_MR and BIND_MW requests naming
+    /// an MR or a window of another device.
+    pub fn op<T: IbDeviceOperations>(&self, qp: &QueuePair<T>) -> Result<SendOp<'_, T>> {
+        let dev = qp.device().as_ptr();
+        let raw = self.raw();
+        // SAFETY: `ex` is plain data, its interpretation depends on the opcode checked below.
+        let (imm, inv) = unsafe { (raw.ex.imm_data, raw.ex.invalidate_rkey) };
+        let op = match raw.opcode {
+            bindings::ib_wr_opcode_IB_WR_SEND => SendOp::Send { imm: None },
+            bindings::ib_wr_opcode_IB_WR_SEND_WITH_IMM => SendOp::Send { imm: Some(imm) },
+            bindings::ib_wr_opcode_IB_WR_SEND_WITH_INV => SendOp::SendWithInv { rkey: inv },
+            bindings::ib_wr_opcode_IB_WR_LOCAL_INV => SendOp::LocalInv { rkey: inv },
+            bindings::ib_wr_opcode_IB_WR_RDMA_WRITE
+            | bindings::ib_wr_opcode_IB_WR_RDMA_WRITE_WITH_IMM
+            | bindings::ib_wr_opcode_IB_WR_RDMA_READ => {
+                // SAFETY: Requests of these opcodes are embedded in `struct ib_rdma_wr` per the
+                // `from_ptr` requirements.
+                let rdma = unsafe { &*self.as_ptr().cast::<bindings::ib_rdma_wr>() };
+                let (remote_addr, rkey) = (rdma.remote_addr, rdma.rkey);
+                match raw.opcode {
+                    bindings::ib_wr_opcode_IB_WR_RDMA_READ => {
+                        SendOp::RdmaRead { remote_addr, rkey }
+                    }
+                    bindings::ib_wr_opcode_IB_WR_RDMA_WRITE => SendOp::RdmaWrite {
+                        remote_addr,
+                        rkey,
+                        imm: None,
+                    },
+                    _ => SendOp::RdmaWrite {
+                        remote_addr,
+                        rkey,
+                        imm: Some(imm),
+                    },
+                }
+            }
+            bindings::ib_wr_opcode_IB_WR_ATOMIC_CMP_AND_SWP
+            | bindings::ib_wr_opcode_IB_WR_ATOMIC_FETCH_AND_ADD => {
+                // SAFETY: Requests of these opcodes are embedded in `struct ib_atomic_wr` per the
+                // `from_ptr` requirements.
+                let atomic = unsafe { &*self.as_ptr().cast::<bindings::ib_atomic_wr>() };
+                match self.sg_list() {
+                    [sge] if sge.length() == 8 && atomic.remote_addr % 8 == 0 => {}
+                    _ => return Err(EINVAL),
+                }
+                if raw.opcode == bindings::ib_wr_opcode_IB_WR_ATOMIC_CMP_AND_SWP {
+                    SendOp::CompSwap {
+                        remote_addr: atomic.remote_addr,
+                        rkey: atomic.rkey,
+                        compare: atomic.compare_add,
+                        swap: atomic.swap,
+                    }
+                } else {
+                    SendOp::FetchAdd {
+                        remote_addr: atomic.remote_addr,
+                        rkey: atomic.rkey,
+                        add: atomic.compare_add,
+                    }
+                }
+            }
+            bindings::ib_wr_opcode_IB_WR_REG_MR => {
+                // SAFETY: Requests of this opcode are embedded in `struct ib_reg_wr` per the
+                // `from_ptr` requirements.
+                let reg = unsafe { &*self.as_ptr().cast::<bindings::ib_reg_wr>() };
+                SendOp::RegMr {
+                    // SAFETY: The MR is valid per the `from_ptr` requirements.
+                    mr: unsafe { Self::mr(reg.mr, dev) }.ok_or(EINVAL)?,
+                    key: reg.key,
+                    access: reg.access as u32,
+                }
+            }
+            bindings::ib_wr_opcode_IB_WR_BIND_MW => {
+                // SAFETY: Requests of this opcode are embedded in a `RawBindMwWr` per the
+                // `from_ptr` requirements.
+                let bind = unsafe { &*self.as_ptr().cast::<RawBindMwWr>() };
+                // SAFETY: The window and the MR are valid per the `from_ptr` requirements.
+                let (mw, mr) = unsafe { (Self::mw(bind.mw, dev), Self::mr(bind.mr, dev)) };
+                if bind.mr.is_null() != mr.is_none() {
+                    return Err(EINVAL);
+                }
+                SendOp::BindMw {
+                    mw: mw.ok_or(EINVAL)?,
+                    mr,
+                    rkey: bind.rkey,
+                    addr: bind.addr,
+                    length: bind.length,
+                    access: bind.access as u32,
+                }
+            }
+            _ => return Err(EINVAL),
+        };
+        if self.inline() && !matches!(op, SendOp::Send { .. } | SendOp::RdmaWrite { .. }) {
+            return Err(EINVAL);
+        }
+        Ok(op)
+    }
+
+    /// Returns the MR of `mr`, if it is one of `dev`.
+    ///
+    /// # Safety
+    ///
+    /// `mr` must be null or point to an MR valid for `'a`.
+    unsafe fn mr<'a, T: IbDeviceOperations>(
+        mr: *mut bindings::ib_mr,
+        dev: *mut bindings::ib_device,
+    ) -> Option<&'a MemoryRegion<T>> {
+        // SAFETY: The MR is valid per the safety requirements.
+        if mr.is_null() || unsafe { (*mr).device } != dev {
+            return None;
+        }
+        // SAFETY: Every MR of a device of `T` was returned by `MemoryRegion::into_raw` with `T`,
+        // and it stays registered for `'a` per the safety requirements.
+        Some(unsafe { MemoryRegion::from_ptr(mr) })
+    }
+
+    /// Returns the window of `mw`, if it is one of `dev`.
+    ///
+    /// # Safety
+    ///
+    /// `mw` must be null or point to a window valid for `'a`.
+    unsafe fn mw<'a, T: IbDeviceOperations>(
+        mw: *mut bindings::ib_mw,
+        dev: *mut bindings::ib_device,
+    ) -> Option<&'a MemoryWindow<T>> {
+        // SAFETY: The window is valid per the safety requirements.
+        if mw.is_null() || unsafe { (*mw).device } != dev {
+            return None;
+        }
+        // SAFETY: Every window of a device of `T` was allocated by `alloc_mw` of `T`, and it
+        // stays allocated for `'a` per the safety requirements.
+        Some(unsafe { MemoryWindow::from_ptr(mw) })
+    }
+
+    /// Returns the destination of the request if it was posted to a datagram QP of `qp_type`.
+    pub fn ud_dest(&self, qp_type: QpType) -> Option<UdDest> {
+        if !matches!(qp_type, QpType::Smi | QpType::Gsi | QpType::Ud) {
+            return None;
+        }
+        // SAFETY: Requests posted to datagram QPs are embedded in `struct ib_ud_wr` per the
+        // `from_ptr` requirements.
+        let ud = unsafe { &*self.as_ptr().cast::<bindings::ib_ud_wr>() };
+        Some(UdDest {
+            ah: ud.ah,
+            remote_qpn: ud.remote_qpn,
+            remote_qkey: ud.remote_qkey,
+            pkey_index: ud.pkey_index,
+            port_num: ud.port_num,
+        })
+    }
+
+    /// Returns the next request of the chain.
+    pub fn next(&self) -> Option<&SendWr> {
+        let next = self.raw().next;
+        if next.is_null() {
+            None
+        } else {
+            // SAFETY: The chain is valid per the `from_ptr` requirements.
+            Some(unsafe { SendWr::from_ptr(next) })
+        }
+    }
+
+    /// Returns an iterator over the chain, starting with `self`.
+    pub fn iter(&self) -> SendWrIter<'_> {
+        SendWrIter { next: Some(self) }
+    }
+}
+
+/// Iterator over a chain of send work requests.
+pub struct SendWrIter<'a> {
+    next: Option<&'a SendWr>,
+}
+
+impl<'a> Iterator for SendWrIter<'a> {
+    type Item = &'a SendWr;
+
+    fn next(&mut self) -> Option<&'a SendWr> {
+        let wr = self.next?;
+        self.next = wr.next();
+        Some(wr)
+    }
+}
+
+/// Storage for every structure a send work request can be embedded in.
+#[repr(C)]
+#[derive(Clone, Copy)]
+union RawSendWr {
+    wr: bindings::ib_send_wr,
+    rdma: bindings::ib_rdma_wr,
+    atomic: bindings::ib_atomic_wr,
+    reg: bindings::ib_reg_wr,
+    bind: RawBindMwWr,
+}
+
+/// A send work request built by an in-kernel consumer.
+///
+/// The request borrows its scatter/gather list, and the MR for REG_MR or the window and MR for
+/// BIND_MW, for `'a`.
+pub struct PostSendWr<'a> {
+    raw: RawSendWr,
+    phantom: marker::PhantomData<&'a Sge>,
+}
+
+impl<'a> PostSendWr<'a> {
+    fn with_opcode(wr_id: u64, opcode: bindings::ib_wr_opcode, sg_list: &'a [Sge]) -> Self {
+        // SAFETY: All structures of the union are plain C data for which all zeroes is valid.
+        let mut raw: RawSendWr = unsafe { core::mem::zeroed() };
+        // SAFETY: `wr` leads every structure of the union.
+        let wr = unsafe { &mut raw.wr };
+        wr.__bindgen_anon_1.wr_id = wr_id;
+        wr.opcode = opcode;
+        // ib_core and providers never write through `sg_list`.
+        wr.sg_list = sg_list.as_ptr() as *mut bindings::ib_sge;
+        wr.num_sge = sg_list.len() as _;
+        Self {
+            raw,
+            phantom: marker::PhantomData,
+        }
+    }
+
+    /// Creates a SEND of the payload in `sg_list`.
+    pub fn send(wr_id: u64, sg_list: &'a [Sge]) -> Self {
+        Self::with_opcode(wr_id, bindings::ib_wr_opcode_IB_WR_SEND, sg_list)
+    }
+
+    fn rdma(
+        wr_id: u64,
+        opcode: bindings::ib_wr_opcode,
+        sg_list: &'a [Sge],
+        remote_addr: u64,
+        rkey: u32,
+    ) -> Self {
+        let mut wr = Self::with_opcode(wr_id, opcode, sg_list);
+        // SAFETY: RDMA opcodes use `struct ib_rdma_wr`.
+        let rdma = unsafe { &mut wr.raw.rdma };
+        rdma.remote_addr = remote_addr;
+        rdma.rkey = rkey;
+        wr
+    }
+
+    /// Creates an RDMA WRITE of the payload in `sg_list` to `remote_addr` in the MR of `rkey`.
+    pub fn rdma_write(wr_id: u64, sg_list: &'a [Sge], remote_addr: u64, rkey: u32) -> Self {
+        let opcode = bindings::ib_wr_opcode_IB_WR_RDMA_WRITE;
+        Self::rdma(wr_id, opcode, sg_list, remote_addr, rkey)
+    }
+
+    /// Creates an RDMA READ from `remote_addr` in the MR of `rkey` into `sg_list`.
+    pub fn rdma_read(wr_id: u64, sg_list: &'a [Sge], remote_addr: u64, rkey: u32) -> Self {
+        let opcode = bindings::ib_wr_opcode_IB_WR_RDMA_READ;
+        Self::rdma(wr_id, opcode, sg_list, remote_addr, rkey)
+    }
+
+    fn atomic(
+        wr_id: u64,
+        opcode: bindings::ib_wr_opcode,
+        sge: &'a Sge,
+        remote_addr: u64,
+        rkey: u32,
+    ) -> Self {
+        let mut wr = Self::with_opcode(wr_id, opcode, core::slice::from_ref(sge));
+        // SAFETY: Atomic opcodes use `struct ib_atomic_wr`.
+        let atomic = unsafe { &mut wr.raw.atomic };
+        atomic.remote_addr = remote_addr;
+        atomic.rkey = rkey;
+        wr
+    }
+
+    /// Creates an atomic compare and swap at `remote_addr`; the original value is stored in
+    /// `sge`, which must be 8 bytes long.
+    pub fn compare_swap(
+        wr_id: u64,
+        sge: &'a Sge,
+        remote_addr: u64,
+        rkey: u32,
+        compare: u64,
+        swap: u64,
+    ) -> Self {
+        let opcode = bindings::ib_wr_opcode_IB_WR_ATOMIC_CMP_AND_SWP;
+        let mut wr = Self::atomic(wr_id, opcode, sge, remote_addr, rkey);
+        // SAFETY: Atomic opcodes use `struct ib_atomic_wr`.
+        let atomic = unsafe { &mut wr.raw.atomic };
+        atomic.compare_add = compare;
+        atomic.swap = swap;
+        wr
+    }
+
+    /// Creates an atomic fetch and add at `remote_addr`; the original value is stored in `sge`,
+    /// which must be 8 bytes long.
+    pub fn fetch_add(wr_id: u64, sge: &'a Sge, remote_addr: u64, rkey: u32, add: u64) -> Self {
+        let opcode = bindings::ib_wr_opcode_IB_WR_ATOMIC_FETCH_AND_ADD;
+        let mut wr = Self::atomic(wr_id, opcode, sge, remote_addr, rkey);
+        // SAFETY: Atomic opcodes use `struct ib_atomic_wr`.
+        unsafe { wr.raw.atomic.compare_add = add };
+        wr
+    }
+
+    /// Creates a fast registration of `mr` under `key` with the `IB_ACCESS_*` flags `access`.
+    pub fn reg_mr<T: IbDeviceOperations>(
+        wr_id: u64,
+        mr: &'a MemoryRegion<T>,
+        key: u32,
+        access: u32,
+    ) -> Self {
+        let mut wr = Self::with_opcode(wr_id, bindings::ib_wr_opcode_IB_WR_REG_MR, &[]);
+        // SAFETY: REG_MR uses `struct ib_reg_wr`.
+        let reg = unsafe { &mut wr.raw.reg };
+        reg.mr = mr.as_ptr();
+        reg.key = key;
+        reg.access = access as _;
+        wr
+    }
+
+    /// Creates a bind of the type 2 window `mw` to `length` bytes at `addr` in `mr`, or an
+    /// unbind if `mr` is `None`, under the variant of `rkey` with the `IB_ACCESS_*` flags
+    /// `access`.
+    pub fn bind_mw<T: IbDeviceOperations>(
+        wr_id: u64,
+        mw: &'a MemoryWindow<T>,
+        mr: Option<&'a MemoryRegion<T>>,
+        rkey: u32,
+        addr: u64,
+        length: u64,
+        access: u32,
+    ) -> Self {
+        let mut wr = Self::with_opcode(wr_id, bindings::ib_wr_opcode_IB_WR_BIND_MW, &[]);
+        // SAFETY: BIND_MW uses `RawBindMwWr`.
+        let bind = unsafe { &mut wr.raw.bind };
+        bind.mw = mw.as_ptr();
+        bind.mr = mr.map_or(core::ptr::null_mut(), MemoryRegion::as_ptr);
+        bind.rkey = rkey;
+        bind.addr = addr;
+        bind.length = length;
+        bind.access = access as _;
+        wr
+    }
+
+    fn wr(&mut self) -> &mut bindings::ib_send_wr {
+        // SAFETY: `wr` leads every structure of the union.
+        unsafe { &mut self.raw.wr }
+    }
+
+    /// Adds immediate data, in network byte order, to a SEND or RDMA WRITE.
+    ///
+    /// Fails with `EINVAL` for other operations.
+    pub fn with_imm(mut self, imm: u32) -> Result<Self> {
+        let wr = self.wr();
+        wr.opcode = match wr.opcode {
+            bindings::ib_wr_opcode_IB_WR_SEND => bindings::ib_wr_opcode_IB_WR_SEND_WITH_IMM,
+            bindings::ib_wr_opcode_IB_WR_RDMA_WRITE => {
+                bindings::ib_wr_opcode_IB_WR_RDMA_WRITE_WITH_IMM
+            }
+            _ => return Err(EINVAL),
+        };
+        wr.ex.imm_data = imm;
+        Ok(self)
+    }
+
+    fn with_flag(mut self, flag: bindings::ib_send_flags) -> Self {
+        self.wr().send_flags |= flag as core::ffi::c_int;
+        self
+    }
+
+    /// Requests a completion even on QPs created with `IB_SIGNAL_REQ_WR`.
+    pub fn signaled(self) -> Self {
+        self.with_flag(bindings::ib_send_flags_IB_SEND_SIGNALED)
+    }
+
+    /// Asks the responder to raise a solicited event.
+    pub fn solicited(self) -> Self {
+        self.with_flag(bindings::ib_send_flags_IB_SEND_SOLICITED)
+    }
+
+    /// Waits for prior RDMA READs and atomics to complete before starting this request.
+    pub fn fence(self) -> Self {
+        self.with_flag(bindings::ib_send_flags_IB_SEND_FENCE)
+    }
+
+    /// Copies the payload at post time, for SEND and RDMA WRITE.
+    pub fn inline(self) -> Self {
+        self.with_flag(bindings::ib_send_flags_IB_SEND_INLINE)
+    }
+
+    /// Returns the request alone, detached from any chain it was linked into.
+    pub fn as_wr(&mut self) -> &SendWr {
+        let wr = self.wr();
+        wr.next = core::ptr::null();
+        // SAFETY: The request is embedded in the structure of its opcode and borrows its
+        // scatter/gather list for `'a`, which outlives the returned reference.
+        unsafe { SendWr::from_ptr(wr) }
+    }
+
+    /// Links `wrs` into a chain, returning its head or `None` if `wrs` is empty.
+    ///
+    /// The chain borrows `wrs`, so the requests cannot move while it is posted.
+    pub fn link<'b>(wrs: &'b mut [Self]) -> Option<&'b SendWr> {
+        let mut next: *const bindings::ib_send_wr = core::ptr::null();
+        for wr in wrs.iter_mut().rev() {
+            let raw = wr.wr();
+            raw.next = next;
+            next = raw;
+        }
+        if next.is_null() {
+            None
+        } else {
+            // SAFETY: `next` is the head of the chain just built from `wrs`, see `as_wr`.
+            Some(unsafe { SendWr::from_ptr(next) })
+        }
+    }
+}
+
+/// A receive work request posted by a consumer, wraps `struct ib_recv_wr`.
+///
+/// Consumers post chains of requests; [`RecvWr::iter`] walks the chain.
+#[repr(transparent)]
+pub struct RecvWr(UnsafeCell<bindings::ib_recv_wr>);
+
+impl RecvWr {
+    /// Creates a reference to a [`RecvWr`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid chain of receive work requests, with valid scatter/gather lists,
+    /// which stays unchanged for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *const bindings::ib_recv_wr) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `RecvWr` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct ib_recv_wr` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::ib_recv_wr {
+        self.0.get()
+    }
+
+    fn raw(&self) -> &bindings::ib_recv_wr {
+        // SAFETY: The request is valid and unchanged per the `from_ptr` requirements.
+        unsafe { &*self.0.get() }
+    }
+
+    /// Returns the identifier reported in the completion.
+    pub fn wr_id(&self) -> u64 {
+        // SAFETY: Providers that use completion entries read them through `wr_cqe`; the bits are
+        // reported back unchanged either way.
+        unsafe { self.raw().__bindgen_anon_1.wr_id }
+    }
+
+    /// Returns the scatter/gather list the payload is written to.
+    pub fn sg_list(&self) -> &[Sge] {
+        let raw = self.raw();
+        sg_slice(raw.sg_list, raw.num_sge)
+    }
+
+    /// Returns the total length of the scatter/gather list, `None` if it overflows.
+    pub fn total_length(&self) -> Option<u32> {
+        self.sg_list()
+            .iter()
+            .try_fold(0u32, |len, sge| len.checked_add(sge.length()))
+    }
+
+    /// Returns the next request of the chain.
+    pub fn next(&self) -> Option<&RecvWr> {
+        let next = self.raw().next;
+        if next.is_null() {
+            None
+        } else {
+            // SAFETY: The chain is valid per the `from_ptr` requirements.
+            Some(unsafe { RecvWr::from_ptr(next) })
+        }
+    }
+
+    /// Returns an iterator over the chain, starting with `self`.
+    pub fn iter(&self) -> RecvWrIter<'_> {
+        RecvWrIter { next: Some(self) }
+    }
+}
+
+/// Iterator over a chain of receive work requests.
+pub struct RecvWrIter<'a> {
+    next: Option<&'a RecvWr>,
+}
+
+impl<'a> Iterator for RecvWrIter<'a> {
+    type Item = &'a RecvWr;
+
+    fn next(&mut self) -> Option<&'a RecvWr> {
+        let wr = self.next?;
+        self.next = wr.next();
+        Some(wr)
+    }
+}
+
+/// A receive work request built by an in-kernel consumer.
+///
+/// The request borrows its scatter/gather list for `'a`.
+pub struct PostRecvWr<'a> {
+    raw: bindings::ib_recv_wr,
+    phantom: marker::PhantomData<&'a Sge>,
+}
+
+impl<'a> PostRecvWr<'a> {
+    /// Creates a receive of up to the length of `sg_list`.
+    pub fn new(wr_id: u64, sg_list: &'a [Sge]) -> Self {
+        // SAFETY: `struct ib_recv_wr` is plain C data for which all zeroes is valid.
+        let mut raw: bindings::ib_recv_wr = unsafe { core::mem::zeroed() };
+        raw.__bindgen_anon_1.wr_id = wr_id;
+        // ib_core and providers never write through `sg_list`.
+        raw.sg_list = sg_list.as_ptr() as *mut bindings::ib_sge;
+        raw.num_sge = sg_list.len() as _;
+        Self {
+            raw,
+            phantom: marker::PhantomData,
+        }
+    }
+
+    /// Returns the request alone, detached from any chain it was linked into.
+    pub fn as_wr(&mut self) -> &RecvWr {
+        self.raw.next = core::ptr::null();
+        // SAFETY: The request borrows its scatter/gather list for `'a`, which outlives the
+        // returned reference.
+        unsafe { RecvWr::from_ptr(&self.raw) }
+    }
+
+    /// Links `wrs` into a chain, returning its head or `None` if `wrs` is empty.
+    ///
+    /// The chain borrows `wrs`, so the requests cannot move while it is posted.
+    pub fn link<'b>(wrs: &'b mut [Self]) -> Option<&'b RecvWr> {
+        let mut next: *const bindings::ib_recv_wr = core::ptr::null();
+        for wr in wrs.iter_mut().rev() {
+            wr.raw.next = next;
+            next = &wr.raw;
+        }
+        if next.is_null() {
+            None
+        } else {
+            // SAFETY: `next` is the head of the chain just built from `wrs`, see `as_wr`.
+            Some(unsafe { RecvWr::from_ptr(next) })
+        }
+    }
+}
@@ -5,13 +5,56 @@
 
 use alloc::boxed::Box;
 use core::pin::Pin;
+use core::sync::atomic::{AtomicPtr, Ordering};
 use core::{marker, ptr};
 use macros::vtable;
 
 use crate::bindings;
-use crate::error::{code::*, Result};
+use crate::error::{code::*, Error, Result};
+use crate::ib::compat::try_pin;
 use crate::str::CStr;
-use crate::workqueue::{BoxedQueue, Queue};
+use wq::Mlx4WorkQueue;
+
+pub mod buf;
+pub mod cm;
+pub mod cmd;
+pub mod db;
+pub mod device;
+pub mod eq;
+pub mod event;
+pub mod mad;
+pub mod mcg;
+pub mod mtt;
+pub mod qp;
+pub mod slave;
+pub mod uar;
+pub mod wq;
+
+pub use buf::Buf;
+pub use cm::{CmIdMap, SlaveCmId};
+pub use cmd::{CmdInput, Mailbox, Mlx4Cmd};
+pub use db::DoorbellRecord;
+pub use device::{FwVersion, Mlx4Caps, Mlx4Device, Mlx4PortCaps, Mlx4PortType};
+pub use eq::EqVector;
+pub use event::{EventMask, Mlx4DevEvent, PortEvent, QpEvent, QpEventHandler, QpHandler};
+pub use mad::MadIfcFlags;
+pub use mcg::{McgAction, McgKey, McgRequest, McgStatus, McgTable};
+pub use mtt::Mtt;
+pub use qp::{QpRange, QpnAllocator};
+pub use slave::{Egress, Ingress, SlaveId, SlaveTable, Sriov};
+pub use uar::{BlueFlame, Uar};
+pub use wq::{QueueConfig, WorkQueueConfig, WqFlags};
+
+/// The `mlx4_ib_cm` queue of the registration, on which the CM IDs of [`Mlx4Operation::sriov`]
+/// are aged; null if it is left out of the [`WorkQueueConfig`] or nothing is registered.
+///
+/// There is at most one registration per module, see [`Registration::register`].
+static CM_QUEUE: AtomicPtr<bindings::workqueue_struct> = AtomicPtr::new(ptr::null_mut());
+
+/// The `mlx4_ib_mcg` queue of the registration, on which the multicast groups of
+/// [`Mlx4Operation::sriov`] time out and are torn down; null if it is left out of the
+/// [`WorkQueueConfig`] or nothing is registered.
+static MCG_QUEUE: AtomicPtr<bindings::workqueue_struct> = AtomicPtr::new(ptr::null_mut());
 
 /// Infiband mlx4 device registration.
 ///
@@ -19,35 +62,41 @@ pub struct Registration<T: Mlx4Operation> {
     registered: bool,
     #[allow(dead_code)]
     name: &'static CStr,
+    config: Mlx4InterfaceConfig,
+    interface: bindings::mlx4_interface,
     wq: Mlx4WorkQueue,
-    cm_wq: CmWorkQueue,
-    qp_wq: QpWorkQueue,
-    mcg_wq: McgWorkQueue,
+    cm_wq: Mlx4WorkQueue,
+    qp_wq: Mlx4WorkQueue,
+    mcg_wq: Mlx4WorkQueue,
     phantom: marker::PhantomData<T>,
+    _pin: marker::PhantomPinned,
 }
 
 impl<T: Mlx4Operation> Registration<T> {
     /// Creates a new [`Registration`] but does not register it yet.
     ///
     /// It is allowed to move.
-    pub fn new(name: &'static CStr) -> Self {
+    pub fn new(name: &'static CStr, config: Mlx4InterfaceConfig) -> Self {
         // INVARIANT: `registered` is `false`
         Self {
             registered: false,
             name,
+            config,
+            interface: bindings::mlx4_interface::default(),
             wq: Mlx4WorkQueue::new(),
-            cm_wq: CmWorkQueue::new(),
-            qp_wq: QpWorkQueue::new(),
-            mcg_wq: McgWorkQueue::new(),
+            cm_wq: Mlx4WorkQueue::new(),
+            qp_wq: Mlx4WorkQueue::new(),
+            mcg_wq: Mlx4WorkQueue::new(),
             phantom: marker::PhantomData,
+            _pin: marker::PhantomPinned,
         }
     }
 
     /// Registers a infiband mlx4 device.
     ///
     /// Returns a pinned heap-allocated representation of the registration.
-    pub fn new_pinned(name: &'static CStr) -> Result<Pin<Box<Self>>> {
-        let mut r = Pin::from(Box::try_new(Self::new(name))?);
+    pub fn new_pinned(name: &'static CStr, config: Mlx4InterfaceConfig) -> Result<Pin<Box<Self>>> {
+        let mut r = try_pin(Self::new(name, config))?;
         r.as_mut().register()?;
         Ok(r)
     }
@@ -64,71 +113,229 @@ impl<T: Mlx4Operation> Registration<T> {
             return Err(EINVAL);
         }
 
-        match this.wq.init() {
-            Ok(()) => {}
-            Err(e) => return Err(e),
+        let queues = this.config.workqueues();
+        let inited = this
+            .wq
+            .init(queues.events())
+            .and_then(|()| this.qp_wq.init(queues.qp_events()))
+            .and_then(|()| this.cm_wq.init(queues.cm()))
+            .and_then(|()| this.mcg_wq.init(queues.mcg()));
+        if let Err(e) = inited {
+            this.clean_queues();
+            return Err(e);
         }
 
-        match this.qp_wq.init() {
-            Ok(()) => {}
-            Err(e) => {
-                this.wq.clean();
-                return Err(e);
-            }
+        // The queues left out are `None`: their events are dropped and their work not done.
+        if let Err(e) = event::start::<T>(this.wq.queue(), this.qp_wq.queue()) {
+            this.clean_queues();
+            return Err(e);
         }
-
-        match this.cm_wq.init() {
-            Ok(()) => {}
-            Err(e) => {
-                this.wq.clean();
-                this.qp_wq.clean();
-                return Err(e);
-            }
+        if let Some(cm_wq) = this.cm_wq.queue() {
+            CM_QUEUE.store(cm_wq.as_raw(), Ordering::Release);
         }
-
-        match this.mcg_wq.init() {
-            Ok(()) => {}
-            Err(e) => {
-                this.wq.clean();
-                this.cm_wq.clean();
-                this.qp_wq.clean();
-                return Err(e);
-            }
+        if let Some(mcg_wq) = this.mcg_wq.queue() {
+            MCG_QUEUE.store(mcg_wq.as_raw(), Ordering::Release);
         }
 
-        // SAFETY: The adapter is compatible with the mlx4 register
-        unsafe {
-            bindings::mlx4_register_interface(Mlx4OperationTable::<T>::build());
+        this.interface = Mlx4OperationTable::<T>::build(this.config);
+        // SAFETY: `this.interface` is pinned and only unregistered in `drop`; mlx4_core links it
+        // into its interface list and calls `add` for every device already probed.
+        let ret = unsafe { bindings::mlx4_register_interface(&mut this.interface) };
+        if ret < 0 {
+            MCG_QUEUE.store(ptr::null_mut(), Ordering::Release);
+            CM_QUEUE.store(ptr::null_mut(), Ordering::Release);
+            event::stop();
+            this.clean_queues();
+            return Err(Error::from_kernel_errno(ret));
         }
 
         this.registered = true;
         Ok(())
     }
+
+    /// Destroys the workqueues, once nothing queues work on them anymore.
+    fn clean_queues(&mut self) {
+        self.mcg_wq.clean();
+        self.cm_wq.clean();
+        self.qp_wq.clean();
+        self.wq.clean();
+    }
+
+    /// Returns the interface configuration used by the samples and the smoke test.
+    ///
+    /// It goes through every [`Mlx4InterfaceConfig`] setter, so that they keep being exercised as
+    /// the configuration grows; the `kunit` cases at the bottom of this file, run by the
+    /// `rust_rdma` KUnit suite, check that they still take effect.
+    pub const fn example_interface_config() -> Mlx4InterfaceConfig {
+        Mlx4InterfaceConfig::new()
+            .with_protocol(Mlx4Protocol::IbIpv6)
+            .with_bonding(true)
+            .with_workqueues(
+                WorkQueueConfig::new()
+                    .with_events(
+                        QueueConfig::ordered(crate::c_str!("mlx4_ib"), WqFlags::HIGHPRI)
+                            .with_name(crate::c_str!("rust_mlx4")),
+                    )
+                    .with_qp_events(QueueConfig::ordered(
+                        crate::c_str!("rust_mlx4_qp_event_wq"),
+                        WqFlags::HIGHPRI,
+                    ))
+                    .with_cm(
+                        QueueConfig::new(crate::c_str!("rust_mlx4_cm"), WqFlags::UNBOUND)
+                            .with_max_active(1),
+                    )
+                    .with_mcg(QueueConfig::ordered(
+                        crate::c_str!("rust_mlx4_mcg"),
+                        WqFlags::MEM_RECLAIM,
+                    )),
+            )
+    }
+
+    /// Returns the event mask used by the samples and the smoke test, to be applied with
+    /// [`Registration::set_event_mask`] once registered.
+    pub const fn example_config() -> EventMask {
+        EventMask::ALL
+    }
+
+    /// Mutes the event classes not in `mask`, among the ones `T` has handlers for.
+    ///
+    /// Muted events are dropped in interrupt context, before reaching a workqueue.
+    pub fn set_event_mask(&self, mask: EventMask) {
+        event::set_mask(mask);
+    }
+
+    /// Returns the event classes that currently reach the handlers of `T`.
+    pub fn event_mask(&self) -> EventMask {
+        event::subscribed::<T>().intersection(event::mask())
+    }
+
+    /// Returns the number of events lost because the handlers fell behind.
+    pub fn dropped_events(&self) -> u64 {
+        event::dropped()
+    }
+
+    /// Returns the number of QP events suppressed because their QP was destroyed.
+    pub fn suppressed_qp_events(&self) -> u64 {
+        event::suppressed()
+    }
 }
 
 impl<T: Mlx4Operation> Drop for Registration<T> {
     /// Removes the registration from the kernel if it has completed successfully before.
     fn drop(&mut self) {
         if self.registered {
-            self.mcg_wq.clean();
-            self.cm_wq.clean();
-            self.qp_wq.clean();
-            self.wq.clean();
+            // SAFETY: [`self.interface`] was registered in `register` and has not moved since.
+            // mlx4_core calls `remove` for every device before this returns, so no callback
+            // runs past it.
+            unsafe { bindings::mlx4_unregister_interface(&mut self.interface) };
+            event::stop();
+            CM_QUEUE.store(ptr::null_mut(), Ordering::Release);
+            MCG_QUEUE.store(ptr::null_mut(), Ordering::Release);
+            self.clean_queues();
         }
     }
 }
 
+// SAFETY: The methods taking `&self` only read `config`, which is written through `&mut self` in
+// `register` only, and otherwise reach the module state of the event dispatch, which synchronises
+// itself: the event mask and the counters are atomics. The interface and the workqueues are only
+// touched through `&mut self`, and by mlx4_core under its own lock.
+unsafe impl<T: Mlx4Operation> Sync for Registration<T> {}
+
+/// Protocol an interface handles, `enum mlx4_protocol`.
+///
+/// It selects the devices `get_dev` is answered for, e.g. so that the Ethernet driver can find the
+/// net device of a port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Mlx4Protocol {
+    /// InfiniBand, with IPv6 addressing of RoCE ports.
+    IbIpv6 = 0,
+    /// Ethernet.
+    Eth = 1,
+    /// InfiniBand, with IPv4 addressing of RoCE ports.
+    IbIpv4 = 2,
+    /// Fibre Channel over Ethernet.
+    Fcoe = 3,
+}
+
+/// Configuration of the `struct mlx4_interface` of a [`Registration`].
+#[derive(Clone, Copy, Debug)]
+pub struct Mlx4InterfaceConfig {
+    protocol: Mlx4Protocol,
+    bonding: bool,
+    workqueues: WorkQueueConfig,
+}
+
+impl Mlx4InterfaceConfig {
+    /// `MLX4_INTFF_BONDING`
+    const INTFF_BONDING: u32 = 1 << 0;
+
+    /// Creates the configuration of an InfiniBand interface that supports bonding.
+    pub const fn new() -> Self {
+        Self {
+            protocol: Mlx4Protocol::IbIpv6,
+            bonding: true,
+            workqueues: WorkQueueConfig::new(),
+        }
+    }
+
+    /// Handles `protocol` instead of InfiniBand over IPv6.
+    pub const fn with_protocol(mut self, protocol: Mlx4Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Sets whether the interface supports bonding of the two ports.
+    ///
+    /// mlx4_core only bonds the ports of a device if every interface attached to it does.
+    pub const fn with_bonding(mut self, bonding: bool) -> Self {
+        self.bonding = bonding;
+        self
+    }
+
+    /// Sets up the workqueues of the registration after `workqueues`.
+    pub const fn with_workqueues(mut self, workqueues: WorkQueueConfig) -> Self {
+        self.workqueues = workqueues;
+        self
+    }
+
+    /// Returns the protocol.
+    pub const fn protocol(&self) -> Mlx4Protocol {
+        self.protocol
+    }
+
+    /// Returns the configuration of the workqueues.
+    pub const fn workqueues(&self) -> &WorkQueueConfig {
+        &self.workqueues
+    }
+
+    /// Returns the `MLX4_INTFF_*` flags.
+    pub const fn flags(&self) -> u32 {
+        if self.bonding {
+            Self::INTFF_BONDING
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Mlx4InterfaceConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// Build kernel's `struct mlx4_interface` type with mlx4 device operation.
 pub struct Mlx4OperationTable<T>(marker::PhantomData<T>);
 
 impl<T: Mlx4Operation> Mlx4OperationTable<T> {
     /// Builds an instance of [`struct mlx4_interface`].
     ///
-    /// # Safety
-    ///
-    /// The caller must ensure that the adapter is compatible with the way the device is registered.
-    pub fn build() -> *mut bindings::mlx4_interface {
-        return &mut bindings::mlx4_interface {
+    /// The interface must not move once it is passed to `mlx4_register_interface`, as mlx4_core
+    /// links it into its list; [`Registration`] keeps it pinned until it unregisters it.
+    pub fn build(config: Mlx4InterfaceConfig) -> bindings::mlx4_interface {
+        bindings::mlx4_interface {
             add: Some(Self::add_callback),
             remove: Some(Self::remove_callback),
             event: Some(Self::event_callback),
@@ -138,32 +345,102 @@ impl<T: Mlx4Operation> Mlx4OperationTable<T> {
                 next: ptr::null_mut(),
                 prev: ptr::null_mut(),
             },
-            // MLX4_PROT_IB_IPV6
-            protocol: 0,
-            // MLX4_INTFF_BONDING
-            flags: 1,
-        };
+            protocol: config.protocol() as _,
+            flags: config.flags() as _,
+        }
     }
 
-    unsafe extern "C" fn add_callback(_dev: *mut bindings::mlx4_dev) -> *mut core::ffi::c_void {
-        let _ = T::add();
-        return ptr::null_mut();
+    unsafe extern "C" fn add_callback(dev: *mut bindings::mlx4_dev) -> *mut core::ffi::c_void {
+        // SAFETY: mlx4_core passes a probed device that outlives the interface's context.
+        let dev = unsafe { Mlx4Device::from_ptr(dev) };
+        match T::add(dev) {
+            Ok(context) => {
+                if let Some(sriov) = T::sriov(&context) {
+                    let queue = CM_QUEUE.load(Ordering::Acquire);
+                    if !queue.is_null() {
+                        // SAFETY: The queue is destroyed only once the interface is unregistered,
+                        // after `remove_callback` stopped the map.
+                        unsafe { sriov.cm_ids().start(queue) };
+                    }
+                    let queue = MCG_QUEUE.load(Ordering::Acquire);
+                    if !queue.is_null() {
+                        // SAFETY: As above, `remove_callback` stops the table first.
+                        unsafe { sriov.mcgs().start::<T>(queue) };
+                    }
+                }
+                // A null context tells mlx4_core that the device was not added, so `remove` is
+                // only called with a context returned here.
+                // SAFETY: The context is turned back into a pinned box by `remove_callback` only.
+                Box::into_raw(unsafe { Pin::into_inner_unchecked(context) }).cast()
+            }
+            Err(_) => ptr::null_mut(),
+        }
     }
 
     unsafe extern "C" fn remove_callback(
-        _dev: *mut bindings::mlx4_dev,
-        _context: *mut core::ffi::c_void,
+        dev: *mut bindings::mlx4_dev,
+        context: *mut core::ffi::c_void,
     ) {
-        let _ = T::remove();
+        // No queued event may refer to the device once it is gone.
+        event::flush();
+        if context.is_null() {
+            return;
+        }
+        // SAFETY: mlx4_core passes the device and the context `add_callback` returned for it,
+        // which is not used anymore once this returns.
+        let (dev, context) = unsafe {
+            (
+                Mlx4Device::from_ptr(dev),
+                Pin::new_unchecked(Box::from_raw(context.cast::<T::Context>())),
+            )
+        };
+        if let Some(sriov) = T::sriov(&context) {
+            sriov.mcgs().teardown();
+            sriov.mcgs().stop();
+            sriov.cm_ids().stop();
+        }
+        T::remove(dev, context);
     }
 
     unsafe extern "C" fn event_callback(
-        _dev: *mut bindings::mlx4_dev,
-        _context: *mut core::ffi::c_void,
-        _event: bindings::mlx4_dev_event,
-        _param: core::ffi::c_ulong,
+        dev: *mut bindings::mlx4_dev,
+        context: *mut core::ffi::c_void,
+        event: bindings::mlx4_dev_event,
+        param: core::ffi::c_ulong,
     ) {
-        let _ = T::event();
+        // SAFETY: The arguments come straight from mlx4_core.
+        let event = match unsafe { Mlx4DevEvent::from_raw(event, param) } {
+            Some(event) => event,
+            None => return,
+        };
+        event::dev_event::<T>(dev, context, event);
+    }
+
+    /// `mlx4_qp.event` handler forwarding to [`Mlx4Operation::qp_event`].
+    ///
+    /// Drivers install it on the QPs they allocate with `mlx4_qp_alloc`.
+    ///
+    /// # Safety
+    ///
+    /// Only mlx4_core may call this, with a valid QP.
+    pub unsafe extern "C" fn qp_event_callback(
+        qp: *mut bindings::mlx4_qp,
+        event: bindings::mlx4_event,
+    ) {
+        // SAFETY: Guaranteed by the safety requirements.
+        event::qp_event::<T>(unsafe { (*qp).qpn }, event);
+    }
+
+    /// `mlx4_cq.comp` handler forwarding to [`Mlx4Operation::completion`].
+    ///
+    /// Drivers install it on the CQs they allocate with `mlx4_cq_alloc`.
+    ///
+    /// # Safety
+    ///
+    /// Only mlx4_core may call this, with a valid CQ.
+    pub unsafe extern "C" fn cq_comp_callback(cq: *mut bindings::mlx4_cq) {
+        // SAFETY: Guaranteed by the safety requirements.
+        event::completion::<T>(unsafe { (*cq).cqn });
     }
 }
 
@@ -172,117 +449,124 @@ impl<T: Mlx4Operation> Mlx4OperationTable<T> {
 /// You implement this trait whenever you would create a `struct mlx4_interface`.
 #[vtable]
 pub trait Mlx4Operation {
-    /// Add a new mlx4 ib device.
-    fn add() -> Result;
-    /// Remove mlx4 ib device.
-    fn remove() -> Result;
-    /// Respond to specific mlx4 ib device event
-    fn event() -> Result;
-}
-
-pub(crate) struct Mlx4WorkQueue {
-    wq: Option<BoxedQueue>,
-}
+    /// Driver state of one device, created by [`Mlx4Operation::add`].
+    type Context: Send + Sync;
 
-impl Mlx4WorkQueue {
-    pub(crate) fn new() -> Self {
-        Self { wq: None }
-    }
+    /// Add a new mlx4 ib device.
+    ///
+    /// `dev` carries the firmware version and device limits read by mlx4_core. The returned
+    /// context is handed back to [`Mlx4Operation::remove`]; on error the device is not added.
+    ///
+    /// The device events are delivered to the typed handlers below, in process context.
+    fn add(dev: &Mlx4Device) -> Result<Pin<Box<Self::Context>>>;
+    /// Remove mlx4 ib device, dropping the context returned by [`Mlx4Operation::add`].
+    fn remove(dev: &Mlx4Device, context: Pin<Box<Self::Context>>);
 
-    pub(crate) fn init(&mut self) -> Result {
-        let wq_tmp = Queue::try_new(format_args!("mlx4_ib"), 655369, 1);
-        self.wq = match wq_tmp {
-            Ok(wq) => Some(wq),
-            Err(e) => return Err(e),
-        };
+    /// Handles a port state or management change, on the `mlx4_ib` workqueue.
+    fn port_event(_dev: &Mlx4Device, _port: u8, _event: PortEvent) {}
 
-        Ok(())
-    }
+    /// Handles an unrecoverable device error, on the `mlx4_ib` workqueue.
+    fn catastrophic_error(_dev: &Mlx4Device) {}
 
-    pub(crate) fn clean(&mut self) {
-        if self.wq.is_some() {
-            drop(self.wq.take().unwrap());
-        }
-    }
-}
-
-pub(crate) struct CmWorkQueue {
-    cm_wq: Option<BoxedQueue>,
-}
+    /// Handles an asynchronous event of QP `qpn`, on the `mlx4_ib_qp_event_wq` workqueue.
+    ///
+    /// Only called for the QPs without a [`QpHandler`].
+    fn qp_event(_qpn: u32, _event: QpEvent) {}
 
-impl CmWorkQueue {
-    pub(crate) fn new() -> Self {
-        Self { cm_wq: None }
-    }
+    /// Sets up the state of SR-IOV slave `slave`, which came up, on the `mlx4_ib` workqueue.
+    fn slave_init(_dev: &Mlx4Device, _slave: SlaveId) {}
 
-    pub(crate) fn init(&mut self) -> Result {
-        let cm_wq_tmp = Queue::try_new(format_args!("mlx4_ib_cm"), 0, 0);
-        self.cm_wq = match cm_wq_tmp {
-            Ok(cm_wq) => Some(cm_wq),
-            Err(e) => return Err(e),
-        };
+    /// Tears down the state of SR-IOV slave `slave`, which is shutting down, on the `mlx4_ib`
+    /// workqueue.
+    ///
+    /// The CM IDs proxied for the slave in [`Mlx4Operation::sriov`] are already dropped, see
+    /// [`CmIdMap::remove_slave`].
+    fn slave_shutdown(_dev: &Mlx4Device, _slave: SlaveId) {}
 
-        Ok(())
+    /// Returns the SR-IOV state of the device whose context is `context`.
+    ///
+    /// Master drivers keep an [`Sriov`] in their context and pass the MADs of their slaves through
+    /// it. The abstraction tracks the slaves of the device in it, ages its CM IDs on `mlx4_ib_cm`
+    /// and runs its multicast groups on `mlx4_ib_mcg` while the device is added, tears them down
+    /// before [`Mlx4Operation::remove`] and drops the IDs of slaves shutting down.
+    fn sriov(_context: &Self::Context) -> Option<&Sriov> {
+        None
     }
 
-    pub(crate) fn clean(&mut self) {
-        if self.cm_wq.is_some() {
-            drop(self.cm_wq.take().unwrap());
-        }
-    }
-}
+    /// Handles a completion event of CQ `cqn`, in interrupt context.
+    fn completion(_cqn: u32) {}
 
-pub(crate) struct McgWorkQueue {
-    clean_wq: Option<BoxedQueue>,
+    /// Carries out `action` for the multicast group `key`: sends a MAD to the SA or answers a
+    /// slave. Called from [`McgTable::request`] and [`McgTable::response`], and on `mlx4_ib_mcg`
+    /// for timeouts and teardown.
+    fn mcg_action(_key: McgKey, _action: McgAction) {}
 }
 
-impl McgWorkQueue {
-    pub(crate) fn new() -> Self {
-        Self { clean_wq: None }
-    }
+/// Cases of the `rust_rdma` KUnit suite checking the example configurations of [`Registration`].
+#[cfg(CONFIG_RUST_RDMA_KUNIT_TEST)]
+pub(crate) mod kunit {
+    use super::{EventMask, Mlx4OperationTable, Mlx4Protocol, Registration, WqFlags};
+    use crate::bindings;
+    use crate::ib::kunit::expect;
+    use crate::rdma::prelude::*;
 
-    pub(crate) fn init(&mut self) -> Result {
-        let clean_wq_tmp = Queue::try_new(format_args!("mlx4_ib_mcg"), 655369, 1);
-        self.clean_wq = match clean_wq_tmp {
-            Ok(clean_wq) => Some(clean_wq),
-            Err(e) => return Err(e),
-        };
+    /// `MLX4_INTFF_BONDING`
+    const INTFF_BONDING: u32 = 1 << 0;
 
-        Ok(())
-    }
-
-    pub(crate) fn clean(&mut self) {
-        if self.clean_wq.is_some() {
-            drop(self.clean_wq.take().unwrap());
-        }
-    }
-}
+    struct Ops;
 
-pub(crate) struct QpWorkQueue {
-    mlx4_ib_qp_event_wq: Option<BoxedQueue>,
-}
+    #[vtable]
+    impl mlx4::Mlx4Operation for Ops {
+        type Context = ();
 
-impl QpWorkQueue {
-    pub(crate) fn new() -> Self {
-        Self {
-            mlx4_ib_qp_event_wq: None,
+        fn add(_dev: &mlx4::Mlx4Device) -> Result<Pin<Box<()>>> {
+            Ok(Pin::from(Box::try_new(())?))
         }
+        fn remove(_dev: &mlx4::Mlx4Device, _context: Pin<Box<()>>) {}
     }
 
-    pub(crate) fn init(&mut self) -> Result {
-        let mlx4_ib_qp_event_wq_tmp =
-            Queue::try_new(format_args!("mlx4_ib_qp_event_wq"), 655361, 1);
-        self.mlx4_ib_qp_event_wq = match mlx4_ib_qp_event_wq_tmp {
-            Ok(mlx4_ib_qp_event_wq) => Some(mlx4_ib_qp_event_wq),
-            Err(e) => return Err(e),
-        };
-
-        Ok(())
+    /// Checks [`Registration::example_interface_config`].
+    pub(crate) unsafe extern "C" fn interface_config(test: *mut bindings::kunit) {
+        let config = Registration::<Ops>::example_interface_config();
+        expect!(test, config.protocol() == Mlx4Protocol::IbIpv6);
+        expect!(test, config.flags() == INTFF_BONDING);
+
+        let interface = Mlx4OperationTable::<Ops>::build(config);
+        expect!(
+            test,
+            interface.protocol as u32 == Mlx4Protocol::IbIpv6 as u32
+        );
+        expect!(test, interface.flags as u32 == INTFF_BONDING);
+
+        let wqs = config.workqueues();
+        let events = wqs.events();
+        expect!(test, events.is_enabled() && events.is_ordered());
+        expect!(test, events.flags().contains(WqFlags::HIGHPRI));
+        expect!(test, events.name().as_bytes() == b"rust_mlx4");
+        let qp_events = wqs.qp_events();
+        expect!(test, qp_events.is_enabled() && qp_events.is_ordered());
+        expect!(test, qp_events.flags().contains(WqFlags::HIGHPRI));
+        expect!(
+            test,
+            qp_events.name().as_bytes() == b"rust_mlx4_qp_event_wq"
+        );
+        let cm = wqs.cm();
+        expect!(test, cm.is_enabled() && !cm.is_ordered());
+        expect!(test, cm.flags().contains(WqFlags::UNBOUND));
+        expect!(test, cm.name().as_bytes() == b"rust_mlx4_cm");
+        let mcg = wqs.mcg();
+        expect!(test, mcg.is_enabled() && mcg.is_ordered());
+        expect!(test, mcg.flags().contains(WqFlags::MEM_RECLAIM));
+        expect!(test, mcg.name().as_bytes() == b"rust_mlx4_mcg");
     }
 
-    pub(crate) fn clean(&mut self) {
-        if self.mlx4_ib_qp_event_wq.is_some() {
-            drop(self.mlx4_ib_qp_event_wq.take().unwrap());
-        }
+    /// Checks [`Registration::example_config`].
+    pub(crate) unsafe extern "C" fn event_mask(test: *mut bindings::kunit) {
+        let mask = Registration::<Ops>::example_config();
+        expect!(test, mask.bits() == EventMask::ALL.bits());
+        expect!(
+            test,
+            mask.contains(EventMask::PORT) && mask.contains(EventMask::CATASTROPHIC)
+        );
     }
 }
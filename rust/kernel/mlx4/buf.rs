@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Queue buffers.
+//!
+//! The work queues, CQs and EQs of an mlx4 device live in host memory the HCA accesses by DMA,
+//! through the translation of an MTT. mlx4_core allocates such a buffer with `mlx4_buf_alloc`:
+//! in one coherent chunk if it is small enough, else page by page, so that large queues do not
+//! need high-order allocations. [`Buf`] owns one of them and frees it when dropped; its entries are
+//! reached through [`Buf::get`] and [`Buf::get_mut`], and the pages to write to the MTT through
+//! [`Buf::dma_pages`].
+
+use core::slice;
+
+use super::device::Mlx4Device;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+
+/// A DMA buffer allocated by mlx4_core, freed when dropped.
+///
+/// # Invariants
+///
+/// `buf` was allocated on `dev` by `mlx4_buf_alloc` with `size` bytes and is not freed yet, and
+/// `dev` stays valid until the [`Buf`] is dropped.
+pub struct Buf {
+    dev: *mut bindings::mlx4_dev,
+    size: usize,
+    buf: bindings::mlx4_buf,
+}
+
+impl Buf {
+    /// Allocates a zeroed buffer of `size` bytes, in one chunk if it is at most `max_direct`
+    /// bytes, else in pages.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`super::Mlx4Operation::add`] ensures this, as [`super::Mlx4Operation::remove`] drops the
+    /// context before mlx4_core removes the device.
+    pub unsafe fn try_new(dev: &Mlx4Device, size: usize, max_direct: usize) -> Result<Self> {
+        if size == 0 || size > i32::MAX as usize {
+            return Err(EINVAL);
+        }
+        let mut buf = bindings::mlx4_buf::default();
+        // SAFETY: The device is valid and `buf` a valid out pointer.
+        let err = unsafe {
+            bindings::mlx4_buf_alloc(
+                dev.as_ptr(),
+                size as _,
+                max_direct.min(i32::MAX as usize) as _,
+                &mut buf,
+            )
+        };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // INVARIANT: The buffer was just allocated, and the safety requirements keep the device
+        // valid until it is freed.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            size,
+            buf,
+        })
+    }
+
+    /// Returns the raw `struct mlx4_dev` pointer of the device the buffer was allocated on.
+    pub fn device(&self) -> *mut bindings::mlx4_dev {
+        self.dev
+    }
+
+    /// Returns the raw `struct mlx4_buf` pointer, e.g. for `mlx4_cq_alloc`.
+    pub fn as_ptr(&self) -> *const bindings::mlx4_buf {
+        &self.buf
+    }
+
+    /// Returns the size of the buffer, in bytes.
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Returns `true` if the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns `true` if the buffer is one contiguous chunk.
+    pub fn is_direct(&self) -> bool {
+        self.buf.nbufs == 1
+    }
+
+    /// Returns the number of MTT pages of the buffer.
+    pub fn npages(&self) -> u32 {
+        self.buf.npages as u32
+    }
+
+    /// Returns the log2 of the size of the MTT pages of the buffer.
+    pub fn page_shift(&self) -> u32 {
+        self.buf.page_shift as u32
+    }
+
+    /// Returns the whole buffer, if it is one contiguous chunk.
+    pub fn as_slice(&self) -> Option<&[u8]> {
+        if !self.is_direct() {
+            return None;
+        }
+        // SAFETY: By the type invariants the chunk holds `size` bytes.
+        Some(unsafe { slice::from_raw_parts(self.buf.direct.buf.cast(), self.size) })
+    }
+
+    /// Returns the whole buffer mutably, if it is one contiguous chunk.
+    pub fn as_mut_slice(&mut self) -> Option<&mut [u8]> {
+        if !self.is_direct() {
+            return None;
+        }
+        // SAFETY: As in `as_slice`, and the mutable borrow of `self` makes the access exclusive.
+        Some(unsafe { slice::from_raw_parts_mut(self.buf.direct.buf.cast(), self.size) })
+    }
+
+    /// Returns the `len` bytes at `offset`, like `mlx4_buf_offset` does for an entry.
+    ///
+    /// Fails with `EINVAL` if they are out of bounds or, in a paged buffer, cross a page, which
+    /// entries of power-of-two size never do.
+    pub fn get(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        let ptr = self.entry(offset, len)?;
+        // SAFETY: `entry` checked the bytes to be in one chunk of the buffer.
+        Ok(unsafe { slice::from_raw_parts(ptr, len) })
+    }
+
+    /// Returns the `len` bytes at `offset` mutably, see [`Buf::get`].
+    pub fn get_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8]> {
+        let ptr = self.entry(offset, len)?;
+        // SAFETY: As in `get`, and the mutable borrow of `self` makes the access exclusive.
+        Ok(unsafe { slice::from_raw_parts_mut(ptr, len) })
+    }
+
+    fn entry(&self, offset: usize, len: usize) -> Result<*mut u8> {
+        let end = offset.checked_add(len).ok_or(EINVAL)?;
+        if end > self.size {
+            return Err(EINVAL);
+        }
+        if self.is_direct() {
+            // SAFETY: The range was checked to be in the chunk.
+            return Ok(unsafe { self.buf.direct.buf.cast::<u8>().add(offset) });
+        }
+        let page = offset >> bindings::PAGE_SHIFT;
+        let in_page = offset & (bindings::PAGE_SIZE as usize - 1);
+        if in_page + len > bindings::PAGE_SIZE as usize {
+            return Err(EINVAL);
+        }
+        // SAFETY: A paged buffer lists `nbufs` pages of `PAGE_SIZE` bytes covering `size`, so the
+        // page of `offset` is in the list.
+        Ok(unsafe {
+            (*self.buf.page_list.add(page))
+                .buf
+                .cast::<u8>()
+                .add(in_page)
+        })
+    }
+
+    /// Returns an iterator over the DMA addresses of the MTT pages of the buffer, in order.
+    pub fn dma_pages(&self) -> impl Iterator<Item = u64> + '_ {
+        let shift = self.page_shift();
+        (0..self.buf.npages as usize).map(move |i| {
+            if self.is_direct() {
+                self.buf.direct.map + ((i as u64) << shift)
+            } else {
+                // SAFETY: A paged buffer lists `npages` pages.
+                unsafe { (*self.buf.page_list.add(i)).map }
+            }
+        })
+    }
+
+    /// Writes the pages of the buffer to `mtt`, with `mlx4_buf_write_mtt`.
+    ///
+    /// # Safety
+    ///
+    /// `mtt` must be a valid MTT of the device of the buffer, with at least [`Buf::npages`]
+    /// entries of [`Buf::page_shift`].
+    pub unsafe fn write_mtt(&self, mtt: *mut bindings::mlx4_mtt) -> Result {
+        // SAFETY: The device and the buffer are valid by the type invariants, and `mtt` by the
+        // safety requirements; `mlx4_buf_write_mtt` only reads the buffer.
+        let err =
+            unsafe { bindings::mlx4_buf_write_mtt(self.dev, mtt, &self.buf as *const _ as *mut _) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Buf<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the buffer was allocated on the device with `size`
+        // bytes, and the device is still valid.
+        unsafe { bindings::mlx4_buf_free(self.dev, self.size as _, &mut self.buf) };
+    }
+}
+
+// SAFETY: The buffer can be freed from any thread.
+unsafe impl Send for Buf {}
+// SAFETY: Shared references only read the buffer.
+unsafe impl Sync for Buf {}
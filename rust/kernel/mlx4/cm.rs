@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! CM MAD proxying for SR-IOV guests.
+//!
+//! Under SR-IOV the master forwards the CM MADs of its slaves, and rewrites their communication
+//! IDs so that IDs picked independently by several guests cannot collide on the wire, like the C
+//! driver's `mlx4_ib_cm`: each (slave, slave CM ID) pair gets a paravirtual ID that is unique on
+//! the device, see [`crate::rdma::cm_proxy`]. The paravirtual MAD multiplexing passes the CM MADs
+//! through [`CmIdMap::multiplex`] on their way out, and [`CmIdMap::demux`] on their way in. Once a
+//! connection is torn down (DREQ) or rejected, its entry is kept a while for late MADs and then
+//! dropped by a delayed work item on `mlx4_ib_cm`.
+//!
+//! Each device has its own map, in the [`super::Sriov`] the driver returns from
+//! [`super::Mlx4Operation::sriov`]; the abstraction ages it while the device is added.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::marker::PhantomPinned;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::ptr;
+
+use super::slave::SlaveId;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::ib::compat::try_pin;
+use crate::rdma::cm_proxy::{ProxyError, PvCmIds};
+use crate::rdma::gid::Gid;
+use crate::sync::smutex::Mutex;
+
+/// How long the ID of a torn down connection is kept, like `CM_CLEANUP_CACHE_TIMEOUT`.
+pub const CM_CLEANUP_TIMEOUT_MS: u32 = 30_000;
+
+/// Largest number of CM IDs proxied at once per device.
+pub const MAX_CM_IDS: usize = 512;
+
+/// A communication ID as chosen by a slave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlaveCmId {
+    /// Function number of the slave.
+    pub slave: SlaveId,
+    /// CM ID picked by the slave.
+    pub sl_cm_id: u32,
+}
+
+impl From<ProxyError> for Error {
+    fn from(e: ProxyError) -> Self {
+        match e {
+            ProxyError::NotCm => EINVAL,
+            ProxyError::UnknownId | ProxyError::NoSlave => ENOENT,
+            ProxyError::Full => ENOMEM,
+        }
+    }
+}
+
+struct State {
+    ids: PvCmIds<SlaveId, MAX_CM_IDS>,
+    /// The queue aging the IDs, null while the device is not added.
+    queue: *mut bindings::workqueue_struct,
+}
+
+/// # Invariants
+///
+/// `work` is initialised, and only queued on `state.queue` while it is non-null.
+struct Inner {
+    state: Mutex<State>,
+    work: UnsafeCell<MaybeUninit<bindings::delayed_work>>,
+    _pin: PhantomPinned,
+}
+
+/// The ID map of the CM proxy of a device.
+pub struct CmIdMap {
+    inner: Pin<Box<Inner>>,
+}
+
+// SAFETY: The state is protected by its mutex, and the work item is only handed to the workqueue
+// core, which synchronises its own accesses.
+unsafe impl Send for CmIdMap {}
+// SAFETY: See the `Send` implementation.
+unsafe impl Sync for CmIdMap {}
+
+fn jiffies() -> u64 {
+    // SAFETY: FFI call without preconditions.
+    unsafe { bindings::get_jiffies_64() }
+}
+
+fn cleanup_delay() -> u64 {
+    // SAFETY: FFI call without preconditions.
+    unsafe { bindings::msecs_to_jiffies(CM_CLEANUP_TIMEOUT_MS) as u64 }
+}
+
+impl CmIdMap {
+    /// Creates an empty map, aged once its device is added.
+    pub fn try_new() -> Result<Self> {
+        let inner = try_pin(Inner {
+            state: Mutex::new(State {
+                ids: PvCmIds::new(),
+                queue: ptr::null_mut(),
+            }),
+            work: UnsafeCell::new(MaybeUninit::uninit()),
+            _pin: PhantomPinned,
+        })?;
+        // SAFETY: The work item is pinned with the map and not queued yet.
+        unsafe { bindings::init_delayed_work(inner.work(), Some(Inner::work_fn)) };
+        // INVARIANT: The work item was just initialised.
+        Ok(Self { inner })
+    }
+
+    /// Returns the paravirtual ID of `id`, allocating one for a new connection.
+    ///
+    /// Fails with `ENOMEM` if [`MAX_CM_IDS`] connections are proxied already.
+    pub fn pv_cm_id(&self, id: SlaveCmId) -> Result<u32> {
+        let mut state = self.inner.state.lock();
+        Ok(state.ids.get_or_alloc(id.slave, id.sl_cm_id)?)
+    }
+
+    /// Returns the slave ID behind the paravirtual ID `pv_cm_id`, for incoming MADs.
+    pub fn lookup(&self, pv_cm_id: u32) -> Option<SlaveCmId> {
+        let state = self.inner.state.lock();
+        let (slave, sl_cm_id) = state.ids.lookup(pv_cm_id)?;
+        Some(SlaveCmId { slave, sl_cm_id })
+    }
+
+    /// Schedules the removal of `id` after [`CM_CLEANUP_TIMEOUT_MS`], once its connection is torn
+    /// down or rejected.
+    pub fn schedule_delete(&self, id: SlaveCmId) {
+        let mut state = self.inner.state.lock();
+        let deadline = jiffies().wrapping_add(cleanup_delay());
+        state.ids.schedule(id.slave, id.sl_cm_id, deadline);
+        self.inner.arm(&state);
+    }
+
+    /// Rewrites the CM MAD `mad` sent by `slave` before it goes on the wire, see
+    /// [`PvCmIds::multiplex`].
+    ///
+    /// Fails with `EINVAL` if `mad` is not a CM MAD, with `ENOENT` if it refers to a connection
+    /// without a paravirtual ID and with `ENOMEM` if [`MAX_CM_IDS`] connections are proxied
+    /// already. The MAD must then be dropped.
+    pub fn multiplex(&self, slave: SlaveId, mad: &mut [u8]) -> Result {
+        let mut state = self.inner.state.lock();
+        let deadline = jiffies().wrapping_add(cleanup_delay());
+        state.ids.multiplex(slave, mad, deadline)?;
+        self.inner.arm(&state);
+        Ok(())
+    }
+
+    /// Returns the slave the received CM MAD `mad` is for and rewrites it for the slave, see
+    /// [`PvCmIds::demux`].
+    ///
+    /// `slave_of` returns the slave owning a port GID, to route the requests. Fails with `EINVAL`
+    /// if `mad` is not a CM MAD, and with `ENOENT` if no slave is found, in which case the MAD is
+    /// left to the master.
+    pub fn demux(
+        &self,
+        mad: &mut [u8],
+        slave_of: impl FnOnce(Gid) -> Option<SlaveId>,
+    ) -> Result<SlaveId> {
+        let mut state = self.inner.state.lock();
+        let deadline = jiffies().wrapping_add(cleanup_delay());
+        let slave = state.ids.demux(mad, deadline, slave_of)?;
+        self.inner.arm(&state);
+        Ok(slave)
+    }
+
+    /// Drops every ID of `slave` right away, e.g. when the slave is shut down.
+    pub fn remove_slave(&self, slave: SlaveId) {
+        self.inner.state.lock().ids.remove_slave(slave);
+    }
+
+    /// Returns the number of proxied IDs.
+    pub fn len(&self) -> usize {
+        self.inner.state.lock().ids.len()
+    }
+
+    /// Returns `true` if no ID is proxied.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Starts aging the IDs on `queue`, once the device is added.
+    ///
+    /// # Safety
+    ///
+    /// `queue` must stay alive until [`CmIdMap::stop`] returns.
+    pub(crate) unsafe fn start(&self, queue: *mut bindings::workqueue_struct) {
+        let mut state = self.inner.state.lock();
+        // INVARIANT: The work item is initialised; it is only queued from now on.
+        state.queue = queue;
+        self.inner.arm(&state);
+    }
+
+    /// Stops aging the IDs and forgets all of them, before the device is removed.
+    ///
+    /// Waits for the work item, so the queue may be destroyed once this returns.
+    pub(crate) fn stop(&self) {
+        {
+            let mut state = self.inner.state.lock();
+            // INVARIANT: The work item is no longer queued once the queue is cleared under the
+            // lock, except by a running instance, which sees the cleared queue.
+            state.queue = ptr::null_mut();
+            state.ids.clear();
+        }
+        // SAFETY: The work item is initialised by the type invariants, and nothing queues it
+        // anymore.
+        unsafe { bindings::cancel_delayed_work_sync(self.inner.work()) };
+    }
+}
+
+impl Drop for CmIdMap {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Inner {
+    fn work(&self) -> *mut bindings::delayed_work {
+        self.work.get().cast()
+    }
+
+    /// Arms the work item for the earliest deadline, if the IDs are aged.
+    ///
+    /// A pending work item keeps its expiry, which is the earliest one as every ID expires
+    /// [`CM_CLEANUP_TIMEOUT_MS`] after it is scheduled.
+    fn arm(&self, state: &State) {
+        if state.queue.is_null() {
+            return;
+        }
+        let now = jiffies();
+        let deadline = match state.ids.next_deadline(now) {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        // SAFETY: By the type invariants the work item is initialised, and the queue is alive
+        // while it is set, which we checked under the lock.
+        unsafe {
+            bindings::queue_delayed_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                state.queue,
+                self.work(),
+                deadline.wrapping_sub(now).max(1) as _,
+            )
+        };
+    }
+
+    unsafe extern "C" fn work_fn(work: *mut bindings::work_struct) {
+        // SAFETY: The work item is the `work` field of a pinned `Inner`, which is dropped only
+        // after the work item is cancelled.
+        let this =
+            unsafe { &*crate::container_of!(work.cast::<bindings::delayed_work>(), Inner, work) };
+        let mut state = this.state.lock();
+        state.ids.expire(jiffies());
+        this.arm(&state);
+    }
+}
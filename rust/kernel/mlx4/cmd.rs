@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Firmware commands.
+//!
+//! mlx4 firmware commands take a 64-bit input parameter, two modifiers and an opcode, and answer
+//! either with nothing, with a 64-bit immediate or by filling an output mailbox: a 4 KiB DMA buffer
+//! allocated by mlx4_core. [`Mlx4Cmd`] describes a command and runs it in one of these three
+//! forms, the counterparts of `mlx4_cmd`, `mlx4_cmd_imm` and `mlx4_cmd_box`, and [`Mailbox`] owns a
+//! mailbox, freed when dropped.
+//!
+//! Commands sleep until the firmware answers, so they are issued in process context only.
+
+use core::slice;
+
+use super::device::Mlx4Device;
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Error, Result};
+
+/// Size of a command mailbox, in bytes, like `MLX4_MAILBOX_SIZE`.
+pub const MAILBOX_SIZE: usize = 4096;
+
+/// Timeout of most commands, in milliseconds, like `MLX4_CMD_TIME_CLASS_A`.
+pub const TIMEOUT_CLASS_A: u64 = bindings::MLX4_CMD_TIME_CLASS_A as u64;
+
+/// Timeout of the port commands, in milliseconds, like `MLX4_CMD_TIME_CLASS_B`.
+pub const TIMEOUT_CLASS_B: u64 = bindings::MLX4_CMD_TIME_CLASS_B as u64;
+
+/// Timeout of the slowest commands, in milliseconds, like `MLX4_CMD_TIME_CLASS_C`.
+pub const TIMEOUT_CLASS_C: u64 = bindings::MLX4_CMD_TIME_CLASS_C as u64;
+
+/// A command mailbox, freed when dropped.
+///
+/// # Invariants
+///
+/// `ptr` was returned by `mlx4_alloc_cmd_mailbox` on `dev` and is not freed yet.
+pub struct Mailbox<'a> {
+    dev: &'a Mlx4Device,
+    ptr: *mut bindings::mlx4_cmd_mailbox,
+}
+
+impl<'a> Mailbox<'a> {
+    /// Allocates a zeroed mailbox on `dev`.
+    pub fn try_new(dev: &'a Mlx4Device) -> Result<Self> {
+        // SAFETY: The device is valid.
+        let ptr = from_kernel_err_ptr(unsafe { bindings::mlx4_alloc_cmd_mailbox(dev.as_ptr()) })?;
+        // INVARIANT: The mailbox was just allocated.
+        Ok(Self { dev, ptr })
+    }
+
+    /// Returns the bus address of the mailbox, to be passed as a command parameter.
+    pub fn dma(&self) -> u64 {
+        // SAFETY: The mailbox is valid by the type invariants.
+        unsafe { (*self.ptr).dma }
+    }
+
+    /// Returns the content of the mailbox.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: `buf` points to `MAILBOX_SIZE` bytes owned by the mailbox, which the firmware
+        // only writes while a command holds a mutable reference to it.
+        unsafe { slice::from_raw_parts((*self.ptr).buf.cast(), MAILBOX_SIZE) }
+    }
+
+    /// Returns the content of the mailbox, for writing the input of a command.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: As in `as_slice`, and the mutable borrow of `self` makes the access exclusive.
+        unsafe { slice::from_raw_parts_mut((*self.ptr).buf.cast(), MAILBOX_SIZE) }
+    }
+
+    /// Returns the big-endian 32-bit word at byte `offset`, as firmware layouts are described.
+    ///
+    /// Fails with `EINVAL` if the word does not fit in the mailbox.
+    pub fn read_be32(&self, offset: usize) -> Result<u32> {
+        let bytes = self.as_slice().get(offset..offset + 4).ok_or(EINVAL)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Writes `value` as a big-endian 32-bit word at byte `offset`.
+    ///
+    /// Fails with `EINVAL` if the word does not fit in the mailbox.
+    pub fn write_be32(&mut self, offset: usize, value: u32) -> Result {
+        let bytes = self
+            .as_mut_slice()
+            .get_mut(offset..offset + 4)
+            .ok_or(EINVAL)?;
+        bytes.copy_from_slice(&value.to_be_bytes());
+        Ok(())
+    }
+}
+
+impl Drop for Mailbox<'_> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the mailbox was allocated on `dev`, which outlives it.
+        unsafe { bindings::mlx4_free_cmd_mailbox(self.dev.as_ptr(), self.ptr) };
+    }
+}
+
+/// The input parameter of a command.
+///
+/// Only mailboxes can be passed from outside the crate: an immediate is an arbitrary firmware
+/// parameter, e.g. a bus address, which the commands built here vet themselves.
+#[derive(Clone, Copy)]
+pub struct CmdInput<'m>(Input<'m>);
+
+#[derive(Clone, Copy)]
+enum Input<'m> {
+    None,
+    Imm(u64),
+    Mailbox(&'m Mailbox<'m>),
+}
+
+impl<'m> CmdInput<'m> {
+    /// No input, the parameter is 0.
+    pub const fn none() -> Self {
+        Self(Input::None)
+    }
+
+    /// The address of `mailbox`, which holds the input.
+    pub const fn mailbox(mailbox: &'m Mailbox<'m>) -> Self {
+        Self(Input::Mailbox(mailbox))
+    }
+
+    /// An immediate value.
+    #[allow(dead_code)]
+    pub(crate) const fn imm(value: u64) -> Self {
+        Self(Input::Imm(value))
+    }
+
+    fn raw(self) -> u64 {
+        match self.0 {
+            Input::None => 0,
+            Input::Imm(value) => value,
+            Input::Mailbox(mailbox) => mailbox.dma(),
+        }
+    }
+}
+
+/// A firmware command, run with [`Mlx4Cmd::run`], [`Mlx4Cmd::run_imm`] or [`Mlx4Cmd::run_box`].
+///
+/// Drivers only get the commands vetted here, such as [`Mlx4Cmd::query_port`] and
+/// [`Mlx4Cmd::set_port`]; building arbitrary commands is left to the abstractions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mlx4Cmd {
+    op: u16,
+    in_modifier: u32,
+    op_modifier: u8,
+    timeout: u64,
+    native: bool,
+}
+
+impl Mlx4Cmd {
+    /// Opcode of `QUERY_PORT`.
+    pub const QUERY_PORT: u16 = bindings::MLX4_CMD_QUERY_PORT as u16;
+
+    /// Opcode of `SET_PORT`.
+    pub const SET_PORT: u16 = bindings::MLX4_CMD_SET_PORT as u16;
+
+    /// Opcode of `MAD_IFC`.
+    pub const MAD_IFC: u16 = bindings::MLX4_CMD_MAD_IFC as u16;
+
+    /// Creates the command `op` with null modifiers, [`TIMEOUT_CLASS_A`] and wrapped execution.
+    pub(crate) const fn new(op: u16) -> Self {
+        Self {
+            op,
+            in_modifier: 0,
+            op_modifier: 0,
+            timeout: TIMEOUT_CLASS_A,
+            native: false,
+        }
+    }
+
+    /// Returns `QUERY_PORT` for `port`, whose answer is written to the output mailbox.
+    pub const fn query_port(port: u32) -> Self {
+        Self::new(Self::QUERY_PORT)
+            .with_in_modifier(port)
+            .with_timeout(TIMEOUT_CLASS_B)
+    }
+
+    /// Returns the IB flavour of `SET_PORT` for `port`, which reads the input mailbox.
+    pub const fn set_port(port: u32) -> Self {
+        Self::new(Self::SET_PORT)
+            .with_in_modifier(port)
+            .with_timeout(TIMEOUT_CLASS_B)
+    }
+
+    /// Sets the input modifier, e.g. the port or the index of an object.
+    pub(crate) const fn with_in_modifier(mut self, in_modifier: u32) -> Self {
+        self.in_modifier = in_modifier;
+        self
+    }
+
+    /// Sets the opcode modifier.
+    pub(crate) const fn with_op_modifier(mut self, op_modifier: u8) -> Self {
+        self.op_modifier = op_modifier;
+        self
+    }
+
+    /// Sets the timeout, in milliseconds.
+    pub(crate) const fn with_timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runs the command natively when `native` is `true`, like `MLX4_CMD_NATIVE`, instead of
+    /// letting the master check it on behalf of a slave function.
+    pub(crate) const fn with_native(mut self, native: bool) -> Self {
+        self.native = native;
+        self
+    }
+
+    /// Returns the opcode.
+    pub const fn op(&self) -> u16 {
+        self.op
+    }
+
+    fn exec(&self, dev: &Mlx4Device, input: CmdInput<'_>, out: *mut u64, imm: bool) -> Result {
+        let native = if self.native {
+            bindings::MLX4_CMD_NATIVE
+        } else {
+            bindings::MLX4_CMD_WRAPPED
+        };
+        // SAFETY: The device is valid and `out` is null or valid for writes. Failed commands,
+        // including bad statuses from the firmware, come back as negative error numbers.
+        let err = unsafe {
+            bindings::__mlx4_cmd(
+                dev.as_ptr(),
+                input.raw(),
+                out,
+                imm as _,
+                self.in_modifier,
+                self.op_modifier,
+                self.op,
+                self.timeout as _,
+                native as _,
+            )
+        };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        Ok(())
+    }
+
+    /// Runs the command, which answers with nothing, like `mlx4_cmd`.
+    pub fn run(&self, dev: &Mlx4Device, input: CmdInput<'_>) -> Result {
+        self.exec(dev, input, core::ptr::null_mut(), false)
+    }
+
+    /// Runs the command and returns the immediate it answers with, like `mlx4_cmd_imm`.
+    pub fn run_imm(&self, dev: &Mlx4Device, input: CmdInput<'_>) -> Result<u64> {
+        let mut out = 0;
+        self.exec(dev, input, &mut out, true)?;
+        Ok(out)
+    }
+
+    /// Runs the command, which writes its answer into `out`, like `mlx4_cmd_box`.
+    pub fn run_box(&self, dev: &Mlx4Device, input: CmdInput<'_>, out: &mut Mailbox<'_>) -> Result {
+        let mut dma = out.dma();
+        self.exec(dev, input, &mut dma, false)
+    }
+}
@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Doorbell records.
+//!
+//! Every work queue and CQ of an mlx4 device has a doorbell record: a few 32-bit words in host
+//! memory where the driver publishes its consumer or producer index, and that the HCA reads by
+//! DMA instead of taking an MMIO write per completion or receive. mlx4_core hands the records
+//! out from shared DMA pages with `mlx4_db_alloc`. [`DoorbellRecord`] owns one of them and frees
+//! it when dropped.
+
+use core::ptr;
+
+use super::device::Mlx4Device;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+
+/// A doorbell record allocated by mlx4_core, freed when dropped.
+///
+/// # Invariants
+///
+/// `db` was allocated on `dev` by `mlx4_db_alloc` with `1 << order` words and is not freed yet,
+/// and `dev` stays valid until the [`DoorbellRecord`] is dropped.
+pub struct DoorbellRecord {
+    dev: *mut bindings::mlx4_dev,
+    db: bindings::mlx4_db,
+}
+
+impl DoorbellRecord {
+    /// Allocates a zeroed record of `1 << order` big-endian words, e.g. order 1 for the update
+    /// and arm words of a CQ, and 0 for the receive counter of a QP.
+    ///
+    /// # Safety
+    ///
+    /// The record must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`super::Mlx4Operation::add`] ensures this, as [`super::Mlx4Operation::remove`] drops the
+    /// context before mlx4_core removes the device.
+    pub unsafe fn try_new(dev: &Mlx4Device, order: u32) -> Result<Self> {
+        if order > 1 {
+            return Err(EINVAL);
+        }
+        let mut db = bindings::mlx4_db::default();
+        // SAFETY: The device is valid and `db` a valid out pointer.
+        let err = unsafe { bindings::mlx4_db_alloc(dev.as_ptr(), &mut db, order as _) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // INVARIANT: The record was just allocated, and the safety requirements keep the device
+        // valid until it is freed.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            db,
+        })
+    }
+
+    /// Returns the DMA address of the record, to be written to the queue's context.
+    pub fn dma(&self) -> u64 {
+        self.db.dma
+    }
+
+    /// Returns the number of words of the record.
+    pub fn len(&self) -> usize {
+        1 << self.db.order
+    }
+
+    /// Returns `true` if the record has no word.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns word `index` of the record, in host byte order.
+    ///
+    /// Fails with `EINVAL` if the record has no such word.
+    pub fn read(&self, index: usize) -> Result<u32> {
+        let word = self.word(index)?;
+        // SAFETY: `word` is in the record, which the HCA only reads.
+        Ok(u32::from_be(unsafe { ptr::read_volatile(word) }))
+    }
+
+    /// Writes `value` to word `index` of the record, big-endian, with a volatile store.
+    ///
+    /// The HCA may read the word at any time; callers order the store after the queue entries it
+    /// publishes with a DMA write barrier. Fails with `EINVAL` if the record has no such word.
+    pub fn write(&mut self, index: usize, value: u32) -> Result {
+        let word = self.word(index)?;
+        // SAFETY: `word` is in the record, and the mutable borrow of `self` serialises the CPU
+        // writers.
+        unsafe { ptr::write_volatile(word, value.to_be()) };
+        Ok(())
+    }
+
+    fn word(&self, index: usize) -> Result<*mut u32> {
+        if index >= self.len() {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants `db` points to `len()` words.
+        Ok(unsafe { self.db.db.add(index).cast() })
+    }
+}
+
+impl Drop for DoorbellRecord {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the record was allocated on the device, which is still
+        // valid.
+        unsafe { bindings::mlx4_db_free(self.dev, &mut self.db) };
+    }
+}
+
+// SAFETY: The record can be freed from any thread; mlx4_core serialises the doorbell pages.
+unsafe impl Send for DoorbellRecord {}
+// SAFETY: Shared references only read the record.
+unsafe impl Sync for DoorbellRecord {}
@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! mlx4 core devices.
+//!
+//! mlx4_core runs `QUERY_FW` and `QUERY_DEV_CAP` while probing the HCA and keeps the results in
+//! `struct mlx4_dev`. [`Mlx4Device::caps`] copies the limits a verbs provider sizes its resource
+//! pools from into a plain [`Mlx4Caps`], and [`Mlx4Device::query_port`] the ones of a port into
+//! [`Mlx4PortCaps`], so drivers never read the C structure directly.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::ib::device::Mtu;
+
+/// Largest number of ports of an HCA, like `MLX4_MAX_PORTS`.
+pub const MAX_PORTS: u32 = 2;
+
+/// A firmware version, as reported by `QUERY_FW`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FwVersion {
+    /// Major version.
+    pub major: u16,
+    /// Minor version.
+    pub minor: u16,
+    /// Sub-minor version.
+    pub subminor: u16,
+}
+
+impl FwVersion {
+    /// Decodes the packed `caps.fw_ver` value.
+    pub const fn from_raw(raw: u64) -> Self {
+        Self {
+            major: (raw >> 32) as u16,
+            minor: (raw >> 16) as u16,
+            subminor: raw as u16,
+        }
+    }
+
+    /// Returns the packed value, suitable for `ib_device_attr.fw_ver`.
+    pub const fn to_raw(self) -> u64 {
+        (self.major as u64) << 32 | (self.minor as u64) << 16 | self.subminor as u64
+    }
+}
+
+impl fmt::Display for FwVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.subminor)
+    }
+}
+
+/// Device limits reported by `QUERY_DEV_CAP`, after mlx4_core applied its module parameters.
+///
+/// Counts of objects include the ones reserved by firmware; the `reserved_*` fields tell how many
+/// of the lowest indices are unavailable to the driver.
+#[derive(Clone, Copy, Debug)]
+pub struct Mlx4Caps {
+    /// Number of physical ports.
+    pub num_ports: u32,
+    /// Number of QPs.
+    pub num_qps: u32,
+    /// Number of QPs reserved by firmware and mlx4_core.
+    pub reserved_qps: u32,
+    /// Maximum number of work requests per work queue.
+    pub max_wqes: u32,
+    /// Maximum number of scatter/gather entries per send work request.
+    pub max_sq_sg: u32,
+    /// Maximum number of scatter/gather entries per receive work request.
+    pub max_rq_sg: u32,
+    /// Maximum number of outstanding RDMA READs and atomics as a requester.
+    pub max_qp_init_rdma: u32,
+    /// Maximum number of outstanding RDMA READs and atomics as a responder.
+    pub max_qp_dest_rdma: u32,
+    /// Number of SRQs.
+    pub num_srqs: u32,
+    /// Number of SRQs reserved by firmware.
+    pub reserved_srqs: u32,
+    /// Maximum number of work requests per SRQ.
+    pub max_srq_wqes: u32,
+    /// Maximum number of scatter/gather entries per SRQ work request.
+    pub max_srq_sge: u32,
+    /// Number of CQs.
+    pub num_cqs: u32,
+    /// Number of CQs reserved by firmware.
+    pub reserved_cqs: u32,
+    /// Maximum number of entries per CQ.
+    pub max_cqes: u32,
+    /// Number of completion vectors available to the driver.
+    pub num_comp_vectors: u32,
+    /// Number of memory protection table entries (MRs and MWs).
+    pub num_mpts: u32,
+    /// Number of MPTs reserved by firmware.
+    pub reserved_mrws: u32,
+    /// Number of memory translation table entries.
+    pub num_mtts: u32,
+    /// Number of PDs.
+    pub num_pds: u32,
+    /// Number of PDs reserved by firmware.
+    pub reserved_pds: u32,
+    /// Number of multicast groups.
+    pub num_mgms: u32,
+    /// Number of QPs that can be attached to one multicast group.
+    pub num_qp_per_mgm: u32,
+    /// Largest message size.
+    pub max_msg_sz: u32,
+    /// Supported page sizes, as a bitmap of sizes.
+    pub page_size_cap: u32,
+    /// `MLX4_DEV_CAP_FLAG_*` capability flags.
+    pub flags: u64,
+    /// `MLX4_DEV_CAP_FLAG2_*` capability flags.
+    pub flags2: u64,
+    /// `MLX4_BMME_FLAG_*` memory management extension flags.
+    pub bmme_flags: u32,
+    /// Largest IB MTU supported by any port.
+    pub max_mtu: Option<Mtu>,
+}
+
+/// Protocol a port runs, `enum mlx4_port_type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mlx4PortType {
+    /// The port is not configured.
+    None,
+    /// InfiniBand.
+    Ib,
+    /// Ethernet, for RoCE.
+    Eth,
+    /// Sensed from the link partner.
+    Auto,
+}
+
+impl Mlx4PortType {
+    /// Converts a raw `enum mlx4_port_type` value.
+    pub const fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Mlx4PortType::Ib,
+            2 => Mlx4PortType::Eth,
+            3 => Mlx4PortType::Auto,
+            _ => Mlx4PortType::None,
+        }
+    }
+}
+
+/// Limits of a port, from the per-port arrays of `QUERY_DEV_CAP` and `QUERY_PORT`.
+#[derive(Clone, Copy, Debug)]
+pub struct Mlx4PortCaps {
+    /// Port number, starting at 1.
+    pub port: u32,
+    /// Protocol the port runs.
+    pub port_type: Mlx4PortType,
+    /// Largest IB MTU the port supports.
+    pub max_mtu: Option<Mtu>,
+    /// IB MTU the port is configured with.
+    pub mtu: Option<Mtu>,
+    /// Largest Ethernet MTU, in bytes.
+    pub eth_mtu: u32,
+    /// Number of entries of the GID table.
+    pub gid_table_len: u32,
+    /// Number of entries of the P_Key table.
+    pub pkey_table_len: u32,
+    /// Supported link widths, as a bitmap of `IB_WIDTH_*`.
+    pub width_cap: u32,
+}
+
+/// An mlx4 HCA probed by mlx4_core, wraps `struct mlx4_dev`.
+#[repr(transparent)]
+pub struct Mlx4Device(UnsafeCell<bindings::mlx4_dev>);
+
+impl Mlx4Device {
+    /// Creates a reference to an [`Mlx4Device`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::mlx4_dev) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `Mlx4Device` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the raw `struct mlx4_dev` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::mlx4_dev {
+        self.0.get()
+    }
+
+    /// Returns the PCI function of the HCA, which maps the DMA of its consumers.
+    pub fn dma_device(&self) -> *mut bindings::device {
+        // SAFETY: The device is valid, and mlx4_core keeps `persist` until it is removed.
+        unsafe { ptr::addr_of_mut!((*(*(*self.as_ptr()).persist).pdev).dev) }
+    }
+
+    fn raw_caps(&self) -> &bindings::mlx4_caps {
+        // SAFETY: The device is valid and mlx4_core no longer changes `caps` once interfaces are
+        // attached.
+        unsafe { &(*self.as_ptr()).caps }
+    }
+
+    /// Returns the firmware version.
+    pub fn fw_version(&self) -> FwVersion {
+        FwVersion::from_raw(self.raw_caps().fw_ver)
+    }
+
+    /// Returns the board ID read from the adapter's VPD, e.g. `MT_1090120019`.
+    ///
+    /// Stops at the first NUL or at the first byte that is not valid UTF-8.
+    pub fn board_id(&self) -> &str {
+        // SAFETY: The device is valid and `board_id` is only written while probing.
+        let raw = unsafe { &(*self.as_ptr()).board_id };
+        // SAFETY: `c_char` and `u8` have the same layout.
+        let bytes = unsafe { &*(raw as *const [core::ffi::c_char] as *const [u8]) };
+        let bytes = match bytes.iter().position(|&b| b == 0) {
+            Some(len) => &bytes[..len],
+            None => bytes,
+        };
+        match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            // SAFETY: `valid_up_to` is the length of the longest valid UTF-8 prefix.
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+        }
+    }
+
+    /// Returns the limits of `port`, numbered from 1.
+    ///
+    /// Fails with `EINVAL` if the device has no such port.
+    pub fn query_port(&self, port: u32) -> Result<Mlx4PortCaps> {
+        let c = self.raw_caps();
+        if port == 0 || port > (c.num_ports as u32).min(MAX_PORTS) {
+            return Err(EINVAL);
+        }
+        let i = port as usize;
+        Ok(Mlx4PortCaps {
+            port,
+            port_type: Mlx4PortType::from_raw(c.port_type[i] as u32),
+            max_mtu: Mtu::from_raw(c.ib_mtu_cap[i] as u32),
+            mtu: Mtu::from_raw(c.port_ib_mtu[i] as u32),
+            eth_mtu: c.eth_mtu_cap[i] as u32,
+            gid_table_len: c.gid_table_len[i] as u32,
+            pkey_table_len: c.pkey_table_len[i] as u32,
+            width_cap: c.port_width_cap[i] as u32,
+        })
+    }
+
+    /// Returns the device limits.
+    pub fn caps(&self) -> Mlx4Caps {
+        let c = self.raw_caps();
+        let ports = 1..=(c.num_ports as u32).min(MAX_PORTS);
+        Mlx4Caps {
+            num_ports: c.num_ports as u32,
+            num_qps: c.num_qps as u32,
+            reserved_qps: c.reserved_qps as u32,
+            max_wqes: c.max_wqes as u32,
+            max_sq_sg: c.max_sq_sg as u32,
+            max_rq_sg: c.max_rq_sg as u32,
+            max_qp_init_rdma: c.max_qp_init_rdma as u32,
+            max_qp_dest_rdma: c.max_qp_dest_rdma as u32,
+            num_srqs: c.num_srqs as u32,
+            reserved_srqs: c.reserved_srqs as u32,
+            max_srq_wqes: c.max_srq_wqes as u32,
+            max_srq_sge: c.max_srq_sge as u32,
+            num_cqs: c.num_cqs as u32,
+            reserved_cqs: c.reserved_cqs as u32,
+            max_cqes: c.max_cqes as u32,
+            num_comp_vectors: c.num_comp_vectors as u32,
+            num_mpts: c.num_mpts as u32,
+            reserved_mrws: c.reserved_mrws as u32,
+            num_mtts: c.num_mtts as u32,
+            num_pds: c.num_pds as u32,
+            reserved_pds: c.reserved_pds as u32,
+            num_mgms: c.num_mgms as u32,
+            num_qp_per_mgm: c.num_qp_per_mgm as u32,
+            max_msg_sz: c.max_msg_sz,
+            page_size_cap: c.page_size_cap,
+            flags: c.flags,
+            flags2: c.flags2,
+            bmme_flags: c.bmme_flags,
+            max_mtu: ports
+                .filter_map(|port| Mtu::from_raw(c.ib_mtu_cap[port as usize] as u32))
+                .max(),
+        }
+    }
+}
+
+// SAFETY: `struct mlx4_dev` is owned by mlx4_core and the accessors above only read fields that
+// are immutable while interfaces are attached.
+unsafe impl Send for Mlx4Device {}
+// SAFETY: See the `Send` implementation.
+unsafe impl Sync for Mlx4Device {}
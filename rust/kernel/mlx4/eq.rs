@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Completion event queues.
+//!
+//! mlx4_core sets up one MSI-X vector per completion EQ and hands them out to the interfaces on
+//! request, like `mlx4_ib_alloc_eqs` does in the C driver. A verbs provider usually takes one
+//! vector per completion vector it advertises, so that the CQs consumers spread with
+//! `comp_vector` end up on different interrupts and CPUs. [`EqVector`] holds one of them and gives
+//! it back when dropped.
+
+use super::device::{Mlx4Device, MAX_PORTS};
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+
+impl Mlx4Device {
+    /// Returns the number of completion EQs that may serve `port`.
+    pub fn eqs_per_port(&self, port: u32) -> u32 {
+        if port == 0 || port > MAX_PORTS {
+            return 0;
+        }
+        // SAFETY: The device is valid and `port` in range.
+        let n = unsafe { bindings::mlx4_get_eqs_per_port(self.as_ptr(), port as u8) };
+        n.max(0) as u32
+    }
+
+    /// Returns `true` if completion EQ `vector` may serve `port`, e.g. to validate the
+    /// `comp_vector` of a CQ.
+    pub fn is_eq_vector_valid(&self, port: u32, vector: i32) -> bool {
+        if port == 0 || port > MAX_PORTS {
+            return false;
+        }
+        // SAFETY: The device is valid and `port` in range.
+        unsafe { bindings::mlx4_is_eq_vector_valid(self.as_ptr(), port as u8, vector) }
+    }
+}
+
+/// A completion EQ assigned to the caller, released when dropped.
+///
+/// # Invariants
+///
+/// `vector` was assigned on `dev` by `mlx4_assign_eq` and is not released yet, and `dev` stays
+/// valid until the [`EqVector`] is dropped.
+pub struct EqVector {
+    dev: *mut bindings::mlx4_dev,
+    port: u32,
+    vector: i32,
+}
+
+impl EqVector {
+    /// Assigns a completion EQ for `port`, preferring `vector` if it is free.
+    ///
+    /// mlx4_core falls back to the least used EQ of the port, so the result may differ from the
+    /// request; see [`EqVector::vector`].
+    ///
+    /// # Safety
+    ///
+    /// The vector must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`super::Mlx4Operation::add`] ensures this, as [`super::Mlx4Operation::remove`] drops the
+    /// context before mlx4_core removes the device.
+    pub unsafe fn try_new(dev: &Mlx4Device, port: u32, vector: i32) -> Result<Self> {
+        if port == 0 || port > MAX_PORTS {
+            return Err(EINVAL);
+        }
+        let mut vector = vector;
+        // SAFETY: The device is valid, `port` in range and `vector` a valid out pointer.
+        let err = unsafe { bindings::mlx4_assign_eq(dev.as_ptr(), port as u8, &mut vector) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // INVARIANT: The vector was just assigned, and the safety requirements keep the device
+        // valid until it is released.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            port,
+            vector,
+        })
+    }
+
+    /// Returns the port the EQ serves.
+    pub fn port(&self) -> u32 {
+        self.port
+    }
+
+    /// Returns the EQ vector, to be used as the EQ of the CQs of this completion vector.
+    pub fn vector(&self) -> i32 {
+        self.vector
+    }
+
+    /// Returns the Linux IRQ number of the EQ, e.g. to set its affinity hint.
+    pub fn irq(&self) -> Result<u32> {
+        // SAFETY: By the type invariants the device is valid and the vector assigned.
+        let irq = unsafe { bindings::mlx4_eq_get_irq(self.dev, self.vector) };
+        if irq < 0 {
+            return Err(Error::from_kernel_errno(irq));
+        }
+        Ok(irq as u32)
+    }
+}
+
+impl Drop for EqVector {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the vector was assigned on the device, which is still
+        // valid.
+        unsafe { bindings::mlx4_release_eq(self.dev, self.vector) };
+    }
+}
+
+// SAFETY: The vector can be released from any thread; mlx4_core serialises EQ assignment.
+unsafe impl Send for EqVector {}
+// SAFETY: Shared references only read the fields.
+unsafe impl Sync for EqVector {}
@@ -0,0 +1,639 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Asynchronous events.
+//!
+//! mlx4_core reports device events through the interface's `event` callback, and QP and CQ events
+//! through the `event`/`comp` callbacks of `struct mlx4_qp`/`struct mlx4_cq`, all from its EQ
+//! interrupt handler. The abstraction drops the events the driver did not subscribe to, queues the
+//! others on an [`EventRing`] and drains it from the matching workqueue, where the typed
+//! [`Mlx4Operation`] handlers run in process context: device and port events on `mlx4_ib`, QP
+//! events on `mlx4_ib_qp_event_wq`. Completion events are latency sensitive and are delivered
+//! right away, in interrupt context.
+//!
+//! The events of a QP go to its own handler when the driver registered one with [`QpHandler`],
+//! and to [`Mlx4Operation::qp_event`] otherwise. Once a [`QpHandler`] is dropped, the events still
+//! queued for its QP are suppressed rather than delivered to whatever uses the QP number next.
+//!
+//! A driver subscribes to a class of events by implementing its handler, and can mute classes at
+//! runtime with [`super::Registration::set_event_mask`].
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::BitOr;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use super::slave::SlaveId;
+use super::{Mlx4Device, Mlx4Operation};
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::ib::compat::OwnedQueue;
+use crate::rdma::event_ring::EventRing;
+use crate::rdma::timeout_map::TimeoutMap;
+use crate::sync::SpinLock;
+
+/// A set of event classes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventMask(u32);
+
+impl EventMask {
+    /// Port state and management changes, see [`Mlx4Operation::port_event`].
+    pub const PORT: Self = Self(1 << 0);
+    /// Unrecoverable device errors, see [`Mlx4Operation::catastrophic_error`].
+    pub const CATASTROPHIC: Self = Self(1 << 1);
+    /// QP asynchronous events, see [`Mlx4Operation::qp_event`].
+    pub const QP: Self = Self(1 << 2);
+    /// CQ completion events, see [`Mlx4Operation::completion`].
+    pub const COMPLETION: Self = Self(1 << 3);
+    /// SR-IOV slaves coming up and going away, see [`Mlx4Operation::slave_init`] and
+    /// [`Mlx4Operation::slave_shutdown`].
+    pub const SLAVE: Self = Self(1 << 4);
+    /// Every class.
+    pub const ALL: Self = Self(0x1f);
+
+    /// Returns the empty set.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Returns `true` if every class of `other` is in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the classes in both `self` and `other`.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for EventMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// A port event, from `enum mlx4_dev_event`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortEvent {
+    /// The link came up.
+    Up,
+    /// The link went down.
+    Down,
+    /// The port must be reconfigured, e.g. after a port type change.
+    Reinit,
+    /// The subnet manager changed the port's attributes (LID, SM LID, P_Key or GID tables).
+    MgmtChange,
+}
+
+/// A device event, from `enum mlx4_dev_event` and its parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mlx4DevEvent {
+    /// The device hit an unrecoverable error.
+    CatastrophicError,
+    /// The link of `port` came up.
+    PortUp {
+        /// Port number.
+        port: u8,
+    },
+    /// The link of `port` went down.
+    PortDown {
+        /// Port number.
+        port: u8,
+    },
+    /// `port` must be reconfigured, e.g. after a port type change.
+    PortReinit {
+        /// Port number.
+        port: u8,
+    },
+    /// The subnet manager changed the attributes of `port`.
+    PortMgmtChange {
+        /// Port number.
+        port: u8,
+    },
+    /// SR-IOV function `slave` came up.
+    SlaveInit {
+        /// Function number of the slave.
+        slave: SlaveId,
+    },
+    /// SR-IOV function `slave` is shutting down.
+    SlaveShutdown {
+        /// Function number of the slave.
+        slave: SlaveId,
+    },
+}
+
+impl Mlx4DevEvent {
+    /// Decodes `event` and its parameter.
+    ///
+    /// # Safety
+    ///
+    /// `event` and `param` must come from mlx4_core's interface `event` callback.
+    pub(crate) unsafe fn from_raw(
+        event: bindings::mlx4_dev_event,
+        param: core::ffi::c_ulong,
+    ) -> Option<Self> {
+        let port = param as u8;
+        Some(match event {
+            bindings::mlx4_dev_event_MLX4_DEV_EVENT_CATASTROPHIC_ERROR => Self::CatastrophicError,
+            bindings::mlx4_dev_event_MLX4_DEV_EVENT_PORT_UP => Self::PortUp { port },
+            bindings::mlx4_dev_event_MLX4_DEV_EVENT_PORT_DOWN => Self::PortDown { port },
+            bindings::mlx4_dev_event_MLX4_DEV_EVENT_PORT_REINIT => Self::PortReinit { port },
+            bindings::mlx4_dev_event_MLX4_DEV_EVENT_PORT_MGMT_CHANGE => {
+                // For management changes `param` points to the EQE rather than holding the port.
+                // SAFETY: mlx4_core passes a valid EQE for this event.
+                let port = unsafe {
+                    (*(param as *const bindings::mlx4_eqe))
+                        .event
+                        .port_mgmt_change
+                        .port
+                };
+                Self::PortMgmtChange { port }
+            }
+            bindings::mlx4_dev_event_MLX4_DEV_EVENT_SLAVE_INIT => Self::SlaveInit {
+                slave: SlaveId::new(param as u32)?,
+            },
+            bindings::mlx4_dev_event_MLX4_DEV_EVENT_SLAVE_SHUTDOWN => Self::SlaveShutdown {
+                slave: SlaveId::new(param as u32)?,
+            },
+            _ => return None,
+        })
+    }
+
+    /// Returns the port and the port event, for the port events.
+    pub fn port_event(self) -> Option<(u8, PortEvent)> {
+        match self {
+            Self::PortUp { port } => Some((port, PortEvent::Up)),
+            Self::PortDown { port } => Some((port, PortEvent::Down)),
+            Self::PortReinit { port } => Some((port, PortEvent::Reinit)),
+            Self::PortMgmtChange { port } => Some((port, PortEvent::MgmtChange)),
+            _ => None,
+        }
+    }
+
+    /// Returns the slave the event is about, for the SR-IOV events.
+    pub fn slave(self) -> Option<SlaveId> {
+        match self {
+            Self::SlaveInit { slave } | Self::SlaveShutdown { slave } => Some(slave),
+            _ => None,
+        }
+    }
+}
+
+/// A QP asynchronous event, from `enum mlx4_event`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QpEvent {
+    /// The QP migrated to its alternate path.
+    PathMigrated,
+    /// The first packet arrived on a QP in RTR.
+    CommEstablished,
+    /// The send queue of a QP in SQD drained.
+    SqDrained,
+    /// The last WQE of a QP in the error state, attached to an SRQ, was consumed.
+    LastWqeReached,
+    /// Migration to the alternate path failed.
+    PathMigFailed,
+    /// A catastrophic error moved the QP to the error state.
+    CatastrophicError,
+    /// An invalid request moved the QP to the error state.
+    InvalidRequest,
+    /// An access violation moved the QP to the error state.
+    AccessError,
+}
+
+impl QpEvent {
+    fn from_raw(raw: bindings::mlx4_event) -> Option<Self> {
+        Some(match raw {
+            bindings::mlx4_event_MLX4_EVENT_TYPE_PATH_MIG => QpEvent::PathMigrated,
+            bindings::mlx4_event_MLX4_EVENT_TYPE_COMM_EST => QpEvent::CommEstablished,
+            bindings::mlx4_event_MLX4_EVENT_TYPE_SQ_DRAINED => QpEvent::SqDrained,
+            bindings::mlx4_event_MLX4_EVENT_TYPE_SRQ_QP_LAST_WQE => QpEvent::LastWqeReached,
+            bindings::mlx4_event_MLX4_EVENT_TYPE_PATH_MIG_FAILED => QpEvent::PathMigFailed,
+            bindings::mlx4_event_MLX4_EVENT_TYPE_WQ_CATAS_ERROR => QpEvent::CatastrophicError,
+            bindings::mlx4_event_MLX4_EVENT_TYPE_WQ_INVAL_REQ_ERROR => QpEvent::InvalidRequest,
+            bindings::mlx4_event_MLX4_EVENT_TYPE_WQ_ACCESS_ERROR => QpEvent::AccessError,
+            _ => return None,
+        })
+    }
+
+    /// Returns `true` if the event moved the QP to the error state.
+    pub fn is_error(self) -> bool {
+        matches!(
+            self,
+            QpEvent::CatastrophicError | QpEvent::InvalidRequest | QpEvent::AccessError
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Event {
+    Catastrophic(*mut bindings::mlx4_dev),
+    Port(*mut bindings::mlx4_dev, u8, PortEvent),
+    /// The device, the driver context of the device, the slave and whether it came up.
+    Slave(
+        *mut bindings::mlx4_dev,
+        *mut core::ffi::c_void,
+        SlaveId,
+        bool,
+    ),
+    Qp(u32, QpEvent),
+}
+
+// SAFETY: The device and context pointers are only dereferenced by the work items, which `remove`
+// flushes before mlx4_core frees the device and the context is dropped.
+unsafe impl Send for Event {}
+
+const RING_SIZE: usize = 64;
+
+/// Queues events for one workqueue.
+///
+/// # Invariants
+///
+/// `queue` is only accessed with [`DISPATCH_LOCK`] held, and is null once the dispatcher is
+/// stopping, after which nothing queues `work` anymore.
+struct Dispatcher {
+    claimed: AtomicBool,
+    queue: UnsafeCell<*mut bindings::workqueue_struct>,
+    work: UnsafeCell<MaybeUninit<bindings::work_struct>>,
+    events: EventRing<Event, RING_SIZE>,
+}
+
+// SAFETY: `queue` is protected by `DISPATCH_LOCK`. `work` is initialised once by the claimer
+// before `queue` is published, and only handed to the workqueue core afterwards, which
+// synchronises its own accesses.
+unsafe impl Sync for Dispatcher {}
+
+crate::init_static_sync! {
+    /// Protects the queues of the dispatchers, taken with interrupts disabled as events are
+    /// posted from the EQ interrupt handler.
+    static DISPATCH_LOCK: SpinLock<()> = ();
+}
+
+impl Dispatcher {
+    const fn new() -> Self {
+        Self {
+            claimed: AtomicBool::new(false),
+            queue: UnsafeCell::new(ptr::null_mut()),
+            work: UnsafeCell::new(MaybeUninit::uninit()),
+            events: EventRing::new(),
+        }
+    }
+
+    fn work(&self) -> *mut bindings::work_struct {
+        self.work.get().cast()
+    }
+
+    /// Returns the queue, or null if the dispatcher is stopping or was never started.
+    fn queue(&self) -> *mut bindings::workqueue_struct {
+        let _guard = DISPATCH_LOCK.lock_irqdisable();
+        // SAFETY: We hold `DISPATCH_LOCK`.
+        unsafe { *self.queue.get() }
+    }
+
+    fn start(&self, queue: &OwnedQueue, func: unsafe extern "C" fn(*mut bindings::work_struct)) {
+        // SAFETY: The caller claimed the dispatcher, so the work item is idle and ours.
+        unsafe { bindings::init_work(self.work(), Some(func)) };
+        let _guard = DISPATCH_LOCK.lock_irqdisable();
+        // SAFETY: We hold `DISPATCH_LOCK`.
+        unsafe { *self.queue.get() = queue.as_raw() };
+    }
+
+    /// Stops queueing the work item and waits for it, after which the queue may be destroyed.
+    fn stop(&self) {
+        {
+            let _guard = DISPATCH_LOCK.lock_irqdisable();
+            // SAFETY: We hold `DISPATCH_LOCK`.
+            let queue = unsafe { &mut *self.queue.get() };
+            if queue.is_null() {
+                return;
+            }
+            // INVARIANT: `post` checks `queue` under the lock, so it is done with the queue.
+            *queue = ptr::null_mut();
+        }
+        // SAFETY: The work item was initialised by `start`; nothing queues it anymore.
+        unsafe { bindings::flush_work(self.work()) };
+        while self.events.pop().is_some() {}
+    }
+
+    fn flush(&self) {
+        if !self.queue().is_null() {
+            // SAFETY: The work item was initialised by `start`.
+            unsafe { bindings::flush_work(self.work()) };
+        }
+    }
+
+    fn post(&self, event: Event) {
+        let _guard = DISPATCH_LOCK.lock_irqdisable();
+        // SAFETY: We hold `DISPATCH_LOCK`.
+        let queue = unsafe { *self.queue.get() };
+        if queue.is_null() || self.events.push(event).is_err() {
+            return;
+        }
+        // SAFETY: `queue` is alive until `stop` returns, which only flushes the work item once
+        // it cleared `queue` under the lock we hold. A work item already pending picks the event
+        // up as well.
+        unsafe { bindings::queue_work_on(bindings::WORK_CPU_UNBOUND as _, queue, self.work()) };
+    }
+}
+
+/// Largest number of QPs with their own event handler.
+pub const MAX_QP_HANDLERS: usize = 512;
+
+/// Handles the asynchronous events of one QP, see [`QpHandler`].
+pub trait QpEventHandler: Send + Sync {
+    /// Handles `event` of QP `qpn`, on the `mlx4_ib_qp_event_wq` workqueue.
+    fn handle(&self, qpn: u32, event: QpEvent);
+}
+
+/// A registered handler, or `None` while the events of a destroyed QP are being flushed.
+type HandlerPtr = Option<*const dyn QpEventHandler>;
+
+/// The per-QP handlers.
+///
+/// # Invariants
+///
+/// `map` is only accessed with `locked` held, which is only taken in process context. A
+/// registered handler stays valid until its entry is removed and `QP_EVENTS` flushed.
+struct QpHandlers {
+    locked: AtomicBool,
+    map: UnsafeCell<TimeoutMap<u32, HandlerPtr, MAX_QP_HANDLERS>>,
+    /// Number of registered handlers, read in interrupt context.
+    active: AtomicU32,
+    suppressed: AtomicU64,
+}
+
+// SAFETY: `map` is protected by `locked`, and the handlers are `Sync`.
+unsafe impl Sync for QpHandlers {}
+
+impl QpHandlers {
+    const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            map: UnsafeCell::new(TimeoutMap::new()),
+            active: AtomicU32::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut TimeoutMap<u32, HandlerPtr, MAX_QP_HANDLERS>) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        // SAFETY: Holding `locked` gives exclusive access.
+        let ret = f(unsafe { &mut *self.map.get() });
+        self.locked.store(false, Ordering::Release);
+        ret
+    }
+}
+
+static QP_HANDLERS: QpHandlers = QpHandlers::new();
+
+/// The event handler of a QP, delivered its events on `mlx4_ib_qp_event_wq` instead of
+/// [`Mlx4Operation::qp_event`].
+///
+/// Dropping it, when the QP is destroyed, waits for the running handler and suppresses the events
+/// still queued for the QP. It must therefore not be dropped from a QP event handler.
+pub struct QpHandler<H: QpEventHandler + 'static> {
+    qpn: u32,
+    handler: Box<H>,
+}
+
+impl<H: QpEventHandler + 'static> QpHandler<H> {
+    /// Registers `handler` for the events of QP `qpn`.
+    ///
+    /// Fails with `EBUSY` if the QP already has a handler, and with `ENOMEM` if
+    /// [`MAX_QP_HANDLERS`] QPs have one.
+    pub fn try_new(qpn: u32, handler: H) -> Result<Self> {
+        let handler = Box::try_new(handler)?;
+        let ptr: *const dyn QpEventHandler = &*handler;
+        QP_HANDLERS.with(|map| {
+            if map.get(&qpn).is_some() {
+                return Err(EBUSY);
+            }
+            map.insert(qpn, Some(ptr)).map_err(|_| ENOMEM)?;
+            Ok(())
+        })?;
+        QP_HANDLERS.active.fetch_add(1, Ordering::Relaxed);
+        Ok(Self { qpn, handler })
+    }
+
+    /// Returns the QP number.
+    pub fn qpn(&self) -> u32 {
+        self.qpn
+    }
+
+    /// Returns the handler.
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+}
+
+impl<H: QpEventHandler + 'static> Drop for QpHandler<H> {
+    fn drop(&mut self) {
+        // Leave a tombstone, so that the events queued so far are suppressed rather than passed
+        // to `Mlx4Operation::qp_event`, then wait for them and for a running handler.
+        QP_HANDLERS.with(|map| map.insert(self.qpn, None).ok());
+        QP_HANDLERS.active.fetch_sub(1, Ordering::Relaxed);
+        QP_EVENTS.flush();
+        QP_HANDLERS.with(|map| map.remove(&self.qpn));
+    }
+}
+
+static DEV_EVENTS: Dispatcher = Dispatcher::new();
+static QP_EVENTS: Dispatcher = Dispatcher::new();
+static MASK: AtomicU32 = AtomicU32::new(EventMask::ALL.0);
+
+/// Returns the event classes `T` has handlers for.
+pub(crate) fn subscribed<T: Mlx4Operation>() -> EventMask {
+    let mut mask = EventMask::empty();
+    if T::HAS_PORT_EVENT {
+        mask = mask | EventMask::PORT;
+    }
+    if T::HAS_CATASTROPHIC_ERROR {
+        mask = mask | EventMask::CATASTROPHIC;
+    }
+    if T::HAS_QP_EVENT {
+        mask = mask | EventMask::QP;
+    }
+    if T::HAS_COMPLETION {
+        mask = mask | EventMask::COMPLETION;
+    }
+    if T::HAS_SLAVE_INIT || T::HAS_SLAVE_SHUTDOWN {
+        mask = mask | EventMask::SLAVE;
+    }
+    mask
+}
+
+fn wants<T: Mlx4Operation>(class: EventMask) -> bool {
+    subscribed::<T>().contains(class) && mask().contains(class)
+}
+
+/// Starts dispatching events to `T` on `wq` and `qp_wq`; without a queue, the events that would
+/// run on it are dropped.
+///
+/// Only one registration per module can receive events; the others fail with `EBUSY`.
+pub(crate) fn start<T: Mlx4Operation>(
+    wq: Option<&OwnedQueue>,
+    qp_wq: Option<&OwnedQueue>,
+) -> Result {
+    if DEV_EVENTS
+        .claimed
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return Err(EBUSY);
+    }
+    MASK.store(EventMask::ALL.0, Ordering::Relaxed);
+    if let Some(wq) = wq {
+        DEV_EVENTS.start(wq, dev_work::<T>);
+    }
+    if let Some(qp_wq) = qp_wq {
+        QP_EVENTS.start(qp_wq, qp_work::<T>);
+    }
+    Ok(())
+}
+
+/// Stops dispatching events and waits for the handlers that are running.
+pub(crate) fn stop() {
+    QP_EVENTS.stop();
+    DEV_EVENTS.stop();
+    DEV_EVENTS.claimed.store(false, Ordering::Release);
+}
+
+/// Waits for the queued events to be handled.
+pub(crate) fn flush() {
+    DEV_EVENTS.flush();
+    QP_EVENTS.flush();
+}
+
+/// Mutes the classes not in `mask`.
+pub(crate) fn set_mask(mask: EventMask) {
+    MASK.store(mask.0, Ordering::Relaxed);
+}
+
+/// Returns the classes that are not muted.
+pub(crate) fn mask() -> EventMask {
+    EventMask(MASK.load(Ordering::Relaxed))
+}
+
+/// Returns the number of events lost because the handlers fell behind.
+pub(crate) fn dropped() -> u64 {
+    DEV_EVENTS.events.dropped() + QP_EVENTS.events.dropped()
+}
+
+/// Returns the number of events suppressed because their QP was destroyed.
+pub(crate) fn suppressed() -> u64 {
+    QP_HANDLERS.suppressed.load(Ordering::Relaxed)
+}
+
+/// Filters and queues a device event of `dev`, whose driver context is `context`, for the process
+/// context handlers.
+pub(crate) fn dev_event<T: Mlx4Operation>(
+    dev: *mut bindings::mlx4_dev,
+    context: *mut core::ffi::c_void,
+    event: Mlx4DevEvent,
+) {
+    let event = if let Some((port, event)) = event.port_event() {
+        if !wants::<T>(EventMask::PORT) {
+            return;
+        }
+        Event::Port(dev, port, event)
+    } else if event == Mlx4DevEvent::CatastrophicError {
+        if !wants::<T>(EventMask::CATASTROPHIC) {
+            return;
+        }
+        Event::Catastrophic(dev)
+    } else if let Some(slave) = event.slave() {
+        // The table is kept up to date, and proxied CM IDs cleaned up, even if `T` does not
+        // subscribe to the slave events.
+        let up = matches!(event, Mlx4DevEvent::SlaveInit { .. });
+        if !context.is_null() {
+            // SAFETY: mlx4_core only calls the event callback with the context of an added
+            // device, and removes the device from the callers before `remove` drops the context.
+            let context = unsafe { &*context.cast::<T::Context>() };
+            if let Some(sriov) = T::sriov(context) {
+                sriov.slaves().set_active(slave, up);
+            }
+        }
+        Event::Slave(dev, context, slave, up)
+    } else {
+        return;
+    };
+    DEV_EVENTS.post(event);
+}
+
+/// Filters and queues a QP event.
+pub(crate) fn qp_event<T: Mlx4Operation>(qpn: u32, event: bindings::mlx4_event) {
+    let handled = T::HAS_QP_EVENT || QP_HANDLERS.active.load(Ordering::Relaxed) != 0;
+    if !handled || !mask().contains(EventMask::QP) {
+        return;
+    }
+    if let Some(event) = QpEvent::from_raw(event) {
+        QP_EVENTS.post(Event::Qp(qpn, event));
+    }
+}
+
+/// Delivers a completion event.
+pub(crate) fn completion<T: Mlx4Operation>(cqn: u32) {
+    if wants::<T>(EventMask::COMPLETION) {
+        T::completion(cqn);
+    }
+}
+
+unsafe extern "C" fn dev_work<T: Mlx4Operation>(_work: *mut bindings::work_struct) {
+    while let Some(event) = DEV_EVENTS.events.pop() {
+        match event {
+            Event::Catastrophic(dev) => {
+                // SAFETY: `remove` flushes this work before the device goes away.
+                T::catastrophic_error(unsafe { Mlx4Device::from_ptr(dev) })
+            }
+            Event::Port(dev, port, event) => {
+                // SAFETY: `remove` flushes this work before the device goes away.
+                T::port_event(unsafe { Mlx4Device::from_ptr(dev) }, port, event)
+            }
+            Event::Slave(dev, context, slave, up) => {
+                if !up && !context.is_null() {
+                    // SAFETY: `remove` flushes this work before the context is dropped.
+                    let context = unsafe { &*context.cast::<T::Context>() };
+                    if let Some(sriov) = T::sriov(context) {
+                        sriov.cm_ids().remove_slave(slave);
+                    }
+                }
+                if !wants::<T>(EventMask::SLAVE) {
+                    continue;
+                }
+                // SAFETY: `remove` flushes this work before the device goes away.
+                let dev = unsafe { Mlx4Device::from_ptr(dev) };
+                if up {
+                    T::slave_init(dev, slave);
+                } else {
+                    T::slave_shutdown(dev, slave);
+                }
+            }
+            Event::Qp(..) => {}
+        }
+    }
+}
+
+unsafe extern "C" fn qp_work<T: Mlx4Operation>(_work: *mut bindings::work_struct) {
+    while let Some(event) = QP_EVENTS.events.pop() {
+        if let Event::Qp(qpn, event) = event {
+            match QP_HANDLERS.with(|map| map.get(&qpn).copied()) {
+                // SAFETY: By the type invariants the handler stays valid until this work item
+                // is flushed, which only happens after we return.
+                Some(Some(handler)) => unsafe { (*handler).handle(qpn, event) },
+                Some(None) => {
+                    QP_HANDLERS.suppressed.fetch_add(1, Ordering::Relaxed);
+                }
+                None => T::qp_event(qpn, event),
+            }
+        }
+    }
+}
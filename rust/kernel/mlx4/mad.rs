@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Management datagrams processed by firmware.
+//!
+//! ConnectX HCAs implement the subnet management agent and the performance management agent in
+//! firmware. The IB driver hands them the MADs received on QP0 and QP1, and the ones ib_core
+//! generates locally, with the `MAD_IFC` command, typically from
+//! [`crate::ib::IbDeviceOperations::process_mad`], and sends back the reply firmware writes.
+//! Received MADs go with their sender and GRH, which the agents check the keys against.
+
+use core::ops::BitOr;
+
+use super::cmd::{CmdInput, Mailbox, Mlx4Cmd, TIMEOUT_CLASS_C};
+use super::device::{Mlx4Device, MAX_PORTS};
+use crate::error::{code::*, Result};
+use crate::ib::MadSource;
+use crate::rdma::mad::{GRH_SIZE, MAD_SIZE};
+
+/// Offset in the input mailbox of the sender of a received MAD, after the MAD.
+const EXT_INFO_OFFSET: usize = MAD_SIZE;
+
+/// Offset of the GRH in the sender information.
+const EXT_GRH_OFFSET: usize = 64;
+
+/// Opcode modifier bit telling that the sender information follows the MAD.
+const OP_MOD_EXT_INFO: u8 = 0x4;
+
+/// Opcode modifier bit of MADs run natively on multi-function devices.
+const OP_MOD_NATIVE: u8 = 0x8;
+
+/// Keys firmware does not check, `MLX4_MAD_IFC_IGNORE_*`.
+///
+/// The values match the `IB_MAD_IGNORE_*` flags of [`crate::ib::MadRequest::flags`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MadIfcFlags(u32);
+
+impl MadIfcFlags {
+    /// Every key is checked.
+    pub const NONE: Self = Self(0);
+    /// The M_Key of SMPs is not checked.
+    pub const IGNORE_MKEY: Self = Self(1 << 0);
+    /// The B_Key of baseboard management MADs is not checked.
+    pub const IGNORE_BKEY: Self = Self(1 << 1);
+    /// The MAD is processed with the view of the network rather than of the function, on
+    /// multi-function devices.
+    pub const NET_VIEW: Self = Self(1 << 2);
+
+    /// Returns the flags set in `raw`, ignoring unknown ones.
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw & (Self::IGNORE_MKEY.0 | Self::IGNORE_BKEY.0))
+    }
+
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if every flag of `other` is set.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for MadIfcFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl Mlx4Device {
+    /// Passes `in_mad` to the firmware agents of `port` and writes their reply to `out_mad`.
+    ///
+    /// `source` is the sender of a received MAD, with its GRH, and `None` for the MADs generated
+    /// locally. Both MADs are [`MAD_SIZE`] bytes. Fails with `EINVAL` if they are shorter or the
+    /// port does not exist, and with the firmware's error otherwise. Sleeps.
+    pub fn mad_ifc(
+        &self,
+        port: u32,
+        flags: MadIfcFlags,
+        source: Option<&MadSource>,
+        in_mad: &[u8],
+        out_mad: &mut [u8],
+    ) -> Result {
+        if port == 0 || port > MAX_PORTS || in_mad.len() < MAD_SIZE || out_mad.len() < MAD_SIZE {
+            return Err(EINVAL);
+        }
+        let mut inbox = Mailbox::try_new(self)?;
+        let buf = inbox.as_mut_slice();
+        buf[..MAD_SIZE].copy_from_slice(&in_mad[..MAD_SIZE]);
+        let mut in_modifier = port;
+        let keys = MadIfcFlags::IGNORE_MKEY | MadIfcFlags::IGNORE_BKEY;
+        let mut op_modifier = (flags.bits() & keys.bits()) as u8;
+        if let Some(source) = source {
+            // The layout of the C driver's `ext_info`.
+            let ext = &mut buf[EXT_INFO_OFFSET..EXT_INFO_OFFSET + MAD_SIZE];
+            ext.fill(0);
+            ext[0..4].copy_from_slice(&source.qp_num.to_be_bytes());
+            ext[8..12].copy_from_slice(&source.src_qp.to_be_bytes());
+            ext[12] = source.sl << 4;
+            ext[13] = source.dlid_path_bits | if source.grh.is_some() { 0x80 } else { 0 };
+            ext[18..20].copy_from_slice(&source.pkey_index.to_be_bytes());
+            if let Some(grh) = &source.grh {
+                ext[EXT_GRH_OFFSET..EXT_GRH_OFFSET + GRH_SIZE].copy_from_slice(grh);
+            }
+            op_modifier |= OP_MOD_EXT_INFO;
+            in_modifier |= (source.slid & 0xffff) << 16;
+        }
+        let mfunc = self.is_master() || self.is_slave();
+        let native = mfunc && (flags.contains(MadIfcFlags::NET_VIEW) || source.is_some());
+        if native && !self.is_master() {
+            op_modifier |= OP_MOD_NATIVE;
+        }
+        let mut outbox = Mailbox::try_new(self)?;
+        Mlx4Cmd::new(Mlx4Cmd::MAD_IFC)
+            .with_in_modifier(in_modifier)
+            .with_op_modifier(op_modifier)
+            .with_timeout(TIMEOUT_CLASS_C)
+            .with_native(native)
+            .run_box(self, CmdInput::mailbox(&inbox), &mut outbox)?;
+        out_mad[..MAD_SIZE].copy_from_slice(&outbox.as_slice()[..MAD_SIZE]);
+        Ok(())
+    }
+}
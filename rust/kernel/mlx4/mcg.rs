@@ -0,0 +1,413 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Multicast group proxying for SR-IOV guests.
+//!
+//! The master joins multicast groups with the SA on behalf of its slaves, like the C driver's
+//! `mlx4_ib_mcg`. The membership rules of a group live in [`crate::rdma::mcg`]; a [`McgTable`]
+//! keeps the groups of a device, hands the resulting actions to [`Mlx4Operation::mcg_action`] and
+//! runs the deferred parts on `mlx4_ib_mcg`: a delayed work item gives up on the MADs the SA does
+//! not answer in time, and another one tears every group down when the device is removed.
+//!
+//! Each device has its own table, in the [`super::Sriov`] the driver returns from
+//! [`Mlx4Operation::sriov`]; the abstraction runs it while the device is added.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::marker::PhantomPinned;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::ptr;
+
+use super::Mlx4Operation;
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::ib::compat::try_pin;
+use crate::pr_warn;
+use crate::rdma::gid::Gid;
+use crate::rdma::mcg::{McgGroup, MAD_TIMEOUT_MS, MAX_PENDING};
+use crate::rdma::ring::Ring;
+use crate::sync::smutex::Mutex;
+
+pub use crate::rdma::mcg::{McgAction, McgRequest, McgState, McgStatus};
+
+/// Largest number of groups proxied at once per device.
+pub const MAX_GROUPS: usize = 64;
+
+/// Largest number of actions of one step of a group.
+const MAX_ACTIONS: usize = 32;
+
+// A step yields at most a reply and a MAD for the request and each queued one, plus the
+// destruction.
+const _: () = assert!(MAX_ACTIONS >= 2 * (MAX_PENDING + 1) + 1);
+
+/// Actions of one step of a group, delivered once the table is unlocked.
+struct Actions {
+    ring: Ring<McgAction, MAX_ACTIONS>,
+    /// Whether an action did not fit in `ring`.
+    lost: bool,
+}
+
+impl Actions {
+    fn new() -> Self {
+        Self {
+            ring: Ring::new(),
+            lost: false,
+        }
+    }
+
+    /// Returns the sink of the step, which records the actions that do not fit.
+    fn push(&mut self) -> impl FnMut(McgAction) + '_ {
+        move |action| {
+            if self.ring.push(action).is_err() {
+                self.lost = true;
+            }
+        }
+    }
+
+    /// Fails with `ENOMEM` if an action of the step was lost.
+    fn result(&self) -> Result {
+        if self.lost {
+            Err(ENOMEM)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A multicast group of a port.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct McgKey {
+    /// Port the group is joined on.
+    pub port: u8,
+    /// Multicast GID of the group.
+    pub mgid: Gid,
+}
+
+struct Slot {
+    key: McgKey,
+    group: McgGroup,
+}
+
+struct Groups {
+    slots: [Option<Slot>; MAX_GROUPS],
+}
+
+impl Groups {
+    const EMPTY: Option<Slot> = None;
+
+    fn position(&self, key: &McgKey) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| matches!(slot, Some(s) if s.key == *key))
+    }
+
+    /// Runs `f` on group `i` and frees it once it is destroyed.
+    fn step(
+        &mut self,
+        i: usize,
+        actions: &mut Actions,
+        f: impl FnOnce(&mut McgGroup, &mut Actions),
+    ) {
+        if let Some(slot) = &mut self.slots[i] {
+            f(&mut slot.group, actions);
+            if slot.group.state() == McgState::Dead {
+                self.slots[i] = None;
+            }
+        }
+    }
+
+    fn next_deadline(&self, now: u64) -> Option<u64> {
+        self.slots
+            .iter()
+            .flatten()
+            .filter_map(|s| s.group.deadline())
+            .min_by_key(|d| d.wrapping_sub(now) as i64)
+    }
+}
+
+struct State {
+    groups: Groups,
+    /// The queue running the work items, null while the device is not added.
+    queue: *mut bindings::workqueue_struct,
+    /// Handler of the registration, set with `queue`.
+    handler: Option<fn(McgKey, McgAction)>,
+}
+
+/// # Invariants
+///
+/// The work items are initialised, and only queued on `state.queue` while it is non-null.
+struct Inner {
+    state: Mutex<State>,
+    timeout_work: UnsafeCell<MaybeUninit<bindings::delayed_work>>,
+    cleanup_work: UnsafeCell<MaybeUninit<bindings::work_struct>>,
+    _pin: PhantomPinned,
+}
+
+/// The multicast groups proxied for the slaves of a device.
+pub struct McgTable {
+    inner: Pin<Box<Inner>>,
+}
+
+// SAFETY: The state is protected by its mutex, and the work items are only handed to the workqueue
+// core, which synchronises its own accesses.
+unsafe impl Send for McgTable {}
+// SAFETY: See the `Send` implementation.
+unsafe impl Sync for McgTable {}
+
+fn jiffies() -> u64 {
+    // SAFETY: `jiffies` is always valid to read; it is volatile as the tick updates it.
+    unsafe { ptr::read_volatile(ptr::addr_of!(bindings::jiffies)) as u64 }
+}
+
+fn forward<T: Mlx4Operation>(key: McgKey, action: McgAction) {
+    T::mcg_action(key, action);
+}
+
+impl McgTable {
+    /// Creates an empty table, run once its device is added.
+    pub fn try_new() -> Result<Self> {
+        let inner = try_pin(Inner {
+            state: Mutex::new(State {
+                groups: Groups {
+                    slots: [Groups::EMPTY; MAX_GROUPS],
+                },
+                queue: ptr::null_mut(),
+                handler: None,
+            }),
+            timeout_work: UnsafeCell::new(MaybeUninit::uninit()),
+            cleanup_work: UnsafeCell::new(MaybeUninit::uninit()),
+            _pin: PhantomPinned,
+        })?;
+        // SAFETY: The work items are pinned with the table and not queued yet.
+        unsafe {
+            bindings::init_delayed_work(inner.timeout_work(), Some(Inner::timeout_fn));
+            bindings::init_work(inner.cleanup_work(), Some(Inner::cleanup_fn));
+        }
+        // INVARIANT: The work items were just initialised.
+        Ok(Self { inner })
+    }
+
+    /// Handles `req` of a slave for the group `key`, creating the group on first use.
+    ///
+    /// The resulting MADs and replies are passed to [`Mlx4Operation::mcg_action`]. Fails with
+    /// `ENOMEM` if [`MAX_GROUPS`] groups are proxied already, and with `EOPNOTSUPP` while the
+    /// device is not added or if the multicast proxy's workqueue is left out of the
+    /// [`super::WorkQueueConfig`].
+    pub fn request(&self, key: McgKey, req: McgRequest) -> Result {
+        let now = jiffies();
+        self.inner.step(key, true, |group, actions| {
+            group.request(req, now, &mut actions.push())
+        })
+    }
+
+    /// Handles the answer of the SA to the MAD in flight for the group `key`; `ok` is `false` if
+    /// the MAD failed.
+    ///
+    /// Fails with `ENOENT` if the group is gone, e.g. after a timeout.
+    pub fn response(&self, key: McgKey, ok: bool) -> Result {
+        let now = jiffies();
+        self.inner.step(key, false, |group, actions| {
+            group.response(ok, now, &mut actions.push())
+        })
+    }
+
+    /// Returns the number of proxied groups.
+    pub fn len(&self) -> usize {
+        self.inner
+            .state
+            .lock()
+            .groups
+            .slots
+            .iter()
+            .flatten()
+            .count()
+    }
+
+    /// Returns `true` if no group is proxied.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Starts running the group timeouts and teardown on `queue`, for `T`, once the device is
+    /// added.
+    ///
+    /// # Safety
+    ///
+    /// `queue` must stay alive until [`McgTable::stop`] returns.
+    pub(crate) unsafe fn start<T: Mlx4Operation>(&self, queue: *mut bindings::workqueue_struct) {
+        let mut state = self.inner.state.lock();
+        // INVARIANT: The work items are initialised; they are only queued from now on.
+        state.handler = Some(forward::<T>);
+        state.queue = queue;
+    }
+
+    /// Tears every group down on the queue and waits for it, when the device is removed.
+    pub(crate) fn teardown(&self) {
+        let queue = self.inner.state.lock().queue;
+        if queue.is_null() {
+            return;
+        }
+        // SAFETY: By the type invariants the work item is initialised, and the queue is alive
+        // until `stop`, which is not called concurrently with the removal of the device.
+        unsafe {
+            bindings::queue_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                queue,
+                self.inner.cleanup_work(),
+            );
+            bindings::flush_work(self.inner.cleanup_work());
+        }
+    }
+
+    /// Stops the work items and forgets every group, before the device is removed.
+    ///
+    /// Waits for the work items, so the queue may be destroyed once this returns.
+    pub(crate) fn stop(&self) {
+        {
+            let mut state = self.inner.state.lock();
+            // INVARIANT: The work items are no longer queued once the queue is cleared under the
+            // lock, except by a running timeout, which sees the cleared queue.
+            state.queue = ptr::null_mut();
+            state.handler = None;
+            state.groups.slots = [Groups::EMPTY; MAX_GROUPS];
+        }
+        // SAFETY: The work items are initialised by the type invariants, and nothing queues them
+        // anymore.
+        unsafe {
+            bindings::cancel_work_sync(self.inner.cleanup_work());
+            bindings::cancel_delayed_work_sync(self.inner.timeout_work());
+        }
+    }
+}
+
+impl Drop for McgTable {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Inner {
+    fn timeout_work(&self) -> *mut bindings::delayed_work {
+        self.timeout_work.get().cast()
+    }
+
+    fn cleanup_work(&self) -> *mut bindings::work_struct {
+        self.cleanup_work.get().cast()
+    }
+
+    /// Hands `actions` of the group `key` to `handler`, then fails if some were lost.
+    fn deliver(
+        handler: Option<fn(McgKey, McgAction)>,
+        key: McgKey,
+        actions: &mut Actions,
+    ) -> Result {
+        if let Some(handler) = handler {
+            while let Some(action) = actions.ring.pop() {
+                handler(key, action);
+            }
+        }
+        actions.result()
+    }
+
+    /// Runs `f` on the group `key`, creating it if `create` is set.
+    fn step(
+        &self,
+        key: McgKey,
+        create: bool,
+        f: impl FnOnce(&mut McgGroup, &mut Actions),
+    ) -> Result {
+        let mut actions = Actions::new();
+        let handler = {
+            let mut state = self.state.lock();
+            if state.queue.is_null() {
+                return Err(EOPNOTSUPP);
+            }
+            let groups = &mut state.groups;
+            let i = match groups.position(&key) {
+                Some(i) => i,
+                None if create => {
+                    let i = groups
+                        .slots
+                        .iter()
+                        .position(Option::is_none)
+                        .ok_or(ENOMEM)?;
+                    // SAFETY: FFI call without preconditions.
+                    let timeout = unsafe { bindings::usecs_to_jiffies(MAD_TIMEOUT_MS * 1000) };
+                    groups.slots[i] = Some(Slot {
+                        key,
+                        group: McgGroup::new(timeout as u64),
+                    });
+                    i
+                }
+                None => return Err(ENOENT),
+            };
+            groups.step(i, &mut actions, f);
+            self.arm(&state);
+            state.handler
+        };
+        Self::deliver(handler, key, &mut actions)
+    }
+
+    /// Arms the timeout work item for the earliest deadline, if the device is added.
+    ///
+    /// A pending work item keeps its expiry, which is never later than the one of a MAD sent
+    /// since.
+    fn arm(&self, state: &State) {
+        if state.queue.is_null() {
+            return;
+        }
+        let now = jiffies();
+        let deadline = match state.groups.next_deadline(now) {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        // SAFETY: By the type invariants the work item is initialised, and the queue is alive
+        // while it is set, which we checked under the lock.
+        unsafe {
+            bindings::queue_delayed_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                state.queue,
+                self.timeout_work(),
+                deadline.wrapping_sub(now).max(1) as _,
+            )
+        };
+    }
+
+    /// Runs `f` on every group, one at a time.
+    fn for_each(&self, f: impl Fn(&mut McgGroup, &mut Actions)) {
+        for i in 0..MAX_GROUPS {
+            let mut actions = Actions::new();
+            let (key, handler) = {
+                let mut state = self.state.lock();
+                let key = state.groups.slots[i].as_ref().map(|s| s.key);
+                state.groups.step(i, &mut actions, &f);
+                (key, state.handler)
+            };
+            if let Some(key) = key {
+                if Self::deliver(handler, key, &mut actions).is_err() {
+                    pr_warn!("Lost the actions of multicast group {:?}\n", key.mgid);
+                }
+            }
+        }
+    }
+
+    unsafe extern "C" fn timeout_fn(work: *mut bindings::work_struct) {
+        // SAFETY: The work item is the `timeout_work` field of a pinned `Inner`, which is dropped
+        // only after the work item is cancelled.
+        let this = unsafe {
+            &*crate::container_of!(work.cast::<bindings::delayed_work>(), Inner, timeout_work)
+        };
+        let now = jiffies();
+        this.for_each(|group, actions| {
+            group.timeout(now, &mut actions.push());
+        });
+        this.arm(&this.state.lock());
+    }
+
+    unsafe extern "C" fn cleanup_fn(work: *mut bindings::work_struct) {
+        // SAFETY: The work item is the `cleanup_work` field of a pinned `Inner`, which is dropped
+        // only after the work item is cancelled.
+        let this = unsafe { &*crate::container_of!(work, Inner, cleanup_work) };
+        this.for_each(|group, actions| group.teardown(&mut actions.push()));
+    }
+}
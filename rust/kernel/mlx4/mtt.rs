@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory translation tables.
+//!
+//! The HCA translates the addresses of memory regions and queue buffers through MTT segments:
+//! arrays of page addresses in its ICM, allocated in power-of-two blocks by mlx4_core. [`Mtt`] owns
+//! such a block, sized for a number of pages of one size, and fills it from the DMA blocks of a
+//! [`Umem`] or the pages of a [`Buf`] mapped for the same device. The block is freed when dropped.
+
+use super::buf::Buf;
+use super::device::Mlx4Device;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::ib::Umem;
+
+/// Number of page addresses written per `mlx4_write_mtt` call when filling from an iterator.
+const CHUNK: usize = 64;
+
+/// An MTT block allocated from mlx4_core, freed when dropped.
+///
+/// # Invariants
+///
+/// `mtt` was initialised on `dev` by `mlx4_mtt_init` for `npages` pages and is not cleaned up yet,
+/// and `dev` stays valid until the [`Mtt`] is dropped.
+pub struct Mtt {
+    dev: *mut bindings::mlx4_dev,
+    mtt: bindings::mlx4_mtt,
+    npages: usize,
+}
+
+impl Mtt {
+    /// Allocates the entries of `npages` pages of `1 << page_shift` bytes.
+    ///
+    /// With no pages, nothing is allocated and the MTT describes physically addressed memory.
+    ///
+    /// # Safety
+    ///
+    /// The MTT must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`super::Mlx4Operation::add`] ensures this, as [`super::Mlx4Operation::remove`] drops the
+    /// context before mlx4_core removes the device.
+    pub unsafe fn try_new(dev: &Mlx4Device, npages: usize, page_shift: u32) -> Result<Self> {
+        if npages > i32::MAX as usize || page_shift >= 64 {
+            return Err(EINVAL);
+        }
+        let mut mtt = bindings::mlx4_mtt::default();
+        // SAFETY: The device is valid and `mtt` a valid out pointer.
+        let err = unsafe {
+            bindings::mlx4_mtt_init(dev.as_ptr(), npages as _, page_shift as _, &mut mtt)
+        };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // INVARIANT: The MTT was just initialised for `npages` pages, and the safety requirements
+        // keep the device valid until it is cleaned up.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            mtt,
+            npages,
+        })
+    }
+
+    /// Returns the raw `struct mlx4_mtt` pointer, e.g. for `mlx4_cq_alloc`.
+    pub fn as_ptr(&self) -> *const bindings::mlx4_mtt {
+        &self.mtt
+    }
+
+    /// Returns the number of pages the MTT translates.
+    pub fn npages(&self) -> usize {
+        self.npages
+    }
+
+    /// Returns the log2 of the page size.
+    pub fn page_shift(&self) -> u32 {
+        self.mtt.page_shift as u32
+    }
+
+    /// Returns the ICM address of the first entry, to be written to the queue or MPT context.
+    pub fn addr(&self) -> u64 {
+        // SAFETY: The device is valid and the MTT initialised by the type invariants;
+        // `mlx4_mtt_addr` only reads it.
+        unsafe { bindings::mlx4_mtt_addr(self.dev, &self.mtt as *const _ as *mut _) }
+    }
+
+    /// Writes the DMA addresses `pages` to the entries starting at `start`.
+    ///
+    /// Fails with `EINVAL` if the entries are out of the MTT.
+    fn write(&mut self, start: usize, pages: &[u64]) -> Result {
+        let end = start.checked_add(pages.len()).ok_or(EINVAL)?;
+        if end > self.npages {
+            return Err(EINVAL);
+        }
+        if pages.is_empty() {
+            return Ok(());
+        }
+        // SAFETY: The MTT is initialised by the type invariants and the entries were checked to
+        // be in it; `mlx4_write_mtt` only reads the page list.
+        let err = unsafe {
+            bindings::mlx4_write_mtt(
+                self.dev,
+                &mut self.mtt,
+                start as _,
+                pages.len() as _,
+                pages.as_ptr() as *mut u64,
+            )
+        };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        Ok(())
+    }
+
+    /// Writes the DMA addresses yielded by `pages` to the entries starting at 0.
+    ///
+    /// Returns the number of entries written. Fails with `EINVAL` if `pages` yields more addresses
+    /// than the MTT has entries.
+    fn write_pages(&mut self, pages: impl IntoIterator<Item = u64>) -> Result<usize> {
+        let mut chunk = [0u64; CHUNK];
+        let mut len = 0;
+        let mut written = 0;
+        for page in pages {
+            chunk[len] = page;
+            len += 1;
+            if len == CHUNK {
+                self.write(written, &chunk)?;
+                written += len;
+                len = 0;
+            }
+        }
+        self.write(written, &chunk[..len])?;
+        Ok(written + len)
+    }
+
+    /// Writes the DMA blocks of `umem`, of the page size of the MTT, like
+    /// `mlx4_ib_umem_write_mtt`.
+    ///
+    /// Returns the number of entries written. Fails with `EINVAL` if the range was mapped for
+    /// another device or the MTT is too small for the blocks.
+    pub fn write_umem(&mut self, umem: &Umem) -> Result<usize> {
+        // SAFETY: The device is valid by the type invariants.
+        let dev = unsafe { Mlx4Device::from_ptr(self.dev) };
+        if umem.dma_device() != dev.dma_device() {
+            return Err(EINVAL);
+        }
+        let pgsz = 1u64 << self.page_shift();
+        if umem.num_dma_blocks(pgsz) > self.npages {
+            return Err(EINVAL);
+        }
+        self.write_pages(umem.dma_blocks(pgsz))
+    }
+
+    /// Writes the pages of `buf`, like `mlx4_buf_write_mtt`.
+    ///
+    /// Fails with `EINVAL` if the buffer was allocated on another device or the MTT does not
+    /// match its pages.
+    pub fn write_buf(&mut self, buf: &Buf) -> Result {
+        if buf.device() != self.dev {
+            return Err(EINVAL);
+        }
+        if buf.npages() as usize > self.npages || buf.page_shift() != self.page_shift() {
+            return Err(EINVAL);
+        }
+        // SAFETY: The MTT is initialised on the device of the buffer by the type invariants, with
+        // enough entries of the page size of the buffer.
+        unsafe { buf.write_mtt(&mut self.mtt) }
+    }
+}
+
+impl Drop for Mtt {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the MTT was initialised on the device, which is still
+        // valid.
+        unsafe { bindings::mlx4_mtt_cleanup(self.dev, &mut self.mtt) };
+    }
+}
+
+// SAFETY: The MTT can be cleaned up from any thread; mlx4_core serialises its allocator.
+unsafe impl Send for Mtt {}
+// SAFETY: Shared references only read the fields.
+unsafe impl Sync for Mtt {}
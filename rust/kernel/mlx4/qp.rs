@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! QP number ranges.
+//!
+//! QP numbers are owned by mlx4_core, which reserves blocks of them for its users with
+//! `mlx4_qp_reserve_range`. A driver that needs many QPs with related numbers, e.g. the steerable
+//! UD QPs of flow steering, reserves one aligned block up front and sub-allocates from it, as
+//! `mlx4_ib_steer_qp_alloc` does with a bitmap. [`QpRange`] holds such a block and gives it back
+//! when dropped, and [`QpnAllocator`] hands out its numbers with an [`IdAllocator`].
+
+use alloc::vec::Vec;
+
+use super::device::Mlx4Device;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::rdma::id_alloc::{words_for, IdAllocator};
+use crate::rdma::pool::IndexRange;
+
+/// A block of consecutive QP numbers reserved from mlx4_core, released when dropped.
+///
+/// # Invariants
+///
+/// `count` numbers starting at `base` were reserved on `dev` by `mlx4_qp_reserve_range` and are
+/// not released yet, and `dev` stays valid until the [`QpRange`] is dropped.
+pub struct QpRange {
+    dev: *mut bindings::mlx4_dev,
+    base: u32,
+    count: u32,
+}
+
+impl QpRange {
+    /// Reserves `count` consecutive QP numbers, the first of which is a multiple of `align`.
+    ///
+    /// # Safety
+    ///
+    /// The range must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`super::Mlx4Operation::add`] ensures this, as [`super::Mlx4Operation::remove`] drops the
+    /// context before mlx4_core removes the device.
+    pub unsafe fn try_new(dev: &Mlx4Device, count: u32, align: u32) -> Result<Self> {
+        if count == 0 || !align.is_power_of_two() {
+            return Err(EINVAL);
+        }
+        let mut base = 0;
+        // SAFETY: The device is valid and `base` a valid out pointer.
+        let err = unsafe {
+            bindings::mlx4_qp_reserve_range(
+                dev.as_ptr(),
+                count as _,
+                align as _,
+                &mut base,
+                0,
+                bindings::mlx4_res_usage_MLX4_RES_USAGE_DRIVER as _,
+            )
+        };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // INVARIANT: The range was just reserved, and the caller drops it before the device
+        // goes away.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            base: base as u32,
+            count,
+        })
+    }
+
+    /// Returns the first QP number.
+    pub fn base(&self) -> u32 {
+        self.base
+    }
+
+    /// Returns the number of QP numbers.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Returns the QP numbers, bounds included.
+    pub fn range(&self) -> IndexRange {
+        IndexRange {
+            min: self.base,
+            max: self.base + self.count - 1,
+        }
+    }
+}
+
+impl Drop for QpRange {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the range was reserved on the device, which is still
+        // valid.
+        unsafe { bindings::mlx4_qp_release_range(self.dev, self.base as _, self.count as _) };
+    }
+}
+
+// SAFETY: The range can be released from any thread; mlx4_core serialises QP number allocation.
+unsafe impl Send for QpRange {}
+// SAFETY: Shared references only read the fields.
+unsafe impl Sync for QpRange {}
+
+/// Sub-allocator of the QP numbers of a [`QpRange`].
+///
+/// Synchronisation is left to the owner, e.g. a mutex around the allocator.
+pub struct QpnAllocator {
+    ids: IdAllocator<Vec<u64>>,
+    range: QpRange,
+}
+
+impl QpnAllocator {
+    /// Creates an allocator handing out the numbers of `range`, all free.
+    pub fn try_new(range: QpRange) -> Result<Self> {
+        let len = words_for(range.count());
+        let mut words = Vec::try_with_capacity(len)?;
+        for _ in 0..len {
+            words.try_push(0)?;
+        }
+        let ids = IdAllocator::new(range.range(), words).ok_or(EINVAL)?;
+        Ok(Self { ids, range })
+    }
+
+    /// Returns the reserved range.
+    pub fn qp_range(&self) -> &QpRange {
+        &self.range
+    }
+
+    /// Allocates one QP number.
+    ///
+    /// Fails with `ENOMEM` once the range is exhausted.
+    pub fn alloc(&mut self) -> Result<u32> {
+        self.ids.alloc().ok_or(ENOMEM)
+    }
+
+    /// Allocates `count` consecutive QP numbers, the first of which is a multiple of `align` from
+    /// the start of the range, and returns the first one.
+    pub fn alloc_block(&mut self, count: u32, align: u32) -> Result<u32> {
+        self.ids.alloc_block(count, align).ok_or(ENOMEM)
+    }
+
+    /// Frees `qpn`. Fails with `EINVAL` if it is not allocated.
+    pub fn free(&mut self, qpn: u32) -> Result {
+        if !self.ids.free(qpn) {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+
+    /// Frees the `count` QP numbers starting at `first`.
+    pub fn free_block(&mut self, first: u32, count: u32) {
+        self.ids.free_block(first, count);
+    }
+
+    /// Returns the number of QP numbers allocated.
+    pub fn used(&self) -> u32 {
+        self.ids.used()
+    }
+}
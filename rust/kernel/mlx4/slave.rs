@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! SR-IOV functions.
+//!
+//! With SR-IOV enabled, the physical function runs as the master of the device and every virtual
+//! function used by a guest as a slave. mlx4_core reports slaves coming up and going away with the
+//! `SLAVE_INIT` and `SLAVE_SHUTDOWN` events. A master driver keeps an [`Sriov`] in the context of
+//! each device and returns it from [`super::Mlx4Operation::sriov`]: the abstraction then keeps
+//! track of the active slaves of the device in its [`SlaveTable`], runs its multicast proxy while
+//! the device is added, drops the CM IDs proxied for a slave once it shuts down, and calls
+//! [`super::Mlx4Operation::slave_init`] and [`super::Mlx4Operation::slave_shutdown`] so that the
+//! driver can set up and tear down its own per-VF state, e.g. the tunnel QPs of the paravirtual
+//! MAD multiplexing.
+//!
+//! The MADs a slave sends on its tunnel QPs go through [`Sriov::multiplex`] before the master
+//! sends them on the wire, and the MADs the master receives go through [`Sriov::demux`] to find
+//! the slave they are for, see [`crate::rdma::pv_mad`].
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::cm::CmIdMap;
+use super::device::{Mlx4Device, MAX_PORTS};
+use super::mcg::McgTable;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::rdma::gid::Gid;
+use crate::rdma::pv_mad;
+
+pub use crate::rdma::pv_mad::{Egress, Ingress};
+
+/// Largest number of functions of a device, the master included, like `MLX4_MFUNC_MAX`.
+pub const MAX_SLAVES: u32 = 128;
+
+/// The function number of an SR-IOV function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SlaveId(u8);
+
+impl SlaveId {
+    /// Returns the ID of function `raw`, `None` if it is out of range.
+    pub const fn new(raw: u32) -> Option<Self> {
+        if raw >= MAX_SLAVES {
+            return None;
+        }
+        Some(Self(raw as u8))
+    }
+
+    /// Returns the function number.
+    pub const fn as_u32(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+impl fmt::Display for SlaveId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Mlx4Device {
+    fn flags(&self) -> u64 {
+        // SAFETY: The device is valid and its flags are set while probing.
+        unsafe { (*self.as_ptr()).flags as u64 }
+    }
+
+    /// Returns `true` if this function is the master of an SR-IOV device.
+    pub fn is_master(&self) -> bool {
+        self.flags() & bindings::MLX4_FLAG_MASTER as u64 != 0
+    }
+
+    /// Returns `true` if this function is a slave, i.e. runs in a guest.
+    pub fn is_slave(&self) -> bool {
+        self.flags() & bindings::MLX4_FLAG_SLAVE as u64 != 0
+    }
+
+    /// Returns the function number of the master.
+    pub fn master_func_num(&self) -> SlaveId {
+        // SAFETY: The device is valid and `caps` is immutable while interfaces are attached.
+        let function = unsafe { (*self.as_ptr()).caps.function };
+        SlaveId(function)
+    }
+
+    /// Returns the number of slaves, 0 without SR-IOV.
+    pub fn num_slaves(&self) -> u32 {
+        // SAFETY: The device is valid and `num_slaves` is set while probing.
+        unsafe { (*self.as_ptr()).num_slaves as u32 }
+    }
+
+    /// Returns `true` if `slave` may send and receive subnet management packets on `port`.
+    pub fn vf_smi_enabled(&self, slave: SlaveId, port: u32) -> bool {
+        if port == 0 || port > MAX_PORTS {
+            return false;
+        }
+        // SAFETY: The device is valid and `port` in range.
+        unsafe { bindings::mlx4_vf_smi_enabled(self.as_ptr(), slave.0 as _, port as _) != 0 }
+    }
+
+    /// Returns the slave owning the RoCE GID `gid` of `port`, to demultiplex the MADs received
+    /// for the guests.
+    ///
+    /// Fails with `EINVAL` if the port does not exist or no function owns the GID.
+    pub fn slave_of_roce_gid(&self, port: u32, gid: &[u8; 16]) -> Result<SlaveId> {
+        if port == 0 || port > MAX_PORTS {
+            return Err(EINVAL);
+        }
+        let mut gid = *gid;
+        let mut slave = 0;
+        // SAFETY: The device is valid, `port` in range and both pointers valid; mlx4_core only
+        // reads the GID.
+        let err = unsafe {
+            bindings::mlx4_get_slave_from_roce_gid(
+                self.as_ptr(),
+                port as _,
+                gid.as_mut_ptr(),
+                &mut slave,
+            )
+        };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        SlaveId::new(slave as u32).ok_or(EINVAL)
+    }
+}
+
+/// The slaves of a device that are up.
+///
+/// It is updated from the event callback, in interrupt context, before the driver's handlers run.
+pub struct SlaveTable {
+    active: [AtomicU64; (MAX_SLAVES / 64) as usize],
+}
+
+impl SlaveTable {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicU64 = AtomicU64::new(0);
+
+    const fn new() -> Self {
+        Self {
+            active: [Self::NONE; (MAX_SLAVES / 64) as usize],
+        }
+    }
+
+    pub(crate) fn set_active(&self, slave: SlaveId, active: bool) {
+        let word = &self.active[slave.0 as usize / 64];
+        let bit = 1 << (slave.0 % 64);
+        if active {
+            word.fetch_or(bit, Ordering::AcqRel);
+        } else {
+            word.fetch_and(!bit, Ordering::AcqRel);
+        }
+    }
+
+    /// Returns `true` if `slave` is up.
+    pub fn is_active(&self, slave: SlaveId) -> bool {
+        self.active[slave.0 as usize / 64].load(Ordering::Acquire) & 1 << (slave.0 % 64) != 0
+    }
+
+    /// Returns the number of slaves that are up.
+    pub fn count(&self) -> u32 {
+        self.active
+            .iter()
+            .map(|word| word.load(Ordering::Acquire).count_ones())
+            .sum()
+    }
+
+    /// Calls `f` for every slave that is up, in increasing order.
+    pub fn for_each_active(&self, mut f: impl FnMut(SlaveId)) {
+        for (i, word) in self.active.iter().enumerate() {
+            let mut bits = word.load(Ordering::Acquire);
+            while bits != 0 {
+                let bit = bits.trailing_zeros();
+                f(SlaveId((i * 64) as u8 + bit as u8));
+                bits &= bits - 1;
+            }
+        }
+    }
+}
+
+/// The SR-IOV state of a master device: its slaves that are up, the IDs of its CM proxy and the
+/// groups of its multicast proxy.
+pub struct Sriov {
+    slaves: SlaveTable,
+    cm_ids: CmIdMap,
+    mcgs: McgTable,
+}
+
+impl Sriov {
+    /// Creates the state of a device without active slaves.
+    pub fn try_new() -> Result<Self> {
+        Ok(Self {
+            slaves: SlaveTable::new(),
+            cm_ids: CmIdMap::try_new()?,
+            mcgs: McgTable::try_new()?,
+        })
+    }
+
+    /// Returns the slaves that are up.
+    pub fn slaves(&self) -> &SlaveTable {
+        &self.slaves
+    }
+
+    /// Returns the ID map of the CM proxy.
+    pub fn cm_ids(&self) -> &CmIdMap {
+        &self.cm_ids
+    }
+
+    /// Returns the groups of the multicast proxy.
+    pub fn mcgs(&self) -> &McgTable {
+        &self.mcgs
+    }
+
+    /// Prepares the MAD `mad` that `slave` sends on `port` through its tunnel QP for the wire.
+    ///
+    /// The requests are tagged for [`Sriov::demux`] to find the slave of their responses, and the
+    /// CM MADs rewritten by the CM proxy.
+    pub fn multiplex(&self, dev: &Mlx4Device, port: u32, slave: SlaveId, mad: &mut [u8]) -> Egress {
+        let smi_enabled = dev.vf_smi_enabled(slave, port);
+        pv_mad::multiplex(mad, slave.0, dev.master_func_num().0, smi_enabled, |mad| {
+            self.cm_ids.multiplex(slave, mad).is_ok()
+        })
+    }
+
+    /// Finds the slave the MAD `mad` received on `port` is for, and rewrites it for the slave.
+    ///
+    /// `dgid` is the destination GID of the GRH of the MAD, if it has one. `slave_of` returns the
+    /// slave owning a GID of the port, e.g. [`Mlx4Device::slave_of_roce_gid`] for RoCE ports.
+    pub fn demux(
+        &self,
+        dev: &Mlx4Device,
+        port: u32,
+        mad: &mut [u8],
+        dgid: Option<Gid>,
+        slave_of: impl Fn(Gid) -> Option<SlaveId>,
+    ) -> Ingress {
+        pv_mad::demux(
+            mad,
+            dev.master_func_num().0,
+            MAX_SLAVES,
+            dgid.and_then(&slave_of).map(|slave| slave.0),
+            |slave| SlaveId::new(slave.into()).map_or(false, |id| dev.vf_smi_enabled(id, port)),
+            |mad| self.cm_ids.demux(mad, &slave_of).ok().map(|slave| slave.0),
+        )
+    }
+}
@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! User access regions and BlueFlame registers.
+//!
+//! A UAR is a page of the HCA's BAR holding the doorbell registers of the queues bound to it: the
+//! driver rings the send doorbell of a QP after posting work requests, and the CQ doorbell to arm
+//! a CQ. [`Uar`] allocates one from mlx4_core and maps it, and its doorbells order the queue
+//! entries and the doorbell record before the MMIO write with a write barrier, so the HCA never
+//! fetches stale descriptors.
+//!
+//! BlueFlame registers live in a write-combining mapping of the UAR pages: the driver copies a
+//! whole small send descriptor there, sparing the HCA the fetch by DMA. [`BlueFlame`] owns one of
+//! these registers; its two halves are written alternately, as the HCA may still be reading the
+//! previous one.
+
+use core::ffi::c_void;
+
+use super::device::Mlx4Device;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+
+/// Offset of the send doorbell in a UAR, like `MLX4_SEND_DOORBELL`.
+pub const SEND_DOORBELL: usize = 0x14;
+
+/// Offset of the CQ doorbell in a UAR, like `MLX4_CQ_DOORBELL`.
+pub const CQ_DOORBELL: usize = 0x20;
+
+/// A UAR allocated from mlx4_core and mapped uncached, freed when dropped.
+///
+/// # Invariants
+///
+/// `uar` was allocated on `dev` by `mlx4_uar_alloc` and is not freed yet, `map` maps its page with
+/// `ioremap`, and `dev` stays valid until the [`Uar`] is dropped.
+pub struct Uar {
+    dev: *mut bindings::mlx4_dev,
+    uar: bindings::mlx4_uar,
+    map: *mut c_void,
+}
+
+impl Uar {
+    /// Allocates a UAR and maps its doorbell page.
+    ///
+    /// # Safety
+    ///
+    /// The UAR must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`super::Mlx4Operation::add`] ensures this, as [`super::Mlx4Operation::remove`] drops the
+    /// context before mlx4_core removes the device.
+    pub unsafe fn try_new(dev: &Mlx4Device) -> Result<Self> {
+        let mut uar = bindings::mlx4_uar::default();
+        // SAFETY: The device is valid and `uar` a valid out pointer.
+        let err = unsafe { bindings::mlx4_uar_alloc(dev.as_ptr(), &mut uar) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // SAFETY: The page of the UAR is part of the BAR of the device.
+        let map = unsafe {
+            bindings::ioremap(
+                (uar.pfn as bindings::phys_addr_t) << bindings::PAGE_SHIFT,
+                bindings::PAGE_SIZE as _,
+            )
+        };
+        if map.is_null() {
+            // SAFETY: The UAR was just allocated on the device.
+            unsafe { bindings::mlx4_uar_free(dev.as_ptr(), &mut uar) };
+            return Err(ENOMEM);
+        }
+        // INVARIANT: The UAR was just allocated and mapped, and the safety requirements keep the
+        // device valid until it is freed.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            uar,
+            map,
+        })
+    }
+
+    /// Returns the index of the UAR, to be written to the contexts of the queues using it.
+    pub fn index(&self) -> u32 {
+        self.uar.index as u32
+    }
+
+    /// Returns the page frame number of the UAR, e.g. to map it to userspace.
+    pub fn pfn(&self) -> u64 {
+        self.uar.pfn as u64
+    }
+
+    /// Writes `value` big-endian to the register at `offset`.
+    ///
+    /// No barrier is issued, the doorbells issue the one they need. Fails with `EINVAL` if
+    /// `offset` is misaligned or out of the page.
+    fn write_be32(&self, offset: usize, value: u32) -> Result {
+        let reg = self.reg(offset, 4)?;
+        // SAFETY: `reg` was checked to be in the mapped page.
+        unsafe { bindings::iowrite32be(value, reg) };
+        Ok(())
+    }
+
+    /// Writes the big-endian words `hi` and `lo` to the 64-bit register at `offset` in a single
+    /// write, like `mlx4_write64`.
+    ///
+    /// Fails with `EINVAL` if `offset` is misaligned or out of the page.
+    fn write_be64(&self, offset: usize, hi: u32, lo: u32) -> Result {
+        let reg = self.reg(offset, 8)?;
+        let words = [hi.to_be(), lo.to_be()];
+        // SAFETY: `reg` was checked to be in the mapped page, and `words` holds one 64-bit word.
+        unsafe { bindings::__iowrite64_copy(reg, words.as_ptr().cast(), 1) };
+        Ok(())
+    }
+
+    /// Rings the send doorbell of the QP whose doorbell value is `qpn`, i.e. its number shifted
+    /// left by 8 bits.
+    ///
+    /// The descriptors posted before are made visible to the HCA first.
+    pub fn send_doorbell(&self, qpn: u32) {
+        // SAFETY: Orders the descriptors before the MMIO write below.
+        unsafe { bindings::wmb() };
+        // The offset is aligned and in the page.
+        let _ = self.write_be32(SEND_DOORBELL, qpn);
+    }
+
+    /// Rings the CQ doorbell with the command word `cmd`, holding the sequence number, command
+    /// and CQN, and the consumer index `ci`, like `mlx4_cq_arm`.
+    ///
+    /// The arm word of the CQ's doorbell record is made visible to the HCA first.
+    pub fn cq_doorbell(&self, cmd: u32, ci: u32) {
+        // SAFETY: Orders the doorbell record before the MMIO write below.
+        unsafe { bindings::wmb() };
+        // The offset is aligned and in the page.
+        let _ = self.write_be64(CQ_DOORBELL, cmd, ci & 0xff_ffff);
+    }
+
+    fn reg(&self, offset: usize, len: usize) -> Result<*mut c_void> {
+        if offset % len != 0 || offset + len > bindings::PAGE_SIZE as usize {
+            return Err(EINVAL);
+        }
+        // SAFETY: The offset was checked to be in the mapped page.
+        Ok(unsafe { self.map.cast::<u8>().add(offset).cast() })
+    }
+}
+
+impl Drop for Uar {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the page is mapped and the UAR allocated on the device,
+        // which is still valid.
+        unsafe {
+            bindings::iounmap(self.map);
+            bindings::mlx4_uar_free(self.dev, &mut self.uar);
+        }
+    }
+}
+
+// SAFETY: The UAR can be freed from any thread, and MMIO writes may be issued from any thread.
+unsafe impl Send for Uar {}
+// SAFETY: Shared references only write whole registers, which the HCA serialises.
+unsafe impl Sync for Uar {}
+
+/// A BlueFlame register allocated from mlx4_core, freed when dropped.
+///
+/// # Invariants
+///
+/// `bf` was allocated on `dev` by `mlx4_bf_alloc` and is not freed yet, and `dev` stays valid until
+/// the [`BlueFlame`] is dropped.
+pub struct BlueFlame {
+    dev: *mut bindings::mlx4_dev,
+    bf: bindings::mlx4_bf,
+}
+
+impl BlueFlame {
+    /// Allocates a BlueFlame register, with its bookkeeping on NUMA node `node`.
+    ///
+    /// Fails with `ENOMEM` if the device has no BlueFlame registers left, or none at all.
+    ///
+    /// # Safety
+    ///
+    /// The register must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`super::Mlx4Operation::add`] ensures this, as [`super::Mlx4Operation::remove`] drops the
+    /// context before mlx4_core removes the device.
+    pub unsafe fn try_new(dev: &Mlx4Device, node: i32) -> Result<Self> {
+        let mut bf = bindings::mlx4_bf::default();
+        // SAFETY: The device is valid and `bf` a valid out pointer.
+        let err = unsafe { bindings::mlx4_bf_alloc(dev.as_ptr(), &mut bf, node) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // INVARIANT: The register was just allocated, and the safety requirements keep the device
+        // valid until it is freed.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            bf,
+        })
+    }
+
+    /// Returns the index of the UAR of the register, to be written to the QP context.
+    pub fn uar_index(&self) -> u32 {
+        // SAFETY: By the type invariants the register, and so its UAR, is allocated.
+        unsafe { (*self.bf.uar).index as u32 }
+    }
+
+    /// Returns the size of each half of the register, the largest descriptor it takes.
+    pub fn buf_size(&self) -> usize {
+        self.bf.buf_size as usize
+    }
+
+    /// Copies the send descriptor `desc` to the register, ringing the doorbell of its QP.
+    ///
+    /// The descriptor is made visible to the HCA before the copy, and the copy is flushed out of
+    /// the write-combining buffer before the next half is used. Fails with `EINVAL` if `desc` is
+    /// empty, not a multiple of 64 bytes or larger than [`BlueFlame::buf_size`].
+    pub fn copy(&mut self, desc: &[u64]) -> Result {
+        let len = desc.len() * 8;
+        if len == 0 || len % 64 != 0 || len > self.buf_size() {
+            return Err(EINVAL);
+        }
+        // SAFETY: The half at `offset` holds `buf_size` bytes of the mapped register, and the
+        // barriers only order memory accesses.
+        unsafe {
+            bindings::wmb();
+            let dst = self.bf.reg.cast::<u8>().add(self.bf.offset as usize);
+            bindings::__iowrite64_copy(dst.cast(), desc.as_ptr().cast(), desc.len());
+            bindings::wmb();
+        }
+        self.bf.offset ^= self.bf.buf_size as u32;
+        Ok(())
+    }
+}
+
+impl Drop for BlueFlame {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the register was allocated on the device, which is still
+        // valid.
+        unsafe { bindings::mlx4_bf_free(self.dev, &mut self.bf) };
+    }
+}
+
+// SAFETY: The register can be freed from any thread.
+unsafe impl Send for BlueFlame {}
+// SAFETY: Shared references only read the fields; copies take a mutable reference.
+unsafe impl Sync for BlueFlame {}
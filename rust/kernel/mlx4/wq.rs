@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Workqueues of a registration.
+//!
+//! A [`super::Registration`] runs its deferred work on up to four workqueues: device and port
+//! events on `mlx4_ib`, QP events on `mlx4_ib_qp_event_wq`, the aging of the CM proxy IDs on
+//! `mlx4_ib_cm` and the multicast group timeouts of the slaves on `mlx4_ib_mcg`. A
+//! [`WorkQueueConfig`] sets the name and flags of each, and lets a driver that does not need a
+//! queue leave it out: the CM and multicast proxies only matter to the master of an SR-IOV
+//! device, so a driver that never runs as one need not allocate their queues.
+
+use core::ops::BitOr;
+
+use crate::bindings;
+use crate::c_str;
+use crate::error::Result;
+use crate::ib::compat::OwnedQueue;
+use crate::str::CStr;
+
+/// Flags of a workqueue, the `WQ_*` flags of `alloc_workqueue`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WqFlags(u32);
+
+impl WqFlags {
+    /// A per-CPU workqueue.
+    pub const NONE: Self = Self(0);
+    /// Work items are not bound to the CPU that queued them.
+    pub const UNBOUND: Self = Self(bindings::WQ_UNBOUND);
+    /// The workqueue has a rescuer thread, so that it makes progress under memory pressure.
+    pub const MEM_RECLAIM: Self = Self(bindings::WQ_MEM_RECLAIM);
+    /// Work items run on the high priority worker pools.
+    pub const HIGHPRI: Self = Self(bindings::WQ_HIGHPRI);
+
+    /// Returns `true` if every flag of `other` is in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns the raw `WQ_*` bits.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+}
+
+impl BitOr for WqFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Configuration of one workqueue.
+#[derive(Clone, Copy, Debug)]
+pub struct QueueConfig {
+    name: &'static CStr,
+    flags: WqFlags,
+    max_active: i32,
+    ordered: bool,
+    enabled: bool,
+}
+
+impl QueueConfig {
+    /// `__WQ_ORDERED | __WQ_ORDERED_EXPLICIT`, set by `alloc_ordered_workqueue`.
+    const ORDERED: u32 = bindings::__WQ_ORDERED | bindings::__WQ_ORDERED_EXPLICIT;
+
+    /// Creates the configuration of a workqueue named `name`, with the default number of work
+    /// items running at once.
+    pub const fn new(name: &'static CStr, flags: WqFlags) -> Self {
+        Self {
+            name,
+            flags,
+            max_active: 0,
+            ordered: false,
+            enabled: true,
+        }
+    }
+
+    /// Creates the configuration of an ordered workqueue named `name`, which runs one work item
+    /// at a time in queueing order, like `alloc_ordered_workqueue`.
+    pub const fn ordered(name: &'static CStr, flags: WqFlags) -> Self {
+        Self {
+            name,
+            flags: WqFlags(flags.0 | WqFlags::UNBOUND.0),
+            max_active: 1,
+            ordered: true,
+            enabled: true,
+        }
+    }
+
+    /// Names the workqueue `name` instead.
+    pub const fn with_name(mut self, name: &'static CStr) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Lets at most `max_active` work items run at once, 0 meaning the default.
+    ///
+    /// It is ignored for an ordered workqueue.
+    pub const fn with_max_active(mut self, max_active: i32) -> Self {
+        if !self.ordered {
+            self.max_active = max_active;
+        }
+        self
+    }
+
+    /// Leaves the workqueue out; the work it would run is not done.
+    pub const fn disabled(mut self) -> Self {
+        self.enabled = false;
+        self
+    }
+
+    /// Returns the name.
+    pub const fn name(&self) -> &'static CStr {
+        self.name
+    }
+
+    /// Returns the flags.
+    pub const fn flags(&self) -> WqFlags {
+        self.flags
+    }
+
+    /// Returns `true` if the workqueue is ordered.
+    pub const fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    /// Returns `true` unless the workqueue is left out.
+    pub const fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns the flags to allocate the workqueue with.
+    const fn raw_flags(&self) -> u32 {
+        if self.ordered {
+            self.flags.0 | Self::ORDERED
+        } else {
+            self.flags.0
+        }
+    }
+}
+
+/// Configuration of the workqueues of a [`super::Registration`].
+#[derive(Clone, Copy, Debug)]
+pub struct WorkQueueConfig {
+    events: QueueConfig,
+    qp_events: QueueConfig,
+    cm: QueueConfig,
+    mcg: QueueConfig,
+}
+
+impl WorkQueueConfig {
+    /// Creates the configuration of mlx4_ib: every queue is enabled, and all but `mlx4_ib_cm`
+    /// are ordered.
+    pub const fn new() -> Self {
+        Self {
+            events: QueueConfig::ordered(c_str!("mlx4_ib"), WqFlags::MEM_RECLAIM),
+            qp_events: QueueConfig::ordered(c_str!("mlx4_ib_qp_event_wq"), WqFlags::NONE),
+            cm: QueueConfig::new(c_str!("mlx4_ib_cm"), WqFlags::NONE),
+            mcg: QueueConfig::ordered(c_str!("mlx4_ib_mcg"), WqFlags::MEM_RECLAIM),
+        }
+    }
+
+    /// Creates the configuration of a driver that handles device and QP events only, without the
+    /// SR-IOV proxies.
+    pub const fn minimal() -> Self {
+        let config = Self::new();
+        Self {
+            cm: config.cm.disabled(),
+            mcg: config.mcg.disabled(),
+            ..config
+        }
+    }
+
+    /// Uses `config` for the queue of the device and port events.
+    ///
+    /// Without it, the events are dropped.
+    pub const fn with_events(mut self, config: QueueConfig) -> Self {
+        self.events = config;
+        self
+    }
+
+    /// Uses `config` for the queue of the QP events.
+    ///
+    /// Without it, the events are dropped.
+    pub const fn with_qp_events(mut self, config: QueueConfig) -> Self {
+        self.qp_events = config;
+        self
+    }
+
+    /// Uses `config` for the queue of the CM proxy.
+    ///
+    /// Without it, the IDs of the proxy are only dropped when their slave shuts down.
+    pub const fn with_cm(mut self, config: QueueConfig) -> Self {
+        self.cm = config;
+        self
+    }
+
+    /// Uses `config` for the queue of the multicast proxy.
+    ///
+    /// Without it, [`super::McgTable::request`] fails with `EOPNOTSUPP`.
+    pub const fn with_mcg(mut self, config: QueueConfig) -> Self {
+        self.mcg = config;
+        self
+    }
+
+    /// Returns the configuration of the queue of the device and port events.
+    pub const fn events(&self) -> &QueueConfig {
+        &self.events
+    }
+
+    /// Returns the configuration of the queue of the QP events.
+    pub const fn qp_events(&self) -> &QueueConfig {
+        &self.qp_events
+    }
+
+    /// Returns the configuration of the queue of the CM proxy.
+    pub const fn cm(&self) -> &QueueConfig {
+        &self.cm
+    }
+
+    /// Returns the configuration of the queue of the multicast proxy.
+    pub const fn mcg(&self) -> &QueueConfig {
+        &self.mcg
+    }
+}
+
+impl Default for WorkQueueConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A workqueue of the registration, allocated by `init` unless its configuration leaves it out.
+pub(crate) struct Mlx4WorkQueue {
+    wq: Option<OwnedQueue>,
+}
+
+impl Mlx4WorkQueue {
+    pub(crate) fn new() -> Self {
+        Self { wq: None }
+    }
+
+    pub(crate) fn init(&mut self, config: &QueueConfig) -> Result {
+        if !config.is_enabled() {
+            return Ok(());
+        }
+        self.wq = Some(OwnedQueue::try_new(
+            format_args!("{}", config.name()),
+            config.raw_flags(),
+            config.max_active,
+        )?);
+        Ok(())
+    }
+
+    pub(crate) fn queue(&self) -> Option<&OwnedQueue> {
+        self.wq.as_ref()
+    }
+
+    pub(crate) fn clean(&mut self) {
+        drop(self.wq.take());
+    }
+}
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Infiniband mlx5 devices.
+//!
+//! mlx5_core splits the RDMA function of a ConnectX-4 or later HCA out as an auxiliary device,
+//! `mlx5_core.rdma`, embedded in a `struct mlx5_adev` along with the core device. A provider binds
+//! to it with [`crate::auxiliary::Driver`] and reaches the core device with
+//! [`Mlx5CoreDev::from_adev`], from which it allocates the resources of its queues.
+
+use core::cell::UnsafeCell;
+
+use crate::auxiliary;
+use crate::bindings;
+
+pub mod db;
+
+pub use db::DoorbellRecord;
+
+/// An mlx5 HCA probed by mlx5_core, wraps `struct mlx5_core_dev`.
+#[repr(transparent)]
+pub struct Mlx5CoreDev(UnsafeCell<bindings::mlx5_core_dev>);
+
+impl Mlx5CoreDev {
+    /// Creates a reference to an [`Mlx5CoreDev`] from a valid pointer.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for the lifetime of the returned instance.
+    pub(crate) unsafe fn from_ptr<'a>(ptr: *mut bindings::mlx5_core_dev) -> &'a Self {
+        // SAFETY: The safety requirements guarantee the validity of the dereference, while the
+        // `Mlx5CoreDev` type being transparent makes the cast ok.
+        unsafe { &*ptr.cast() }
+    }
+
+    /// Returns the core device of the auxiliary device `adev`.
+    ///
+    /// # Safety
+    ///
+    /// `adev` must be an `mlx5_core` auxiliary device, e.g. one matched by `mlx5_core.rdma`.
+    pub unsafe fn from_adev(adev: &auxiliary::Device) -> &Self {
+        // SAFETY: mlx5_core embeds its auxiliary devices in the `adev` field of a
+        // `struct mlx5_adev`, whose core device outlives the auxiliary one.
+        unsafe {
+            let madev = adev.container::<bindings::mlx5_adev>(0);
+            Self::from_ptr(madev.mdev)
+        }
+    }
+
+    /// Returns the raw `struct mlx5_core_dev` pointer.
+    pub fn as_ptr(&self) -> *mut bindings::mlx5_core_dev {
+        self.0.get()
+    }
+}
+
+// SAFETY: `struct mlx5_core_dev` is owned by mlx5_core, which serialises the allocations made on
+// it.
+unsafe impl Send for Mlx5CoreDev {}
+// SAFETY: See the `Send` implementation.
+unsafe impl Sync for Mlx5CoreDev {}
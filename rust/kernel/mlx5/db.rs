@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Doorbell records.
+//!
+//! Like the mlx4 ones, see [`crate::mlx4::DoorbellRecord`], the work queues and CQs of an mlx5
+//! device publish their indices in doorbell records that the HCA reads by DMA. mlx5_core hands
+//! them out from per-node DMA pages with `mlx5_db_alloc`, one cache line each, of which drivers
+//! use the first two words: the receive and send counters of a QP, or the consumer index and arm
+//! words of a CQ. [`DoorbellRecord`] owns one of them and frees it when dropped.
+
+use core::ptr;
+
+use super::Mlx5CoreDev;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+
+/// Number of words of a record drivers use, `MLX5_RCV_DBR` and `MLX5_SND_DBR`.
+pub const WORDS: usize = 2;
+
+/// A doorbell record allocated by mlx5_core, freed when dropped.
+///
+/// # Invariants
+///
+/// `db` was allocated on `dev` by `mlx5_db_alloc` and is not freed yet, and `dev` stays valid until
+/// the [`DoorbellRecord`] is dropped.
+pub struct DoorbellRecord {
+    dev: *mut bindings::mlx5_core_dev,
+    db: bindings::mlx5_db,
+}
+
+impl DoorbellRecord {
+    /// Allocates a zeroed record on the NUMA node of `dev`.
+    ///
+    /// # Safety
+    ///
+    /// The record must be dropped before `dev` is removed. Keeping it in the context returned by
+    /// [`crate::auxiliary::Driver::probe`] ensures this, as [`crate::auxiliary::Driver::remove`]
+    /// drops the context before mlx5_core removes the auxiliary device, and the core device
+    /// outlives it.
+    pub unsafe fn try_new(dev: &Mlx5CoreDev) -> Result<Self> {
+        let mut db = bindings::mlx5_db::default();
+        // SAFETY: The device is valid and `db` a valid out pointer.
+        let err = unsafe { bindings::mlx5_db_alloc(dev.as_ptr(), &mut db) };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        // INVARIANT: The record was just allocated, and the safety requirements keep the device
+        // valid until it is freed.
+        Ok(Self {
+            dev: dev.as_ptr(),
+            db,
+        })
+    }
+
+    /// Returns the DMA address of the record, to be written to the queue's context.
+    pub fn dma(&self) -> u64 {
+        self.db.dma
+    }
+
+    /// Returns word `index` of the record, in host byte order.
+    ///
+    /// Fails with `EINVAL` if `index` is not below [`WORDS`].
+    pub fn read(&self, index: usize) -> Result<u32> {
+        let word = self.word(index)?;
+        // SAFETY: `word` is in the record, which the HCA only reads.
+        Ok(u32::from_be(unsafe { ptr::read_volatile(word) }))
+    }
+
+    /// Writes `value` to word `index` of the record, big-endian, with a volatile store.
+    ///
+    /// As with mlx4, callers order the store after the queue entries it publishes with a DMA
+    /// write barrier. Fails with `EINVAL` if `index` is not below [`WORDS`].
+    pub fn write(&mut self, index: usize, value: u32) -> Result {
+        let word = self.word(index)?;
+        // SAFETY: `word` is in the record, and the mutable borrow of `self` serialises the CPU
+        // writers.
+        unsafe { ptr::write_volatile(word, value.to_be()) };
+        Ok(())
+    }
+
+    fn word(&self, index: usize) -> Result<*mut u32> {
+        if index >= WORDS {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants `db` points to a cache line, which holds `WORDS` words.
+        Ok(unsafe { self.db.db.add(index).cast() })
+    }
+}
+
+impl Drop for DoorbellRecord {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the record was allocated on the device, which is still
+        // valid.
+        unsafe { bindings::mlx5_db_free(self.dev, &mut self.db) };
+    }
+}
+
+// SAFETY: The record can be freed from any thread; mlx5_core serialises the doorbell pages.
+unsafe impl Send for DoorbellRecord {}
+// SAFETY: Shared references only read the record.
+unsafe impl Sync for DoorbellRecord {}
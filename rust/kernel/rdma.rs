@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Protocol logic shared by the RDMA providers.
+//!
+//! Nothing in here touches `bindings`: the modules only depend on `core`, so the same sources are
+//! also built for userspace by `rust/rdma-host-tests` and unit tested with a plain `cargo test`.
+//! The exception is [`prelude`], which gathers the provider abstractions for driver authors.
+
+pub mod atomic;
+pub mod cm;
+pub mod cm_proxy;
+pub mod cq_mode;
+pub mod crc;
+pub mod dedup;
+pub mod dev_config;
+pub mod event_ring;
+pub mod fault;
+pub mod flap;
+pub mod fw_str;
+pub mod gid;
+pub mod hdr;
+pub mod icrc;
+pub mod id_alloc;
+pub mod init_once;
+pub mod ip_filter;
+pub mod link_params;
+pub mod mad;
+pub mod mcg;
+pub mod mr_cache;
+pub mod mr_key;
+pub mod mw;
+pub mod odp;
+pub mod opcode;
+pub mod page_map;
+pub mod page_size;
+pub mod pool;
+pub mod prelude;
+pub mod port_counters;
+pub mod psn;
+pub mod pv_mad;
+pub mod qp_fault;
+pub mod qp_state;
+pub mod qp_trace;
+pub mod queue;
+pub mod ring;
+pub mod sa;
+pub mod scrub;
+pub mod snapshot;
+pub mod task_state;
+pub mod timeout;
+pub mod timeout_map;
+pub mod tracker;
+pub mod tunables;
+pub mod violation;
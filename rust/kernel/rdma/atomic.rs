@@ -0,0 +1,244 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! 64-bit atomic operations on registered memory.
+//!
+//! These implement the responder side of the IBTA CMP&SWP and FETCH&ADD requests on top of
+//! `core::sync::atomic`, so no architecture specific assembly is needed and the same code runs
+//! against kernel MR mappings and against plain buffers in the userspace simulation.
+//!
+//! # Ordering
+//!
+//! Every operation is a single read-modify-write with `AcqRel` ordering: payload written by
+//! earlier packets on the same QP is visible before the atomic takes effect (release), and later
+//! RDMA READs or local accesses ordered after the ATOMIC ACK observe its result (acquire). This
+//! matches the guarantee the C driver gets from `cmpxchg()`/`atomic64_add_return()`.
+//!
+//! # Duplicates
+//!
+//! Atomics are not idempotent, so a retransmitted request must not run twice. The responder keeps
+//! the results of its last atomics in an [`AtomicReplay`], sized by the responder resources of the
+//! QP, and answers duplicates from there.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::hdr::{self, Packet, PacketMut, ParseError};
+use super::mw::ACCESS_REMOTE_ATOMIC;
+use super::opcode;
+use super::page_map::PageMap;
+use super::psn::Psn;
+
+/// Size in bytes of an atomic operand.
+pub const ATOMIC_OPERAND_SIZE: usize = 8;
+
+/// Reasons an atomic request cannot be executed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtomicError {
+    /// The target address is not 8-byte aligned.
+    ///
+    /// The responder reports this with an "invalid request" NAK.
+    Misaligned,
+    /// The target is outside the region or the region grants no remote atomic access.
+    ///
+    /// The responder reports this with a "remote access error" NAK.
+    Access,
+}
+
+impl AtomicError {
+    /// Returns the AETH syndrome of the NAK reporting the error.
+    pub const fn syndrome(self) -> u8 {
+        match self {
+            Self::Misaligned => hdr::AETH_NAK_INVALID_REQUEST,
+            Self::Access => hdr::AETH_NAK_REMOTE_ACCESS,
+        }
+    }
+}
+
+/// An atomic operation requested through an ATMETH header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AtomicOp {
+    /// Replace the target with `swap` if it equals `compare`.
+    CompareSwap {
+        /// Value the target is compared with.
+        compare: u64,
+        /// Value stored on a successful comparison.
+        swap: u64,
+    },
+    /// Add `add` to the target, wrapping on overflow.
+    FetchAdd {
+        /// Value added to the target.
+        add: u64,
+    },
+}
+
+/// Checks that `addr` is suitably aligned for an atomic operand.
+pub const fn check_alignment(addr: usize) -> Result<(), AtomicError> {
+    if addr & (ATOMIC_OPERAND_SIZE - 1) != 0 {
+        return Err(AtomicError::Misaligned);
+    }
+    Ok(())
+}
+
+impl AtomicOp {
+    /// Applies the operation to `target` and returns its original value, which is what the
+    /// ATOMIC ACK carries back to the requester.
+    pub fn apply(self, target: &AtomicU64) -> u64 {
+        match self {
+            AtomicOp::CompareSwap { compare, swap } => {
+                match target.compare_exchange(compare, swap, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(orig) | Err(orig) => orig,
+                }
+            }
+            AtomicOp::FetchAdd { add } => target.fetch_add(add, Ordering::AcqRel),
+        }
+    }
+
+    /// Applies the operation to the 8 bytes at `addr` and returns their original value.
+    ///
+    /// The operand is interpreted in host byte order; converting from and to the big-endian
+    /// wire format is up to the caller.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be valid for reads and writes of 8 bytes for the duration of the call, and the
+    /// memory must only be accessed atomically by other threads while the call is running.
+    pub unsafe fn execute(self, addr: *mut u8) -> Result<u64, AtomicError> {
+        check_alignment(addr as usize)?;
+        // SAFETY: `addr` is aligned and valid per the safety requirements, and `AtomicU64` has
+        // the same in-memory representation as `u64`.
+        let target = unsafe { &*(addr as *const AtomicU64) };
+        Ok(self.apply(target))
+    }
+}
+
+/// An atomic request received by the responder.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtomicRequest {
+    /// The operation.
+    pub op: AtomicOp,
+    /// Remote virtual address of the target.
+    pub va: u64,
+    /// Remote key of the region holding the target.
+    pub rkey: u32,
+}
+
+impl AtomicRequest {
+    /// Decodes the request carried by `pkt`.
+    ///
+    /// Returns `None` if `pkt` is not a CMP&SWP or FETCH&ADD request.
+    pub fn parse(pkt: &Packet<'_>) -> Option<Self> {
+        let eth = pkt.atmeth()?;
+        let op = match pkt.bth().opcode() {
+            opcode::RC_COMPARE_SWAP => AtomicOp::CompareSwap {
+                compare: eth.compare(),
+                swap: eth.swap_add(),
+            },
+            opcode::RC_FETCH_ADD => AtomicOp::FetchAdd {
+                add: eth.swap_add(),
+            },
+            _ => return None,
+        };
+        Some(Self {
+            op,
+            va: eth.va(),
+            rkey: eth.rkey(),
+        })
+    }
+
+    /// Checks the request against the region `map` registered with the `IB_ACCESS_*` flags
+    /// `access`, and returns the address of the target.
+    pub fn check(&self, map: &PageMap<'_>, access: u32) -> Result<u64, AtomicError> {
+        if access & ACCESS_REMOTE_ATOMIC == 0 || !map.contains(self.va, ATOMIC_OPERAND_SIZE as _) {
+            return Err(AtomicError::Access);
+        }
+        check_alignment(self.va as usize)?;
+        // Blocks are at least 8-byte aligned, so an aligned operand never straddles two.
+        map.translate(self.va).ok_or(AtomicError::Access)
+    }
+
+    /// Executes the request against the region `map` registered with `access`, and returns the
+    /// original value of the target for the ATOMIC ACK.
+    ///
+    /// # Safety
+    ///
+    /// The addresses of `map` must be CPU addresses, as with the virtual DMA of soft providers,
+    /// valid for the duration of the call and only accessed atomically meanwhile.
+    pub unsafe fn execute_in(&self, map: &PageMap<'_>, access: u32) -> Result<u64, AtomicError> {
+        let addr = self.check(map, access)?;
+        // SAFETY: The target lies inside `map`, whose addresses are valid CPU addresses per the
+        // safety requirements.
+        unsafe { self.op.execute(addr as usize as *mut u8) }
+    }
+}
+
+/// Lays out the ATOMIC ACK of the request `psn` at the start of `buf`.
+///
+/// The BTH carries `dest_qp` and `psn`, the AETH an ACK with `msn`, and the AtomicAckETH the
+/// original value `orig`. The caller fills in the P_Key and the ICRC.
+pub fn build_atomic_ack(
+    buf: &mut [u8],
+    dest_qp: u32,
+    psn: Psn,
+    msn: u32,
+    orig: u64,
+) -> Result<PacketMut<'_>, ParseError> {
+    let mut pkt = PacketMut::init(buf, opcode::RC_ATOMIC_ACKNOWLEDGE, 0)?;
+    let mut bth = pkt.bth();
+    bth.set_dest_qp(dest_qp);
+    bth.set_psn(psn);
+    if let Some(mut aeth) = pkt.aeth() {
+        aeth.set_syndrome(hdr::AETH_ACK_UNLIMITED);
+        aeth.set_msn(msn);
+    }
+    if let Some(mut atmack) = pkt.atmack() {
+        atmack.set_orig(orig);
+    }
+    Ok(pkt)
+}
+
+/// Results of the last `N` atomics executed by a responder, to answer duplicate requests.
+///
+/// Synchronisation is left to the owner, the responder of the QP.
+pub struct AtomicReplay<const N: usize> {
+    entries: [Option<(Psn, u64)>; N],
+    next: usize,
+}
+
+impl<const N: usize> AtomicReplay<N> {
+    /// Creates an empty cache.
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; N],
+            next: 0,
+        }
+    }
+
+    /// Remembers that the atomic `psn` returned `orig`, forgetting the oldest result if full.
+    pub fn remember(&mut self, psn: Psn, orig: u64) {
+        if N == 0 {
+            return;
+        }
+        self.entries[self.next] = Some((psn, orig));
+        self.next = (self.next + 1) % N;
+    }
+
+    /// Returns the result of the atomic `psn`, if it is still remembered.
+    pub fn lookup(&self, psn: Psn) -> Option<u64> {
+        self.entries
+            .iter()
+            .flatten()
+            .find(|(p, _)| *p == psn)
+            .map(|(_, orig)| *orig)
+    }
+
+    /// Forgets every result, e.g. when the QP is reset.
+    pub fn clear(&mut self) {
+        self.entries = [None; N];
+        self.next = 0;
+    }
+}
+
+impl<const N: usize> Default for AtomicReplay<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Communication management messages.
+//!
+//! The CM sets up RC and UC connections with a three-way handshake of MADs: the active side sends
+//! a REQ, the passive side answers with a REP, or a REJ, and the active side confirms with an RTU.
+//! Either side tears the connection down with a DREQ, which the other answers with a DREP. Every
+//! message has a private data area for the consumers, of a fixed size per message, and the events
+//! reporting a received message hand that area over without its length: [`CmMessage`] knows the
+//! sizes. The timeouts carried by REQs and REPs are exponents, converted by [`cm_timeout`].
+//!
+//! On the wire, the messages are told apart by their [`attr`] ID. Every message starts with the
+//! communication IDs both sides picked for the connection, which proxies rewrite with
+//! [`set_local_comm_id`] and [`set_remote_comm_id`].
+
+use super::gid::Gid;
+use super::mad::{class, MadHdr, MAD_HDR_SIZE, MAD_SIZE};
+
+/// CM messages that carry private data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CmMessage {
+    /// Connection request.
+    Req,
+    /// Message receipt acknowledgement, asking the peer for more time.
+    Mra,
+    /// Connection reject.
+    Rej,
+    /// Connection reply.
+    Rep,
+    /// Ready to use.
+    Rtu,
+    /// Disconnection request.
+    Dreq,
+    /// Disconnection reply.
+    Drep,
+    /// Service ID resolution request.
+    SidrReq,
+    /// Service ID resolution reply.
+    SidrRep,
+}
+
+impl CmMessage {
+    /// Returns the size of the private data area of the message, `IB_CM_*_PRIVATE_DATA_SIZE`.
+    pub const fn private_data_size(self) -> usize {
+        match self {
+            CmMessage::Req => 92,
+            CmMessage::Mra => 222,
+            CmMessage::Rej => 148,
+            CmMessage::Rep => 196,
+            CmMessage::Rtu => 224,
+            CmMessage::Dreq => 220,
+            CmMessage::Drep => 224,
+            CmMessage::SidrReq => 216,
+            CmMessage::SidrRep => 136,
+        }
+    }
+
+    /// Returns the length of `data` as the `private_data_len` of the message, or an error if it
+    /// does not fit in its private data area.
+    pub fn check_private_data(self, data: &[u8]) -> Result<u8, PrivateDataTooLong> {
+        if data.len() > self.private_data_size() {
+            return Err(PrivateDataTooLong {
+                message: self,
+                len: data.len(),
+            });
+        }
+        Ok(data.len() as u8)
+    }
+}
+
+/// Private data larger than the area of its message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrivateDataTooLong {
+    /// Message the data was meant for.
+    pub message: CmMessage,
+    /// Length of the data.
+    pub len: usize,
+}
+
+/// Why a connection was rejected, `enum ib_cm_rej_reason`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RejReason {
+    /// No QP available.
+    NoQp,
+    /// No resources available.
+    NoResources,
+    /// The peer did not answer in time.
+    Timeout,
+    /// The request is not supported.
+    Unsupported,
+    /// No listener for the service ID.
+    InvalidServiceId,
+    /// The QP is still in use by a previous connection.
+    StaleConn,
+    /// The path MTU is not supported.
+    InvalidMtu,
+    /// Not enough responder resources.
+    InsufficientRespResources,
+    /// Rejected by the consumer, with its own reason in the private data.
+    ConsumerDefined,
+    /// Any other reason, by code.
+    Other(u16),
+}
+
+impl RejReason {
+    /// Returns the reason of the code of a REJ.
+    pub const fn from_code(code: u16) -> Self {
+        match code {
+            1 => RejReason::NoQp,
+            3 => RejReason::NoResources,
+            4 => RejReason::Timeout,
+            5 => RejReason::Unsupported,
+            8 => RejReason::InvalidServiceId,
+            10 => RejReason::StaleConn,
+            26 => RejReason::InvalidMtu,
+            27 => RejReason::InsufficientRespResources,
+            28 => RejReason::ConsumerDefined,
+            code => RejReason::Other(code),
+        }
+    }
+
+    /// Returns the code of the reason as sent in a REJ.
+    pub const fn code(self) -> u16 {
+        match self {
+            RejReason::NoQp => 1,
+            RejReason::NoResources => 3,
+            RejReason::Timeout => 4,
+            RejReason::Unsupported => 5,
+            RejReason::InvalidServiceId => 8,
+            RejReason::StaleConn => 10,
+            RejReason::InvalidMtu => 26,
+            RejReason::InsufficientRespResources => 27,
+            RejReason::ConsumerDefined => 28,
+            RejReason::Other(code) => code,
+        }
+    }
+}
+
+/// Largest CM timeout exponent.
+pub const MAX_CM_TIMEOUT: u8 = 31;
+
+/// Returns the duration of the CM timeout exponent `exp`, 4.096 µs * 2^`exp`, in nanoseconds.
+pub const fn cm_timeout_ns(exp: u8) -> u64 {
+    let exp = if exp > MAX_CM_TIMEOUT {
+        MAX_CM_TIMEOUT
+    } else {
+        exp
+    };
+    4096 << exp
+}
+
+/// Returns the smallest CM timeout exponent lasting at least `ms` milliseconds, capped at
+/// [`MAX_CM_TIMEOUT`].
+pub const fn cm_timeout(ms: u32) -> u8 {
+    let ns = ms as u64 * 1_000_000;
+    let mut exp = 0;
+    while exp < MAX_CM_TIMEOUT && cm_timeout_ns(exp) < ns {
+        exp += 1;
+    }
+    exp
+}
+
+/// Attribute IDs of the CM messages, `CM_*_ATTR_ID`.
+pub mod attr {
+    /// Connection request.
+    pub const REQ: u16 = 0x10;
+    /// Message receipt acknowledgement.
+    pub const MRA: u16 = 0x11;
+    /// Connection reject.
+    pub const REJ: u16 = 0x12;
+    /// Connection reply.
+    pub const REP: u16 = 0x13;
+    /// Ready to use.
+    pub const RTU: u16 = 0x14;
+    /// Disconnection request.
+    pub const DREQ: u16 = 0x15;
+    /// Disconnection reply.
+    pub const DREP: u16 = 0x16;
+    /// Service ID resolution request.
+    pub const SIDR_REQ: u16 = 0x17;
+    /// Service ID resolution reply.
+    pub const SIDR_REP: u16 = 0x18;
+    /// Load alternate path.
+    pub const LAP: u16 = 0x19;
+    /// Alternate path response.
+    pub const APR: u16 = 0x1a;
+}
+
+/// Offset of the local communication ID in a CM MAD, and of the request ID in a SIDR message.
+const LOCAL_COMM_ID: usize = MAD_HDR_SIZE;
+
+/// Offset of the remote communication ID in a CM MAD.
+const REMOTE_COMM_ID: usize = MAD_HDR_SIZE + 4;
+
+/// Offset of the reason in a REJ.
+const REJ_REASON: usize = MAD_HDR_SIZE + 10;
+
+/// Offset of the primary remote port GID in a REQ.
+const REQ_REMOTE_GID: usize = MAD_HDR_SIZE + 72;
+
+/// Returns the attribute ID of `mad`, `None` if it is not a whole CM MAD.
+pub fn cm_attr(mad: &[u8]) -> Option<u16> {
+    let hdr = MadHdr::parse(mad)?;
+    if hdr.mgmt_class != class::CM || mad.len() < MAD_SIZE {
+        return None;
+    }
+    Some(hdr.attr_id)
+}
+
+/// Returns the offset of the local or remote communication ID of the CM MAD `mad`.
+fn comm_id_offset(mad: &[u8], local: bool) -> Option<usize> {
+    match (cm_attr(mad)?, local) {
+        (attr::SIDR_REQ, true) | (attr::SIDR_REP, false) => Some(LOCAL_COMM_ID),
+        (attr::SIDR_REQ, false) | (attr::SIDR_REP, true) | (attr::REQ, false) => None,
+        (attr::REQ..=attr::APR, true) => Some(LOCAL_COMM_ID),
+        (attr::REQ..=attr::APR, false) => Some(REMOTE_COMM_ID),
+        _ => None,
+    }
+}
+
+fn read_be32(mad: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&mad[offset..offset + 4]);
+    u32::from_be_bytes(bytes)
+}
+
+/// Returns the communication ID the sender of the CM MAD `mad` picked, the request ID of a
+/// SIDR_REQ.
+///
+/// `None` if `mad` is not a CM MAD or its message does not carry the sender's ID, like a SIDR_REP.
+pub fn local_comm_id(mad: &[u8]) -> Option<u32> {
+    Some(read_be32(mad, comm_id_offset(mad, true)?))
+}
+
+/// Writes the communication ID of the sender of the CM MAD `mad`, see [`local_comm_id`].
+///
+/// Returns `false` if the message has no such ID.
+pub fn set_local_comm_id(mad: &mut [u8], id: u32) -> bool {
+    match comm_id_offset(mad, true) {
+        Some(offset) => {
+            mad[offset..offset + 4].copy_from_slice(&id.to_be_bytes());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the communication ID the receiver of the CM MAD `mad` picked, the request ID of a
+/// SIDR_REP.
+///
+/// `None` if `mad` is not a CM MAD or its message does not carry the receiver's ID, like a REQ or
+/// a SIDR_REQ.
+pub fn remote_comm_id(mad: &[u8]) -> Option<u32> {
+    Some(read_be32(mad, comm_id_offset(mad, false)?))
+}
+
+/// Writes the communication ID of the receiver of the CM MAD `mad`, see [`remote_comm_id`].
+///
+/// Returns `false` if the message has no such ID.
+pub fn set_remote_comm_id(mad: &mut [u8], id: u32) -> bool {
+    match comm_id_offset(mad, false) {
+        Some(offset) => {
+            mad[offset..offset + 4].copy_from_slice(&id.to_be_bytes());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the reason of the REJ `mad`, `None` for the other MADs.
+pub fn rej_reason(mad: &[u8]) -> Option<RejReason> {
+    if cm_attr(mad)? != attr::REJ {
+        return None;
+    }
+    Some(RejReason::from_code(u16::from_be_bytes([
+        mad[REJ_REASON],
+        mad[REJ_REASON + 1],
+    ])))
+}
+
+/// Returns the primary remote port GID of the REQ `mad`, i.e. the GID of the port it is sent to,
+/// `None` for the other MADs.
+pub fn req_remote_gid(mad: &[u8]) -> Option<Gid> {
+    if cm_attr(mad)? != attr::REQ {
+        return None;
+    }
+    let mut raw = [0; 16];
+    raw.copy_from_slice(&mad[REQ_REMOTE_GID..REQ_REMOTE_GID + 16]);
+    Some(Gid::from_raw(raw))
+}
@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Paravirtual CM IDs.
+//!
+//! A master forwarding the CM MADs of its slaves, like mlx4 under SR-IOV, cannot put their
+//! communication IDs on the wire as they are: the slaves pick them independently, so two of them
+//! may use the same ID towards one peer. [`PvCmIds`] gives each (slave, slave ID) pair a
+//! paravirtual ID that is unique on the device. [`PvCmIds::multiplex`] puts it in place of the
+//! slave's ID in the MADs a slave sends, and [`PvCmIds::demux`] finds the slave a received MAD is
+//! for and restores its ID, like the C driver's `mlx4_ib_multiplex_cm_handler` and
+//! `mlx4_ib_demux_cm_handler`.
+//!
+//! The ID of a connection that is torn down or rejected is kept a while for its late MADs: it is
+//! scheduled to expire at the deadline passed along, which the owner enforces with
+//! [`PvCmIds::expire`]. Synchronisation is left to the owner.
+
+use super::cm::{self, attr, RejReason};
+use super::gid::Gid;
+use super::timeout_map::TimeoutMap;
+
+/// Why a CM MAD could not be forwarded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProxyError {
+    /// The MAD is not a whole CM MAD.
+    NotCm,
+    /// The MAD refers to a connection that has no paravirtual ID.
+    UnknownId,
+    /// Every paravirtual ID is in use.
+    Full,
+    /// No slave owns the port GID the request is sent to.
+    NoSlave,
+}
+
+/// The paravirtual IDs of at most `N` connections of slaves `S`.
+pub struct PvCmIds<S, const N: usize> {
+    map: TimeoutMap<(S, u32), u32, N>,
+    next: u32,
+}
+
+impl<S: Copy + PartialEq, const N: usize> PvCmIds<S, N> {
+    /// Creates an empty map.
+    pub const fn new() -> Self {
+        Self {
+            map: TimeoutMap::new(),
+            next: 1,
+        }
+    }
+
+    /// Returns the number of connections with a paravirtual ID.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if no connection has a paravirtual ID.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Returns the paravirtual ID of the connection `cm_id` of `slave`, if it has one.
+    pub fn get(&self, slave: S, cm_id: u32) -> Option<u32> {
+        self.map.get(&(slave, cm_id)).copied()
+    }
+
+    /// Returns the paravirtual ID of the connection `cm_id` of `slave`, allocating one for a new
+    /// connection.
+    ///
+    /// Fails with [`ProxyError::Full`] if `N` connections have one already.
+    pub fn get_or_alloc(&mut self, slave: S, cm_id: u32) -> Result<u32, ProxyError> {
+        if let Some(pv) = self.get(slave, cm_id) {
+            return Ok(pv);
+        }
+        if self.map.len() == self.map.capacity() {
+            return Err(ProxyError::Full);
+        }
+        // Cyclic allocation, so that a recycled ID is not mistaken for a recent one. Zero is
+        // never used, as it stands for no ID in the CM messages.
+        let mut pv = self.next;
+        while pv == 0 || self.lookup(pv).is_some() {
+            pv = pv.wrapping_add(1);
+        }
+        self.next = pv.wrapping_add(1);
+        self.map
+            .insert((slave, cm_id), pv)
+            .map_err(|_| ProxyError::Full)?;
+        Ok(pv)
+    }
+
+    /// Returns the slave and slave ID behind the paravirtual ID `pv`.
+    pub fn lookup(&self, pv: u32) -> Option<(S, u32)> {
+        self.map.find(|_, v| *v == pv).map(|(k, _)| *k)
+    }
+
+    /// Schedules the paravirtual ID of the connection `cm_id` of `slave` to expire at `deadline`.
+    ///
+    /// An ID already scheduled keeps its deadline. Returns `false` if the connection has no ID.
+    pub fn schedule(&mut self, slave: S, cm_id: u32, deadline: u64) -> bool {
+        self.map.schedule(&(slave, cm_id), deadline)
+    }
+
+    /// Drops every ID of `slave` right away, e.g. when the slave is shut down.
+    pub fn remove_slave(&mut self, slave: S) {
+        self.map.retain(|k, _| k.0 != slave);
+    }
+
+    /// Drops every ID.
+    pub fn clear(&mut self) {
+        self.map.retain(|_, _| false);
+    }
+
+    /// Drops the IDs whose deadline is reached at `now`, and returns their number.
+    pub fn expire(&mut self, now: u64) -> usize {
+        self.map.expire(now, |_, _| {})
+    }
+
+    /// Returns the earliest deadline of the IDs scheduled to expire, as seen from `now`.
+    pub fn next_deadline(&self, now: u64) -> Option<u64> {
+        self.map.next_deadline(now)
+    }
+
+    /// Rewrites the CM MAD `mad` sent by `slave`, putting the paravirtual ID of its connection in
+    /// place of the slave's ID.
+    ///
+    /// Requests, replies, MRAs and timeout REJs may start a connection and get an ID allocated;
+    /// the other messages must refer to a connection that has one. The other REJs and the
+    /// SIDR_REPs carry no ID of the slave and are left as they are. The ID of a connection the
+    /// slave disconnects with a DREQ expires at `deadline`.
+    pub fn multiplex(&mut self, slave: S, mad: &mut [u8], deadline: u64) -> Result<(), ProxyError> {
+        let attr_id = cm::cm_attr(mad).ok_or(ProxyError::NotCm)?;
+        let new_conn = matches!(
+            attr_id,
+            attr::REQ | attr::REP | attr::MRA | attr::SIDR_REQ
+        ) || cm::rej_reason(mad) == Some(RejReason::Timeout);
+        if !new_conn && matches!(attr_id, attr::REJ | attr::SIDR_REP) {
+            return Ok(());
+        }
+        let cm_id = cm::local_comm_id(mad).ok_or(ProxyError::NotCm)?;
+        let pv = if new_conn {
+            self.get_or_alloc(slave, cm_id)?
+        } else {
+            self.get(slave, cm_id).ok_or(ProxyError::UnknownId)?
+        };
+        cm::set_local_comm_id(mad, pv);
+        if attr_id == attr::DREQ {
+            self.schedule(slave, cm_id, deadline);
+        }
+        Ok(())
+    }
+
+    /// Finds the slave the received CM MAD `mad` is for, and puts the slave's ID of the
+    /// connection in place of the paravirtual one.
+    ///
+    /// A REQ goes to the slave `slave_of` returns for the port GID it is sent to, and is left as it
+    /// is. A SIDR_REQ has no such GID and fails with [`ProxyError::NoSlave`], which leaves it to
+    /// the master. The other messages go to the slave of the connection whose paravirtual ID they
+    /// carry; the ID of a connection the peer disconnects or rejects expires at `deadline`.
+    pub fn demux(
+        &mut self,
+        mad: &mut [u8],
+        deadline: u64,
+        slave_of: impl FnOnce(Gid) -> Option<S>,
+    ) -> Result<S, ProxyError> {
+        let attr_id = cm::cm_attr(mad).ok_or(ProxyError::NotCm)?;
+        match attr_id {
+            attr::REQ => {
+                return cm::req_remote_gid(mad)
+                    .and_then(slave_of)
+                    .ok_or(ProxyError::NoSlave)
+            }
+            attr::SIDR_REQ => return Err(ProxyError::NoSlave),
+            _ => {}
+        }
+        let pv = cm::remote_comm_id(mad).ok_or(ProxyError::NotCm)?;
+        let (slave, cm_id) = self.lookup(pv).ok_or(ProxyError::UnknownId)?;
+        cm::set_remote_comm_id(mad, cm_id);
+        if matches!(attr_id, attr::DREQ | attr::REJ) {
+            self.schedule(slave, cm_id, deadline);
+        }
+        Ok(slave)
+    }
+}
+
+impl<S: Copy + PartialEq, const N: usize> Default for PvCmIds<S, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
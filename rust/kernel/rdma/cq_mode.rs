@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Event and polling modes of a completion queue.
+//!
+//! In event mode a consumer arms the CQ and is called back on the next completion, which costs an
+//! interrupt-like callback per batch. Benchmarks of the soft provider against the C rxe driver want
+//! the other extreme as well: notifications turned off and the consumer busy-polling with a fixed
+//! budget per call. [`CqModeState`] holds that switch; it can be flipped at runtime, from any
+//! context.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+/// How a consumer learns about completions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CqMode {
+    /// The consumer arms the CQ and its completion handler is called.
+    Event,
+    /// Notifications are off; the consumer polls.
+    Poll,
+}
+
+/// Mode and poll budget of a completion queue.
+pub struct CqModeState {
+    poll: AtomicBool,
+    budget: AtomicU32,
+    suppressed: AtomicU64,
+}
+
+impl CqModeState {
+    /// Creates the state of a CQ in event mode, with an unlimited poll budget.
+    pub const fn new() -> Self {
+        Self {
+            poll: AtomicBool::new(false),
+            budget: AtomicU32::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the current mode.
+    pub fn mode(&self) -> CqMode {
+        if self.poll.load(Ordering::Acquire) {
+            CqMode::Poll
+        } else {
+            CqMode::Event
+        }
+    }
+
+    /// Switches to `mode`.
+    pub fn set_mode(&self, mode: CqMode) {
+        self.poll.store(mode == CqMode::Poll, Ordering::Release);
+    }
+
+    /// Returns the largest number of completions returned by one poll, 0 meaning unlimited.
+    pub fn budget(&self) -> u32 {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    /// Sets the poll budget, 0 meaning unlimited.
+    pub fn set_budget(&self, budget: u32) {
+        self.budget.store(budget, Ordering::Relaxed);
+    }
+
+    /// Returns how many of `requested` completions one poll may return.
+    pub fn entries(&self, requested: usize) -> usize {
+        match self.budget() {
+            0 => requested,
+            budget => requested.min(budget as usize),
+        }
+    }
+
+    /// Returns `true` and counts the request if notifications are off.
+    pub fn suppress(&self) -> bool {
+        let poll = self.mode() == CqMode::Poll;
+        if poll {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+        }
+        poll
+    }
+
+    /// Returns the number of notification requests ignored in poll mode.
+    pub fn suppressed(&self) -> u64 {
+        self.suppressed.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for CqModeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Software CRC32 as used by the RoCE invariant CRC.
+//!
+//! This is the bit-reflected IEEE 802.3 polynomial with the same calling convention as the
+//! kernel's `crc32_le()`: the caller provides the seed and is responsible for any final
+//! inversion.
+
+/// Reflected IEEE 802.3 CRC32 polynomial.
+pub const CRC32_POLY_LE: u32 = 0xedb8_8320;
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLY_LE
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = build_table();
+
+/// Updates `seed` with `data`, the equivalent of the kernel's `crc32_le(seed, data, len)`.
+pub fn crc32_le(seed: u32, data: &[u8]) -> u32 {
+    data.iter().fold(seed, |crc, &byte| {
+        CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
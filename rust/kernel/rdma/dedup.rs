@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Detection of packets delivered twice.
+//!
+//! With sockets on several ports, or behind some bonding and port mirroring setups, the same
+//! packet can reach the receive path twice within microseconds. The transport would treat the
+//! second copy as a duplicate request or a stale response, which costs an ACK or a NAK and shows
+//! up in the retry counters. [`DupFilter`] remembers the packets seen in the last few ticks and
+//! flags a copy of one of them, so that it is dropped before the transport sees it.
+//!
+//! A retransmission looks exactly like the original packet, but it is only sent after the
+//! retransmission timeout, which is much longer than the window of the filter.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use super::hdr::{Packet, ICRC_SIZE};
+use super::psn::Psn;
+
+/// What identifies a packet: its destination, its sequence number and its ICRC, which covers the
+/// whole packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DupKey {
+    /// Destination QP number.
+    pub qpn: u32,
+    /// Packet sequence number.
+    pub psn: Psn,
+    /// Invariant CRC of the packet.
+    pub icrc: [u8; ICRC_SIZE],
+}
+
+impl DupKey {
+    /// Returns the key of a received packet.
+    pub fn of(pkt: &Packet<'_>) -> Self {
+        let bth = pkt.bth();
+        Self {
+            qpn: bth.dest_qp(),
+            psn: bth.psn(),
+            icrc: pkt.icrc(),
+        }
+    }
+
+    /// Returns a non-zero 32-bit digest of the key.
+    fn fingerprint(&self) -> u32 {
+        let h = (self.qpn as u64) << 24 ^ self.psn.value() as u64;
+        let h = h ^ (u32::from_ne_bytes(self.icrc) as u64) << 13;
+        // Multiplicative hashing spreads the bits the slot index is taken from.
+        let h = (h.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) as u32;
+        h.max(1)
+    }
+}
+
+/// Counters of a [`DupFilter`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DupStats {
+    /// Packets checked.
+    pub checked: u64,
+    /// Packets found to be copies of one seen within the window.
+    pub duplicates: u64,
+}
+
+/// Recently seen packets, in `N` slots.
+///
+/// Each slot holds the fingerprint of a packet and the tick it was seen at, packed in one atomic
+/// word, so [`DupFilter::check`] runs lock-free on every CPU receiving packets. Two packets that
+/// share a slot and a fingerprint within the window are taken for copies of each other; with 32-bit
+/// fingerprints this is rare enough to be dwarfed by packet loss.
+pub struct DupFilter<const N: usize> {
+    slots: [AtomicU64; N],
+    window: AtomicU32,
+    checked: AtomicU64,
+    duplicates: AtomicU64,
+}
+
+impl<const N: usize> DupFilter<N> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicU64 = AtomicU64::new(0);
+
+    /// Creates an empty filter flagging copies seen at most `window` ticks apart.
+    pub const fn new(window: u32) -> Self {
+        Self {
+            slots: [Self::EMPTY; N],
+            window: AtomicU32::new(window),
+            checked: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the window, in ticks.
+    pub fn window(&self) -> u32 {
+        self.window.load(Ordering::Relaxed)
+    }
+
+    /// Sets the window, in ticks.
+    pub fn set_window(&self, window: u32) {
+        self.window.store(window, Ordering::Relaxed);
+    }
+
+    /// Records the packet `key` seen at tick `now` and returns `true` if it is a copy of a packet
+    /// seen within the window.
+    pub fn check(&self, key: &DupKey, now: u32) -> bool {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        if N == 0 {
+            return false;
+        }
+        let fingerprint = key.fingerprint();
+        let slot = &self.slots[fingerprint as usize % N];
+        let entry = (fingerprint as u64) << 32 | now as u64;
+        let mut old = slot.load(Ordering::Relaxed);
+        loop {
+            if old != 0
+                && (old >> 32) as u32 == fingerprint
+                && now.wrapping_sub(old as u32) <= self.window()
+            {
+                self.duplicates.fetch_add(1, Ordering::Relaxed);
+                return true;
+            }
+            // A copy racing on another CPU either sees this entry or makes the exchange fail.
+            match slot.compare_exchange_weak(old, entry, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return false,
+                Err(current) => old = current,
+            }
+        }
+    }
+
+    /// Returns the counters.
+    pub fn stats(&self) -> DupStats {
+        DupStats {
+            checked: self.checked.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Forgets every packet seen so far; the counters are kept.
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.store(0, Ordering::Relaxed);
+        }
+    }
+}
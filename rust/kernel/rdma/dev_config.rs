@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Per-device settings read on the data path.
+//!
+//! Unlike the [`super::tunables`], which only shape resources when they are created, these
+//! settings are looked at for every packet: the MTU override, how the ICRC is handled and the
+//! pacing of the send path. The packet path must see them consistently, e.g. not the new CRC mode
+//! with the old MTU, and must not take a lock for it. A [`DeviceConfig`] is therefore never
+//! modified in place: writers build a modified copy with [`DeviceConfig::with`] and publish it as
+//! a whole, the kernel side swapping it in under RCU.
+
+use core::fmt;
+
+use super::tunables::TuneError;
+
+/// How the ICRC of packets is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrcMode {
+    /// Generated on transmit and checked on receive, the default.
+    Check,
+    /// Generated on transmit, not checked on receive, e.g. behind a NIC that already did.
+    GenerateOnly,
+    /// Neither generated nor checked; only for benchmarks between peers doing the same.
+    Off,
+}
+
+impl CrcMode {
+    /// Every mode, in the order they are listed by `show`.
+    pub const ALL: [Self; 3] = [Self::Check, Self::GenerateOnly, Self::Off];
+
+    /// Returns the name of the mode.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Check => "check",
+            Self::GenerateOnly => "generate",
+            Self::Off => "off",
+        }
+    }
+
+    /// Looks a mode up by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name() == name)
+    }
+
+    /// Returns `true` if the ICRC of received packets is checked.
+    pub const fn checks(self) -> bool {
+        matches!(self, Self::Check)
+    }
+
+    /// Returns `true` if an ICRC is computed for sent packets.
+    pub const fn generates(self) -> bool {
+        !matches!(self, Self::Off)
+    }
+}
+
+/// A setting of [`DeviceConfig`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigKey {
+    /// [`DeviceConfig::mtu_override`].
+    Mtu,
+    /// [`DeviceConfig::crc_mode`].
+    CrcMode,
+    /// [`DeviceConfig::pacing_rate`].
+    PacingRate,
+}
+
+impl ConfigKey {
+    /// Every setting, in the order of their sysfs attributes.
+    pub const ALL: [Self; 3] = [Self::Mtu, Self::CrcMode, Self::PacingRate];
+
+    /// Returns the name of the sysfs attribute.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Mtu => "mtu_override",
+            Self::CrcMode => "crc_mode",
+            Self::PacingRate => "tx_pacing_kbps",
+        }
+    }
+
+    /// Looks a setting up by attribute name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+}
+
+/// Settings of a device read on the data path.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceConfig {
+    /// Path MTU in bytes used instead of the one derived from the net device, 0 for none.
+    pub mtu_override: u32,
+    /// How the ICRC is handled.
+    pub crc_mode: CrcMode,
+    /// Send rate of the device in kbit/s, 0 meaning unlimited.
+    pub pacing_rate: u32,
+}
+
+impl DeviceConfig {
+    /// The settings of a new device: no override, ICRC checked, no pacing.
+    pub const DEFAULT: Self = Self {
+        mtu_override: 0,
+        crc_mode: CrcMode::Check,
+        pacing_rate: 0,
+    };
+
+    /// Returns `true` if `mtu` is a valid override: 0, or an IB MTU from 256 to 4096 bytes.
+    pub const fn is_valid_mtu(mtu: u32) -> bool {
+        mtu == 0 || (mtu >= 256 && mtu <= 4096 && mtu.is_power_of_two())
+    }
+
+    /// Returns the path MTU to use on a net device with MTU `netdev_mtu`, already converted to an
+    /// IB MTU.
+    pub const fn path_mtu(&self, netdev_mtu: u32) -> u32 {
+        if self.mtu_override != 0 && self.mtu_override < netdev_mtu {
+            self.mtu_override
+        } else {
+            netdev_mtu
+        }
+    }
+
+    /// Returns a copy with `key` set to the value parsed from `text`, e.g. written to a sysfs
+    /// attribute.
+    pub fn with(&self, key: ConfigKey, text: &str) -> Result<Self, TuneError> {
+        let text = text.trim();
+        let mut config = *self;
+        match key {
+            ConfigKey::Mtu => {
+                let mtu = text.parse().map_err(|_| TuneError::Parse)?;
+                if !Self::is_valid_mtu(mtu) {
+                    return Err(TuneError::OutOfRange);
+                }
+                config.mtu_override = mtu;
+            }
+            ConfigKey::CrcMode => {
+                config.crc_mode = CrcMode::from_name(text).ok_or(TuneError::Parse)?;
+            }
+            ConfigKey::PacingRate => {
+                config.pacing_rate = text.parse().map_err(|_| TuneError::Parse)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Writes the value of `key`, e.g. for a sysfs `show` callback.
+    ///
+    /// The CRC mode is shown like a sysfs choice, every mode with the current one in brackets.
+    pub fn show(&self, key: ConfigKey, w: &mut dyn fmt::Write) -> fmt::Result {
+        match key {
+            ConfigKey::Mtu => writeln!(w, "{}", self.mtu_override),
+            ConfigKey::PacingRate => writeln!(w, "{}", self.pacing_rate),
+            ConfigKey::CrcMode => {
+                for (i, mode) in CrcMode::ALL.into_iter().enumerate() {
+                    let sep = if i == 0 { "" } else { " " };
+                    if mode == self.crc_mode {
+                        write!(w, "{}[{}]", sep, mode.name())?;
+                    } else {
+                        write!(w, "{}{}", sep, mode.name())?;
+                    }
+                }
+                writeln!(w)
+            }
+        }
+    }
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
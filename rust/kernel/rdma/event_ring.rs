@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Lock-free ring for handing events from interrupt context to a work item.
+//!
+//! Hardware providers learn about asynchronous events in their interrupt handlers, where they can
+//! neither sleep nor allocate, but handle them from a workqueue. [`EventRing`] is a bounded queue
+//! with inline storage that any number of CPUs may push to and pop from concurrently without a
+//! lock: each slot carries a sequence number telling whether it is free for the current lap of
+//! producers or filled for the current lap of consumers. A full ring rejects the event and counts
+//! it as dropped instead of blocking the interrupt handler.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+struct Slot<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// A bounded multi-producer multi-consumer queue of at most `N` events.
+///
+/// `N` must be a non-zero power of two, which is checked at compile time.
+///
+/// # Invariants
+///
+/// Sequences are stored relative to the slot index, so that an empty ring is all zeroes: with
+/// `lap = pos - pos % N`, the slot for position `pos` is free for a producer when its sequence is
+/// `lap`, and holds an initialised value for a consumer when its sequence is `lap + 1`.
+pub struct EventRing<T: Copy, const N: usize> {
+    slots: [Slot<T>; N],
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+// SAFETY: Values are only moved in and out of slots by the thread that claimed the slot's position,
+// as arbitrated by the sequence numbers, so sharing the ring only shares `T` values by copy.
+unsafe impl<T: Copy + Send, const N: usize> Sync for EventRing<T, N> {}
+
+impl<T> Slot<T> {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        seq: AtomicUsize::new(0),
+        value: UnsafeCell::new(MaybeUninit::uninit()),
+    };
+}
+
+impl<T: Copy, const N: usize> EventRing<T, N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "ring capacity must be a power of two");
+        N - 1
+    };
+
+    /// Creates an empty ring.
+    pub const fn new() -> Self {
+        let _ = Self::MASK;
+        Self {
+            slots: [Slot::EMPTY; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the capacity of the ring.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Appends `event`, from any context.
+    ///
+    /// Hands `event` back, and counts it as dropped, if the ring is full.
+    pub fn push(&self, event: T) -> Result<(), T> {
+        let mut pos = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & Self::MASK];
+            let lap = pos & !Self::MASK;
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(lap) as isize;
+            if diff == 0 {
+                match self.head.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: Winning the exchange made us the only producer of `pos`, and
+                        // no consumer reads the slot before its sequence moves on below.
+                        unsafe { (*slot.value.get()).write(event) };
+                        slot.seq.store(lap.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(head) => pos = head,
+                }
+            } else if diff < 0 {
+                // The slot still holds the event of the previous lap.
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                return Err(event);
+            } else {
+                pos = self.head.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Removes and returns the oldest event, from any context.
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & Self::MASK];
+            let lap = pos & !Self::MASK;
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq.wrapping_sub(lap.wrapping_add(1)) as isize;
+            if diff == 0 {
+                match self.tail.compare_exchange_weak(
+                    pos,
+                    pos.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: The sequence says the producer of `pos` finished writing, and
+                        // winning the exchange made us its only consumer.
+                        let event = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.seq.store(lap.wrapping_add(N), Ordering::Release);
+                        return Some(event);
+                    }
+                    Err(tail) => pos = tail,
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.tail.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Returns `true` if no event is queued.
+    ///
+    /// Only a hint while producers or consumers run concurrently.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed) == self.tail.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of events rejected because the ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T: Copy, const N: usize> Default for EventRing<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Packet loss and latency injection.
+//!
+//! Retransmission, RNR and congestion handling only run when the network misbehaves. To exercise
+//! them on healthy networks, a [`FaultInjector`] makes the transmit path drop or hold back a
+//! configurable fraction of the outgoing packets. Every device has its own knobs, all at 0 by
+//! default, and the injection itself is only compiled in with
+//! `CONFIG_RUST_RDMA_FAULT_INJECTION`.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use super::tunables::TuneError;
+
+/// Unit of the fractions, parts per million.
+pub const PPM: u32 = 1_000_000;
+
+/// Longest delay that can be injected, in microseconds.
+pub const MAX_DELAY_US: u32 = 1_000_000;
+
+/// What to do with an outgoing packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TxFault {
+    /// Send it now.
+    Pass,
+    /// Pretend it was sent but lose it.
+    Drop,
+    /// Send it after this many microseconds.
+    Delay(u32),
+}
+
+/// A fault injection setting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultKnob {
+    /// Fraction of packets dropped, in [`PPM`].
+    DropPpm,
+    /// Fraction of packets delayed, in [`PPM`].
+    DelayPpm,
+    /// Delay of the delayed packets, up to [`MAX_DELAY_US`].
+    DelayUs,
+}
+
+impl FaultKnob {
+    /// Every knob, in the order of their sysfs attributes.
+    pub const ALL: [Self; 3] = [Self::DropPpm, Self::DelayPpm, Self::DelayUs];
+
+    /// Returns the name of the sysfs attribute.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::DropPpm => "fault_drop_ppm",
+            Self::DelayPpm => "fault_delay_ppm",
+            Self::DelayUs => "fault_delay_us",
+        }
+    }
+
+    /// Looks a knob up by attribute name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+}
+
+/// Fault injection settings and counters of a device.
+pub struct FaultInjector {
+    drop_ppm: AtomicU32,
+    delay_ppm: AtomicU32,
+    delay_us: AtomicU32,
+    dropped: AtomicU64,
+    delayed: AtomicU64,
+}
+
+impl FaultInjector {
+    /// Creates an injector that lets every packet through.
+    pub const fn new() -> Self {
+        Self {
+            drop_ppm: AtomicU32::new(0),
+            delay_ppm: AtomicU32::new(0),
+            delay_us: AtomicU32::new(0),
+            dropped: AtomicU64::new(0),
+            delayed: AtomicU64::new(0),
+        }
+    }
+
+    fn field(&self, knob: FaultKnob) -> &AtomicU32 {
+        match knob {
+            FaultKnob::DropPpm => &self.drop_ppm,
+            FaultKnob::DelayPpm => &self.delay_ppm,
+            FaultKnob::DelayUs => &self.delay_us,
+        }
+    }
+
+    /// Returns `true` if some packets may be dropped or delayed.
+    pub fn is_active(&self) -> bool {
+        self.drop_ppm.load(Ordering::Relaxed) != 0
+            || (self.delay_ppm.load(Ordering::Relaxed) != 0
+                && self.delay_us.load(Ordering::Relaxed) != 0)
+    }
+
+    /// Returns the current value of `knob`.
+    pub fn get(&self, knob: FaultKnob) -> u32 {
+        self.field(knob).load(Ordering::Relaxed)
+    }
+
+    /// Sets `knob` to `value`.
+    ///
+    /// The dropped and delayed fractions together cannot exceed [`PPM`].
+    pub fn set(&self, knob: FaultKnob, value: u32) -> Result<(), TuneError> {
+        let ok = match knob {
+            FaultKnob::DropPpm => value <= PPM - self.get(FaultKnob::DelayPpm),
+            FaultKnob::DelayPpm => value <= PPM - self.get(FaultKnob::DropPpm),
+            FaultKnob::DelayUs => value <= MAX_DELAY_US,
+        };
+        if !ok {
+            return Err(TuneError::OutOfRange);
+        }
+        self.field(knob).store(value, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Parses `text`, e.g. written to a sysfs attribute, and sets `knob` to it.
+    pub fn store(&self, knob: FaultKnob, text: &str) -> Result<(), TuneError> {
+        let value = text.trim().parse().map_err(|_| TuneError::Parse)?;
+        self.set(knob, value)
+    }
+
+    /// Writes the current value of `knob`, e.g. for a sysfs `show` callback.
+    pub fn show(&self, knob: FaultKnob, w: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(w, "{}", self.get(knob))
+    }
+
+    /// Decides the fate of an outgoing packet from a uniformly distributed `random` value.
+    pub fn decide(&self, random: u32) -> TxFault {
+        let sample = random % PPM;
+        let drop_ppm = self.drop_ppm.load(Ordering::Relaxed);
+        if sample < drop_ppm {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return TxFault::Drop;
+        }
+        let delay_us = self.delay_us.load(Ordering::Relaxed);
+        if delay_us != 0 && sample - drop_ppm < self.delay_ppm.load(Ordering::Relaxed) {
+            self.delayed.fetch_add(1, Ordering::Relaxed);
+            return TxFault::Delay(delay_us);
+        }
+        TxFault::Pass
+    }
+
+    /// Returns the number of packets dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of packets delayed so far.
+    pub fn delayed(&self) -> u64 {
+        self.delayed.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
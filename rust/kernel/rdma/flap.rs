@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Coalescing of link state flaps.
+//!
+//! A link bouncing during a switch reconfiguration sends a burst of `NETDEV_DOWN`/`NETDEV_UP`
+//! events. Propagated one by one, every `DOWN` moves the port and the QPs using it to the error
+//! state and floods the consumers with flushed completions, only for the link to come back a few
+//! milliseconds later. A [`FlapDebouncer`] holds the events of each link back for a holdoff that
+//! restarts with every new event, and only reports the state the link settled in, if it differs
+//! from the last one reported. A link that went down and up again within the holdoff is never
+//! reported at all.
+//!
+//! The clock is the caller's, e.g. jiffies, and may wrap. Synchronisation is left to the owner.
+
+use super::timeout_map::reached;
+
+/// A link state change, as reported by the netdev notifier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkEvent {
+    /// The link came up, `NETDEV_UP` or a carrier gained.
+    Up,
+    /// The link went down, `NETDEV_DOWN` or a carrier lost.
+    Down,
+}
+
+/// What to do with an event passed to [`FlapDebouncer::event`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlapVerdict {
+    /// Propagate the event now: debouncing is off or the link could not be tracked.
+    Propagate(LinkEvent),
+    /// The event is held back; [`FlapDebouncer::expire`] must run at the given deadline.
+    Deferred(u64),
+    /// The event repeats the state already pending or reported.
+    Ignored,
+}
+
+/// Counters of a [`FlapDebouncer`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlapStats {
+    /// Events passed to [`FlapDebouncer::event`].
+    pub events: u64,
+    /// State changes propagated, immediately or once the holdoff elapsed.
+    pub propagated: u64,
+}
+
+impl FlapStats {
+    /// Returns the number of events that were absorbed instead of propagated.
+    pub const fn absorbed(&self) -> u64 {
+        self.events.saturating_sub(self.propagated)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    /// The link, 0 if the slot is free.
+    link: u64,
+    /// The state last propagated, `None` until the first one is.
+    reported: Option<LinkEvent>,
+    /// The latest state and the end of its holdoff.
+    pending: Option<(LinkEvent, u64)>,
+}
+
+impl Slot {
+    const FREE: Self = Self {
+        link: 0,
+        reported: None,
+        pending: None,
+    };
+}
+
+/// Debounces the events of up to `N` links.
+pub struct FlapDebouncer<const N: usize> {
+    holdoff: u64,
+    slots: [Slot; N],
+    stats: FlapStats,
+}
+
+impl<const N: usize> FlapDebouncer<N> {
+    /// Creates a debouncer holding events back for `holdoff` ticks; 0 propagates them right away.
+    pub const fn new(holdoff: u64) -> Self {
+        Self {
+            holdoff,
+            slots: [Slot::FREE; N],
+            stats: FlapStats {
+                events: 0,
+                propagated: 0,
+            },
+        }
+    }
+
+    /// Returns the holdoff in ticks.
+    pub const fn holdoff(&self) -> u64 {
+        self.holdoff
+    }
+
+    /// Changes the holdoff of the events to come; pending events keep their deadline.
+    pub fn set_holdoff(&mut self, holdoff: u64) {
+        self.holdoff = holdoff;
+    }
+
+    fn find(&self, link: u64) -> Option<usize> {
+        self.slots.iter().position(|s| s.link == link)
+    }
+
+    /// Records `event` on `link` at `now`.
+    ///
+    /// Links are identified by the caller, e.g. by their interface index; 0 is never tracked.
+    /// Every event restarts the holdoff of its link, so a link only gets reported once it stayed
+    /// quiet for the whole holdoff.
+    pub fn event(&mut self, link: u64, event: LinkEvent, now: u64) -> FlapVerdict {
+        self.stats.events += 1;
+        let slot = match self.find(link) {
+            Some(i) if link != 0 => Some(i),
+            _ if link == 0 || self.holdoff == 0 => None,
+            _ => self.find(0),
+        };
+        if let Some(i) = slot {
+            self.slots[i].link = link;
+        }
+        let s = match slot {
+            Some(i) if self.holdoff != 0 => &mut self.slots[i],
+            _ => {
+                // Untracked or not debounced: better a storm than a lost state change.
+                if let Some(s) = slot.map(|i| &mut self.slots[i]) {
+                    s.reported = Some(event);
+                    s.pending = None;
+                }
+                self.stats.propagated += 1;
+                return FlapVerdict::Propagate(event);
+            }
+        };
+        if s.pending.is_none() && s.reported == Some(event) {
+            return FlapVerdict::Ignored;
+        }
+        let deadline = now.wrapping_add(self.holdoff);
+        s.pending = Some((event, deadline));
+        FlapVerdict::Deferred(deadline)
+    }
+
+    /// Calls `f` with the link and the state it settled in for each holdoff elapsed at `now`,
+    /// unless the state is the one last reported.
+    pub fn expire(&mut self, now: u64, mut f: impl FnMut(u64, LinkEvent)) {
+        for s in self.slots.iter_mut().filter(|s| s.link != 0) {
+            let (event, deadline) = match s.pending {
+                Some(pending) => pending,
+                None => continue,
+            };
+            if !reached(now, deadline) {
+                continue;
+            }
+            s.pending = None;
+            if s.reported != Some(event) {
+                s.reported = Some(event);
+                self.stats.propagated += 1;
+                f(s.link, event);
+            }
+        }
+    }
+
+    /// Returns the earliest pending deadline, if any.
+    pub fn next_deadline(&self, now: u64) -> Option<u64> {
+        self.slots
+            .iter()
+            .filter_map(|s| s.pending.map(|(_, deadline)| deadline))
+            .min_by_key(|deadline| deadline.wrapping_sub(now) as i64)
+    }
+
+    /// Stops tracking `link`, e.g. on `NETDEV_UNREGISTER`, dropping its pending event.
+    pub fn forget(&mut self, link: u64) {
+        if let Some(i) = self.find(link).filter(|_| link != 0) {
+            self.slots[i] = Slot::FREE;
+        }
+    }
+
+    /// Returns `true` if an event of `link` is held back.
+    pub fn is_pending(&self, link: u64) -> bool {
+        match self.find(link) {
+            Some(i) => link != 0 && self.slots[i].pending.is_some(),
+            None => false,
+        }
+    }
+
+    /// Returns the counters.
+    pub const fn stats(&self) -> FlapStats {
+        self.stats
+    }
+}
@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Firmware version strings.
+//!
+//! ib_core hands providers a fixed-size `char` buffer to describe their firmware, shown by
+//! `ibv_devinfo` and `rdma dev show`. [`FwStr`] lets them fill it with `write!`, truncating like
+//! `snprintf()` does and keeping the buffer NUL-terminated at all times.
+
+use core::fmt;
+
+/// A NUL-terminated string being written into a fixed-size buffer.
+///
+/// # Invariants
+///
+/// `buf[..len]` is valid UTF-8 and `buf[len]` is NUL.
+pub struct FwStr<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    truncated: bool,
+}
+
+impl<'a> FwStr<'a> {
+    /// Wraps `buf` as an empty string.
+    ///
+    /// Returns `None` if `buf` has no room for the terminating NUL.
+    pub fn new(buf: &'a mut [u8]) -> Option<Self> {
+        *buf.first_mut()? = 0;
+        // INVARIANT: The empty string is terminated by the NUL just written.
+        Some(Self {
+            buf,
+            len: 0,
+            truncated: false,
+        })
+    }
+
+    /// Returns the maximum length of the string, NUL excluded.
+    pub fn capacity(&self) -> usize {
+        self.buf.len() - 1
+    }
+
+    /// Returns the string written so far.
+    pub fn as_str(&self) -> &str {
+        // By the type invariants the bytes are valid UTF-8, so this never falls back.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+
+    /// Returns `true` if some output did not fit.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl fmt::Write for FwStr<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.capacity() - self.len;
+        let mut n = s.len().min(room);
+        if n < s.len() {
+            self.truncated = true;
+            // Never split a character.
+            while !s.is_char_boundary(n) {
+                n -= 1;
+            }
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        // INVARIANT: Whole characters were appended and the NUL moved past them.
+        self.buf[self.len] = 0;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for FwStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Global identifiers.
+//!
+//! On RoCE ports GIDs are IP addresses: IPv6 addresses as is, IPv4 addresses mapped into
+//! `::ffff:0:0/96`, plus the link-local default GID derived from the interface's MAC address.
+
+use core::fmt;
+
+use super::ip_filter::{ParseError, PeerAddr};
+
+/// A 128-bit global identifier, as carried in the GRH, in network byte order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Gid([u8; 16]);
+
+impl Gid {
+    /// The all-zero GID, which marks unused table entries.
+    pub const ZERO: Gid = Gid([0; 16]);
+
+    /// Creates a GID from its raw bytes.
+    pub const fn from_raw(raw: [u8; 16]) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the raw bytes.
+    pub const fn raw(&self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Creates the GID of an IPv4 address, i.e. the IPv4-mapped IPv6 address.
+    pub const fn from_ipv4(addr: [u8; 4]) -> Self {
+        Self(PeerAddr::from_v4(addr).octets())
+    }
+
+    /// Creates the GID of an IPv6 address.
+    pub const fn from_ipv6(addr: [u8; 16]) -> Self {
+        Self(addr)
+    }
+
+    /// Creates the link-local GID `fe80::/64` with the modified EUI-64 of `mac`, which is the
+    /// default GID of a RoCE port.
+    pub const fn link_local(mac: [u8; 6]) -> Self {
+        let eui = eui64(mac);
+        let mut raw = [0; 16];
+        raw[0] = 0xfe;
+        raw[1] = 0x80;
+        let mut i = 0;
+        while i < 8 {
+            raw[8 + i] = eui[i];
+            i += 1;
+        }
+        Self(raw)
+    }
+
+    /// Returns `true` for the all-zero GID.
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+
+    /// Returns `true` if the GID is an IPv4-mapped address, i.e. used by RoCE v2 over IPv4.
+    pub fn is_v4_mapped(&self) -> bool {
+        self.to_addr().is_v4()
+    }
+
+    /// Returns the IP address the GID represents.
+    pub const fn to_addr(&self) -> PeerAddr {
+        PeerAddr::from_v6(self.0)
+    }
+
+    /// Returns `true` if the GID is a multicast address: `ff00::/8`, or `224.0.0.0/4` mapped.
+    pub fn is_multicast(&self) -> bool {
+        if self.is_v4_mapped() {
+            self.0[12] & 0xf0 == 0xe0
+        } else {
+            self.0[0] == 0xff
+        }
+    }
+
+    /// Returns the Ethernet address frames to a multicast GID are sent to, `01:00:5e` and the
+    /// lower 23 bits of an IPv4 group, `33:33` and the lower 32 bits of an IPv6 one, as
+    /// `ip_eth_mc_map()` and `ipv6_eth_mc_map()` compute it.
+    pub fn multicast_mac(&self) -> Option<[u8; 6]> {
+        if !self.is_multicast() {
+            return None;
+        }
+        let g = &self.0;
+        if self.is_v4_mapped() {
+            Some([0x01, 0x00, 0x5e, g[13] & 0x7f, g[14], g[15]])
+        } else {
+            Some([0x33, 0x33, g[12], g[13], g[14], g[15]])
+        }
+    }
+
+    /// Returns the upper 64 bits, the subnet prefix on InfiniBand.
+    pub fn subnet_prefix(&self) -> u64 {
+        let mut half = [0; 8];
+        half.copy_from_slice(&self.0[..8]);
+        u64::from_be_bytes(half)
+    }
+
+    /// Returns the lower 64 bits, the interface identifier.
+    pub fn interface_id(&self) -> u64 {
+        let mut half = [0; 8];
+        half.copy_from_slice(&self.0[8..]);
+        u64::from_be_bytes(half)
+    }
+}
+
+/// Returns the modified EUI-64 of `mac`, as `addrconf_addr_eui48()` computes it.
+///
+/// Soft RoCE devices also use it as their node GUID.
+pub const fn eui64(mac: [u8; 6]) -> [u8; 8] {
+    [
+        mac[0] ^ 2,
+        mac[1],
+        mac[2],
+        0xff,
+        0xfe,
+        mac[3],
+        mac[4],
+        mac[5],
+    ]
+}
+
+impl From<PeerAddr> for Gid {
+    fn from(addr: PeerAddr) -> Self {
+        Self(addr.octets())
+    }
+}
+
+impl core::str::FromStr for Gid {
+    type Err = ParseError;
+
+    /// Parses an IPv6 or IPv4 address, the latter giving an IPv4-mapped GID.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        s.parse::<PeerAddr>().map(Gid::from)
+    }
+}
+
+impl fmt::Display for Gid {
+    /// Formats the GID as eight groups of four hex digits, like the `gids` sysfs attributes.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, group) in self.0.chunks(2).enumerate() {
+            if i != 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:02x}{:02x}", group[0], group[1])?;
+        }
+        Ok(())
+    }
+}
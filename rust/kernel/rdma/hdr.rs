@@ -0,0 +1,692 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! RoCEv2 transport headers.
+//!
+//! The header types are zero-copy views over a byte buffer: over `&[u8]` they only read fields,
+//! over `&mut [u8]` they can also write them, always in network byte order. [`Packet`] splits a
+//! received UDP payload into its headers according to the opcode table of [`super::opcode`] and
+//! checks that the lengths add up; [`PacketMut`] lays out an outgoing packet the same way, so the
+//! transmit path fills headers by name instead of by offset.
+
+use core::fmt;
+use core::ops::Range;
+
+use super::opcode::{self, Header, OpcodeInfo, OpcodeMask};
+use super::psn::Psn;
+
+/// Length of the invariant CRC trailing every packet.
+pub const ICRC_SIZE: usize = 4;
+
+/// Transport header version of the packets we understand.
+pub const TVER: u8 = 0;
+
+fn get<const N: usize>(buf: &[u8], offset: usize) -> [u8; N] {
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(&buf[offset..offset + N]);
+    bytes
+}
+
+fn get16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes(get(buf, offset))
+}
+
+fn get32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(get(buf, offset))
+}
+
+fn get64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_be_bytes(get(buf, offset))
+}
+
+fn put(buf: &mut [u8], offset: usize, bytes: &[u8]) {
+    buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+fn set_bit(buf: &mut [u8], offset: usize, mask: u8, value: bool) {
+    if value {
+        buf[offset] |= mask;
+    } else {
+        buf[offset] &= !mask;
+    }
+}
+
+/// Defines a header view type checking the buffer length on construction.
+macro_rules! header {
+    ($(#[$doc:meta])* $name:ident, $hdr:expr) => {
+        $(#[$doc])*
+        #[derive(Clone, Copy, Debug)]
+        pub struct $name<B>(B);
+
+        impl<B: AsRef<[u8]>> $name<B> {
+            /// Length of the header in bytes.
+            pub const SIZE: usize = $hdr.size();
+
+            /// Creates a view of the header at the start of `buf`.
+            ///
+            /// Returns `None` if `buf` is too short.
+            pub fn new(buf: B) -> Option<Self> {
+                if buf.as_ref().len() < Self::SIZE {
+                    return None;
+                }
+                Some(Self(buf))
+            }
+
+            fn buf(&self) -> &[u8] {
+                self.0.as_ref()
+            }
+        }
+
+        impl<B: AsMut<[u8]>> $name<B> {
+            fn buf_mut(&mut self) -> &mut [u8] {
+                self.0.as_mut()
+            }
+        }
+    };
+}
+
+header!(
+    /// Base transport header.
+    Bth,
+    Header::Bth
+);
+
+impl<B: AsRef<[u8]>> Bth<B> {
+    /// Returns the opcode.
+    pub fn opcode(&self) -> u8 {
+        self.buf()[0]
+    }
+
+    /// Returns `true` if the requester asks for a solicited event.
+    pub fn solicited(&self) -> bool {
+        self.buf()[1] & 0x80 != 0
+    }
+
+    /// Returns the migration request bit.
+    pub fn migreq(&self) -> bool {
+        self.buf()[1] & 0x40 != 0
+    }
+
+    /// Returns the number of pad bytes after the payload.
+    pub fn pad(&self) -> u8 {
+        (self.buf()[1] >> 4) & 0x3
+    }
+
+    /// Returns the transport header version.
+    pub fn tver(&self) -> u8 {
+        self.buf()[1] & 0xf
+    }
+
+    /// Returns the partition key.
+    pub fn pkey(&self) -> u16 {
+        get16(self.buf(), 2)
+    }
+
+    /// Returns the forward explicit congestion notification bit.
+    pub fn fecn(&self) -> bool {
+        self.buf()[4] & 0x80 != 0
+    }
+
+    /// Returns the backward explicit congestion notification bit.
+    pub fn becn(&self) -> bool {
+        self.buf()[4] & 0x40 != 0
+    }
+
+    /// Returns the destination QP number.
+    pub fn dest_qp(&self) -> u32 {
+        get32(self.buf(), 4) & 0xff_ffff
+    }
+
+    /// Returns `true` if the requester asks for an acknowledgement.
+    pub fn ack_req(&self) -> bool {
+        self.buf()[8] & 0x80 != 0
+    }
+
+    /// Returns the packet sequence number.
+    pub fn psn(&self) -> Psn {
+        Psn::new(get32(self.buf(), 8))
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Bth<B> {
+    /// Sets the opcode.
+    pub fn set_opcode(&mut self, opcode: u8) {
+        self.buf_mut()[0] = opcode;
+    }
+
+    /// Sets the solicited event bit.
+    pub fn set_solicited(&mut self, value: bool) {
+        set_bit(self.buf_mut(), 1, 0x80, value);
+    }
+
+    /// Sets the migration request bit.
+    pub fn set_migreq(&mut self, value: bool) {
+        set_bit(self.buf_mut(), 1, 0x40, value);
+    }
+
+    /// Sets the number of pad bytes, at most 3.
+    pub fn set_pad(&mut self, pad: u8) {
+        let buf = self.buf_mut();
+        buf[1] = (buf[1] & !0x30) | ((pad & 0x3) << 4);
+    }
+
+    /// Sets the transport header version.
+    pub fn set_tver(&mut self, tver: u8) {
+        let buf = self.buf_mut();
+        buf[1] = (buf[1] & !0xf) | (tver & 0xf);
+    }
+
+    /// Sets the partition key.
+    pub fn set_pkey(&mut self, pkey: u16) {
+        put(self.buf_mut(), 2, &pkey.to_be_bytes());
+    }
+
+    /// Sets the forward explicit congestion notification bit.
+    pub fn set_fecn(&mut self, value: bool) {
+        set_bit(self.buf_mut(), 4, 0x80, value);
+    }
+
+    /// Sets the backward explicit congestion notification bit.
+    pub fn set_becn(&mut self, value: bool) {
+        set_bit(self.buf_mut(), 4, 0x40, value);
+    }
+
+    /// Sets the destination QP number, truncated to 24 bits.
+    pub fn set_dest_qp(&mut self, qpn: u32) {
+        let flags = u32::from(self.buf()[4] & 0xc0) << 24;
+        put(
+            self.buf_mut(),
+            4,
+            &(flags | (qpn & 0xff_ffff)).to_be_bytes(),
+        );
+    }
+
+    /// Sets the acknowledge request bit.
+    pub fn set_ack_req(&mut self, value: bool) {
+        set_bit(self.buf_mut(), 8, 0x80, value);
+    }
+
+    /// Sets the packet sequence number.
+    pub fn set_psn(&mut self, psn: Psn) {
+        let flags = u32::from(self.buf()[8] & 0x80) << 24;
+        put(self.buf_mut(), 8, &(flags | psn.value()).to_be_bytes());
+    }
+}
+
+header!(
+    /// RDMA extended transport header, on RDMA WRITE and READ requests.
+    Reth,
+    Header::Reth
+);
+
+impl<B: AsRef<[u8]>> Reth<B> {
+    /// Returns the remote virtual address.
+    pub fn va(&self) -> u64 {
+        get64(self.buf(), 0)
+    }
+
+    /// Returns the remote key.
+    pub fn rkey(&self) -> u32 {
+        get32(self.buf(), 8)
+    }
+
+    /// Returns the length of the whole DMA transfer.
+    pub fn len(&self) -> u32 {
+        get32(self.buf(), 12)
+    }
+
+    /// Returns `true` if the transfer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Reth<B> {
+    /// Sets the remote virtual address.
+    pub fn set_va(&mut self, va: u64) {
+        put(self.buf_mut(), 0, &va.to_be_bytes());
+    }
+
+    /// Sets the remote key.
+    pub fn set_rkey(&mut self, rkey: u32) {
+        put(self.buf_mut(), 8, &rkey.to_be_bytes());
+    }
+
+    /// Sets the length of the whole DMA transfer.
+    pub fn set_len(&mut self, len: u32) {
+        put(self.buf_mut(), 12, &len.to_be_bytes());
+    }
+}
+
+/// AETH syndrome of an ACK that advertises no credit limit.
+pub const AETH_ACK_UNLIMITED: u8 = 0x1f;
+/// AETH syndrome of a PSN sequence error NAK.
+pub const AETH_NAK_PSN_SEQ_ERROR: u8 = 0x60;
+/// AETH syndrome of an invalid request NAK.
+pub const AETH_NAK_INVALID_REQUEST: u8 = 0x61;
+/// AETH syndrome of a remote access error NAK.
+pub const AETH_NAK_REMOTE_ACCESS: u8 = 0x62;
+/// AETH syndrome of a remote operational error NAK.
+pub const AETH_NAK_REMOTE_OP: u8 = 0x63;
+
+header!(
+    /// ACK extended transport header, on acknowledgements and read responses.
+    Aeth,
+    Header::Aeth
+);
+
+impl<B: AsRef<[u8]>> Aeth<B> {
+    /// Returns the syndrome: ACK, RNR NAK or NAK with its credit count, timer or code.
+    pub fn syndrome(&self) -> u8 {
+        self.buf()[0]
+    }
+
+    /// Returns the message sequence number.
+    pub fn msn(&self) -> u32 {
+        get32(self.buf(), 0) & 0xff_ffff
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Aeth<B> {
+    /// Sets the syndrome.
+    pub fn set_syndrome(&mut self, syndrome: u8) {
+        self.buf_mut()[0] = syndrome;
+    }
+
+    /// Sets the message sequence number, truncated to 24 bits.
+    pub fn set_msn(&mut self, msn: u32) {
+        let syndrome = u32::from(self.syndrome()) << 24;
+        put(
+            self.buf_mut(),
+            0,
+            &(syndrome | (msn & 0xff_ffff)).to_be_bytes(),
+        );
+    }
+}
+
+header!(
+    /// Atomic extended transport header, on atomic requests.
+    Atmeth,
+    Header::Atmeth
+);
+
+impl<B: AsRef<[u8]>> Atmeth<B> {
+    /// Returns the remote virtual address.
+    pub fn va(&self) -> u64 {
+        get64(self.buf(), 0)
+    }
+
+    /// Returns the remote key.
+    pub fn rkey(&self) -> u32 {
+        get32(self.buf(), 8)
+    }
+
+    /// Returns the swap value of a compare and swap, or the addend of a fetch and add.
+    pub fn swap_add(&self) -> u64 {
+        get64(self.buf(), 12)
+    }
+
+    /// Returns the compare value of a compare and swap.
+    pub fn compare(&self) -> u64 {
+        get64(self.buf(), 20)
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Atmeth<B> {
+    /// Sets the remote virtual address.
+    pub fn set_va(&mut self, va: u64) {
+        put(self.buf_mut(), 0, &va.to_be_bytes());
+    }
+
+    /// Sets the remote key.
+    pub fn set_rkey(&mut self, rkey: u32) {
+        put(self.buf_mut(), 8, &rkey.to_be_bytes());
+    }
+
+    /// Sets the swap value or addend.
+    pub fn set_swap_add(&mut self, value: u64) {
+        put(self.buf_mut(), 12, &value.to_be_bytes());
+    }
+
+    /// Sets the compare value.
+    pub fn set_compare(&mut self, value: u64) {
+        put(self.buf_mut(), 20, &value.to_be_bytes());
+    }
+}
+
+header!(
+    /// Atomic ACK extended transport header, on atomic responses.
+    Atmack,
+    Header::Atmack
+);
+
+impl<B: AsRef<[u8]>> Atmack<B> {
+    /// Returns the value the target held before the operation.
+    pub fn orig(&self) -> u64 {
+        get64(self.buf(), 0)
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Atmack<B> {
+    /// Sets the original value.
+    pub fn set_orig(&mut self, value: u64) {
+        put(self.buf_mut(), 0, &value.to_be_bytes());
+    }
+}
+
+header!(
+    /// Immediate data.
+    Immdt,
+    Header::Immdt
+);
+
+impl<B: AsRef<[u8]>> Immdt<B> {
+    /// Returns the immediate data in network byte order, as reported in work completions.
+    pub fn imm(&self) -> u32 {
+        u32::from_ne_bytes(get(self.buf(), 0))
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Immdt<B> {
+    /// Sets the immediate data, given in network byte order as posted.
+    pub fn set_imm(&mut self, imm: u32) {
+        put(self.buf_mut(), 0, &imm.to_ne_bytes());
+    }
+}
+
+header!(
+    /// Invalidate extended transport header, on SENDs with invalidate.
+    Ieth,
+    Header::Ieth
+);
+
+impl<B: AsRef<[u8]>> Ieth<B> {
+    /// Returns the remote key to invalidate.
+    pub fn rkey(&self) -> u32 {
+        get32(self.buf(), 0)
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Ieth<B> {
+    /// Sets the remote key to invalidate.
+    pub fn set_rkey(&mut self, rkey: u32) {
+        put(self.buf_mut(), 0, &rkey.to_be_bytes());
+    }
+}
+
+header!(
+    /// Datagram extended transport header, on UD packets.
+    Deth,
+    Header::Deth
+);
+
+impl<B: AsRef<[u8]>> Deth<B> {
+    /// Returns the queue key.
+    pub fn qkey(&self) -> u32 {
+        get32(self.buf(), 0)
+    }
+
+    /// Returns the source QP number.
+    pub fn src_qp(&self) -> u32 {
+        get32(self.buf(), 4) & 0xff_ffff
+    }
+}
+
+impl<B: AsRef<[u8]> + AsMut<[u8]>> Deth<B> {
+    /// Sets the queue key.
+    pub fn set_qkey(&mut self, qkey: u32) {
+        put(self.buf_mut(), 0, &qkey.to_be_bytes());
+    }
+
+    /// Sets the source QP number, truncated to 24 bits.
+    pub fn set_src_qp(&mut self, qpn: u32) {
+        put(self.buf_mut(), 4, &(qpn & 0xff_ffff).to_be_bytes());
+    }
+}
+
+/// Reasons a packet is malformed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// The buffer is shorter than the headers, pad and ICRC of the opcode.
+    Truncated,
+    /// The opcode is not supported.
+    UnknownOpcode(u8),
+    /// The transport header version is not [`TVER`].
+    BadVersion(u8),
+    /// The opcode carries no payload, but the packet has payload or pad bytes.
+    UnexpectedPayload,
+    /// The payload length does not fit the position of the packet in its message.
+    PayloadLength,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Truncated => f.write_str("truncated packet"),
+            ParseError::UnknownOpcode(op) => write!(f, "unknown opcode {op:#04x}"),
+            ParseError::BadVersion(tver) => write!(f, "bad transport version {tver}"),
+            ParseError::UnexpectedPayload => f.write_str("payload on an opcode without one"),
+            ParseError::PayloadLength => f.write_str("payload length does not match the MTU"),
+        }
+    }
+}
+
+/// Returns the byte range of `hdr` in packets described by `info`.
+fn ext_range(info: &OpcodeInfo, hdr: Header) -> Option<Range<usize>> {
+    let offset = info.offset(hdr)?;
+    Some(offset..offset + hdr.size())
+}
+
+/// Generates the accessors of the extended headers of a packet.
+macro_rules! ext_headers {
+    (@shared $lt:lifetime; $($(#[$doc:meta])* $name:ident => $ty:ident,)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(&self) -> Option<$ty<&$lt [u8]>> {
+                $ty::new(&self.buf[ext_range(&self.info, Header::$ty)?])
+            }
+        )*
+    };
+    (@exclusive; $($(#[$doc:meta])* $name:ident => $ty:ident,)*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(&mut self) -> Option<$ty<&mut [u8]>> {
+                $ty::new(&mut self.buf[ext_range(&self.info, Header::$ty)?])
+            }
+        )*
+    };
+}
+
+/// A received packet, from the BTH to the ICRC included.
+#[derive(Clone, Copy, Debug)]
+pub struct Packet<'a> {
+    buf: &'a [u8],
+    info: OpcodeInfo,
+    payload_len: usize,
+}
+
+impl<'a> Packet<'a> {
+    /// Splits `buf`, the UDP payload of a RoCEv2 packet, into its headers and payload.
+    pub fn parse(buf: &'a [u8]) -> Result<Self, ParseError> {
+        let bth = Bth::new(buf).ok_or(ParseError::Truncated)?;
+        let info = opcode::info(bth.opcode()).ok_or(ParseError::UnknownOpcode(bth.opcode()))?;
+        if bth.tver() != TVER {
+            return Err(ParseError::BadVersion(bth.tver()));
+        }
+        let pad = bth.pad() as usize;
+        let payload_len = buf
+            .len()
+            .checked_sub(info.header_len() + pad + ICRC_SIZE)
+            .ok_or(ParseError::Truncated)?;
+        if !info.mask.contains(OpcodeMask::PAYLOAD) && payload_len + pad != 0 {
+            return Err(ParseError::UnexpectedPayload);
+        }
+        Ok(Self {
+            buf,
+            info,
+            payload_len,
+        })
+    }
+
+    /// Checks the payload length against the path MTU: the first and middle packets of a
+    /// message are exactly `mtu` bytes long, the others at most `mtu` bytes.
+    pub fn check_mtu(&self, mtu: usize) -> Result<(), ParseError> {
+        let full = self
+            .info
+            .mask
+            .intersects(OpcodeMask::START | OpcodeMask::MIDDLE)
+            && !self.info.mask.contains(OpcodeMask::END);
+        if self.payload_len > mtu || (full && self.payload_len != mtu) {
+            return Err(ParseError::PayloadLength);
+        }
+        Ok(())
+    }
+
+    /// Returns the opcode description.
+    pub fn info(&self) -> OpcodeInfo {
+        self.info
+    }
+
+    /// Returns the length of the packet, ICRC included.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns `true` if the packet is empty, which a parsed packet never is.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Returns the base transport header.
+    pub fn bth(&self) -> Bth<&'a [u8]> {
+        Bth(self.buf)
+    }
+
+    ext_headers! { @shared 'a;
+        /// Returns the DETH, if the opcode carries one.
+        deth => Deth,
+        /// Returns the RETH, if the opcode carries one.
+        reth => Reth,
+        /// Returns the AtomicETH, if the opcode carries one.
+        atmeth => Atmeth,
+        /// Returns the AETH, if the opcode carries one.
+        aeth => Aeth,
+        /// Returns the AtomicAckETH, if the opcode carries one.
+        atmack => Atmack,
+        /// Returns the immediate data header, if the opcode carries one.
+        immdt => Immdt,
+        /// Returns the IETH, if the opcode carries one.
+        ieth => Ieth,
+    }
+
+    /// Returns the payload, without pad bytes.
+    pub fn payload(&self) -> &'a [u8] {
+        let start = self.info.header_len();
+        &self.buf[start..start + self.payload_len]
+    }
+
+    /// Returns the ICRC as found on the wire.
+    pub fn icrc(&self) -> [u8; ICRC_SIZE] {
+        get(self.buf, self.buf.len() - ICRC_SIZE)
+    }
+}
+
+/// A packet being built, from the BTH to the ICRC included.
+#[derive(Debug)]
+pub struct PacketMut<'a> {
+    buf: &'a mut [u8],
+    info: OpcodeInfo,
+    payload_len: usize,
+}
+
+impl<'a> PacketMut<'a> {
+    /// Lays out a packet of `opcode` carrying `payload_len` bytes at the start of `buf`.
+    ///
+    /// The headers and pad bytes are cleared, then the opcode, pad count and version are set.
+    pub fn init(buf: &'a mut [u8], opcode: u8, payload_len: usize) -> Result<Self, ParseError> {
+        let info = opcode::info(opcode).ok_or(ParseError::UnknownOpcode(opcode))?;
+        if payload_len != 0 && !info.mask.contains(OpcodeMask::PAYLOAD) {
+            return Err(ParseError::UnexpectedPayload);
+        }
+        let pad = payload_len.wrapping_neg() & 3;
+        let hdr_len = info.header_len();
+        let len = hdr_len + payload_len + pad + ICRC_SIZE;
+        if buf.len() < len {
+            return Err(ParseError::Truncated);
+        }
+        let buf = &mut buf[..len];
+        buf[..hdr_len].fill(0);
+        buf[hdr_len + payload_len..].fill(0);
+        let mut bth = Bth(&mut *buf);
+        bth.set_opcode(opcode);
+        bth.set_pad(pad as u8);
+        bth.set_tver(TVER);
+        Ok(Self {
+            buf,
+            info,
+            payload_len,
+        })
+    }
+
+    /// Returns the opcode description.
+    pub fn info(&self) -> OpcodeInfo {
+        self.info
+    }
+
+    /// Returns the length of the packet, ICRC included.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns `true` if the packet is empty, which an initialised packet never is.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Returns the base transport header.
+    pub fn bth(&mut self) -> Bth<&mut [u8]> {
+        Bth(&mut *self.buf)
+    }
+
+    ext_headers! { @exclusive;
+        /// Returns the DETH, if the opcode carries one.
+        deth => Deth,
+        /// Returns the RETH, if the opcode carries one.
+        reth => Reth,
+        /// Returns the AtomicETH, if the opcode carries one.
+        atmeth => Atmeth,
+        /// Returns the AETH, if the opcode carries one.
+        aeth => Aeth,
+        /// Returns the AtomicAckETH, if the opcode carries one.
+        atmack => Atmack,
+        /// Returns the immediate data header, if the opcode carries one.
+        immdt => Immdt,
+        /// Returns the IETH, if the opcode carries one.
+        ieth => Ieth,
+    }
+
+    /// Returns the payload to fill in.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        let start = self.info.header_len();
+        &mut self.buf[start..start + self.payload_len]
+    }
+
+    /// Returns the packet without its ICRC, which covers these bytes.
+    pub fn without_icrc(&self) -> &[u8] {
+        &self.buf[..self.buf.len() - ICRC_SIZE]
+    }
+
+    /// Stores the ICRC as it goes on the wire.
+    pub fn set_icrc(&mut self, icrc: [u8; ICRC_SIZE]) {
+        let offset = self.buf.len() - ICRC_SIZE;
+        put(self.buf, offset, &icrc);
+    }
+
+    /// Returns the finished packet.
+    pub fn into_packet(self) -> Packet<'a> {
+        Packet {
+            buf: self.buf,
+            info: self.info,
+            payload_len: self.payload_len,
+        }
+    }
+}
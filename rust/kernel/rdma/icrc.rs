@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! RoCEv2 invariant CRC.
+//!
+//! The ICRC is a CRC32 over the IP, UDP and transport headers with the fields routers may rewrite
+//! masked to all ones, followed by the payload and pad bytes. As RoCEv2 has no Local Route Header,
+//! the computation starts from the CRC of the eight masked LRH bytes, like the C driver does.
+//!
+//! The CRC32 routine is passed in, so that the kernel can use its optimised `crc32_le()` while the
+//! host tests use the software [`super::crc::crc32_le`].
+
+use super::hdr::ICRC_SIZE;
+use super::opcode::Header;
+
+/// A CRC32 update function with the calling convention of the kernel's `crc32_le()`.
+pub type Crc32 = fn(u32, &[u8]) -> u32;
+
+/// CRC32 of eight bytes of all ones, standing for the masked LRH, from a seed of all ones.
+pub const SEED: u32 = 0xdebb_20e3;
+
+/// Length of the UDP header.
+pub const UDP_HDR_LEN: usize = 8;
+
+const IPV4_MIN_HDR_LEN: usize = 20;
+const IPV6_HDR_LEN: usize = 40;
+const MAX_PSEUDO_HDR_LEN: usize = 60 + UDP_HDR_LEN + Header::Bth.size();
+
+/// Reasons an ICRC cannot be checked or does not match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IcrcError {
+    /// The IP header is not IPv4 or IPv6, or a header is cut short.
+    Malformed,
+    /// The ICRC of the packet differs from the computed one.
+    Mismatch {
+        /// ICRC computed over the packet.
+        expected: u32,
+        /// ICRC found at the end of the packet.
+        found: u32,
+    },
+}
+
+/// Returns the length of the IPv4 or IPv6 header at the start of `ip`.
+pub fn ip_hdr_len(ip: &[u8]) -> Option<usize> {
+    let len = match ip.first()? >> 4 {
+        4 => usize::from(ip[0] & 0xf) * 4,
+        6 => IPV6_HDR_LEN,
+        _ => return None,
+    };
+    if len < IPV4_MIN_HDR_LEN || ip.len() < len {
+        return None;
+    }
+    Some(len)
+}
+
+/// Computes the ICRC of a packet.
+///
+/// `ip_udp` holds the IP header, options included, and the UDP header. `packet` runs from the BTH
+/// to the last pad byte, without the ICRC itself. Returns `None` if the headers are malformed.
+pub fn compute(crc32: Crc32, ip_udp: &[u8], packet: &[u8]) -> Option<u32> {
+    let ip_len = ip_hdr_len(ip_udp)?;
+    let len = ip_len + UDP_HDR_LEN + Header::Bth.size();
+    if ip_udp.len() != ip_len + UDP_HDR_LEN || packet.len() < Header::Bth.size() {
+        return None;
+    }
+
+    let mut pseudo = [0u8; MAX_PSEUDO_HDR_LEN];
+    pseudo[..ip_len + UDP_HDR_LEN].copy_from_slice(ip_udp);
+    pseudo[ip_len + UDP_HDR_LEN..len].copy_from_slice(&packet[..Header::Bth.size()]);
+    if pseudo[0] >> 4 == 4 {
+        // Type of service, time to live and header checksum.
+        pseudo[1] = 0xff;
+        pseudo[8] = 0xff;
+        pseudo[10..12].fill(0xff);
+    } else {
+        // Traffic class, flow label and hop limit.
+        pseudo[0] |= 0xf;
+        pseudo[1..4].fill(0xff);
+        pseudo[7] = 0xff;
+    }
+    // UDP checksum.
+    pseudo[ip_len + 6..ip_len + 8].fill(0xff);
+    // BTH FECN, BECN and reserved bits.
+    pseudo[ip_len + UDP_HDR_LEN + 4] = 0xff;
+
+    let crc = crc32(SEED, &pseudo[..len]);
+    Some(!crc32(crc, &packet[Header::Bth.size()..]))
+}
+
+/// Returns the ICRC as it goes on the wire, least significant byte first.
+pub fn to_wire(icrc: u32) -> [u8; ICRC_SIZE] {
+    icrc.to_le_bytes()
+}
+
+/// Checks the ICRC at the end of `packet`, which runs from the BTH to the ICRC included.
+pub fn verify(crc32: Crc32, ip_udp: &[u8], packet: &[u8]) -> Result<(), IcrcError> {
+    let body_len = packet
+        .len()
+        .checked_sub(ICRC_SIZE)
+        .ok_or(IcrcError::Malformed)?;
+    let (body, trailer) = packet.split_at(body_len);
+    let expected = compute(crc32, ip_udp, body).ok_or(IcrcError::Malformed)?;
+    let mut found = [0; ICRC_SIZE];
+    found.copy_from_slice(trailer);
+    let found = u32::from_le_bytes(found);
+    if expected != found {
+        return Err(IcrcError::Mismatch { expected, found });
+    }
+    Ok(())
+}
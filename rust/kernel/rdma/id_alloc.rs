@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Bitmap allocation of IDs.
+//!
+//! Several resources are numbered from a fixed range and need a free number on allocation: the
+//! QP numbers a driver sub-allocates from a range reserved in the firmware, the entries of a
+//! hardware GID table, the indices of a pool. [`IdAllocator`] keeps one bit per ID of an
+//! [`IndexRange`], like the kernel's `bitmap_find_next_zero_area()` users, and hands IDs out
+//! either cyclically, so that a freed ID is not reused right away, or as aligned blocks of
+//! consecutive IDs. Parts of the range can be reserved, e.g. for the special QPs.
+//!
+//! The bitmap is stored in `S`, a fixed array or a heap buffer of `u64` words, of at least
+//! [`words_for`] words for the range. Synchronisation is left to the owner.
+
+use super::pool::IndexRange;
+
+/// Returns the number of `u64` words needed for a bitmap of `size` bits.
+pub const fn words_for(size: u32) -> usize {
+    (size as usize + 63) >> 6
+}
+
+/// An allocator of the IDs of a range.
+pub struct IdAllocator<S> {
+    words: S,
+    range: IndexRange,
+    next: u32,
+    used: u32,
+}
+
+impl<S: AsRef<[u64]> + AsMut<[u64]>> IdAllocator<S> {
+    /// Creates an allocator of the IDs of `range`, with every ID free, keeping its bitmap in
+    /// `words`.
+    ///
+    /// Returns `None` if `words` is too short for the range.
+    pub fn new(range: IndexRange, mut words: S) -> Option<Self> {
+        if range.max < range.min || words.as_ref().len() < words_for(range.size()) {
+            return None;
+        }
+        words.as_mut().fill(0);
+        Some(Self {
+            words,
+            range,
+            next: 0,
+            used: 0,
+        })
+    }
+
+    /// Returns the range.
+    pub fn range(&self) -> IndexRange {
+        self.range
+    }
+
+    /// Returns the number of IDs allocated or reserved.
+    pub fn used(&self) -> u32 {
+        self.used
+    }
+
+    /// Returns `true` if no ID is left.
+    pub fn is_full(&self) -> bool {
+        self.used == self.range.size()
+    }
+
+    /// Returns `true` if `id` is allocated or reserved.
+    pub fn is_used(&self, id: u32) -> bool {
+        self.range.contains(id) && self.test(id - self.range.min)
+    }
+
+    /// Allocates the first free ID at or after the one following the last allocation, wrapping
+    /// around the range.
+    pub fn alloc(&mut self) -> Option<u32> {
+        let size = self.range.size();
+        let bit = self
+            .find_zero(self.next, size)
+            .or_else(|| self.find_zero(0, self.next))?;
+        self.set(bit, true);
+        self.used += 1;
+        self.next = if bit + 1 == size { 0 } else { bit + 1 };
+        Some(self.range.min + bit)
+    }
+
+    /// Allocates `id` itself, failing if it is out of range or already used.
+    pub fn alloc_at(&mut self, id: u32) -> bool {
+        if !self.range.contains(id) || self.test(id - self.range.min) {
+            return false;
+        }
+        self.set(id - self.range.min, true);
+        self.used += 1;
+        true
+    }
+
+    /// Allocates `count` consecutive IDs, the first of which is a multiple of `align` from the
+    /// start of the range, and returns the first one.
+    ///
+    /// `align` must be a power of two; 0 is taken as 1. The search starts at the beginning of the
+    /// range, so that blocks stay packed.
+    pub fn alloc_block(&mut self, count: u32, align: u32) -> Option<u32> {
+        let align = align.max(1);
+        if count == 0 || !align.is_power_of_two() {
+            return None;
+        }
+        let size = self.range.size();
+        let mut start = 0;
+        while count <= size && start <= size - count {
+            match (start..start + count).find(|&bit| self.test(bit)) {
+                // Restart after the used ID, rounded up to the alignment.
+                Some(used) => start = (used + 1 + align - 1) & !(align - 1),
+                None => {
+                    for bit in start..start + count {
+                        self.set(bit, true);
+                    }
+                    self.used += count;
+                    return Some(self.range.min + start);
+                }
+            }
+        }
+        None
+    }
+
+    /// Reserves the IDs of `range` that are free, so that they are never allocated.
+    ///
+    /// Returns `false`, reserving nothing, if `range` is not within the allocator's range.
+    pub fn reserve(&mut self, range: IndexRange) -> bool {
+        if range.max < range.min
+            || !self.range.contains(range.min)
+            || !self.range.contains(range.max)
+        {
+            return false;
+        }
+        for id in range.min..=range.max {
+            let bit = id - self.range.min;
+            if !self.test(bit) {
+                self.set(bit, true);
+                self.used += 1;
+            }
+        }
+        true
+    }
+
+    /// Frees `id`, which was allocated or reserved. Returns `false` if it was not.
+    pub fn free(&mut self, id: u32) -> bool {
+        if !self.is_used(id) {
+            return false;
+        }
+        self.set(id - self.range.min, false);
+        self.used -= 1;
+        true
+    }
+
+    /// Frees the `count` IDs starting at `first`, e.g. a block from [`IdAllocator::alloc_block`].
+    ///
+    /// Returns the number of IDs that were actually used.
+    pub fn free_block(&mut self, first: u32, count: u32) -> u32 {
+        (first..first.saturating_add(count))
+            .filter(|&id| self.free(id))
+            .count() as u32
+    }
+
+    fn test(&self, bit: u32) -> bool {
+        self.words.as_ref()[bit as usize / 64] & 1 << (bit % 64) != 0
+    }
+
+    fn set(&mut self, bit: u32, used: bool) {
+        let word = &mut self.words.as_mut()[bit as usize / 64];
+        if used {
+            *word |= 1 << (bit % 64);
+        } else {
+            *word &= !(1 << (bit % 64));
+        }
+    }
+
+    /// Returns the first free bit in `from..to`.
+    fn find_zero(&self, from: u32, to: u32) -> Option<u32> {
+        let words = self.words.as_ref();
+        let mut bit = from;
+        while bit < to {
+            // Skip the bits below `bit` in its word, then whole words at once.
+            let free = !words[bit as usize / 64] & (u64::MAX << (bit % 64));
+            if free != 0 {
+                let found = bit - bit % 64 + free.trailing_zeros();
+                return if found < to { Some(found) } else { None };
+            }
+            bit = bit - bit % 64 + 64;
+        }
+        None
+    }
+}
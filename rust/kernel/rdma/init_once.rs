@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Deferred initialisation that runs at most once successfully.
+//!
+//! Some setup is only worth doing once it is needed, e.g. claiming the RoCEv2 UDP port when the
+//! first soft-RoCE link is created rather than when the module is loaded. [`InitOnce`] lets any
+//! number of callers race to trigger it: one runs the initialiser while the others wait for its
+//! outcome, and a failed attempt is retried by the next caller.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const IDLE: u8 = 0;
+const RUNNING: u8 = 1;
+const DONE: u8 = 2;
+
+/// Tracks whether a piece of deferred initialisation has been done.
+#[derive(Debug, Default)]
+pub struct InitOnce(AtomicU8);
+
+impl InitOnce {
+    /// Creates a tracker with nothing initialised yet.
+    pub const fn new() -> Self {
+        Self(AtomicU8::new(IDLE))
+    }
+
+    /// Returns `true` once an initialiser succeeded.
+    pub fn is_done(&self) -> bool {
+        self.0.load(Ordering::Acquire) == DONE
+    }
+
+    /// Runs `init` unless a previous call already succeeded.
+    ///
+    /// If another caller is running its initialiser, `relax` is called until it is finished; the
+    /// outcome is then shared when it succeeded, and `init` is tried again otherwise. Callers must
+    /// be able to wait, as the other initialiser may sleep.
+    pub fn call<E>(
+        &self,
+        init: impl FnOnce() -> Result<(), E>,
+        mut relax: impl FnMut(),
+    ) -> Result<(), E> {
+        loop {
+            match self
+                .0
+                .compare_exchange(IDLE, RUNNING, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(DONE) => return Ok(()),
+                Err(_) => relax(),
+            }
+        }
+        let ret = init();
+        let state = if ret.is_ok() { DONE } else { IDLE };
+        self.0.store(state, Ordering::Release);
+        ret
+    }
+
+    /// Forgets a successful initialisation, once the caller tore down what it set up.
+    ///
+    /// The caller must make sure no [`InitOnce::call`] runs concurrently.
+    pub fn reset(&self) {
+        self.0.store(IDLE, Ordering::Release);
+    }
+}
@@ -0,0 +1,336 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Source address filtering.
+//!
+//! A [`PrefixList`] is the optional allowlist of peer prefixes of one device. While it is empty
+//! every peer is accepted; once a prefix is added, packets from addresses outside all the
+//! prefixes are rejected. The list is a plain value: the soft-RoCE devices publish it under RCU,
+//! see `kernel::rxe::SourceFilter`, so that the packet path never waits for a writer.
+
+use core::fmt;
+
+/// An IPv4 or IPv6 address, IPv4 being stored as an IPv4-mapped IPv6 address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PeerAddr([u8; 16]);
+
+impl PeerAddr {
+    /// Creates an IPv4 address.
+    pub const fn from_v4(octets: [u8; 4]) -> Self {
+        let mut addr = [0; 16];
+        addr[10] = 0xff;
+        addr[11] = 0xff;
+        addr[12] = octets[0];
+        addr[13] = octets[1];
+        addr[14] = octets[2];
+        addr[15] = octets[3];
+        Self(addr)
+    }
+
+    /// Creates an IPv6 address.
+    pub const fn from_v6(octets: [u8; 16]) -> Self {
+        Self(octets)
+    }
+
+    /// Returns the IPv6 representation.
+    pub const fn octets(&self) -> [u8; 16] {
+        self.0
+    }
+
+    /// Returns `true` for IPv4 addresses.
+    pub fn is_v4(&self) -> bool {
+        self.0[..10] == [0; 10] && self.0[10] == 0xff && self.0[11] == 0xff
+    }
+
+    fn to_bits(self) -> u128 {
+        u128::from_be_bytes(self.0)
+    }
+
+    fn from_bits(bits: u128) -> Self {
+        Self(bits.to_be_bytes())
+    }
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_v4() {
+            let o = &self.0[12..];
+            return write!(f, "{}.{}.{}.{}", o[0], o[1], o[2], o[3]);
+        }
+        for (i, group) in self.0.chunks(2).enumerate() {
+            if i != 0 {
+                f.write_str(":")?;
+            }
+            write!(f, "{:x}", u16::from_be_bytes([group[0], group[1]]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when parsing an address or prefix fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseError;
+
+fn parse_v4(s: &str) -> Result<[u8; 4], ParseError> {
+    let mut octets = [0; 4];
+    let mut parts = s.split('.');
+    for octet in octets.iter_mut() {
+        *octet = parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or(ParseError)?;
+    }
+    if parts.next().is_some() {
+        return Err(ParseError);
+    }
+    Ok(octets)
+}
+
+fn parse_groups(s: &str, groups: &mut [u16]) -> Result<usize, ParseError> {
+    if s.is_empty() {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for part in s.split(':') {
+        if count == groups.len() || part.is_empty() || part.len() > 4 {
+            return Err(ParseError);
+        }
+        groups[count] = u16::from_str_radix(part, 16).map_err(|_| ParseError)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn parse_v6(s: &str) -> Result<[u8; 16], ParseError> {
+    let mut groups = [0u16; 8];
+    match s.find("::") {
+        Some(pos) => {
+            let mut tail = [0u16; 8];
+            let head = parse_groups(&s[..pos], &mut groups)?;
+            let tail_len = parse_groups(&s[pos + 2..], &mut tail)?;
+            if head + tail_len > 7 {
+                return Err(ParseError);
+            }
+            groups[8 - tail_len..].copy_from_slice(&tail[..tail_len]);
+        }
+        None => {
+            if parse_groups(s, &mut groups)? != 8 {
+                return Err(ParseError);
+            }
+        }
+    }
+    let mut octets = [0; 16];
+    for (i, group) in groups.iter().enumerate() {
+        octets[2 * i..2 * i + 2].copy_from_slice(&group.to_be_bytes());
+    }
+    Ok(octets)
+}
+
+impl core::str::FromStr for PeerAddr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        if s.contains(':') {
+            parse_v6(s).map(Self::from_v6)
+        } else {
+            parse_v4(s).map(Self::from_v4)
+        }
+    }
+}
+
+/// An address prefix, e.g. `10.0.0.0/8` or `fd00::/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpPrefix {
+    addr: PeerAddr,
+    // Length over the IPv6 representation, i.e. 96 more than the IPv4 length.
+    len: u8,
+}
+
+fn prefix_mask(len: u8) -> u128 {
+    match len {
+        0 => 0,
+        len => u128::MAX << (128 - len as u32),
+    }
+}
+
+impl IpPrefix {
+    /// Creates a prefix of `len` bits, which is counted on the IPv4 address for IPv4 prefixes.
+    ///
+    /// Host bits of `addr` are cleared. Returns `None` if `len` is too long.
+    pub fn new(addr: PeerAddr, len: u8) -> Option<Self> {
+        let len = if addr.is_v4() {
+            if len > 32 {
+                return None;
+            }
+            len + 96
+        } else {
+            if len > 128 {
+                return None;
+            }
+            len
+        };
+        Some(Self {
+            addr: PeerAddr::from_bits(addr.to_bits() & prefix_mask(len)),
+            len,
+        })
+    }
+
+    /// Returns the network address.
+    pub fn addr(&self) -> PeerAddr {
+        self.addr
+    }
+
+    /// Returns the prefix length, counted on the IPv4 address for IPv4 prefixes.
+    pub fn len(&self) -> u8 {
+        if self.addr.is_v4() && self.len >= 96 {
+            self.len - 96
+        } else {
+            self.len
+        }
+    }
+
+    /// Returns `true` for a zero-length prefix, which matches every address of its family.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if `addr` is inside the prefix.
+    pub fn contains(&self, addr: PeerAddr) -> bool {
+        (addr.to_bits() ^ self.addr.to_bits()) & prefix_mask(self.len) == 0
+    }
+}
+
+impl fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len())
+    }
+}
+
+impl core::str::FromStr for IpPrefix {
+    type Err = ParseError;
+
+    /// Parses `addr/len`, or a bare address meaning a host prefix.
+    fn from_str(s: &str) -> Result<Self, ParseError> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len.parse::<u8>().map_err(|_| ParseError)?)),
+            None => (s, None),
+        };
+        let addr: PeerAddr = addr.parse()?;
+        let len = len.unwrap_or(if addr.is_v4() { 32 } else { 128 });
+        Self::new(addr, len).ok_or(ParseError)
+    }
+}
+
+/// Error returned when changing a [`PrefixList`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterError {
+    /// The allowlist has no room left.
+    Full,
+    /// The text could not be parsed.
+    Parse,
+}
+
+impl From<ParseError> for FilterError {
+    fn from(_: ParseError) -> Self {
+        FilterError::Parse
+    }
+}
+
+/// Allowlist of up to `N` peer prefixes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrefixList<const N: usize> {
+    prefixes: [IpPrefix; N],
+    len: usize,
+}
+
+impl<const N: usize> PrefixList<N> {
+    /// Creates an empty list, which accepts every peer.
+    pub const fn new() -> Self {
+        const UNUSED: IpPrefix = IpPrefix {
+            addr: PeerAddr([0; 16]),
+            len: 0,
+        };
+        Self {
+            prefixes: [UNUSED; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the number of prefixes in the list.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the list holds no prefix, i.e. is not in force.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an iterator over the prefixes, in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = &IpPrefix> + '_ {
+        self.prefixes[..self.len].iter()
+    }
+
+    /// Adds `prefix` to the list; adding a prefix that is already present does nothing.
+    pub fn add(&mut self, prefix: IpPrefix) -> Result<(), FilterError> {
+        if self.iter().any(|p| *p == prefix) {
+            return Ok(());
+        }
+        if self.len == N {
+            return Err(FilterError::Full);
+        }
+        self.prefixes[self.len] = prefix;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes `prefix` from the list, returns `false` if it was not present.
+    pub fn remove(&mut self, prefix: IpPrefix) -> bool {
+        let found = self.iter().position(|p| *p == prefix);
+        if let Some(i) = found {
+            self.prefixes.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+        found.is_some()
+    }
+
+    /// Removes every prefix, accepting all peers again.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Replaces the list with the prefixes in `text`, separated by whitespace or commas.
+    ///
+    /// An empty text clears the list. Nothing is changed if any prefix fails to parse or the
+    /// prefixes do not fit.
+    pub fn store(&mut self, text: &str) -> Result<(), FilterError> {
+        let mut list = Self::new();
+        for prefix in text
+            .split(|c: char| c == ',' || c.is_ascii_whitespace())
+            .filter(|s| !s.is_empty())
+        {
+            list.add(prefix.parse()?)?;
+        }
+        *self = list;
+        Ok(())
+    }
+
+    /// Writes the list, one prefix per line, e.g. for a sysfs `show` callback.
+    pub fn show(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        for prefix in self.iter() {
+            writeln!(w, "{}", prefix)?;
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if packets from `addr` are accepted: the list is empty or one of its
+    /// prefixes contains `addr`.
+    pub fn permits(&self, addr: PeerAddr) -> bool {
+        self.is_empty() || self.iter().any(|p| p.contains(addr))
+    }
+}
+
+impl<const N: usize> Default for PrefixList<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,243 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Per-link overrides of the device defaults.
+//!
+//! `rdma link add NAME type rxe netdev DEV` creates a device with the defaults of the provider:
+//! ib_core passes nothing but the name and the net device to `newlink`. To create devices with
+//! different settings without reloading the module, soft transports also answer a generic netlink
+//! `newlink` command whose optional attributes, see [`attr`], override the defaults of the device
+//! it creates. A [`LinkParams`] holds the overrides of one device.
+//!
+//! Every override is optional; what is not overridden keeps the provider's default.
+
+use core::fmt;
+
+use super::dev_config::{ConfigKey, DeviceConfig};
+use super::tunables::TuneError;
+
+/// Longest device name, `IB_DEVICE_NAME_MAX` without the NUL.
+pub const MAX_NAME_LEN: usize = 63;
+
+/// Attributes of the generic netlink `newlink` command.
+///
+/// Integers are in host byte order, like those of the other netlink families.
+pub mod attr {
+    /// Name of the device to create, a NUL-terminated string of at most
+    /// [`super::MAX_NAME_LEN`] bytes. Required.
+    pub const IBDEV_NAME: u16 = 1;
+    /// Name of the network device to bind the device to, a NUL-terminated string. Required.
+    pub const NDEV_NAME: u16 = 2;
+    /// [`super::LinkParams::udp_port`], a `u16`.
+    pub const UDP_PORT: u16 = 3;
+    /// [`super::LinkParams::max_qp`], a `u32`.
+    pub const MAX_QP: u16 = 4;
+    /// [`super::LinkParams::max_qp_wr`], a `u32`.
+    pub const MAX_QP_WR: u16 = 5;
+    /// [`super::LinkParams::max_cq`], a `u32`.
+    pub const MAX_CQ: u16 = 6;
+    /// [`super::LinkParams::max_mr`], a `u32`.
+    pub const MAX_MR: u16 = 7;
+    /// Data path settings, a NUL-terminated list of `key=value` with the keys of
+    /// [`super::ConfigKey`], e.g. `crc_mode=off,mtu_override=1024`.
+    pub const CONFIG: u16 = 8;
+    /// Highest attribute type.
+    pub const MAX: u16 = CONFIG;
+}
+
+/// Error returned when parsing overrides.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkParamError {
+    /// The key is not a known override.
+    UnknownKey,
+    /// The value does not parse, or the text is not a list of `key=value`.
+    Parse,
+    /// The value is out of range, e.g. 0 for a limit.
+    OutOfRange,
+}
+
+impl From<TuneError> for LinkParamError {
+    fn from(e: TuneError) -> Self {
+        match e {
+            TuneError::Parse => Self::Parse,
+            TuneError::OutOfRange => Self::OutOfRange,
+        }
+    }
+}
+
+/// Overrides of the defaults of one device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkParams {
+    /// UDP destination port of the packets the device sends, `udp_port`.
+    pub udp_port: Option<u16>,
+    /// Maximum number of QPs, `max_qp`.
+    pub max_qp: Option<u32>,
+    /// Maximum number of work requests per queue, `max_qp_wr`.
+    pub max_qp_wr: Option<u32>,
+    /// Maximum number of CQs, `max_cq`.
+    pub max_cq: Option<u32>,
+    /// Maximum number of MRs, `max_mr`.
+    pub max_mr: Option<u32>,
+    /// Data path settings, changed with the keys of [`ConfigKey`].
+    pub config: DeviceConfig,
+}
+
+impl LinkParams {
+    /// No override.
+    pub const DEFAULT: Self = Self {
+        udp_port: None,
+        max_qp: None,
+        max_qp_wr: None,
+        max_cq: None,
+        max_mr: None,
+        config: DeviceConfig::DEFAULT,
+    };
+
+    /// Keys of the limits, in the order they are shown.
+    const LIMITS: [&'static str; 4] = ["max_qp", "max_qp_wr", "max_cq", "max_mr"];
+
+    fn limits(&self) -> [Option<u32>; 4] {
+        [self.max_qp, self.max_qp_wr, self.max_cq, self.max_mr]
+    }
+
+    fn limit(&mut self, key: &str) -> Option<&mut Option<u32>> {
+        match key {
+            "max_qp" => Some(&mut self.max_qp),
+            "max_qp_wr" => Some(&mut self.max_qp_wr),
+            "max_cq" => Some(&mut self.max_cq),
+            "max_mr" => Some(&mut self.max_mr),
+            _ => None,
+        }
+    }
+
+    fn limit_attr(&mut self, ty: u16) -> Option<&mut Option<u32>> {
+        match ty {
+            attr::MAX_QP => Some(&mut self.max_qp),
+            attr::MAX_QP_WR => Some(&mut self.max_qp_wr),
+            attr::MAX_CQ => Some(&mut self.max_cq),
+            attr::MAX_MR => Some(&mut self.max_mr),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy with the override `key` set to `value`.
+    pub fn with(&self, key: &str, value: &str) -> Result<Self, LinkParamError> {
+        let mut params = *self;
+        if key == "udp_port" {
+            let port: u16 = value.parse().map_err(|_| LinkParamError::Parse)?;
+            if port == 0 {
+                return Err(LinkParamError::OutOfRange);
+            }
+            params.udp_port = Some(port);
+        } else if let Some(limit) = params.limit(key) {
+            let value: u32 = value.parse().map_err(|_| LinkParamError::Parse)?;
+            if value == 0 {
+                return Err(LinkParamError::OutOfRange);
+            }
+            *limit = Some(value);
+        } else {
+            let key = ConfigKey::from_name(key).ok_or(LinkParamError::UnknownKey)?;
+            params.config = params.config.with(key, value)?;
+        }
+        Ok(params)
+    }
+
+    /// Returns a copy with the override carried by the `newlink` attribute of type `ty`, whose
+    /// payload is `payload`, see [`attr`].
+    ///
+    /// The names are not overrides: they fail with [`LinkParamError::UnknownKey`], like unknown
+    /// types. A zero port or limit fails with [`LinkParamError::OutOfRange`].
+    pub fn with_attr(&self, ty: u16, payload: &[u8]) -> Result<Self, LinkParamError> {
+        let mut params = *self;
+        if ty == attr::UDP_PORT {
+            let port = u16::from_ne_bytes(payload.try_into().map_err(|_| LinkParamError::Parse)?);
+            if port == 0 {
+                return Err(LinkParamError::OutOfRange);
+            }
+            params.udp_port = Some(port);
+        } else if let Some(limit) = params.limit_attr(ty) {
+            let value = u32::from_ne_bytes(payload.try_into().map_err(|_| LinkParamError::Parse)?);
+            if value == 0 {
+                return Err(LinkParamError::OutOfRange);
+            }
+            *limit = Some(value);
+        } else if ty == attr::CONFIG {
+            let text = payload.split(|&b| b == 0).next().unwrap_or_default();
+            let text = core::str::from_utf8(text).map_err(|_| LinkParamError::Parse)?;
+            for item in items(text) {
+                let (key, value) = item?;
+                let key = ConfigKey::from_name(key).ok_or(LinkParamError::UnknownKey)?;
+                params.config = params.config.with(key, value)?;
+            }
+        } else {
+            return Err(LinkParamError::UnknownKey);
+        }
+        Ok(params)
+    }
+
+    /// Parses a list of `key=value` separated by spaces or commas, e.g.
+    /// `max_qp=256,crc_mode=off`.
+    pub fn parse(text: &str) -> Result<Self, LinkParamError> {
+        let mut params = Self::DEFAULT;
+        for item in items(text) {
+            let (key, value) = item?;
+            params = params.with(key, value)?;
+        }
+        Ok(params)
+    }
+
+    /// Returns `true` if nothing is overridden.
+    pub fn is_default(&self) -> bool {
+        *self == Self::DEFAULT
+    }
+}
+
+impl Default for LinkParams {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl fmt::Display for LinkParams {
+    /// Writes the overrides in the syntax of [`LinkParams::parse`], space separated.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        if let Some(port) = self.udp_port {
+            write!(f, "udp_port={}", port)?;
+            sep = " ";
+        }
+        for (key, limit) in Self::LIMITS.into_iter().zip(self.limits()) {
+            if let Some(value) = limit {
+                write!(f, "{}{}={}", sep, key, value)?;
+                sep = " ";
+            }
+        }
+        let default = DeviceConfig::DEFAULT;
+        if self.config.mtu_override != default.mtu_override {
+            write!(
+                f,
+                "{}{}={}",
+                sep,
+                ConfigKey::Mtu.name(),
+                self.config.mtu_override
+            )?;
+            sep = " ";
+        }
+        if self.config.crc_mode != default.crc_mode {
+            let mode = self.config.crc_mode.name();
+            write!(f, "{}{}={}", sep, ConfigKey::CrcMode.name(), mode)?;
+            sep = " ";
+        }
+        if self.config.pacing_rate != default.pacing_rate {
+            let rate = self.config.pacing_rate;
+            write!(f, "{}{}={}", sep, ConfigKey::PacingRate.name(), rate)?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a list of `key=value` separated by spaces or commas.
+fn items(text: &str) -> impl Iterator<Item = Result<(&str, &str), LinkParamError>> {
+    text.split(|c: char| c == ',' || c.is_ascii_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.split_once('=').ok_or(LinkParamError::Parse))
+}
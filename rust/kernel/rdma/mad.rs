@@ -0,0 +1,489 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Management datagram headers.
+//!
+//! MADs are the 256-byte messages of the InfiniBand management classes, carried on QP0 for subnet
+//! management (SMPs) and on QP1 for the general services (GMPs). They all start with the common
+//! header read and written by [`MadHdr`]; SMPs add an M_Key and the directed route fields handled
+//! by [`SmpHdr`]. Every field is in network byte order.
+//!
+//! The classes whose messages do not fit in one MAD, subnet administration and the vendor
+//! classes, split them with the reliable multi-packet protocol: every segment carries the
+//! [`RmppHdr`] that follows the common header.
+
+use core::ops::BitOr;
+
+/// Size of a MAD, headers included.
+pub const MAD_SIZE: usize = 256;
+
+/// Size of the common MAD header.
+pub const MAD_HDR_SIZE: usize = 24;
+
+/// Offset of the attribute data of an SMP.
+pub const SMP_DATA_OFFSET: usize = 64;
+
+/// Size of the attribute data of an SMP.
+pub const SMP_DATA_SIZE: usize = 64;
+
+/// Base version of the MADs we understand.
+pub const BASE_VERSION: u8 = 1;
+
+/// Size of the global route header of MADs received with one.
+pub const GRH_SIZE: usize = 40;
+
+/// Offset of the RMPP header.
+pub const RMPP_HDR_OFFSET: usize = MAD_HDR_SIZE;
+
+/// Size of the common and RMPP headers, `IB_MGMT_RMPP_HDR`.
+pub const RMPP_HDR_SIZE: usize = 36;
+
+/// Version of RMPP we understand, `IB_MGMT_RMPP_VERSION`.
+pub const RMPP_VERSION: u8 = 1;
+
+/// Management classes, `IB_MGMT_CLASS_*`.
+pub mod class {
+    /// Subnet management, LID routed.
+    pub const SUBN_LID_ROUTED: u8 = 0x01;
+    /// Subnet administration.
+    pub const SUBN_ADM: u8 = 0x03;
+    /// Performance management.
+    pub const PERF_MGMT: u8 = 0x04;
+    /// Baseboard management.
+    pub const BM: u8 = 0x05;
+    /// Device management.
+    pub const DEVICE_MGMT: u8 = 0x06;
+    /// Communication management.
+    pub const CM: u8 = 0x07;
+    /// Subnet management, directed route.
+    pub const SUBN_DIRECTED_ROUTE: u8 = 0x81;
+}
+
+/// Methods, `IB_MGMT_METHOD_*`.
+pub mod method {
+    /// Reads an attribute.
+    pub const GET: u8 = 0x01;
+    /// Writes an attribute.
+    pub const SET: u8 = 0x02;
+    /// Sends a message, e.g. of the connection managers.
+    pub const SEND: u8 = 0x03;
+    /// Reports an event to the manager.
+    pub const TRAP: u8 = 0x05;
+    /// Forwards an event to a subscriber.
+    pub const REPORT: u8 = 0x06;
+    /// Answers a `GET` or a `SET`.
+    pub const GET_RESP: u8 = 0x81;
+    /// Acknowledges a `TRAP`.
+    pub const TRAP_REPRESS: u8 = 0x07;
+    /// Set in the method of every response.
+    pub const RESP: u8 = 0x80;
+}
+
+/// RMPP packet types, `IB_MGMT_RMPP_TYPE_*`.
+pub mod rmpp_type {
+    /// A data segment.
+    pub const DATA: u8 = 1;
+    /// Acknowledges the segments received so far and opens the window.
+    pub const ACK: u8 = 2;
+    /// Stops the transfer, on the receiver's side.
+    pub const STOP: u8 = 3;
+    /// Aborts the transfer, on the sender's side.
+    pub const ABORT: u8 = 4;
+}
+
+/// Status code of an unsupported class version.
+pub const STATUS_BAD_VERSION: u16 = 0x0004;
+
+/// Status code of an unsupported method.
+pub const STATUS_UNSUPPORTED_METHOD: u16 = 0x0008;
+
+/// Status code of an unsupported method and attribute combination.
+pub const STATUS_UNSUPPORTED_METHOD_ATTRIB: u16 = 0x000c;
+
+/// Status code of an invalid attribute or attribute modifier.
+pub const STATUS_INVALID_ATTRIB_VALUE: u16 = 0x001c;
+
+fn get<const N: usize>(buf: &[u8], offset: usize) -> [u8; N] {
+    let mut bytes = [0; N];
+    bytes.copy_from_slice(&buf[offset..offset + N]);
+    bytes
+}
+
+fn put(buf: &mut [u8], offset: usize, bytes: &[u8]) {
+    buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// The common header of a MAD, `struct ib_mad_hdr`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MadHdr {
+    /// Version of the MAD base format.
+    pub base_version: u8,
+    /// Management class, see [`class`].
+    pub mgmt_class: u8,
+    /// Version of the management class.
+    pub class_version: u8,
+    /// Method, see [`method`].
+    pub method: u8,
+    /// Status of a response.
+    pub status: u16,
+    /// Class specific field, the hop pointer and count of directed route SMPs.
+    pub class_specific: u16,
+    /// Transaction ID, echoed by responses.
+    pub tid: u64,
+    /// Attribute ID.
+    pub attr_id: u16,
+    /// Attribute modifier.
+    pub attr_mod: u32,
+}
+
+impl MadHdr {
+    /// Returns a request header, with the current base version and null fields elsewhere.
+    pub const fn new(mgmt_class: u8, class_version: u8, method: u8, tid: u64) -> Self {
+        Self {
+            base_version: BASE_VERSION,
+            mgmt_class,
+            class_version,
+            method,
+            status: 0,
+            class_specific: 0,
+            tid,
+            attr_id: 0,
+            attr_mod: 0,
+        }
+    }
+
+    /// Sets the attribute ID and modifier.
+    pub const fn with_attr(mut self, attr_id: u16, attr_mod: u32) -> Self {
+        self.attr_id = attr_id;
+        self.attr_mod = attr_mod;
+        self
+    }
+
+    /// Reads the header at the start of `buf`, `None` if it is too short.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < MAD_HDR_SIZE {
+            return None;
+        }
+        Some(Self {
+            base_version: buf[0],
+            mgmt_class: buf[1],
+            class_version: buf[2],
+            method: buf[3],
+            status: u16::from_be_bytes(get(buf, 4)),
+            class_specific: u16::from_be_bytes(get(buf, 6)),
+            tid: u64::from_be_bytes(get(buf, 8)),
+            attr_id: u16::from_be_bytes(get(buf, 16)),
+            attr_mod: u32::from_be_bytes(get(buf, 20)),
+        })
+    }
+
+    /// Writes the header at the start of `buf`; returns `false` if it is too short.
+    ///
+    /// The reserved field is cleared.
+    pub fn write(&self, buf: &mut [u8]) -> bool {
+        if buf.len() < MAD_HDR_SIZE {
+            return false;
+        }
+        buf[0] = self.base_version;
+        buf[1] = self.mgmt_class;
+        buf[2] = self.class_version;
+        buf[3] = self.method;
+        put(buf, 4, &self.status.to_be_bytes());
+        put(buf, 6, &self.class_specific.to_be_bytes());
+        put(buf, 8, &self.tid.to_be_bytes());
+        put(buf, 16, &self.attr_id.to_be_bytes());
+        put(buf, 18, &[0, 0]);
+        put(buf, 20, &self.attr_mod.to_be_bytes());
+        true
+    }
+
+    /// Returns `true` for subnet management packets, which go to QP0.
+    pub const fn is_smp(&self) -> bool {
+        matches!(
+            self.mgmt_class,
+            class::SUBN_LID_ROUTED | class::SUBN_DIRECTED_ROUTE
+        )
+    }
+
+    /// Returns `true` for responses.
+    pub const fn is_response(&self) -> bool {
+        self.method & method::RESP != 0
+    }
+
+    /// Returns the header of the response to this request with `status`.
+    ///
+    /// `TRAP` is answered with `TRAP_REPRESS` and the other methods with `GET_RESP`; the
+    /// transaction ID and the attribute are kept.
+    pub const fn response(&self, status: u16) -> Self {
+        let mut resp = *self;
+        resp.method = match self.method {
+            method::TRAP => method::TRAP_REPRESS,
+            _ => method::GET_RESP,
+        };
+        resp.status = status;
+        resp
+    }
+}
+
+/// The header of an SMP, `struct ib_smp` without the data and paths.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SmpHdr {
+    /// The common header.
+    pub mad: MadHdr,
+    /// Management key.
+    pub mkey: u64,
+    /// Directed route source LID.
+    pub dr_slid: u16,
+    /// Directed route destination LID.
+    pub dr_dlid: u16,
+}
+
+impl SmpHdr {
+    /// Directed route direction bit, in the status field.
+    pub const DIRECTION: u16 = 0x8000;
+
+    /// Reads the header of the SMP in `buf`, `None` if it is too short or not an SMP.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let mad = MadHdr::parse(buf)?;
+        if !mad.is_smp() || buf.len() < SMP_DATA_OFFSET {
+            return None;
+        }
+        Some(Self {
+            mad,
+            mkey: u64::from_be_bytes(get(buf, 24)),
+            dr_slid: u16::from_be_bytes(get(buf, 32)),
+            dr_dlid: u16::from_be_bytes(get(buf, 34)),
+        })
+    }
+
+    /// Writes the header at the start of `buf`; returns `false` if it is too short.
+    pub fn write(&self, buf: &mut [u8]) -> bool {
+        if buf.len() < SMP_DATA_OFFSET || !self.mad.write(buf) {
+            return false;
+        }
+        put(buf, 24, &self.mkey.to_be_bytes());
+        put(buf, 32, &self.dr_slid.to_be_bytes());
+        put(buf, 34, &self.dr_dlid.to_be_bytes());
+        true
+    }
+
+    /// Returns `true` if the SMP is directed route.
+    pub const fn is_directed_route(&self) -> bool {
+        self.mad.mgmt_class == class::SUBN_DIRECTED_ROUTE
+    }
+
+    /// Returns the hop pointer of a directed route SMP.
+    pub const fn hop_ptr(&self) -> u8 {
+        (self.mad.class_specific >> 8) as u8
+    }
+
+    /// Returns the hop count of a directed route SMP.
+    pub const fn hop_cnt(&self) -> u8 {
+        self.mad.class_specific as u8
+    }
+
+    /// Returns `true` if the SMP travels back to its requester.
+    pub const fn is_returning(&self) -> bool {
+        self.mad.status & Self::DIRECTION != 0
+    }
+
+    /// Returns the status, without the direction bit of directed route SMPs.
+    pub const fn status(&self) -> u16 {
+        if self.is_directed_route() {
+            self.mad.status & !Self::DIRECTION
+        } else {
+            self.mad.status
+        }
+    }
+
+    /// Returns the header of the response to this SMP with `status`.
+    ///
+    /// Directed route responses travel back, so the direction bit is set.
+    pub const fn response(&self, status: u16) -> Self {
+        let mut resp = *self;
+        resp.mad = self.mad.response(status);
+        if self.is_directed_route() {
+            resp.mad.status |= Self::DIRECTION;
+        }
+        resp
+    }
+}
+
+/// Flags of an RMPP header, `IB_MGMT_RMPP_FLAG_*`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RmppFlags(u8);
+
+impl RmppFlags {
+    /// No flag: the MAD is not part of an RMPP transfer.
+    pub const NONE: Self = Self(0);
+    /// The MAD is part of an RMPP transfer, the other fields are valid.
+    pub const ACTIVE: Self = Self(1 << 0);
+    /// First segment of the transfer.
+    pub const FIRST: Self = Self(1 << 1);
+    /// Last segment of the transfer.
+    pub const LAST: Self = Self(1 << 2);
+
+    /// Returns the flags of `bits`, ignoring the unknown ones.
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & 0x7)
+    }
+
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the union of `self` and `other`.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns `true` if every flag of `other` is set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for RmppFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+/// The RMPP header, `struct ib_rmpp_hdr`, following the common header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RmppHdr {
+    /// Version of RMPP.
+    pub version: u8,
+    /// Packet type, see [`rmpp_type`].
+    pub rmpp_type: u8,
+    /// Response time exponent, the low 5 bits.
+    pub rtime: u8,
+    /// Flags.
+    pub flags: RmppFlags,
+    /// Status of a STOP or an ABORT.
+    pub status: u8,
+    /// Segment number of a DATA, last segment received of an ACK.
+    pub seg_num: u32,
+    /// Payload length of the first and last DATA segments, new window last of an ACK.
+    pub paylen_newwin: u32,
+}
+
+impl RmppHdr {
+    /// The header of MADs sent outside of RMPP transfers.
+    pub const INACTIVE: Self = Self {
+        version: 0,
+        rmpp_type: 0,
+        rtime: 0,
+        flags: RmppFlags::NONE,
+        status: 0,
+        seg_num: 0,
+        paylen_newwin: 0,
+    };
+
+    /// Returns the header of the DATA segment `seg_num` of a transfer of `paylen` bytes, out of
+    /// `segments`, whose last segment holds `last_len` of them.
+    ///
+    /// The payload length is only carried by the first segment, which gets `paylen`, and the last
+    /// one, which gets `last_len`; a transfer of a single segment gets `paylen`.
+    pub const fn data(seg_num: u32, segments: u32, paylen: u32, last_len: u32) -> Self {
+        let mut flags = RmppFlags::ACTIVE;
+        if seg_num == 1 {
+            flags = flags.union(RmppFlags::FIRST);
+        }
+        if seg_num == segments {
+            flags = flags.union(RmppFlags::LAST);
+        }
+        Self {
+            version: RMPP_VERSION,
+            rmpp_type: rmpp_type::DATA,
+            rtime: 0,
+            flags,
+            status: 0,
+            seg_num,
+            paylen_newwin: if seg_num == 1 {
+                paylen
+            } else if seg_num == segments {
+                last_len
+            } else {
+                0
+            },
+        }
+    }
+
+    /// Reads the RMPP header of the MAD in `buf`, `None` if it is too short.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < RMPP_HDR_SIZE {
+            return None;
+        }
+        let hdr = &buf[RMPP_HDR_OFFSET..];
+        Some(Self {
+            version: hdr[0],
+            rmpp_type: hdr[1],
+            rtime: hdr[2] >> 3,
+            flags: RmppFlags::from_bits(hdr[2]),
+            status: hdr[3],
+            seg_num: u32::from_be_bytes(get(hdr, 4)),
+            paylen_newwin: u32::from_be_bytes(get(hdr, 8)),
+        })
+    }
+
+    /// Writes the RMPP header of the MAD in `buf`; returns `false` if it is too short.
+    pub fn write(&self, buf: &mut [u8]) -> bool {
+        if buf.len() < RMPP_HDR_SIZE {
+            return false;
+        }
+        let hdr = &mut buf[RMPP_HDR_OFFSET..];
+        hdr[0] = self.version;
+        hdr[1] = self.rmpp_type;
+        hdr[2] = (self.rtime << 3) | self.flags.bits();
+        hdr[3] = self.status;
+        put(hdr, 4, &self.seg_num.to_be_bytes());
+        put(hdr, 8, &self.paylen_newwin.to_be_bytes());
+        true
+    }
+
+    /// Returns `true` if the MAD is part of an RMPP transfer.
+    pub const fn is_active(&self) -> bool {
+        self.flags.contains(RmppFlags::ACTIVE)
+    }
+}
+
+/// What `process_mad` did with a MAD, `IB_MAD_RESULT_*`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MadResult(u32);
+
+impl MadResult {
+    /// The MAD was not handled, `IB_MAD_RESULT_FAILURE`.
+    pub const FAILURE: Self = Self(0);
+    /// The MAD was handled.
+    pub const SUCCESS: Self = Self(1 << 0);
+    /// The output MAD holds a reply to send.
+    pub const REPLY: Self = Self(1 << 1);
+    /// The MAD must not be passed to the agents.
+    pub const CONSUMED: Self = Self(1 << 2);
+
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the union of `self` and `other`.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns `true` if every flag of `other` is set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for MadResult {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Multicast group membership of SR-IOV slaves.
+//!
+//! Under SR-IOV the master joins multicast groups with the SA on behalf of its slaves, like the C
+//! driver's `mlx4_ib_mcg`: a group is joined once for all of them, with the union of the join
+//! states they asked for, and only left once no slave needs a join state anymore. A [`McgGroup`]
+//! is the state machine of one group. It keeps at most one MAD in flight, queues the requests that
+//! arrive meanwhile, and reports what to do as [`McgAction`]s:
+//!
+//! - leaves are acknowledged to the slave right away and the SA is only told once the last user
+//!   of a join state is gone;
+//! - a join or leave the SA does not answer within the timeout is given up on, see
+//!   [`McgGroup::timeout`];
+//! - [`McgGroup::teardown`] fails everything pending and leaves the SA when the device goes away.
+//!
+//! Times are ticks of a wrapping clock such as jiffies. Synchronisation is left to the owner.
+
+use super::ring::Ring;
+use super::timeout_map::reached;
+
+/// Largest number of functions of a device, like `MLX4_MFUNC_MAX`.
+pub const MAX_SLAVES: usize = 128;
+
+/// Time the SA is given to answer a MAD, in milliseconds, like `MAD_TIMEOUT_MS`.
+pub const MAD_TIMEOUT_MS: u32 = 2000;
+
+/// Largest number of requests waiting for the one in flight.
+pub const MAX_PENDING: usize = 8;
+
+/// Full member join state.
+pub const JOIN_FULL: u8 = 1 << 0;
+/// Non-member join state.
+pub const JOIN_NON_MEMBER: u8 = 1 << 1;
+/// Send-only non-member join state.
+pub const JOIN_SEND_ONLY: u8 = 1 << 2;
+
+const JOIN_MASK: u8 = JOIN_FULL | JOIN_NON_MEMBER | JOIN_SEND_ONLY;
+
+/// State of a group towards the SA.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McgState {
+    /// No MAD in flight.
+    Idle,
+    /// A join was sent for a slave, which waits for the answer.
+    JoinSent,
+    /// A leave was sent.
+    LeaveSent,
+    /// The group was torn down and must be freed.
+    Dead,
+}
+
+/// A request of a slave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McgRequest {
+    /// Join the group with the join states of `mask`.
+    Join {
+        /// Function number of the slave.
+        slave: u8,
+        /// Requested join states.
+        mask: u8,
+    },
+    /// Give up the join states of `mask`.
+    Leave {
+        /// Function number of the slave.
+        slave: u8,
+        /// Join states to give up.
+        mask: u8,
+    },
+}
+
+impl McgRequest {
+    /// Returns the function number of the requesting slave.
+    pub fn slave(self) -> u8 {
+        match self {
+            Self::Join { slave, .. } | Self::Leave { slave, .. } => slave,
+        }
+    }
+
+    /// Returns the join states of the request.
+    pub fn mask(self) -> u8 {
+        match self {
+            Self::Join { mask, .. } | Self::Leave { mask, .. } => mask,
+        }
+    }
+}
+
+/// Outcome of a request, reported to the slave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McgStatus {
+    /// The request was carried out.
+    Ok,
+    /// The SA rejected the join.
+    Rejected,
+    /// The SA did not answer in time.
+    Timeout,
+    /// Too many requests are pending.
+    Busy,
+    /// The request is malformed, e.g. an unknown slave or an empty join state.
+    Invalid,
+    /// The device is going away.
+    Removed,
+}
+
+/// What the owner of a group must do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum McgAction {
+    /// Send a join MAD for the join states of `mask` to the SA.
+    SendJoin {
+        /// Join states to join with.
+        mask: u8,
+    },
+    /// Send a leave MAD for the join states of `mask` to the SA.
+    SendLeave {
+        /// Join states to leave.
+        mask: u8,
+    },
+    /// Answer the request of `slave`.
+    Reply {
+        /// Function number of the slave.
+        slave: u8,
+        /// Outcome of the request.
+        status: McgStatus,
+    },
+    /// Nobody uses the group anymore, it can be freed.
+    Destroy,
+}
+
+/// The membership state machine of one multicast group.
+///
+/// # Invariants
+///
+/// `inflight` is set, and `deadline` meaningful, exactly when the state is [`McgState::JoinSent`] or
+/// [`McgState::LeaveSent`].
+pub struct McgGroup {
+    state: McgState,
+    timeout: u64,
+    deadline: u64,
+    /// Join states of each slave.
+    members: [u8; MAX_SLAVES],
+    /// Join states the group holds at the SA.
+    joined: u8,
+    /// The request the MAD in flight is for.
+    inflight: Option<McgRequest>,
+    pending: Ring<McgRequest, MAX_PENDING>,
+}
+
+impl McgGroup {
+    /// Creates an idle group whose MADs time out after `timeout` ticks.
+    pub const fn new(timeout: u64) -> Self {
+        Self {
+            state: McgState::Idle,
+            timeout,
+            deadline: 0,
+            members: [0; MAX_SLAVES],
+            joined: 0,
+            inflight: None,
+            pending: Ring::new(),
+        }
+    }
+
+    /// Returns the state towards the SA.
+    pub fn state(&self) -> McgState {
+        self.state
+    }
+
+    /// Returns the join states held at the SA.
+    pub fn joined(&self) -> u8 {
+        self.joined
+    }
+
+    /// Returns the join states of `slave`.
+    pub fn member(&self, slave: u8) -> u8 {
+        self.members.get(usize::from(slave)).copied().unwrap_or(0)
+    }
+
+    /// Returns the time at which the MAD in flight times out.
+    pub fn deadline(&self) -> Option<u64> {
+        self.inflight.map(|_| self.deadline)
+    }
+
+    /// Returns the number of requests waiting for the one in flight.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn needed(&self) -> u8 {
+        self.members.iter().fold(0, |acc, m| acc | m)
+    }
+
+    /// Handles `req` of a slave at time `now`, or queues it if a MAD is in flight.
+    pub fn request(&mut self, req: McgRequest, now: u64, out: &mut impl FnMut(McgAction)) {
+        let (slave, mask) = (req.slave(), req.mask());
+        if usize::from(slave) >= MAX_SLAVES || mask == 0 || mask & !JOIN_MASK != 0 {
+            out(McgAction::Reply {
+                slave,
+                status: McgStatus::Invalid,
+            });
+            return;
+        }
+        match self.state {
+            McgState::Idle => {
+                self.process(req, now, out);
+                self.drain(now, out);
+            }
+            McgState::JoinSent | McgState::LeaveSent => {
+                if self.pending.push(req).is_err() {
+                    out(McgAction::Reply {
+                        slave,
+                        status: McgStatus::Busy,
+                    });
+                }
+            }
+            McgState::Dead => out(McgAction::Reply {
+                slave,
+                status: McgStatus::Removed,
+            }),
+        }
+    }
+
+    /// Handles the answer of the SA to the MAD in flight; `ok` is `false` if it failed.
+    ///
+    /// Answers that come after a timeout are ignored.
+    pub fn response(&mut self, ok: bool, now: u64, out: &mut impl FnMut(McgAction)) {
+        match (self.state, self.inflight) {
+            (McgState::JoinSent, Some(McgRequest::Join { slave, mask })) => {
+                let status = if ok {
+                    self.joined |= mask;
+                    self.members[usize::from(slave)] |= mask;
+                    McgStatus::Ok
+                } else {
+                    McgStatus::Rejected
+                };
+                out(McgAction::Reply { slave, status });
+            }
+            (McgState::LeaveSent, Some(McgRequest::Leave { mask, .. })) => {
+                // The join states are gone even if the SA complains about the leave.
+                self.joined &= !mask;
+            }
+            _ => return,
+        }
+        self.idle();
+        self.drain(now, out);
+    }
+
+    /// Gives up on the MAD in flight if its deadline is reached at `now`.
+    ///
+    /// A join fails with [`McgStatus::Timeout`]; a leave is considered done, as the SA ages out
+    /// unanswered members anyway. Returns `true` if the MAD timed out.
+    pub fn timeout(&mut self, now: u64, out: &mut impl FnMut(McgAction)) -> bool {
+        if !matches!(self.deadline(), Some(d) if reached(now, d)) {
+            return false;
+        }
+        match self.inflight {
+            Some(McgRequest::Join { slave, .. }) => out(McgAction::Reply {
+                slave,
+                status: McgStatus::Timeout,
+            }),
+            Some(McgRequest::Leave { mask, .. }) => self.joined &= !mask,
+            None => {}
+        }
+        self.idle();
+        self.drain(now, out);
+        true
+    }
+
+    /// Tears the group down because the device goes away.
+    ///
+    /// Every waiting slave is answered with [`McgStatus::Removed`], the join states held at the SA
+    /// are left without waiting for the answer, and the group is destroyed.
+    pub fn teardown(&mut self, out: &mut impl FnMut(McgAction)) {
+        if self.state == McgState::Dead {
+            return;
+        }
+        if let Some(McgRequest::Join { slave, .. }) = self.inflight {
+            out(McgAction::Reply {
+                slave,
+                status: McgStatus::Removed,
+            });
+        }
+        while let Some(req) = self.pending.pop() {
+            out(McgAction::Reply {
+                slave: req.slave(),
+                status: McgStatus::Removed,
+            });
+        }
+        if self.joined != 0 {
+            out(McgAction::SendLeave { mask: self.joined });
+        }
+        self.members = [0; MAX_SLAVES];
+        self.joined = 0;
+        self.inflight = None;
+        self.state = McgState::Dead;
+        out(McgAction::Destroy);
+    }
+
+    fn idle(&mut self) {
+        self.state = McgState::Idle;
+        self.inflight = None;
+    }
+
+    fn send(&mut self, state: McgState, req: McgRequest, now: u64) {
+        // INVARIANT: Both are set together with the state.
+        self.state = state;
+        self.inflight = Some(req);
+        self.deadline = now.wrapping_add(self.timeout);
+    }
+
+    /// Handles `req` with no MAD in flight.
+    fn process(&mut self, req: McgRequest, now: u64, out: &mut impl FnMut(McgAction)) {
+        match req {
+            McgRequest::Join { slave, mask } => {
+                if self.joined & mask == mask {
+                    // The group already holds the join states, no need to ask the SA.
+                    self.members[usize::from(slave)] |= mask;
+                    out(McgAction::Reply {
+                        slave,
+                        status: McgStatus::Ok,
+                    });
+                    return;
+                }
+                self.send(McgState::JoinSent, req, now);
+                out(McgAction::SendJoin {
+                    mask: self.joined | mask,
+                });
+            }
+            McgRequest::Leave { slave, mask } => {
+                self.members[usize::from(slave)] &= !mask;
+                out(McgAction::Reply {
+                    slave,
+                    status: McgStatus::Ok,
+                });
+                let surplus = self.joined & !self.needed();
+                if surplus != 0 {
+                    self.send(
+                        McgState::LeaveSent,
+                        McgRequest::Leave {
+                            slave,
+                            mask: surplus,
+                        },
+                        now,
+                    );
+                    out(McgAction::SendLeave { mask: surplus });
+                }
+            }
+        }
+    }
+
+    /// Handles the queued requests until a MAD is in flight, and destroys the group once it is
+    /// unused.
+    fn drain(&mut self, now: u64, out: &mut impl FnMut(McgAction)) {
+        while self.state == McgState::Idle {
+            match self.pending.pop() {
+                Some(req) => self.process(req, now, out),
+                None => break,
+            }
+        }
+        if self.state == McgState::Idle && self.joined == 0 && self.needed() == 0 {
+            self.state = McgState::Dead;
+            out(McgAction::Destroy);
+        }
+    }
+}
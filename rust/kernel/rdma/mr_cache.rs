@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Per-QP cache of resolved memory region keys.
+//!
+//! The responder resolves the rkey of every RDMA WRITE, READ and atomic request to a memory region,
+//! which costs a pool lookup under a lock. Streams of requests usually hit the same few regions, so
+//! each QP keeps the last few translations in an [`MrCache`].
+//!
+//! Invalidation is global: deregistering or invalidating any MR of a device bumps its
+//! [`MrGeneration`], which makes every cached entry of every QP stale at once. This keeps the
+//! dereg path free of any knowledge of the QPs, and deregistrations are rare next to lookups.
+//! Providers hand the generation of a device to the abstraction through
+//! `IbDeviceOperations::mr_generation`, which bumps it on the deregistrations and the memory
+//! window binds and invalidations it sees.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Invalidation counter shared by all the MR caches of a device.
+pub struct MrGeneration(AtomicU32);
+
+impl MrGeneration {
+    /// Creates a counter.
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    /// Returns the current generation.
+    pub fn current(&self) -> u32 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Makes every cached translation stale, called when an MR is deregistered or invalidated.
+    ///
+    /// Must be called before the MR stops being usable, so that no cache hands it out afterwards.
+    pub fn bump(&self) {
+        self.0.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+impl Default for MrGeneration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Entry<M> {
+    key: u32,
+    generation: u32,
+    /// Value of the cache's clock when the entry was last used.
+    used: u64,
+    mr: M,
+}
+
+/// Cache of up to `N` rkey translations, owned by one QP.
+///
+/// Entries hold a clone of `M`, typically a reference counted handle. Stale entries are dropped on
+/// the next lookup that notices the generation change, or by [`MrCache::clear`]. Once the cache is
+/// full, the least recently used entry makes room for a new one.
+pub struct MrCache<M, const N: usize> {
+    entries: [Option<Entry<M>>; N],
+    /// Counts the uses of the entries, to order them.
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl<M: Clone, const N: usize> MrCache<M, N> {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self {
+            entries: [(); N].map(|_| None),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Returns the MR for `key`, calling `resolve` on a miss and caching its result.
+    pub fn lookup(
+        &mut self,
+        generation: &MrGeneration,
+        key: u32,
+        resolve: impl FnOnce(u32) -> Option<M>,
+    ) -> Option<M> {
+        // Read the generation before resolving, so a concurrent bump makes the new entry stale.
+        let current = generation.current();
+        if let Some(mr) = self.get(current, key).cloned() {
+            self.hits += 1;
+            return Some(mr);
+        }
+        self.misses += 1;
+        let mr = resolve(key)?;
+        self.insert(current, key, mr.clone());
+        Some(mr)
+    }
+
+    /// Returns the cached MR for `key` if its entry is from generation `current`.
+    ///
+    /// Entries from older generations are dropped. A hit makes the entry the most recently used.
+    pub fn get(&mut self, current: u32, key: u32) -> Option<&M> {
+        let mut found = None;
+        for (i, slot) in self.entries.iter_mut().enumerate() {
+            match slot {
+                Some(entry) if entry.generation != current => *slot = None,
+                Some(entry) if entry.key == key => found = Some(i),
+                _ => {}
+            }
+        }
+        self.clock += 1;
+        let entry = self.entries[found?].as_mut()?;
+        entry.used = self.clock;
+        Some(&entry.mr)
+    }
+
+    /// Caches `mr` for `key`, resolved at generation `generation`, evicting the least recently
+    /// used entry if the cache is full.
+    pub fn insert(&mut self, generation: u32, key: u32, mr: M) {
+        if N == 0 {
+            return;
+        }
+        self.clock += 1;
+        let entry = Some(Entry {
+            key,
+            generation,
+            used: self.clock,
+            mr,
+        });
+        if let Some(slot) = self
+            .entries
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(e) if e.key == key) || slot.is_none())
+        {
+            *slot = entry;
+            return;
+        }
+        if let Some(lru) = self
+            .entries
+            .iter_mut()
+            .min_by_key(|slot| slot.as_ref().map_or(0, |e| e.used))
+        {
+            *lru = entry;
+        }
+    }
+
+    /// Drops the entry of `key`, if any.
+    pub fn invalidate(&mut self, key: u32) {
+        for slot in self.entries.iter_mut() {
+            if matches!(slot, Some(e) if e.key == key) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Drops every entry, e.g. when the QP is reset or destroyed.
+    pub fn clear(&mut self) {
+        for slot in self.entries.iter_mut() {
+            *slot = None;
+        }
+    }
+
+    /// Returns the number of lookups served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    /// Returns the number of lookups that had to resolve the key.
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+impl<M: Clone, const N: usize> Default for MrCache<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
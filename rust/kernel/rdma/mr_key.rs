@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory region keys.
+//!
+//! An lkey or rkey packs the index of the MR in the provider's pool in its upper 24 bits and a
+//! variant in the low byte, as in the C rxe driver. The variant lets a key be retired and the slot
+//! reused (fast registration, invalidation) without stale keys matching the new registration.
+
+/// Number of bits of the variant.
+pub const VARIANT_BITS: u32 = 8;
+
+/// Largest pool index that fits in a key.
+pub const MAX_INDEX: u32 = u32::MAX >> VARIANT_BITS;
+
+/// An lkey or rkey.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MrKey(u32);
+
+impl MrKey {
+    /// Creates a key for pool slot `index`, which must not exceed [`MAX_INDEX`].
+    pub const fn new(index: u32, variant: u8) -> Self {
+        Self(((index & MAX_INDEX) << VARIANT_BITS) | variant as u32)
+    }
+
+    /// Creates a key from its wire value.
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// Returns the wire value.
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the pool index.
+    pub const fn index(self) -> u32 {
+        self.0 >> VARIANT_BITS
+    }
+
+    /// Returns the variant.
+    pub const fn variant(self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Returns the key of the same slot with `variant` replaced, as requested by a fast
+    /// registration work request.
+    pub const fn with_variant(self, variant: u8) -> Self {
+        Self::new(self.index(), variant)
+    }
+
+    /// Returns the key of the same slot with the next variant.
+    pub const fn next_variant(self) -> Self {
+        self.with_variant(self.variant().wrapping_add(1))
+    }
+}
+
+impl From<MrKey> for u32 {
+    fn from(key: MrKey) -> u32 {
+        key.raw()
+    }
+}
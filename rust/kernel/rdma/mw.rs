@@ -0,0 +1,382 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory window binding rules.
+//!
+//! A memory window grants remote access to part of a memory region under its own rkey, taken from
+//! the same key space as the MRs (see [`MrKey`]). Type 1 windows are bound by a verb and may be
+//! used by any QP of their PD; type 2 windows are bound by a work request posted on a QP, only
+//! serve that QP and can be invalidated like fast-registered MRs. This follows the checks of
+//! IBTA 10.6.7 as the C rxe driver implements them in `rxe_mw.c`.
+//!
+//! The MR side keeps a [`BoundWindows`] count so that a region cannot go away under a window.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::mr_key::MrKey;
+
+/// `IB_ACCESS_LOCAL_WRITE`.
+pub const ACCESS_LOCAL_WRITE: u32 = 1 << 0;
+/// `IB_ACCESS_REMOTE_WRITE`.
+pub const ACCESS_REMOTE_WRITE: u32 = 1 << 1;
+/// `IB_ACCESS_REMOTE_READ`.
+pub const ACCESS_REMOTE_READ: u32 = 1 << 2;
+/// `IB_ACCESS_REMOTE_ATOMIC`.
+pub const ACCESS_REMOTE_ATOMIC: u32 = 1 << 3;
+/// `IB_ACCESS_MW_BIND`.
+pub const ACCESS_MW_BIND: u32 = 1 << 4;
+/// `IB_ZERO_BASED`.
+pub const ZERO_BASED: u32 = 1 << 5;
+
+/// Type of a memory window, `enum ib_mw_type`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum MwType {
+    /// Bound by a verb, usable by every QP of the PD.
+    Type1 = 1,
+    /// Bound by a work request, usable by the binding QP only.
+    Type2 = 2,
+}
+
+impl MwType {
+    /// Converts `enum ib_mw_type`.
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            1 => Some(Self::Type1),
+            2 => Some(Self::Type2),
+            _ => None,
+        }
+    }
+}
+
+/// State of a memory window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MwState {
+    /// Not bound, only type 2 windows can be in this state.
+    Free,
+    /// Bound and usable, or a type 1 window that was never bound.
+    Valid,
+    /// Invalidated or deallocated.
+    Invalid,
+}
+
+/// The memory region a window is being bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MrView {
+    /// Local key of the region.
+    pub lkey: MrKey,
+    /// Identity of the PD of the region, e.g. the address of its `struct ib_pd`.
+    pub pd: usize,
+    /// First I/O virtual address of the region.
+    pub iova: u64,
+    /// Length of the region.
+    pub length: u64,
+    /// `IB_ACCESS_*` flags of the region.
+    pub access: u32,
+}
+
+/// A bind request, from the bind verb or a bind work request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BindMw {
+    /// Variant, the low byte, of the new rkey.
+    pub key: u8,
+    /// Start of the window, an offset into the region if [`ZERO_BASED`] is requested.
+    pub addr: u64,
+    /// Length of the window.
+    pub length: u64,
+    /// `IB_ACCESS_*` flags granted through the window.
+    pub access: u32,
+    /// Region to bind to, `None` to unbind.
+    pub mr: Option<MrView>,
+}
+
+/// Reasons a bind request fails, reported as a bind error completion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindError {
+    /// The window is not in a state that can be bound: type 1 windows must be valid and type 2
+    /// windows free.
+    State,
+    /// Type 1 windows cannot be zero based.
+    ZeroBasedType1,
+    /// The window, the region and the binding QP are not all in the same PD.
+    PdMismatch,
+    /// The region is zero based itself.
+    ZeroBasedMr,
+    /// The region was not registered with `IB_ACCESS_MW_BIND`.
+    NotBindable,
+    /// Remote write or atomic access requires local write access to the region.
+    NoLocalWrite,
+    /// The window does not fit inside the region.
+    OutOfBounds,
+    /// The region is being deregistered.
+    Deregistered,
+}
+
+/// Reasons an invalidation fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidateError {
+    /// The key does not match the window's rkey.
+    Key,
+    /// Only bound type 2 windows can be invalidated.
+    State,
+}
+
+/// State of a memory window, protected by its owner.
+///
+/// Windows are bound and used from several QPs, and checked from the receive path in softirq
+/// context, so the owner keeps them behind a lock taken with bottom halves disabled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Window {
+    mw_type: MwType,
+    state: MwState,
+    rkey: MrKey,
+    pd: usize,
+    qpn: Option<u32>,
+    addr: u64,
+    length: u64,
+    access: u32,
+    mr: Option<MrKey>,
+}
+
+impl Window {
+    /// Creates a window of `mw_type` in PD `pd` with the initial `rkey`.
+    ///
+    /// Type 1 windows start valid, granting no access until bound; type 2 windows start free.
+    pub const fn new(mw_type: MwType, rkey: MrKey, pd: usize) -> Self {
+        Self {
+            mw_type,
+            state: match mw_type {
+                MwType::Type1 => MwState::Valid,
+                MwType::Type2 => MwState::Free,
+            },
+            rkey,
+            pd,
+            qpn: None,
+            addr: 0,
+            length: 0,
+            access: 0,
+            mr: None,
+        }
+    }
+
+    /// Returns the type of the window.
+    pub const fn mw_type(&self) -> MwType {
+        self.mw_type
+    }
+
+    /// Returns the state of the window.
+    pub const fn state(&self) -> MwState {
+        self.state
+    }
+
+    /// Returns the current rkey.
+    pub const fn rkey(&self) -> MrKey {
+        self.rkey
+    }
+
+    /// Returns the key of the region the window is bound to.
+    pub const fn mr(&self) -> Option<MrKey> {
+        self.mr
+    }
+
+    fn check_bind(&self, qp_pd: usize, req: &BindMw) -> Result<(), BindError> {
+        match self.mw_type {
+            MwType::Type1 => {
+                if self.state != MwState::Valid {
+                    return Err(BindError::State);
+                }
+                if req.access & ZERO_BASED != 0 {
+                    return Err(BindError::ZeroBasedType1);
+                }
+            }
+            MwType::Type2 => {
+                if self.state != MwState::Free {
+                    return Err(BindError::State);
+                }
+                if qp_pd != self.pd {
+                    return Err(BindError::PdMismatch);
+                }
+            }
+        }
+
+        let mr = match req.mr {
+            Some(mr) => mr,
+            None => return Ok(()),
+        };
+        if mr.pd != self.pd {
+            return Err(BindError::PdMismatch);
+        }
+        if mr.access & ZERO_BASED != 0 {
+            return Err(BindError::ZeroBasedMr);
+        }
+        if mr.access & ACCESS_MW_BIND == 0 {
+            return Err(BindError::NotBindable);
+        }
+        if req.access & (ACCESS_REMOTE_WRITE | ACCESS_REMOTE_ATOMIC) != 0
+            && mr.access & ACCESS_LOCAL_WRITE == 0
+        {
+            return Err(BindError::NoLocalWrite);
+        }
+        let fits = if req.access & ZERO_BASED != 0 {
+            req.length <= mr.length
+        } else {
+            let end = req.addr.checked_add(req.length);
+            req.addr >= mr.iova
+                && matches!(end, Some(end) if end <= mr.iova.saturating_add(mr.length))
+        };
+        if !fits {
+            return Err(BindError::OutOfBounds);
+        }
+        Ok(())
+    }
+
+    /// Binds the window on behalf of QP `qpn` of PD `qp_pd`, or unbinds it if `req.mr` is
+    /// `None`.
+    ///
+    /// On success the rkey takes the variant of the request, and the key of the region the
+    /// window was bound to before, if any, is returned so that its [`BoundWindows`] count can be
+    /// dropped; the caller raises the count of the new region beforehand.
+    pub fn bind(
+        &mut self,
+        qp_pd: usize,
+        qpn: u32,
+        req: &BindMw,
+    ) -> Result<Option<MrKey>, BindError> {
+        self.check_bind(qp_pd, req)?;
+        let old = self.mr.take();
+        self.rkey = self.rkey.with_variant(req.key);
+        match req.mr {
+            Some(mr) => {
+                self.state = MwState::Valid;
+                self.mr = Some(mr.lkey);
+                self.access = req.access;
+                self.addr = req.addr;
+                self.length = req.length;
+                self.qpn = match self.mw_type {
+                    MwType::Type1 => None,
+                    MwType::Type2 => Some(qpn),
+                };
+            }
+            None => {
+                self.access = 0;
+                self.addr = 0;
+                self.length = 0;
+                self.qpn = None;
+            }
+        }
+        Ok(old)
+    }
+
+    /// Invalidates a type 2 window bound under `rkey`, e.g. for a LOCAL_INV or SEND_WITH_INV.
+    ///
+    /// Returns the key of the region it was bound to, whose [`BoundWindows`] count must be
+    /// dropped.
+    pub fn invalidate(&mut self, rkey: MrKey) -> Result<Option<MrKey>, InvalidateError> {
+        if rkey != self.rkey {
+            return Err(InvalidateError::Key);
+        }
+        if self.mw_type != MwType::Type2 || self.state != MwState::Valid {
+            return Err(InvalidateError::State);
+        }
+        self.state = MwState::Free;
+        self.access = 0;
+        self.qpn = None;
+        Ok(self.mr.take())
+    }
+
+    /// Marks the window invalid before it is deallocated.
+    ///
+    /// Returns the key of the region it was bound to, whose [`BoundWindows`] count must be
+    /// dropped.
+    pub fn dealloc(&mut self) -> Option<MrKey> {
+        self.state = MwState::Invalid;
+        self.access = 0;
+        self.mr.take()
+    }
+
+    /// Resolves an access through the window by QP `qpn` with `rkey`.
+    ///
+    /// Returns the key of the underlying region and the I/O virtual address to access in it if
+    /// `access` is granted for `length` bytes at `addr`.
+    pub fn check_access(
+        &self,
+        rkey: MrKey,
+        qpn: u32,
+        access: u32,
+        addr: u64,
+        length: u64,
+    ) -> Option<(MrKey, u64)> {
+        if rkey != self.rkey || self.state != MwState::Valid {
+            return None;
+        }
+        let mr = self.mr?;
+        if matches!(self.qpn, Some(bound) if bound != qpn) || self.access & access != access {
+            return None;
+        }
+        let (offset, start) = if self.access & ZERO_BASED != 0 {
+            (addr, self.addr)
+        } else {
+            (addr.checked_sub(self.addr)?, self.addr)
+        };
+        if offset.checked_add(length)? > self.length {
+            return None;
+        }
+        Some((mr, start + offset))
+    }
+}
+
+/// Number of windows bound to a memory region, each holding the region.
+///
+/// A region with windows bound cannot be deregistered or invalidated, as remote peers may still
+/// reach it through the windows. Deregistration [retires](BoundWindows::retire) the count, after
+/// which no window can be bound anymore, so that a bind cannot slip in between the check and the
+/// region going away.
+#[derive(Debug, Default)]
+pub struct BoundWindows(AtomicU32);
+
+/// Count of a retired region.
+const RETIRED: u32 = u32::MAX;
+
+impl BoundWindows {
+    /// Creates a count of 0.
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    /// Records a window bound to the region.
+    ///
+    /// Returns `false`, recording nothing, if the region is retired.
+    pub fn bind(&self) -> bool {
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                n.checked_add(1).filter(|&n| n != RETIRED)
+            })
+            .is_ok()
+    }
+
+    /// Records a window unbound from the region.
+    pub fn unbind(&self) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                if n == RETIRED {
+                    None
+                } else {
+                    n.checked_sub(1)
+                }
+            });
+    }
+
+    /// Retires the count of a region being deregistered.
+    ///
+    /// Returns `false`, leaving the count alone, if windows are bound to the region.
+    pub fn retire(&self) -> bool {
+        self.0
+            .compare_exchange(0, RETIRED, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Returns `true` if windows are bound to the region.
+    pub fn is_bound(&self) -> bool {
+        !matches!(self.0.load(Ordering::Acquire), 0 | RETIRED)
+    }
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! On-demand paging.
+//!
+//! An ODP memory region does not pin its pages: the device faults them in as it touches them,
+//! and the MMU notifier of the region tells the provider when the CPU mapping of a range changes,
+//! so that it stops using the pages before they go away. [`OdpCaps`] describes what a device can
+//! fault on, and [`OdpSpan`] does the page arithmetic of faults and invalidations.
+
+use core::ops::BitOr;
+
+/// General ODP support, `IB_ODP_SUPPORT`.
+pub const ODP_SUPPORT: u64 = 1 << 0;
+
+/// Support of implicit ODP regions covering the whole address space, `IB_ODP_SUPPORT_IMPLICIT`.
+pub const ODP_SUPPORT_IMPLICIT: u64 = 1 << 1;
+
+/// Operations of one transport that may fault, `IB_ODP_SUPPORT_*`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OdpTransportCaps(u32);
+
+impl OdpTransportCaps {
+    /// No operation faults.
+    pub const NONE: Self = Self(0);
+    /// Gathering the payload of SENDs.
+    pub const SEND: Self = Self(1 << 0);
+    /// Scattering received SENDs.
+    pub const RECV: Self = Self(1 << 1);
+    /// RDMA WRITEs.
+    pub const WRITE: Self = Self(1 << 2);
+    /// RDMA READs.
+    pub const READ: Self = Self(1 << 3);
+    /// Atomic operations.
+    pub const ATOMIC: Self = Self(1 << 4);
+    /// Scattering SENDs received through an SRQ.
+    pub const SRQ_RECV: Self = Self(1 << 5);
+
+    /// Returns the capabilities of `bits`.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the union of `self` and `other`.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns `true` if every capability of `other` is set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns `true` if no operation faults.
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for OdpTransportCaps {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+/// The ODP capabilities of a device, `struct ib_odp_caps`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OdpCaps {
+    /// Whether implicit regions are supported.
+    pub implicit: bool,
+    /// Operations of RC QPs that may fault.
+    pub rc: OdpTransportCaps,
+    /// Operations of UC QPs that may fault.
+    pub uc: OdpTransportCaps,
+    /// Operations of UD QPs that may fault.
+    pub ud: OdpTransportCaps,
+    /// Operations of XRC QPs that may fault.
+    pub xrc: OdpTransportCaps,
+}
+
+impl OdpCaps {
+    /// Returns capabilities without ODP support.
+    pub const fn new() -> Self {
+        Self {
+            implicit: false,
+            rc: OdpTransportCaps::NONE,
+            uc: OdpTransportCaps::NONE,
+            ud: OdpTransportCaps::NONE,
+            xrc: OdpTransportCaps::NONE,
+        }
+    }
+
+    /// Returns `true` if some operation of some transport may fault.
+    pub const fn is_supported(&self) -> bool {
+        !(self.rc.is_empty() && self.uc.is_empty() && self.ud.is_empty() && self.xrc.is_empty())
+    }
+
+    /// Returns the `general_caps` bits of the capabilities.
+    pub const fn general_caps(&self) -> u64 {
+        match (self.is_supported(), self.implicit) {
+            (false, _) => 0,
+            (true, false) => ODP_SUPPORT,
+            (true, true) => ODP_SUPPORT | ODP_SUPPORT_IMPLICIT,
+        }
+    }
+}
+
+/// The pages of an ODP region overlapped by a range of addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OdpSpan {
+    /// Start of the overlap.
+    pub start: u64,
+    /// End of the overlap, exclusive.
+    pub end: u64,
+    /// Index of the first page of the overlap in the region.
+    pub first_page: usize,
+    /// Number of pages of the overlap.
+    pub npages: usize,
+}
+
+impl OdpSpan {
+    /// Returns the overlap of `[start, end)` with the region `[umem_start, umem_end)` of pages of
+    /// `1 << page_shift` bytes, or `None` if they are disjoint.
+    ///
+    /// The region is page aligned, as `ib_umem_start()` and `ib_umem_end()` return it; the
+    /// overlap is extended to whole pages.
+    pub fn of(
+        umem_start: u64,
+        umem_end: u64,
+        page_shift: u32,
+        start: u64,
+        end: u64,
+    ) -> Option<Self> {
+        let start = start.max(umem_start);
+        let end = end.min(umem_end);
+        if start >= end {
+            return None;
+        }
+        let mask = (1u64 << page_shift) - 1;
+        let start = start & !mask;
+        let end = end
+            .checked_add(mask)
+            .map_or(umem_end, |e| (e & !mask).min(umem_end));
+        Some(Self {
+            start,
+            end,
+            first_page: ((start - umem_start) >> page_shift) as usize,
+            npages: ((end - start) >> page_shift) as usize,
+        })
+    }
+}
@@ -0,0 +1,603 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! InfiniBand transport opcodes.
+//!
+//! Mirrors the C driver's `rxe_opcode` table: for each opcode it records which extended transport
+//! headers follow the BTH and what kind of work the packet represents, from which header offsets
+//! and lengths are derived.
+
+use core::ops::BitOr;
+
+/// Set of header and property flags attached to an opcode.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpcodeMask(u32);
+
+impl OpcodeMask {
+    /// No flags.
+    pub const NONE: Self = Self(0);
+    /// Reliable datagram extended transport header present.
+    pub const RDETH: Self = Self(1 << 0);
+    /// Datagram extended transport header present.
+    pub const DETH: Self = Self(1 << 1);
+    /// RDMA extended transport header present.
+    pub const RETH: Self = Self(1 << 2);
+    /// Atomic extended transport header present.
+    pub const ATMETH: Self = Self(1 << 3);
+    /// ACK extended transport header present.
+    pub const AETH: Self = Self(1 << 4);
+    /// Atomic ACK extended transport header present.
+    pub const ATMACK: Self = Self(1 << 5);
+    /// Immediate data header present.
+    pub const IMMDT: Self = Self(1 << 6);
+    /// Invalidate extended transport header present.
+    pub const IETH: Self = Self(1 << 7);
+    /// Packet carries a payload.
+    pub const PAYLOAD: Self = Self(1 << 8);
+    /// Packet is a request.
+    pub const REQ: Self = Self(1 << 9);
+    /// Packet is a response or acknowledgement.
+    pub const ACK: Self = Self(1 << 10);
+    /// Packet belongs to a SEND operation.
+    pub const SEND: Self = Self(1 << 11);
+    /// Packet belongs to an RDMA WRITE operation.
+    pub const WRITE: Self = Self(1 << 12);
+    /// Packet belongs to an RDMA READ operation.
+    pub const READ: Self = Self(1 << 13);
+    /// Packet belongs to an atomic operation.
+    pub const ATOMIC: Self = Self(1 << 14);
+    /// Packet consumes a receive work request.
+    pub const RWR: Self = Self(1 << 15);
+    /// Packet generates a receive completion.
+    pub const COMP: Self = Self(1 << 16);
+    /// First packet of a message.
+    pub const START: Self = Self(1 << 17);
+    /// Middle packet of a message.
+    pub const MIDDLE: Self = Self(1 << 18);
+    /// Last packet of a message.
+    pub const END: Self = Self(1 << 19);
+
+    /// Returns the raw bits.
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the union of `self` and `other`.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns `true` if every flag of `other` is set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns `true` if any flag of `other` is set in `self`.
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for OpcodeMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
+
+/// Transport headers that may appear in a RoCEv2 packet, in wire order after the BTH.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Header {
+    /// Base transport header.
+    Bth,
+    /// Reliable datagram extended transport header.
+    Rdeth,
+    /// Datagram extended transport header.
+    Deth,
+    /// RDMA extended transport header.
+    Reth,
+    /// Atomic extended transport header.
+    Atmeth,
+    /// ACK extended transport header.
+    Aeth,
+    /// Atomic ACK extended transport header.
+    Atmack,
+    /// Immediate data.
+    Immdt,
+    /// Invalidate extended transport header.
+    Ieth,
+}
+
+impl Header {
+    /// Extended headers in the order they are laid out on the wire.
+    pub const ORDER: [Header; 8] = [
+        Header::Rdeth,
+        Header::Deth,
+        Header::Reth,
+        Header::Atmeth,
+        Header::Aeth,
+        Header::Atmack,
+        Header::Immdt,
+        Header::Ieth,
+    ];
+
+    /// Returns the length of the header in bytes.
+    pub const fn size(self) -> usize {
+        match self {
+            Header::Bth => 12,
+            Header::Rdeth => 4,
+            Header::Deth => 8,
+            Header::Reth => 16,
+            Header::Atmeth => 28,
+            Header::Aeth => 4,
+            Header::Atmack => 8,
+            Header::Immdt => 4,
+            Header::Ieth => 4,
+        }
+    }
+
+    /// Returns the mask flag announcing the header, or `NONE` for the always present BTH.
+    pub const fn mask(self) -> OpcodeMask {
+        match self {
+            Header::Bth => OpcodeMask::NONE,
+            Header::Rdeth => OpcodeMask::RDETH,
+            Header::Deth => OpcodeMask::DETH,
+            Header::Reth => OpcodeMask::RETH,
+            Header::Atmeth => OpcodeMask::ATMETH,
+            Header::Aeth => OpcodeMask::AETH,
+            Header::Atmack => OpcodeMask::ATMACK,
+            Header::Immdt => OpcodeMask::IMMDT,
+            Header::Ieth => OpcodeMask::IETH,
+        }
+    }
+}
+
+/// Transport service encoded in the top three bits of an opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// Reliable connection.
+    Rc,
+    /// Unreliable connection.
+    Uc,
+    /// Reliable datagram.
+    Rd,
+    /// Unreliable datagram.
+    Ud,
+}
+
+impl Transport {
+    /// Decodes the transport service of `opcode`.
+    pub const fn of(opcode: u8) -> Option<Self> {
+        match opcode >> 5 {
+            0 => Some(Self::Rc),
+            1 => Some(Self::Uc),
+            2 => Some(Self::Rd),
+            3 => Some(Self::Ud),
+            _ => None,
+        }
+    }
+}
+
+/// `IB_OPCODE_RC_SEND_FIRST`.
+pub const RC_SEND_FIRST: u8 = 0x00;
+/// `IB_OPCODE_RC_SEND_MIDDLE`.
+pub const RC_SEND_MIDDLE: u8 = 0x01;
+/// `IB_OPCODE_RC_SEND_LAST`.
+pub const RC_SEND_LAST: u8 = 0x02;
+/// `IB_OPCODE_RC_SEND_LAST_WITH_IMMEDIATE`.
+pub const RC_SEND_LAST_WITH_IMMEDIATE: u8 = 0x03;
+/// `IB_OPCODE_RC_SEND_ONLY`.
+pub const RC_SEND_ONLY: u8 = 0x04;
+/// `IB_OPCODE_RC_SEND_ONLY_WITH_IMMEDIATE`.
+pub const RC_SEND_ONLY_WITH_IMMEDIATE: u8 = 0x05;
+/// `IB_OPCODE_RC_RDMA_WRITE_FIRST`.
+pub const RC_RDMA_WRITE_FIRST: u8 = 0x06;
+/// `IB_OPCODE_RC_RDMA_WRITE_MIDDLE`.
+pub const RC_RDMA_WRITE_MIDDLE: u8 = 0x07;
+/// `IB_OPCODE_RC_RDMA_WRITE_LAST`.
+pub const RC_RDMA_WRITE_LAST: u8 = 0x08;
+/// `IB_OPCODE_RC_RDMA_WRITE_LAST_WITH_IMMEDIATE`.
+pub const RC_RDMA_WRITE_LAST_WITH_IMMEDIATE: u8 = 0x09;
+/// `IB_OPCODE_RC_RDMA_WRITE_ONLY`.
+pub const RC_RDMA_WRITE_ONLY: u8 = 0x0a;
+/// `IB_OPCODE_RC_RDMA_WRITE_ONLY_WITH_IMMEDIATE`.
+pub const RC_RDMA_WRITE_ONLY_WITH_IMMEDIATE: u8 = 0x0b;
+/// `IB_OPCODE_RC_RDMA_READ_REQUEST`.
+pub const RC_RDMA_READ_REQUEST: u8 = 0x0c;
+/// `IB_OPCODE_RC_RDMA_READ_RESPONSE_FIRST`.
+pub const RC_RDMA_READ_RESPONSE_FIRST: u8 = 0x0d;
+/// `IB_OPCODE_RC_RDMA_READ_RESPONSE_MIDDLE`.
+pub const RC_RDMA_READ_RESPONSE_MIDDLE: u8 = 0x0e;
+/// `IB_OPCODE_RC_RDMA_READ_RESPONSE_LAST`.
+pub const RC_RDMA_READ_RESPONSE_LAST: u8 = 0x0f;
+/// `IB_OPCODE_RC_RDMA_READ_RESPONSE_ONLY`.
+pub const RC_RDMA_READ_RESPONSE_ONLY: u8 = 0x10;
+/// `IB_OPCODE_RC_ACKNOWLEDGE`.
+pub const RC_ACKNOWLEDGE: u8 = 0x11;
+/// `IB_OPCODE_RC_ATOMIC_ACKNOWLEDGE`.
+pub const RC_ATOMIC_ACKNOWLEDGE: u8 = 0x12;
+/// `IB_OPCODE_RC_COMPARE_SWAP`.
+pub const RC_COMPARE_SWAP: u8 = 0x13;
+/// `IB_OPCODE_RC_FETCH_ADD`.
+pub const RC_FETCH_ADD: u8 = 0x14;
+/// `IB_OPCODE_RC_SEND_LAST_WITH_INVALIDATE`.
+pub const RC_SEND_LAST_WITH_INVALIDATE: u8 = 0x16;
+/// `IB_OPCODE_RC_SEND_ONLY_WITH_INVALIDATE`.
+pub const RC_SEND_ONLY_WITH_INVALIDATE: u8 = 0x17;
+
+/// `IB_OPCODE_UC_SEND_FIRST`.
+pub const UC_SEND_FIRST: u8 = 0x20;
+/// `IB_OPCODE_UC_SEND_MIDDLE`.
+pub const UC_SEND_MIDDLE: u8 = 0x21;
+/// `IB_OPCODE_UC_SEND_LAST`.
+pub const UC_SEND_LAST: u8 = 0x22;
+/// `IB_OPCODE_UC_SEND_LAST_WITH_IMMEDIATE`.
+pub const UC_SEND_LAST_WITH_IMMEDIATE: u8 = 0x23;
+/// `IB_OPCODE_UC_SEND_ONLY`.
+pub const UC_SEND_ONLY: u8 = 0x24;
+/// `IB_OPCODE_UC_SEND_ONLY_WITH_IMMEDIATE`.
+pub const UC_SEND_ONLY_WITH_IMMEDIATE: u8 = 0x25;
+/// `IB_OPCODE_UC_RDMA_WRITE_FIRST`.
+pub const UC_RDMA_WRITE_FIRST: u8 = 0x26;
+/// `IB_OPCODE_UC_RDMA_WRITE_MIDDLE`.
+pub const UC_RDMA_WRITE_MIDDLE: u8 = 0x27;
+/// `IB_OPCODE_UC_RDMA_WRITE_LAST`.
+pub const UC_RDMA_WRITE_LAST: u8 = 0x28;
+/// `IB_OPCODE_UC_RDMA_WRITE_LAST_WITH_IMMEDIATE`.
+pub const UC_RDMA_WRITE_LAST_WITH_IMMEDIATE: u8 = 0x29;
+/// `IB_OPCODE_UC_RDMA_WRITE_ONLY`.
+pub const UC_RDMA_WRITE_ONLY: u8 = 0x2a;
+/// `IB_OPCODE_UC_RDMA_WRITE_ONLY_WITH_IMMEDIATE`.
+pub const UC_RDMA_WRITE_ONLY_WITH_IMMEDIATE: u8 = 0x2b;
+
+/// `IB_OPCODE_UD_SEND_ONLY`.
+pub const UD_SEND_ONLY: u8 = 0x64;
+/// `IB_OPCODE_UD_SEND_ONLY_WITH_IMMEDIATE`.
+pub const UD_SEND_ONLY_WITH_IMMEDIATE: u8 = 0x65;
+
+/// Static description of one opcode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    /// Printable name, as used by the C driver.
+    pub name: &'static str,
+    /// Headers and properties of the opcode.
+    pub mask: OpcodeMask,
+}
+
+impl OpcodeInfo {
+    const fn new(name: &'static str, mask: OpcodeMask) -> Self {
+        Self { name, mask }
+    }
+
+    /// Returns `true` if packets with this opcode carry `hdr`.
+    pub const fn has(&self, hdr: Header) -> bool {
+        match hdr {
+            Header::Bth => true,
+            _ => self.mask.contains(hdr.mask()),
+        }
+    }
+
+    /// Returns the byte offset of `hdr` from the start of the BTH, if the opcode carries it.
+    pub const fn offset(&self, hdr: Header) -> Option<usize> {
+        if !self.has(hdr) {
+            return None;
+        }
+        let mut offset = Header::Bth.size();
+        let mut i = 0;
+        while i < Header::ORDER.len() {
+            let cur = Header::ORDER[i];
+            if cur as u32 == hdr as u32 {
+                return Some(offset);
+            }
+            if self.has(cur) {
+                offset += cur.size();
+            }
+            i += 1;
+        }
+        // Only reached for the BTH itself.
+        Some(0)
+    }
+
+    /// Returns the total length of all transport headers, BTH included.
+    pub const fn header_len(&self) -> usize {
+        let mut len = Header::Bth.size();
+        let mut i = 0;
+        while i < Header::ORDER.len() {
+            if self.has(Header::ORDER[i]) {
+                len += Header::ORDER[i].size();
+            }
+            i += 1;
+        }
+        len
+    }
+}
+
+const fn m(flags: &[OpcodeMask]) -> OpcodeMask {
+    let mut mask = OpcodeMask::NONE;
+    let mut i = 0;
+    while i < flags.len() {
+        mask = mask.union(flags[i]);
+        i += 1;
+    }
+    mask
+}
+
+use OpcodeMask as M;
+
+/// Returns the description of `opcode`, or `None` if the opcode is not supported.
+pub const fn info(opcode: u8) -> Option<OpcodeInfo> {
+    let info = match opcode {
+        RC_SEND_FIRST => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_FIRST",
+            m(&[M::PAYLOAD, M::REQ, M::RWR, M::SEND, M::START]),
+        ),
+        RC_SEND_MIDDLE => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_MIDDLE",
+            m(&[M::PAYLOAD, M::REQ, M::SEND, M::MIDDLE]),
+        ),
+        RC_SEND_LAST => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_LAST",
+            m(&[M::PAYLOAD, M::REQ, M::COMP, M::RWR, M::SEND, M::END]),
+        ),
+        RC_SEND_LAST_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_LAST_WITH_IMMEDIATE",
+            m(&[
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::END,
+            ]),
+        ),
+        RC_SEND_ONLY => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_ONLY",
+            m(&[
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::START,
+                M::END,
+            ]),
+        ),
+        RC_SEND_ONLY_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_ONLY_WITH_IMMEDIATE",
+            m(&[
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::START,
+                M::END,
+            ]),
+        ),
+        RC_RDMA_WRITE_FIRST => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_WRITE_FIRST",
+            m(&[M::RETH, M::PAYLOAD, M::REQ, M::WRITE, M::START]),
+        ),
+        RC_RDMA_WRITE_MIDDLE => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_WRITE_MIDDLE",
+            m(&[M::PAYLOAD, M::REQ, M::WRITE, M::MIDDLE]),
+        ),
+        RC_RDMA_WRITE_LAST => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_WRITE_LAST",
+            m(&[M::PAYLOAD, M::REQ, M::WRITE, M::END]),
+        ),
+        RC_RDMA_WRITE_LAST_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_WRITE_LAST_WITH_IMMEDIATE",
+            m(&[
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::WRITE,
+                M::COMP,
+                M::RWR,
+                M::END,
+            ]),
+        ),
+        RC_RDMA_WRITE_ONLY => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_WRITE_ONLY",
+            m(&[M::RETH, M::PAYLOAD, M::REQ, M::WRITE, M::START, M::END]),
+        ),
+        RC_RDMA_WRITE_ONLY_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_WRITE_ONLY_WITH_IMMEDIATE",
+            m(&[
+                M::RETH,
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::WRITE,
+                M::COMP,
+                M::RWR,
+                M::START,
+                M::END,
+            ]),
+        ),
+        RC_RDMA_READ_REQUEST => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_READ_REQUEST",
+            m(&[M::RETH, M::REQ, M::READ, M::START, M::END]),
+        ),
+        RC_RDMA_READ_RESPONSE_FIRST => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_READ_RESPONSE_FIRST",
+            m(&[M::AETH, M::PAYLOAD, M::ACK, M::START]),
+        ),
+        RC_RDMA_READ_RESPONSE_MIDDLE => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_READ_RESPONSE_MIDDLE",
+            m(&[M::PAYLOAD, M::ACK, M::MIDDLE]),
+        ),
+        RC_RDMA_READ_RESPONSE_LAST => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_READ_RESPONSE_LAST",
+            m(&[M::AETH, M::PAYLOAD, M::ACK, M::END]),
+        ),
+        RC_RDMA_READ_RESPONSE_ONLY => OpcodeInfo::new(
+            "IB_OPCODE_RC_RDMA_READ_RESPONSE_ONLY",
+            m(&[M::AETH, M::PAYLOAD, M::ACK, M::START, M::END]),
+        ),
+        RC_ACKNOWLEDGE => OpcodeInfo::new(
+            "IB_OPCODE_RC_ACKNOWLEDGE",
+            m(&[M::AETH, M::ACK, M::START, M::END]),
+        ),
+        RC_ATOMIC_ACKNOWLEDGE => OpcodeInfo::new(
+            "IB_OPCODE_RC_ATOMIC_ACKNOWLEDGE",
+            m(&[M::AETH, M::ATMACK, M::ACK, M::START, M::END]),
+        ),
+        RC_COMPARE_SWAP => OpcodeInfo::new(
+            "IB_OPCODE_RC_COMPARE_SWAP",
+            m(&[M::ATMETH, M::REQ, M::ATOMIC, M::START, M::END]),
+        ),
+        RC_FETCH_ADD => OpcodeInfo::new(
+            "IB_OPCODE_RC_FETCH_ADD",
+            m(&[M::ATMETH, M::REQ, M::ATOMIC, M::START, M::END]),
+        ),
+        RC_SEND_LAST_WITH_INVALIDATE => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_LAST_WITH_INVALIDATE",
+            m(&[
+                M::IETH,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::END,
+            ]),
+        ),
+        RC_SEND_ONLY_WITH_INVALIDATE => OpcodeInfo::new(
+            "IB_OPCODE_RC_SEND_ONLY_WITH_INVALIDATE",
+            m(&[
+                M::IETH,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::START,
+                M::END,
+            ]),
+        ),
+        UC_SEND_FIRST => OpcodeInfo::new(
+            "IB_OPCODE_UC_SEND_FIRST",
+            m(&[M::PAYLOAD, M::REQ, M::RWR, M::SEND, M::START]),
+        ),
+        UC_SEND_MIDDLE => OpcodeInfo::new(
+            "IB_OPCODE_UC_SEND_MIDDLE",
+            m(&[M::PAYLOAD, M::REQ, M::SEND, M::MIDDLE]),
+        ),
+        UC_SEND_LAST => OpcodeInfo::new(
+            "IB_OPCODE_UC_SEND_LAST",
+            m(&[M::PAYLOAD, M::REQ, M::COMP, M::RWR, M::SEND, M::END]),
+        ),
+        UC_SEND_LAST_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_UC_SEND_LAST_WITH_IMMEDIATE",
+            m(&[
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::END,
+            ]),
+        ),
+        UC_SEND_ONLY => OpcodeInfo::new(
+            "IB_OPCODE_UC_SEND_ONLY",
+            m(&[
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::START,
+                M::END,
+            ]),
+        ),
+        UC_SEND_ONLY_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_UC_SEND_ONLY_WITH_IMMEDIATE",
+            m(&[
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::START,
+                M::END,
+            ]),
+        ),
+        UC_RDMA_WRITE_FIRST => OpcodeInfo::new(
+            "IB_OPCODE_UC_RDMA_WRITE_FIRST",
+            m(&[M::RETH, M::PAYLOAD, M::REQ, M::WRITE, M::START]),
+        ),
+        UC_RDMA_WRITE_MIDDLE => OpcodeInfo::new(
+            "IB_OPCODE_UC_RDMA_WRITE_MIDDLE",
+            m(&[M::PAYLOAD, M::REQ, M::WRITE, M::MIDDLE]),
+        ),
+        UC_RDMA_WRITE_LAST => OpcodeInfo::new(
+            "IB_OPCODE_UC_RDMA_WRITE_LAST",
+            m(&[M::PAYLOAD, M::REQ, M::WRITE, M::END]),
+        ),
+        UC_RDMA_WRITE_LAST_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_UC_RDMA_WRITE_LAST_WITH_IMMEDIATE",
+            m(&[
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::WRITE,
+                M::COMP,
+                M::RWR,
+                M::END,
+            ]),
+        ),
+        UC_RDMA_WRITE_ONLY => OpcodeInfo::new(
+            "IB_OPCODE_UC_RDMA_WRITE_ONLY",
+            m(&[M::RETH, M::PAYLOAD, M::REQ, M::WRITE, M::START, M::END]),
+        ),
+        UC_RDMA_WRITE_ONLY_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_UC_RDMA_WRITE_ONLY_WITH_IMMEDIATE",
+            m(&[
+                M::RETH,
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::WRITE,
+                M::COMP,
+                M::RWR,
+                M::START,
+                M::END,
+            ]),
+        ),
+        UD_SEND_ONLY => OpcodeInfo::new(
+            "IB_OPCODE_UD_SEND_ONLY",
+            m(&[
+                M::DETH,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::START,
+                M::END,
+            ]),
+        ),
+        UD_SEND_ONLY_WITH_IMMEDIATE => OpcodeInfo::new(
+            "IB_OPCODE_UD_SEND_ONLY_WITH_IMMEDIATE",
+            m(&[
+                M::DETH,
+                M::IMMDT,
+                M::PAYLOAD,
+                M::REQ,
+                M::COMP,
+                M::RWR,
+                M::SEND,
+                M::START,
+                M::END,
+            ]),
+        ),
+        _ => return None,
+    };
+    Some(info)
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! MR page maps.
+//!
+//! A page map translates an I/O virtual address inside a memory region to the DMA address backing
+//! it. Pinned memory comes as one address per base page, but buffers backed by huge pages, or
+//! simply physically contiguous, can be described with far fewer entries: [`PageMap::collapse`]
+//! picks the largest block size the layout allows and compacts the page list in place, so the
+//! responder indexes a table of 2 MiB or 1 GiB blocks instead of millions of 4 KiB pages.
+
+use super::page_size::PageSizeCap;
+
+const fn mask(shift: u32) -> u64 {
+    (1 << shift) - 1
+}
+
+/// Returns the largest block shift, supported by `cap` and at least `page_shift`, in which every
+/// block of the map is physically contiguous.
+///
+/// `pages[i]` is the DMA address of the `i`-th base page of the region starting at `iova`.
+pub fn collapse_shift(cap: PageSizeCap, iova: u64, page_shift: u32, pages: &[u64]) -> u32 {
+    let page_size = 1 << page_shift;
+    let mut va = iova & !mask(page_shift);
+    let mut shift = u64::BITS - 1;
+    let mut prev: Option<u64> = None;
+    for &addr in pages {
+        // Within a block, virtual and DMA addresses share their offset bits.
+        shift = shift.min((addr ^ va).trailing_zeros());
+        // A discontinuity must fall on a block boundary.
+        if let Some(prev) = prev {
+            if prev.wrapping_add(page_size) != addr {
+                shift = shift.min(va.trailing_zeros());
+            }
+        }
+        if shift <= page_shift {
+            return page_shift;
+        }
+        prev = Some(addr);
+        va = va.wrapping_add(page_size);
+    }
+
+    let allowed = cap.bits() & !mask(page_shift) & (u64::MAX >> (u64::BITS - 1 - shift));
+    if allowed == 0 {
+        page_shift
+    } else {
+        u64::BITS - 1 - allowed.leading_zeros()
+    }
+}
+
+/// Translation table of a memory region.
+#[derive(Clone, Copy, Debug)]
+pub struct PageMap<'a> {
+    iova: u64,
+    length: u64,
+    shift: u32,
+    blocks: &'a [u64],
+}
+
+impl<'a> PageMap<'a> {
+    /// Creates a map of `length` bytes at `iova` whose `i`-th block of `1 << shift` bytes is at
+    /// `blocks[i]`.
+    ///
+    /// Returns `None` if `blocks` is too short to cover the region.
+    pub fn new(iova: u64, length: u64, shift: u32, blocks: &'a [u64]) -> Option<Self> {
+        let map = Self {
+            iova,
+            length,
+            shift,
+            blocks,
+        };
+        if length != 0 && map.block_index(iova.checked_add(length - 1)?) >= blocks.len() {
+            return None;
+        }
+        Some(map)
+    }
+
+    /// Creates a map from the per-page addresses in `pages`, collapsed to the largest block size
+    /// allowed by `cap` and the layout.
+    ///
+    /// `pages` is overwritten with the block addresses and the map borrows its prefix. Returns
+    /// `None` if `pages` is too short to cover the region.
+    pub fn collapse(
+        cap: PageSizeCap,
+        iova: u64,
+        length: u64,
+        page_shift: u32,
+        pages: &'a mut [u64],
+    ) -> Option<Self> {
+        let shift = collapse_shift(cap, iova, page_shift, pages);
+        let first = (iova & !mask(page_shift)) >> shift;
+        let mut count = 0;
+        for i in 0..pages.len() {
+            let va = (iova & !mask(page_shift)).wrapping_add((i as u64) << page_shift);
+            if ((va >> shift) - first) as usize == count {
+                pages[count] = pages[i] & !mask(shift);
+                count += 1;
+            }
+        }
+        Self::new(iova, length, shift, &pages[..count])
+    }
+
+    fn block_index(&self, iova: u64) -> usize {
+        ((iova >> self.shift) - (self.iova >> self.shift)) as usize
+    }
+
+    /// Returns the first I/O virtual address of the region.
+    pub fn iova(&self) -> u64 {
+        self.iova
+    }
+
+    /// Returns the length of the region in bytes.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Returns the log2 of the block size.
+    pub fn block_shift(&self) -> u32 {
+        self.shift
+    }
+
+    /// Returns the block addresses.
+    pub fn blocks(&self) -> &'a [u64] {
+        self.blocks
+    }
+
+    /// Returns `true` if `[iova, iova + len)` lies inside the region.
+    pub fn contains(&self, iova: u64, len: u64) -> bool {
+        match iova.checked_sub(self.iova) {
+            Some(offset) => offset <= self.length && len <= self.length - offset,
+            None => false,
+        }
+    }
+
+    /// Returns the DMA address backing `iova`.
+    pub fn translate(&self, iova: u64) -> Option<u64> {
+        self.chunk(iova).map(|(addr, _)| addr)
+    }
+
+    /// Returns the DMA address backing `iova` and the number of bytes contiguous from there,
+    /// bounded by the end of its block and of the region.
+    pub fn chunk(&self, iova: u64) -> Option<(u64, u64)> {
+        if !self.contains(iova, 1) {
+            return None;
+        }
+        let offset = iova & mask(self.shift);
+        let addr = self.blocks[self.block_index(iova)] + offset;
+        let to_end = self.iova + self.length - iova;
+        Some((addr, ((1 << self.shift) - offset).min(to_end)))
+    }
+}
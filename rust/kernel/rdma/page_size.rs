@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Memory region limits and block size selection.
+//!
+//! [`best_page_size`] is a port of `ib_umem_find_best_pgsz()` that works on any list of DMA
+//! segments, so providers can pick the block size of their MR page maps (and the simulation can
+//! test it) without an `ib_umem` at hand.
+
+/// Bitmap of the page sizes a device can map, one bit per power of two.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageSizeCap(u64);
+
+impl PageSizeCap {
+    /// Creates a capability from a bitmap of supported sizes.
+    pub const fn new(bitmap: u64) -> Self {
+        Self(bitmap)
+    }
+
+    /// Creates a capability covering every power of two from `min` to `max` bytes, inclusive.
+    pub const fn range(min_shift: u32, max_shift: u32) -> Self {
+        Self(genmask(max_shift, min_shift))
+    }
+
+    /// Returns the raw bitmap, as reported in `ib_device_attr::page_size_cap`.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the smallest supported page size.
+    pub const fn min(self) -> Option<u64> {
+        if self.0 == 0 {
+            return None;
+        }
+        Some(1 << self.0.trailing_zeros())
+    }
+
+    /// Returns `true` if `size` is one of the supported page sizes.
+    pub const fn supports(self, size: u64) -> bool {
+        size.is_power_of_two() && self.0 & size != 0
+    }
+}
+
+/// Limits applied to memory registrations on a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MrLimits {
+    /// Largest registrable length in bytes.
+    pub max_mr_size: u64,
+    /// Supported MR page sizes.
+    pub page_size_cap: PageSizeCap,
+}
+
+impl MrLimits {
+    /// Defaults of the C rxe driver: unlimited size, page sizes from 4 KiB to 2 GiB.
+    pub const fn new() -> Self {
+        Self {
+            max_mr_size: u64::MAX,
+            page_size_cap: PageSizeCap::range(12, 31),
+        }
+    }
+
+    /// Limits registrations to `max_mr_size` bytes.
+    pub const fn with_max_mr_size(mut self, max_mr_size: u64) -> Self {
+        self.max_mr_size = max_mr_size;
+        self
+    }
+
+    /// Restricts the supported page sizes.
+    pub const fn with_page_size_cap(mut self, cap: PageSizeCap) -> Self {
+        self.page_size_cap = cap;
+        self
+    }
+
+    /// Returns `true` if a registration of `length` bytes is allowed.
+    pub const fn allows(&self, length: u64) -> bool {
+        length != 0 && length <= self.max_mr_size
+    }
+}
+
+impl Default for MrLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns a mask with bits `low..=high` set.
+const fn genmask(high: u32, low: u32) -> u64 {
+    let high = if high > 63 { 63 } else { high };
+    if low > high {
+        return 0;
+    }
+    (u64::MAX >> (63 - high)) & (u64::MAX << low)
+}
+
+/// Returns the number of bits needed to represent `value`.
+const fn bits_per(value: u64) -> u32 {
+    64 - value.leading_zeros()
+}
+
+/// Selects the best page size to map `length` bytes at IO virtual address `iova`.
+///
+/// `segments` are the `(dma_address, dma_length)` pairs backing the region, in order, and `offset`
+/// is the offset of the region in the first segment. The result is the largest size in `cap` for
+/// which every block boundary of the IOVA space lands on a segment boundary, which yields the
+/// fewest page map entries. Returns `None` if no supported size works, in which case the region
+/// cannot be mapped with `cap`, or if the region or a segment wraps around the address space, or
+/// `offset` is past the first segment.
+pub fn best_page_size(
+    cap: PageSizeCap,
+    iova: u64,
+    length: u64,
+    offset: u64,
+    segments: impl ExactSizeIterator<Item = (u64, u64)>,
+) -> Option<u64> {
+    if length == 0 {
+        return None;
+    }
+    let mut bitmap = cap.bits();
+    // Page sizes larger than what the VA bits that never change across the region allow.
+    let mut mask = bitmap & genmask(63, bits_per(iova.checked_add(length - 1)? ^ iova));
+    let mut va = iova;
+    let mut pgoff = offset;
+    let last = segments.len().saturating_sub(1);
+
+    for (i, (dma_addr, dma_len)) in segments.enumerate() {
+        // Any bit where VA and DMA address differ caps the page size.
+        mask |= dma_addr.checked_add(pgoff)? ^ va;
+        let len = dma_len.checked_sub(pgoff)?;
+        // The next segment starts at this VA, so its low bits must be zero too.
+        if i != last {
+            va = va.checked_add(len)?;
+            mask |= va;
+        }
+        pgoff = 0;
+    }
+
+    if mask != 0 {
+        bitmap &= genmask(mask.trailing_zeros(), 0);
+    }
+    if bitmap == 0 {
+        return None;
+    }
+    Some(1 << (63 - bitmap.leading_zeros()))
+}
@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Index allocation and reference counting of the rxe object pools.
+//!
+//! As in the C driver's `rxe_pool.c`, every verbs object of a device gets an index from the range
+//! of its kind: QP numbers, the index part of MR and MW keys, and so on. MRs and MWs share the key
+//! space, so they get disjoint halves of it. Indices are handed out cyclically, so that a freed
+//! index is not reused right away and a late packet does not hit the object that took its place.
+//!
+//! The objects live in a fixed number of slots; an index maps to the slot `(index - min) %
+//! capacity`, and a new index is only handed out if its slot is free. Lookups are then a single
+//! load, suitable for the receive path. [`SlotIndices`] tracks the free slots with an
+//! [`IdAllocator`], which finds them cyclically, and gives a new object the next index of its
+//! slot, see [`IndexRange::index_of_slot`].
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::id_alloc::IdAllocator;
+use super::tracker::ResourceKind;
+
+/// Largest index of any pool, like `RXE_MAX_INDEX`.
+pub const MAX_INDEX: u32 = (1 << 20) - 1;
+
+/// A range of pool indices, bounds included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexRange {
+    /// Smallest index.
+    pub min: u32,
+    /// Largest index.
+    pub max: u32,
+}
+
+impl IndexRange {
+    /// Returns the index range of objects of `kind`.
+    ///
+    /// QP numbers 0 and 1 are the special QPs and the first ones are reserved, like
+    /// `RXE_MIN_QP_INDEX`.
+    pub const fn of(kind: ResourceKind) -> Self {
+        match kind {
+            ResourceKind::Qp => Self {
+                min: 16,
+                max: MAX_INDEX,
+            },
+            ResourceKind::Mr => Self {
+                min: 1,
+                max: MAX_INDEX >> 1,
+            },
+            ResourceKind::Mw => Self {
+                min: (MAX_INDEX >> 1) + 1,
+                max: MAX_INDEX,
+            },
+            ResourceKind::Pd | ResourceKind::Cq | ResourceKind::Srq | ResourceKind::Ah => Self {
+                min: 1,
+                max: MAX_INDEX,
+            },
+        }
+    }
+
+    /// Returns the number of indices in the range.
+    pub const fn size(&self) -> u32 {
+        self.max - self.min + 1
+    }
+
+    /// Returns `true` if `index` is in the range.
+    pub const fn contains(&self, index: u32) -> bool {
+        index >= self.min && index <= self.max
+    }
+
+    /// Returns the slot of `index` in a pool of `capacity` slots.
+    pub const fn slot(&self, index: u32, capacity: u32) -> Option<usize> {
+        if !self.contains(index) || capacity == 0 {
+            return None;
+        }
+        Some(((index - self.min) % capacity) as usize)
+    }
+
+    /// Returns the first index at or after `next`, wrapping around the range, whose slot in a pool
+    /// of `capacity` slots is `slot`.
+    ///
+    /// `next` must be in the range, and `slot` below `capacity`, which must not exceed the size of
+    /// the range.
+    pub const fn index_of_slot(&self, next: u32, slot: usize, capacity: u32) -> u32 {
+        let from = (next - self.min) % capacity;
+        let index = next + (slot as u32 + capacity - from) % capacity;
+        if index > self.max {
+            return self.min + slot as u32;
+        }
+        index
+    }
+}
+
+/// The slots of a pool in use, and the next index to hand out.
+///
+/// Synchronisation is left to the owner.
+pub struct SlotIndices<S> {
+    slots: IdAllocator<S>,
+    range: IndexRange,
+    capacity: u32,
+    next: u32,
+}
+
+impl<S: AsRef<[u64]> + AsMut<[u64]>> SlotIndices<S> {
+    /// Creates the indices of a pool of `capacity` slots for the indices of `range`, keeping the
+    /// bitmap of the slots in `words`.
+    ///
+    /// Returns `None` if `capacity` is zero or larger than the range, or if `words` is too short
+    /// for it, see [`super::id_alloc::words_for`].
+    pub fn new(range: IndexRange, capacity: u32, words: S) -> Option<Self> {
+        if capacity == 0 || range.max < range.min || capacity > range.size() {
+            return None;
+        }
+        let slots = IdAllocator::new(
+            IndexRange {
+                min: 0,
+                max: capacity - 1,
+            },
+            words,
+        )?;
+        Some(Self {
+            slots,
+            range,
+            capacity,
+            next: range.min,
+        })
+    }
+
+    /// Allocates a free slot and returns the next index of that slot with the slot.
+    ///
+    /// The slots are taken cyclically, so an index freed last is the last one handed out again.
+    pub fn alloc(&mut self) -> Option<(u32, usize)> {
+        let slot = self.slots.alloc()? as usize;
+        let index = self.range.index_of_slot(self.next, slot, self.capacity);
+        self.next = if index == self.range.max {
+            self.range.min
+        } else {
+            index + 1
+        };
+        Some((index, slot))
+    }
+
+    /// Allocates the slot of `index` and returns it, or `None` if it is taken or `index` is out
+    /// of the range.
+    pub fn alloc_at(&mut self, index: u32) -> Option<usize> {
+        let slot = self.range.slot(index, self.capacity)?;
+        if !self.slots.alloc_at(slot as u32) {
+            return None;
+        }
+        Some(slot)
+    }
+
+    /// Frees `slot`. Returns `false` if it was not in use.
+    pub fn free(&mut self, slot: usize) -> bool {
+        self.slots.free(slot as u32)
+    }
+}
+
+/// Reference count of a pool object, like its `kref`.
+///
+/// The count starts at one, the reference of the owner. Once it dropped to zero the object is
+/// being destroyed and lookups must not take new references.
+pub struct RefCount(AtomicU32);
+
+impl RefCount {
+    /// Creates a count holding the owner's reference.
+    pub const fn new() -> Self {
+        Self(AtomicU32::new(1))
+    }
+
+    /// Takes a reference unless the count already dropped to zero, like
+    /// `kref_get_unless_zero()`.
+    pub fn get_unless_zero(&self) -> bool {
+        let mut count = self.0.load(Ordering::Relaxed);
+        while count != 0 {
+            match self.0.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(c) => count = c,
+            }
+        }
+        false
+    }
+
+    /// Takes another reference; the caller must already hold one.
+    pub fn get(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Drops a reference and returns `true` if it was the last one.
+    pub fn put(&self) -> bool {
+        self.0.fetch_sub(1, Ordering::AcqRel) == 1
+    }
+
+    /// Returns the number of references.
+    pub fn count(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for RefCount {
+    fn default() -> Self {
+        Self::new()
+    }
+}
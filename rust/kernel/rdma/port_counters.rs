@@ -0,0 +1,311 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! PortCounters of the performance management class.
+//!
+//! Providers count in 64 bits, which do not wrap in the lifetime of a port, but the
+//! `PortCounters` attribute of the PMA reports 32-bit data and packet counters and even narrower
+//! error counters, which the spec makes saturate rather than wrap, and which a `Set` resets.
+//! Resetting the provider's counters would also reset what `rdma statistic` and snapshots show,
+//! so [`PortCounterAging`] keeps a baseline instead: the 64-bit [`PortTotals`] at the last reset,
+//! and reports the difference, clamped to the width of each field.
+//!
+//! Providers answering the PMA themselves pass the `Get` and `Set` of both attributes to
+//! [`PortCounterAging::process`] from their `process_mad`.
+//!
+//! Counters kept by hardware in 32-bit registers are widened to 64 bits with a
+//! [`CounterExtender`], read often enough that they cannot wrap twice in between.
+
+use super::mad::{class, method, MadHdr, MAD_SIZE};
+
+/// Attribute ID of `PortCounters`.
+pub const ATTR_PORT_COUNTERS: u16 = 0x0012;
+
+/// Attribute ID of `PortCountersExtended`.
+pub const ATTR_PORT_COUNTERS_EXT: u16 = 0x001d;
+
+/// Offset of the attribute data in a PMA MAD, after the header and 40 reserved bytes.
+pub const PMA_DATA_OFFSET: usize = 64;
+
+/// Size of the `PortCounters` attribute.
+pub const PORT_COUNTERS_SIZE: usize = 44;
+
+/// Size of the `PortCountersExtended` attribute.
+pub const PORT_COUNTERS_EXT_SIZE: usize = 72;
+
+/// `CounterSelect` bits of a `PortCounters` `Set`, each resetting one counter.
+pub mod select {
+    /// `LinkDownedCounter`.
+    pub const LINK_DOWNED: u16 = 1 << 2;
+    /// `PortRcvErrors`.
+    pub const RCV_ERRORS: u16 = 1 << 3;
+    /// `PortXmitDiscards`.
+    pub const XMIT_DISCARDS: u16 = 1 << 6;
+    /// `PortXmitData`.
+    pub const XMIT_DATA: u16 = 1 << 12;
+    /// `PortRcvData`.
+    pub const RCV_DATA: u16 = 1 << 13;
+    /// `PortXmitPkts`.
+    pub const XMIT_PKTS: u16 = 1 << 14;
+    /// `PortRcvPkts`.
+    pub const RCV_PKTS: u16 = 1 << 15;
+    /// Every counter.
+    pub const ALL: u16 = 0xffff;
+}
+
+/// The 64-bit counters of a port behind its `PortCounters`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortTotals {
+    /// Bytes sent.
+    pub xmit_bytes: u64,
+    /// Bytes received.
+    pub rcv_bytes: u64,
+    /// Packets sent.
+    pub xmit_pkts: u64,
+    /// Packets received.
+    pub rcv_pkts: u64,
+    /// Packets received with errors.
+    pub rcv_errors: u64,
+    /// Packets dropped on the way out.
+    pub xmit_discards: u64,
+    /// Times the link went down.
+    pub link_downed: u64,
+}
+
+impl PortTotals {
+    /// Nothing counted yet.
+    pub const ZERO: Self = Self {
+        xmit_bytes: 0,
+        rcv_bytes: 0,
+        xmit_pkts: 0,
+        rcv_pkts: 0,
+        rcv_errors: 0,
+        xmit_discards: 0,
+        link_downed: 0,
+    };
+
+    /// Returns what was counted since `base`, by wrapping subtraction.
+    pub fn since(&self, base: &Self) -> Self {
+        Self {
+            xmit_bytes: self.xmit_bytes.wrapping_sub(base.xmit_bytes),
+            rcv_bytes: self.rcv_bytes.wrapping_sub(base.rcv_bytes),
+            xmit_pkts: self.xmit_pkts.wrapping_sub(base.xmit_pkts),
+            rcv_pkts: self.rcv_pkts.wrapping_sub(base.rcv_pkts),
+            rcv_errors: self.rcv_errors.wrapping_sub(base.rcv_errors),
+            xmit_discards: self.xmit_discards.wrapping_sub(base.xmit_discards),
+            link_downed: self.link_downed.wrapping_sub(base.link_downed),
+        }
+    }
+}
+
+fn put_be(buf: &mut [u8], offset: usize, bytes: &[u8]) {
+    buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+/// The baselines of the `PortCounters` and `PortCountersExtended` of a port.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PortCounterAging {
+    base: PortTotals,
+    ext_base: PortTotals,
+}
+
+impl PortCounterAging {
+    /// Creates baselines at 0, for counters created with the port.
+    pub const fn new() -> Self {
+        Self {
+            base: PortTotals::ZERO,
+            ext_base: PortTotals::ZERO,
+        }
+    }
+
+    /// Returns what `PortCounters` reports for `now`, before clamping.
+    pub fn deltas(&self, now: &PortTotals) -> PortTotals {
+        now.since(&self.base)
+    }
+
+    /// Resets the `PortCounters` picked by `counter_select`, a mask of [`select`] bits, to `now`.
+    pub fn reset(&mut self, now: &PortTotals, counter_select: u16) {
+        let base = &mut self.base;
+        let fields = [
+            (select::LINK_DOWNED, &mut base.link_downed, now.link_downed),
+            (select::RCV_ERRORS, &mut base.rcv_errors, now.rcv_errors),
+            (
+                select::XMIT_DISCARDS,
+                &mut base.xmit_discards,
+                now.xmit_discards,
+            ),
+            (select::XMIT_DATA, &mut base.xmit_bytes, now.xmit_bytes),
+            (select::RCV_DATA, &mut base.rcv_bytes, now.rcv_bytes),
+            (select::XMIT_PKTS, &mut base.xmit_pkts, now.xmit_pkts),
+            (select::RCV_PKTS, &mut base.rcv_pkts, now.rcv_pkts),
+        ];
+        for (bit, field, value) in fields {
+            if counter_select & bit != 0 {
+                *field = value;
+            }
+        }
+    }
+
+    /// Resets the `PortCountersExtended` picked by `counter_select`: bit 0 to 3 for the data and
+    /// packet counters, in the order of the attribute.
+    pub fn reset_ext(&mut self, now: &PortTotals, counter_select: u16) {
+        let base = &mut self.ext_base;
+        let fields = [
+            (&mut base.xmit_bytes, now.xmit_bytes),
+            (&mut base.rcv_bytes, now.rcv_bytes),
+            (&mut base.xmit_pkts, now.xmit_pkts),
+            (&mut base.rcv_pkts, now.rcv_pkts),
+        ];
+        for (i, (field, value)) in fields.into_iter().enumerate() {
+            if counter_select & (1 << i) != 0 {
+                *field = value;
+            }
+        }
+    }
+
+    /// Writes the `PortCounters` of `now` to the attribute data `buf`; returns `false` if it is
+    /// too short.
+    ///
+    /// Fields are clamped to their width instead of wrapping, and data is counted in units of 4
+    /// bytes, as the spec requires. Counters a soft device does not have are 0.
+    pub fn write(&self, now: &PortTotals, port_select: u8, buf: &mut [u8]) -> bool {
+        if buf.len() < PORT_COUNTERS_SIZE {
+            return false;
+        }
+        let d = self.deltas(now);
+        buf[..PORT_COUNTERS_SIZE].fill(0);
+        buf[1] = port_select;
+        buf[7] = d.link_downed.min(u8::MAX as u64) as u8;
+        put_be(
+            buf,
+            8,
+            &(d.rcv_errors.min(u16::MAX as u64) as u16).to_be_bytes(),
+        );
+        put_be(
+            buf,
+            14,
+            &(d.xmit_discards.min(u16::MAX as u64) as u16).to_be_bytes(),
+        );
+        let wide = [
+            (24, d.xmit_bytes / 4),
+            (28, d.rcv_bytes / 4),
+            (32, d.xmit_pkts),
+            (36, d.rcv_pkts),
+        ];
+        for (offset, value) in wide {
+            put_be(
+                buf,
+                offset,
+                &(value.min(u32::MAX as u64) as u32).to_be_bytes(),
+            );
+        }
+        true
+    }
+
+    /// Writes the `PortCountersExtended` of `now` to the attribute data `buf`; returns `false`
+    /// if it is too short.
+    ///
+    /// The 64-bit fields wrap like the counters behind them. The unicast and multicast counters
+    /// are 0.
+    pub fn write_ext(&self, now: &PortTotals, port_select: u8, buf: &mut [u8]) -> bool {
+        if buf.len() < PORT_COUNTERS_EXT_SIZE {
+            return false;
+        }
+        let d = now.since(&self.ext_base);
+        buf[..PORT_COUNTERS_EXT_SIZE].fill(0);
+        buf[1] = port_select;
+        let fields = [
+            (8, d.xmit_bytes / 4),
+            (16, d.rcv_bytes / 4),
+            (24, d.xmit_pkts),
+            (32, d.rcv_pkts),
+        ];
+        for (offset, value) in fields {
+            put_be(buf, offset, &value.to_be_bytes());
+        }
+        true
+    }
+
+    /// Answers the PMA MAD `mad`, a `Get` or `Set` of `PortCounters` or `PortCountersExtended`,
+    /// with the totals `now` of the port; returns `false` for other MADs, which are left to the
+    /// agents of the MAD layer, and leaves `out` alone then.
+    ///
+    /// A `Set` first resets the counters its `CounterSelect` picks. The response is written to
+    /// `out`, which holds at least [`MAD_SIZE`] bytes like `mad`.
+    pub fn process(&mut self, now: &PortTotals, mad: &[u8], out: &mut [u8]) -> bool {
+        let hdr = match MadHdr::parse(mad) {
+            Some(hdr) => hdr,
+            None => return false,
+        };
+        if hdr.mgmt_class != class::PERF_MGMT
+            || !matches!(hdr.method, method::GET | method::SET)
+            || mad.len() < MAD_SIZE
+            || out.len() < MAD_SIZE
+        {
+            return false;
+        }
+        let ext = match hdr.attr_id {
+            ATTR_PORT_COUNTERS => false,
+            ATTR_PORT_COUNTERS_EXT => true,
+            _ => return false,
+        };
+        let (port_select, counter_select) = match parse_select(&mad[PMA_DATA_OFFSET..]) {
+            Some(select) => select,
+            None => return false,
+        };
+        if hdr.method == method::SET {
+            if ext {
+                self.reset_ext(now, counter_select);
+            } else {
+                self.reset(now, counter_select);
+            }
+        }
+        out[..MAD_SIZE].copy_from_slice(&mad[..MAD_SIZE]);
+        hdr.response(0).write(out);
+        let data = &mut out[PMA_DATA_OFFSET..];
+        if ext {
+            self.write_ext(now, port_select, data)
+        } else {
+            self.write(now, port_select, data)
+        }
+    }
+}
+
+/// Reads the `PortSelect` and `CounterSelect` of a `Set` from the attribute data `buf`.
+pub fn parse_select(buf: &[u8]) -> Option<(u8, u16)> {
+    if buf.len() < 4 {
+        return None;
+    }
+    Some((buf[1], u16::from_be_bytes([buf[2], buf[3]])))
+}
+
+/// Widens a counter kept in a 32-bit register to 64 bits.
+///
+/// Every reading adds the distance from the previous one, modulo 2^32, so the register may wrap
+/// between readings, but not twice.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CounterExtender {
+    last: u32,
+    total: u64,
+}
+
+impl CounterExtender {
+    /// Creates an extender for a register reading `start` now.
+    pub const fn new(start: u32) -> Self {
+        Self {
+            last: start,
+            total: 0,
+        }
+    }
+
+    /// Takes the register reading `raw` into account and returns the 64-bit total.
+    pub fn update(&mut self, raw: u32) -> u64 {
+        self.total = self.total.wrapping_add(raw.wrapping_sub(self.last) as u64);
+        self.last = raw;
+        self.total
+    }
+
+    /// Returns the 64-bit total as of the last reading.
+    pub const fn total(&self) -> u64 {
+        self.total
+    }
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! The RDMA driver prelude.
+//!
+//! Provider modules and samples start with `use kernel::rdma::prelude::*;`, which brings in the
+//! kernel prelude (`Box`, `Result`, `pr_info!`, `#[vtable]`, `module!`, ...) along with the
+//! registrations and operation traits of the providers enabled in Kconfig, the verbs objects they
+//! get in their callbacks and the wire types of the protocol helpers. This is the curated API
+//! surface; anything else is reached through its module.
+//!
+//! The registrations and the traits of the transports share their names in their own modules, so
+//! they are exported here under a prefixed name, e.g. [`RxeRegistration`] for
+//! `kernel::rxe::Registration`.
+//!
+//! Unlike the rest of [`crate::rdma`], this module is not built for userspace.
+
+pub use crate::prelude::*;
+pub use crate::{pr_debug, pr_err, pr_info, pr_warn};
+
+// Protocol helpers and wire types.
+pub use super::flap::LinkEvent;
+pub use super::gid::Gid;
+pub use super::hdr::{self, Packet, PacketMut, ParseError};
+pub use super::link_params::LinkParams;
+pub use super::opcode::{OpcodeInfo, Transport};
+pub use super::psn::Psn;
+pub use super::qp_state::QpState;
+pub use super::tunables::TuneError;
+
+// Verbs providers.
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+pub use crate::ib::{
+    self, AddressHandle, AttributeGroup, CompletionQueue, Device, DeviceAttribute, DeviceObserver,
+    DeviceRef, DriverAttrs, DriverId, HwCounter, HwStats, IbDeviceOperations, MadResult,
+    MemoryRegion, MemoryWindow, NetDevice, ObservedDevice, ObserverRegistration, ProtectionDomain,
+    QpAsyncEvent, QpAttr, QueuePair, RecvWr, SendWr, Sge, SharedReceiveQueue, StatCounters, Umem,
+    WorkCompletion,
+};
+#[cfg(CONFIG_RUST_RDMA_PCI)]
+pub use crate::ib::{PciDevice, PciDeviceId, PciRdmaAdapter, PciRdmaDriver};
+
+// Soft-RoCE.
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+pub use crate::rxe::net::{SkBuff, UdpRecvVerdict};
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+pub use crate::rxe::{
+    self, Counter, Counters, Registration as RxeRegistration, RxeOperation, Snapshot,
+};
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+pub use crate::{rxe_add, rxe_inc};
+
+// mlx4.
+#[cfg(CONFIG_RUST_RDMA_MLX4)]
+pub use crate::mlx4::{
+    self, Mlx4DevEvent, Mlx4Device, Mlx4Operation, Registration as Mlx4Registration,
+};
+
+// Auxiliary bus drivers.
+#[cfg(CONFIG_RUST_RDMA_AUXILIARY)]
+pub use crate::auxiliary::{
+    self, Device as AuxiliaryDevice, DeviceId as AuxiliaryDeviceId, Driver as AuxiliaryDriver,
+    Registration as AuxiliaryRegistration,
+};
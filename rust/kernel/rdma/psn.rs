@@ -0,0 +1,211 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Packet sequence number arithmetic.
+//!
+//! PSNs are 24-bit counters that wrap around, so they can only be ordered relative to each
+//! other: `a` is "after" `b` when `a - b` (modulo 2^24) falls in the lower half of the space.
+
+use core::cmp::Ordering;
+
+/// Number of significant bits in a PSN.
+pub const PSN_BITS: u32 = 24;
+
+/// Mask selecting the significant bits of a PSN.
+pub const PSN_MASK: u32 = (1 << PSN_BITS) - 1;
+
+/// A 24-bit packet sequence number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Psn(u32);
+
+impl Psn {
+    /// Creates a PSN, discarding the bits above the 24-bit range.
+    pub const fn new(value: u32) -> Self {
+        Self(value & PSN_MASK)
+    }
+
+    /// Returns the raw 24-bit value.
+    pub const fn value(self) -> u32 {
+        self.0
+    }
+
+    /// Returns the PSN `n` packets after `self`, wrapping at 2^24.
+    pub const fn add(self, n: u32) -> Self {
+        Self::new(self.0.wrapping_add(n))
+    }
+
+    /// Returns the PSN `n` packets before `self`, wrapping at 2^24.
+    pub const fn sub(self, n: u32) -> Self {
+        Self::new(self.0.wrapping_sub(n))
+    }
+
+    /// Returns the PSN following `self`.
+    pub const fn next(self) -> Self {
+        self.add(1)
+    }
+
+    /// Returns the signed distance from `other` to `self`.
+    ///
+    /// The result is positive when `self` is after `other`, and is only meaningful while the two
+    /// PSNs are less than 2^23 apart.
+    pub const fn diff(self, other: Self) -> i32 {
+        // Shift the 24-bit difference into the top of an `i32` so that the sign bit of the PSN
+        // space becomes the sign bit of the result, then shift it back down.
+        ((self.0.wrapping_sub(other.0) << (32 - PSN_BITS)) as i32) >> (32 - PSN_BITS)
+    }
+
+    /// Compares two PSNs taking wraparound into account.
+    ///
+    /// This is the equivalent of the C driver's `psn_compare()`. It is deliberately not an `Ord`
+    /// implementation because the relation is not transitive over the whole PSN space.
+    pub const fn compare(self, other: Self) -> Ordering {
+        let diff = self.diff(other);
+        if diff < 0 {
+            Ordering::Less
+        } else if diff > 0 {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+
+    /// Returns `true` if `self` comes strictly before `other`.
+    pub const fn before(self, other: Self) -> bool {
+        self.diff(other) < 0
+    }
+
+    /// Returns `true` if `self` comes strictly after `other`.
+    pub const fn after(self, other: Self) -> bool {
+        self.diff(other) > 0
+    }
+}
+
+impl From<u32> for Psn {
+    fn from(value: u32) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<Psn> for u32 {
+    fn from(psn: Psn) -> Self {
+        psn.0
+    }
+}
+
+/// Largest number of PSNs a [`PsnWindow`] can span, half of the PSN space.
+///
+/// Beyond that, "before" and "after" become ambiguous.
+pub const PSN_WINDOW_MAX: u32 = 1 << (PSN_BITS - 1);
+
+/// Where a PSN falls relative to a [`PsnWindow`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PsnClass {
+    /// Before the window: a packet that was already handled, e.g. a retransmitted request or a
+    /// late response.
+    Duplicate,
+    /// Inside the window, at this offset from its start.
+    InWindow(u32),
+    /// After the window: packets were lost in between, or the PSN is invalid.
+    Ahead,
+}
+
+/// A range of consecutive PSNs, wrapping at 2^24.
+///
+/// The responder keeps a one-PSN window at the PSN it expects next: [`PsnClass::InWindow`] is the
+/// expected packet, [`PsnClass::Duplicate`] a retried one to answer again and [`PsnClass::Ahead`]
+/// a sequence error to NAK. The requester keeps its outstanding requests, from the oldest
+/// unacknowledged PSN to the next one to send, and drops responses that are not in there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PsnWindow {
+    start: Psn,
+    len: u32,
+}
+
+impl PsnWindow {
+    /// Creates a window of `len` PSNs from `start`, clamping `len` to [`PSN_WINDOW_MAX`].
+    pub const fn new(start: Psn, len: u32) -> Self {
+        let len = if len > PSN_WINDOW_MAX {
+            PSN_WINDOW_MAX
+        } else {
+            len
+        };
+        Self { start, len }
+    }
+
+    /// Returns the first PSN of the window.
+    pub const fn start(&self) -> Psn {
+        self.start
+    }
+
+    /// Returns the PSN right after the window.
+    pub const fn end(&self) -> Psn {
+        self.start.add(self.len)
+    }
+
+    /// Returns the number of PSNs in the window.
+    pub const fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Returns `true` if the window holds no PSN.
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Tells where `psn` falls relative to the window.
+    ///
+    /// PSNs up to 2^23 before the start are duplicates and the others after the window, as
+    /// IBTA 9.7.5.1.2 prescribes for the responder.
+    pub const fn classify(&self, psn: Psn) -> PsnClass {
+        let diff = psn.diff(self.start);
+        if diff < 0 {
+            PsnClass::Duplicate
+        } else if (diff as u32) < self.len {
+            PsnClass::InWindow(diff as u32)
+        } else {
+            PsnClass::Ahead
+        }
+    }
+
+    /// Returns `true` if `psn` is in the window.
+    pub const fn contains(&self, psn: Psn) -> bool {
+        matches!(self.classify(psn), PsnClass::InWindow(_))
+    }
+
+    /// Grows the window by `n` PSNs at its end, up to [`PSN_WINDOW_MAX`].
+    ///
+    /// Returns `false`, leaving the window unchanged, if it would get too large.
+    pub fn extend(&mut self, n: u32) -> bool {
+        match self.len.checked_add(n) {
+            Some(len) if len <= PSN_WINDOW_MAX => {
+                self.len = len;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves the start of the window forward to `psn`, keeping its end.
+    ///
+    /// Returns the number of PSNs dropped, 0 if `psn` is not in the window or right after it.
+    pub fn advance_to(&mut self, psn: Psn) -> u32 {
+        let diff = psn.diff(self.start);
+        if diff <= 0 || diff as u32 > self.len {
+            return 0;
+        }
+        self.start = psn;
+        self.len -= diff as u32;
+        diff as u32
+    }
+
+    /// Drops every PSN up to and including `psn`, e.g. when it is acknowledged.
+    ///
+    /// Returns the number of PSNs dropped.
+    pub fn acknowledge(&mut self, psn: Psn) -> u32 {
+        self.advance_to(psn.next())
+    }
+
+    /// Slides the whole window forward by `n` PSNs, keeping its length.
+    pub fn slide(&mut self, n: u32) {
+        self.start = self.start.add(n);
+    }
+}
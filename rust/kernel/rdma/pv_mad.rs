@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Paravirtual MAD multiplexing.
+//!
+//! Under SR-IOV only the master owns the real QP0 and QP1 of a port: the slaves send their MADs
+//! to the master through tunnel QPs, and the master forwards the MADs it receives to the slave
+//! they are for, like the C driver's `mlx4_ib_multiplex_mad` and `mlx4_ib_demux_mad`.
+//!
+//! [`multiplex`] stamps the requests a slave sends with its function number in the most
+//! significant byte of the transaction ID, which the slaves leave null, so that [`demux`] can send
+//! the responses back to it. Both filter the management classes a slave may use, and leave the
+//! connection management MADs to the CM proxy, see [`super::cm_proxy`], and the multicast member
+//! records to the multicast proxy, see [`super::mcg`]. Slaves are function numbers, the master
+//! included.
+
+use super::mad::{class, method, MadHdr};
+use super::sa::{self, ATTR_MC_MEMBER_REC};
+
+/// Transaction ID byte of the MADs of the master's own agents, which are not demultiplexed.
+pub const MASTER_TID: u8 = 0xff;
+
+/// Offset of the byte of the transaction ID holding the slave, its most significant one.
+const TID_SLAVE: usize = 8;
+
+/// What to do with a MAD a slave sends, see [`multiplex`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Egress {
+    /// Send it on the wire.
+    Send,
+    /// Hand the multicast member record to the multicast proxy, which joins or leaves the group
+    /// with the SA on behalf of the slave.
+    McMember,
+    /// Drop it.
+    Drop,
+}
+
+/// What to do with a MAD received on a port, see [`demux`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ingress {
+    /// Forward it to the slave.
+    Slave(u8),
+    /// Hand the multicast member record answered by the SA to the multicast proxy.
+    McMember,
+    /// Drop it.
+    Drop,
+}
+
+/// Returns `true` for the request methods whose responses come back to the slave.
+fn is_request(method: u8) -> bool {
+    matches!(
+        method,
+        method::GET
+            | method::SET
+            | method::REPORT
+            | sa::method::GET_TABLE
+            | sa::method::DELETE
+            | sa::method::GET_MULTI
+            | sa::method::GET_TRACE_TBL
+    )
+}
+
+/// Prepares the MAD `mad` sent by `slave` for the wire.
+///
+/// `master` is the function number of the master, and `smi_enabled` tells whether `slave` may
+/// send subnet management packets. `cm` passes a CM MAD through the CM proxy, and returns `false`
+/// if it must be dropped.
+pub fn multiplex(
+    mad: &mut [u8],
+    slave: u8,
+    master: u8,
+    smi_enabled: bool,
+    cm: impl FnOnce(&mut [u8]) -> bool,
+) -> Egress {
+    let hdr = match MadHdr::parse(mad) {
+        Some(hdr) => hdr,
+        None => return Egress::Drop,
+    };
+    if is_request(hdr.method) {
+        // A slave sending requests with its own tags would get responses meant for others.
+        if mad[TID_SLAVE] != 0 {
+            return Egress::Drop;
+        }
+        mad[TID_SLAVE] = slave;
+    }
+    let ok = match hdr.mgmt_class {
+        class::SUBN_LID_ROUTED | class::SUBN_DIRECTED_ROUTE => slave == master || smi_enabled,
+        class::SUBN_ADM => {
+            if hdr.attr_id == ATTR_MC_MEMBER_REC
+                && matches!(hdr.method, method::SET | sa::method::DELETE)
+            {
+                return Egress::McMember;
+            }
+            true
+        }
+        class::CM => cm(mad),
+        class::DEVICE_MGMT => matches!(hdr.method, method::GET | method::SET),
+        // The other classes are not supported for the slaves.
+        _ => slave == master,
+    };
+    if ok {
+        Egress::Send
+    } else {
+        Egress::Drop
+    }
+}
+
+/// Finds the slave the MAD `mad` received on a port is for, and restores its transaction ID.
+///
+/// The responses go to the slave stamped in their transaction ID by [`multiplex`], the other MADs
+/// to the master. `dgid_slave` is the slave owning the destination GID of the GRH, if the MAD has
+/// one, and takes precedence. `num_funcs` is the number of functions, the master included, and
+/// `smi_enabled` tells whether a slave may receive subnet management packets. `cm` passes a CM MAD
+/// through the CM proxy, and returns the slave it is for.
+pub fn demux(
+    mad: &mut [u8],
+    master: u8,
+    num_funcs: u32,
+    dgid_slave: Option<u8>,
+    smi_enabled: impl FnOnce(u8) -> bool,
+    cm: impl FnOnce(&mut [u8]) -> Option<u8>,
+) -> Ingress {
+    let hdr = match MadHdr::parse(mad) {
+        Some(hdr) => hdr,
+        None => return Ingress::Drop,
+    };
+    let mut slave = master;
+    if hdr.is_response() {
+        slave = mad[TID_SLAVE];
+        if slave != MASTER_TID {
+            mad[TID_SLAVE] = 0;
+        }
+    }
+    if let Some(owner) = dgid_slave {
+        slave = owner;
+    }
+    match hdr.mgmt_class {
+        class::SUBN_LID_ROUTED | class::SUBN_DIRECTED_ROUTE => {
+            // Slaves only get the answers to their own SMPs.
+            if slave != MASTER_TID && slave != master && (!smi_enabled(slave) || !hdr.is_response())
+            {
+                return Ingress::Drop;
+            }
+        }
+        class::SUBN_ADM => {
+            if hdr.attr_id == ATTR_MC_MEMBER_REC && hdr.is_response() {
+                return Ingress::McMember;
+            }
+        }
+        class::CM => match cm(mad) {
+            Some(owner) => slave = owner,
+            None => return Ingress::Drop,
+        },
+        class::DEVICE_MGMT => {
+            if hdr.method != method::GET_RESP {
+                return Ingress::Drop;
+            }
+        }
+        _ => {
+            if slave != master {
+                return Ingress::Drop;
+            }
+        }
+    }
+    if u32::from(slave) >= num_funcs {
+        return Ingress::Drop;
+    }
+    Ingress::Slave(slave)
+}
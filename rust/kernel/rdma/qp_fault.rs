@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Queue pair error injection.
+//!
+//! Consumers must recover when `modify_qp` fails or a QP drops into the error state on its own,
+//! which healthy setups almost never do. A [`QpFaultInjector`] makes both happen on demand: its
+//! rules fail chosen transitions, optionally only for one QP or a number of times, and QPs can be
+//! queued to be moved to the error state the next time the provider looks at them. It is driven
+//! by text commands, e.g. written to a debugfs file:
+//!
+//! - `fail <from> <to> [qpn=<n>] [count=<n>]` fails the transitions from `from`, or `any` state,
+//!   to `to`, every time unless a count is given.
+//! - `error <qpn>` moves the QP to the error state.
+//! - `clear` drops every rule and pending error.
+//!
+//! States are named as by [`QpState::name`]. Like the packet faults of [`super::fault`], the
+//! injection is only compiled in with `CONFIG_RUST_RDMA_FAULT_INJECTION`.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use super::qp_state::QpState;
+use super::tunables::TuneError;
+
+/// Number of rules an injector holds.
+pub const MAX_RULES: usize = 8;
+
+/// Number of QPs that can wait to be moved to the error state.
+pub const MAX_PENDING: usize = 8;
+
+/// Largest QP number, QP numbers being 24 bits wide.
+pub const MAX_QPN: u32 = 0xff_ffff;
+
+/// Largest count of a rule.
+pub const MAX_COUNT: u32 = 0xff_ffff;
+
+/// A rule failing `modify_qp` transitions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QpFaultRule {
+    /// State the QP must be in, `None` for any.
+    pub from: Option<QpState>,
+    /// State requested by `modify_qp`.
+    pub to: QpState,
+    /// QP the rule is restricted to, `None` for every QP.
+    pub qpn: Option<u32>,
+    /// Number of transitions left to fail, 0 meaning every one.
+    pub count: u32,
+}
+
+// Layout of a rule slot: bit 63 set if used, bits 0-3 `from` (7 for any), 4-7 `to`, 8-31 `count`,
+// 32-55 `qpn` and bit 56 set if `qpn` applies.
+const USED: u64 = 1 << 63;
+const HAS_QPN: u64 = 1 << 56;
+const ANY_STATE: u64 = 7;
+
+impl QpFaultRule {
+    /// Creates a rule failing every transition from `from` to `to`.
+    pub const fn new(from: Option<QpState>, to: QpState) -> Self {
+        Self {
+            from,
+            to,
+            qpn: None,
+            count: 0,
+        }
+    }
+
+    /// Restricts the rule to the QP numbered `qpn`.
+    pub const fn with_qpn(mut self, qpn: u32) -> Self {
+        self.qpn = Some(qpn);
+        self
+    }
+
+    /// Only fails the next `count` matching transitions.
+    pub const fn with_count(mut self, count: u32) -> Self {
+        self.count = count;
+        self
+    }
+
+    /// Returns `true` if the rule fails the transition of QP `qpn` from `from` to `to`.
+    pub fn matches(&self, qpn: u32, from: QpState, to: QpState) -> bool {
+        (self.from.is_none() || self.from == Some(from))
+            && self.to == to
+            && (self.qpn.is_none() || self.qpn == Some(qpn))
+    }
+
+    fn encode(&self) -> u64 {
+        let from = self.from.map_or(ANY_STATE, |s| s.to_raw() as u64);
+        let qpn = self.qpn.map_or(0, |n| HAS_QPN | (n as u64) << 32);
+        USED | from | (self.to.to_raw() as u64) << 4 | (self.count as u64) << 8 | qpn
+    }
+
+    fn decode(word: u64) -> Option<Self> {
+        if word & USED == 0 {
+            return None;
+        }
+        Some(Self {
+            from: QpState::from_raw((word & 0xf) as u32),
+            to: QpState::from_raw((word >> 4 & 0xf) as u32)?,
+            qpn: if word & HAS_QPN != 0 {
+                Some((word >> 32) as u32 & MAX_QPN)
+            } else {
+                None
+            },
+            count: (word >> 8) as u32 & MAX_COUNT,
+        })
+    }
+}
+
+impl fmt::Display for QpFaultRule {
+    /// Writes the rule as the `fail` command that adds it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = self.from.map_or("any", |s| s.name());
+        write!(f, "fail {} {}", from, self.to.name())?;
+        if let Some(qpn) = self.qpn {
+            write!(f, " qpn={}", qpn)?;
+        }
+        if self.count != 0 {
+            write!(f, " count={}", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+/// A command understood by [`QpFaultInjector::apply`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QpFaultCommand {
+    /// Adds a rule.
+    Fail(QpFaultRule),
+    /// Moves a QP to the error state.
+    Error(u32),
+    /// Drops every rule and pending error.
+    Clear,
+}
+
+impl QpFaultCommand {
+    /// Parses a command, see the [module documentation](self) for the syntax.
+    pub fn parse(text: &str) -> Result<Self, TuneError> {
+        let mut words = text.split_whitespace();
+        let cmd = match words.next().ok_or(TuneError::Parse)? {
+            "clear" => Self::Clear,
+            "error" => Self::Error(parse_qpn(words.next())?),
+            "fail" => {
+                let from = match words.next().ok_or(TuneError::Parse)? {
+                    "any" => None,
+                    name => Some(QpState::from_name(name).ok_or(TuneError::Parse)?),
+                };
+                let to = words
+                    .next()
+                    .and_then(QpState::from_name)
+                    .ok_or(TuneError::Parse)?;
+                let mut rule = QpFaultRule::new(from, to);
+                for option in words.by_ref() {
+                    match option.split_once('=').ok_or(TuneError::Parse)? {
+                        ("qpn", value) => rule.qpn = Some(parse_qpn(Some(value))?),
+                        ("count", value) => {
+                            rule.count = value.parse().map_err(|_| TuneError::Parse)?;
+                            if rule.count > MAX_COUNT {
+                                return Err(TuneError::OutOfRange);
+                            }
+                        }
+                        _ => return Err(TuneError::Parse),
+                    }
+                }
+                Self::Fail(rule)
+            }
+            _ => return Err(TuneError::Parse),
+        };
+        if words.next().is_some() {
+            return Err(TuneError::Parse);
+        }
+        Ok(cmd)
+    }
+}
+
+fn parse_qpn(word: Option<&str>) -> Result<u32, TuneError> {
+    let qpn = word
+        .ok_or(TuneError::Parse)?
+        .parse()
+        .map_err(|_| TuneError::Parse)?;
+    if qpn > MAX_QPN {
+        return Err(TuneError::OutOfRange);
+    }
+    Ok(qpn)
+}
+
+/// Injected `modify_qp` failures and errors of a provider.
+pub struct QpFaultInjector {
+    rules: [AtomicU64; MAX_RULES],
+    // QP numbers plus one, 0 for a free slot.
+    pending: [AtomicU32; MAX_PENDING],
+    failed: AtomicU64,
+    forced: AtomicU64,
+}
+
+impl QpFaultInjector {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_RULE: AtomicU64 = AtomicU64::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_QP: AtomicU32 = AtomicU32::new(0);
+
+    /// Creates an injector without rules, e.g. in a `static`.
+    pub const fn new() -> Self {
+        Self {
+            rules: [Self::NO_RULE; MAX_RULES],
+            pending: [Self::NO_QP; MAX_PENDING],
+            failed: AtomicU64::new(0),
+            forced: AtomicU64::new(0),
+        }
+    }
+
+    /// Adds `rule`.
+    ///
+    /// Fails with [`TuneError::OutOfRange`] if its QP number or count is too large, or all
+    /// [`MAX_RULES`] slots are used.
+    pub fn add_rule(&self, rule: QpFaultRule) -> Result<(), TuneError> {
+        if matches!(rule.qpn, Some(n) if n > MAX_QPN) || rule.count > MAX_COUNT {
+            return Err(TuneError::OutOfRange);
+        }
+        let word = rule.encode();
+        self.rules
+            .iter()
+            .find(|slot| {
+                slot.compare_exchange(0, word, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .map(|_| ())
+            .ok_or(TuneError::OutOfRange)
+    }
+
+    /// Queues QP `qpn` to be moved to the error state, see [`QpFaultInjector::take_error`].
+    ///
+    /// Fails with [`TuneError::OutOfRange`] if all [`MAX_PENDING`] slots are used.
+    pub fn force_error(&self, qpn: u32) -> Result<(), TuneError> {
+        if qpn > MAX_QPN {
+            return Err(TuneError::OutOfRange);
+        }
+        self.pending
+            .iter()
+            .find(|slot| {
+                slot.compare_exchange(0, qpn + 1, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .map(|_| ())
+            .ok_or(TuneError::OutOfRange)
+    }
+
+    /// Drops every rule and pending error. The counters are kept.
+    pub fn clear(&self) {
+        for slot in &self.rules {
+            slot.store(0, Ordering::Release);
+        }
+        for slot in &self.pending {
+            slot.store(0, Ordering::Release);
+        }
+    }
+
+    /// Runs `cmd`.
+    pub fn apply(&self, cmd: QpFaultCommand) -> Result<(), TuneError> {
+        match cmd {
+            QpFaultCommand::Fail(rule) => self.add_rule(rule),
+            QpFaultCommand::Error(qpn) => self.force_error(qpn),
+            QpFaultCommand::Clear => {
+                self.clear();
+                Ok(())
+            }
+        }
+    }
+
+    /// Parses and runs the command in `text`, e.g. written to a debugfs file.
+    pub fn store(&self, text: &str) -> Result<(), TuneError> {
+        self.apply(QpFaultCommand::parse(text)?)
+    }
+
+    /// Returns `true` if some transition or QP may be affected.
+    pub fn is_active(&self) -> bool {
+        self.rules.iter().any(|s| s.load(Ordering::Relaxed) != 0)
+            || self.pending.iter().any(|s| s.load(Ordering::Relaxed) != 0)
+    }
+
+    /// Returns `true` if the transition of QP `qpn` from `from` to `to` must fail.
+    ///
+    /// A matching rule with a count is used up by this call, and dropped once it reaches 0.
+    pub fn fail_modify(&self, qpn: u32, from: QpState, to: QpState) -> bool {
+        for slot in &self.rules {
+            let mut word = slot.load(Ordering::Acquire);
+            loop {
+                let rule = match QpFaultRule::decode(word) {
+                    Some(rule) if rule.matches(qpn, from, to) => rule,
+                    _ => break,
+                };
+                let next = match rule.count {
+                    0 => word,
+                    1 => 0,
+                    n => rule.with_count(n - 1).encode(),
+                };
+                match slot.compare_exchange(word, next, Ordering::AcqRel, Ordering::Acquire) {
+                    Ok(_) => {
+                        self.failed.fetch_add(1, Ordering::Relaxed);
+                        return true;
+                    }
+                    Err(current) => word = current,
+                }
+            }
+        }
+        false
+    }
+
+    /// Returns `true` once if QP `qpn` was queued by [`QpFaultInjector::force_error`].
+    ///
+    /// The caller moves the QP to the error state and reports it to the consumer.
+    pub fn take_error(&self, qpn: u32) -> bool {
+        if qpn > MAX_QPN {
+            return false;
+        }
+        // Only the slot holding the QP is written, as this runs for every post.
+        let taken = self.pending.iter().any(|slot| {
+            slot.load(Ordering::Relaxed) == qpn + 1
+                && slot
+                    .compare_exchange(qpn + 1, 0, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+        });
+        if taken {
+            self.forced.fetch_add(1, Ordering::Relaxed);
+        }
+        taken
+    }
+
+    /// Returns the number of transitions failed so far.
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of QPs moved to the error state so far.
+    pub fn forced(&self) -> u64 {
+        self.forced.load(Ordering::Relaxed)
+    }
+
+    /// Writes the rules and pending errors as the commands that set them up, then the counters.
+    pub fn show(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        for slot in &self.rules {
+            if let Some(rule) = QpFaultRule::decode(slot.load(Ordering::Acquire)) {
+                writeln!(w, "{}", rule)?;
+            }
+        }
+        for slot in &self.pending {
+            let qpn = slot.load(Ordering::Acquire);
+            if qpn != 0 {
+                writeln!(w, "error {}", qpn - 1)?;
+            }
+        }
+        writeln!(w, "failed {}", self.failed())?;
+        writeln!(w, "forced {}", self.forced())
+    }
+}
+
+impl Default for QpFaultInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Queue pair state machine as defined by the IBTA specification.
+
+/// State of a queue pair.
+///
+/// The discriminants match the kernel's `enum ib_qp_state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum QpState {
+    /// Freshly created, no resources are active.
+    Reset = 0,
+    /// Initialised, receive requests may be posted.
+    Init = 1,
+    /// Ready to receive.
+    Rtr = 2,
+    /// Ready to send.
+    Rts = 3,
+    /// Send queue drain.
+    Sqd = 4,
+    /// Send queue error.
+    Sqe = 5,
+    /// Error, all outstanding work requests are flushed.
+    Err = 6,
+}
+
+impl QpState {
+    /// Converts a raw `enum ib_qp_state` value.
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        Some(match raw {
+            0 => Self::Reset,
+            1 => Self::Init,
+            2 => Self::Rtr,
+            3 => Self::Rts,
+            4 => Self::Sqd,
+            5 => Self::Sqe,
+            6 => Self::Err,
+            _ => return None,
+        })
+    }
+
+    /// Returns the raw `enum ib_qp_state` value.
+    pub const fn to_raw(self) -> u32 {
+        self as u32
+    }
+
+    /// Returns the lower-case name of the state, e.g. `rts`.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Reset => "reset",
+            Self::Init => "init",
+            Self::Rtr => "rtr",
+            Self::Rts => "rts",
+            Self::Sqd => "sqd",
+            Self::Sqe => "sqe",
+            Self::Err => "err",
+        }
+    }
+
+    /// Looks a state up by its [`QpState::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..=6)
+            .filter_map(Self::from_raw)
+            .find(|s| s.name() == name)
+    }
+
+    /// Returns `true` if the IBTA state machine allows moving from `self` to `next`.
+    ///
+    /// Every state may go back to `Reset` or to `Err`; the remaining edges follow the
+    /// RESET -> INIT -> RTR -> RTS path plus the SQD and SQE detours.
+    pub const fn can_transition_to(self, next: Self) -> bool {
+        matches!(
+            (self, next),
+            (_, Self::Reset)
+                | (_, Self::Err)
+                | (Self::Reset, Self::Init)
+                | (Self::Init, Self::Init)
+                | (Self::Init, Self::Rtr)
+                | (Self::Rtr, Self::Rts)
+                | (Self::Rts, Self::Rts)
+                | (Self::Rts, Self::Sqd)
+                | (Self::Sqd, Self::Rts)
+                | (Self::Sqd, Self::Sqd)
+                | (Self::Sqe, Self::Rts)
+        )
+    }
+
+    /// Returns `true` if the send queue may process work requests in this state.
+    pub const fn can_send(self) -> bool {
+        matches!(self, Self::Rts)
+    }
+
+    /// Returns `true` if incoming packets may be processed in this state.
+    pub const fn can_receive(self) -> bool {
+        matches!(self, Self::Rtr | Self::Rts | Self::Sqd | Self::Sqe)
+    }
+}
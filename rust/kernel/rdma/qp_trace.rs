@@ -0,0 +1,268 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Flight recorder of QP protocol events.
+//!
+//! When a connection stalls or errors out, the counters say that something went wrong but not in
+//! which order. A [`QpTrace`] keeps the last `N` state changes, ACKs, NAKs, retries and timeouts
+//! of a QP so that they can be dumped once it misbehaves. Recording never blocks and never fails:
+//! the oldest records are overwritten, from any context.
+//!
+//! Each slot is guarded by a sequence number, like a seqlock, and readers skip the slots that are
+//! being written. A writer lapped by another one, `N` records later, before it is done may lose its
+//! record or garble the newer one; that takes `N` events during one record and is accepted for a
+//! debugging aid.
+
+use core::fmt;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+use super::qp_state::QpState;
+
+/// Default depth of the per-QP trace.
+pub const DEFAULT_DEPTH: usize = 64;
+
+/// A protocol event of a QP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QpEvent {
+    /// The QP moved from `from` to `to`.
+    State {
+        /// Previous state.
+        from: QpState,
+        /// New state.
+        to: QpState,
+    },
+    /// Requests up to `psn` were acknowledged.
+    Ack {
+        /// Acknowledged PSN.
+        psn: u32,
+    },
+    /// A NAK was sent or received.
+    Nak {
+        /// PSN of the NAK.
+        psn: u32,
+        /// AETH syndrome.
+        syndrome: u8,
+    },
+    /// Requests from `psn` on are retransmitted.
+    Retry {
+        /// First retransmitted PSN.
+        psn: u32,
+        /// Retries left before the QP errors out.
+        retries_left: u8,
+    },
+    /// Requests from `psn` on are resent after an RNR NAK.
+    RnrRetry {
+        /// First resent PSN.
+        psn: u32,
+        /// RNR retries left, 7 meaning infinite.
+        retries_left: u8,
+    },
+    /// The local ACK timeout expired.
+    AckTimeout,
+    /// The RNR NAK back-off expired.
+    RnrTimeout,
+}
+
+const TAG_STATE: u64 = 1;
+const TAG_ACK: u64 = 2;
+const TAG_NAK: u64 = 3;
+const TAG_RETRY: u64 = 4;
+const TAG_RNR_RETRY: u64 = 5;
+const TAG_ACK_TIMEOUT: u64 = 6;
+const TAG_RNR_TIMEOUT: u64 = 7;
+
+const PSN_MASK: u32 = 0x00ff_ffff;
+
+impl QpEvent {
+    /// Packs the event into a word: the tag in bits 0-7, a PSN in bits 8-31 and an argument in
+    /// bits 32-63.
+    fn encode(self) -> u64 {
+        let (tag, psn, arg) = match self {
+            Self::State { from, to } => (TAG_STATE, 0, from.to_raw() | to.to_raw() << 8),
+            Self::Ack { psn } => (TAG_ACK, psn, 0),
+            Self::Nak { psn, syndrome } => (TAG_NAK, psn, syndrome.into()),
+            Self::Retry { psn, retries_left } => (TAG_RETRY, psn, retries_left.into()),
+            Self::RnrRetry { psn, retries_left } => (TAG_RNR_RETRY, psn, retries_left.into()),
+            Self::AckTimeout => (TAG_ACK_TIMEOUT, 0, 0),
+            Self::RnrTimeout => (TAG_RNR_TIMEOUT, 0, 0),
+        };
+        tag | u64::from(psn & PSN_MASK) << 8 | u64::from(arg) << 32
+    }
+
+    fn decode(word: u64) -> Option<Self> {
+        let psn = (word >> 8) as u32 & PSN_MASK;
+        let arg = (word >> 32) as u32;
+        Some(match word & 0xff {
+            TAG_STATE => Self::State {
+                from: QpState::from_raw(arg & 0xff)?,
+                to: QpState::from_raw(arg >> 8)?,
+            },
+            TAG_ACK => Self::Ack { psn },
+            TAG_NAK => Self::Nak {
+                psn,
+                syndrome: arg as u8,
+            },
+            TAG_RETRY => Self::Retry {
+                psn,
+                retries_left: arg as u8,
+            },
+            TAG_RNR_RETRY => Self::RnrRetry {
+                psn,
+                retries_left: arg as u8,
+            },
+            TAG_ACK_TIMEOUT => Self::AckTimeout,
+            TAG_RNR_TIMEOUT => Self::RnrTimeout,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for QpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::State { from, to } => write!(f, "state {:?} -> {:?}", from, to),
+            Self::Ack { psn } => write!(f, "ack psn {:#08x}", psn),
+            Self::Nak { psn, syndrome } => {
+                write!(f, "nak psn {:#08x} syndrome {:#04x}", psn, syndrome)
+            }
+            Self::Retry { psn, retries_left } => {
+                write!(f, "retry psn {:#08x} left {}", psn, retries_left)
+            }
+            Self::RnrRetry { psn, retries_left } => {
+                write!(f, "rnr retry psn {:#08x} left {}", psn, retries_left)
+            }
+            Self::AckTimeout => f.write_str("ack timeout"),
+            Self::RnrTimeout => f.write_str("rnr timeout"),
+        }
+    }
+}
+
+/// A recorded event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Position of the record since the trace was created.
+    pub seq: u64,
+    /// Time of the event, in the unit of the recorder, e.g. jiffies.
+    pub stamp: u64,
+    /// The event.
+    pub event: QpEvent,
+}
+
+struct Slot {
+    seq: AtomicU64,
+    stamp: AtomicU64,
+    word: AtomicU64,
+}
+
+impl Slot {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Self {
+        seq: AtomicU64::new(0),
+        stamp: AtomicU64::new(0),
+        word: AtomicU64::new(0),
+    };
+}
+
+/// The last `N` protocol events of a QP.
+///
+/// `N` must be a non-zero power of two, which is checked at compile time.
+///
+/// # Invariants
+///
+/// The slot of position `pos` holds a complete record of that position when its sequence is
+/// `2 * pos + 2`, and is being written when it is `2 * pos + 1`.
+pub struct QpTrace<const N: usize = DEFAULT_DEPTH> {
+    slots: [Slot; N],
+    head: AtomicU64,
+}
+
+impl<const N: usize> QpTrace<N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "trace depth must be a power of two");
+        N - 1
+    };
+
+    /// Creates an empty trace.
+    pub const fn new() -> Self {
+        let _ = Self::MASK;
+        Self {
+            slots: [Slot::EMPTY; N],
+            head: AtomicU64::new(0),
+        }
+    }
+
+    /// Records `event` at time `stamp`, overwriting the oldest record if the trace is full.
+    pub fn record(&self, stamp: u64, event: QpEvent) {
+        let pos = self.head.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[pos as usize & Self::MASK];
+        // A writer of a later lap that got there first wins, the older record is lost.
+        if slot.seq.fetch_max(2 * pos + 1, Ordering::Relaxed) > 2 * pos + 1 {
+            return;
+        }
+        // Release: readers that see the new contents also see the odd sequence.
+        fence(Ordering::Release);
+        slot.stamp.store(stamp, Ordering::Relaxed);
+        slot.word.store(event.encode(), Ordering::Relaxed);
+        // Only publish if no later writer took the slot over meanwhile.
+        let _ = slot.seq.compare_exchange(
+            2 * pos + 1,
+            2 * pos + 2,
+            Ordering::Release,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Returns the number of events recorded since the trace was created.
+    pub fn recorded(&self) -> u64 {
+        self.head.load(Ordering::Relaxed)
+    }
+
+    /// Calls `f` with the records still in the trace, oldest first.
+    pub fn for_each(&self, mut f: impl FnMut(TraceRecord)) {
+        let head = self.head.load(Ordering::Acquire);
+        for pos in head.saturating_sub(N as u64)..head {
+            let slot = &self.slots[pos as usize & Self::MASK];
+            let seq = slot.seq.load(Ordering::Acquire);
+            if seq != 2 * pos + 2 {
+                continue;
+            }
+            let stamp = slot.stamp.load(Ordering::Relaxed);
+            let word = slot.word.load(Ordering::Relaxed);
+            // Acquire: the contents are read before the sequence is checked again.
+            fence(Ordering::Acquire);
+            if slot.seq.load(Ordering::Relaxed) != seq {
+                continue;
+            }
+            if let Some(event) = QpEvent::decode(word) {
+                f(TraceRecord {
+                    seq: pos,
+                    stamp,
+                    event,
+                });
+            }
+        }
+    }
+
+    /// Writes the records, oldest first, one per line, e.g. for a debugfs file.
+    pub fn dump(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        let recorded = self.recorded();
+        writeln!(
+            w,
+            "recorded {} lost {}",
+            recorded,
+            recorded.saturating_sub(N as u64)
+        )?;
+        let mut res = Ok(());
+        self.for_each(|r| {
+            if res.is_ok() {
+                res = writeln!(w, "{:>8} {:>12} {}", r.seq, r.stamp, r.event);
+            }
+        });
+        res
+    }
+}
+
+impl<const N: usize> Default for QpTrace<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,330 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Work and completion queues shared with userspace.
+//!
+//! The buffers of soft-RoCE queues are mapped into the verbs consumer, so they follow the layout
+//! of the C driver's `struct rxe_queue_buf`, which librxe in rdma-core relies on: a header with
+//! the element size, the index mask and the producer and consumer indices on their own cache
+//! lines, then a power-of-two number of slots. One slot always stays free to tell a full queue
+//! from an empty one.
+//!
+//! Each side keeps its own index privately and only publishes it, with release semantics, once
+//! the slot is written or read. The other side's index is read with acquire semantics and masked,
+//! as userspace may write anything there.
+
+use core::marker::PhantomData;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use super::scrub::{Scrub, Scrubber};
+
+/// Header of a queue buffer, laid out like `struct rxe_queue_buf`.
+#[repr(C)]
+pub struct QueueHeader {
+    log2_elem_size: u32,
+    index_mask: u32,
+    _pad1: [u32; 30],
+    producer_index: AtomicU32,
+    _pad2: [u32; 31],
+    consumer_index: AtomicU32,
+    _pad3: [u32; 31],
+}
+
+/// Size of [`QueueHeader`], the offset of the first slot.
+pub const HEADER_SIZE: usize = 384;
+
+const _: () = assert!(core::mem::size_of::<QueueHeader>() == HEADER_SIZE);
+
+/// Largest number of slots of a queue.
+pub const MAX_SLOTS: u32 = 1 << 20;
+
+/// Shape of a queue buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geometry {
+    log2_elem_size: u32,
+    slots: u32,
+}
+
+impl Geometry {
+    /// Returns the geometry of a queue holding at least `num_elem` elements of `elem_size`
+    /// bytes, both rounded up to powers of two.
+    ///
+    /// Returns `None` if `elem_size` is 0 or the queue would exceed [`MAX_SLOTS`].
+    pub fn new(num_elem: u32, elem_size: u32) -> Option<Self> {
+        if elem_size == 0 || num_elem >= MAX_SLOTS {
+            return None;
+        }
+        Some(Self {
+            log2_elem_size: elem_size.checked_next_power_of_two()?.trailing_zeros(),
+            slots: (num_elem + 1).next_power_of_two(),
+        })
+    }
+
+    /// Returns the number of elements the queue can hold.
+    pub const fn capacity(&self) -> u32 {
+        self.slots - 1
+    }
+
+    /// Returns the size of a slot.
+    pub const fn elem_size(&self) -> usize {
+        1 << self.log2_elem_size
+    }
+
+    /// Returns the size of the whole buffer, header included.
+    pub const fn buf_size(&self) -> usize {
+        HEADER_SIZE + ((self.slots as usize) << self.log2_elem_size)
+    }
+
+    const fn mask(&self) -> u32 {
+        self.slots - 1
+    }
+}
+
+/// Published indices of a queue, as read by [`Queue::indices`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueIndices {
+    /// Number of slots, a power of two.
+    pub slots: u32,
+    /// Producer index, masked.
+    pub producer: u32,
+    /// Consumer index, masked.
+    pub consumer: u32,
+}
+
+impl QueueIndices {
+    /// Returns the number of elements between the indices.
+    pub const fn count(&self) -> u32 {
+        self.producer.wrapping_sub(self.consumer) & (self.slots - 1)
+    }
+}
+
+/// A queue buffer.
+///
+/// # Invariants
+///
+/// `base` points to a buffer of `geometry.buf_size()` bytes, aligned for [`QueueHeader`], whose
+/// header matches `geometry` and which lives for `'a`.
+pub struct Queue<'a> {
+    base: *mut u8,
+    geometry: Geometry,
+    _p: PhantomData<&'a mut [u8]>,
+}
+
+impl<'a> Queue<'a> {
+    /// Writes the header of an empty queue into the buffer at `base`.
+    ///
+    /// # Safety
+    ///
+    /// `base` must point to `geometry.buf_size()` writable bytes, aligned for [`QueueHeader`],
+    /// that are only accessed through the returned queue, and its mappings, for `'a`.
+    pub unsafe fn init(base: *mut u8, geometry: Geometry) -> Self {
+        let hdr = base.cast::<QueueHeader>();
+        // SAFETY: The caller guarantees `base` is valid and aligned for the header.
+        unsafe {
+            hdr.write(QueueHeader {
+                log2_elem_size: geometry.log2_elem_size,
+                index_mask: geometry.mask(),
+                _pad1: [0; 30],
+                producer_index: AtomicU32::new(0),
+                _pad2: [0; 31],
+                consumer_index: AtomicU32::new(0),
+                _pad3: [0; 31],
+            })
+        };
+        // INVARIANT: The header was just written from `geometry`.
+        Self {
+            base,
+            geometry,
+            _p: PhantomData,
+        }
+    }
+
+    /// Uses the queue in a buffer initialised by [`Queue::init`], keeping its indices.
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Queue::init`], and the buffer must have been initialised with `geometry`.
+    pub unsafe fn attach(base: *mut u8, geometry: Geometry) -> Self {
+        // INVARIANT: The caller guarantees the header matches `geometry`.
+        Self {
+            base,
+            geometry,
+            _p: PhantomData,
+        }
+    }
+
+    /// Returns the shape of the queue.
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    fn header(&self) -> &QueueHeader {
+        // SAFETY: By the type invariants the header is valid, and only accessed atomically or
+        // read after it was written by `init`.
+        unsafe { &*self.base.cast::<QueueHeader>() }
+    }
+
+    /// Returns the slot at `index`, which must be masked.
+    fn slot(&self, index: u32) -> *mut [u8] {
+        let offset = HEADER_SIZE + ((index as usize) << self.geometry.log2_elem_size);
+        // SAFETY: By the type invariants the buffer holds `mask + 1` slots past the header, and
+        // `index` is at most `mask`.
+        let start = unsafe { self.base.add(offset) };
+        ptr::slice_from_raw_parts_mut(start, self.geometry.elem_size())
+    }
+
+    /// Returns the published indices, e.g. for a snapshot of the device state.
+    ///
+    /// The indices of each side are the last ones it published, not the private ones of a
+    /// [`Producer`] or [`Consumer`] in use.
+    pub fn indices(&self) -> QueueIndices {
+        let mask = self.geometry.mask();
+        let hdr = self.header();
+        QueueIndices {
+            slots: self.geometry.slots,
+            producer: hdr.producer_index.load(Ordering::Acquire) & mask,
+            consumer: hdr.consumer_index.load(Ordering::Acquire) & mask,
+        }
+    }
+
+    /// Returns the producer and the consumer of the queue.
+    ///
+    /// When the queue is shared with userspace, the kernel only uses the half it owns: the
+    /// producer of completion queues and the consumer of work queues.
+    pub fn split(&mut self) -> (Producer<'_, 'a>, Consumer<'_, 'a>) {
+        let mask = self.geometry.mask();
+        let hdr = self.header();
+        let producer = hdr.producer_index.load(Ordering::Acquire) & mask;
+        let consumer = hdr.consumer_index.load(Ordering::Acquire) & mask;
+        (
+            Producer {
+                queue: self,
+                index: producer,
+            },
+            Consumer {
+                queue: self,
+                index: consumer,
+            },
+        )
+    }
+}
+
+// SAFETY: The buffer is not tied to a thread.
+unsafe impl Send for Queue<'_> {}
+
+impl Scrub for Queue<'_> {
+    /// Zeroes the slots, on the release of the buffer. The header carries no payload.
+    fn scrub(&mut self, scrubber: &Scrubber) {
+        let len = self.geometry.buf_size() - HEADER_SIZE;
+        // SAFETY: By the type invariants the slots follow the header in the buffer, and borrowing
+        // `self` mutably keeps the producer and the consumer from using them.
+        let slots = unsafe { core::slice::from_raw_parts_mut(self.base.add(HEADER_SIZE), len) };
+        scrubber.release(slots);
+    }
+}
+
+/// The producing half of a [`Queue`].
+pub struct Producer<'q, 'a> {
+    queue: &'q Queue<'a>,
+    index: u32,
+}
+
+impl Producer<'_, '_> {
+    /// Returns the number of queued elements, as far as the producer knows.
+    pub fn count(&self) -> u32 {
+        let hdr = self.queue.header();
+        let consumer = hdr.consumer_index.load(Ordering::Acquire);
+        self.index.wrapping_sub(consumer) & self.queue.geometry.mask()
+    }
+
+    /// Returns `true` if no slot is free.
+    pub fn is_full(&self) -> bool {
+        self.count() == self.queue.geometry.capacity()
+    }
+
+    /// Returns the next free slot, to be filled and then published with [`Producer::advance`].
+    pub fn next_slot(&mut self) -> Option<&mut [u8]> {
+        if self.is_full() {
+            return None;
+        }
+        // SAFETY: The slot is free, so the consumer does not access it until it is published,
+        // and the returned borrow of `self` keeps us from handing it out twice.
+        Some(unsafe { &mut *self.queue.slot(self.index) })
+    }
+
+    /// Publishes the slot returned by [`Producer::next_slot`].
+    pub fn advance(&mut self) {
+        if self.is_full() {
+            return;
+        }
+        self.index = (self.index + 1) & self.queue.geometry.mask();
+        // Release: the consumer sees the slot contents once it sees the index.
+        self.queue
+            .header()
+            .producer_index
+            .store(self.index, Ordering::Release);
+    }
+
+    /// Copies `elem` into the next free slot and publishes it, zero-filling the rest of the slot.
+    ///
+    /// Returns `false` if the queue is full or `elem` does not fit a slot.
+    pub fn push(&mut self, elem: &[u8]) -> bool {
+        let slot = match self.next_slot() {
+            Some(slot) if elem.len() <= slot.len() => slot,
+            _ => return false,
+        };
+        slot[..elem.len()].copy_from_slice(elem);
+        slot[elem.len()..].fill(0);
+        self.advance();
+        true
+    }
+}
+
+// SAFETY: The producer only touches free slots and the atomic indices.
+unsafe impl Send for Producer<'_, '_> {}
+
+/// The consuming half of a [`Queue`].
+pub struct Consumer<'q, 'a> {
+    queue: &'q Queue<'a>,
+    index: u32,
+}
+
+impl Consumer<'_, '_> {
+    /// Returns the number of queued elements.
+    pub fn count(&self) -> u32 {
+        // Acquire: the slots up to the index are written.
+        let producer = self.queue.header().producer_index.load(Ordering::Acquire);
+        producer.wrapping_sub(self.index) & self.queue.geometry.mask()
+    }
+
+    /// Returns `true` if nothing is queued.
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Returns the oldest queued element, to be released with [`Consumer::advance`].
+    pub fn peek(&mut self) -> Option<&mut [u8]> {
+        if self.is_empty() {
+            return None;
+        }
+        // SAFETY: The slot is published, so the producer does not touch it until it is
+        // released, and the returned borrow of `self` keeps us from handing it out twice.
+        Some(unsafe { &mut *self.queue.slot(self.index) })
+    }
+
+    /// Releases the element returned by [`Consumer::peek`].
+    pub fn advance(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        self.index = (self.index + 1) & self.queue.geometry.mask();
+        // Release: the producer only reuses the slot once we are done reading it.
+        self.queue
+            .header()
+            .consumer_index
+            .store(self.index, Ordering::Release);
+    }
+}
+
+// SAFETY: The consumer only touches published slots and the atomic indices.
+unsafe impl Send for Consumer<'_, '_> {}
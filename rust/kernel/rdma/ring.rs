@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Fixed-capacity ring buffer for small, hot queues.
+//!
+//! The capacity is a const generic, so the storage lives inline in the owning structure and index
+//! wrapping compiles down to a constant mask. This is meant for per-packet structures such as the
+//! ACK pool or the responder resources, where a heap allocation and runtime capacity math would
+//! sit on the fast path. Synchronisation is left to the owner.
+
+use core::mem::MaybeUninit;
+
+/// A FIFO ring holding at most `N` elements inline.
+///
+/// `N` must be a non-zero power of two, which is checked at compile time.
+///
+/// # Invariants
+///
+/// The slots `tail..head` (taken modulo `N`) are initialised, all others are not, and
+/// `head - tail <= N` in wrapping arithmetic.
+pub struct Ring<T, const N: usize> {
+    slots: [MaybeUninit<T>; N],
+    head: usize,
+    tail: usize,
+}
+
+impl<T, const N: usize> Ring<T, N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "ring capacity must be a power of two");
+        N - 1
+    };
+
+    /// Creates an empty ring.
+    pub const fn new() -> Self {
+        let _ = Self::MASK;
+        Self {
+            // SAFETY: An array of `MaybeUninit` does not require initialisation.
+            slots: unsafe { MaybeUninit::uninit().assume_init() },
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    /// Returns the capacity of the ring.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of queued elements.
+    pub const fn len(&self) -> usize {
+        self.head.wrapping_sub(self.tail)
+    }
+
+    /// Returns `true` if no element is queued.
+    pub const fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    /// Returns `true` if no more element can be queued.
+    pub const fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Appends `value` at the back of the ring.
+    ///
+    /// Hands `value` back if the ring is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        self.slots[self.head & Self::MASK].write(value);
+        self.head = self.head.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Removes and returns the element at the front of the ring.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let slot = &self.slots[self.tail & Self::MASK];
+        self.tail = self.tail.wrapping_add(1);
+        // SAFETY: The slot was in `tail..head`, so it is initialised, and moving `tail` past it
+        // ensures it is never read again.
+        Some(unsafe { slot.assume_init_read() })
+    }
+
+    /// Returns a reference to the element at the front of the ring.
+    pub fn front(&self) -> Option<&T> {
+        if self.is_empty() {
+            return None;
+        }
+        // SAFETY: The slot at `tail` is initialised because the ring is not empty.
+        Some(unsafe { self.slots[self.tail & Self::MASK].assume_init_ref() })
+    }
+
+    /// Returns a mutable reference to the element at the front of the ring.
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        if self.is_empty() {
+            return None;
+        }
+        // SAFETY: The slot at `tail` is initialised because the ring is not empty.
+        Some(unsafe { self.slots[self.tail & Self::MASK].assume_init_mut() })
+    }
+
+    /// Returns a reference to the `index`-th element counted from the front.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len() {
+            return None;
+        }
+        let slot = &self.slots[self.tail.wrapping_add(index) & Self::MASK];
+        // SAFETY: `index < len`, so the slot is in `tail..head` and initialised.
+        Some(unsafe { slot.assume_init_ref() })
+    }
+
+    /// Iterates over the queued elements from front to back.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+
+    /// Drops every queued element.
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T, const N: usize> Default for Ring<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Ring<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
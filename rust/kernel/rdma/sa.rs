@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Subnet administration queries.
+//!
+//! The SA answers queries on the records of the subnet, such as the paths between two ports or
+//! the members of a multicast group. A query is a record with the fields to match set, and a
+//! component mask telling which fields those are, bit `n` standing for the `n`-th field of the
+//! record in the order of the spec. [`SaCompMask`] holds such masks, with the bits of the two
+//! records the connection setup needs.
+
+use core::ops::BitOr;
+
+/// Attribute ID of path records.
+pub const ATTR_PATH_REC: u16 = 0x0035;
+
+/// Attribute ID of multicast member records.
+pub const ATTR_MC_MEMBER_REC: u16 = 0x0038;
+
+/// Methods of the SA on top of the common ones, `IB_SA_METHOD_*`.
+pub mod method {
+    /// Reads every record matching the query.
+    pub const GET_TABLE: u8 = 0x12;
+    /// Reads the path records of a trace.
+    pub const GET_TRACE_TBL: u8 = 0x13;
+    /// Reads the records matching a multi-part query.
+    pub const GET_MULTI: u8 = 0x14;
+    /// Deletes a record, e.g. leaves a multicast group.
+    pub const DELETE: u8 = 0x15;
+}
+
+/// Component mask of an SA query, `ib_sa_comp_mask` in host order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SaCompMask(u64);
+
+impl SaCompMask {
+    /// No component: every record matches.
+    pub const NONE: Self = Self(0);
+
+    /// Service ID of a path, which spans two components.
+    pub const PATH_SERVICE_ID: Self = Self(0b11);
+    /// Destination GID of a path.
+    pub const PATH_DGID: Self = Self(1 << 2);
+    /// Source GID of a path.
+    pub const PATH_SGID: Self = Self(1 << 3);
+    /// Destination LID of a path.
+    pub const PATH_DLID: Self = Self(1 << 4);
+    /// Source LID of a path.
+    pub const PATH_SLID: Self = Self(1 << 5);
+    /// Traffic class of a path.
+    pub const PATH_TRAFFIC_CLASS: Self = Self(1 << 10);
+    /// Whether the path must be usable in both directions.
+    pub const PATH_REVERSIBLE: Self = Self(1 << 11);
+    /// Largest number of paths to return.
+    pub const PATH_NUMB_PATH: Self = Self(1 << 12);
+    /// P_Key of a path.
+    pub const PATH_PKEY: Self = Self(1 << 13);
+    /// Service level of a path.
+    pub const PATH_SL: Self = Self(1 << 15);
+    /// MTU selector of a path.
+    pub const PATH_MTU_SELECTOR: Self = Self(1 << 16);
+    /// MTU of a path.
+    pub const PATH_MTU: Self = Self(1 << 17);
+
+    /// Multicast GID of a group.
+    pub const MCMEMBER_MGID: Self = Self(1 << 0);
+    /// GID of the member port.
+    pub const MCMEMBER_PORT_GID: Self = Self(1 << 1);
+    /// Q_Key of a group.
+    pub const MCMEMBER_QKEY: Self = Self(1 << 2);
+    /// Multicast LID of a group.
+    pub const MCMEMBER_MLID: Self = Self(1 << 3);
+    /// MTU of a group.
+    pub const MCMEMBER_MTU: Self = Self(1 << 5);
+    /// Traffic class of a group.
+    pub const MCMEMBER_TRAFFIC_CLASS: Self = Self(1 << 6);
+    /// P_Key of a group.
+    pub const MCMEMBER_PKEY: Self = Self(1 << 7);
+    /// Rate of a group.
+    pub const MCMEMBER_RATE: Self = Self(1 << 9);
+    /// Service level of a group.
+    pub const MCMEMBER_SL: Self = Self(1 << 12);
+    /// Flow label of a group.
+    pub const MCMEMBER_FLOW_LABEL: Self = Self(1 << 13);
+    /// Hop limit of a group.
+    pub const MCMEMBER_HOP_LIMIT: Self = Self(1 << 14);
+    /// Scope of a group.
+    pub const MCMEMBER_SCOPE: Self = Self(1 << 15);
+    /// Join state of the member.
+    pub const MCMEMBER_JOIN_STATE: Self = Self(1 << 16);
+
+    /// The components a join of an existing group must set, like `ipoib` and `rdma_cm` do.
+    pub const MCMEMBER_JOIN: Self = Self(
+        Self::MCMEMBER_MGID.0
+            | Self::MCMEMBER_PORT_GID.0
+            | Self::MCMEMBER_PKEY.0
+            | Self::MCMEMBER_JOIN_STATE.0,
+    );
+
+    /// Returns the mask of `bits`.
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    /// Returns the raw bits, in host order.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the mask in network order, as the SA MADs and the `ib_sa_*` functions take it.
+    pub const fn to_be(self) -> u64 {
+        self.0.to_be()
+    }
+
+    /// Returns the union of `self` and `other`.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns `true` if every component of `other` is set in `self`.
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for SaCompMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        self.union(rhs)
+    }
+}
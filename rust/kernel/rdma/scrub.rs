@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Scrubbing of freed payload buffers.
+//!
+//! Soft devices are shared by every user of the host, and the buffers that carry RDMA payloads
+//! (queue rings, MR bounce buffers) go back to pools once the objects using them are destroyed. In
+//! hardened setups a [`Scrubber`] zeroes them on the way back so the next user cannot read stale
+//! payloads. It is off by default since it costs a full write of every released buffer.
+//!
+//! Objects that carry payloads implement [`Scrub`] to hand their buffers to the scrubber when they
+//! are released.
+
+use core::sync::atomic::{compiler_fence, AtomicBool, AtomicU64, Ordering};
+
+/// Zeroes `buf` in a way the compiler cannot elide, like `memzero_explicit()`.
+pub fn scrub(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        // SAFETY: `byte` is a valid, exclusive reference.
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Scrubbing policy of a device, with a count of the bytes it zeroed.
+///
+/// Providers keep one in the data of each device, so that hardened devices can scrub while the
+/// others sharing the host do not pay for it, and hand it to the release of the device's buffers.
+pub struct Scrubber {
+    enabled: AtomicBool,
+    scrubbed: AtomicU64,
+}
+
+impl Scrubber {
+    /// Creates a disabled scrubber.
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            scrubbed: AtomicU64::new(0),
+        }
+    }
+
+    /// Enables or disables scrubbing; buffers released from then on follow the new policy.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if released buffers are scrubbed.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Prepares `buf` for its return to a pool, zeroing it if scrubbing is enabled.
+    pub fn release(&self, buf: &mut [u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+        scrub(buf);
+        self.scrubbed.fetch_add(buf.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the number of bytes zeroed so far.
+    pub fn scrubbed(&self) -> u64 {
+        self.scrubbed.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for Scrubber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An object carrying payloads, e.g. a queue or the bounce buffer of an MR.
+pub trait Scrub {
+    /// Hands every payload buffer of the object to [`Scrubber::release`].
+    fn scrub(&mut self, scrubber: &Scrubber);
+}
+
+impl Scrub for [u8] {
+    fn scrub(&mut self, scrubber: &Scrubber) {
+        scrubber.release(self);
+    }
+}
+
+impl<const N: usize> Scrub for [u8; N] {
+    fn scrub(&mut self, scrubber: &Scrubber) {
+        scrubber.release(self);
+    }
+}
@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Point-in-time dumps of the state of a device.
+//!
+//! Bug reports are much more useful with the state the device was in: which objects were alive and
+//! where the queue indices stood. [`Snapshot`] writes that state as JSON lines, one object per
+//! record with a `"type"` field, so that it is both readable in a report and easy to load into a
+//! script for offline analysis.
+//!
+//! Every record is read consistently on its own, pools under RCU and queue indices atomically, but
+//! the snapshot as a whole is not atomic: objects may come and go while it is written.
+
+use core::fmt::{self, Write};
+
+use super::queue::QueueIndices;
+use super::tracker::{LiveResources, ResourceKind, ResourceTracker};
+
+/// Version of the record layout, bumped when fields change meaning.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A snapshot being written.
+///
+/// Writing stops at the first error, which [`Snapshot::finish`] returns.
+pub struct Snapshot<'w> {
+    w: &'w mut dyn Write,
+    res: fmt::Result,
+    records: u32,
+}
+
+impl<'w> Snapshot<'w> {
+    /// Starts a snapshot taken at tick `stamp` by writing its header record.
+    pub fn new(w: &'w mut dyn Write, stamp: u64) -> Self {
+        let mut snap = Self {
+            w,
+            res: Ok(()),
+            records: 0,
+        };
+        snap.record("snapshot")
+            .u64("version", SNAPSHOT_VERSION as u64)
+            .u64("stamp", stamp)
+            .finish();
+        snap
+    }
+
+    /// Starts a record of type `ty`, to be completed with fields and [`Record::finish`].
+    pub fn record(&mut self, ty: &str) -> Record<'_, 'w> {
+        let mut rec = Record { snap: self };
+        rec.raw(format_args!("{{\"type\":"));
+        rec.string(ty);
+        rec
+    }
+
+    /// Writes one record per kind with the number of live objects of `tracker`.
+    pub fn resources(&mut self, tracker: &ResourceTracker) {
+        for kind in ResourceKind::ALL {
+            self.record("resources")
+                .str("kind", kind.name())
+                .u64("live", tracker.live(kind) as u64)
+                .finish();
+        }
+    }
+
+    /// Writes a record for a pool of `capacity` objects, then one per object alive in it.
+    pub fn pool(&mut self, kind: ResourceKind, capacity: u32, pool: &dyn LiveResources) {
+        let mut len = 0;
+        pool.for_each_live(&mut |_| len += 1);
+        self.record("pool")
+            .str("kind", kind.name())
+            .u64("len", len)
+            .u64("capacity", capacity as u64)
+            .finish();
+        self.objects(pool);
+    }
+
+    /// Writes one record per object alive in `pool`.
+    pub fn objects(&mut self, pool: &dyn LiveResources) {
+        pool.for_each_live(&mut |obj| {
+            self.record("object")
+                .str("kind", obj.kind.name())
+                .u64("index", obj.index as u64)
+                .u64("owner", obj.owner as u64)
+                .finish();
+        });
+    }
+
+    /// Writes a record for the queue `name` of the object `owner`, e.g. the `"sq"` of a QP.
+    pub fn queue(&mut self, owner: u32, name: &str, queue: QueueIndices) {
+        self.record("queue")
+            .u64("owner", owner as u64)
+            .str("name", name)
+            .u64("slots", queue.slots as u64)
+            .u64("producer", queue.producer as u64)
+            .u64("consumer", queue.consumer as u64)
+            .u64("count", queue.count() as u64)
+            .finish();
+    }
+
+    /// Returns the number of records written, the header included.
+    pub fn records(&self) -> u32 {
+        self.records
+    }
+
+    /// Returns the first error met while writing.
+    pub fn finish(self) -> fmt::Result {
+        self.res
+    }
+}
+
+/// A record being written.
+pub struct Record<'s, 'w> {
+    snap: &'s mut Snapshot<'w>,
+}
+
+impl Record<'_, '_> {
+    fn raw(&mut self, args: fmt::Arguments<'_>) {
+        if self.snap.res.is_ok() {
+            self.snap.res = self.snap.w.write_fmt(args);
+        }
+    }
+
+    /// Writes `s` as a JSON string, escaping quotes, backslashes and control characters.
+    fn string(&mut self, s: &str) {
+        self.raw(format_args!("\""));
+        for c in s.chars() {
+            match c {
+                '"' | '\\' => self.raw(format_args!("\\{}", c)),
+                c if (c as u32) < 0x20 => self.raw(format_args!("\\u{:04x}", c as u32)),
+                c => self.raw(format_args!("{}", c)),
+            }
+        }
+        self.raw(format_args!("\""));
+    }
+
+    fn key(&mut self, name: &str) {
+        self.raw(format_args!(","));
+        self.string(name);
+        self.raw(format_args!(":"));
+    }
+
+    /// Adds the integer field `name`.
+    pub fn u64(mut self, name: &str, value: u64) -> Self {
+        self.key(name);
+        self.raw(format_args!("{}", value));
+        self
+    }
+
+    /// Adds the string field `name`.
+    pub fn str(mut self, name: &str, value: &str) -> Self {
+        self.key(name);
+        self.string(value);
+        self
+    }
+
+    /// Adds the boolean field `name`.
+    pub fn bool(mut self, name: &str, value: bool) -> Self {
+        self.key(name);
+        self.raw(format_args!("{}", value));
+        self
+    }
+
+    /// Ends the record.
+    pub fn finish(mut self) {
+        self.raw(format_args!("}}\n"));
+        self.snap.records += 1;
+    }
+}
@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Scheduling state of the per-QP tasks.
+//!
+//! The requester, responder and completer of a soft-RoCE QP each run as a task that may be asked
+//! to run from many contexts at once: posting a work request, receiving a packet, a timer. Like the
+//! C driver's `rxe_task.c`, [`TaskState`] makes sure one pass runs at a time: a request while the
+//! task runs arms it instead, and the running pass loops once more before going idle. Tasks can be
+//! disabled, which makes the current pass stop at the next iteration and remembers requests for
+//! when they are enabled again, and killed for good before their QP is destroyed.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+const BUSY: u8 = 1 << 0;
+const ARMED: u8 = 1 << 1;
+const DISABLED: u8 = 1 << 2;
+const DEAD: u8 = 1 << 3;
+
+/// Outcome of one call of a task function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    /// Some work was done and more may be pending, call again.
+    More,
+    /// Nothing left to do.
+    Done,
+}
+
+/// What the owner of a reservation does after [`TaskState::finish`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Finish {
+    /// The reservation was released.
+    Idle,
+    /// The task was armed while running, run another pass.
+    Rerun,
+}
+
+/// How [`TaskState::drive`] returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Drive {
+    /// The task ran out of work and released its reservation.
+    Idle,
+    /// The task used up its budget and still holds its reservation: requeue it.
+    Yield,
+    /// The task was disabled and released its reservation, to run again once enabled.
+    Paused,
+}
+
+/// Scheduling state of one task.
+#[derive(Debug, Default)]
+pub struct TaskState(AtomicU8);
+
+impl TaskState {
+    /// Creates an idle, enabled task.
+    pub const fn new() -> Self {
+        Self(AtomicU8::new(0))
+    }
+
+    fn update(&self, f: impl FnMut(u8) -> Option<u8>) -> u8 {
+        match self.0.fetch_update(Ordering::AcqRel, Ordering::Acquire, f) {
+            Ok(old) | Err(old) => old,
+        }
+    }
+
+    /// Asks the task to run.
+    ///
+    /// Returns `true` if the caller got the reservation and must run the task or queue it. The
+    /// request is remembered otherwise, unless the task is dead.
+    pub fn reserve(&self) -> bool {
+        let old = self.update(|s| {
+            if s & DEAD != 0 {
+                None
+            } else if s & (BUSY | DISABLED) != 0 {
+                Some(s | ARMED)
+            } else {
+                Some((s | BUSY) & !ARMED)
+            }
+        });
+        old & (BUSY | DISABLED | DEAD) == 0
+    }
+
+    /// Called by the owner of the reservation when a pass found no work.
+    pub fn finish(&self) -> Finish {
+        let old = self.update(|s| {
+            if s & ARMED != 0 && s & DISABLED == 0 {
+                Some(s & !ARMED)
+            } else {
+                Some(s & !BUSY)
+            }
+        });
+        if old & ARMED != 0 && old & DISABLED == 0 {
+            Finish::Rerun
+        } else {
+            Finish::Idle
+        }
+    }
+
+    /// Releases the reservation of a disabled task, to run it again once enabled.
+    pub fn pause(&self) {
+        self.update(|s| {
+            if s & DEAD != 0 {
+                Some(s & !BUSY)
+            } else {
+                Some((s & !BUSY) | ARMED)
+            }
+        });
+    }
+
+    /// Runs passes of `pass` while it has work, for at most `budget` passes.
+    ///
+    /// The caller must own the reservation.
+    pub fn drive(&self, budget: usize, mut pass: impl FnMut() -> Pass) -> Drive {
+        let mut passes = 0;
+        loop {
+            if self.is_disabled() {
+                self.pause();
+                return Drive::Paused;
+            }
+            if passes == budget {
+                return Drive::Yield;
+            }
+            passes += 1;
+            if pass() == Pass::Done && self.finish() == Finish::Idle {
+                return Drive::Idle;
+            }
+        }
+    }
+
+    /// Makes the running pass, if any, stop at its next iteration, and holds back new ones.
+    ///
+    /// The caller must wait for [`TaskState::is_busy`] to clear before relying on the task being
+    /// stopped.
+    pub fn disable(&self) {
+        self.update(|s| Some(s | DISABLED));
+    }
+
+    /// Lets the task run again.
+    ///
+    /// Returns `true` if it was asked to run while disabled; the caller then got the reservation
+    /// and must run the task or queue it.
+    pub fn enable(&self) -> bool {
+        let old = self.update(|s| {
+            if s & DEAD != 0 {
+                None
+            } else if s & ARMED != 0 && s & BUSY == 0 {
+                Some((s & !(DISABLED | ARMED)) | BUSY)
+            } else {
+                Some(s & !DISABLED)
+            }
+        });
+        old & DEAD == 0 && old & ARMED != 0 && old & BUSY == 0
+    }
+
+    /// Disables the task for good, e.g. before destroying its QP.
+    pub fn kill(&self) {
+        self.update(|s| Some((s | DISABLED | DEAD) & !ARMED));
+    }
+
+    /// Returns `true` while a pass holds the reservation.
+    pub fn is_busy(&self) -> bool {
+        self.0.load(Ordering::Acquire) & BUSY != 0
+    }
+
+    /// Returns `true` if the task is disabled or dead.
+    pub fn is_disabled(&self) -> bool {
+        self.0.load(Ordering::Acquire) & DISABLED != 0
+    }
+
+    /// Returns `true` if the task was asked to run again and has not yet.
+    pub fn is_armed(&self) -> bool {
+        self.0.load(Ordering::Acquire) & ARMED != 0
+    }
+}
@@ -0,0 +1,40 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Timeouts of reliable connected QPs.
+//!
+//! The local ACK timeout is set as an exponent, `4.096 us << timeout` with 0 disabling it, and
+//! the RNR NAK timer as a 5-bit code looked up in a table of the IBTA specification (table 45).
+//! These helpers turn both into microseconds for the per-QP timers.
+
+/// Delay in microseconds of each RNR NAK timer code, code 0 being the longest.
+const RNR_NAK_USECS: [u32; 32] = [
+    655_360, 10, 20, 30, 40, 60, 80, 120, 160, 240, 320, 480, 640, 960, 1_280, 1_920, 2_560, 3_840,
+    5_120, 7_680, 10_240, 15_360, 20_480, 30_720, 40_960, 61_440, 81_920, 122_880, 163_840,
+    245_760, 327_680, 491_520,
+];
+
+/// Largest valid local ACK timeout exponent.
+pub const MAX_ACK_TIMEOUT: u8 = 31;
+
+/// Returns the local ACK timeout in microseconds, or `None` if `timeout` is 0 and the
+/// retransmit timer is disabled.
+///
+/// Values above [`MAX_ACK_TIMEOUT`] are clamped to it.
+pub const fn ack_timeout_usecs(timeout: u8) -> Option<u64> {
+    if timeout == 0 {
+        return None;
+    }
+    let exp = if timeout > MAX_ACK_TIMEOUT {
+        MAX_ACK_TIMEOUT
+    } else {
+        timeout
+    };
+    Some((4096u64 << exp) / 1000)
+}
+
+/// Returns the delay in microseconds encoded by the RNR NAK timer `code`.
+///
+/// Only the low five bits of `code` are used, as in the AETH syndrome.
+pub const fn rnr_nak_usecs(code: u8) -> u32 {
+    RNR_NAK_USECS[(code & 0x1f) as usize]
+}
@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Fixed-capacity map whose entries can be scheduled to expire.
+//!
+//! Paravirtualisation layers such as the mlx4 CM proxy remember identifiers on behalf of their
+//! guests and must forget them some time after the connection is torn down, once late MADs can no
+//! longer refer to them. [`TimeoutMap`] keeps such entries inline, so it can live in a `static`,
+//! and only ages the ones that were scheduled: the owner calls [`TimeoutMap::expire`] from a
+//! delayed work item armed for [`TimeoutMap::next_deadline`].
+//!
+//! Deadlines are ticks of a clock such as `get_jiffies_64()`, compared like `time_after_eq64()`
+//! so that they may wrap.
+//! Synchronisation is left to the owner.
+
+/// Returns `true` if `now` is at or after `deadline` on a wrapping clock, like `time_after_eq64()`.
+pub const fn reached(now: u64, deadline: u64) -> bool {
+    now.wrapping_sub(deadline) as i64 >= 0
+}
+
+struct Entry<K, V> {
+    key: K,
+    value: V,
+    deadline: Option<u64>,
+}
+
+/// A map of at most `N` entries, each of which may be scheduled to expire.
+pub struct TimeoutMap<K, V, const N: usize> {
+    slots: [Option<Entry<K, V>>; N],
+    len: usize,
+}
+
+impl<K: PartialEq, V, const N: usize> TimeoutMap<K, V, N> {
+    const EMPTY: Option<Entry<K, V>> = None;
+
+    /// Creates an empty map.
+    pub const fn new() -> Self {
+        Self {
+            slots: [Self::EMPTY; N],
+            len: 0,
+        }
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the map holds no entry.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the largest number of entries.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    fn position(&self, key: &K) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| matches!(slot, Some(e) if e.key == *key))
+    }
+
+    /// Inserts `value` under `key`, not scheduled to expire, and returns the previous value.
+    ///
+    /// Hands `key` and `value` back if the map is full.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        if let Some(i) = self.position(&key) {
+            let old = self.slots[i].replace(Entry {
+                key,
+                value,
+                deadline: None,
+            });
+            return Ok(old.map(|e| e.value));
+        }
+        match self.slots.iter().position(Option::is_none) {
+            Some(i) => {
+                self.slots[i] = Some(Entry {
+                    key,
+                    value,
+                    deadline: None,
+                });
+                self.len += 1;
+                Ok(None)
+            }
+            None => Err((key, value)),
+        }
+    }
+
+    /// Returns the value under `key`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.position(key)
+            .and_then(|i| self.slots[i].as_ref())
+            .map(|e| &e.value)
+    }
+
+    /// Returns the first entry `pred` accepts, e.g. to look an entry up by value.
+    pub fn find(&self, mut pred: impl FnMut(&K, &V) -> bool) -> Option<(&K, &V)> {
+        self.slots
+            .iter()
+            .flatten()
+            .find(|e| pred(&e.key, &e.value))
+            .map(|e| (&e.key, &e.value))
+    }
+
+    /// Removes the entry under `key` and returns its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.slots[self.position(key)?].take()?;
+        self.len -= 1;
+        Some(entry.value)
+    }
+
+    /// Removes the entries `pred` rejects.
+    pub fn retain(&mut self, mut pred: impl FnMut(&K, &V) -> bool) {
+        for slot in &mut self.slots {
+            if matches!(slot, Some(e) if !pred(&e.key, &e.value)) {
+                *slot = None;
+                self.len -= 1;
+            }
+        }
+    }
+
+    /// Schedules the entry under `key` to expire at `deadline`.
+    ///
+    /// An entry already scheduled keeps its deadline. Returns `false` if there is no such entry.
+    pub fn schedule(&mut self, key: &K, deadline: u64) -> bool {
+        match self.position(key) {
+            Some(i) => {
+                if let Some(e) = &mut self.slots[i] {
+                    e.deadline.get_or_insert(deadline);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancels the expiry of the entry under `key`.
+    ///
+    /// Returns `false` if there is no such entry or it was not scheduled.
+    pub fn cancel(&mut self, key: &K) -> bool {
+        match self.position(key).and_then(|i| self.slots[i].as_mut()) {
+            Some(e) => e.deadline.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Removes the entries whose deadline is reached at `now`, passing each of them to `f`.
+    ///
+    /// Returns the number of entries removed.
+    pub fn expire(&mut self, now: u64, mut f: impl FnMut(K, V)) -> usize {
+        let mut count = 0;
+        for slot in &mut self.slots {
+            if matches!(slot, Some(Entry { deadline: Some(d), .. }) if reached(now, *d)) {
+                if let Some(e) = slot.take() {
+                    self.len -= 1;
+                    count += 1;
+                    f(e.key, e.value);
+                }
+            }
+        }
+        count
+    }
+
+    /// Returns the earliest deadline of the scheduled entries, as seen from `now`.
+    pub fn next_deadline(&self, now: u64) -> Option<u64> {
+        self.slots
+            .iter()
+            .flatten()
+            .filter_map(|e| e.deadline)
+            .min_by_key(|d| d.wrapping_sub(now) as i64)
+    }
+}
+
+impl<K: PartialEq, V, const N: usize> Default for TimeoutMap<K, V, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
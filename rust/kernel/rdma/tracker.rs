@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Accounting of live verbs objects, used to report leaks when a device goes away.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Kind of a tracked verbs object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// Protection domain.
+    Pd,
+    /// Completion queue.
+    Cq,
+    /// Queue pair.
+    Qp,
+    /// Shared receive queue.
+    Srq,
+    /// Memory region.
+    Mr,
+    /// Memory window.
+    Mw,
+    /// Address handle.
+    Ah,
+}
+
+impl ResourceKind {
+    /// Every kind, in reporting order.
+    pub const ALL: [ResourceKind; 7] = [
+        ResourceKind::Pd,
+        ResourceKind::Cq,
+        ResourceKind::Qp,
+        ResourceKind::Srq,
+        ResourceKind::Mr,
+        ResourceKind::Mw,
+        ResourceKind::Ah,
+    ];
+
+    /// Returns the short name used in reports.
+    pub const fn name(self) -> &'static str {
+        match self {
+            ResourceKind::Pd => "PD",
+            ResourceKind::Cq => "CQ",
+            ResourceKind::Qp => "QP",
+            ResourceKind::Srq => "SRQ",
+            ResourceKind::Mr => "MR",
+            ResourceKind::Mw => "MW",
+            ResourceKind::Ah => "AH",
+        }
+    }
+
+    const fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// One live object, as reported by a [`LiveResources`] implementation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LiveResource {
+    /// Kind of the object.
+    pub kind: ResourceKind,
+    /// Pool index of the object.
+    pub index: u32,
+    /// PID of the task that created the object, 0 for kernel users.
+    pub owner: u32,
+}
+
+/// Implemented by object pools that can enumerate their live entries.
+pub trait LiveResources {
+    /// Calls `f` for every object still alive.
+    fn for_each_live(&self, f: &mut dyn FnMut(LiveResource));
+}
+
+/// Counts of live objects per kind.
+pub struct ResourceTracker {
+    live: [AtomicUsize; 7],
+    strict: AtomicBool,
+}
+
+impl ResourceTracker {
+    /// Creates a tracker with no live object, in non-strict mode.
+    pub const fn new() -> Self {
+        Self {
+            live: [
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            ],
+            strict: AtomicBool::new(false),
+        }
+    }
+
+    /// Records the creation of an object of `kind`.
+    pub fn add(&self, kind: ResourceKind) {
+        self.live[kind.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the destruction of an object of `kind`.
+    pub fn remove(&self, kind: ResourceKind) {
+        let prev = self.live[kind.index()].fetch_sub(1, Ordering::Relaxed);
+        debug_assert!(prev > 0, "unbalanced resource accounting");
+    }
+
+    /// Returns the number of live objects of `kind`.
+    pub fn live(&self, kind: ResourceKind) -> usize {
+        self.live[kind.index()].load(Ordering::Relaxed)
+    }
+
+    /// Selects whether leaks are treated as errors rather than only reported.
+    pub fn set_strict(&self, strict: bool) {
+        self.strict.store(strict, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if leaks are treated as errors.
+    pub fn is_strict(&self) -> bool {
+        self.strict.load(Ordering::Relaxed)
+    }
+
+    /// Takes a snapshot of the live counts.
+    pub fn summary(&self) -> LeakSummary {
+        let mut counts = [0; 7];
+        for kind in ResourceKind::ALL {
+            counts[kind.index()] = self.live(kind);
+        }
+        LeakSummary { counts }
+    }
+}
+
+impl Default for ResourceTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Snapshot of the live object counts of a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LeakSummary {
+    counts: [usize; 7],
+}
+
+impl LeakSummary {
+    /// Returns the number of live objects of `kind`.
+    pub fn count(&self, kind: ResourceKind) -> usize {
+        self.counts[kind.index()]
+    }
+
+    /// Returns the total number of live objects.
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns `true` if nothing is alive.
+    pub fn is_clean(&self) -> bool {
+        self.total() == 0
+    }
+
+    /// Iterates over the kinds that still have live objects.
+    pub fn leaked(&self) -> impl Iterator<Item = (ResourceKind, usize)> + '_ {
+        ResourceKind::ALL
+            .into_iter()
+            .map(move |kind| (kind, self.count(kind)))
+            .filter(|&(_, count)| count != 0)
+    }
+}
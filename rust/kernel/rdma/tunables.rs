@@ -0,0 +1,189 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Device limits that can be tuned at runtime.
+//!
+//! Some limits only shape resources when they are created, so changing them does not require
+//! reloading the driver: [`TunableLimits`] holds the current values behind a sequence counter, QP
+//! creation takes one consistent [`QpLimits`] snapshot and checks the request against it, and
+//! sysfs attributes update single values within the ceiling fixed by the driver. Existing
+//! resources keep the limits they were created with.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Limits applied to new queue pairs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QpLimits {
+    /// Maximum number of work requests per send or receive queue.
+    pub max_qp_wr: u32,
+    /// Maximum number of bytes of inline data per send work request.
+    pub max_inline_data: u32,
+    /// Default send rate of new QPs in kbit/s, 0 meaning unlimited.
+    pub pacing_rate: u32,
+}
+
+impl QpLimits {
+    /// Returns `true` if a QP with these queue sizes and inline data fits the limits.
+    pub const fn allows(&self, send_wr: u32, recv_wr: u32, inline_data: u32) -> bool {
+        send_wr <= self.max_qp_wr
+            && recv_wr <= self.max_qp_wr
+            && inline_data <= self.max_inline_data
+    }
+}
+
+/// A limit that can be changed at runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tunable {
+    /// [`QpLimits::max_qp_wr`], between 1 and the ceiling.
+    MaxQpWr,
+    /// [`QpLimits::max_inline_data`], up to the ceiling.
+    MaxInlineData,
+    /// [`QpLimits::pacing_rate`], up to the ceiling unless the ceiling is 0.
+    PacingRate,
+}
+
+impl Tunable {
+    /// Every tunable, in the order of their sysfs attributes.
+    pub const ALL: [Self; 3] = [Self::MaxQpWr, Self::MaxInlineData, Self::PacingRate];
+
+    /// Returns the name of the sysfs attribute.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::MaxQpWr => "max_qp_wr",
+            Self::MaxInlineData => "max_inline_data",
+            Self::PacingRate => "pacing_rate_kbps",
+        }
+    }
+
+    /// Looks a tunable up by attribute name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+/// Error returned when changing a [`TunableLimits`] value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TuneError {
+    /// The text is not a decimal number.
+    Parse,
+    /// The value exceeds the ceiling of the device, or is 0 where that is meaningless.
+    OutOfRange,
+}
+
+/// Current limits of a device, tunable up to the ceiling the driver set.
+pub struct TunableLimits {
+    ceiling: QpLimits,
+    max_qp_wr: AtomicU32,
+    max_inline_data: AtomicU32,
+    pacing_rate: AtomicU32,
+    seq: AtomicU32,
+    writer: AtomicBool,
+}
+
+impl TunableLimits {
+    /// Creates limits starting at, and never exceeding, `ceiling`.
+    pub const fn new(ceiling: QpLimits) -> Self {
+        Self {
+            ceiling,
+            max_qp_wr: AtomicU32::new(ceiling.max_qp_wr),
+            max_inline_data: AtomicU32::new(ceiling.max_inline_data),
+            pacing_rate: AtomicU32::new(ceiling.pacing_rate),
+            seq: AtomicU32::new(0),
+            writer: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the limits the device was created with.
+    pub fn ceiling(&self) -> QpLimits {
+        self.ceiling
+    }
+
+    fn field(&self, tunable: Tunable) -> &AtomicU32 {
+        match tunable {
+            Tunable::MaxQpWr => &self.max_qp_wr,
+            Tunable::MaxInlineData => &self.max_inline_data,
+            Tunable::PacingRate => &self.pacing_rate,
+        }
+    }
+
+    fn write<R>(&self, f: impl FnOnce() -> R) -> R {
+        while self
+            .writer
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        self.seq.fetch_add(1, Ordering::AcqRel);
+        let ret = f();
+        self.seq.fetch_add(1, Ordering::Release);
+        self.writer.store(false, Ordering::Release);
+        ret
+    }
+
+    /// Returns a consistent copy of the current limits, to apply to a resource being created.
+    pub fn snapshot(&self) -> QpLimits {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+            let limits = QpLimits {
+                max_qp_wr: self.max_qp_wr.load(Ordering::Relaxed),
+                max_inline_data: self.max_inline_data.load(Ordering::Relaxed),
+                pacing_rate: self.pacing_rate.load(Ordering::Relaxed),
+            };
+            core::sync::atomic::fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == seq {
+                return limits;
+            }
+        }
+    }
+
+    /// Returns the current value of `tunable`.
+    pub fn get(&self, tunable: Tunable) -> u32 {
+        self.field(tunable).load(Ordering::Relaxed)
+    }
+
+    /// Sets `tunable` to `value` for resources created from now on.
+    pub fn set(&self, tunable: Tunable, value: u32) -> Result<(), TuneError> {
+        let ok = match tunable {
+            Tunable::MaxQpWr => (1..=self.ceiling.max_qp_wr).contains(&value),
+            Tunable::MaxInlineData => value <= self.ceiling.max_inline_data,
+            Tunable::PacingRate => {
+                self.ceiling.pacing_rate == 0 || value <= self.ceiling.pacing_rate
+            }
+        };
+        if !ok {
+            return Err(TuneError::OutOfRange);
+        }
+        self.write(|| self.field(tunable).store(value, Ordering::Relaxed));
+        Ok(())
+    }
+
+    /// Goes back to the ceiling for every limit.
+    pub fn reset(&self) {
+        self.write(|| {
+            for tunable in Tunable::ALL {
+                let value = match tunable {
+                    Tunable::MaxQpWr => self.ceiling.max_qp_wr,
+                    Tunable::MaxInlineData => self.ceiling.max_inline_data,
+                    Tunable::PacingRate => self.ceiling.pacing_rate,
+                };
+                self.field(tunable).store(value, Ordering::Relaxed);
+            }
+        });
+    }
+
+    /// Parses `text`, e.g. written to a sysfs attribute, and sets `tunable` to it.
+    pub fn store(&self, tunable: Tunable, text: &str) -> Result<(), TuneError> {
+        let value = text.trim().parse().map_err(|_| TuneError::Parse)?;
+        self.set(tunable, value)
+    }
+
+    /// Writes the current value of `tunable`, e.g. for a sysfs `show` callback.
+    pub fn show(&self, tunable: Tunable, w: &mut dyn fmt::Write) -> fmt::Result {
+        writeln!(w, "{}", self.get(tunable))
+    }
+}
@@ -0,0 +1,170 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! P_Key and Q_Key checks of the datagram receive path.
+//!
+//! Packets whose P_Key does not match the port's, or whose Q_Key does not match the destination
+//! QP's, must be silently dropped (IBTA 10.9.3 and 10.2.4). Management software still expects to
+//! see them: [`KeyViolations`] counts them for `hw_counters` and the MAD `PortInfo` and
+//! `PortCounters` attributes, and can call a hook to raise a trap or trace the offending peer.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Default P_Key, full membership.
+pub const DEFAULT_PKEY_FULL: u16 = 0xffff;
+
+/// Well-known Q_Key of the GSI QP.
+pub const GSI_QKEY: u32 = 0x8001_0000;
+
+/// Membership bit of a P_Key, set for full members.
+const PKEY_FULL_MEMBER: u16 = 0x8000;
+
+/// Returns `true` if two P_Keys match: same non-zero partition, and at least one full member.
+pub const fn pkey_match(a: u16, b: u16) -> bool {
+    let base = a & !PKEY_FULL_MEMBER;
+    base != 0 && base == b & !PKEY_FULL_MEMBER && (a | b) & PKEY_FULL_MEMBER != 0
+}
+
+/// Returns the Q_Key a packet for QP `qp_num` must carry, `qp_qkey` being the QP's Q_Key.
+pub const fn expected_qkey(qp_num: u32, qp_qkey: u32) -> u32 {
+    if qp_num == 1 {
+        GSI_QKEY
+    } else {
+        qp_qkey
+    }
+}
+
+/// Kind of a key violation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// The packet's P_Key does not match the port's.
+    PKey,
+    /// The packet's Q_Key does not match the QP's.
+    QKey,
+}
+
+/// A rejected packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// What did not match.
+    pub kind: ViolationKind,
+    /// Key the receiver expected.
+    pub expected: u32,
+    /// Key found in the packet.
+    pub received: u32,
+    /// Destination QP of the packet.
+    pub dest_qp: u32,
+    /// Source QP of the packet.
+    pub src_qp: u32,
+}
+
+/// Hook called for every violation, e.g. to send a trap or trace the packet.
+pub type ViolationHook = fn(&Violation);
+
+/// Key violation counters of a port.
+pub struct KeyViolations {
+    pkey: AtomicU64,
+    qkey: AtomicU64,
+    hook: Option<ViolationHook>,
+}
+
+impl KeyViolations {
+    /// Creates zeroed counters without a hook.
+    pub const fn new() -> Self {
+        Self {
+            pkey: AtomicU64::new(0),
+            qkey: AtomicU64::new(0),
+            hook: None,
+        }
+    }
+
+    /// Sets the hook called for every violation.
+    pub const fn with_hook(mut self, hook: ViolationHook) -> Self {
+        self.hook = Some(hook);
+        self
+    }
+
+    fn record(&self, violation: Violation) -> Violation {
+        let counter = match violation.kind {
+            ViolationKind::PKey => &self.pkey,
+            ViolationKind::QKey => &self.qkey,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        if let Some(hook) = self.hook {
+            hook(&violation);
+        }
+        violation
+    }
+
+    /// Checks the P_Key of a packet from `src_qp` to `dest_qp` against the port's `port_pkey`.
+    pub fn check_pkey(
+        &self,
+        port_pkey: u16,
+        pkt_pkey: u16,
+        dest_qp: u32,
+        src_qp: u32,
+    ) -> Result<(), Violation> {
+        if pkey_match(pkt_pkey, port_pkey) {
+            return Ok(());
+        }
+        Err(self.record(Violation {
+            kind: ViolationKind::PKey,
+            expected: port_pkey as u32,
+            received: pkt_pkey as u32,
+            dest_qp,
+            src_qp,
+        }))
+    }
+
+    /// Checks the Q_Key of a datagram from `src_qp` to `dest_qp`, whose own Q_Key is `qp_qkey`.
+    pub fn check_qkey(
+        &self,
+        qp_qkey: u32,
+        pkt_qkey: u32,
+        dest_qp: u32,
+        src_qp: u32,
+    ) -> Result<(), Violation> {
+        let expected = expected_qkey(dest_qp, qp_qkey);
+        if pkt_qkey == expected {
+            return Ok(());
+        }
+        Err(self.record(Violation {
+            kind: ViolationKind::QKey,
+            expected,
+            received: pkt_qkey,
+            dest_qp,
+            src_qp,
+        }))
+    }
+
+    /// Returns the number of P_Key violations.
+    pub fn pkey_violations(&self) -> u64 {
+        self.pkey.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of Q_Key violations.
+    pub fn qkey_violations(&self) -> u64 {
+        self.qkey.load(Ordering::Relaxed)
+    }
+
+    /// Returns the P_Key violations as the saturating 16-bit counter of `PortInfo`.
+    pub fn pkey_violations_u16(&self) -> u16 {
+        self.pkey_violations().min(u16::MAX as u64) as u16
+    }
+
+    /// Returns the Q_Key violations as the saturating 16-bit counter of `PortInfo`.
+    pub fn qkey_violations_u16(&self) -> u16 {
+        self.qkey_violations().min(u16::MAX as u64) as u16
+    }
+
+    /// Resets both counters, as a `Set(PortCounters)` MAD does.
+    pub fn reset(&self) {
+        self.pkey.store(0, Ordering::Relaxed);
+        self.qkey.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Default for KeyViolations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
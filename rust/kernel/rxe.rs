@@ -2,44 +2,118 @@
 
 //! Infiniband soft-Roce devices.
 use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
 use core::pin::Pin;
-use core::{marker, ptr};
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU16, AtomicU64, Ordering};
+use core::{marker, mem, ptr};
 use macros::vtable;
 
 use crate::error::{code::*, Error, Result};
+use crate::ib::compat::try_pin;
+use crate::ib::{IbDeviceOperations, ObservedDevice};
+use crate::rdma::dedup::{DupFilter, DupKey};
+use crate::rdma::init_once::InitOnce;
+use crate::rdma::tracker::{LiveResources, ResourceTracker};
 use crate::str::CStr;
-use crate::{bindings, pr_err, pr_info};
+use crate::sync::smutex::Mutex;
+use crate::{bindings, pr_err, pr_info, pr_warn};
+
+pub mod config;
+pub mod counters;
+mod debugfs;
+pub mod external;
+pub mod filter;
+mod genl;
+pub mod icrc;
+mod link;
+pub mod mcast;
+pub mod net;
+pub mod pool;
+pub mod queue;
+pub mod task;
+pub mod trace;
+
+pub use crate::rdma::dedup::DupStats;
+pub use crate::rdma::dev_config::{ConfigKey, CrcMode, DeviceConfig};
+pub use crate::rdma::flap::{FlapStats, LinkEvent};
+pub use crate::rdma::hdr;
+pub use crate::rdma::link_params::{LinkParamError, LinkParams};
+pub use crate::rdma::qp_fault::{QpFaultCommand, QpFaultInjector, QpFaultRule};
+pub use crate::rdma::scrub::{Scrub, Scrubber};
+pub use crate::rdma::snapshot::Snapshot;
+pub use config::RcuConfig;
+pub use counters::{Counter, CounterValues, Counters};
+pub use debugfs::DeviceDir;
+pub use filter::SourceFilter;
+pub use mcast::McastTable;
+pub use pool::{Pool, PoolEntry, PoolRef};
+
+use debugfs::DebugFs;
+use net::{Namespace, NetDevice, SkBuff, UdpRecvVerdict};
 
 /// Soft-Roce transport registration.
 ///
+/// The UDP sockets and the netdev notifier are only set up when the first link is created, so
+/// that loading the module does not claim the RoCEv2 port on systems that never use it. Call
+/// [`Registration::prewarm`] to set them up right away instead.
 pub struct Registration<T: RxeOperation> {
     registered: bool,
     #[allow(dead_code)]
     name: &'static CStr,
-    net_socket: RxeRecvSockets<T>,
+    config: SocketConfig,
+    net_socket: UnsafeCell<RxeRecvSockets<T>>,
+    sockets: InitOnce,
+    rx: RxPause,
+    links: link::LinkFlaps<T>,
     rxe_link_ops: bindings::rdma_link_ops,
+    family: genl::LinkFamily<T>,
+    debugfs: DebugFs,
+    resources: ResourceTracker,
     phantom: marker::PhantomData<T>,
+    _pin: marker::PhantomPinned,
 }
 
 impl<T: RxeOperation> Registration<T> {
     /// Creates a new [`Registration`] but does not register it yet.
     ///
     /// It is allowed to move.
-    pub fn new(name: &'static CStr) -> Self {
+    pub fn new(name: &'static CStr, config: SocketConfig) -> Self {
         // INVARIANT: `registered` is `false`
         Self {
             registered: false,
             name,
-            net_socket: RxeRecvSockets::new(),
+            config,
+            net_socket: UnsafeCell::new(RxeRecvSockets::new(config)),
+            sockets: InitOnce::new(),
+            rx: RxPause::new(),
+            links: link::LinkFlaps::new(&config),
             rxe_link_ops: bindings::rdma_link_ops::default(),
+            family: genl::LinkFamily::new(),
+            debugfs: DebugFs::new(),
+            resources: ResourceTracker::new(),
             phantom: marker::PhantomData,
+            _pin: marker::PhantomPinned,
         }
     }
 
+    /// Returns the socket configuration used by the samples and the smoke test.
+    ///
+    /// It goes through every [`SocketConfig`] setter, so that they keep being exercised as the
+    /// configuration grows; the `kunit` cases at the bottom of this file, run by the `rust_rdma`
+    /// KUnit suite, check that they still take effect.
+    pub const fn example_config() -> SocketConfig {
+        SocketConfig::new()
+            .with_port(ROCE_V2_UDP_DPORT)
+            .with_legacy_port(false)
+            .with_per_netns(false)
+            .with_flap_holdoff(DEFAULT_FLAP_HOLDOFF_MS)
+    }
+
     /// Registers a infiniband soft-Roce device
     /// Returns a pinned heap-allocated representation of the registration.
-    pub fn new_pinned(name: &'static CStr) -> Result<Pin<Box<Self>>> {
-        let mut r = Pin::from(Box::try_new(Self::new(name))?);
+    pub fn new_pinned(name: &'static CStr, config: SocketConfig) -> Result<Pin<Box<Self>>> {
+        let mut r = try_pin(Self::new(name, config))?;
         r.as_mut().register()?;
         Ok(r)
     }
@@ -55,32 +129,292 @@ impl<T: RxeOperation> Registration<T> {
             // Already registered
             return Err(EINVAL);
         }
+        if let Err(e) = net::check_kernel() {
+            pr_err!("Cannot register: {}\n", e.describe());
+            return Err(e.into());
+        }
 
-        match this.net_socket.alloc() {
-            Ok(()) => {}
-            Err(e) => return Err(e),
+        // Only soft transports have a link type, and unregistering `RDMA_DRIVER_UNKNOWN` would
+        // tear down the devices of unrelated drivers.
+        let link_type = T::Device::DRIVER_ID.link_type().ok_or(EINVAL)?;
+
+        // The soft-RoCE plumbing can only be registered once, and `newlink` finds us through `ACTIVE`.
+        let active = this as *mut Self as *mut core::ffi::c_void;
+        if ACTIVE
+            .compare_exchange(ptr::null_mut(), active, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            pr_err!("Another rxe registration is active\n");
+            return Err(EBUSY);
         }
 
-        this.rxe_link_ops = RxeRdmaLinkTable::<T>::build();
+        this.rxe_link_ops = RxeRdmaLinkTable::<T>::build(link_type);
 
         // SAFETY: The adapter is compatible with the rdma_link_register
         unsafe {
             bindings::rdma_link_register(&mut this.rxe_link_ops);
         }
+        // SAFETY: The registration is pinned, and `drop` unregisters the family.
+        if let Err(e) = unsafe { this.family.register(link_type) } {
+            // SAFETY: The link ops were registered just above.
+            unsafe { bindings::rdma_link_unregister(&mut this.rxe_link_ops) };
+            ACTIVE.store(ptr::null_mut(), Ordering::Release);
+            return Err(e);
+        }
+
+        let reg = this as *const Self;
+        // SAFETY: The registration is pinned, and `drop` removes the directory before anything
+        // the snapshot reads goes away.
+        unsafe { this.debugfs.create(this.name, &*reg) };
 
         this.registered = true;
         pr_info!("loaded");
         Ok(())
     }
+
+    /// Sets up the UDP sockets and the netdev notifier now rather than on the first `newlink`.
+    ///
+    /// This restores the behaviour of claiming the UDP port at module init, e.g. to fail loading
+    /// early if it is taken. Does nothing if they are already set up.
+    pub fn prewarm(&self) -> Result {
+        self.ensure_sockets()
+    }
+
+    /// Returns the number of packets received on [`LEGACY_UDP_DPORT`].
+    ///
+    /// Once it stops growing, no peer uses the old port anymore and the compatibility sockets can
+    /// be turned off.
+    pub fn legacy_packets(&self) -> u64 {
+        LEGACY_PACKETS.load(Ordering::Relaxed)
+    }
+
+    /// Returns the counters of the duplicate filter, which runs while sockets listen on more than
+    /// one port.
+    pub fn dedup_stats(&self) -> DupStats {
+        DEDUP.stats()
+    }
+
+    /// Returns the counters of the link event debouncing, see [`SocketConfig::with_flap_holdoff`].
+    pub fn flap_stats(&self) -> FlapStats {
+        self.links.stats()
+    }
+
+    /// Writes a snapshot of the registration and of the state `T` adds to it, see
+    /// [`RxeOperation::snapshot`].
+    ///
+    /// This is what the debugfs file `snapshot`, in the directory named after the registration,
+    /// shows.
+    pub fn write_snapshot(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        let mut snap = Snapshot::new(w, jiffies());
+        snap.resources(&self.resources);
+        self.record_sockets(&mut snap);
+        let flaps = self.links.stats();
+        snap.record("link_events")
+            .u64("events", flaps.events)
+            .u64("propagated", flaps.propagated)
+            .u64("absorbed", flaps.absorbed())
+            .finish();
+        T::pools(&mut |pool| snap.objects(pool));
+        T::snapshot(&mut snap);
+        snap.finish()
+    }
+
+    /// Writes the state of the UDP sockets, as a snapshot holding a single `sockets` record.
+    ///
+    /// This is what the debugfs file `sockets`, in the directory of each device, shows.
+    pub fn write_sockets(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        let mut snap = Snapshot::new(w, jiffies());
+        self.record_sockets(&mut snap);
+        snap.finish()
+    }
+
+    fn record_sockets(&self, snap: &mut Snapshot<'_>) {
+        let dedup = DEDUP.stats();
+        snap.record("sockets")
+            .u64("port", self.config.port() as u64)
+            .u64("legacy_port", self.config.legacy_port().unwrap_or(0) as u64)
+            .bool("per_netns", self.config.per_netns())
+            .bool("warm", self.is_warm())
+            .u64("legacy_packets", self.legacy_packets())
+            .bool("rx_paused", self.is_rx_paused())
+            .u64("paused_drops", self.rx.drops.load(Ordering::Relaxed))
+            .u64("dedup_checked", dedup.checked)
+            .u64("dedup_duplicates", dedup.duplicates)
+            .finish();
+    }
+
+    /// Stops handing received packets to [`RxeOperation::udp_recv`], e.g. while the devices are
+    /// frozen, see [`Registration::update_config`], or torn down.
+    ///
+    /// The sockets are pointed to a receive function that drops every packet, and the packets
+    /// being received meanwhile are waited for, so `udp_recv` does not run anymore once this
+    /// returns. Sockets set up while paused start paused. Pauses nest: reception resumes with the
+    /// last [`Registration::resume_rx`]. With [`SocketConfig::with_per_netns`] the sockets of every
+    /// namespace are swapped, while namespaces are kept from coming and going. Sleeps and must
+    /// not be called under RTNL, which the setup of the sockets takes.
+    pub fn pause_rx(&self) -> Result {
+        self.set_rx_paused(true)
+    }
+
+    /// Undoes a [`Registration::pause_rx`].
+    pub fn resume_rx(&self) -> Result {
+        self.set_rx_paused(false)
+    }
+
+    /// Returns `true` while received packets are dropped, see [`Registration::pause_rx`].
+    pub fn is_rx_paused(&self) -> bool {
+        self.rx.is_paused()
+    }
+
+    fn set_rx_paused(&self, paused: bool) -> Result {
+        // Pauses are serialised, as none may return before the sockets are swapped. RTNL cannot
+        // be used for this: the sockets may be waited for, and their setup takes it.
+        let mut count = self.rx.count.lock();
+        let pauses = *count;
+        let next = match (paused, pauses) {
+            (true, n) => n + 1,
+            (false, 0) => return Err(EINVAL),
+            (false, n) => n - 1,
+        };
+        *count = next;
+        self.rx.paused.store(next > 0, Ordering::Release);
+        if (pauses == 0) != (next == 0) {
+            // Waits for sockets being set up, which then see the new state, or keeps them from
+            // being set up while we look.
+            let warm = self
+                .sockets
+                .call(
+                    || Err(ENODATA),
+                    || {
+                        // SAFETY: FFI call without preconditions.
+                        unsafe { bindings::cond_resched() }
+                    },
+                )
+                .is_ok();
+            if warm {
+                // SAFETY: The sockets were set up, they only change again when dropped.
+                unsafe { (*self.net_socket.get()).swap_encap_rcv(&self.rx) };
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes the version of `config` that `f` derives from the current one with reception
+    /// paused, see [`RcuConfig::update`].
+    ///
+    /// A packet reads the settings more than once on its way through the device, and could see
+    /// two versions if they were swapped meanwhile. Settings deciding how packets are handled,
+    /// e.g. the [`DeviceConfig`] of a device, are swapped through this instead, so that no
+    /// packet is received across the swap; the packets arriving meanwhile are dropped. Sleeps,
+    /// like [`Registration::pause_rx`].
+    pub fn update_config<C: Copy>(
+        &self,
+        config: &RcuConfig<C>,
+        f: impl FnOnce(C) -> Result<C>,
+    ) -> Result<C> {
+        self.pause_rx()?;
+        let ret = config.update(f);
+        // Balanced by the pause above, so it cannot fail.
+        let _ = self.resume_rx();
+        ret
+    }
+
+    /// Returns `true` once the UDP sockets and the netdev notifier are set up.
+    pub fn is_warm(&self) -> bool {
+        self.sockets.is_done()
+    }
+
+    fn ensure_sockets(&self) -> Result {
+        self.sockets.call(
+            || {
+                // SAFETY: `InitOnce` runs one initialiser at a time and none once the sockets are
+                // set up, so nothing else accesses them meanwhile.
+                let sockets = unsafe { &mut *self.net_socket.get() };
+                sockets.alloc(&self.rx)?;
+                // SAFETY: The registration is pinned once registered, and the notifier is
+                // unregistered when it is dropped. `InitOnce` serialises the calls.
+                if let Err(e) = unsafe { self.links.start() } {
+                    sockets.rxe_net_release();
+                    return Err(e);
+                }
+                Ok(())
+            },
+            // SAFETY: FFI call without preconditions; `newlink` and module init may sleep.
+            || unsafe { bindings::cond_resched() },
+        )
+    }
+
+    /// Returns the live object accounting of the devices created through this registration.
+    ///
+    /// Providers record every object they create and destroy so that leaks show up on teardown.
+    pub fn resources(&self) -> &ResourceTracker {
+        &self.resources
+    }
+
+    /// Logs the objects that are still alive.
+    ///
+    /// The pools of [`RxeOperation::pools`], and `pools`, are asked for the index and owner of
+    /// each live object. Returns `EBUSY` if anything leaked and the tracker is in strict mode, so
+    /// explicit teardown paths can refuse to go on.
+    pub fn report_leaks(&self, pools: &[&dyn LiveResources]) -> Result {
+        let summary = self.resources.summary();
+        if summary.is_clean() {
+            return Ok(());
+        }
+
+        pr_warn!("{} objects still alive on unregister\n", summary.total());
+        for (kind, count) in summary.leaked() {
+            pr_warn!("  {}: {}\n", kind.name(), count);
+        }
+        let mut report = |pool: &dyn LiveResources| {
+            pool.for_each_live(&mut |res| {
+                pr_warn!(
+                    "  {} index {} owned by pid {}\n",
+                    res.kind.name(),
+                    res.index,
+                    res.owner
+                );
+            })
+        };
+        T::pools(&mut report);
+        for pool in pools {
+            report(*pool);
+        }
+
+        if self.resources.is_strict() {
+            return Err(EBUSY);
+        }
+        Ok(())
+    }
 }
 
 impl<T: RxeOperation> Drop for Registration<T> {
     fn drop(&mut self) {
         if self.registered {
+            // Snapshots read the state torn down below.
+            self.debugfs.remove();
+            self.family.unregister();
             // SAFETY: [`self.rxe_link_ops`] was previously created using RxeRdmaLinkTable::<T>::build()
             unsafe { bindings::rdma_link_unregister(&mut self.rxe_link_ops) };
-            // SAFETY: unregister ib driver with driver_id bindings::rdma_driver_id_RDMA_DRIVER_RXE
-            unsafe { bindings::ib_unregister_driver(bindings::rdma_driver_id_RDMA_DRIVER_RXE) };
+            // No `newlink` runs anymore once the link type and the family are unregistered.
+            ACTIVE.store(ptr::null_mut(), Ordering::Release);
+            // Keep packets away from the devices going away. Only unbalanced resumes fail.
+            let _ = self.pause_rx();
+            // Devices handed to ib_core with `ib::Device::register` are torn down here through
+            // their `dealloc_driver` callback, unless `rdma link delete` or a netdev removal got
+            // to them first.
+            // SAFETY: `register` checked that `T::Device::DRIVER_ID` is the ID of a soft transport, so
+            // only the devices of `T`'s driver are unregistered.
+            unsafe { bindings::ib_unregister_driver(T::Device::DRIVER_ID.to_raw()) };
+
+            // Every device is gone now, so whatever is still accounted for leaked, and still in the
+            // pools of `T`. Module unload cannot be refused anymore at this point, strict mode can
+            // only make it loud.
+            if self.report_leaks(&[]).is_err() {
+                pr_err!("Leaked objects in strict mode\n");
+                // SAFETY: `dump_stack` has no preconditions.
+                unsafe { bindings::dump_stack() };
+            }
         }
     }
 }
@@ -89,62 +423,275 @@ impl<T: RxeOperation> Drop for Registration<T> {
 // (it is fine for multiple threads to have a shared reference to it).
 unsafe impl<T: RxeOperation> Sync for Registration<T> {}
 
+/// The registered [`Registration`], for `newlink` to set the sockets up.
+static ACTIVE: AtomicPtr<core::ffi::c_void> = AtomicPtr::new(ptr::null_mut());
+
+/// Standard RoCEv2 UDP destination port, as assigned by IANA.
+pub const ROCE_V2_UDP_DPORT: u16 = 4791;
+
+/// Port the soft-Roce sockets used to listen on, in host byte order.
+///
+/// The sockets used to store 46866 in the big-endian port field without swapping it, so they
+/// actually listened on 46866 read in big-endian order: 4791, the standard port, on
+/// little-endian hosts, and 46866 only on big-endian ones. [`SocketConfig::legacy_port`] is
+/// `None` where this is the port already listened on.
+pub const LEGACY_UDP_DPORT: u16 = u16::from_be(46866);
+
+/// Default of [`SocketConfig::with_flap_holdoff`], long enough for a link renegotiation.
+pub const DEFAULT_FLAP_HOLDOFF_MS: u32 = 200;
+
+/// Number of packets received on [`LEGACY_UDP_DPORT`].
+static LEGACY_PACKETS: AtomicU64 = AtomicU64::new(0);
+
+/// Reception pauses of a registration, see [`Registration::pause_rx`].
+///
+/// The sockets of the registration carry it as their `sk_user_data`, for the receive function
+/// dropping the packets to count them. It outlives the sockets, which are released before the
+/// registration goes away.
+pub(crate) struct RxPause {
+    /// Number of pauses not undone yet.
+    count: Mutex<u32>,
+    /// Whether `count` is non-zero, for the sockets being set up.
+    paused: AtomicBool,
+    /// Number of packets dropped while paused.
+    drops: AtomicU64,
+}
+
+impl RxPause {
+    const fn new() -> Self {
+        Self {
+            count: Mutex::new(0),
+            paused: AtomicBool::new(false),
+            drops: AtomicU64::new(0),
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+}
+
+/// Number of packets remembered by the duplicate filter.
+const DEDUP_SLOTS: usize = 256;
+
+/// How long a packet is remembered by the duplicate filter, far below any retransmission timeout.
+const DEDUP_WINDOW_US: u32 = 1000;
+
+/// Drops copies of a packet, e.g. received on both ports, while `DEDUP_ENABLED` is set.
+static DEDUP: DupFilter<DEDUP_SLOTS> = DupFilter::new(1);
+static DEDUP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+fn jiffies() -> u64 {
+    // SAFETY: FFI call without preconditions.
+    unsafe { bindings::get_jiffies_64() }
+}
+
+/// Turns the duplicate filter on or off.
+fn set_dedup(enabled: bool) {
+    if enabled {
+        // SAFETY: FFI call without preconditions.
+        let window = unsafe { bindings::usecs_to_jiffies(DEDUP_WINDOW_US) };
+        DEDUP.set_window((window as u32).max(1));
+        DEDUP.clear();
+    }
+    DEDUP_ENABLED.store(enabled, Ordering::Release);
+}
+
+/// Configuration of the soft-Roce UDP tunnel sockets.
+#[derive(Clone, Copy, Debug)]
+pub struct SocketConfig {
+    port: u16,
+    legacy_port: bool,
+    per_netns: bool,
+    flap_holdoff_ms: u32,
+}
+
+impl SocketConfig {
+    /// Creates a configuration listening on [`ROCE_V2_UDP_DPORT`].
+    pub const fn new() -> Self {
+        Self {
+            port: ROCE_V2_UDP_DPORT,
+            legacy_port: false,
+            per_netns: false,
+            flap_holdoff_ms: DEFAULT_FLAP_HOLDOFF_MS,
+        }
+    }
+
+    /// Listens on `port` instead of the standard RoCEv2 port.
+    ///
+    /// Peers must use the same port, so this is only meant for test rigs and private
+    /// deployments.
+    pub const fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Also listens on [`LEGACY_UDP_DPORT`], with a second set of sockets.
+    ///
+    /// Packets of both ports go to the same [`RxeOperation::udp_recv`], so that peers still on the
+    /// old port keep working while the others move to the standard one. Copies of a packet
+    /// received twice within a millisecond are dropped, see [`Registration::dedup_stats`]. Does
+    /// nothing where [`LEGACY_UDP_DPORT`] is the port listened on already.
+    pub const fn with_legacy_port(mut self, legacy_port: bool) -> Self {
+        self.legacy_port = legacy_port;
+        self
+    }
+
+    /// Creates the sockets in every network namespace instead of only in `init_net`.
+    ///
+    /// This is what containerised users need, but only one registration at a time may use it.
+    pub const fn with_per_netns(mut self, per_netns: bool) -> Self {
+        self.per_netns = per_netns;
+        self
+    }
+
+    /// Holds the link events of the netdev notifier back for `ms` milliseconds, 0 to pass them
+    /// on right away.
+    ///
+    /// A link bouncing during a switch reconfiguration is then reported to
+    /// [`RxeOperation::port_event`] once, in the state it settled in, or not at all if it came
+    /// back up within the holdoff, instead of moving the port and its QPs to the error state on
+    /// every bounce. Each event restarts the holdoff of its link, so a link flapping for longer
+    /// is reported late rather than often.
+    pub const fn with_flap_holdoff(mut self, ms: u32) -> Self {
+        self.flap_holdoff_ms = ms;
+        self
+    }
+
+    /// Returns `true` if the sockets are created in every network namespace.
+    pub const fn per_netns(&self) -> bool {
+        self.per_netns
+    }
+
+    /// Returns the link event holdoff in milliseconds.
+    pub const fn flap_holdoff(&self) -> u32 {
+        self.flap_holdoff_ms
+    }
+
+    /// Returns the UDP port in host byte order.
+    pub const fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the compatibility port in host byte order, if it is enabled and differs from
+    /// [`SocketConfig::port`], which it does not on little-endian hosts listening on the standard
+    /// port, see [`LEGACY_UDP_DPORT`].
+    pub const fn legacy_port(&self) -> Option<u16> {
+        if self.legacy_port && self.port != LEGACY_UDP_DPORT {
+            Some(LEGACY_UDP_DPORT)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// soft-Roce register net sockets
 pub struct RxeRecvSockets<T: RxeOperation> {
+    config: SocketConfig,
     sk4: Option<*mut bindings::socket>,
     sk6: Option<*mut bindings::socket>,
-    rxe_net_notifier: Option<bindings::notifier_block>,
+    legacy_sk4: Option<*mut bindings::socket>,
+    legacy_sk6: Option<*mut bindings::socket>,
+    pernet_ops: Option<bindings::pernet_operations>,
     phantom: marker::PhantomData<T>,
 }
 
 impl<T: RxeOperation> RxeRecvSockets<T> {
     /// Create net socket but not init it yet.
-    pub fn new() -> Self {
+    pub fn new(config: SocketConfig) -> Self {
         Self {
+            config,
             sk4: None,
             sk6: None,
-            rxe_net_notifier: None,
+            legacy_sk4: None,
+            legacy_sk6: None,
+            pernet_ops: None,
             phantom: marker::PhantomData,
         }
     }
 
-    /// Init rxe net socket
-    pub fn alloc(&mut self) -> Result<()> {
-        match self.ipv4_init() {
-            Ok(_tmp) => {}
-            Err(e) => return Err(e),
-        }
+    /// Init rxe net socket, paused if `rx` is
+    pub(crate) fn alloc(&mut self, rx: &RxPause) -> Result<()> {
+        // With sockets on two ports, a mirrored packet may reach both.
+        set_dedup(self.config.legacy_port().is_some());
+        if self.config.per_netns {
+            self.pernet_init(rx)?;
+        } else {
+            match self.ipv4_init(rx) {
+                Ok(_tmp) => {}
+                Err(e) => return Err(e),
+            }
 
-        match self.ipv6_init() {
-            Ok(_tmp) => {}
-            Err(e) => {
-                self.rxe_net_release();
-                return Err(e);
+            match self.ipv6_init(rx) {
+                Ok(_tmp) => {}
+                Err(e) => {
+                    self.rxe_net_release();
+                    return Err(e);
+                }
             }
-        }
 
-        match self.net_notifier_register() {
-            Ok(_tmp) => {}
-            Err(e) => {
-                self.rxe_net_release();
-                return Err(e);
+            match self.legacy_init(rx) {
+                Ok(_tmp) => {}
+                Err(e) => {
+                    self.rxe_net_release();
+                    return Err(e);
+                }
             }
         }
         Ok(())
     }
 
     /// Init ipv4 socket
-    fn ipv4_init(&mut self) -> Result<()> {
+    fn ipv4_init(&mut self, rx: &RxPause) -> Result<()> {
+        self.sk4 = Some(Self::ipv4_sock_create(
+            net::init_ns(),
+            self.config.port,
+            false,
+            rx,
+        )?);
+        Ok(())
+    }
+
+    /// if CONFIG_IPV6=y, init ipv6 socket
+    fn ipv6_init(&mut self, rx: &RxPause) -> Result<()> {
+        self.sk6 = Self::ipv6_sock_create(net::init_ns(), self.config.port, false, rx)?;
+        Ok(())
+    }
+
+    /// If enabled, init the sockets of the compatibility port
+    fn legacy_init(&mut self, rx: &RxPause) -> Result<()> {
+        if let Some(port) = self.config.legacy_port() {
+            self.legacy_sk4 = Some(Self::ipv4_sock_create(net::init_ns(), port, true, rx)?);
+            self.legacy_sk6 = Self::ipv6_sock_create(net::init_ns(), port, true, rx)?;
+        }
+        Ok(())
+    }
+
+    /// Creates an IPv4 UDP tunnel socket listening on `port` in `ns`.
+    ///
+    /// `legacy` tells whether `port` is the compatibility port, whose packets are counted, and
+    /// `rx` whether the socket starts paused.
+    fn ipv4_sock_create(
+        ns: &Namespace,
+        port: u16,
+        legacy: bool,
+        rx: &RxPause,
+    ) -> Result<*mut bindings::socket> {
         let mut udp_cfg = bindings::udp_port_cfg::default();
         let mut tnl_cfg = bindings::udp_tunnel_sock_cfg::default();
         let mut sock: *mut bindings::socket = ptr::null_mut();
 
         udp_cfg.family = bindings::AF_INET as u8;
-        udp_cfg.local_udp_port = 46866;
-        // SAFETY: [`bindings::init_net`] and [`udp_cfg`] can be safely passed to [`bindings::udp_sock_create4`]
-        // [`sock`] will be pass to [`self.sk4`] later, it will live at least as long as the module, which is an implicit requirement
-        let err =
-            unsafe { bindings::udp_sock_create4(&mut bindings::init_net, &mut udp_cfg, &mut sock) };
+        udp_cfg.local_udp_port = port.to_be();
+        // SAFETY: [`ns`] and [`udp_cfg`] can be safely passed to [`bindings::udp_sock_create4`]
+        // [`sock`] is handed to the caller, which releases it before the namespace goes away
+        let err = unsafe { bindings::udp_sock_create4(ns.as_ptr(), &mut udp_cfg, &mut sock) };
 
         if err < 0 {
             pr_err!("Failed to create IPv4 UDP tunnel\n");
@@ -152,17 +699,24 @@ impl<T: RxeOperation> RxeRecvSockets<T> {
         }
 
         tnl_cfg.encap_type = 1;
-        tnl_cfg.encap_rcv = RxeUdpEncapRecvFuncTable::<T>::build_func();
+        tnl_cfg.encap_rcv = RxeUdpEncapRecvFuncTable::<T>::build_func(legacy, rx.is_paused());
+        tnl_cfg.sk_user_data = rx as *const RxPause as *mut core::ffi::c_void;
 
-        // SAFETY: [`bindings::init_net`] and [`tnl_cfg`] can be safely passed to [`bindings::setup_udp_tunnel_sock`]
-        // [`sock`] will be pass to [`self.sk4`] later, it will live at least as long as the module, which is an implicit requirement
-        unsafe { bindings::setup_udp_tunnel_sock(&mut bindings::init_net, sock, &mut tnl_cfg) }
-        self.sk4 = Some(sock);
-        Ok(())
+        // SAFETY: [`ns`] and [`tnl_cfg`] can be safely passed to [`bindings::setup_udp_tunnel_sock`]
+        // [`sock`] was successfully created above
+        unsafe { bindings::setup_udp_tunnel_sock(ns.as_ptr(), sock, &mut tnl_cfg) }
+        Ok(sock)
     }
 
-    /// if CONFIG_IPV6=y, init ipv6 socket
-    fn ipv6_init(&mut self) -> Result<()> {
+    /// Creates an IPv6 UDP tunnel socket listening on `port` in `ns`.
+    ///
+    /// Returns `None` if IPv6 is not available.
+    fn ipv6_sock_create(
+        ns: &Namespace,
+        port: u16,
+        legacy: bool,
+        rx: &RxPause,
+    ) -> Result<Option<*mut bindings::socket>> {
         #[cfg(CONFIG_IPV6)]
         {
             let mut udp_cfg = bindings::udp_port_cfg::default();
@@ -171,18 +725,16 @@ impl<T: RxeOperation> RxeRecvSockets<T> {
 
             udp_cfg.family = bindings::AF_INET6 as u8;
             udp_cfg.set_ipv6_v6only(1);
-            udp_cfg.local_udp_port = 46866;
-            // SAFETY: [`bindings::init_net`] and [`udp_cfg`] can be safely passed to [`bindings::udp_sock_create4`]
-            // [`sock`] will be pass to [`self.sk6`] later, it will live at least as long as the module, which is an implicit requirement
-            let err = unsafe {
-                bindings::udp_sock_create6(&mut bindings::init_net, &mut udp_cfg, &mut sock)
-            };
+            udp_cfg.local_udp_port = port.to_be();
+            // SAFETY: [`ns`] and [`udp_cfg`] can be safely passed to [`bindings::udp_sock_create6`]
+            // [`sock`] is handed to the caller, which releases it before the namespace goes away
+            let err = unsafe { bindings::udp_sock_create6(ns.as_ptr(), &mut udp_cfg, &mut sock) };
 
             if err < 0 {
                 // EAFNOSUPPORT
                 if err == -97 {
                     pr_err!("IPv6 is not supported, can not create a UDPv6 socket\n");
-                    return Ok(());
+                    return Ok(None);
                 } else {
                     pr_err!("Failed to create IPv6 UDP tunnel\n");
                     return Err(Error::from_kernel_errno(err));
@@ -190,43 +742,85 @@ impl<T: RxeOperation> RxeRecvSockets<T> {
             }
 
             tnl_cfg.encap_type = 1;
-            tnl_cfg.encap_rcv = RxeUdpEncapRecvFuncTable::<T>::build_func();
+            tnl_cfg.encap_rcv = RxeUdpEncapRecvFuncTable::<T>::build_func(legacy, rx.is_paused());
+            tnl_cfg.sk_user_data = rx as *const RxPause as *mut core::ffi::c_void;
 
-            // SAFETY: [`bindings::init_net`] and [`tnl_cfg`] can be safely passed to [`bindings::setup_udp_tunnel_sock`]
-            // [`sock`] will be pass to [`self.sk6`] later, it will live at least as long as the module, which is an implicit requirement
-            unsafe { bindings::setup_udp_tunnel_sock(&mut bindings::init_net, sock, &mut tnl_cfg) }
-            self.sk6 = Some(sock);
+            // SAFETY: [`ns`] and [`tnl_cfg`] can be safely passed to [`bindings::setup_udp_tunnel_sock`]
+            // [`sock`] was successfully created above
+            unsafe { bindings::setup_udp_tunnel_sock(ns.as_ptr(), sock, &mut tnl_cfg) }
+            return Ok(Some(sock));
+        }
+        #[cfg(not(CONFIG_IPV6))]
+        {
+            let _ = (ns, port, legacy, rx);
+            Ok(None)
         }
-        Ok(())
     }
 
-    /// Rxe receive notifier info and handle func
-    fn net_notifier_register(&mut self) -> Result<()> {
-        let err: i32;
-        self.rxe_net_notifier = Some(RxeNotifyFuncTable::<T>::build());
-        // SAFETY: [`self.rxe_net_notifier`] is Some, it was previously created by
-        // RxeNotifyFuncTable::<T>::build().
-        unsafe {
-            err = bindings::register_netdevice_notifier(self.rxe_net_notifier.as_mut().unwrap());
+    /// Registers the per-namespace operations, which create the sockets in every namespace.
+    fn pernet_init(&mut self, rx: &RxPause) -> Result<()> {
+        if PERNET.in_use.swap(true, Ordering::AcqRel) {
+            pr_err!("Per-namespace sockets are already registered\n");
+            return Err(EBUSY);
         }
+        PERNET.port.store(self.config.port, Ordering::Release);
+        PERNET
+            .legacy_port
+            .store(self.config.legacy_port().unwrap_or(0), Ordering::Release);
+        PERNET
+            .rx
+            .store(rx as *const RxPause as *mut RxPause, Ordering::Release);
+
+        self.pernet_ops = Some(RxePernetTable::<T>::build());
+        // SAFETY: [`self.pernet_ops`] is Some, it lives inside the pinned registration until
+        // it is unregistered in rxe_net_release(&mut self).
+        let err = unsafe { bindings::register_pernet_subsys(self.pernet_ops.as_mut().unwrap()) };
         if err != 0 {
-            pr_err!("Failed to register netdev notifier\n");
-            if self.rxe_net_notifier.is_some() {
-                // SAFETY: [`self.rxe_net_notifier`] is Some, it was previously created by
-                // RxeNotifyFuncTable::<T>::build().
-                unsafe {
-                    bindings::unregister_netdevice_notifier(
-                        &mut self.rxe_net_notifier.take().unwrap(),
-                    )
-                };
-            }
+            pr_err!("Failed to register pernet operations\n");
+            self.pernet_ops = None;
+            PERNET.in_use.store(false, Ordering::Release);
             return Err(Error::from_kernel_errno(err));
         }
         Ok(())
     }
 
+    /// Points the sockets to the receive function matching `rx`, and waits for the packets
+    /// being received by the previous one.
+    ///
+    /// Called with the sockets set up.
+    fn swap_encap_rcv(&self, rx: &RxPause) {
+        let paused = rx.is_paused();
+        if self.pernet_ops.is_some() {
+            // SAFETY: The pernet operations are registered.
+            unsafe { RxePernetTable::<T>::swap_encap_rcv(paused) };
+        }
+        for (sock, legacy) in [
+            (self.sk4, false),
+            (self.sk6, false),
+            (self.legacy_sk4, true),
+            (self.legacy_sk6, true),
+        ] {
+            if let Some(sock) = sock {
+                // SAFETY: The socket was set up by `setup_udp_tunnel_sock` and is not released
+                // before the registration is dropped.
+                unsafe { set_encap_rcv::<T>(sock, legacy, paused) };
+            }
+        }
+        // Receive functions run in softirqs, which are RCU read-side critical sections.
+        // SAFETY: FFI call without preconditions; our callers may sleep.
+        unsafe { bindings::synchronize_net() };
+    }
+
     /// release registered socket when error occur
     fn rxe_net_release(&mut self) {
+        set_dedup(false);
+        if let Some(ops) = self.pernet_ops.as_mut() {
+            // SAFETY: [`ops`] is the block registered in pernet_init(&mut self), still at the
+            // same address; unregistering it releases the sockets of every namespace.
+            unsafe { bindings::unregister_pernet_subsys(ops) };
+            self.pernet_ops = None;
+            PERNET.in_use.store(false, Ordering::Release);
+        }
         if self.sk4.is_some() {
             // SAFETY: [`self.sk4`] is Some, it was previously created in ipv4_init(&mut self).
             unsafe {
@@ -239,6 +833,12 @@ impl<T: RxeOperation> RxeRecvSockets<T> {
                 bindings::udp_tunnel_sock_release(self.sk6.take().unwrap());
             }
         }
+        for sock in [&mut self.legacy_sk4, &mut self.legacy_sk6] {
+            if let Some(sock) = sock.take() {
+                // SAFETY: [`sock`] was previously created in legacy_init(&mut self).
+                unsafe { bindings::udp_tunnel_sock_release(sock) };
+            }
+        }
     }
 }
 
@@ -246,13 +846,10 @@ impl<T: RxeOperation> Drop for RxeRecvSockets<T> {
     /// Removes the registration from the kernel if it has completed successfully before.
     fn drop(&mut self) {
         self.rxe_net_release();
-        if self.rxe_net_notifier.is_some() {
-            // SAFETY: [`self.rxe_net_notifier`] is Some, it was previously created by
-            // RxeNotifyFuncTable::<T>::build().
-            unsafe {
-                bindings::unregister_netdevice_notifier(&mut self.rxe_net_notifier.take().unwrap());
-            };
-        }
+        // Packets being received still reach the `RxPause` of the registration through their
+        // socket, so it must not go away before they are done.
+        // SAFETY: FFI call without preconditions; registrations are dropped in process context.
+        unsafe { bindings::synchronize_net() };
     }
 }
 
@@ -263,40 +860,265 @@ unsafe impl<T: RxeOperation> Sync for RxeRecvSockets<T> {}
 /// Implement this trait to complete the function.
 #[vtable]
 pub trait RxeOperation {
+    /// The devices created by `newlink`.
+    ///
+    /// Their [`IbDeviceOperations::DRIVER_ID`] selects the link type of `rdma link add` and the
+    /// devices unregistered when the [`Registration`] is dropped, so that another soft transport
+    /// such as siw can reuse the registration. It must have a
+    /// [`crate::ib::DriverId::link_type`].
+    type Device: IbDeviceOperations;
+
     /// notify() corresponds to the kernel's rxe_notify.
     fn notify() -> Result;
     /// newlink() corresponds to the kernel's rxe_newlink.
-    fn newlink() -> Result;
+    ///
+    /// `ibdev_name` is the name requested for the new RDMA device and `ndev` the network
+    /// interface it must be bound to.
+    /// `params` holds the overrides passed as attributes of the generic netlink `newlink`
+    /// command, and is [`LinkParams::DEFAULT`] for `rdma link add`, which cannot pass any.
+    /// Providers apply them to the device they create, and refuse those they do not support,
+    /// e.g. with `EOPNOTSUPP`.
+    fn newlink(ibdev_name: &CStr, ndev: &NetDevice, params: &LinkParams) -> Result;
     /// udp_recv() implement skb reception processing.
-    fn udp_recv() -> Result;
+    ///
+    /// `skb` starts at the UDP header of a packet received on the RoCEv2 port, or on
+    /// [`LEGACY_UDP_DPORT`] if [`SocketConfig::with_legacy_port`] is set. Returning
+    /// [`UdpRecvVerdict::Refused`] hands the packet back to the UDP stack, and
+    /// [`UdpRecvVerdict::Dropped`] frees it as a drop, e.g. when a [`SourceFilter`] rejects it.
+    fn udp_recv(skb: &SkBuff) -> UdpRecvVerdict;
+    /// Adds the provider's state to a snapshot, see [`Registration::write_snapshot`].
+    ///
+    /// Called in process context, e.g. when the debugfs `snapshot` file is read, after the
+    /// objects of the pools handed to [`RxeOperation::pools`]. Providers typically write the
+    /// indices of their queues with [`Snapshot::queue`].
+    fn snapshot(_snap: &mut Snapshot<'_>) {}
+    /// Hands each object [`Pool`] of the provider to `f`, for the leak report of
+    /// [`Registration::report_leaks`] and for the snapshots of [`Registration::write_snapshot`].
+    ///
+    /// Also called when the registration is dropped, once the devices are gone, so the pools must
+    /// outlive the devices, e.g. be reachable from a static of the provider.
+    fn pools(_f: &mut dyn FnMut(&dyn LiveResources)) {}
+    /// Reports that the link of `ndev` went up or down.
+    ///
+    /// Called under RTNL, once the link settled for [`SocketConfig::with_flap_holdoff`]. Only
+    /// states differing from the last one reported for the link are passed on, so providers can
+    /// map them one to one onto the port state of the device bound to `ndev`. Once this returns,
+    /// the event is dispatched to ib_core and the consumers for every port of that device, so
+    /// `query_port` must report the new state by then.
+    fn port_event(_ndev: &NetDevice, _event: LinkEvent) {}
+    /// Returns the injector controlled through the debugfs `qp_faults` file, the one the devices'
+    /// [`crate::ib::IbDeviceOperations::qp_faults`] return.
+    ///
+    /// The file only exists with `CONFIG_RUST_RDMA_FAULT_INJECTION` and if this returns an
+    /// injector, typically a `static` of the provider.
+    fn qp_faults() -> Option<&'static QpFaultInjector> {
+        None
+    }
+    /// Adds the provider's files to the debugfs directory of `dev`, a device of the driver of
+    /// [`RxeOperation::Device`], with [`DeviceDir::file`].
+    ///
+    /// Called in process context once the device is registered, or when the registration is
+    /// created for the devices that already exist. The files are removed, and their closures
+    /// dropped, before the device is freed or the registration goes away.
+    fn debugfs(_dev: &ObservedDevice, _dir: &mut DeviceDir) {}
 }
 
-///Build kernel's 'struct notifier_block' type with rxe device operation
-struct RxeNotifyFuncTable<T>(marker::PhantomData<T>);
+/// Points `sock` to the receive function dropping every packet if `paused` is set, and to the
+/// regular one otherwise.
+///
+/// # Safety
+///
+/// `sock` must have been set up by `setup_udp_tunnel_sock` and not be released yet.
+unsafe fn set_encap_rcv<T: RxeOperation>(sock: *mut bindings::socket, legacy: bool, paused: bool) {
+    let encap_rcv = RxeUdpEncapRecvFuncTable::<T>::build_func(legacy, paused);
+    // SAFETY: The socket is valid by the safety requirements. The UDP stack reads `encap_rcv`
+    // with `READ_ONCE`, so a single volatile write pairs with it.
+    unsafe {
+        let up = bindings::udp_sk((*sock).sk);
+        ptr::write_volatile(ptr::addr_of_mut!((*up).encap_rcv), encap_rcv);
+    }
+}
 
-impl<T: RxeOperation> RxeNotifyFuncTable<T> {
-    /// Builds an instance of [`struct notifier_block`].
+/// Sockets owned by one network namespace, stored in its `net_generic` area.
+#[repr(C)]
+struct RxeNsSockets {
+    sk4: *mut bindings::socket,
+    sk6: *mut bindings::socket,
+    legacy_sk4: *mut bindings::socket,
+    legacy_sk6: *mut bindings::socket,
+}
+
+impl RxeNsSockets {
+    /// Releases the sockets created so far.
+    fn release(&mut self) {
+        for sock in [
+            &mut self.sk4,
+            &mut self.sk6,
+            &mut self.legacy_sk4,
+            &mut self.legacy_sk6,
+        ] {
+            if !sock.is_null() {
+                // SAFETY: The socket was created in rxe_ns_init() and not released since.
+                unsafe { bindings::udp_tunnel_sock_release(*sock) };
+                *sock = ptr::null_mut();
+            }
+        }
+    }
+}
+
+/// State shared with the per-namespace callbacks.
+///
+/// `pernet_operations` callbacks only get the namespace, so the settings they need live in a
+/// global, like the C driver's `rxe_net_id`. This limits per-namespace sockets to one
+/// registration at a time.
+struct PernetState {
+    net_id: UnsafeCell<core::ffi::c_uint>,
+    port: AtomicU16,
+    /// The compatibility port, or 0.
+    legacy_port: AtomicU16,
+    /// The pauses of the registration, set before the operations are registered.
+    rx: AtomicPtr<RxPause>,
+    in_use: AtomicBool,
+}
+
+// SAFETY: `net_id` is only written by `register_pernet_subsys`, before any callback can read it.
+unsafe impl Sync for PernetState {}
+
+static PERNET: PernetState = PernetState {
+    net_id: UnsafeCell::new(0),
+    port: AtomicU16::new(ROCE_V2_UDP_DPORT),
+    legacy_port: AtomicU16::new(0),
+    rx: AtomicPtr::new(ptr::null_mut()),
+    in_use: AtomicBool::new(false),
+};
+
+/// Build kernel's 'struct pernet_operations' type with rxe socket management
+struct RxePernetTable<T>(marker::PhantomData<T>);
+
+impl<T: RxeOperation> RxePernetTable<T> {
+    /// Builds an instance of [`struct pernet_operations`].
     ///
     /// # Safety
     ///
     /// The caller must ensure that the adapter is compatible with the way the device is registered.
-    pub(crate) fn build() -> bindings::notifier_block {
-        Self::NOTIFYFUNC
+    pub(crate) fn build() -> bindings::pernet_operations {
+        bindings::pernet_operations {
+            init: Some(Self::rxe_ns_init),
+            exit: Some(Self::rxe_ns_exit),
+            id: PERNET.net_id.get(),
+            size: mem::size_of::<RxeNsSockets>(),
+            ..Default::default()
+        }
     }
 
-    const NOTIFYFUNC: bindings::notifier_block = bindings::notifier_block {
-        notifier_call: Some(Self::rxe_notify),
-        next: ptr::null_mut(),
-        priority: 0,
-    };
+    /// Returns the sockets slot of `net`.
+    ///
+    /// # Safety
+    ///
+    /// `net` must be valid and the pernet operations must be registered.
+    unsafe fn sockets(net: *mut bindings::net) -> *mut RxeNsSockets {
+        // SAFETY: `net_id` was assigned by `register_pernet_subsys` and the area has the size of
+        // `RxeNsSockets`.
+        unsafe { bindings::net_generic(net, *PERNET.net_id.get()).cast() }
+    }
 
-    unsafe extern "C" fn rxe_notify(
-        _not_blk: *mut bindings::notifier_block,
-        _event: core::ffi::c_ulong,
-        _arg: *mut core::ffi::c_void,
-    ) -> core::ffi::c_int {
-        let _ = T::notify();
-        return 0;
+    unsafe extern "C" fn rxe_ns_init(net: *mut bindings::net) -> core::ffi::c_int {
+        // SAFETY: The networking core passes a valid namespace that outlives this call.
+        let ns = unsafe { Namespace::from_ptr(net) };
+        let port = PERNET.port.load(Ordering::Acquire);
+        // SAFETY: `rx` was set before the operations were registered, and the registration it
+        // belongs to unregisters them before it goes away.
+        let rx = unsafe { &*PERNET.rx.load(Ordering::Acquire) };
+        // SAFETY: The area is zero-initialised by the networking core and only used by us.
+        let socks = unsafe { &mut *Self::sockets(net) };
+
+        if let Err(e) = Self::ns_sockets_create(ns, socks, port, false, rx) {
+            return e.to_kernel_errno();
+        }
+        let legacy_port = PERNET.legacy_port.load(Ordering::Acquire);
+        if legacy_port != 0 {
+            if let Err(e) = Self::ns_sockets_create(ns, socks, legacy_port, true, rx) {
+                socks.release();
+                return e.to_kernel_errno();
+            }
+        }
+        0
+    }
+
+    /// Creates the sockets of `port` in `ns`, the compatibility ones if `legacy` is set.
+    ///
+    /// The IPv4 socket is released again if the IPv6 one cannot be created.
+    fn ns_sockets_create(
+        ns: &Namespace,
+        socks: &mut RxeNsSockets,
+        port: u16,
+        legacy: bool,
+        rx: &RxPause,
+    ) -> Result {
+        let sk4 = RxeRecvSockets::<T>::ipv4_sock_create(ns, port, legacy, rx)?;
+        let sk6 = match RxeRecvSockets::<T>::ipv6_sock_create(ns, port, legacy, rx) {
+            Ok(sock) => sock.unwrap_or(ptr::null_mut()),
+            Err(e) => {
+                // SAFETY: `sk4` was created just above.
+                unsafe { bindings::udp_tunnel_sock_release(sk4) };
+                return Err(e);
+            }
+        };
+        if legacy {
+            socks.legacy_sk4 = sk4;
+            socks.legacy_sk6 = sk6;
+        } else {
+            socks.sk4 = sk4;
+            socks.sk6 = sk6;
+        }
+        Ok(())
+    }
+
+    /// Points the sockets of every namespace to the receive function matching `paused`; the
+    /// caller waits for the packets being received.
+    ///
+    /// # Safety
+    ///
+    /// The pernet operations must be registered.
+    unsafe fn swap_encap_rcv(paused: bool) {
+        let lock = ptr::addr_of_mut!(bindings::pernet_ops_rwsem);
+        // Namespaces are set up and torn down with the pernet lock held for reading, and listed
+        // or unlisted meanwhile, so holding it for writing keeps the list and the sockets of the
+        // listed namespaces stable. Namespaces being set up concurrently already saw the new
+        // state in `rxe_ns_init`.
+        // SAFETY: FFI call without preconditions; our callers may sleep and do not hold RTNL,
+        // which nests inside the pernet lock.
+        unsafe { bindings::down_write(lock) };
+        let head = ptr::addr_of_mut!(bindings::net_namespace_list);
+        // SAFETY: The list is stable while we hold the pernet lock.
+        let mut pos = unsafe { (*head).next };
+        while pos != head {
+            // SAFETY: The entries of the list are the `list` fields of live namespaces, whose
+            // `rxe_ns_init` ran since the operations are registered.
+            unsafe {
+                let net = crate::container_of!(pos, bindings::net, list) as *mut bindings::net;
+                let socks = &*Self::sockets(net);
+                for (sock, legacy) in [
+                    (socks.sk4, false),
+                    (socks.sk6, false),
+                    (socks.legacy_sk4, true),
+                    (socks.legacy_sk6, true),
+                ] {
+                    if !sock.is_null() {
+                        set_encap_rcv::<T>(sock, legacy, paused);
+                    }
+                }
+                pos = (*pos).next;
+            }
+        }
+        // SAFETY: Paired with the `down_write` above.
+        unsafe { bindings::up_write(lock) };
+    }
+
+    unsafe extern "C" fn rxe_ns_exit(net: *mut bindings::net) {
+        // SAFETY: The networking core passes a valid namespace with our area still allocated.
+        let socks = unsafe { &mut *Self::sockets(net) };
+        socks.release();
     }
 }
 
@@ -304,17 +1126,19 @@ impl<T: RxeOperation> RxeNotifyFuncTable<T> {
 struct RxeRdmaLinkTable<T>(marker::PhantomData<T>);
 
 impl<T: RxeOperation> RxeRdmaLinkTable<T> {
-    /// Builds an instance of [`struct rxe_link_ops`].
+    /// Builds an instance of [`struct rxe_link_ops`] for the link type `link_type`.
     ///
     /// # Safety
     ///
     /// The caller must ensure that the adapter is compatible with the way the device is registered.
-    pub(crate) fn build() -> bindings::rdma_link_ops {
-        Self::RXELINKFUNC
+    pub(crate) fn build(link_type: &'static CStr) -> bindings::rdma_link_ops {
+        let mut ops = Self::RXELINKFUNC;
+        ops.type_ = link_type.as_char_ptr();
+        ops
     }
 
     const RXELINKFUNC: bindings::rdma_link_ops = bindings::rdma_link_ops {
-        type_: "rxe".as_ptr() as *const i8,
+        type_: ptr::null(),
         newlink: Some(Self::rxe_newlink),
         list: bindings::list_head {
             next: ptr::null_mut(),
@@ -322,12 +1146,52 @@ impl<T: RxeOperation> RxeRdmaLinkTable<T> {
         },
     };
 
+    /// Creates the device `ibdev_name` on `ndev` with the overrides `params`, for both
+    /// `rdma link add` and the generic netlink `newlink` command.
+    pub(crate) fn add(ibdev_name: &CStr, ndev: &NetDevice, params: &LinkParams) -> Result {
+        if let Err(e) = net::check_link(ndev) {
+            pr_err!(
+                "Cannot add {} on {}: {}\n",
+                ibdev_name,
+                ndev.name(),
+                e.describe()
+            );
+            return Err(e.to_error());
+        }
+        let reg = ACTIVE.load(Ordering::Acquire).cast::<Registration<T>>();
+        // SAFETY: The link ops and the family are only registered while `ACTIVE` points to the
+        // pinned registration they belong to, and `Drop` unregisters them before it goes away.
+        if let Some(reg) = unsafe { reg.as_ref() } {
+            reg.ensure_sockets()?;
+        }
+        T::newlink(ibdev_name, ndev, params).map_err(|e| {
+            if !params.is_default() {
+                pr_err!("Cannot add {} with {}\n", ibdev_name, params);
+            }
+            e
+        })
+    }
+
     unsafe extern "C" fn rxe_newlink(
-        _ibdev_name: *const core::ffi::c_char,
-        _ndev: *mut bindings::net_device,
+        ibdev_name: *const core::ffi::c_char,
+        ndev: *mut bindings::net_device,
     ) -> core::ffi::c_int {
-        let _ = T::newlink();
-        return 0;
+        if ibdev_name.is_null() {
+            return EINVAL.to_kernel_errno();
+        }
+        if ndev.is_null() {
+            return net::SetupError::NoNetDevice.to_error().to_kernel_errno();
+        }
+        // SAFETY: `ibdev_name` is a non-null, NUL-terminated string owned by the netlink request
+        // for the duration of this callback.
+        let ibdev_name = unsafe { CStr::from_char_ptr(ibdev_name) };
+        // SAFETY: `ndev` is non-null and ib_core holds a reference to it (under RTNL) for the
+        // duration of this callback.
+        let ndev = unsafe { NetDevice::from_ptr(ndev) };
+        match Self::add(ibdev_name, ndev, &LinkParams::DEFAULT) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
     }
 }
 
@@ -338,19 +1202,160 @@ impl<T: RxeOperation> RxeUdpEncapRecvFuncTable<T> {
     /// # Safety
     ///
     /// The caller must ensure that the adapter is compatible with the way the device is registered.
-    pub(crate) fn build_func() -> Option<
+    pub(crate) fn build_func(
+        legacy: bool,
+        paused: bool,
+    ) -> Option<
         unsafe extern "C" fn(
             sk: *mut bindings::sock,
             skb: *mut bindings::sk_buff,
         ) -> core::ffi::c_int,
     > {
-        Some(Self::rxe_udp_encap_recv)
+        if paused {
+            Some(Self::rxe_udp_encap_drop)
+        } else if legacy {
+            Some(Self::rxe_udp_encap_recv_legacy)
+        } else {
+            Some(Self::rxe_udp_encap_recv)
+        }
+    }
+
+    unsafe extern "C" fn rxe_udp_encap_drop(
+        sk: *mut bindings::sock,
+        skb: *mut bindings::sk_buff,
+    ) -> core::ffi::c_int {
+        // SAFETY: The sockets are set up with the `RxPause` of their registration as user data,
+        // which outlives the packets they receive.
+        let rx = unsafe { &*(*sk).sk_user_data.cast::<RxPause>() };
+        rx.drops.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: The UDP tunnel layer transfers ownership of a valid skb to `encap_rcv`; it is
+        // released when it goes out of scope.
+        drop(unsafe { SkBuff::from_raw(skb) });
+        0
+    }
+
+    unsafe extern "C" fn rxe_udp_encap_recv_legacy(
+        sk: *mut bindings::sock,
+        skb: *mut bindings::sk_buff,
+    ) -> core::ffi::c_int {
+        LEGACY_PACKETS.fetch_add(1, Ordering::Relaxed);
+        // SAFETY: Both ports share the same dispatch; the arguments are passed on unchanged.
+        unsafe { Self::rxe_udp_encap_recv(sk, skb) }
     }
     unsafe extern "C" fn rxe_udp_encap_recv(
         _sk: *mut bindings::sock,
-        _skb: *mut bindings::sk_buff,
+        skb: *mut bindings::sk_buff,
     ) -> core::ffi::c_int {
-        let _ = T::udp_recv();
-        return 0;
+        // SAFETY: The UDP tunnel layer transfers ownership of a valid skb to `encap_rcv`.
+        let skb = unsafe { SkBuff::from_raw(skb) };
+        if DEDUP_ENABLED.load(Ordering::Acquire) {
+            if let Ok(pkt) = skb.roce_packet() {
+                if DEDUP.check(&DupKey::of(&pkt), jiffies() as u32) {
+                    // The copy is released when it goes out of scope.
+                    return 0;
+                }
+            }
+        }
+        trace::recv_pkt(&skb);
+        match T::udp_recv(&skb) {
+            // The skb is released when it goes out of scope.
+            UdpRecvVerdict::Consumed => 0,
+            // A positive return value asks the UDP stack to process the skb as a regular
+            // datagram, so ownership goes back to it.
+            UdpRecvVerdict::Refused => {
+                skb.into_raw();
+                1
+            }
+            UdpRecvVerdict::Dropped => {
+                skb.drop_packet();
+                0
+            }
+        }
+    }
+}
+
+/// Cases of the `rust_rdma` KUnit suite checking [`Registration::example_config`].
+#[cfg(CONFIG_RUST_RDMA_KUNIT_TEST)]
+pub(crate) mod kunit {
+    use super::{Registration, DEFAULT_FLAP_HOLDOFF_MS, ROCE_V2_UDP_DPORT};
+    use crate::bindings;
+    use crate::ib::cq::{CqInitAttr, CqNotify};
+    use crate::ib::device::{DeviceAttr, PortAttr, PortImmutable};
+    use crate::ib::kunit::expect;
+    use crate::ib::qp::QpInitAttr;
+    use crate::rdma::prelude::*;
+
+    /// Devices of [`Ops`], which never creates any.
+    struct Dev;
+
+    #[vtable]
+    impl IbDeviceOperations for Dev {
+        type Data = ();
+
+        const DRIVER_ID: DriverId = DriverId::Rxe;
+
+        fn query_device(_dev: &DeviceRef<Self>, _attr: &mut DeviceAttr) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn query_port(_dev: &DeviceRef<Self>, _port: u32, _attr: &mut PortAttr) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn get_port_immutable(
+            _dev: &DeviceRef<Self>,
+            _port: u32,
+            _imm: &mut PortImmutable,
+        ) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn alloc_pd(_dev: &DeviceRef<Self>) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn create_cq(_dev: &DeviceRef<Self>, _attr: &mut CqInitAttr) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn poll_cq(_cq: &CompletionQueue<Self>) -> Option<WorkCompletion> {
+            None
+        }
+        fn req_notify_cq(_cq: &CompletionQueue<Self>, _notify: CqNotify) -> Result<bool> {
+            Err(EOPNOTSUPP)
+        }
+        fn create_qp(_dev: &DeviceRef<Self>, _init: &QpInitAttr) -> Result<(u32, ())> {
+            Err(EOPNOTSUPP)
+        }
+        fn modify_qp(_qp: &QueuePair<Self>, _attr: &QpAttr) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn post_send(_qp: &QueuePair<Self>, _wr: &SendWr) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn post_recv(_qp: &QueuePair<Self>, _wr: &RecvWr) -> Result {
+            Err(EOPNOTSUPP)
+        }
+    }
+
+    struct Ops;
+
+    #[vtable]
+    impl rxe::RxeOperation for Ops {
+        type Device = Dev;
+
+        fn notify() -> Result {
+            Ok(())
+        }
+        fn newlink(_ibdev_name: &CStr, _ndev: &NetDevice, _params: &LinkParams) -> Result {
+            Err(EOPNOTSUPP)
+        }
+        fn udp_recv(_skb: &SkBuff) -> UdpRecvVerdict {
+            UdpRecvVerdict::Refused
+        }
+    }
+
+    /// Checks [`Registration::example_config`].
+    pub(crate) unsafe extern "C" fn socket_config(test: *mut bindings::kunit) {
+        let config = Registration::<Ops>::example_config();
+        expect!(test, config.port() == ROCE_V2_UDP_DPORT);
+        expect!(test, config.legacy_port().is_none());
+        expect!(test, !config.per_netns());
+        expect!(test, config.flap_holdoff() == DEFAULT_FLAP_HOLDOFF_MS);
     }
 }
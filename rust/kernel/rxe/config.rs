@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Read-mostly device settings published under RCU.
+//!
+//! An [`RcuConfig`] holds the current [`DeviceConfig`] of a device, or any other small `Copy`
+//! settings, in a heap allocation that is never modified once published. The packet path reads it
+//! under `rcu_read_lock()`, without taking a lock or retrying, and always sees one consistent
+//! version. Writers, e.g. sysfs `store` callbacks, build a modified copy, swap it in and free the
+//! old version once an RCU grace period has elapsed.
+//!
+//! The settings themselves and their parsing live in [`crate::rdma::dev_config`].
+
+use alloc::boxed::Box;
+use core::fmt;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::rdma::dev_config::{ConfigKey, DeviceConfig};
+use crate::sync::smutex::Mutex;
+
+/// Settings swapped as a whole under RCU.
+///
+/// # Invariants
+///
+/// `current` points to a valid allocation created by `Box`, which is only freed after it was
+/// replaced and an RCU grace period elapsed, or when the [`RcuConfig`] is dropped.
+pub struct RcuConfig<T: Copy> {
+    current: AtomicPtr<T>,
+    /// Serialises the writers, which sleep while waiting for readers.
+    writer: Mutex<()>,
+}
+
+// SAFETY: The settings are copied out to readers of any thread, and freed by the writer that
+// replaced them.
+unsafe impl<T: Copy + Send> Send for RcuConfig<T> {}
+// SAFETY: Readers only get shared access to published versions; writers are serialised.
+unsafe impl<T: Copy + Send + Sync> Sync for RcuConfig<T> {}
+
+impl<T: Copy> RcuConfig<T> {
+    /// Publishes `value` as the first version.
+    pub fn try_new(value: T) -> Result<Self> {
+        // INVARIANT: The pointer comes from a `Box`.
+        Ok(Self {
+            current: AtomicPtr::new(Box::into_raw(Box::try_new(value)?)),
+            writer: Mutex::new(()),
+        })
+    }
+
+    /// Runs `f` on the current version, e.g. on the packet path.
+    ///
+    /// `f` runs in an RCU read-side critical section, so it must not sleep. A concurrent update
+    /// does not affect the version `f` sees.
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        // SAFETY: FFI call without preconditions.
+        unsafe { bindings::rcu_read_lock() };
+        // SAFETY: By the type invariants the version is valid, and it is not freed before a grace
+        // period elapses, which cannot happen while we are in the read-side critical section.
+        let ret = f(unsafe { &*self.current.load(Ordering::Acquire) });
+        // SAFETY: Paired with the `rcu_read_lock` above.
+        unsafe { bindings::rcu_read_unlock() };
+        ret
+    }
+
+    /// Returns a copy of the current version.
+    pub fn get(&self) -> T {
+        self.read(|value| *value)
+    }
+
+    /// Publishes the version `f` derives from the current one, and returns it.
+    ///
+    /// Concurrent updates are serialised, so none of them is lost. The previous version is freed
+    /// once the readers that may still see it are done, which means waiting for a grace period:
+    /// must be called in process context. Nothing is published if `f` fails.
+    ///
+    /// Readers see either version, so settings that must not change while a packet is handled
+    /// are swapped with [`super::Registration::update_config`] instead.
+    pub fn update(&self, f: impl FnOnce(T) -> Result<T>) -> Result<T> {
+        let _writer = self.writer.lock();
+        let old = self.current.load(Ordering::Relaxed);
+        // SAFETY: By the type invariants `old` is valid, and only a writer, which we exclude,
+        // could free it.
+        let value = f(unsafe { *old })?;
+        let next = Box::try_new(value)?;
+        // INVARIANT: The new version comes from a `Box`; the old one is freed after a grace
+        // period below.
+        self.current.store(Box::into_raw(next), Ordering::Release);
+
+        // SAFETY: FFI call without preconditions; updates run in process context.
+        unsafe { bindings::synchronize_rcu() };
+        // SAFETY: `old` is unpublished and out of sight of RCU readers, and the next writer only
+        // gets the lock once we are done with it.
+        drop(unsafe { Box::from_raw(old) });
+        Ok(value)
+    }
+
+    /// Publishes `value`.
+    pub fn set(&self, value: T) -> Result {
+        self.update(|_| Ok(value)).map(|_| ())
+    }
+}
+
+impl RcuConfig<DeviceConfig> {
+    /// Parses `text`, e.g. written to a sysfs attribute, and publishes the settings with `key` set
+    /// to it.
+    ///
+    /// Fails with `EINVAL` if the text does not parse or the value is out of range.
+    pub fn store(&self, key: ConfigKey, text: &str) -> Result {
+        self.update(|config| config.with(key, text).map_err(|_| EINVAL))
+            .map(|_| ())
+    }
+
+    /// Writes the current value of `key`, e.g. for a sysfs `show` callback.
+    pub fn show(&self, key: ConfigKey, w: &mut dyn fmt::Write) -> fmt::Result {
+        self.get().show(key, w)
+    }
+}
+
+impl<T: Copy> Drop for RcuConfig<T> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants the version is valid; with `&mut self` nobody reads it
+        // anymore.
+        drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+    }
+}
@@ -0,0 +1,277 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Per-CPU software counters of the data path.
+//!
+//! The requester, responder and completer of every QP count packets and protocol events on
+//! whichever CPU they run. A shared atomic would bounce its cache line between those CPUs on
+//! every packet, so each CPU gets its own copy of the counters, which is only written by that
+//! CPU, and the copies are summed when the counters are read, which is rare: `rdma statistic`,
+//! the hardware counters of the port, snapshots.
+//!
+//! Providers keep a [`Counters`] in their device data, count with [`rxe_inc!`] and [`rxe_add!`],
+//! and report the sums through [`crate::ib::IbDeviceOperations::get_hw_stats`] with
+//! [`Counters::fill`], describing them with [`Counters::HW_STATS`]. The send path counts the
+//! packets it hands to the network itself, see [`super::net::transmit`].
+//!
+//! The P_Key and Q_Key violations the receive path finds with [`Counters::violations`] are
+//! reported after the per-CPU counters, as `pkey_violations` and `qkey_violations`.
+
+use crate::bindings;
+use crate::c_str;
+use crate::error::{code::*, Result};
+use crate::ib::hw_stats::{HwCounter, HwStats};
+use crate::rdma::port_counters::PortTotals;
+use crate::rdma::violation::KeyViolations;
+use crate::str::CStr;
+
+/// A software counter of the data path.
+///
+/// The names are those of the C driver, which tools and dashboards already know.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Counter {
+    /// Packets handed to the network, `sent_pkts`.
+    SentPkts,
+    /// Packets received and accepted, `rcvd_pkts`.
+    RcvdPkts,
+    /// Requests received again, e.g. after a lost ACK, `duplicate_request`.
+    DuplicateRequest,
+    /// Requests received ahead of the expected PSN, `out_of_seq_request`.
+    OutOfSeqRequest,
+    /// Sequence error NAKs received, `rcvd_seq_err`.
+    RcvdSeqErr,
+    /// Retransmissions started by the completer, `completer_retry_err`.
+    CompleterRetryErr,
+    /// QPs that ran out of retries, `retry_exceeded_err`.
+    RetryExceededErr,
+    /// Packets that could not be sent, `send_err`.
+    SendErr,
+}
+
+/// Number of [`Counter`]s.
+pub const NUM_COUNTERS: usize = 8;
+
+impl Counter {
+    /// Every counter, in the order of their values.
+    pub const ALL: [Self; NUM_COUNTERS] = [
+        Self::SentPkts,
+        Self::RcvdPkts,
+        Self::DuplicateRequest,
+        Self::OutOfSeqRequest,
+        Self::RcvdSeqErr,
+        Self::CompleterRetryErr,
+        Self::RetryExceededErr,
+        Self::SendErr,
+    ];
+
+    /// Returns the name of the counter, as shown by `rdma statistic`.
+    pub const fn name(self) -> &'static CStr {
+        match self {
+            Self::SentPkts => c_str!("sent_pkts"),
+            Self::RcvdPkts => c_str!("rcvd_pkts"),
+            Self::DuplicateRequest => c_str!("duplicate_request"),
+            Self::OutOfSeqRequest => c_str!("out_of_seq_request"),
+            Self::RcvdSeqErr => c_str!("rcvd_seq_err"),
+            Self::CompleterRetryErr => c_str!("completer_retry_err"),
+            Self::RetryExceededErr => c_str!("retry_exceeded_err"),
+            Self::SendErr => c_str!("send_err"),
+        }
+    }
+}
+
+/// Number of hardware counters: the [`Counter`]s, then the P_Key and Q_Key violations.
+const NUM_HW_COUNTERS: usize = NUM_COUNTERS + 2;
+
+/// The hardware counter descriptions of the [`Counter`]s, in the same order, then of the
+/// [`KeyViolations`].
+const HW_COUNTERS: [HwCounter; NUM_HW_COUNTERS] = [
+    HwCounter::new(Counter::SentPkts.name()),
+    HwCounter::new(Counter::RcvdPkts.name()),
+    HwCounter::new(Counter::DuplicateRequest.name()),
+    HwCounter::new(Counter::OutOfSeqRequest.name()),
+    HwCounter::new(Counter::RcvdSeqErr.name()),
+    HwCounter::new(Counter::CompleterRetryErr.name()),
+    HwCounter::new(Counter::RetryExceededErr.name()),
+    HwCounter::new(Counter::SendErr.name()),
+    HwCounter::new(c_str!("pkey_violations")),
+    HwCounter::new(c_str!("qkey_violations")),
+];
+
+/// The sums of the per-CPU counters at one point in time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CounterValues([u64; NUM_COUNTERS]);
+
+impl CounterValues {
+    /// Returns the value of `counter`.
+    pub fn get(&self, counter: Counter) -> u64 {
+        self.0[counter as usize]
+    }
+
+    /// Returns the values in the order of [`Counter::ALL`].
+    pub fn as_slice(&self) -> &[u64] {
+        &self.0
+    }
+
+    /// Returns the port totals behind the `PortCounters` of the PMA, see
+    /// [`crate::rdma::port_counters`]; byte counts are not kept and stay 0.
+    pub fn port_totals(&self) -> PortTotals {
+        PortTotals {
+            xmit_pkts: self.get(Counter::SentPkts),
+            rcv_pkts: self.get(Counter::RcvdPkts),
+            xmit_discards: self.get(Counter::SendErr),
+            ..PortTotals::ZERO
+        }
+    }
+}
+
+/// A set of [`Counter`]s with a copy per CPU.
+///
+/// # Invariants
+///
+/// `ptr` is a per-CPU allocation of `NUM_COUNTERS` `u64`s, owned by the set.
+pub struct Counters {
+    ptr: *mut u64,
+    violations: KeyViolations,
+}
+
+// SAFETY: Each CPU only writes its own copy, with per-CPU operations; readers only read.
+unsafe impl Send for Counters {}
+// SAFETY: As above.
+unsafe impl Sync for Counters {}
+
+impl Counters {
+    /// The description of the counters for [`crate::ib::IbDeviceOperations::alloc_hw_port_stats`].
+    pub const HW_STATS: HwStats = HwStats::new(&HW_COUNTERS);
+
+    /// Allocates a set of counters, all 0.
+    pub fn try_new() -> Result<Self> {
+        let size = core::mem::size_of::<[u64; NUM_COUNTERS]>();
+        // SAFETY: FFI call without preconditions; the allocation is zeroed.
+        let ptr = unsafe { bindings::__alloc_percpu(size, core::mem::align_of::<u64>()) };
+        if ptr.is_null() {
+            return Err(ENOMEM);
+        }
+        // INVARIANT: The allocation has room for every counter on every CPU.
+        Ok(Self {
+            ptr: ptr.cast(),
+            violations: KeyViolations::new(),
+        })
+    }
+
+    /// Returns the key violation counters, which check the keys of received packets.
+    pub fn violations(&self) -> &KeyViolations {
+        &self.violations
+    }
+
+    /// Adds `n` to `counter` on the current CPU.
+    ///
+    /// Safe in any context, including hard interrupts; see also [`rxe_add!`].
+    #[inline]
+    pub fn add(&self, counter: Counter, n: u64) {
+        // SAFETY: By the type invariants `ptr` is a per-CPU allocation holding every counter.
+        unsafe { bindings::this_cpu_add_u64(self.ptr.add(counter as usize), n) };
+    }
+
+    /// Adds 1 to `counter` on the current CPU, see also [`rxe_inc!`].
+    #[inline]
+    pub fn inc(&self, counter: Counter) {
+        self.add(counter, 1);
+    }
+
+    /// Returns the sum of `counter` over every CPU.
+    ///
+    /// Increments running meanwhile may or may not be included.
+    pub fn get(&self, counter: Counter) -> u64 {
+        let mut sum = 0u64;
+        self.for_each_cpu(|values| {
+            sum = sum.wrapping_add(values[counter as usize]);
+        });
+        sum
+    }
+
+    /// Returns the sums of every counter.
+    pub fn values(&self) -> CounterValues {
+        let mut sums = CounterValues::default();
+        self.for_each_cpu(|values| {
+            for (sum, value) in sums.0.iter_mut().zip(values) {
+                *sum = sum.wrapping_add(*value);
+            }
+        });
+        sums
+    }
+
+    /// Writes the sums to `values`, in the order of [`Counters::HW_STATS`], and returns how many
+    /// were written, as [`crate::ib::IbDeviceOperations::get_hw_stats`] expects.
+    pub fn fill(&self, values: &mut [u64]) -> usize {
+        let sums = self.values();
+        let mut all = [0; NUM_HW_COUNTERS];
+        all[..NUM_COUNTERS].copy_from_slice(&sums.0);
+        all[NUM_COUNTERS] = self.violations.pkey_violations();
+        all[NUM_COUNTERS + 1] = self.violations.qkey_violations();
+        let n = values.len().min(NUM_HW_COUNTERS);
+        values[..n].copy_from_slice(&all[..n]);
+        n
+    }
+
+    /// Resets every counter to 0, the key violations included, e.g. when the device is reset.
+    ///
+    /// Increments running meanwhile may be lost.
+    pub fn clear(&self) {
+        self.violations.reset();
+        self.for_each_cpu_ptr(|ptr| {
+            for i in 0..NUM_COUNTERS {
+                // SAFETY: `ptr` points to the counters of one CPU, a tear with a concurrent
+                // increment loses that increment at worst.
+                unsafe { core::ptr::write_volatile(ptr.add(i), 0) };
+            }
+        });
+    }
+
+    fn for_each_cpu_ptr(&self, mut f: impl FnMut(*mut u64)) {
+        // SAFETY: Reading `nr_cpu_ids` has no preconditions, it is set during early boot.
+        let nr_cpus = unsafe { bindings::nr_cpu_ids };
+        for cpu in 0..nr_cpus {
+            // SAFETY: FFI call without preconditions.
+            if unsafe { bindings::cpu_possible(cpu) } {
+                // SAFETY: By the type invariants `ptr` is a per-CPU allocation, which has a copy
+                // for every possible CPU.
+                f(unsafe { bindings::per_cpu_ptr(self.ptr.cast(), cpu) }.cast());
+            }
+        }
+    }
+
+    fn for_each_cpu(&self, mut f: impl FnMut(&[u64; NUM_COUNTERS])) {
+        self.for_each_cpu_ptr(|ptr| {
+            let mut values = [0; NUM_COUNTERS];
+            for (i, value) in values.iter_mut().enumerate() {
+                // SAFETY: `ptr` points to the counters of one CPU, which its CPU keeps writing,
+                // hence the volatile reads.
+                *value = unsafe { core::ptr::read_volatile(ptr.add(i)) };
+            }
+            f(&values);
+        });
+    }
+}
+
+impl Drop for Counters {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants `ptr` was allocated by `__alloc_percpu` and is ours.
+        unsafe { bindings::free_percpu(self.ptr.cast()) };
+    }
+}
+
+/// Adds 1 to a counter of a [`Counters`], e.g. `rxe_inc!(counters, SentPkts)`.
+#[macro_export]
+macro_rules! rxe_inc {
+    ($counters:expr, $counter:ident) => {
+        $counters.inc($crate::rxe::counters::Counter::$counter)
+    };
+}
+
+/// Adds to a counter of a [`Counters`], e.g. `rxe_add!(counters, RcvdPkts, n)`.
+#[macro_export]
+macro_rules! rxe_add {
+    ($counters:expr, $counter:ident, $n:expr) => {
+        $counters.add($crate::rxe::counters::Counter::$counter, $n)
+    };
+}
@@ -0,0 +1,519 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! debugfs files of a soft-RoCE registration.
+//!
+//! Every registration gets a directory named after it at the debugfs root, holding a `snapshot`
+//! file. Reading it dumps the state of the registration and of its provider in the format of
+//! [`crate::rdma::snapshot`], built at once into the `seq_file` buffer so that a single `cat`
+//! yields a coherent report.
+//!
+//! With `CONFIG_RUST_RDMA_FAULT_INJECTION`, providers that have a [`QpFaultInjector`] also get a
+//! `qp_faults` file: writing a command such as `fail rtr rts count=1` to it adds a rule, and
+//! reading it lists the rules and counters, see [`crate::rdma::qp_fault`].
+//!
+//! Every device of the registration also gets a directory named after it, e.g. `rxe0`, created
+//! once the device is registered and removed before it is freed. It holds a `sockets` file with
+//! the state of the UDP sockets the device receives from, and the files the provider adds with
+//! [`DeviceDir::file`] from [`RxeOperation::debugfs`], such as its QP list or CQ depths.
+//!
+//! Without `CONFIG_DEBUG_FS` nothing is created.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ptr;
+
+use super::{Registration, RxeOperation};
+use crate::bindings;
+#[cfg(CONFIG_DEBUG_FS)]
+use crate::error::code::*;
+use crate::error::Result;
+use crate::ib::{
+    DeviceObserver, DriverId, IbDeviceOperations, ObservedDevice, ObserverRegistration,
+};
+use crate::rdma::qp_fault::QpFaultInjector;
+use crate::str::CStr;
+
+/// Writes into a `seq_file`.
+#[cfg(CONFIG_DEBUG_FS)]
+pub(crate) struct SeqWriter(pub(crate) *mut bindings::seq_file);
+
+#[cfg(CONFIG_DEBUG_FS)]
+impl core::fmt::Write for SeqWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // SAFETY: The `seq_file` is valid for the duration of the `show` callback. On overflow
+        // seq_file retries the whole `show` with a larger buffer.
+        let ret = unsafe { bindings::seq_write(self.0, s.as_ptr().cast(), s.len() as _) };
+        if ret != 0 {
+            return Err(core::fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// Writes the contents of a file of a [`DeviceDir`].
+type ShowFn = Box<dyn Fn(&mut dyn fmt::Write) -> fmt::Result + Send + Sync>;
+
+/// The debugfs directory of a device, handed to [`RxeOperation::debugfs`] to add files to.
+///
+/// # Invariants
+///
+/// `dir` is null or was returned by `debugfs_create_dir`. The files created in it point to the
+/// elements of `files`, which are only dropped once `dir` is removed, and to `fops`, which
+/// outlives `dir`.
+pub struct DeviceDir {
+    dir: *mut bindings::dentry,
+    ibdev: *mut bindings::ib_device,
+    fops: *const bindings::file_operations,
+    files: Vec<Box<ShowFn>>,
+}
+
+impl DeviceDir {
+    /// Adds the read-only file `name`, whose contents `show` writes when it is read.
+    ///
+    /// `show` runs in process context and may be called several times per read, as the
+    /// `seq_file` buffer grows, so it should write a consistent view each time, e.g. the QPs of
+    /// the device with their states or the depths of its CQs. Fails with `ENOMEM` only; files
+    /// debugfs refuses are just missing.
+    pub fn file(
+        &mut self,
+        name: &CStr,
+        show: impl Fn(&mut dyn fmt::Write) -> fmt::Result + Send + Sync + 'static,
+    ) -> Result {
+        let show: ShowFn = Box::try_new(show)?;
+        self.files.try_push(Box::try_new(show)?)?;
+        #[cfg(CONFIG_DEBUG_FS)]
+        if !self.dir.is_null() {
+            let show = self
+                .files
+                .last()
+                .map_or(ptr::null(), |f| &**f as *const ShowFn);
+            // SAFETY: `name` is a valid string and debugfs copes with an error pointer as parent.
+            // By the type invariants `show` and `fops` outlive the directory, whose removal waits
+            // for the readers.
+            unsafe {
+                bindings::debugfs_create_file(
+                    name.as_char_ptr(),
+                    0o400,
+                    self.dir,
+                    show as *mut core::ffi::c_void,
+                    self.fops,
+                )
+            };
+        }
+        #[cfg(not(CONFIG_DEBUG_FS))]
+        let _ = name;
+        Ok(())
+    }
+}
+
+impl Drop for DeviceDir {
+    fn drop(&mut self) {
+        #[cfg(CONFIG_DEBUG_FS)]
+        if !self.dir.is_null() {
+            // SAFETY: By the type invariants `dir` was returned by `debugfs_create_dir`. Removal
+            // waits for the readers, so `files` can be dropped right after.
+            unsafe { bindings::debugfs_remove(self.dir) };
+        }
+    }
+}
+
+// SAFETY: The directory may be removed from any thread, and the closures are `Send`.
+unsafe impl Send for DeviceDir {}
+
+/// Creates and removes the directories of the devices of a registration as they come and go.
+///
+/// # Invariants
+///
+/// `debugfs` and `reg` stay valid while the observer is registered.
+#[cfg_attr(not(CONFIG_DEBUG_FS), allow(dead_code))]
+struct DirObserver {
+    debugfs: *const DebugFs,
+    reg: *const core::ffi::c_void,
+    driver: DriverId,
+    populate: fn(&ObservedDevice, &mut DeviceDir),
+}
+
+// SAFETY: The observer only reads through its pointers, and the callbacks are serialised.
+unsafe impl Send for DirObserver {}
+// SAFETY: As above.
+unsafe impl Sync for DirObserver {}
+
+impl DeviceObserver for DirObserver {
+    fn added(&self, dev: &ObservedDevice) {
+        if dev.belongs_to(self.driver) {
+            // SAFETY: By the type invariants `debugfs` is valid.
+            unsafe { &*self.debugfs }.add_device(dev, self.reg, self.populate);
+        }
+    }
+
+    fn removed(&self, dev: &ObservedDevice) {
+        if dev.belongs_to(self.driver) {
+            // SAFETY: By the type invariants `debugfs` is valid.
+            unsafe { &*self.debugfs }.remove_device(dev);
+        }
+    }
+}
+
+/// The debugfs directory of a registration.
+///
+/// # Invariants
+///
+/// `dir` is null or was returned by `debugfs_create_dir`, and `fops`, `fault_fops`, `sockets_fops`
+/// and `dir_fops` do not move while `dir` is not null. `devices` is only accessed from the
+/// callbacks of `observer`, which are serialised, or once it is dropped.
+#[cfg_attr(not(CONFIG_DEBUG_FS), allow(dead_code))]
+pub(crate) struct DebugFs {
+    dir: *mut bindings::dentry,
+    fops: bindings::file_operations,
+    fault_fops: bindings::file_operations,
+    sockets_fops: bindings::file_operations,
+    dir_fops: bindings::file_operations,
+    devices: UnsafeCell<Vec<DeviceDir>>,
+    observer: Option<ObserverRegistration<DirObserver>>,
+}
+
+impl DebugFs {
+    /// Creates an empty directory handle, nothing is created in debugfs yet.
+    pub(crate) fn new() -> Self {
+        // INVARIANT: `dir` is null.
+        Self {
+            dir: ptr::null_mut(),
+            fops: bindings::file_operations::default(),
+            fault_fops: bindings::file_operations::default(),
+            sockets_fops: bindings::file_operations::default(),
+            dir_fops: bindings::file_operations::default(),
+            devices: UnsafeCell::new(Vec::new()),
+            observer: None,
+        }
+    }
+
+    /// Creates the directory `name` and its `snapshot` file, which reads `reg`, plus the
+    /// `qp_faults` file if `T` has a [`QpFaultInjector`], then the directories of the devices of
+    /// `T`'s driver as they are registered.
+    ///
+    /// debugfs being unavailable is not an error: the files are only missing then.
+    ///
+    /// # Safety
+    ///
+    /// `self` and `reg` must not move until [`DebugFs::remove`] is called.
+    pub(crate) unsafe fn create<T: RxeOperation>(&mut self, name: &CStr, reg: &Registration<T>) {
+        #[cfg(not(CONFIG_DEBUG_FS))]
+        let _ = (name, reg);
+        #[cfg(CONFIG_DEBUG_FS)]
+        self.create_files(name, reg);
+    }
+
+    #[cfg(CONFIG_DEBUG_FS)]
+    fn create_files<T: RxeOperation>(&mut self, name: &CStr, reg: &Registration<T>) {
+        self.fops = SnapshotTable::<T>::build();
+        // SAFETY: `name` is a valid string; a null parent is the debugfs root.
+        let dir = unsafe { bindings::debugfs_create_dir(name.as_char_ptr(), ptr::null_mut()) };
+        // SAFETY: debugfs copes with an error pointer as parent. The caller of `create`
+        // guarantees that `reg` and `fops` stay valid until `remove`, which waits for the readers.
+        unsafe {
+            bindings::debugfs_create_file(
+                b"snapshot\0".as_ptr().cast(),
+                0o400,
+                dir,
+                reg as *const Registration<T> as *mut core::ffi::c_void,
+                &self.fops,
+            )
+        };
+        if let Some(faults) = T::qp_faults().filter(|_| cfg!(CONFIG_RUST_RDMA_FAULT_INJECTION)) {
+            self.fault_fops = QpFaultTable::build();
+            // SAFETY: As above; the injector is static.
+            unsafe {
+                bindings::debugfs_create_file(
+                    b"qp_faults\0".as_ptr().cast(),
+                    0o600,
+                    dir,
+                    faults as *const QpFaultInjector as *mut core::ffi::c_void,
+                    &self.fault_fops,
+                )
+            };
+        }
+        // INVARIANT: `dir` was just created, and the caller of `create` keeps `self` in place.
+        self.dir = dir;
+
+        self.sockets_fops = SocketsTable::<T>::build();
+        self.dir_fops = DeviceFileTable::build();
+        let observer = DirObserver {
+            debugfs: self,
+            reg: reg as *const Registration<T> as *const core::ffi::c_void,
+            driver: T::Device::DRIVER_ID,
+            populate: T::debugfs,
+        };
+        // The devices only lack their directories if no observer slot is left.
+        // INVARIANT: The caller of `create` keeps `self` and `reg` in place until `remove`, which
+        // unregisters the observer first.
+        self.observer = ObserverRegistration::try_new(observer).ok();
+    }
+
+    /// Creates the directory of `dev`, with its `sockets` file reading `reg`, and lets
+    /// `populate` add the provider's files.
+    #[cfg(CONFIG_DEBUG_FS)]
+    fn add_device(
+        &self,
+        dev: &ObservedDevice,
+        reg: *const core::ffi::c_void,
+        populate: fn(&ObservedDevice, &mut DeviceDir),
+    ) {
+        if self.dir.is_null() {
+            return;
+        }
+        // SAFETY: The device name is a valid string; debugfs copes with an error pointer as
+        // parent.
+        let dir = unsafe { bindings::debugfs_create_dir(dev.name().as_char_ptr(), self.dir) };
+        // INVARIANT: `dir` was just created, and `dir_fops` stays in place until `remove`, which
+        // removes the device directories first.
+        let mut devdir = DeviceDir {
+            dir,
+            ibdev: dev.as_ptr(),
+            fops: &self.dir_fops,
+            files: Vec::new(),
+        };
+        // SAFETY: As above; `reg` and `sockets_fops` stay valid until `remove`.
+        unsafe {
+            bindings::debugfs_create_file(
+                b"sockets\0".as_ptr().cast(),
+                0o400,
+                dir,
+                reg as *mut core::ffi::c_void,
+                &self.sockets_fops,
+            )
+        };
+        populate(dev, &mut devdir);
+        // SAFETY: By the type invariants only the serialised observer callbacks get here.
+        let devices = unsafe { &mut *self.devices.get() };
+        // Without memory the directory is removed right away by dropping it.
+        let _ = devices.try_push(devdir);
+    }
+
+    #[cfg(not(CONFIG_DEBUG_FS))]
+    fn add_device(
+        &self,
+        _dev: &ObservedDevice,
+        _reg: *const core::ffi::c_void,
+        _populate: fn(&ObservedDevice, &mut DeviceDir),
+    ) {
+    }
+
+    /// Removes the directory of `dev`, waiting for the running reads of its files.
+    fn remove_device(&self, dev: &ObservedDevice) {
+        // SAFETY: By the type invariants only the serialised observer callbacks get here.
+        let devices = unsafe { &mut *self.devices.get() };
+        if let Some(i) = devices.iter().position(|d| d.ibdev == dev.as_ptr()) {
+            drop(devices.swap_remove(i));
+        }
+    }
+
+    /// Removes the directory and waits for the running reads of its files.
+    pub(crate) fn remove(&mut self) {
+        // Unregistering the observer removes the directories of the remaining devices.
+        self.observer = None;
+        self.devices.get_mut().clear();
+        #[cfg(CONFIG_DEBUG_FS)]
+        if !self.dir.is_null() {
+            // SAFETY: By the type invariants `dir` was returned by `debugfs_create_dir`, which
+            // `debugfs_remove` accepts even if it is an error pointer.
+            unsafe { bindings::debugfs_remove(self.dir) };
+            self.dir = ptr::null_mut();
+        }
+    }
+}
+
+/// Build kernel's `struct file_operations` type for the `snapshot` file.
+#[cfg(CONFIG_DEBUG_FS)]
+struct SnapshotTable<T>(core::marker::PhantomData<T>);
+
+#[cfg(CONFIG_DEBUG_FS)]
+impl<T: RxeOperation> SnapshotTable<T> {
+    fn build() -> bindings::file_operations {
+        bindings::file_operations {
+            open: Some(Self::open_callback),
+            read: Some(bindings::seq_read),
+            llseek: Some(bindings::seq_lseek),
+            release: Some(bindings::single_release),
+            ..Default::default()
+        }
+    }
+
+    unsafe extern "C" fn open_callback(
+        inode: *mut bindings::inode,
+        file: *mut bindings::file,
+    ) -> core::ffi::c_int {
+        // SAFETY: The VFS passes a valid inode and file; `i_private` is the registration given to
+        // `debugfs_create_file`.
+        unsafe { bindings::single_open(file, Some(Self::show_callback), (*inode).i_private) }
+    }
+
+    unsafe extern "C" fn show_callback(
+        m: *mut bindings::seq_file,
+        _v: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: `private` is the registration passed to `single_open`, which outlives the file
+        // as `DebugFs::remove` waits for its readers.
+        let reg = unsafe { &*(*m).private.cast::<Registration<T>>() };
+        let mut w = SeqWriter(m);
+        // Overflows are detected by seq_file itself, which calls us again.
+        let _ = reg.write_snapshot(&mut w);
+        0
+    }
+}
+
+/// Build kernel's `struct file_operations` type for the `sockets` file of a device.
+#[cfg(CONFIG_DEBUG_FS)]
+struct SocketsTable<T>(core::marker::PhantomData<T>);
+
+#[cfg(CONFIG_DEBUG_FS)]
+impl<T: RxeOperation> SocketsTable<T> {
+    fn build() -> bindings::file_operations {
+        bindings::file_operations {
+            open: Some(Self::open_callback),
+            read: Some(bindings::seq_read),
+            llseek: Some(bindings::seq_lseek),
+            release: Some(bindings::single_release),
+            ..Default::default()
+        }
+    }
+
+    unsafe extern "C" fn open_callback(
+        inode: *mut bindings::inode,
+        file: *mut bindings::file,
+    ) -> core::ffi::c_int {
+        // SAFETY: The VFS passes a valid inode and file; `i_private` is the registration given to
+        // `debugfs_create_file`.
+        unsafe { bindings::single_open(file, Some(Self::show_callback), (*inode).i_private) }
+    }
+
+    unsafe extern "C" fn show_callback(
+        m: *mut bindings::seq_file,
+        _v: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: `private` is the registration passed to `single_open`, which outlives the file
+        // as `DebugFs::remove` waits for its readers.
+        let reg = unsafe { &*(*m).private.cast::<Registration<T>>() };
+        let _ = reg.write_sockets(&mut SeqWriter(m));
+        0
+    }
+}
+
+/// Build kernel's `struct file_operations` type for the files added with [`DeviceDir::file`].
+#[cfg(CONFIG_DEBUG_FS)]
+struct DeviceFileTable;
+
+#[cfg(CONFIG_DEBUG_FS)]
+impl DeviceFileTable {
+    fn build() -> bindings::file_operations {
+        bindings::file_operations {
+            open: Some(Self::open_callback),
+            read: Some(bindings::seq_read),
+            llseek: Some(bindings::seq_lseek),
+            release: Some(bindings::single_release),
+            ..Default::default()
+        }
+    }
+
+    unsafe extern "C" fn open_callback(
+        inode: *mut bindings::inode,
+        file: *mut bindings::file,
+    ) -> core::ffi::c_int {
+        // SAFETY: The VFS passes a valid inode and file; `i_private` is the closure given to
+        // `debugfs_create_file`.
+        unsafe { bindings::single_open(file, Some(Self::show_callback), (*inode).i_private) }
+    }
+
+    unsafe extern "C" fn show_callback(
+        m: *mut bindings::seq_file,
+        _v: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: `private` is the closure passed to `single_open`, which the `DeviceDir` only
+        // drops once the removal of the directory waited for the readers.
+        let show = unsafe { &*(*m).private.cast::<ShowFn>() };
+        let _ = show(&mut SeqWriter(m));
+        0
+    }
+}
+
+/// Longest command accepted by the `qp_faults` file.
+#[cfg(CONFIG_DEBUG_FS)]
+const MAX_COMMAND: usize = 64;
+
+/// Build kernel's `struct file_operations` type for the `qp_faults` file.
+#[cfg(CONFIG_DEBUG_FS)]
+struct QpFaultTable;
+
+#[cfg(CONFIG_DEBUG_FS)]
+impl QpFaultTable {
+    fn build() -> bindings::file_operations {
+        bindings::file_operations {
+            open: Some(Self::open_callback),
+            read: Some(bindings::seq_read),
+            write: Some(Self::write_callback),
+            llseek: Some(bindings::seq_lseek),
+            release: Some(bindings::single_release),
+            ..Default::default()
+        }
+    }
+
+    unsafe extern "C" fn open_callback(
+        inode: *mut bindings::inode,
+        file: *mut bindings::file,
+    ) -> core::ffi::c_int {
+        // SAFETY: The VFS passes a valid inode and file; `i_private` is the injector given to
+        // `debugfs_create_file`.
+        unsafe { bindings::single_open(file, Some(Self::show_callback), (*inode).i_private) }
+    }
+
+    unsafe extern "C" fn show_callback(
+        m: *mut bindings::seq_file,
+        _v: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: `private` is the static injector passed to `single_open`.
+        let faults = unsafe { &*(*m).private.cast::<QpFaultInjector>() };
+        let _ = faults.show(&mut SeqWriter(m));
+        0
+    }
+
+    unsafe extern "C" fn write_callback(
+        file: *mut bindings::file,
+        buf: *const core::ffi::c_char,
+        count: usize,
+        _ppos: *mut bindings::loff_t,
+    ) -> isize {
+        if count > MAX_COMMAND {
+            return EINVAL.to_kernel_errno() as isize;
+        }
+        let mut cmd = [0u8; MAX_COMMAND];
+        let mut pos = 0;
+        // SAFETY: `buf` is a user pointer to `count` bytes, which fit in `cmd`.
+        let len = unsafe {
+            bindings::simple_write_to_buffer(
+                cmd.as_mut_ptr().cast(),
+                MAX_COMMAND,
+                &mut pos,
+                buf.cast(),
+                count,
+            )
+        };
+        if len < 0 {
+            return len;
+        }
+        // SAFETY: The file was opened by `open_callback`, so its private data is the `seq_file`
+        // whose `private` is the static injector.
+        let faults = unsafe {
+            &*(*(*file).private_data.cast::<bindings::seq_file>())
+                .private
+                .cast::<QpFaultInjector>()
+        };
+        let ret = core::str::from_utf8(&cmd[..len as usize])
+            .map_err(|_| EINVAL)
+            .and_then(|text| faults.store(text).map_err(|_| EINVAL));
+        match ret {
+            Ok(()) => count as isize,
+            Err(e) => e.to_kernel_errno() as isize,
+        }
+    }
+}
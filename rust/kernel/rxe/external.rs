@@ -0,0 +1,464 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! The Rust data path on devices allocated by C.
+//!
+//! Drivers moving to Rust one piece at a time keep their C `struct ib_device` and verbs for a
+//! while: a C shim allocates and registers the device, and hands it to Rust, which runs the
+//! requester, responder and completer [`Tasks`] of its QPs. A [`DataPath`] is that half: it
+//! holds a reference on the foreign device, and a pool of the QP engines the shim creates,
+//! schedules and destroys through the functions its Rust module exports. The packets the shim
+//! receives go through [`DataPath::recv`], which finds their QP in the pool.
+//!
+//! With `CONFIG_RUST_RDMA_QP_TRACE` the engines keep a trace of their last protocol events, see
+//! [`Tasks::try_new_traced`]: the receive path records the ACKs and NAKs of the QP, and its timers
+//! their expiries. The traces of the attached QPs are listed in the `qp_traces` file of the
+//! debugfs directory of the data path, `rust_rxe_external_` followed by the device name.
+//!
+//! Unregistering a device waits for its references, so the data path registers an ib_client
+//! along with its reference, and gives the reference up in the client's `remove`, which ib_core
+//! calls before it waits. The QP engines stay until the shim removes them or the data path is
+//! dropped.
+//!
+//! Only the data path moves: the device keeps the ops, the lifetime and the sysfs surface the C
+//! side gave it, which is why this is separate from [`crate::ib::Device`].
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::{PhantomData, PhantomPinned};
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use super::net::SkBuff;
+use super::pool::{Pool, PoolEntry, PoolRef};
+use super::task::{QpEvent, QpTasks, TaskKind, Tasks};
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::ib::compat::try_pin;
+use crate::ib::ObservedDevice;
+use crate::rdma::flap::LinkEvent;
+use crate::rdma::opcode::OpcodeMask;
+use crate::rdma::pool::IndexRange;
+use crate::rdma::tracker::{LiveResources, ResourceKind};
+use crate::sync::smutex::Mutex;
+
+/// A reference on a registered `struct ib_device` of another driver.
+///
+/// # Invariants
+///
+/// `ptr` is a valid device on which the instance holds a reference.
+pub struct ExternalDevice {
+    ptr: *mut bindings::ib_device,
+}
+
+// SAFETY: The reference may be dropped from any thread, and the device is only read.
+unsafe impl Send for ExternalDevice {}
+// SAFETY: As above.
+unsafe impl Sync for ExternalDevice {}
+
+impl ExternalDevice {
+    /// Takes a reference on `ptr`.
+    ///
+    /// Fails with `ENODEV` if the device is not registered or already being unregistered. The
+    /// reference blocks the unregistration of the device until it is dropped; [`DataPath`] gives
+    /// it up when the device is unregistered.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must point to a valid device for the duration of the call, e.g. because the caller
+    /// holds a reference or is its driver.
+    pub unsafe fn from_raw(ptr: *mut bindings::ib_device) -> Result<Self> {
+        // SAFETY: `ptr` is valid per the safety requirements.
+        if !unsafe { bindings::ib_device_try_get(ptr) } {
+            return Err(ENODEV);
+        }
+        // INVARIANT: The reference was just taken.
+        Ok(Self { ptr })
+    }
+
+    /// Reports a port state change of the device, like
+    /// [`crate::ib::DeviceRef::dispatch_port_event`].
+    pub fn dispatch_port_event(&self, port: u32, event: LinkEvent) {
+        let mut ev = bindings::ib_event::default();
+        ev.device = self.ptr;
+        ev.event = match event {
+            LinkEvent::Up => bindings::ib_event_type_IB_EVENT_PORT_ACTIVE,
+            LinkEvent::Down => bindings::ib_event_type_IB_EVENT_PORT_ERR,
+        };
+        ev.element.port_num = port;
+        // SAFETY: By the type invariants the device is valid, and `ev` lives for the duration of
+        // the call.
+        unsafe { bindings::ib_dispatch_event(&ev) };
+    }
+}
+
+impl Deref for ExternalDevice {
+    type Target = ObservedDevice;
+
+    fn deref(&self) -> &ObservedDevice {
+        // SAFETY: By the type invariants the device stays valid while we hold the reference.
+        unsafe { ObservedDevice::from_ptr(self.ptr) }
+    }
+}
+
+impl Drop for ExternalDevice {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants we hold a reference, which unregistration waits for.
+        unsafe { bindings::ib_device_put(self.ptr) };
+    }
+}
+
+/// The QP engines of a QP of a foreign device.
+pub type QpEngines<C> = Pin<Box<Tasks<C>>>;
+
+/// The ib_client of a data path, which gives the device reference up on unregistration.
+///
+/// # Invariants
+///
+/// `client` is registered from [`DataPath::attach`] until the [`DataPath`] is dropped, and only
+/// added to `target`, with the link as its client data.
+struct Link {
+    client: UnsafeCell<bindings::ib_client>,
+    target: *mut bindings::ib_device,
+    dev: Mutex<Option<ExternalDevice>>,
+    /// Set once ib_core added the client to `target`.
+    linked: AtomicBool,
+    _pin: PhantomPinned,
+}
+
+// SAFETY: The client is only handed to ib_core, which synchronises its accesses, `target` is only
+// compared and `dev` is behind a mutex.
+unsafe impl Send for Link {}
+// SAFETY: As above.
+unsafe impl Sync for Link {}
+
+/// Serialises the registration of the clients, so that [`LINKING`] is the one being registered.
+static ATTACH: Mutex<()> = Mutex::new(());
+
+/// The link whose client is being registered, null otherwise.
+static LINKING: AtomicPtr<Link> = AtomicPtr::new(ptr::null_mut());
+
+impl Link {
+    unsafe extern "C" fn add(ibdev: *mut bindings::ib_device) -> core::ffi::c_int {
+        let link = LINKING.load(Ordering::Acquire);
+        // SAFETY: `LINKING` points to a pinned link while its client is being registered, which
+        // is when ib_core calls `add` for the devices that exist.
+        if link.is_null() || unsafe { (*link).target } != ibdev {
+            // ib_core then neither keeps a context for the device nor calls `remove` for it.
+            return EOPNOTSUPP.to_kernel_errno();
+        }
+        // SAFETY: As above; the client is registered, and the data lives until it is not.
+        unsafe {
+            bindings::ib_set_client_data(ibdev, (*link).client.get(), link.cast());
+            (*link).linked.store(true, Ordering::Release);
+        }
+        0
+    }
+
+    unsafe extern "C" fn remove(_ibdev: *mut bindings::ib_device, data: *mut core::ffi::c_void) {
+        // SAFETY: By the type invariants the client data is the link, which lives until the
+        // client is unregistered, which waits for this callback.
+        let link = unsafe { &*data.cast::<Link>() };
+        // Unregistration waits for the reference right after the clients are removed.
+        drop(link.dev.lock().take());
+    }
+}
+
+/// Prefix of the name of the debugfs directory of a data path.
+#[cfg(CONFIG_DEBUG_FS)]
+const DIR_PREFIX: &[u8] = b"rust_rxe_external_";
+
+/// Writes the trace of every attached QP of `qps` that keeps one, after its QP number.
+fn write_traces<C: QpTasks>(qps: &Pool<QpEngines<C>>, w: &mut dyn fmt::Write) -> fmt::Result {
+    let mut res = Ok(());
+    qps.for_each_live(&mut |qp| {
+        if res.is_err() {
+            return;
+        }
+        // A QP being removed is skipped.
+        if let Some(engines) = qps.lookup(qp.index).filter(|e| e.is_traced()) {
+            res = writeln!(w, "qp {:#x}", qp.index).and_then(|()| engines.dump_trace(w));
+        }
+    });
+    res
+}
+
+/// The debugfs directory of a data path, with its `qp_traces` file.
+///
+/// # Invariants
+///
+/// `dir` is null or was returned by `debugfs_create_dir`. Its file points to `fops`, which does
+/// not move while `dir` is not null, and to a pool that outlives the instance.
+#[cfg_attr(not(CONFIG_DEBUG_FS), allow(dead_code))]
+struct TraceDir<C: QpTasks> {
+    dir: *mut bindings::dentry,
+    fops: bindings::file_operations,
+    _p: PhantomData<C>,
+    _pin: PhantomPinned,
+}
+
+// SAFETY: The directory may be removed from any thread, and the file operations are only read.
+unsafe impl<C: QpTasks> Send for TraceDir<C> {}
+// SAFETY: As above.
+unsafe impl<C: QpTasks> Sync for TraceDir<C> {}
+
+impl<C: QpTasks> TraceDir<C> {
+    /// Creates the directory of `dev`, with the `qp_traces` file reading `qps`.
+    ///
+    /// Nothing is created without `CONFIG_RUST_RDMA_QP_TRACE`, as no QP keeps a trace then.
+    /// debugfs being unavailable is not an error: the file is only missing then.
+    ///
+    /// # Safety
+    ///
+    /// `qps` must not move and must outlive the returned directory.
+    unsafe fn try_new(dev: &ExternalDevice, qps: &Pool<QpEngines<C>>) -> Result<Pin<Box<Self>>> {
+        // INVARIANT: `dir` is null.
+        #[cfg_attr(not(CONFIG_DEBUG_FS), allow(unused_mut))]
+        let mut this = try_pin(Self {
+            dir: ptr::null_mut(),
+            fops: bindings::file_operations::default(),
+            _p: PhantomData,
+            _pin: PhantomPinned,
+        })?;
+        #[cfg(not(CONFIG_DEBUG_FS))]
+        let _ = (dev, qps);
+        #[cfg(CONFIG_DEBUG_FS)]
+        if cfg!(CONFIG_RUST_RDMA_QP_TRACE) {
+            // Device names are shorter than `IB_DEVICE_NAME_MAX`, which leaves room for the NUL.
+            let mut name = [0u8; DIR_PREFIX.len() + bindings::IB_DEVICE_NAME_MAX as usize];
+            let dev_name = dev.name().as_bytes();
+            name[..DIR_PREFIX.len()].copy_from_slice(DIR_PREFIX);
+            name[DIR_PREFIX.len()..][..dev_name.len()].copy_from_slice(dev_name);
+            // SAFETY: Nothing is moved out, the instance is only completed in place.
+            let inner = unsafe { this.as_mut().get_unchecked_mut() };
+            inner.fops = bindings::file_operations {
+                open: Some(Self::open_callback),
+                read: Some(bindings::seq_read),
+                llseek: Some(bindings::seq_lseek),
+                release: Some(bindings::single_release),
+                ..Default::default()
+            };
+            // SAFETY: `name` is NUL-terminated; a null parent is the debugfs root.
+            let dir =
+                unsafe { bindings::debugfs_create_dir(name.as_ptr().cast(), ptr::null_mut()) };
+            // SAFETY: debugfs copes with an error pointer as parent. `fops` is pinned with the
+            // instance and `qps` outlives it by the safety requirements, and `drop` removes the
+            // directory, which waits for the readers.
+            unsafe {
+                bindings::debugfs_create_file(
+                    b"qp_traces\0".as_ptr().cast(),
+                    0o400,
+                    dir,
+                    qps as *const Pool<QpEngines<C>> as *mut core::ffi::c_void,
+                    &inner.fops,
+                )
+            };
+            // INVARIANT: `dir` was just created, pointing to the pinned `fops`.
+            inner.dir = dir;
+        }
+        Ok(this)
+    }
+
+    #[cfg(CONFIG_DEBUG_FS)]
+    unsafe extern "C" fn open_callback(
+        inode: *mut bindings::inode,
+        file: *mut bindings::file,
+    ) -> core::ffi::c_int {
+        // SAFETY: The VFS passes a valid inode and file; `i_private` is the pool given to
+        // `debugfs_create_file`.
+        unsafe { bindings::single_open(file, Some(Self::show_callback), (*inode).i_private) }
+    }
+
+    #[cfg(CONFIG_DEBUG_FS)]
+    unsafe extern "C" fn show_callback(
+        m: *mut bindings::seq_file,
+        _v: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: `private` is the pool passed to `single_open`, which outlives the directory,
+        // whose removal waits for the readers.
+        let qps = unsafe { &*(*m).private.cast::<Pool<QpEngines<C>>>() };
+        // Overflows are detected by seq_file itself, which calls us again.
+        let _ = write_traces(qps, &mut super::debugfs::SeqWriter(m));
+        0
+    }
+}
+
+impl<C: QpTasks> Drop for TraceDir<C> {
+    fn drop(&mut self) {
+        #[cfg(CONFIG_DEBUG_FS)]
+        if !self.dir.is_null() {
+            // SAFETY: By the type invariants `dir` was returned by `debugfs_create_dir`, which
+            // `debugfs_remove` accepts even if it is an error pointer.
+            unsafe { bindings::debugfs_remove(self.dir) };
+        }
+    }
+}
+
+/// The Rust data path of a foreign device: its QP engines, indexed by QP number.
+///
+/// The device reference is given up when the C side unregisters the device; QPs still attached
+/// when the data path is dropped are then killed like on their removal.
+pub struct DataPath<C: QpTasks> {
+    // Must be dropped first: the debugfs file reads `qps`.
+    _traces: Pin<Box<TraceDir<C>>>,
+    qps: Box<Pool<QpEngines<C>>>,
+    link: Pin<Box<Link>>,
+}
+
+impl<C: QpTasks> DataPath<C> {
+    /// Attaches a data path for the QP numbers up to `max_qpn` to `ibdev`.
+    ///
+    /// Every QP number of the range has its own slot, so that the numbers the C side picks never
+    /// collide; the special and reserved QP numbers below 16 have none. Fails with `EINVAL` if
+    /// `max_qpn` is out of the range of QP numbers, and with `ENODEV` if the device is not
+    /// registered.
+    ///
+    /// # Safety
+    ///
+    /// `ibdev` must point to a valid device for the duration of the call, see
+    /// [`ExternalDevice::from_raw`].
+    pub unsafe fn attach(ibdev: *mut bindings::ib_device, max_qpn: u32) -> Result<Self> {
+        let range = IndexRange::of(ResourceKind::Qp);
+        if !range.contains(max_qpn) {
+            return Err(EINVAL);
+        }
+        let qps = Box::try_new(Pool::try_new(ResourceKind::Qp, max_qpn - range.min + 1)?)?;
+        // SAFETY: Guaranteed by the safety requirements.
+        let dev = unsafe { ExternalDevice::from_raw(ibdev) }?;
+        // SAFETY: The pool is boxed, and `traces` is dropped before it, here and in `DataPath`.
+        let traces = unsafe { TraceDir::try_new(&dev, &qps) }?;
+        let mut client = bindings::ib_client::default();
+        client.name = crate::c_str!("rust_rxe_external").as_char_ptr();
+        client.add = Some(Link::add);
+        client.remove = Some(Link::remove);
+        let link = try_pin(Link {
+            client: UnsafeCell::new(client),
+            target: ibdev,
+            dev: Mutex::new(Some(dev)),
+            linked: AtomicBool::new(false),
+            _pin: PhantomPinned,
+        })?;
+
+        let ret = {
+            let _guard = ATTACH.lock();
+            LINKING.store(&*link as *const Link as *mut Link, Ordering::Release);
+            // SAFETY: The client is pinned with the link, and unregistered in `drop`, or below.
+            let ret = unsafe { bindings::ib_register_client(link.client.get()) };
+            LINKING.store(ptr::null_mut(), Ordering::Release);
+            ret
+        };
+        if ret != 0 {
+            return Err(Error::from_kernel_errno(ret));
+        }
+        // INVARIANT: The client is registered and `add` only accepted `ibdev`.
+        let path = Self {
+            _traces: traces,
+            qps,
+            link,
+        };
+        if !path.link.linked.load(Ordering::Acquire) {
+            // The device was being unregistered, past the point where the clients are added.
+            return Err(ENODEV);
+        }
+        Ok(path)
+    }
+
+    /// Runs `f` on the device the data path is attached to, and returns its result.
+    ///
+    /// Returns `None` once the device is unregistered. The device cannot be unregistered while
+    /// `f` runs, so `f` must not wait for that.
+    pub fn with_device<R>(&self, f: impl FnOnce(&ExternalDevice) -> R) -> Option<R> {
+        self.link.dev.lock().as_ref().map(f)
+    }
+
+    /// Returns `true` until the device is unregistered.
+    pub fn is_attached(&self) -> bool {
+        self.link.dev.lock().is_some()
+    }
+
+    /// Starts the engines of the QP `qpn`, the number the C side gave it, working on `ctx`.
+    ///
+    /// Fails with `EINVAL` if `qpn` is not a QP number of the data path, e.g. a special QP or one
+    /// above the `max_qpn` it was attached with, and with `EBUSY` if the QP is attached already.
+    pub fn add_qp(&self, qpn: u32, ctx: C) -> Result<PoolEntry<QpEngines<C>>> {
+        // The pool has one slot per QP number up to `max_qpn`, the ones above would share them.
+        if qpn > IndexRange::of(ResourceKind::Qp).min + self.qps.capacity() - 1 {
+            return Err(EINVAL);
+        }
+        self.qps.add_at(qpn, Tasks::try_new_traced(ctx)?)
+    }
+
+    /// Looks the engines of QP `qpn` up, e.g. for a packet received for it.
+    pub fn qp(&self, qpn: u32) -> Option<PoolRef<QpEngines<C>>> {
+        self.qps.lookup(qpn)
+    }
+
+    /// Hands a packet received for the device to the engines of its QP, like the C driver's
+    /// `rxe_rcv`, e.g. from the `udp_recv` of the C side.
+    ///
+    /// The QP is looked up by the destination QP number of the BTH, under RCU and without taking
+    /// a lock. Requests are queued for the responder and responses for the completer, which is
+    /// then scheduled; the ACKs and NAKs of the responses are recorded in the trace of the QP.
+    /// Fails with `EINVAL` if the packet is malformed and with `ENOENT` if no such QP is
+    /// attached, which drops the packet.
+    pub fn recv(&self, skb: SkBuff) -> Result {
+        let (qpn, kind, event) = {
+            let pkt = skb.roce_packet()?;
+            let kind = if pkt.info().mask.contains(OpcodeMask::REQ) {
+                TaskKind::Responder
+            } else {
+                TaskKind::Completer
+            };
+            let psn = pkt.bth().psn().value();
+            // Bits 6:5 of the syndrome are clear for ACKs, and set for RNR NAKs and NAKs.
+            let event = pkt.aeth().map(|aeth| match aeth.syndrome() {
+                syndrome if syndrome & 0x60 == 0 => QpEvent::Ack { psn },
+                syndrome => QpEvent::Nak { psn, syndrome },
+            });
+            (pkt.bth().dest_qp(), kind, event)
+        };
+        let qp = self.qp(qpn).ok_or(ENOENT)?;
+        if let Some(event) = event {
+            qp.record(event);
+        }
+        qp.context().enqueue(kind, skb)?;
+        qp.sched(kind);
+        Ok(())
+    }
+
+    /// Schedules the `kind` task of QP `qpn`, e.g. from the C `post_send`.
+    ///
+    /// Fails with `ENOENT` if no such QP is attached.
+    pub fn sched(&self, qpn: u32, kind: TaskKind) -> Result {
+        self.qp(qpn).ok_or(ENOENT)?.sched(kind);
+        Ok(())
+    }
+
+    /// Detaches a QP, once its engines finished, and returns them.
+    ///
+    /// Waits for packets still being processed by the QP. Must be called in process context.
+    pub fn remove_qp(&self, entry: PoolEntry<QpEngines<C>>) -> Result<QpEngines<C>> {
+        self.qps.remove(entry).map_err(|_| EINVAL)
+    }
+
+    /// Returns the number of attached QPs.
+    pub fn qp_count(&self) -> u32 {
+        self.qps.len()
+    }
+
+    /// Writes the trace of every attached QP that keeps one, as the `qp_traces` debugfs file
+    /// does, e.g. to log them once a QP errors out.
+    pub fn write_traces(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        write_traces(&self.qps, w)
+    }
+}
+
+impl<C: QpTasks> Drop for DataPath<C> {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants of `Link` the client is registered. ib_core calls
+        // `remove` for the device if it is still registered, and waits for the callbacks.
+        unsafe { bindings::ib_unregister_client(self.link.client.get()) };
+    }
+}
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Per-device source address filtering.
+//!
+//! A [`SourceFilter`] holds the optional allowlist of peer prefixes of one device, see
+//! [`crate::rdma::ip_filter`]. The packet path reads it for every received packet, while it only
+//! changes when an administrator writes to sysfs, so the list is an [`RcuConfig`]: readers never
+//! wait for a writer, even one interrupted by the softirq they run in.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::config::RcuConfig;
+use crate::error::{code::*, Error, Result};
+use crate::rdma::ip_filter::{FilterError, IpPrefix, PeerAddr, PrefixList};
+
+/// Allowlist of up to `N` peer prefixes of a device, with a counter of the packets it rejected.
+pub struct SourceFilter<const N: usize> {
+    list: RcuConfig<PrefixList<N>>,
+    dropped: AtomicU64,
+}
+
+impl<const N: usize> SourceFilter<N> {
+    /// Creates an empty filter, which accepts every peer.
+    pub fn try_new() -> Result<Self> {
+        Ok(Self {
+            list: RcuConfig::try_new(PrefixList::new())?,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Returns `true` if the allowlist is in force, i.e. holds at least one prefix.
+    pub fn is_enabled(&self) -> bool {
+        self.list.read(|list| !list.is_empty())
+    }
+
+    /// Returns `true` if packets from `addr` are accepted, counting the ones that are not.
+    ///
+    /// Never sleeps nor spins, so it can be called from the receive softirq.
+    pub fn permits(&self, addr: PeerAddr) -> bool {
+        let ok = self.list.read(|list| list.permits(addr));
+        if !ok {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        ok
+    }
+
+    /// Adds `prefix` to the allowlist, fails with `ENOSPC` if it is full.
+    ///
+    /// Waits for an RCU grace period: must be called in process context.
+    pub fn add(&self, prefix: IpPrefix) -> Result {
+        self.list
+            .update(|mut list| list.add(prefix).map(|_| list).map_err(filter_error))
+            .map(|_| ())
+    }
+
+    /// Removes `prefix` from the allowlist, fails with `ENOENT` if it was not present.
+    ///
+    /// Must be called in process context.
+    pub fn remove(&self, prefix: IpPrefix) -> Result {
+        self.list
+            .update(|mut list| {
+                if list.remove(prefix) {
+                    Ok(list)
+                } else {
+                    Err(ENOENT)
+                }
+            })
+            .map(|_| ())
+    }
+
+    /// Replaces the allowlist with the prefixes in `text`, see [`PrefixList::store`].
+    ///
+    /// Fails with `EINVAL` if a prefix does not parse and `ENOSPC` if they do not fit, leaving the
+    /// allowlist unchanged. Must be called in process context, e.g. from a sysfs `store` callback.
+    pub fn store(&self, text: &str) -> Result {
+        self.list
+            .update(|mut list| list.store(text).map(|_| list).map_err(filter_error))
+            .map(|_| ())
+    }
+
+    /// Writes the allowlist, one prefix per line, e.g. for a sysfs `show` callback.
+    pub fn show(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        self.list.get().show(w)
+    }
+
+    /// Returns the number of packets rejected so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+fn filter_error(e: FilterError) -> Error {
+    match e {
+        FilterError::Full => ENOSPC,
+        FilterError::Parse => EINVAL,
+    }
+}
@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Generic netlink `newlink` command of a soft-RoCE registration.
+//!
+//! ib_core's `newlink` callback only gets the name of the device to create and its net device, so
+//! `rdma link add` cannot pass overrides. Every registration therefore also registers the generic
+//! netlink family `rdma_` followed by its link type, e.g. `rdma_rxe`, whose [`CMD_NEWLINK`] command
+//! creates a device like `rdma link add` does, with the optional attributes of
+//! [`crate::rdma::link_params::attr`] turned into the [`LinkParams`] handed to
+//! [`RxeOperation::newlink`]. The command needs `CAP_NET_ADMIN`, and looks the net device up in
+//! the namespace of the requester.
+
+use core::marker::PhantomData;
+
+use super::net::{self, NetDevice};
+use super::{RxeOperation, RxeRdmaLinkTable};
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::rdma::link_params::{attr, LinkParams, MAX_NAME_LEN};
+use crate::str::CStr;
+use crate::{pr_err, pr_warn};
+
+/// Command creating a device, with the attributes of [`crate::rdma::link_params::attr`].
+pub const CMD_NEWLINK: u8 = 1;
+
+/// Longest `attr::CONFIG` list, NUL included.
+const MAX_CONFIG_LEN: u16 = 256;
+
+const NUM_ATTRS: usize = attr::MAX as usize + 1;
+
+/// The generic netlink family of a registration.
+///
+/// # Invariants
+///
+/// `family`, `ops` and `policy` do not move while `registered` is set, and the family points to
+/// them.
+pub(crate) struct LinkFamily<T: RxeOperation> {
+    family: bindings::genl_family,
+    ops: [bindings::genl_ops; 1],
+    policy: [bindings::nla_policy; NUM_ATTRS],
+    registered: bool,
+    _p: PhantomData<T>,
+}
+
+fn policy(ty: u32, len: u16) -> bindings::nla_policy {
+    bindings::nla_policy {
+        type_: ty as _,
+        len,
+        ..Default::default()
+    }
+}
+
+impl<T: RxeOperation> LinkFamily<T> {
+    /// Creates an unregistered family.
+    pub(crate) fn new() -> Self {
+        // INVARIANT: Not registered.
+        Self {
+            family: bindings::genl_family::default(),
+            ops: [bindings::genl_ops::default()],
+            policy: [bindings::nla_policy::default(); NUM_ATTRS],
+            registered: false,
+            _p: PhantomData,
+        }
+    }
+
+    /// Registers the family `rdma_<link_type>`.
+    ///
+    /// # Safety
+    ///
+    /// `self` must not move until it is dropped, and calls to `register` and `unregister` must be
+    /// serialised.
+    pub(crate) unsafe fn register(&mut self, link_type: &CStr) -> Result {
+        if self.registered {
+            return Err(EINVAL);
+        }
+        let name = link_type.as_bytes();
+        if b"rdma_".len() + name.len() >= bindings::GENL_NAMSIZ as usize {
+            return Err(ENAMETOOLONG);
+        }
+
+        let strings = bindings::NLA_NUL_STRING;
+        self.policy[attr::IBDEV_NAME as usize] = policy(strings, MAX_NAME_LEN as u16);
+        self.policy[attr::NDEV_NAME as usize] = policy(strings, bindings::IFNAMSIZ as u16 - 1);
+        self.policy[attr::UDP_PORT as usize] = policy(bindings::NLA_U16, 0);
+        for ty in [attr::MAX_QP, attr::MAX_QP_WR, attr::MAX_CQ, attr::MAX_MR] {
+            self.policy[ty as usize] = policy(bindings::NLA_U32, 0);
+        }
+        self.policy[attr::CONFIG as usize] = policy(strings, MAX_CONFIG_LEN - 1);
+
+        self.ops[0] = bindings::genl_ops {
+            cmd: CMD_NEWLINK,
+            flags: bindings::GENL_ADMIN_PERM as _,
+            doit: Some(Self::newlink_doit),
+            ..Default::default()
+        };
+
+        self.family = bindings::genl_family {
+            version: 1,
+            maxattr: attr::MAX as _,
+            policy: self.policy.as_ptr(),
+            ops: self.ops.as_ptr(),
+            n_ops: self.ops.len() as _,
+            ..Default::default()
+        };
+        self.family.set_netnsok(1);
+        for (dst, src) in self.family.name.iter_mut().zip(b"rdma_".iter().chain(name)) {
+            *dst = *src as _;
+        }
+
+        // SAFETY: The family and the tables it points to do not move until `unregister` by the
+        // safety requirements. Without a module reference, `Drop` of the registration unregisters
+        // the family before the module goes away, which waits for the commands being handled.
+        let ret = unsafe { bindings::genl_register_family(&mut self.family) };
+        if ret != 0 {
+            pr_err!("Failed to register the rdma_{} netlink family\n", link_type);
+            return Err(Error::from_kernel_errno(ret));
+        }
+        // INVARIANT: The family was just registered, pointing to our tables.
+        self.registered = true;
+        Ok(())
+    }
+
+    /// Unregisters the family; no command runs anymore once this returns.
+    pub(crate) fn unregister(&mut self) {
+        if !self.registered {
+            return;
+        }
+        // SAFETY: The family was registered by `register`.
+        if unsafe { bindings::genl_unregister_family(&self.family) } != 0 {
+            pr_warn!("Failed to unregister the link netlink family\n");
+        }
+        self.registered = false;
+    }
+
+    /// Returns the payload of the attribute of type `ty` of `info`, if present.
+    fn attr(info: &bindings::genl_info, ty: u16) -> Option<&[u8]> {
+        // SAFETY: The attributes of our requests are parsed up to `attr::MAX`, so `attrs` has
+        // `attr::MAX + 1` entries.
+        let nla = unsafe { *info.attrs.add(ty as usize) };
+        if nla.is_null() {
+            return None;
+        }
+        // SAFETY: The attribute was validated against the policy, so its payload holds `nla_len`
+        // bytes, which live as long as the request.
+        Some(unsafe {
+            core::slice::from_raw_parts(
+                bindings::nla_data(nla).cast::<u8>(),
+                bindings::nla_len(nla) as usize,
+            )
+        })
+    }
+
+    fn newlink(info: &bindings::genl_info) -> Result {
+        let ibdev_name = Self::attr(info, attr::IBDEV_NAME).ok_or(EINVAL)?;
+        let ndev_name = Self::attr(info, attr::NDEV_NAME).ok_or(EINVAL)?;
+        let mut params = LinkParams::DEFAULT;
+        for ty in attr::UDP_PORT..=attr::MAX {
+            if let Some(payload) = Self::attr(info, ty) {
+                params = params.with_attr(ty, payload).map_err(|_| EINVAL)?;
+            }
+        }
+        // SAFETY: The policy makes both names NUL-terminated strings within their payload.
+        let ibdev_name = unsafe { CStr::from_char_ptr(ibdev_name.as_ptr().cast()) };
+        // SAFETY: As above; the lookup takes a reference to the device.
+        let ndev = unsafe {
+            bindings::dev_get_by_name(bindings::genl_info_net(info), ndev_name.as_ptr().cast())
+        };
+        if ndev.is_null() {
+            return Err(net::SetupError::NoNetDevice.to_error());
+        }
+        // SAFETY: `ndev` is non-null, and we hold a reference to it until the `dev_put` below.
+        let ret =
+            RxeRdmaLinkTable::<T>::add(ibdev_name, unsafe { NetDevice::from_ptr(ndev) }, &params);
+        // SAFETY: Drops the reference taken by `dev_get_by_name`.
+        unsafe { bindings::dev_put(ndev) };
+        ret
+    }
+
+    unsafe extern "C" fn newlink_doit(
+        _skb: *mut bindings::sk_buff,
+        info: *mut bindings::genl_info,
+    ) -> core::ffi::c_int {
+        // SAFETY: genetlink hands `doit` a valid request of our family, with its attributes
+        // parsed.
+        match Self::newlink(unsafe { &*info }) {
+            Ok(()) => 0,
+            Err(e) => e.to_kernel_errno(),
+        }
+    }
+}
+
+impl<T: RxeOperation> Drop for LinkFamily<T> {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Invariant CRC of soft-RoCE packets.
+//!
+//! The functions take linear socket buffers with the network header set, and data starting at
+//! the UDP header, as for packets handed to [`super::RxeOperation::udp_recv`], or at the network
+//! header itself.
+
+use super::net::SkBuff;
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::rdma::hdr::ICRC_SIZE;
+use crate::rdma::icrc::{self, UDP_HDR_LEN};
+
+pub use crate::rdma::icrc::IcrcError;
+
+/// The kernel's `crc32_le()`.
+fn crc32(seed: u32, data: &[u8]) -> u32 {
+    // SAFETY: `data` is valid for reads of `data.len()` bytes.
+    unsafe { bindings::crc32_le(seed, data.as_ptr(), data.len()) }
+}
+
+/// Splits the linear data of `skb` into its IP and UDP headers and the rest of the packet.
+fn split(skb: &SkBuff) -> Option<(&[u8], &[u8])> {
+    if skb.headlen() != skb.len() {
+        return None;
+    }
+    let data = skb.network_data().ok()?;
+    let len = icrc::ip_hdr_len(data)? + UDP_HDR_LEN;
+    if data.len() < len {
+        return None;
+    }
+    Some(data.split_at(len))
+}
+
+/// Checks the ICRC at the end of a received packet.
+///
+/// Packets with paged data must be linearised first; they are reported as malformed otherwise.
+pub fn verify(skb: &SkBuff) -> core::result::Result<(), IcrcError> {
+    let (ip_udp, packet) = split(skb).ok_or(IcrcError::Malformed)?;
+    icrc::verify(crc32, ip_udp, packet)
+}
+
+/// Computes the ICRC of an outgoing packet and appends it.
+///
+/// The IP and UDP length fields are covered by the ICRC, so they must already count the
+/// [`ICRC_SIZE`] bytes this appends. Returns `EINVAL` if the headers are malformed or the packet
+/// has paged data, and `ENOSPC` if the buffer lacks tailroom.
+pub fn append(skb: &mut SkBuff) -> Result {
+    let (ip_udp, packet) = split(skb).ok_or(EINVAL)?;
+    let value = icrc::compute(crc32, ip_udp, packet).ok_or(EINVAL)?;
+    skb.put(ICRC_SIZE as u32)?
+        .copy_from_slice(&icrc::to_wire(value));
+    Ok(())
+}
+
+/// Computes the ICRC of an outgoing packet into its last [`ICRC_SIZE`] bytes.
+///
+/// This is for packets laid out with room for the ICRC, e.g. by [`crate::rdma::hdr::PacketMut`].
+/// Returns `EINVAL` if the headers are malformed or the packet has paged data.
+pub fn generate(skb: &mut SkBuff) -> Result {
+    let (ip_udp, packet) = split(skb).ok_or(EINVAL)?;
+    let body_len = packet.len().checked_sub(ICRC_SIZE).ok_or(EINVAL)?;
+    let value = icrc::compute(crc32, ip_udp, &packet[..body_len]).ok_or(EINVAL)?;
+    let data = skb.data_mut();
+    let tail = data.len() - ICRC_SIZE;
+    data[tail..].copy_from_slice(&icrc::to_wire(value));
+    Ok(())
+}
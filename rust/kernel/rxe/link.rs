@@ -0,0 +1,244 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Debouncing of the link events seen by the netdev notifier.
+//!
+//! The notifier of a registration records `NETDEV_UP`, `NETDEV_DOWN` and carrier changes in its
+//! [`FlapDebouncer`] instead of reporting them right away, and a delayed work item on `system_wq`
+//! reports the state each link settled in once its holdoff elapsed, see
+//! [`SocketConfig::with_flap_holdoff`]. Settled states go to [`RxeOperation::port_event`], then
+//! to ib_core and the consumers of the device bound to the link as `IB_EVENT_PORT_ACTIVE` or
+//! `IB_EVENT_PORT_ERR`.
+//!
+//! Links are keyed by their `struct net_device`, which stays valid while the link is tracked:
+//! `NETDEV_UNREGISTER` forgets it, and both the notifier and the work item run under RTNL.
+
+use core::cell::UnsafeCell;
+use core::marker::{PhantomData, PhantomPinned};
+use core::mem::MaybeUninit;
+use core::ptr;
+
+use super::net::NetDevice;
+use super::{jiffies, RxeOperation, SocketConfig};
+use crate::error::{Error, Result};
+use crate::ib::{device, IbDeviceOperations};
+use crate::rdma::flap::{FlapDebouncer, FlapStats, FlapVerdict, LinkEvent};
+use crate::sync::smutex::Mutex;
+use crate::{bindings, pr_err};
+
+/// Largest number of links debounced at once; the events of other links are not held back.
+pub const MAX_LINKS: usize = 64;
+
+struct State {
+    flaps: FlapDebouncer<MAX_LINKS>,
+    /// Whether the notifier is registered, and the work item may be queued.
+    started: bool,
+}
+
+/// The netdev notifier of a registration, with its debouncer and work item.
+///
+/// # Invariants
+///
+/// The delayed work item is initialised once `state.started` was first set, and only queued
+/// while it is set. `notifier` is registered while `state.started` is set.
+pub(crate) struct LinkFlaps<T: RxeOperation> {
+    notifier: UnsafeCell<bindings::notifier_block>,
+    state: Mutex<State>,
+    work: UnsafeCell<MaybeUninit<bindings::delayed_work>>,
+    _p: PhantomData<T>,
+    _pin: PhantomPinned,
+}
+
+// SAFETY: The debouncer is protected by its mutex; the notifier block and the work item are only
+// handed to the notifier and workqueue cores, which synchronise their own accesses.
+unsafe impl<T: RxeOperation> Sync for LinkFlaps<T> {}
+
+fn holdoff(config: &SocketConfig) -> u64 {
+    // SAFETY: FFI call without preconditions.
+    unsafe { bindings::msecs_to_jiffies(config.flap_holdoff()) as u64 }
+}
+
+impl<T: RxeOperation> LinkFlaps<T> {
+    /// Creates a debouncer with the holdoff of `config`; nothing is registered yet.
+    pub(crate) fn new(config: &SocketConfig) -> Self {
+        // INVARIANT: Not started.
+        Self {
+            notifier: UnsafeCell::new(bindings::notifier_block {
+                notifier_call: Some(Self::notify_callback),
+                next: ptr::null_mut(),
+                priority: 0,
+            }),
+            state: Mutex::new(State {
+                flaps: FlapDebouncer::new(holdoff(config)),
+                started: false,
+            }),
+            work: UnsafeCell::new(MaybeUninit::uninit()),
+            _p: PhantomData,
+            _pin: PhantomPinned,
+        }
+    }
+
+    fn work(&self) -> *mut bindings::delayed_work {
+        self.work.get().cast()
+    }
+
+    /// Registers the notifier, once the sockets are set up. Does nothing if it is registered.
+    ///
+    /// # Safety
+    ///
+    /// `self` must not move until it is dropped, and calls to `start` and `stop` must be
+    /// serialised.
+    pub(crate) unsafe fn start(&self) -> Result {
+        if self.state.lock().started {
+            return Ok(());
+        }
+        // SAFETY: The work item is idle: it is never queued while `started` is clear, and a
+        // running instance was waited for by `stop`.
+        unsafe { bindings::init_delayed_work(self.work(), Some(Self::work_fn)) };
+        // INVARIANT: The work item was just initialised. The notifier may report links right
+        // away, so it must find the work item ready.
+        self.state.lock().started = true;
+        // SAFETY: The block does not move by the safety requirements, and `stop` unregisters it.
+        let err = unsafe { bindings::register_netdevice_notifier(self.notifier.get()) };
+        if err != 0 {
+            pr_err!("Failed to register netdev notifier\n");
+            self.state.lock().started = false;
+            return Err(Error::from_kernel_errno(err));
+        }
+        Ok(())
+    }
+
+    /// Unregisters the notifier and drops the pending events.
+    ///
+    /// Must not be called under RTNL, which the work item and the unregistration take.
+    pub(crate) fn stop(&self) {
+        if !self.state.lock().started {
+            return;
+        }
+        // SAFETY: The notifier was registered by `start`.
+        unsafe { bindings::unregister_netdevice_notifier(self.notifier.get()) };
+        // INVARIANT: The work item is not queued anymore once `started` is clear, except by a
+        // running instance, which sees it clear.
+        let holdoff = {
+            let mut state = self.state.lock();
+            state.started = false;
+            state.flaps.holdoff()
+        };
+        // SAFETY: The work item was initialised by `start`, and nothing queues it anymore.
+        unsafe { bindings::cancel_delayed_work_sync(self.work()) };
+        self.state.lock().flaps = FlapDebouncer::new(holdoff);
+    }
+
+    /// Returns the counters of the debouncer.
+    pub(crate) fn stats(&self) -> FlapStats {
+        self.state.lock().flaps.stats()
+    }
+
+    /// Queues the work item in `delay` jiffies, if the notifier is registered.
+    fn arm(&self, state: &State, delay: u64) {
+        if !state.started {
+            return;
+        }
+        // SAFETY: By the type invariants the work item is initialised since `started` is set,
+        // and `stop` cancels it. A pending work item keeps its expiry.
+        unsafe {
+            bindings::queue_delayed_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                bindings::system_wq,
+                self.work(),
+                delay as _,
+            )
+        };
+    }
+
+    /// Reports the settled `event` of `ndev` to `T`, then to the consumers of its device.
+    ///
+    /// Called under RTNL.
+    fn report(ndev: &NetDevice, event: LinkEvent) {
+        T::port_event(ndev, event);
+        device::dispatch_netdev_event(ndev, T::Device::DRIVER_ID, event);
+    }
+
+    /// Records a notifier `event` of `ndev`, and reports it unless it is held back.
+    ///
+    /// Called under RTNL.
+    fn notify(&self, ndev: &NetDevice, event: core::ffi::c_ulong) {
+        let link = ndev.as_ptr() as u64;
+        let event = match event as u32 {
+            bindings::netdev_cmd_NETDEV_UP => LinkEvent::Up,
+            bindings::netdev_cmd_NETDEV_DOWN => LinkEvent::Down,
+            bindings::netdev_cmd_NETDEV_CHANGE if ndev.is_up() && ndev.has_carrier() => {
+                LinkEvent::Up
+            }
+            bindings::netdev_cmd_NETDEV_CHANGE => LinkEvent::Down,
+            bindings::netdev_cmd_NETDEV_UNREGISTER => {
+                self.state.lock().flaps.forget(link);
+                return;
+            }
+            _ => return,
+        };
+        let now = jiffies();
+        let verdict = {
+            let mut state = self.state.lock();
+            let verdict = state.flaps.event(link, event, now);
+            if let FlapVerdict::Deferred(deadline) = verdict {
+                self.arm(&state, deadline.wrapping_sub(now).max(1));
+            }
+            verdict
+        };
+        if let FlapVerdict::Propagate(event) = verdict {
+            Self::report(ndev, event);
+        }
+    }
+
+    unsafe extern "C" fn notify_callback(
+        notifier: *mut bindings::notifier_block,
+        event: core::ffi::c_ulong,
+        arg: *mut core::ffi::c_void,
+    ) -> core::ffi::c_int {
+        // SAFETY: The notifier block is the `notifier` field of a `LinkFlaps<T>`, which
+        // unregisters it before it goes away.
+        let this = unsafe { &*crate::container_of!(notifier, Self, notifier) };
+        // SAFETY: Netdev notifiers get a `struct netdev_notifier_info` naming a device that is
+        // valid for the duration of the call, which runs under RTNL.
+        let ndev =
+            unsafe { NetDevice::from_ptr(bindings::netdev_notifier_info_to_dev(arg.cast())) };
+        this.notify(ndev, event);
+        let _ = T::notify();
+        0
+    }
+
+    unsafe extern "C" fn work_fn(work: *mut bindings::work_struct) {
+        // SAFETY: The work item is the `work` field of a `LinkFlaps<T>`, which cancels it before
+        // it goes away.
+        let this =
+            unsafe { &*crate::container_of!(work.cast::<bindings::delayed_work>(), Self, work) };
+        let mut settled = [(0, LinkEvent::Up); MAX_LINKS];
+        let mut n = 0;
+        // SAFETY: FFI call without preconditions; work items may sleep.
+        unsafe { bindings::rtnl_lock() };
+        let now = jiffies();
+        {
+            let mut state = this.state.lock();
+            state.flaps.expire(now, |link, event| {
+                settled[n] = (link, event);
+                n += 1;
+            });
+            if let Some(deadline) = state.flaps.next_deadline(now) {
+                this.arm(&state, deadline.wrapping_sub(now).max(1));
+            }
+        }
+        for (link, event) in &settled[..n] {
+            // SAFETY: The link was tracked until now, so it was not unregistered yet, and it
+            // cannot be while we hold RTNL.
+            Self::report(unsafe { NetDevice::from_ptr(*link as *const _) }, *event);
+        }
+        // SAFETY: Paired with the `rtnl_lock` above.
+        unsafe { bindings::rtnl_unlock() };
+    }
+}
+
+impl<T: RxeOperation> Drop for LinkFlaps<T> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
@@ -0,0 +1,399 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Multicast groups of soft-RoCE devices.
+//!
+//! UD QPs attached to a multicast GID receive every packet sent to it. A [`McastTable`] keeps the
+//! groups of one device in a hash keyed by MGID, like the C driver's `rxe_mcast.c`: the receive
+//! path looks a group up under RCU and hands the packet to each attached QP, while attaching and
+//! detaching run in process context.
+//!
+//! The first attachment to a group joins it on the network device the table is bound to: its
+//! Ethernet address is added to the receive filter, and IGMP or MLD membership is announced so
+//! that snooping switches forward the group to us. The last detachment leaves it again, and so
+//! does dropping the table.
+
+use alloc::boxed::Box;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use super::net::NetDevice;
+use crate::bindings;
+use crate::error::{code::*, Error, Result};
+use crate::pr_warn;
+use crate::rdma::gid::Gid;
+use crate::sync::smutex::Mutex;
+
+/// Largest number of groups of a device, like `RXE_MAX_MCAST_GRP`.
+pub const MAX_MCAST_GRP: u32 = 8192;
+
+/// Largest number of QPs attached to one group, like `RXE_MAX_MCAST_QP_ATTACH`.
+pub const MAX_MCAST_QP_ATTACH: usize = 56;
+
+/// Largest number of attachments over all the groups of a device, like
+/// `RXE_MAX_TOT_MCAST_QP_ATTACH`.
+pub const MAX_TOTAL_MCAST_QP_ATTACH: u32 = 0x70000;
+
+const MCAST_BUCKETS: usize = 64;
+
+/// Marks a free member slot; QP 0 is never a UD QP, so it cannot be attached.
+const NO_QP: u32 = 0;
+
+fn to_result(ret: core::ffi::c_int) -> Result {
+    if ret < 0 {
+        return Err(Error::from_kernel_errno(ret));
+    }
+    Ok(())
+}
+
+fn bucket(mgid: &Gid) -> usize {
+    let hash = mgid.subnet_prefix() ^ mgid.interface_id();
+    ((hash ^ (hash >> 32)) as u32 as usize) % MCAST_BUCKETS
+}
+
+struct Group {
+    mgid: Gid,
+    next: AtomicPtr<Group>,
+    /// QP numbers of the members, [`NO_QP`] for free slots.
+    qpns: [AtomicU32; MAX_MCAST_QP_ATTACH],
+    /// Number of members; only accessed by writers.
+    count: AtomicU32,
+}
+
+/// The multicast groups of a soft-RoCE device.
+///
+/// # Invariants
+///
+/// `ndev` is valid, and a reference on it is held until the table is dropped. The lists of
+/// `buckets` are only changed, and the member slots of their groups only written, with `writer`
+/// held. Every group in a list has at least one member and is joined on `ndev`; it is only freed
+/// after it was unlinked and an RCU grace period elapsed.
+pub struct McastTable {
+    ndev: *mut bindings::net_device,
+    writer: Mutex<()>,
+    buckets: [AtomicPtr<Group>; MCAST_BUCKETS],
+    groups: AtomicU32,
+    attached: AtomicU32,
+}
+
+// SAFETY: Groups are shared with the lookups of any thread, and freed by the writers.
+unsafe impl Send for McastTable {}
+// SAFETY: Writers are serialised by `writer`, readers only load atomics under RCU.
+unsafe impl Sync for McastTable {}
+
+impl McastTable {
+    /// Creates an empty table, whose groups are joined on `ndev`, the interface of the device.
+    ///
+    /// The table holds a reference on `ndev` until it is dropped, like the device binding to it.
+    pub fn new(ndev: &NetDevice) -> Self {
+        // SAFETY: By the type invariants of `NetDevice` the device is valid; the reference is
+        // dropped with the table.
+        unsafe { bindings::dev_hold(ndev.as_ptr()) };
+        // INVARIANT: The reference was taken above, and the table is empty.
+        Self {
+            ndev: ndev.as_ptr(),
+            writer: Mutex::new(()),
+            buckets: core::array::from_fn(|_| AtomicPtr::new(ptr::null_mut())),
+            groups: AtomicU32::new(0),
+            attached: AtomicU32::new(0),
+        }
+    }
+
+    /// Returns the number of groups.
+    pub fn len(&self) -> u32 {
+        self.groups.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if no QP is attached to any group.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of attachments over all groups.
+    pub fn attached(&self) -> u32 {
+        self.attached.load(Ordering::Relaxed)
+    }
+
+    /// Calls `f` with the number of each QP attached to `mgid`, and returns how many there are.
+    ///
+    /// This is the lookup of the receive path: a packet whose destination GID is multicast is
+    /// handed to every QP `f` is called for, and dropped if none is. Runs under
+    /// `rcu_read_lock()`, so `f` must not sleep; a QP detached meanwhile may still be reported,
+    /// [`McastTable::detach`] waits for `f` to return.
+    pub fn for_each_member(&self, mgid: &Gid, mut f: impl FnMut(u32)) -> usize {
+        let mut found = 0;
+        // SAFETY: FFI call without preconditions.
+        unsafe { bindings::rcu_read_lock() };
+        let mut group = self.buckets[bucket(mgid)].load(Ordering::Acquire);
+        // SAFETY: By the type invariants a group is only freed after a grace period, which cannot
+        // elapse while we are in the read-side critical section.
+        while let Some(g) = unsafe { group.as_ref() } {
+            if g.mgid == *mgid {
+                for qpn in g.qpns.iter().map(|q| q.load(Ordering::Acquire)) {
+                    if qpn != NO_QP {
+                        f(qpn);
+                        found += 1;
+                    }
+                }
+                break;
+            }
+            group = g.next.load(Ordering::Acquire);
+        }
+        // SAFETY: Paired with the `rcu_read_lock` above.
+        unsafe { bindings::rcu_read_unlock() };
+        found
+    }
+
+    /// Returns the network device the groups are joined on.
+    fn ndev(&self) -> &NetDevice {
+        // SAFETY: By the type invariants the device is valid while the table holds a reference.
+        unsafe { NetDevice::from_ptr(self.ndev) }
+    }
+
+    /// Attaches the QP `qpn` to the group `mgid`, e.g. from
+    /// [`crate::ib::IbDeviceOperations::attach_mcast`].
+    ///
+    /// The group is joined on the network device if it is new. Attaching a QP twice is not an
+    /// error. Fails with `EINVAL` if `mgid` is not a multicast GID, and with `ENOMEM` once
+    /// [`MAX_MCAST_GRP`], [`MAX_MCAST_QP_ATTACH`] or [`MAX_TOTAL_MCAST_QP_ATTACH`] is reached.
+    /// Sleeps, and takes RTNL to join, so it must not be called under it.
+    pub fn attach(&self, mgid: &Gid, qpn: u32) -> Result {
+        if !mgid.is_multicast() || qpn == NO_QP {
+            return Err(EINVAL);
+        }
+        let _guard = self.writer.lock();
+        let group = match self.find(mgid) {
+            // SAFETY: Groups in the table are only freed by writers, and we hold `writer`.
+            Some(group) => unsafe { &*group },
+            None => return self.create(mgid, qpn),
+        };
+        if group.qpns.iter().any(|q| q.load(Ordering::Relaxed) == qpn) {
+            return Ok(());
+        }
+        if self.attached() >= MAX_TOTAL_MCAST_QP_ATTACH {
+            return Err(ENOMEM);
+        }
+        let slot = group
+            .qpns
+            .iter()
+            .find(|q| q.load(Ordering::Relaxed) == NO_QP)
+            .ok_or(ENOMEM)?;
+        slot.store(qpn, Ordering::Release);
+        group.count.fetch_add(1, Ordering::Relaxed);
+        self.attached.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Creates the group `mgid` with `qpn` as its first member.
+    fn create(&self, mgid: &Gid, qpn: u32) -> Result {
+        if self.len() >= MAX_MCAST_GRP || self.attached() >= MAX_TOTAL_MCAST_QP_ATTACH {
+            return Err(ENOMEM);
+        }
+        let head = &self.buckets[bucket(mgid)];
+        let group = Box::try_new(Group {
+            mgid: *mgid,
+            next: AtomicPtr::new(head.load(Ordering::Relaxed)),
+            qpns: core::array::from_fn(|_| AtomicU32::new(NO_QP)),
+            count: AtomicU32::new(1),
+        })?;
+        group.qpns[0].store(qpn, Ordering::Relaxed);
+        join(self.ndev(), mgid)?;
+        // INVARIANT: The group has a member and was joined; release publishes it.
+        head.store(Box::into_raw(group), Ordering::Release);
+        self.groups.fetch_add(1, Ordering::Relaxed);
+        self.attached.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Detaches the QP `qpn` from the group `mgid`, e.g. from
+    /// [`crate::ib::IbDeviceOperations::detach_mcast`].
+    ///
+    /// The group is left on the network device once its last QP is detached. Waits for the
+    /// lookups that may still report `qpn`, so the QP can be destroyed once this returns. Fails
+    /// with `EINVAL` if the QP is not attached to the group. Sleeps, and takes RTNL to leave.
+    pub fn detach(&self, mgid: &Gid, qpn: u32) -> Result {
+        if qpn == NO_QP {
+            return Err(EINVAL);
+        }
+        let _guard = self.writer.lock();
+        let group = self.find(mgid).ok_or(EINVAL)?;
+        // SAFETY: Groups in the table are only freed by writers, and we hold `writer`.
+        let g = unsafe { &*group };
+        let slot = g
+            .qpns
+            .iter()
+            .find(|q| q.load(Ordering::Relaxed) == qpn)
+            .ok_or(EINVAL)?;
+        self.attached.fetch_sub(1, Ordering::Relaxed);
+        if g.count.fetch_sub(1, Ordering::Relaxed) == 1 {
+            // SAFETY: `group` is in the table and we hold `writer`.
+            unsafe { self.remove(group) };
+        } else {
+            slot.store(NO_QP, Ordering::Release);
+            // Lookups that loaded the slot before it was cleared may still hand packets to the
+            // QP.
+            // SAFETY: FFI call without preconditions; writers run in process context.
+            unsafe { bindings::synchronize_rcu() };
+        }
+        Ok(())
+    }
+
+    /// Leaves every group and empties the table, e.g. when the device goes away.
+    ///
+    /// Sleeps, and takes RTNL to leave.
+    pub fn clear(&self) {
+        let _guard = self.writer.lock();
+        for head in self.buckets.iter() {
+            loop {
+                let group = head.load(Ordering::Relaxed);
+                if group.is_null() {
+                    break;
+                }
+                // SAFETY: The group is alive while it is in the table.
+                let count = unsafe { (*group).count.load(Ordering::Relaxed) };
+                self.attached.fetch_sub(count, Ordering::Relaxed);
+                // SAFETY: `group` is in the table and we hold `writer`.
+                unsafe { self.remove(group) };
+            }
+        }
+    }
+
+    /// Unlinks `group`, waits for the lookups that may still see it, leaves it and frees it.
+    ///
+    /// # Safety
+    ///
+    /// `group` must be in the table, and `writer` held.
+    unsafe fn remove(&self, group: *mut Group) {
+        // SAFETY: The group is alive while it is in the table.
+        let (mgid, next) = unsafe { ((*group).mgid, (*group).next.load(Ordering::Relaxed)) };
+        let mut link = &self.buckets[bucket(&mgid)];
+        while link.load(Ordering::Relaxed) != group {
+            // SAFETY: The groups of the list are alive, and the safety requirements guarantee
+            // that `group` is one of them.
+            link = unsafe { &(*link.load(Ordering::Relaxed)).next };
+        }
+        link.store(next, Ordering::Release);
+        self.groups.fetch_sub(1, Ordering::Relaxed);
+        // SAFETY: FFI call without preconditions; writers run in process context.
+        unsafe { bindings::synchronize_rcu() };
+        leave(self.ndev(), &mgid);
+        // SAFETY: The group is unlinked and out of sight of RCU readers.
+        drop(unsafe { Box::from_raw(group) });
+    }
+
+    /// Returns the group `mgid`; only meaningful with `writer` held.
+    fn find(&self, mgid: &Gid) -> Option<*mut Group> {
+        let mut group = self.buckets[bucket(mgid)].load(Ordering::Acquire);
+        // SAFETY: By the type invariants groups in the table are alive; our callers hold `writer`,
+        // so none is freed meanwhile.
+        while let Some(g) = unsafe { group.as_ref() } {
+            if g.mgid == *mgid {
+                return Some(group);
+            }
+            group = g.next.load(Ordering::Acquire);
+        }
+        None
+    }
+}
+
+impl Drop for McastTable {
+    fn drop(&mut self) {
+        if !self.is_empty() {
+            pr_warn!("Leaving {} multicast groups still joined\n", self.len());
+            self.clear();
+        }
+        // SAFETY: The reference was taken by `new`, and the groups joined with it are left.
+        unsafe { bindings::dev_put(self.ndev) };
+    }
+}
+
+/// Joins the group `mgid` on `ndev`: adds its Ethernet address to the receive filter and
+/// announces the membership with IGMP or MLD.
+fn join(ndev: &NetDevice, mgid: &Gid) -> Result {
+    let mac = mgid.multicast_mac().ok_or(EINVAL)?;
+    // SAFETY: The device is valid and `mac` holds an Ethernet address.
+    to_result(unsafe { bindings::dev_mc_add(ndev.as_ptr(), mac.as_ptr()) })?;
+    // SAFETY: FFI call without preconditions; our callers may sleep.
+    unsafe { bindings::rtnl_lock() };
+    let ret = join_ip(ndev, mgid);
+    // SAFETY: Paired with the `rtnl_lock` above.
+    unsafe { bindings::rtnl_unlock() };
+    if ret.is_err() {
+        // SAFETY: The address was added above.
+        unsafe { bindings::dev_mc_del(ndev.as_ptr(), mac.as_ptr()) };
+    }
+    ret
+}
+
+/// Undoes [`join`].
+fn leave(ndev: &NetDevice, mgid: &Gid) {
+    // SAFETY: FFI call without preconditions; our callers may sleep.
+    unsafe { bindings::rtnl_lock() };
+    leave_ip(ndev, mgid);
+    // SAFETY: Paired with the `rtnl_lock` above.
+    unsafe { bindings::rtnl_unlock() };
+    if let Some(mac) = mgid.multicast_mac() {
+        // SAFETY: The address was added by `join`.
+        unsafe { bindings::dev_mc_del(ndev.as_ptr(), mac.as_ptr()) };
+    }
+}
+
+/// Announces the membership of `ndev` in the IP group of `mgid`. Called under RTNL.
+fn join_ip(ndev: &NetDevice, mgid: &Gid) -> Result {
+    let raw = mgid.raw();
+    if mgid.is_v4_mapped() {
+        // SAFETY: The device is valid; the reference taken is dropped below.
+        let in_dev = unsafe { bindings::in_dev_get(ndev.as_ptr()) };
+        if in_dev.is_null() {
+            return Err(ENODEV);
+        }
+        let group = u32::from_ne_bytes([raw[12], raw[13], raw[14], raw[15]]);
+        // SAFETY: `in_dev` is valid and RTNL is held.
+        let ret = unsafe { bindings::ip_mc_inc_group(in_dev, group) };
+        // SAFETY: Paired with `in_dev_get` above.
+        unsafe { bindings::in_dev_put(in_dev) };
+        return to_result(ret);
+    }
+    #[cfg(CONFIG_IPV6)]
+    {
+        // SAFETY: `in6_addr` is a plain C structure for which all zeroes is valid.
+        let mut group: bindings::in6_addr = unsafe { core::mem::zeroed() };
+        group.in6_u.u6_addr8 = raw;
+        // SAFETY: The device is valid.
+        return to_result(unsafe { bindings::ipv6_dev_mc_inc(ndev.as_ptr(), &group) });
+    }
+    #[cfg(not(CONFIG_IPV6))]
+    {
+        // Without IPv6 there is no MLD; the receive filter still lets the group in.
+        let _ = ndev;
+        Ok(())
+    }
+}
+
+/// Undoes [`join_ip`]. Called under RTNL.
+fn leave_ip(ndev: &NetDevice, mgid: &Gid) {
+    let raw = mgid.raw();
+    if mgid.is_v4_mapped() {
+        // SAFETY: The device is valid; the reference taken is dropped below.
+        let in_dev = unsafe { bindings::in_dev_get(ndev.as_ptr()) };
+        if in_dev.is_null() {
+            return;
+        }
+        let group = u32::from_ne_bytes([raw[12], raw[13], raw[14], raw[15]]);
+        // SAFETY: `in_dev` is valid, RTNL is held and the group was joined by `join_ip`.
+        unsafe {
+            bindings::ip_mc_dec_group(in_dev, group);
+            bindings::in_dev_put(in_dev);
+        }
+        return;
+    }
+    #[cfg(CONFIG_IPV6)]
+    {
+        // SAFETY: `in6_addr` is a plain C structure for which all zeroes is valid.
+        let mut group: bindings::in6_addr = unsafe { core::mem::zeroed() };
+        group.in6_u.u6_addr8 = raw;
+        // SAFETY: The device is valid and the group was joined by `join_ip`.
+        unsafe { bindings::ipv6_dev_mc_dec(ndev.as_ptr(), &group) };
+    }
+    #[cfg(not(CONFIG_IPV6))]
+    let _ = ndev;
+}
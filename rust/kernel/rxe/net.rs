@@ -0,0 +1,953 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Network device glue for the soft-RoCE transport.
+
+use alloc::boxed::Box;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::{mem, slice};
+
+use super::counters::{Counter, Counters};
+use crate::bindings;
+use crate::error::{code::*, from_kernel_err_ptr, Error, Result};
+use crate::ib::compat::try_pin;
+use crate::rdma::fault::{FaultInjector, TxFault};
+use crate::rdma::hdr::Packet;
+use crate::rdma::icrc::UDP_HDR_LEN;
+use crate::rdma::ip_filter::PeerAddr;
+use crate::rdma::link_params::LinkParams;
+use crate::sync::SpinLock;
+
+pub use crate::ib::netdev::{init_ns, Namespace, NetDevice};
+
+/// An owned reference to a kernel `struct sk_buff`.
+///
+/// The buffer is released with `consume_skb` when the [`SkBuff`] is dropped.
+///
+/// # Invariants
+///
+/// `ptr` is a valid `struct sk_buff` that this instance owns one reference to.
+pub struct SkBuff {
+    ptr: *mut bindings::sk_buff,
+}
+
+impl SkBuff {
+    /// Takes ownership of a raw `struct sk_buff`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a valid socket buffer and the caller must transfer its reference to the
+    /// returned [`SkBuff`].
+    pub(crate) unsafe fn from_raw(ptr: *mut bindings::sk_buff) -> Self {
+        // INVARIANT: The safety requirements guarantee ownership of a valid buffer.
+        Self { ptr }
+    }
+
+    /// Allocates a buffer for an outgoing packet of `len` bytes on `ndev`, BTH to ICRC, with
+    /// headroom for the IP, UDP and link-layer headers that [`transmit`] pushes.
+    ///
+    /// The packet data is zeroed, ready to be filled through [`SkBuff::data_mut`].
+    pub fn alloc_tx(ndev: &NetDevice, len: u32) -> Result<Self> {
+        // SAFETY: By the type invariants of `NetDevice` the device is valid.
+        let headroom = unsafe { bindings::ll_reserved_space(ndev.as_ptr()) } + TX_HDR_ROOM;
+        // SAFETY: `alloc_skb` has no preconditions; this may run in atomic context.
+        let ptr = unsafe { bindings::alloc_skb(headroom + len, bindings::BINDINGS_GFP_ATOMIC) };
+        if ptr.is_null() {
+            return Err(ENOMEM);
+        }
+        // INVARIANT: `alloc_skb` returned a new buffer that we own.
+        let mut skb = Self { ptr };
+        // SAFETY: The buffer is empty and has room for `headroom + len` bytes.
+        unsafe { bindings::skb_reserve(ptr, headroom as _) };
+        skb.put(len)?;
+        Ok(skb)
+    }
+
+    /// Gives up ownership of the buffer and returns the raw pointer.
+    pub(crate) fn into_raw(self) -> *mut bindings::sk_buff {
+        let ptr = self.ptr;
+        core::mem::forget(self);
+        ptr
+    }
+
+    /// Returns the raw `struct sk_buff` pointer.
+    pub(crate) fn as_ptr(&self) -> *mut bindings::sk_buff {
+        self.ptr
+    }
+
+    /// Returns the source address of the IPv4 or IPv6 header of the packet.
+    ///
+    /// Returns `None` for other protocols.
+    pub fn source_addr(&self) -> Option<PeerAddr> {
+        self.ip_addr(true)
+    }
+
+    /// Returns the destination address of the IPv4 or IPv6 header of the packet, e.g. to tell
+    /// multicast packets apart.
+    ///
+    /// Returns `None` for other protocols.
+    pub fn dest_addr(&self) -> Option<PeerAddr> {
+        self.ip_addr(false)
+    }
+
+    fn ip_addr(&self, source: bool) -> Option<PeerAddr> {
+        // SAFETY: By the type invariants `ptr` is valid. Packets reaching the UDP tunnel have
+        // their network header set and pulled into the linear area.
+        unsafe {
+            let protocol = u16::from_be((*self.ptr).protocol);
+            if protocol == bindings::ETH_P_IP as u16 {
+                let hdr = bindings::ip_hdr(self.ptr);
+                let addr = if source { (*hdr).saddr } else { (*hdr).daddr };
+                Some(PeerAddr::from_v4(addr.to_ne_bytes()))
+            } else if protocol == bindings::ETH_P_IPV6 as u16 {
+                let hdr = bindings::ipv6_hdr(self.ptr);
+                let addr = if source { (*hdr).saddr } else { (*hdr).daddr };
+                Some(PeerAddr::from_v6(addr.in6_u.u6_addr8))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Returns the index of the interface the packet arrived on, 0 for outgoing packets.
+    pub fn ifindex(&self) -> i32 {
+        // SAFETY: By the type invariants `ptr` is valid.
+        unsafe { (*self.ptr).skb_iif }
+    }
+
+    /// Returns the total length of the packet data, including paged fragments.
+    pub fn len(&self) -> u32 {
+        // SAFETY: By the type invariants `ptr` is valid.
+        unsafe { (*self.ptr).len }
+    }
+
+    /// Returns `true` if the buffer holds no data.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the length of the linear part of the packet data.
+    pub fn headlen(&self) -> u32 {
+        // SAFETY: By the type invariants `ptr` is valid.
+        unsafe { (*self.ptr).len - (*self.ptr).data_len }
+    }
+
+    /// Returns the linear part of the packet data.
+    ///
+    /// Call [`SkBuff::linearize`] first to be able to see the whole packet.
+    pub fn data(&self) -> &[u8] {
+        // SAFETY: By the type invariants `ptr` is valid, and `data` points to `headlen()`
+        // initialised bytes owned by the buffer.
+        unsafe { slice::from_raw_parts((*self.ptr).data, self.headlen() as usize) }
+    }
+
+    /// Decodes the RoCEv2 transport headers of a packet handed to
+    /// [`super::RxeOperation::udp_recv`], whose data starts at the UDP header.
+    ///
+    /// Returns `EINVAL` if the packet is malformed or not entirely in the linear area.
+    pub fn roce_packet(&self) -> Result<Packet<'_>> {
+        if self.headlen() != self.len() {
+            return Err(EINVAL);
+        }
+        let udp = self
+            .data()
+            .get(core::mem::size_of::<bindings::udphdr>()..)
+            .ok_or(EINVAL)?;
+        Packet::parse(udp).map_err(|_| EINVAL)
+    }
+
+    /// Returns the linear data from the network header on.
+    ///
+    /// For packets handed to [`super::RxeOperation::udp_recv`] this is the IP header followed
+    /// by [`SkBuff::data`]. Returns `EINVAL` if the network header is not in front of the data.
+    pub fn network_data(&self) -> Result<&[u8]> {
+        // SAFETY: By the type invariants `ptr` is valid.
+        let (net, data) = unsafe { (bindings::skb_network_header(self.ptr), (*self.ptr).data) };
+        if net.is_null() || net > data {
+            return Err(EINVAL);
+        }
+        // SAFETY: Both pointers are in the linear area of the buffer, `net` first.
+        let offset = unsafe { data.offset_from(net) } as usize;
+        // SAFETY: The bytes from the network header to the end of the linear area are initialised
+        // and owned by the buffer.
+        Ok(unsafe { slice::from_raw_parts(net, offset + self.headlen() as usize) })
+    }
+
+    /// Extends the packet by `len` zeroed bytes and returns them.
+    ///
+    /// Returns `EINVAL` if the packet has paged data and `ENOSPC` if the buffer lacks tailroom.
+    pub fn put(&mut self, len: u32) -> Result<&mut [u8]> {
+        // SAFETY: By the type invariants `ptr` is valid.
+        if unsafe { (*self.ptr).data_len } != 0 {
+            return Err(EINVAL);
+        }
+        // SAFETY: By the type invariants `ptr` is valid.
+        if unsafe { bindings::skb_tailroom(self.ptr) } < len as core::ffi::c_int {
+            return Err(ENOSPC);
+        }
+        // SAFETY: The buffer is linear, owned by us and has `len` bytes of tailroom, which
+        // `skb_put_zero` initialises.
+        unsafe {
+            let tail = bindings::skb_put_zero(self.ptr, len) as *mut u8;
+            Ok(slice::from_raw_parts_mut(tail, len as usize))
+        }
+    }
+
+    /// Returns the linear part of the packet data for writing.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        // SAFETY: By the type invariants `ptr` is valid, `data` points to `headlen()` initialised
+        // bytes owned by the buffer, and `&mut self` makes this the only reference to them.
+        unsafe { slice::from_raw_parts_mut((*self.ptr).data, self.headlen() as usize) }
+    }
+
+    /// Prepends `len` bytes to the packet and returns them, for the caller to fill in.
+    fn push(&mut self, len: u32) -> Result<&mut [u8]> {
+        // SAFETY: By the type invariants `ptr` is valid, and `data` never precedes `head`.
+        let headroom = unsafe { (*self.ptr).data.offset_from((*self.ptr).head) } as u32;
+        if headroom < len {
+            return Err(ENOSPC);
+        }
+        // SAFETY: The buffer is owned by us and has `len` bytes of headroom.
+        unsafe {
+            let data = bindings::skb_push(self.ptr, len);
+            Ok(slice::from_raw_parts_mut(data, len as usize))
+        }
+    }
+
+    /// Moves all paged data into the linear area.
+    pub fn linearize(&mut self) -> Result {
+        // SAFETY: By the type invariants `ptr` is valid.
+        let data_len = unsafe { (*self.ptr).data_len };
+        if data_len == 0 {
+            return Ok(());
+        }
+        // SAFETY: By the type invariants `ptr` is valid and owned by us.
+        let data = unsafe { bindings::__pskb_pull_tail(self.ptr, data_len as _) };
+        if data.is_null() {
+            return Err(ENOMEM);
+        }
+        Ok(())
+    }
+
+    /// Removes `len` bytes from the start of the packet and returns the remaining linear data.
+    ///
+    /// Paged data is pulled into the linear area if needed.
+    pub fn pull(&mut self, len: u32) -> Result<&[u8]> {
+        if len > self.len() {
+            return Err(EINVAL);
+        }
+        let headlen = self.headlen();
+        if len > headlen {
+            // SAFETY: By the type invariants `ptr` is valid and owned by us.
+            let data = unsafe { bindings::__pskb_pull_tail(self.ptr, (len - headlen) as _) };
+            if data.is_null() {
+                return Err(ENOMEM);
+            }
+        }
+        // SAFETY: `len` bytes are now available in the linear area, so `skb_pull` cannot fail.
+        unsafe { bindings::skb_pull(self.ptr, len) };
+        Ok(self.data())
+    }
+
+    /// Cuts the packet down to `len` bytes, doing nothing if it is already shorter.
+    pub fn trim(&mut self, len: u32) -> Result {
+        if len >= self.len() {
+            return Ok(());
+        }
+        // SAFETY: By the type invariants `ptr` is valid and owned by us.
+        let err = unsafe {
+            if (*self.ptr).data_len != 0 {
+                bindings::___pskb_trim(self.ptr, len)
+            } else {
+                bindings::skb_trim(self.ptr, len);
+                0
+            }
+        };
+        if err != 0 {
+            return Err(Error::from_kernel_errno(err));
+        }
+        Ok(())
+    }
+
+    /// Releases the buffer of a packet that was dropped rather than processed, with `kfree_skb`.
+    pub(crate) fn drop_packet(self) {
+        // SAFETY: By the type invariants we own a reference to a valid buffer, which is given up
+        // here.
+        unsafe { bindings::kfree_skb(self.into_raw()) };
+    }
+
+    /// Returns the network device the packet arrived on.
+    pub fn dev(&self) -> Option<&NetDevice> {
+        // SAFETY: By the type invariants `ptr` is valid. A received packet holds its device,
+        // which stays alive while the packet is processed.
+        let ndev = unsafe {
+            (*self.ptr)
+                .__bindgen_anon_1
+                .__bindgen_anon_1
+                .__bindgen_anon_1
+                .dev
+        };
+        // SAFETY: As above.
+        (!ndev.is_null()).then(|| unsafe { NetDevice::from_ptr(ndev) })
+    }
+
+    /// Returns a clone sharing the packet data, e.g. to keep the packet around after the
+    /// receive callback returned.
+    pub fn try_clone(&self) -> Result<Self> {
+        // SAFETY: By the type invariants `ptr` is valid.
+        let ptr = unsafe { bindings::skb_clone(self.ptr, bindings::BINDINGS_GFP_ATOMIC) };
+        if ptr.is_null() {
+            return Err(ENOMEM);
+        }
+        // INVARIANT: `skb_clone` returned a new buffer that we own.
+        Ok(Self { ptr })
+    }
+}
+
+impl Drop for SkBuff {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants we own a reference to a valid buffer.
+        unsafe { bindings::consume_skb(self.ptr) };
+    }
+}
+
+// SAFETY: A `struct sk_buff` is not tied to the thread that allocated it.
+unsafe impl Send for SkBuff {}
+
+/// What happened to a packet handed to [`super::RxeOperation::udp_recv`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UdpRecvVerdict {
+    /// The packet was handled by the driver and its buffer can be released.
+    Consumed,
+    /// The packet is not for the driver and is handed back to the UDP stack.
+    Refused,
+    /// The packet was rejected, e.g. by a [`super::SourceFilter`]; its buffer is freed with
+    /// `kfree_skb`, so drop monitors see it.
+    Dropped,
+}
+
+/// Smallest path MTU of a RoCE port, `IB_MTU_256`.
+const MIN_IB_MTU: u32 = 256;
+
+/// Largest RoCEv2 encapsulation: IPv6, UDP, BTH, the largest extended headers and the ICRC.
+const MAX_ROCE_OVERHEAD: u32 = 40 + 8 + 12 + 28 + 4;
+
+/// Prerequisites of soft-RoCE that are not met, each reported with its own error code.
+///
+/// Checksum offloads are not among them: the UDP checksum of RoCEv2 packets is left at 0 and the
+/// ICRC is computed in software, so any Ethernet device will do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupError {
+    /// The kernel has no UDP tunnel support, `CONFIG_NET_UDP_TUNNEL`: `EPROTONOSUPPORT`.
+    NoUdpTunnel,
+    /// The network device does not exist or was detached: `ENODEV`.
+    NoNetDevice,
+    /// The network device is administratively down, `ip link set <dev> up` first: `ENETDOWN`.
+    LinkDown,
+    /// The network device has no Ethernet address to derive the GIDs from: `EPFNOSUPPORT`.
+    NotEthernet,
+    /// The MTU of the network device cannot carry the smallest RoCE packet: `EMSGSIZE`.
+    MtuTooSmall,
+}
+
+impl SetupError {
+    /// Returns the error code reported for this problem.
+    pub fn to_error(self) -> Error {
+        let errno = match self {
+            Self::NoUdpTunnel => bindings::EPROTONOSUPPORT,
+            Self::NoNetDevice => bindings::ENODEV,
+            Self::LinkDown => bindings::ENETDOWN,
+            Self::NotEthernet => bindings::EPFNOSUPPORT,
+            Self::MtuTooSmall => bindings::EMSGSIZE,
+        };
+        Error::from_kernel_errno(-(errno as i32))
+    }
+
+    /// Returns what to fix, for the kernel log.
+    pub const fn describe(self) -> &'static str {
+        match self {
+            Self::NoUdpTunnel => "kernel built without CONFIG_NET_UDP_TUNNEL",
+            Self::NoNetDevice => "network device missing or detached",
+            Self::LinkDown => "network device is down",
+            Self::NotEthernet => "network device has no Ethernet address",
+            Self::MtuTooSmall => "network device MTU below 348 bytes",
+        }
+    }
+}
+
+impl From<SetupError> for Error {
+    fn from(e: SetupError) -> Self {
+        e.to_error()
+    }
+}
+
+/// Checks what soft-RoCE needs from the kernel before it sets up its sockets.
+pub fn check_kernel() -> core::result::Result<(), SetupError> {
+    if cfg!(any(CONFIG_NET_UDP_TUNNEL, CONFIG_NET_UDP_TUNNEL = "m")) {
+        Ok(())
+    } else {
+        Err(SetupError::NoUdpTunnel)
+    }
+}
+
+/// Checks that `ndev` can carry a soft-RoCE device.
+pub fn check_link(ndev: &NetDevice) -> core::result::Result<(), SetupError> {
+    if !ndev.is_present() {
+        return Err(SetupError::NoNetDevice);
+    }
+    if !ndev.is_up() {
+        return Err(SetupError::LinkDown);
+    }
+    if ndev.mac().is_none() {
+        return Err(SetupError::NotEthernet);
+    }
+    if ndev.mtu() < MIN_IB_MTU + MAX_ROCE_OVERHEAD {
+        return Err(SetupError::MtuTooSmall);
+    }
+    Ok(())
+}
+
+/// Room for the largest IP header and the UDP header of outgoing packets.
+const TX_HDR_ROOM: u32 =
+    (mem::size_of::<bindings::ipv6hdr>() + mem::size_of::<bindings::udphdr>()) as u32;
+
+const IPV4_HDR_LEN: usize = 20;
+const IPV6_HDR_LEN: usize = 40;
+
+/// First UDP source port of RoCEv2 flows; the low 14 bits spread QPs over ECMP paths.
+pub const ROCE_V2_SPORT_BASE: u16 = 0xc000;
+
+/// A route to a RoCEv2 peer.
+///
+/// # Invariants
+///
+/// `dst` is a valid `struct dst_entry` that this instance owns one reference to.
+pub struct Route {
+    dst: NonNull<bindings::dst_entry>,
+    saddr: PeerAddr,
+    daddr: PeerAddr,
+}
+
+impl Route {
+    /// Resolves the route from `saddr` on `ndev` to `daddr`, in the namespace of `ndev`.
+    ///
+    /// Both addresses must be of the same family. Returns `EAFNOSUPPORT` for IPv6 peers on
+    /// kernels without IPv6, and the routing error if `daddr` is unreachable.
+    pub fn resolve(ndev: &NetDevice, saddr: PeerAddr, daddr: PeerAddr) -> Result<Self> {
+        if saddr.is_v4() != daddr.is_v4() {
+            return Err(EINVAL);
+        }
+        let dst = if daddr.is_v4() {
+            Self::resolve4(ndev, saddr, daddr)?
+        } else {
+            Self::resolve6(ndev, saddr, daddr)?
+        };
+        // INVARIANT: Both lookups hand over a reference to a valid entry.
+        Ok(Self { dst, saddr, daddr })
+    }
+
+    fn resolve4(
+        ndev: &NetDevice,
+        saddr: PeerAddr,
+        daddr: PeerAddr,
+    ) -> Result<NonNull<bindings::dst_entry>> {
+        // SAFETY: `flowi4` is a plain C structure for which all zeroes is the empty key.
+        let mut fl: bindings::flowi4 = unsafe { mem::zeroed() };
+        fl.__fl_common.flowic_oif = ndev.ifindex();
+        fl.__fl_common.flowic_proto = bindings::IPPROTO_UDP as u8;
+        fl.saddr = u32::from_ne_bytes(v4_octets(saddr));
+        fl.daddr = u32::from_ne_bytes(v4_octets(daddr));
+        // SAFETY: The namespace is valid while `ndev` is, and `fl` is a valid key.
+        let rt = unsafe {
+            bindings::ip_route_output_flow(ndev.namespace().as_ptr(), &mut fl, ptr::null())
+        };
+        let rt = from_kernel_err_ptr(rt)?;
+        // SAFETY: `rt` is a valid route we own a reference to, which covers its `dst`.
+        Ok(unsafe { NonNull::new_unchecked(ptr::addr_of_mut!((*rt).dst)) })
+    }
+
+    fn resolve6(
+        ndev: &NetDevice,
+        saddr: PeerAddr,
+        daddr: PeerAddr,
+    ) -> Result<NonNull<bindings::dst_entry>> {
+        #[cfg(CONFIG_IPV6)]
+        {
+            // SAFETY: `flowi6` is a plain C structure for which all zeroes is the empty key.
+            let mut fl6: bindings::flowi6 = unsafe { mem::zeroed() };
+            fl6.__fl_common.flowic_oif = ndev.ifindex();
+            fl6.__fl_common.flowic_proto = bindings::IPPROTO_UDP as u8;
+            fl6.saddr.in6_u.u6_addr8 = saddr.octets();
+            fl6.daddr.in6_u.u6_addr8 = daddr.octets();
+            // SAFETY: The namespace is valid while `ndev` is, and `fl6` is a valid key.
+            let dst = unsafe {
+                bindings::ipv6_dst_lookup_flow(ndev.namespace().as_ptr(), ptr::null_mut(), &mut fl6)
+            };
+            let dst = from_kernel_err_ptr(dst)?;
+            // SAFETY: The lookup returned a valid entry we own a reference to.
+            let err = unsafe { (*dst).error };
+            if err != 0 {
+                // SAFETY: We own the reference and drop it here.
+                unsafe { bindings::dst_release(dst) };
+                return Err(Error::from_kernel_errno(err));
+            }
+            // SAFETY: `from_kernel_err_ptr` only lets valid pointers through.
+            Ok(unsafe { NonNull::new_unchecked(dst) })
+        }
+        #[cfg(not(CONFIG_IPV6))]
+        {
+            let _ = (ndev, saddr, daddr);
+            Err(EAFNOSUPPORT)
+        }
+    }
+
+    /// Returns the local address packets are sent from.
+    pub fn saddr(&self) -> PeerAddr {
+        self.saddr
+    }
+
+    /// Returns the peer address.
+    pub fn daddr(&self) -> PeerAddr {
+        self.daddr
+    }
+}
+
+impl Clone for Route {
+    fn clone(&self) -> Self {
+        // SAFETY: By the type invariants the entry is valid; the new instance owns the reference
+        // taken here.
+        unsafe { bindings::dst_clone(self.dst.as_ptr()) };
+        // INVARIANT: The clone owns its own reference.
+        Self {
+            dst: self.dst,
+            saddr: self.saddr,
+            daddr: self.daddr,
+        }
+    }
+}
+
+impl Drop for Route {
+    fn drop(&mut self) {
+        // SAFETY: By the type invariants we own a reference to a valid entry.
+        unsafe { bindings::dst_release(self.dst.as_ptr()) };
+    }
+}
+
+// SAFETY: Route entries are reference counted and may be used and released from any thread.
+unsafe impl Send for Route {}
+
+// SAFETY: Shared references only take additional references on the entry.
+unsafe impl Sync for Route {}
+
+/// Returns the IPv4 octets of an IPv4-mapped address.
+fn v4_octets(addr: PeerAddr) -> [u8; 4] {
+    let octets = addr.octets();
+    [octets[12], octets[13], octets[14], octets[15]]
+}
+
+/// Header fields of outgoing packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxParams {
+    /// UDP source port, which selects the ECMP path of the flow.
+    pub src_port: u16,
+    /// UDP destination port.
+    pub dst_port: u16,
+    /// IPv4 type of service or IPv6 traffic class.
+    pub tos: u8,
+    /// IPv4 time to live or IPv6 hop limit.
+    pub ttl: u8,
+    /// Sets the IPv4 "don't fragment" bit.
+    pub dont_fragment: bool,
+}
+
+impl TxParams {
+    /// Returns the parameters of the packets of QP `qpn`, with the source port derived from the
+    /// QP number like the C driver does.
+    pub const fn for_qp(qpn: u32) -> Self {
+        // `hash_32(qpn, 14)`.
+        let hash = qpn.wrapping_mul(0x61c8_8647) >> (32 - 14);
+        Self {
+            src_port: ROCE_V2_SPORT_BASE + (hash & 0x3fff) as u16,
+            dst_port: super::ROCE_V2_UDP_DPORT,
+            tos: 0,
+            ttl: 64,
+            dont_fragment: true,
+        }
+    }
+
+    /// Returns the parameters of the packets of QP `qpn` on a device created with the overrides
+    /// `params`, whose [`LinkParams::udp_port`] replaces the RoCEv2 destination port.
+    pub const fn for_link(qpn: u32, params: &LinkParams) -> Self {
+        let mut tx = Self::for_qp(qpn);
+        if let Some(port) = params.udp_port {
+            tx.dst_port = port;
+        }
+        tx
+    }
+}
+
+/// Sends `skb`, whose data runs from the BTH to the ICRC, to the peer of `route`.
+///
+/// The UDP and IP headers are pushed in front of the data, so the buffer needs the headroom
+/// [`SkBuff::alloc_tx`] reserves, then the ICRC is computed into the last bytes of the packet.
+/// Returns `EAGAIN` if the packet was dropped on the way out. The packet is counted in the
+/// `sent_pkts` of `counters`, or in their `send_err` if it could not be sent.
+pub fn transmit(skb: SkBuff, route: &Route, params: &TxParams, counters: &Counters) -> Result {
+    count(xmit(skb, route, params), counters)
+}
+
+fn count(ret: Result, counters: &Counters) -> Result {
+    match ret {
+        Ok(()) => counters.inc(Counter::SentPkts),
+        Err(_) => counters.inc(Counter::SendErr),
+    }
+    ret
+}
+
+fn xmit(mut skb: SkBuff, route: &Route, params: &TxParams) -> Result {
+    let v4 = route.daddr.is_v4();
+    let udp_len = skb.len() as usize + UDP_HDR_LEN;
+    let ip_len = if v4 { IPV4_HDR_LEN } else { IPV6_HDR_LEN };
+    if udp_len + ip_len > u16::MAX as usize {
+        return Err(EINVAL);
+    }
+    let udp = skb.push(UDP_HDR_LEN as u32)?;
+    udp[0..2].copy_from_slice(&params.src_port.to_be_bytes());
+    udp[2..4].copy_from_slice(&params.dst_port.to_be_bytes());
+    udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+    udp[6..8].fill(0);
+    let ptr = skb.as_ptr();
+    // SAFETY: `ptr` is a valid buffer we own. Scrubbing drops any previous route, which is then
+    // replaced by a new reference to ours, and the control block is reset for the IP layer.
+    unsafe {
+        bindings::skb_reset_transport_header(ptr);
+        bindings::skb_scrub_packet(ptr, false);
+        bindings::skb_clear_hash(ptr);
+        bindings::skb_dst_set(ptr, bindings::dst_clone(route.dst.as_ptr()));
+        (*ptr).cb.fill(0);
+    }
+
+    let ip = skb.push(ip_len as u32)?;
+    if v4 {
+        ip[0] = 0x45;
+        ip[1] = params.tos;
+        ip[2..4].copy_from_slice(&((ip_len + udp_len) as u16).to_be_bytes());
+        ip[4..6].fill(0);
+        ip[6..8].copy_from_slice(&(if params.dont_fragment { 0x4000u16 } else { 0 }).to_be_bytes());
+        ip[8] = params.ttl;
+        ip[9] = bindings::IPPROTO_UDP as u8;
+        ip[10..12].fill(0);
+        ip[12..16].copy_from_slice(&v4_octets(route.saddr));
+        ip[16..20].copy_from_slice(&v4_octets(route.daddr));
+    } else {
+        ip[0] = 0x60 | (params.tos >> 4);
+        ip[1] = params.tos << 4;
+        ip[2..4].fill(0);
+        ip[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        ip[6] = bindings::IPPROTO_UDP as u8;
+        ip[7] = params.ttl;
+        ip[8..24].copy_from_slice(&route.saddr.octets());
+        ip[24..40].copy_from_slice(&route.daddr.octets());
+    }
+    let iph = ip.as_mut_ptr();
+    // SAFETY: `ptr` is a valid buffer we own whose data now starts at the IP header, and the
+    // route it holds a reference to keeps its device alive.
+    let net = unsafe {
+        bindings::skb_reset_network_header(ptr);
+        let net = bindings::dev_net((*route.dst.as_ptr()).dev);
+        if v4 {
+            (*ptr).protocol = (bindings::ETH_P_IP as u16).to_be();
+            bindings::__ip_select_ident(net, iph.cast(), 1);
+        } else {
+            (*ptr).protocol = (bindings::ETH_P_IPV6 as u16).to_be();
+        }
+        net
+    };
+    // The IPv4 identification is covered by the ICRC, so it has to be picked first.
+    super::icrc::generate(&mut skb)?;
+    // SAFETY: The route holds a reference to its device.
+    let ifindex = unsafe { (*(*route.dst.as_ptr()).dev).ifindex };
+    super::trace::send_pkt(ifindex, &skb.data()[ip_len + UDP_HDR_LEN..]);
+
+    // SAFETY: `ptr` is a valid buffer we own with its route set. The output functions take over
+    // our reference whatever they return.
+    let err = unsafe {
+        if v4 {
+            bindings::ip_local_out(net, ptr::null_mut(), skb.into_raw())
+        } else {
+            local_out6(net, skb)
+        }
+    };
+    // `net_xmit_eval()`: congestion notifications still mean the packet went out.
+    if err != 0 && err != bindings::NET_XMIT_CN as core::ffi::c_int {
+        return Err(EAGAIN);
+    }
+    Ok(())
+}
+
+/// Hands an IPv6 packet to `ip6_local_out()`.
+///
+/// # Safety
+///
+/// `net` must be the valid namespace of the route set on `skb`.
+unsafe fn local_out6(net: *mut bindings::net, skb: SkBuff) -> core::ffi::c_int {
+    #[cfg(CONFIG_IPV6)]
+    {
+        // SAFETY: Guaranteed by the safety requirements; the reference to `skb` is handed over.
+        unsafe { bindings::ip6_local_out(net, ptr::null_mut(), skb.into_raw()) }
+    }
+    #[cfg(not(CONFIG_IPV6))]
+    {
+        // Routes to IPv6 peers cannot be resolved without IPv6.
+        let _ = (net, skb);
+        -(bindings::EAFNOSUPPORT as core::ffi::c_int)
+    }
+}
+
+/// Largest number of packets a device holds back at once; the next ones are sent right away.
+pub const MAX_DELAYED_TX: usize = 256;
+
+/// The packets a device holds back, each slot owned by the timer of its packet.
+struct Pending {
+    slots: [*mut DelayedTx; MAX_DELAYED_TX],
+}
+
+/// The fault injection state of the transmit path of a device: its knobs, and the packets it
+/// holds back.
+///
+/// Providers keep one per device and pass it to [`transmit_with_faults`]. Dropping it sends the
+/// packets still held back and waits for the timers, so the device may go away right after.
+///
+/// # Invariants
+///
+/// `pending` is initialised. Its non-null slots point to packets allocated by
+/// [`DelayedTx::try_new`] whose timer is armed, and `in_flight` counts those packets plus the
+/// ones whose timer function is running.
+pub struct TxFaults {
+    inner: Pin<Box<FaultsInner>>,
+}
+
+struct FaultsInner {
+    knobs: FaultInjector,
+    pending: SpinLock<Pending>,
+    in_flight: AtomicUsize,
+    _pin: PhantomPinned,
+}
+
+// SAFETY: The slots are only accessed with `pending` locked, and the packets they point to are
+// owned by either their timer or `TxFaults::drop`, see `DelayedTx::expired`.
+unsafe impl Send for FaultsInner {}
+// SAFETY: See the `Send` implementation.
+unsafe impl Sync for FaultsInner {}
+
+impl TxFaults {
+    /// Creates the state of a device, whose knobs let every packet through.
+    pub fn try_new() -> Result<Self> {
+        let mut inner = try_pin(FaultsInner {
+            knobs: FaultInjector::new(),
+            // SAFETY: `spinlock_init!` is called below.
+            pending: unsafe {
+                SpinLock::new(Pending {
+                    slots: [ptr::null_mut(); MAX_DELAYED_TX],
+                })
+            },
+            in_flight: AtomicUsize::new(0),
+            _pin: PhantomPinned,
+        })?;
+        // SAFETY: `pending` is pinned along with `inner`.
+        let pending = unsafe { inner.as_mut().map_unchecked_mut(|i| &mut i.pending) };
+        crate::spinlock_init!(pending, "TxFaults::pending");
+        // INVARIANT: The lock was just initialised and no packet is held back.
+        Ok(Self { inner })
+    }
+
+    /// Returns the knobs of the device, e.g. for its sysfs attributes.
+    pub fn knobs(&self) -> &FaultInjector {
+        &self.inner.knobs
+    }
+
+    /// Returns the number of packets held back and not sent yet.
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for TxFaults {
+    fn drop(&mut self) {
+        // The timers find their slot empty from now on, and leave their packet to us.
+        let slots = mem::replace(
+            &mut self.inner.pending.lock_irqdisable().slots,
+            [ptr::null_mut(); MAX_DELAYED_TX],
+        );
+        for this in slots.into_iter().filter(|p| !p.is_null()) {
+            // SAFETY: By the type invariants the packet is alive with its timer set up; it is only
+            // freed below, once its timer function returned.
+            unsafe { bindings::del_timer_sync(ptr::addr_of_mut!((*this).timer)) };
+            // The device is going away, so the packet is sent now rather than lost.
+            // SAFETY: The timer is inactive, and its function left the packet to us.
+            let (skb, route, params) = unsafe { DelayedTx::take(this) };
+            let _ = xmit(skb, &route, &params);
+            self.inner.in_flight.fetch_sub(1, Ordering::AcqRel);
+        }
+        // Timer functions that emptied their slot before we did may still be sending.
+        while self.in_flight() != 0 {
+            // SAFETY: FFI call without preconditions.
+            unsafe { bindings::msleep(1) };
+        }
+    }
+}
+
+/// Sends `skb` like [`transmit`], unless the knobs of `faults` decide to drop or delay it.
+///
+/// Injection is only compiled in with `CONFIG_RUST_RDMA_FAULT_INJECTION`; without it, or while
+/// the knobs are at 0, this is [`transmit`]. Dropped packets are reported as sent. Delayed ones
+/// are sent from a timer, or right away if no memory or no slot is left to hold them. Both are
+/// counted as sent in `counters`, as the requester sees them leave.
+pub fn transmit_with_faults(
+    skb: SkBuff,
+    route: &Route,
+    params: &TxParams,
+    faults: &TxFaults,
+    counters: &Counters,
+) -> Result {
+    let knobs = faults.knobs();
+    if !cfg!(CONFIG_RUST_RDMA_FAULT_INJECTION) || !knobs.is_active() {
+        return transmit(skb, route, params, counters);
+    }
+    // SAFETY: FFI call without preconditions.
+    let ret = match knobs.decide(unsafe { bindings::get_random_u32() }) {
+        TxFault::Pass => xmit(skb, route, params),
+        TxFault::Drop => Ok(()),
+        TxFault::Delay(usecs) => match DelayedTx::try_new(skb, route, params, &faults.inner) {
+            Ok(delayed) => match DelayedTx::start(delayed, usecs) {
+                Ok(()) => Ok(()),
+                Err(delayed) => {
+                    // SAFETY: The timer was never armed.
+                    let (skb, route, params) = unsafe { DelayedTx::take(delayed.as_ptr()) };
+                    xmit(skb, &route, &params)
+                }
+            },
+            Err(skb) => xmit(skb, route, params),
+        },
+    };
+    count(ret, counters)
+}
+
+/// A packet waiting for its timer to be sent.
+#[repr(C)]
+struct DelayedTx {
+    // Must stay first: the timer function finds the packet from its `timer_list`.
+    timer: bindings::timer_list,
+    owner: *const FaultsInner,
+    slot: usize,
+    skb: SkBuff,
+    route: Route,
+    params: TxParams,
+}
+
+impl DelayedTx {
+    /// Moves the packet to an atomic allocation, handing it back if there is no memory.
+    fn try_new(
+        skb: SkBuff,
+        route: &Route,
+        params: &TxParams,
+        owner: &FaultsInner,
+    ) -> core::result::Result<NonNull<Self>, SkBuff> {
+        // SAFETY: FFI call without preconditions; packets may be sent from atomic context.
+        let ptr = unsafe {
+            bindings::kmalloc(
+                mem::size_of::<Self>(),
+                bindings::BINDINGS_GFP_ATOMIC | bindings::BINDINGS___GFP_ZERO,
+            )
+        };
+        let ptr = match NonNull::new(ptr.cast::<Self>()) {
+            Some(ptr) => ptr,
+            None => return Err(skb),
+        };
+        let this = ptr.as_ptr();
+        // SAFETY: `this` is a fresh zeroed allocation of the right size, and a zeroed timer is
+        // valid to set up.
+        unsafe {
+            ptr::addr_of_mut!((*this).owner).write(owner);
+            ptr::addr_of_mut!((*this).skb).write(skb);
+            ptr::addr_of_mut!((*this).route).write(route.clone());
+            ptr::addr_of_mut!((*this).params).write(*params);
+            bindings::timer_setup(ptr::addr_of_mut!((*this).timer), Some(Self::expired), 0);
+        }
+        Ok(ptr)
+    }
+
+    /// Sends the packet in `usecs` microseconds, at least one jiffy away, handing it back if its
+    /// device holds [`MAX_DELAYED_TX`] packets already.
+    fn start(this: NonNull<Self>, usecs: u32) -> core::result::Result<(), NonNull<Self>> {
+        let raw = this.as_ptr();
+        // SAFETY: `this` comes from `try_new`, so `owner` is set, and the `TxFaults` it belongs
+        // to is borrowed by our caller.
+        let owner = unsafe { &*(*raw).owner };
+        let mut pending = owner.pending.lock_irqdisable();
+        let slot = match pending.slots.iter().position(|p| p.is_null()) {
+            Some(slot) => slot,
+            None => return Err(this),
+        };
+        // SAFETY: FFI call without preconditions.
+        let delay = unsafe { bindings::usecs_to_jiffies(usecs) }.max(1);
+        // SAFETY: `jiffies` is always valid to read; it is volatile as the tick updates it.
+        let now = unsafe { ptr::read_volatile(ptr::addr_of!(bindings::jiffies)) };
+        // INVARIANT: The packet takes the slot with its timer armed, and is counted.
+        owner.in_flight.fetch_add(1, Ordering::AcqRel);
+        pending.slots[slot] = raw;
+        // SAFETY: `raw` comes from `try_new`, so its timer is set up, and from now on only the
+        // timer function or `TxFaults::drop` touch it, which both take the lock we hold first.
+        unsafe {
+            (*raw).slot = slot;
+            (*raw).timer.expires = now.wrapping_add(delay);
+            bindings::add_timer(ptr::addr_of_mut!((*raw).timer));
+        }
+        Ok(())
+    }
+
+    /// Frees the packet and returns its contents.
+    ///
+    /// # Safety
+    ///
+    /// `this` must come from [`DelayedTx::try_new`], with its timer inactive, and must not be
+    /// used afterwards.
+    unsafe fn take(this: *mut Self) -> (SkBuff, Route, TxParams) {
+        // SAFETY: Guaranteed by the safety requirements. The fields are moved out before the
+        // memory is freed.
+        unsafe {
+            let fields = (
+                ptr::addr_of!((*this).skb).read(),
+                ptr::addr_of!((*this).route).read(),
+                ptr::addr_of!((*this).params).read(),
+            );
+            bindings::kfree(this.cast());
+            fields
+        }
+    }
+
+    unsafe extern "C" fn expired(timer: *mut bindings::timer_list) {
+        let this = timer.cast::<Self>();
+        // SAFETY: `timer` is the first field of a `DelayedTx` passed to `start`. Its owner is
+        // alive until its `in_flight` drops to 0, which only we or `TxFaults::drop` do.
+        let owner = unsafe { &*(*this).owner };
+        {
+            let mut pending = owner.pending.lock_irqdisable();
+            // SAFETY: `slot` was set by `start` before the timer was armed.
+            let slot = unsafe { (*this).slot };
+            if pending.slots[slot] != this {
+                // `TxFaults::drop` took the packet, and waits for us to return to send it.
+                return;
+            }
+            pending.slots[slot] = ptr::null_mut();
+        }
+        // SAFETY: The timer fired and we emptied the slot, so the packet is ours.
+        let (skb, route, params) = unsafe { Self::take(this) };
+        // Nobody is left to report a failure to; the peer sees a lost packet.
+        let _ = xmit(skb, &route, &params);
+        drop(route);
+        owner.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,423 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Keyed object pools.
+//!
+//! A [`Pool`] gives each object an index, like the C driver's `rxe_pool.c`, and resolves QP
+//! numbers and MR or MW keys back to the object on the receive path. Lookups run under
+//! `rcu_read_lock()` and take a reference, which keeps the object alive while a packet is being
+//! processed; removal unpublishes the object, waits for those references to go away and for the
+//! RCU readers that might still see it, and only then frees it.
+//!
+//! The index allocation and the reference count live in [`crate::rdma::pool`].
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+
+use crate::error::{code::*, Result};
+use crate::rdma::id_alloc::words_for;
+use crate::rdma::mr_key::MrKey;
+use crate::rdma::pool::{IndexRange, RefCount, SlotIndices};
+use crate::rdma::scrub::{Scrub, Scrubber};
+use crate::rdma::snapshot::Snapshot;
+use crate::rdma::tracker::{LiveResource, LiveResources, ResourceKind};
+use crate::sync::smutex::Mutex;
+use crate::{bindings, pr_warn};
+
+/// How long removal waits for the references before warning, like `RXE_POOL_TIMEOUT`.
+const POOL_TIMEOUT_MS: u32 = 200;
+
+struct Elem<T> {
+    refs: RefCount,
+    index: u32,
+    key: AtomicU32,
+    /// Completed when the last reference is dropped.
+    done: UnsafeCell<MaybeUninit<bindings::completion>>,
+    data: T,
+}
+
+impl<T> Elem<T> {
+    fn done(&self) -> *mut bindings::completion {
+        self.done.get().cast()
+    }
+
+    fn put(&self) {
+        if self.refs.put() {
+            // SAFETY: The completion is initialised before the element is published, and freed
+            // only once it completed.
+            unsafe { bindings::complete(self.done()) };
+        }
+    }
+}
+
+/// A pool of objects of one kind, indexed for lookups from the receive path.
+///
+/// # Invariants
+///
+/// A non-null slot points to an element created by
+/// [`Pool::add`] or [`Pool::add_at`] whose index maps to that slot, and which is only freed after
+/// it was cleared from the slot and an RCU grace period elapsed. The slots in use are the ones
+/// allocated in `indices`.
+pub struct Pool<T> {
+    kind: ResourceKind,
+    indices: Mutex<SlotIndices<Vec<u64>>>,
+    range: IndexRange,
+    capacity: u32,
+    slots: Vec<AtomicPtr<Elem<T>>>,
+    count: AtomicU32,
+}
+
+// SAFETY: Elements are shared with the lookups of any thread, and freed by the thread that
+// removes them.
+unsafe impl<T: Send + Sync> Send for Pool<T> {}
+// SAFETY: The allocator is behind a mutex, the slots are atomic.
+unsafe impl<T: Send + Sync> Sync for Pool<T> {}
+
+impl<T> Pool<T> {
+    /// Creates a pool of at most `capacity` objects of `kind`.
+    ///
+    /// Fails with `EINVAL` if `capacity` is zero or larger than the index range of `kind`.
+    pub fn try_new(kind: ResourceKind, capacity: u32) -> Result<Self> {
+        let range = IndexRange::of(kind);
+        if capacity == 0 || capacity > range.size() {
+            return Err(EINVAL);
+        }
+        let len = words_for(capacity);
+        let mut words = Vec::try_with_capacity(len)?;
+        for _ in 0..len {
+            words.try_push(0)?;
+        }
+        let indices = SlotIndices::new(range, capacity, words).ok_or(EINVAL)?;
+        let mut slots = Vec::try_with_capacity(capacity as usize)?;
+        for _ in 0..capacity {
+            slots.try_push(AtomicPtr::new(ptr::null_mut()))?;
+        }
+        Ok(Self {
+            kind,
+            indices: Mutex::new(indices),
+            range,
+            capacity,
+            slots,
+            count: AtomicU32::new(0),
+        })
+    }
+
+    /// Returns the kind of the objects.
+    pub fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+
+    /// Returns the number of objects in the pool.
+    pub fn len(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if the pool holds no object.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the largest number of objects in the pool.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Writes the pool and its live objects to `snap`.
+    pub fn snapshot(&self, snap: &mut Snapshot<'_>) {
+        snap.pool(self.kind, self.capacity, self);
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut SlotIndices<Vec<u64>>) -> R) -> R {
+        f(&mut self.indices.lock())
+    }
+
+    /// Adds `data` to the pool under a new index, whose key has variant 0.
+    ///
+    /// Fails with `EBUSY` if the pool is full. Must be called in process context.
+    pub fn add(&self, data: T) -> Result<PoolEntry<T>> {
+        self.insert(data, SlotIndices::alloc)
+    }
+
+    /// Adds `data` to the pool under `index`, e.g. the QP number another driver gave the QP.
+    ///
+    /// Fails with `EINVAL` if `index` is out of the range of the pool's kind, and with `EBUSY` if
+    /// its slot is taken. Must be called in process context.
+    pub fn add_at(&self, index: u32, data: T) -> Result<PoolEntry<T>> {
+        if !self.range.contains(index) {
+            return Err(EINVAL);
+        }
+        self.insert(data, |indices| Some((index, indices.alloc_at(index)?)))
+    }
+
+    /// Publishes `data` under the index and slot `pick` allocates, failing with `EBUSY` if it
+    /// does not.
+    fn insert(
+        &self,
+        data: T,
+        pick: impl FnOnce(&mut SlotIndices<Vec<u64>>) -> Option<(u32, usize)>,
+    ) -> Result<PoolEntry<T>> {
+        let elem = Box::try_new(Elem {
+            refs: RefCount::new(),
+            index: 0,
+            key: AtomicU32::new(0),
+            done: UnsafeCell::new(MaybeUninit::uninit()),
+            data,
+        })?;
+        // SAFETY: The completion is not in use yet, and stays at its address in the box.
+        unsafe { bindings::init_completion(elem.done()) };
+        let elem = Box::into_raw(elem);
+        let added = self.with(|indices| {
+            let (index, slot) = pick(indices)?;
+            // SAFETY: `elem` is not published yet.
+            unsafe {
+                (*elem).index = index;
+                (*elem)
+                    .key
+                    .store(MrKey::new(index, 0).raw(), Ordering::Relaxed);
+            }
+            // INVARIANT: The slot of `index` was free; release publishes the element.
+            self.slots[slot].store(elem, Ordering::Release);
+            Some(())
+        });
+        if added.is_none() {
+            // SAFETY: `elem` was never published.
+            drop(unsafe { Box::from_raw(elem) });
+            return Err(EBUSY);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        Ok(PoolEntry { elem })
+    }
+
+    /// Looks the object of `index` up and takes a reference to it.
+    ///
+    /// Objects being removed are not found.
+    pub fn lookup(&self, index: u32) -> Option<PoolRef<T>> {
+        let slot = self.range.slot(index, self.capacity)?;
+        // SAFETY: FFI call without preconditions.
+        unsafe { bindings::rcu_read_lock() };
+        let elem = self.slots[slot].load(Ordering::Acquire);
+        // SAFETY: By the type invariants a published element is only freed after a grace period,
+        // which cannot elapse while we are in the read-side critical section.
+        let found =
+            !elem.is_null() && unsafe { (*elem).index == index && (*elem).refs.get_unless_zero() };
+        // SAFETY: Paired with the `rcu_read_lock` above.
+        unsafe { bindings::rcu_read_unlock() };
+        if !found {
+            return None;
+        }
+        // INVARIANT: The reference taken above keeps the element alive.
+        Some(PoolRef { elem })
+    }
+
+    /// Looks an MR or MW up by its lkey or rkey and takes a reference to it.
+    ///
+    /// A key whose variant is stale does not match.
+    pub fn lookup_key(&self, key: MrKey) -> Option<PoolRef<T>> {
+        self.lookup(key.index()).filter(|r| r.key() == key)
+    }
+
+    /// Removes the object of `entry` from the pool and returns its data.
+    ///
+    /// Waits until the references taken by lookups are dropped, warning if that takes long, and
+    /// for the RCU readers that may still see the object. Must be called in process context.
+    ///
+    /// Hands `entry` back if it belongs to another pool.
+    pub fn remove(&self, entry: PoolEntry<T>) -> core::result::Result<T, PoolEntry<T>> {
+        let elem = entry.elem;
+        let index = entry.index();
+        let slot = match self.range.slot(index, self.capacity) {
+            Some(slot) => slot,
+            None => return Err(entry),
+        };
+        // An element is published in a single slot, so finding it there proves it is ours.
+        let unpublished = self.with(|indices| {
+            let ours = self.slots[slot]
+                .compare_exchange(elem, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+                .is_ok();
+            if ours {
+                indices.free(slot);
+            }
+            ours
+        });
+        if !unpublished {
+            return Err(entry);
+        }
+        self.count.fetch_sub(1, Ordering::Relaxed);
+
+        // SAFETY: The element is alive until it is freed below.
+        let elem_ref = unsafe { &*elem };
+        elem_ref.put();
+        // SAFETY: FFI call without preconditions.
+        let timeout = unsafe { bindings::usecs_to_jiffies(POOL_TIMEOUT_MS * 1000) };
+        // SAFETY: The completion was initialised by `add`.
+        if unsafe { bindings::wait_for_completion_timeout(elem_ref.done(), timeout as _) } == 0 {
+            pr_warn!(
+                "Timed out waiting for {}#{} to complete\n",
+                self.kind.name(),
+                index
+            );
+            // SAFETY: As above.
+            unsafe { bindings::wait_for_completion(elem_ref.done()) };
+        }
+        // Lookups that found the element before it was unpublished may still look at it.
+        // SAFETY: FFI call without preconditions; removal runs in process context.
+        unsafe { bindings::synchronize_rcu() };
+        // SAFETY: The element is unpublished, unreferenced and out of sight of RCU readers.
+        Ok(unsafe { Box::from_raw(elem) }.data)
+    }
+
+    /// Removes the object of `entry` like [`Pool::remove`] and frees it, scrubbing its payloads
+    /// first if `scrubber`, the one of the device the object belongs to, asks for it.
+    ///
+    /// This is how MRs and MWs holding bounce buffers go away. Hands `entry` back if it belongs
+    /// to another pool.
+    pub fn release(
+        &self,
+        entry: PoolEntry<T>,
+        scrubber: &Scrubber,
+    ) -> core::result::Result<(), PoolEntry<T>>
+    where
+        T: Scrub,
+    {
+        let mut data = self.remove(entry)?;
+        data.scrub(scrubber);
+        Ok(())
+    }
+}
+
+impl<T> LiveResources for Pool<T> {
+    fn for_each_live(&self, f: &mut dyn FnMut(LiveResource)) {
+        // SAFETY: FFI call without preconditions.
+        unsafe { bindings::rcu_read_lock() };
+        for slot in self.slots.iter() {
+            let elem = slot.load(Ordering::Acquire);
+            if !elem.is_null() {
+                f(LiveResource {
+                    kind: self.kind,
+                    // SAFETY: As in `lookup`, the element outlives the read-side critical section.
+                    index: unsafe { (*elem).index },
+                    owner: 0,
+                });
+            }
+        }
+        // SAFETY: Paired with the `rcu_read_lock` above.
+        unsafe { bindings::rcu_read_unlock() };
+    }
+}
+
+/// The owner's handle of an object in a [`Pool`], returned by [`Pool::add`].
+///
+/// It must be handed back to [`Pool::remove`]; an entry that is dropped instead leaks the object,
+/// which is then reported by [`crate::rxe::Registration::report_leaks`].
+///
+/// # Invariants
+///
+/// `elem` is a published element of a pool, kept alive by the owner's reference.
+#[must_use]
+pub struct PoolEntry<T> {
+    elem: *mut Elem<T>,
+}
+
+// SAFETY: The entry gives shared access to the data only.
+unsafe impl<T: Send + Sync> Send for PoolEntry<T> {}
+// SAFETY: As above.
+unsafe impl<T: Send + Sync> Sync for PoolEntry<T> {}
+
+impl<T> PoolEntry<T> {
+    fn elem(&self) -> &Elem<T> {
+        // SAFETY: By the type invariants the element is alive.
+        unsafe { &*self.elem }
+    }
+
+    /// Returns the pool index, e.g. the QP number.
+    pub fn index(&self) -> u32 {
+        self.elem().index
+    }
+
+    /// Returns the current key of an MR or MW.
+    pub fn key(&self) -> MrKey {
+        MrKey::from_raw(self.elem().key.load(Ordering::Acquire))
+    }
+
+    /// Changes the variant of the key, e.g. on a fast registration or a rebind, so that the old
+    /// key no longer resolves to the object.
+    pub fn set_variant(&self, variant: u8) -> MrKey {
+        let key = self.key().with_variant(variant);
+        self.elem().key.store(key.raw(), Ordering::Release);
+        key
+    }
+
+    /// Takes a reference to the object.
+    pub fn get(&self) -> PoolRef<T> {
+        self.elem().refs.get();
+        // INVARIANT: A reference was just taken.
+        PoolRef { elem: self.elem }
+    }
+}
+
+impl<T> Deref for PoolEntry<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.elem().data
+    }
+}
+
+/// A counted reference to an object of a [`Pool`], e.g. from a lookup on the receive path.
+///
+/// The object is not freed before the reference is dropped.
+///
+/// # Invariants
+///
+/// `elem` is an element of a pool on which this instance holds a reference.
+pub struct PoolRef<T> {
+    elem: *mut Elem<T>,
+}
+
+// SAFETY: The reference gives shared access to the data only, and may be dropped anywhere.
+unsafe impl<T: Send + Sync> Send for PoolRef<T> {}
+// SAFETY: As above.
+unsafe impl<T: Send + Sync> Sync for PoolRef<T> {}
+
+impl<T> PoolRef<T> {
+    fn elem(&self) -> &Elem<T> {
+        // SAFETY: By the type invariants the reference keeps the element alive.
+        unsafe { &*self.elem }
+    }
+
+    /// Returns the pool index, e.g. the QP number.
+    pub fn index(&self) -> u32 {
+        self.elem().index
+    }
+
+    /// Returns the current key of an MR or MW.
+    pub fn key(&self) -> MrKey {
+        MrKey::from_raw(self.elem().key.load(Ordering::Acquire))
+    }
+}
+
+impl<T> Clone for PoolRef<T> {
+    fn clone(&self) -> Self {
+        self.elem().refs.get();
+        // INVARIANT: A reference was just taken.
+        Self { elem: self.elem }
+    }
+}
+
+impl<T> Deref for PoolRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.elem().data
+    }
+}
+
+impl<T> Drop for PoolRef<T> {
+    fn drop(&mut self) {
+        self.elem().put();
+    }
+}
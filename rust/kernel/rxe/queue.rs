@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Queue buffers of soft-RoCE QPs, SRQs and CQs.
+//!
+//! The layout and the producer/consumer protocol live in [`crate::rdma::queue`]; this allocates
+//! the buffers so that they can be mapped into the verbs consumer, like the C driver's
+//! `rxe_queue_init()`. Owners free the buffers with [`QueueBuf::release`], which scrubs the slots
+//! first if the [`Scrubber`] of their device asks for it.
+
+use core::mem::ManuallyDrop;
+use core::ptr::NonNull;
+
+use crate::bindings;
+use crate::error::{code::*, Result};
+use crate::rdma::scrub::{Scrub, Scrubber};
+
+pub use crate::rdma::queue::{Consumer, Geometry, Producer, Queue, QueueHeader, HEADER_SIZE};
+
+/// A queue buffer allocated for mapping to userspace.
+///
+/// Owners that map the buffer must keep it until the last mapping is gone, as the C driver does
+/// with `rxe_mmap_info`, then hand it to [`QueueBuf::release`]. A buffer dropped instead is always
+/// scrubbed, as the policy of its device is unknown then.
+///
+/// # Invariants
+///
+/// `buf` was returned by `vmalloc_user()` for `geometry.buf_size()` bytes and holds an
+/// initialised queue.
+pub struct QueueBuf {
+    buf: NonNull<u8>,
+    geometry: Geometry,
+}
+
+impl QueueBuf {
+    /// Allocates an empty queue of at least `num_elem` elements of `elem_size` bytes.
+    ///
+    /// Returns `EINVAL` if the queue would be too large.
+    pub fn try_new(num_elem: u32, elem_size: u32) -> Result<Self> {
+        let geometry = Geometry::new(num_elem, elem_size).ok_or(EINVAL)?;
+        // SAFETY: FFI call without preconditions. The buffer is zeroed and page aligned.
+        let buf = unsafe { bindings::vmalloc_user(geometry.buf_size() as _) };
+        let buf = NonNull::new(buf.cast::<u8>()).ok_or(ENOMEM)?;
+        // SAFETY: The buffer was just allocated with the size of `geometry`, and page alignment
+        // suits the header.
+        unsafe { Queue::init(buf.as_ptr(), geometry) };
+        // INVARIANT: The buffer was allocated by `vmalloc_user()` and initialised.
+        Ok(Self { buf, geometry })
+    }
+
+    /// Returns the shape of the queue.
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    /// Returns the size of the buffer, to map to userspace.
+    pub fn buf_size(&self) -> usize {
+        self.geometry.buf_size()
+    }
+
+    /// Returns the buffer address, for `remap_vmalloc_range()`.
+    pub fn as_ptr(&self) -> *mut core::ffi::c_void {
+        self.buf.as_ptr().cast()
+    }
+
+    /// Returns the queue in the buffer.
+    pub fn queue(&mut self) -> Queue<'_> {
+        // SAFETY: By the type invariants the buffer is valid and initialised for this geometry,
+        // and borrowing `self` mutably keeps it to one queue at a time.
+        unsafe { Queue::attach(self.buf.as_ptr(), self.geometry) }
+    }
+}
+
+impl QueueBuf {
+    /// Frees the buffer once its last mapping is gone, scrubbing the slots first if `scrubber`,
+    /// the one of the device the queue belongs to, asks for it.
+    pub fn release(self, scrubber: &Scrubber) {
+        let mut this = ManuallyDrop::new(self);
+        this.free(scrubber);
+    }
+
+    fn free(&mut self, scrubber: &Scrubber) {
+        // The last mapping is gone, so nobody sees the slots being zeroed.
+        self.queue().scrub(scrubber);
+        // SAFETY: By the type invariants the buffer came from `vmalloc_user()`, and the owner
+        // outlives its mappings. The buffer is not used anymore.
+        unsafe { bindings::vfree(self.as_ptr()) };
+    }
+}
+
+impl Drop for QueueBuf {
+    fn drop(&mut self) {
+        let scrubber = Scrubber::new();
+        scrubber.set_enabled(true);
+        self.free(&scrubber);
+    }
+}
+
+// SAFETY: The buffer is not tied to a thread.
+unsafe impl Send for QueueBuf {}
@@ -0,0 +1,391 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Requester, responder and completer tasks of soft-RoCE QPs.
+//!
+//! Mirrors the C driver's `rxe_task.c`: each QP runs its three state machines as tasks that can be
+//! scheduled on a workqueue or run inline from any context, one pass at a time, see
+//! [`TaskState`]. [`Tasks`] owns the QP context the tasks work on, so that it cannot go away while
+//! a pass runs: dropping it kills the tasks and waits for the running passes before the context
+//! is dropped.
+//!
+//! The retransmit and RNR NAK timers of reliable connected QPs live next to the tasks they
+//! schedule when they expire, and are cancelled on drop as well.
+//!
+//! QPs created with [`Tasks::try_new_traced`] also keep a [`QpTrace`] of their last protocol
+//! events, which the state machines feed through [`Tasks::record`] and timer expiries feed
+//! themselves.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+
+use super::net::SkBuff;
+use crate::bindings;
+use crate::error::Result;
+use crate::ib::compat::try_pin;
+use crate::rdma::qp_trace::QpTrace;
+use crate::rdma::task_state::{Drive, TaskState};
+use crate::rdma::timeout;
+
+pub use crate::rdma::qp_trace::QpEvent;
+pub use crate::rdma::task_state::Pass;
+
+/// Passes a task runs before giving the CPU back, like the C driver's `RXE_MAX_ITERATIONS`.
+pub const MAX_ITERATIONS: usize = 1024;
+
+/// The state machines of a QP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum TaskKind {
+    /// Turns send work requests into request packets.
+    Requester = 0,
+    /// Handles request packets and sends responses.
+    Responder = 1,
+    /// Handles responses and completes send work requests.
+    Completer = 2,
+}
+
+impl TaskKind {
+    /// Every kind of task.
+    pub const ALL: [Self; 3] = [Self::Requester, Self::Responder, Self::Completer];
+}
+
+/// Implemented by the QP context of a provider, one method per state machine.
+///
+/// Each method does one step of its state machine and tells whether there is more to do. Passes
+/// of one kind never overlap, but passes of different kinds run concurrently.
+pub trait QpTasks: Send + Sync {
+    /// Runs one step of the requester.
+    fn requester(&self) -> Pass;
+
+    /// Runs one step of the responder.
+    fn responder(&self) -> Pass;
+
+    /// Runs one step of the completer.
+    fn completer(&self) -> Pass;
+
+    /// Queues a packet received for the QP for the `kind` task, the responder for requests and
+    /// the completer for responses, which is scheduled once this returns.
+    ///
+    /// Called on the receive path, possibly in softirq context. Fails if the packet cannot be
+    /// queued, e.g. because the queue is full, which drops it.
+    fn enqueue(&self, kind: TaskKind, skb: SkBuff) -> Result;
+}
+
+/// The timers of a QP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(usize)]
+pub enum TimerKind {
+    /// Local ACK timeout, which makes the completer retransmit unacknowledged requests.
+    Retransmit = 0,
+    /// Back-off after an RNR NAK, which makes the requester resend to a responder that was not
+    /// ready.
+    RnrNak = 1,
+}
+
+impl TimerKind {
+    /// Every kind of timer.
+    pub const ALL: [Self; 2] = [Self::Retransmit, Self::RnrNak];
+
+    /// Returns the task scheduled when the timer expires.
+    pub const fn task(self) -> TaskKind {
+        match self {
+            Self::Retransmit => TaskKind::Completer,
+            Self::RnrNak => TaskKind::Requester,
+        }
+    }
+
+    /// Returns the event traced when the timer expires.
+    pub const fn event(self) -> QpEvent {
+        match self {
+            Self::Retransmit => QpEvent::AckTimeout,
+            Self::RnrNak => QpEvent::RnrTimeout,
+        }
+    }
+}
+
+#[repr(C)]
+struct Timer {
+    // Must stay first: the timer function finds the timer from its `timer_list`.
+    timer: UnsafeCell<MaybeUninit<bindings::timer_list>>,
+    kind: TimerKind,
+    owner: *const (),
+}
+
+impl Timer {
+    fn timer(&self) -> *mut bindings::timer_list {
+        self.timer.get().cast()
+    }
+}
+
+#[repr(C)]
+struct Task {
+    // Must stay first: the work function finds the task from its work item.
+    work: UnsafeCell<MaybeUninit<bindings::work_struct>>,
+    state: TaskState,
+    kind: TaskKind,
+    owner: *const (),
+}
+
+impl Task {
+    fn work(&self) -> *mut bindings::work_struct {
+        self.work.get().cast()
+    }
+}
+
+/// The three tasks of a QP, its timers and the context they run on.
+///
+/// # Invariants
+///
+/// The work items and timers are initialised and the `owner` of every task and timer points to
+/// the pinned instance that contains it.
+pub struct Tasks<C: QpTasks> {
+    tasks: [Task; 3],
+    timers: [Timer; 2],
+    trace: Option<Box<QpTrace>>,
+    ctx: C,
+}
+
+fn jiffies() -> u64 {
+    // SAFETY: `jiffies` is always valid to read; it is volatile as the tick updates it.
+    unsafe { core::ptr::read_volatile(core::ptr::addr_of!(bindings::jiffies)) as u64 }
+}
+
+// SAFETY: Tasks only touch `ctx` through `&C`, with `C: Sync`, and their work items are
+// synchronised by the workqueue core.
+unsafe impl<C: QpTasks> Sync for Tasks<C> {}
+
+// SAFETY: Dropping waits for every pass, so the context is only dropped by the owning thread.
+unsafe impl<C: QpTasks> Send for Tasks<C> {}
+
+impl<C: QpTasks> Tasks<C> {
+    /// Takes ownership of `ctx` and sets up its tasks, idle and enabled, and its timers, stopped.
+    ///
+    /// Scheduled passes run on the system unbound workqueue.
+    pub fn try_new(ctx: C) -> Result<Pin<Box<Self>>> {
+        Self::try_new_inner(ctx, None)
+    }
+
+    /// Same as [`Tasks::try_new`], and keeps a trace of the last protocol events of the QP.
+    ///
+    /// Without `CONFIG_RUST_RDMA_QP_TRACE` this is [`Tasks::try_new`].
+    pub fn try_new_traced(ctx: C) -> Result<Pin<Box<Self>>> {
+        if !cfg!(CONFIG_RUST_RDMA_QP_TRACE) {
+            return Self::try_new(ctx);
+        }
+        let trace = Box::try_new(QpTrace::new())?;
+        Self::try_new_inner(ctx, Some(trace))
+    }
+
+    fn try_new_inner(ctx: C, trace: Option<Box<QpTrace>>) -> Result<Pin<Box<Self>>> {
+        let new_task = |kind| Task {
+            work: UnsafeCell::new(MaybeUninit::uninit()),
+            state: TaskState::new(),
+            kind,
+            owner: core::ptr::null(),
+        };
+        let new_timer = |kind| Timer {
+            timer: UnsafeCell::new(MaybeUninit::uninit()),
+            kind,
+            owner: core::ptr::null(),
+        };
+        let mut this = try_pin(Self {
+            tasks: TaskKind::ALL.map(new_task),
+            timers: TimerKind::ALL.map(new_timer),
+            trace,
+            ctx,
+        })?;
+        // SAFETY: Nothing is moved out, the instance is only completed in place.
+        let inner = unsafe { this.as_mut().get_unchecked_mut() };
+        let owner = inner as *const Self as *const ();
+        for task in &mut inner.tasks {
+            task.owner = owner;
+            // SAFETY: The work item is ours and not queued anywhere yet.
+            unsafe { bindings::init_work(task.work(), Some(Self::work_fn)) };
+        }
+        for timer in &mut inner.timers {
+            timer.owner = owner;
+            // SAFETY: The timer is ours and not armed yet.
+            unsafe { bindings::timer_setup(timer.timer(), Some(Self::timer_fn), 0) };
+        }
+        // INVARIANT: The work items and timers were initialised and the owners set to the pinned
+        // address.
+        Ok(this)
+    }
+
+    /// Returns the QP context.
+    pub fn context(&self) -> &C {
+        &self.ctx
+    }
+
+    /// Records `event` in the trace of the QP, if it has one.
+    pub fn record(&self, event: QpEvent) {
+        if let Some(trace) = &self.trace {
+            trace.record(jiffies(), event);
+        }
+    }
+
+    /// Returns `true` if the QP keeps a trace.
+    pub fn is_traced(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Writes the trace of the QP, oldest event first with its time in jiffies.
+    ///
+    /// Writes nothing if the QP keeps no trace.
+    pub fn dump_trace(&self, w: &mut dyn fmt::Write) -> fmt::Result {
+        match &self.trace {
+            Some(trace) => trace.dump(w),
+            None => Ok(()),
+        }
+    }
+
+    fn task(&self, kind: TaskKind) -> &Task {
+        &self.tasks[kind as usize]
+    }
+
+    fn queue(&self, task: &Task) {
+        // SAFETY: By the type invariants the work item is initialised, and `Drop` flushes it
+        // before freeing it. `system_unbound_wq` lives forever.
+        unsafe {
+            bindings::queue_work_on(
+                bindings::WORK_CPU_UNBOUND as _,
+                bindings::system_unbound_wq,
+                task.work(),
+            )
+        };
+    }
+
+    /// Runs passes of a task whose reservation we own, requeuing it if it used up its budget.
+    fn drive(&self, task: &Task) {
+        let drive = task.state.drive(MAX_ITERATIONS, || match task.kind {
+            TaskKind::Requester => self.ctx.requester(),
+            TaskKind::Responder => self.ctx.responder(),
+            TaskKind::Completer => self.ctx.completer(),
+        });
+        if drive == Drive::Yield {
+            self.queue(task);
+        }
+    }
+
+    /// Schedules the task `kind` to run on the workqueue.
+    ///
+    /// If the task is already running, the running pass loops once more instead.
+    pub fn sched(&self, kind: TaskKind) {
+        let task = self.task(kind);
+        if task.state.reserve() {
+            self.queue(task);
+        }
+    }
+
+    /// Runs the task `kind` in the calling context, or arms it if it is already running.
+    pub fn run(&self, kind: TaskKind) {
+        let task = self.task(kind);
+        if task.state.reserve() {
+            self.drive(task);
+        }
+    }
+
+    /// Stops the task `kind` and waits for its running pass, if any.
+    ///
+    /// Requests made while disabled run once the task is enabled again. Must be called from a
+    /// context that can sleep.
+    pub fn disable(&self, kind: TaskKind) {
+        let task = self.task(kind);
+        task.state.disable();
+        Self::wait(task);
+    }
+
+    /// Re-enables the task `kind`, scheduling it if it was asked to run while disabled.
+    pub fn enable(&self, kind: TaskKind) {
+        let task = self.task(kind);
+        if task.state.enable() {
+            self.queue(task);
+        }
+    }
+
+    /// Returns `true` if the task `kind` holds its reservation, i.e. is running or queued.
+    pub fn is_busy(&self, kind: TaskKind) -> bool {
+        self.task(kind).state.is_busy()
+    }
+
+    /// (Re)starts the timer `kind` to expire in `usecs` microseconds, at least one jiffy away.
+    pub fn arm_timer(&self, kind: TimerKind, usecs: u64) {
+        let usecs = u32::try_from(usecs).unwrap_or(u32::MAX);
+        // SAFETY: FFI call without preconditions.
+        let delay = unsafe { bindings::usecs_to_jiffies(usecs) }.max(1);
+        let expires = jiffies().wrapping_add(delay as u64) as _;
+        // SAFETY: By the type invariants the timer is initialised, and `Drop` stops it before
+        // freeing it.
+        unsafe { bindings::mod_timer(self.timers[kind as usize].timer(), expires) };
+    }
+
+    /// Starts the retransmit timer for the local ACK timeout exponent `timeout`.
+    ///
+    /// A `timeout` of 0 disables the retransmit timer, which is then stopped.
+    pub fn arm_retransmit(&self, timeout: u8) {
+        match timeout::ack_timeout_usecs(timeout) {
+            Some(usecs) => self.arm_timer(TimerKind::Retransmit, usecs),
+            None => self.cancel_timer(TimerKind::Retransmit),
+        }
+    }
+
+    /// Starts the RNR NAK timer for the timer code received in an RNR NAK.
+    pub fn arm_rnr_nak(&self, code: u8) {
+        self.arm_timer(TimerKind::RnrNak, timeout::rnr_nak_usecs(code).into());
+    }
+
+    /// Stops the timer `kind` if it is pending; an expiry already running still schedules its task.
+    pub fn cancel_timer(&self, kind: TimerKind) {
+        // SAFETY: By the type invariants the timer is initialised.
+        unsafe { bindings::del_timer(self.timers[kind as usize].timer()) };
+    }
+
+    fn wait(task: &Task) {
+        // SAFETY: By the type invariants the work item is initialised.
+        unsafe { bindings::flush_work(task.work()) };
+        // Inline passes on other CPUs stop at their next iteration.
+        while task.state.is_busy() {
+            // SAFETY: FFI call without preconditions.
+            unsafe { bindings::cond_resched() };
+        }
+    }
+
+    unsafe extern "C" fn work_fn(work: *mut bindings::work_struct) {
+        // SAFETY: `work` is the first field of a `Task` inside a live `Tasks<C>`, as `Drop`
+        // flushes the work items before freeing it.
+        let task = unsafe { &*work.cast::<Task>() };
+        // SAFETY: By the type invariants `owner` points to the instance containing `task`.
+        let this = unsafe { &*task.owner.cast::<Self>() };
+        // Queued work items always carry the reservation of their task.
+        this.drive(task);
+    }
+
+    unsafe extern "C" fn timer_fn(timer: *mut bindings::timer_list) {
+        // SAFETY: `timer` is the first field of a `Timer` inside a live `Tasks<C>`, as `Drop`
+        // stops the timers before freeing it.
+        let timer = unsafe { &*timer.cast::<Timer>() };
+        // SAFETY: By the type invariants `owner` points to the instance containing `timer`.
+        let this = unsafe { &*timer.owner.cast::<Self>() };
+        this.record(timer.kind.event());
+        this.sched(timer.kind.task());
+    }
+}
+
+impl<C: QpTasks> Drop for Tasks<C> {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.state.kill();
+        }
+        for task in &self.tasks {
+            Self::wait(task);
+        }
+        // No pass can re-arm the timers anymore, and expiries only find dead tasks.
+        for timer in &self.timers {
+            // SAFETY: By the type invariants the timer is initialised.
+            unsafe { bindings::del_timer_sync(timer.timer()) };
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Data path tracepoints, the counterparts of the packet traces of the C driver.
+//!
+//! The packets are only decoded while an event is enabled; `rxe_recv_pkt` fires before the
+//! packet is handed to [`super::RxeOperation::udp_recv`], and `rxe_send_pkt` once
+//! [`super::net::transmit`] puts the headers in front of it.
+
+use super::net::SkBuff;
+use crate::rdma::hdr::Packet;
+
+crate::declare_trace! {
+    /// A packet was handed to the IP layer of interface `ifindex`.
+    pub rxe_send_pkt(ifindex: i32, qpn: u32, opcode: u8, psn: u32, len: u32)
+        => trace_rxe_send_pkt, trace_rxe_send_pkt_enabled;
+
+    /// A packet was received on interface `ifindex`.
+    pub rxe_recv_pkt(ifindex: i32, qpn: u32, opcode: u8, psn: u32, len: u32)
+        => trace_rxe_recv_pkt, trace_rxe_recv_pkt_enabled;
+}
+
+/// Fires `rxe_send_pkt` for `data`, which runs from the BTH to the ICRC.
+pub(crate) fn send_pkt(ifindex: i32, data: &[u8]) {
+    if rxe_send_pkt::enabled() {
+        if let Ok(pkt) = Packet::parse(data) {
+            let bth = pkt.bth();
+            let (qpn, psn) = (bth.dest_qp(), bth.psn().value());
+            rxe_send_pkt::emit(ifindex, qpn, bth.opcode(), psn, pkt.len() as u32);
+        }
+    }
+}
+
+/// Fires `rxe_recv_pkt` for `skb`, whose data starts at the UDP header.
+pub(crate) fn recv_pkt(skb: &SkBuff) {
+    if rxe_recv_pkt::enabled() {
+        if let Ok(pkt) = skb.roce_packet() {
+            let bth = pkt.bth();
+            let (qpn, psn) = (bth.dest_qp(), bth.psn().value());
+            rxe_recv_pkt::emit(skb.ifindex(), qpn, bth.opcode(), psn, pkt.len() as u32);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Userspace build of `kernel::rdma`.
+//!
+//! The modules are compiled straight from `rust/kernel/rdma` so that the kernel and the tests
+//! always see the same sources. Only modules that are free of `bindings` may be listed here.
+
+#![no_std]
+
+#[path = "../../kernel/rdma/atomic.rs"]
+pub mod atomic;
+#[path = "../../kernel/rdma/cm.rs"]
+pub mod cm;
+#[path = "../../kernel/rdma/cm_proxy.rs"]
+pub mod cm_proxy;
+#[path = "../../kernel/rdma/cq_mode.rs"]
+pub mod cq_mode;
+#[path = "../../kernel/rdma/crc.rs"]
+pub mod crc;
+#[path = "../../kernel/rdma/dedup.rs"]
+pub mod dedup;
+#[path = "../../kernel/rdma/dev_config.rs"]
+pub mod dev_config;
+#[path = "../../kernel/rdma/event_ring.rs"]
+pub mod event_ring;
+#[path = "../../kernel/rdma/fault.rs"]
+pub mod fault;
+#[path = "../../kernel/rdma/flap.rs"]
+pub mod flap;
+#[path = "../../kernel/rdma/fw_str.rs"]
+pub mod fw_str;
+#[path = "../../kernel/rdma/gid.rs"]
+pub mod gid;
+#[path = "../../kernel/rdma/hdr.rs"]
+pub mod hdr;
+#[path = "../../kernel/rdma/icrc.rs"]
+pub mod icrc;
+#[path = "../../kernel/rdma/id_alloc.rs"]
+pub mod id_alloc;
+#[path = "../../kernel/rdma/init_once.rs"]
+pub mod init_once;
+#[path = "../../kernel/rdma/ip_filter.rs"]
+pub mod ip_filter;
+#[path = "../../kernel/rdma/link_params.rs"]
+pub mod link_params;
+#[path = "../../kernel/rdma/mad.rs"]
+pub mod mad;
+#[path = "../../kernel/rdma/mcg.rs"]
+pub mod mcg;
+#[path = "../../kernel/rdma/mr_cache.rs"]
+pub mod mr_cache;
+#[path = "../../kernel/rdma/mr_key.rs"]
+pub mod mr_key;
+#[path = "../../kernel/rdma/mw.rs"]
+pub mod mw;
+#[path = "../../kernel/rdma/odp.rs"]
+pub mod odp;
+#[path = "../../kernel/rdma/opcode.rs"]
+pub mod opcode;
+#[path = "../../kernel/rdma/page_map.rs"]
+pub mod page_map;
+#[path = "../../kernel/rdma/page_size.rs"]
+pub mod page_size;
+#[path = "../../kernel/rdma/pool.rs"]
+pub mod pool;
+#[path = "../../kernel/rdma/port_counters.rs"]
+pub mod port_counters;
+#[path = "../../kernel/rdma/psn.rs"]
+pub mod psn;
+#[path = "../../kernel/rdma/pv_mad.rs"]
+pub mod pv_mad;
+#[path = "../../kernel/rdma/qp_fault.rs"]
+pub mod qp_fault;
+#[path = "../../kernel/rdma/qp_state.rs"]
+pub mod qp_state;
+#[path = "../../kernel/rdma/qp_trace.rs"]
+pub mod qp_trace;
+#[path = "../../kernel/rdma/queue.rs"]
+pub mod queue;
+#[path = "../../kernel/rdma/ring.rs"]
+pub mod ring;
+#[path = "../../kernel/rdma/sa.rs"]
+pub mod sa;
+#[path = "../../kernel/rdma/scrub.rs"]
+pub mod scrub;
+#[path = "../../kernel/rdma/snapshot.rs"]
+pub mod snapshot;
+#[path = "../../kernel/rdma/task_state.rs"]
+pub mod task_state;
+#[path = "../../kernel/rdma/timeout.rs"]
+pub mod timeout;
+#[path = "../../kernel/rdma/timeout_map.rs"]
+pub mod timeout_map;
+#[path = "../../kernel/rdma/tracker.rs"]
+pub mod tracker;
+#[path = "../../kernel/rdma/tunables.rs"]
+pub mod tunables;
+#[path = "../../kernel/rdma/violation.rs"]
+pub mod violation;
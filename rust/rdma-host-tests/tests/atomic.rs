@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use rdma_host_tests::atomic::{
+    build_atomic_ack, check_alignment, AtomicError, AtomicOp, AtomicReplay, AtomicRequest,
+};
+use rdma_host_tests::hdr::{PacketMut, AETH_ACK_UNLIMITED, AETH_NAK_REMOTE_ACCESS};
+use rdma_host_tests::mw::{ACCESS_REMOTE_ATOMIC, ACCESS_REMOTE_READ};
+use rdma_host_tests::opcode;
+use rdma_host_tests::page_map::PageMap;
+use rdma_host_tests::psn::Psn;
+
+#[test]
+fn compare_swap_returns_original() {
+    let target = AtomicU64::new(5);
+    let op = AtomicOp::CompareSwap {
+        compare: 5,
+        swap: 9,
+    };
+    assert_eq!(op.apply(&target), 5);
+    assert_eq!(target.load(Ordering::Relaxed), 9);
+    // A failed comparison leaves the target untouched but still reports it.
+    assert_eq!(op.apply(&target), 9);
+    assert_eq!(target.load(Ordering::Relaxed), 9);
+}
+
+#[test]
+fn fetch_add_wraps() {
+    let target = AtomicU64::new(u64::MAX);
+    assert_eq!(AtomicOp::FetchAdd { add: 2 }.apply(&target), u64::MAX);
+    assert_eq!(target.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn execute_checks_alignment() {
+    let mut buf = [0u64; 2];
+    let base = buf.as_mut_ptr().cast::<u8>();
+    let op = AtomicOp::FetchAdd { add: 1 };
+    // SAFETY: `base` points to 16 writable bytes only used by this thread.
+    unsafe {
+        assert_eq!(op.execute(base.add(8)), Ok(0));
+        assert_eq!(op.execute(base.add(4)), Err(AtomicError::Misaligned));
+    }
+    assert_eq!(buf, [0, 1]);
+    assert_eq!(check_alignment(24), Ok(()));
+    assert_eq!(check_alignment(3), Err(AtomicError::Misaligned));
+}
+
+fn request(buf: &mut [u8], opcode: u8, va: u64, swap_add: u64, compare: u64) -> AtomicRequest {
+    let mut pkt = PacketMut::init(buf, opcode, 0).unwrap();
+    let mut eth = pkt.atmeth().unwrap();
+    eth.set_va(va);
+    eth.set_rkey(0x1234);
+    eth.set_swap_add(swap_add);
+    eth.set_compare(compare);
+    AtomicRequest::parse(&pkt.into_packet()).unwrap()
+}
+
+#[test]
+fn requests_run_against_regions() {
+    let mut pkt = [0u8; 64];
+    let req = request(&mut pkt, opcode::RC_COMPARE_SWAP, 0x1008, 7, 3);
+    assert_eq!(
+        req.op,
+        AtomicOp::CompareSwap {
+            compare: 3,
+            swap: 7
+        }
+    );
+    assert_eq!(req.rkey, 0x1234);
+
+    let mut mem = [3u64; 4];
+    let blocks = [mem.as_mut_ptr() as u64];
+    let map = PageMap::new(0x1000, 32, 12, &blocks).unwrap();
+    // SAFETY: The map covers `mem`, only accessed by this thread.
+    unsafe {
+        assert_eq!(req.execute_in(&map, ACCESS_REMOTE_ATOMIC), Ok(3));
+        assert_eq!(
+            req.execute_in(&map, ACCESS_REMOTE_READ),
+            Err(AtomicError::Access)
+        );
+        let req = request(&mut pkt, opcode::RC_FETCH_ADD, 0x1018, 5, 0);
+        assert_eq!(req.execute_in(&map, ACCESS_REMOTE_ATOMIC), Ok(3));
+        let beyond = request(&mut pkt, opcode::RC_FETCH_ADD, 0x101c, 5, 0);
+        assert_eq!(
+            beyond.execute_in(&map, ACCESS_REMOTE_ATOMIC),
+            Err(AtomicError::Access)
+        );
+        let misaligned = request(&mut pkt, opcode::RC_FETCH_ADD, 0x1004, 5, 0);
+        assert_eq!(
+            misaligned.execute_in(&map, ACCESS_REMOTE_ATOMIC),
+            Err(AtomicError::Misaligned)
+        );
+    }
+    assert_eq!(mem, [3, 7, 3, 8]);
+    assert_eq!(AtomicError::Access.syndrome(), AETH_NAK_REMOTE_ACCESS);
+}
+
+#[test]
+fn atomic_ack_and_replay() {
+    let mut buf = [0xffu8; 64];
+    let pkt = build_atomic_ack(&mut buf, 0x11, Psn::new(42), 9, 0xdead_beef).unwrap();
+    let pkt = pkt.into_packet();
+    assert_eq!(pkt.bth().opcode(), opcode::RC_ATOMIC_ACKNOWLEDGE);
+    assert_eq!(pkt.bth().dest_qp(), 0x11);
+    assert_eq!(pkt.bth().psn(), Psn::new(42));
+    assert_eq!(pkt.aeth().unwrap().syndrome(), AETH_ACK_UNLIMITED);
+    assert_eq!(pkt.aeth().unwrap().msn(), 9);
+    assert_eq!(pkt.atmack().unwrap().orig(), 0xdead_beef);
+
+    let mut replay = AtomicReplay::<2>::new();
+    replay.remember(Psn::new(1), 10);
+    replay.remember(Psn::new(2), 20);
+    assert_eq!(replay.lookup(Psn::new(1)), Some(10));
+    replay.remember(Psn::new(3), 30);
+    assert_eq!(replay.lookup(Psn::new(1)), None);
+    assert_eq!(replay.lookup(Psn::new(3)), Some(30));
+    replay.clear();
+    assert_eq!(replay.lookup(Psn::new(2)), None);
+}
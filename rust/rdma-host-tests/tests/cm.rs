@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::cm::{
+    self, attr, cm_timeout, cm_timeout_ns, CmMessage, PrivateDataTooLong, RejReason,
+    MAX_CM_TIMEOUT,
+};
+use rdma_host_tests::gid::Gid;
+use rdma_host_tests::mad::{class, MadHdr, MAD_SIZE};
+
+fn cm_mad(attr_id: u16) -> [u8; MAD_SIZE] {
+    let mut mad = [0; MAD_SIZE];
+    MadHdr::new(class::CM, 2, 0x03, 7)
+        .with_attr(attr_id, 0)
+        .write(&mut mad);
+    mad
+}
+
+#[test]
+fn private_data_fits_its_message() {
+    assert_eq!(CmMessage::Req.check_private_data(&[0; 92]), Ok(92));
+    assert_eq!(CmMessage::Rtu.check_private_data(&[]), Ok(0));
+    assert_eq!(
+        CmMessage::Req.check_private_data(&[0; 93]),
+        Err(PrivateDataTooLong {
+            message: CmMessage::Req,
+            len: 93
+        })
+    );
+    // The largest areas still fit the `u8` length of the verbs.
+    assert_eq!(CmMessage::Drep.check_private_data(&[0; 224]), Ok(224));
+}
+
+#[test]
+fn rej_reasons_round_trip() {
+    for code in 0..64 {
+        assert_eq!(RejReason::from_code(code).code(), code);
+    }
+    assert_eq!(RejReason::from_code(8), RejReason::InvalidServiceId);
+    assert_eq!(RejReason::from_code(2), RejReason::Other(2));
+    assert_eq!(RejReason::ConsumerDefined.code(), 28);
+}
+
+#[test]
+fn timeouts_round_up() {
+    assert_eq!(cm_timeout_ns(0), 4096);
+    assert_eq!(cm_timeout(0), 0);
+    // 4.096 µs * 2^8 = 1.048576 ms.
+    assert_eq!(cm_timeout(1), 8);
+    assert_eq!(cm_timeout(2), 9);
+    assert!(cm_timeout_ns(cm_timeout(1000)) >= 1_000_000_000);
+    assert!(cm_timeout_ns(cm_timeout(1000) - 1) < 1_000_000_000);
+    assert_eq!(cm_timeout(u32::MAX), MAX_CM_TIMEOUT);
+    assert_eq!(cm_timeout_ns(40), cm_timeout_ns(MAX_CM_TIMEOUT));
+}
+
+#[test]
+fn comm_ids_sit_where_the_message_puts_them() {
+    let mut rep = cm_mad(attr::REP);
+    rep[24..28].copy_from_slice(&0x11u32.to_be_bytes());
+    rep[28..32].copy_from_slice(&0x22u32.to_be_bytes());
+    assert_eq!(cm::local_comm_id(&rep), Some(0x11));
+    assert_eq!(cm::remote_comm_id(&rep), Some(0x22));
+    assert!(cm::set_remote_comm_id(&mut rep, 0x33));
+    assert_eq!(&rep[28..32], &0x33u32.to_be_bytes());
+
+    // A REQ has no remote ID yet, and the SIDR messages only carry the request ID.
+    let mut req = cm_mad(attr::REQ);
+    assert_eq!(cm::remote_comm_id(&req), None);
+    assert!(!cm::set_remote_comm_id(&mut req, 1));
+    let mut sidr_rep = cm_mad(attr::SIDR_REP);
+    sidr_rep[24..28].copy_from_slice(&0x44u32.to_be_bytes());
+    assert_eq!(cm::local_comm_id(&sidr_rep), None);
+    assert_eq!(cm::remote_comm_id(&sidr_rep), Some(0x44));
+    assert_eq!(cm::local_comm_id(&cm_mad(attr::SIDR_REQ)), Some(0));
+    assert_eq!(cm::remote_comm_id(&cm_mad(attr::SIDR_REQ)), None);
+}
+
+#[test]
+fn other_mads_have_no_comm_ids() {
+    let mut sa = cm_mad(attr::REP);
+    sa[1] = class::SUBN_ADM;
+    assert_eq!(cm::cm_attr(&sa), None);
+    assert_eq!(cm::local_comm_id(&sa), None);
+    assert!(!cm::set_local_comm_id(&mut sa, 1));
+    // Truncated MADs are refused rather than read out of bounds.
+    assert_eq!(cm::local_comm_id(&cm_mad(attr::REP)[..100]), None);
+    assert_eq!(cm::local_comm_id(&cm_mad(0x99)), None);
+}
+
+#[test]
+fn rej_reason_and_req_gid() {
+    let mut rej = cm_mad(attr::REJ);
+    rej[34..36].copy_from_slice(&4u16.to_be_bytes());
+    assert_eq!(cm::rej_reason(&rej), Some(RejReason::Timeout));
+    assert_eq!(cm::rej_reason(&cm_mad(attr::REP)), None);
+
+    let mut req = cm_mad(attr::REQ);
+    let gid = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+    req[96..112].copy_from_slice(&gid);
+    assert_eq!(cm::req_remote_gid(&req), Some(Gid::from_raw(gid)));
+    assert_eq!(cm::req_remote_gid(&rej), None);
+}
@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::cm::{self, attr};
+use rdma_host_tests::cm_proxy::{ProxyError, PvCmIds};
+use rdma_host_tests::gid::Gid;
+use rdma_host_tests::mad::{class, MadHdr, MAD_SIZE};
+
+fn cm_mad(attr_id: u16, local: u32, remote: u32) -> [u8; MAD_SIZE] {
+    let mut mad = [0; MAD_SIZE];
+    MadHdr::new(class::CM, 2, 0x03, 7)
+        .with_attr(attr_id, 0)
+        .write(&mut mad);
+    mad[24..28].copy_from_slice(&local.to_be_bytes());
+    mad[28..32].copy_from_slice(&remote.to_be_bytes());
+    mad
+}
+
+#[test]
+fn slaves_get_distinct_ids_for_the_same_slave_id() {
+    let mut ids = PvCmIds::<u8, 4>::new();
+    let mut a = cm_mad(attr::REQ, 5, 0);
+    let mut b = cm_mad(attr::REQ, 5, 0);
+    assert_eq!(ids.multiplex(1, &mut a, 100), Ok(()));
+    assert_eq!(ids.multiplex(2, &mut b, 100), Ok(()));
+    let (pv_a, pv_b) = (cm::local_comm_id(&a).unwrap(), cm::local_comm_id(&b).unwrap());
+    assert_ne!(pv_a, pv_b);
+    assert_eq!(ids.lookup(pv_a), Some((1, 5)));
+    assert_eq!(ids.lookup(pv_b), Some((2, 5)));
+    // A retransmitted REQ keeps its ID.
+    let mut again = cm_mad(attr::REQ, 5, 0);
+    assert_eq!(ids.multiplex(1, &mut again, 100), Ok(()));
+    assert_eq!(cm::local_comm_id(&again), Some(pv_a));
+    assert_eq!(ids.len(), 2);
+}
+
+#[test]
+fn replies_go_back_to_their_slave() {
+    let mut ids = PvCmIds::<u8, 4>::new();
+    let mut req = cm_mad(attr::REQ, 9, 0);
+    ids.multiplex(3, &mut req, 100).unwrap();
+    let pv = cm::local_comm_id(&req).unwrap();
+
+    let mut rep = cm_mad(attr::REP, 0x77, pv);
+    assert_eq!(ids.demux(&mut rep, 100, |_| None), Ok(3));
+    assert_eq!(cm::remote_comm_id(&rep), Some(9));
+    assert_eq!(cm::local_comm_id(&rep), Some(0x77));
+
+    let mut stale = cm_mad(attr::REP, 0x77, pv + 1);
+    assert_eq!(
+        ids.demux(&mut stale, 100, |_| None),
+        Err(ProxyError::UnknownId)
+    );
+}
+
+#[test]
+fn requests_are_routed_by_gid() {
+    let mut ids = PvCmIds::<u8, 4>::new();
+    let gid = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 6];
+    let mut req = cm_mad(attr::REQ, 1, 0);
+    req[96..112].copy_from_slice(&gid);
+    let slave_of = |g: Gid| (g.interface_id() == 6).then_some(6u8);
+    assert_eq!(ids.demux(&mut req.clone(), 100, slave_of), Ok(6));
+    req[111] = 7;
+    assert_eq!(ids.demux(&mut req, 100, slave_of), Err(ProxyError::NoSlave));
+    let mut sidr = cm_mad(attr::SIDR_REQ, 1, 0);
+    assert_eq!(
+        ids.demux(&mut sidr, 100, |_| Some(1)),
+        Err(ProxyError::NoSlave)
+    );
+    // Requests for the slaves allocate nothing until they answer.
+    assert!(ids.is_empty());
+}
+
+#[test]
+fn unknown_connections_and_pass_through_messages() {
+    let mut ids = PvCmIds::<u8, 4>::new();
+    let mut rtu = cm_mad(attr::RTU, 4, 1);
+    assert_eq!(ids.multiplex(1, &mut rtu, 100), Err(ProxyError::UnknownId));
+    // Plain REJs and SIDR_REPs are forwarded untouched.
+    let mut rej = cm_mad(attr::REJ, 4, 1);
+    let before = rej;
+    assert_eq!(ids.multiplex(1, &mut rej, 100), Ok(()));
+    assert_eq!(rej, before);
+    // A timeout REJ may be the first message of the slave on a connection.
+    rej[34..36].copy_from_slice(&4u16.to_be_bytes());
+    assert_eq!(ids.multiplex(1, &mut rej, 100), Ok(()));
+    assert_eq!(ids.len(), 1);
+    let mut sa = cm_mad(attr::REP, 4, 1);
+    sa[1] = class::SUBN_ADM;
+    assert_eq!(ids.multiplex(1, &mut sa, 100), Err(ProxyError::NotCm));
+}
+
+#[test]
+fn disconnected_ids_expire() {
+    let mut ids = PvCmIds::<u8, 4>::new();
+    let mut req = cm_mad(attr::REQ, 2, 0);
+    ids.multiplex(1, &mut req, 0).unwrap();
+    let pv = cm::local_comm_id(&req).unwrap();
+    assert_eq!(ids.next_deadline(0), None);
+
+    let mut dreq = cm_mad(attr::DREQ, 2, 0x50);
+    ids.multiplex(1, &mut dreq, 1000).unwrap();
+    assert_eq!(cm::local_comm_id(&dreq), Some(pv));
+    assert_eq!(ids.next_deadline(10), Some(1000));
+    // Late MADs still find the connection until the deadline.
+    assert_eq!(ids.expire(999), 0);
+    let mut drep = cm_mad(attr::DREP, 0x50, pv);
+    assert_eq!(ids.demux(&mut drep, 2000, |_| None), Ok(1));
+    assert_eq!(ids.expire(1000), 1);
+    assert!(ids.is_empty());
+
+    // The peer disconnecting schedules the ID as well.
+    let mut req = cm_mad(attr::REQ, 3, 0);
+    ids.multiplex(2, &mut req, 0).unwrap();
+    let pv = cm::local_comm_id(&req).unwrap();
+    let mut dreq = cm_mad(attr::DREQ, 0x60, pv);
+    assert_eq!(ids.demux(&mut dreq, 500, |_| None), Ok(2));
+    assert_eq!(ids.next_deadline(0), Some(500));
+}
+
+#[test]
+fn ids_are_recycled_cyclically_and_bounded() {
+    let mut ids = PvCmIds::<u8, 2>::new();
+    assert_eq!(ids.get_or_alloc(1, 10), Ok(1));
+    assert_eq!(ids.get_or_alloc(1, 11), Ok(2));
+    assert_eq!(ids.get_or_alloc(1, 12), Err(ProxyError::Full));
+    ids.remove_slave(1);
+    assert!(ids.is_empty());
+    // Freed IDs are not reused right away.
+    assert_eq!(ids.get_or_alloc(2, 10), Ok(3));
+    ids.clear();
+    assert!(ids.is_empty());
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::cq_mode::{CqMode, CqModeState};
+
+#[test]
+fn poll_mode_suppresses_notifications() {
+    let state = CqModeState::new();
+    assert_eq!(state.mode(), CqMode::Event);
+    assert!(!state.suppress());
+
+    state.set_mode(CqMode::Poll);
+    assert!(state.suppress());
+    assert!(state.suppress());
+    assert_eq!(state.suppressed(), 2);
+
+    state.set_mode(CqMode::Event);
+    assert!(!state.suppress());
+    assert_eq!(state.suppressed(), 2);
+}
+
+#[test]
+fn budget_caps_polls() {
+    let state = CqModeState::new();
+    assert_eq!(state.entries(64), 64);
+    state.set_budget(16);
+    assert_eq!(state.budget(), 16);
+    assert_eq!(state.entries(64), 16);
+    assert_eq!(state.entries(4), 4);
+    state.set_budget(0);
+    assert_eq!(state.entries(64), 64);
+}
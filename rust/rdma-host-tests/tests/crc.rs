@@ -0,0 +1,15 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::crc::crc32_le;
+
+#[test]
+fn matches_ieee_check_value() {
+    assert_eq!(!crc32_le(!0, b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn incremental_update() {
+    let whole = crc32_le(!0, b"hello world");
+    let split = crc32_le(crc32_le(!0, b"hello "), b"world");
+    assert_eq!(whole, split);
+}
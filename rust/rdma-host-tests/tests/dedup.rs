@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::dedup::{DupFilter, DupKey, DupStats};
+use rdma_host_tests::hdr::PacketMut;
+use rdma_host_tests::opcode;
+use rdma_host_tests::psn::Psn;
+
+fn key(qpn: u32, psn: u32) -> DupKey {
+    DupKey {
+        qpn,
+        psn: Psn::new(psn),
+        icrc: [1, 2, 3, 4],
+    }
+}
+
+#[test]
+fn copies_within_window_are_flagged() {
+    let filter = DupFilter::<64>::new(2);
+    assert!(!filter.check(&key(17, 100), 10));
+    assert!(filter.check(&key(17, 100), 11));
+    assert!(!filter.check(&key(17, 101), 11));
+    assert!(!filter.check(&key(18, 100), 11));
+    // A retransmission after the window is delivered.
+    assert!(!filter.check(&key(17, 100), 20));
+    // Ticks wrap around.
+    assert!(!filter.check(&key(19, 5), u32::MAX));
+    assert!(filter.check(&key(19, 5), 1));
+    assert_eq!(
+        filter.stats(),
+        DupStats {
+            checked: 7,
+            duplicates: 2,
+        }
+    );
+
+    filter.clear();
+    assert!(!filter.check(&key(17, 101), 11));
+    assert_eq!(filter.stats().duplicates, 2);
+}
+
+#[test]
+fn key_of_packet() {
+    let mut buf = [0u8; 64];
+    let mut pkt = PacketMut::init(&mut buf, opcode::RC_SEND_ONLY, 4).unwrap();
+    pkt.bth().set_dest_qp(0x12);
+    pkt.bth().set_psn(Psn::new(7));
+    pkt.set_icrc([9, 8, 7, 6]);
+    let pkt = pkt.into_packet();
+
+    let key = DupKey::of(&pkt);
+    assert_eq!(key.qpn, 0x12);
+    assert_eq!(key.psn, Psn::new(7));
+    assert_eq!(key.icrc, [9, 8, 7, 6]);
+    let other = DupKey {
+        icrc: [0; 4],
+        ..key
+    };
+
+    let filter = DupFilter::<8>::new(0);
+    assert!(!filter.check(&key, 5));
+    assert!(filter.check(&key, 5));
+    assert!(!filter.check(&other, 5));
+}
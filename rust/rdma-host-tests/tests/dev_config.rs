@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::dev_config::{ConfigKey, CrcMode, DeviceConfig};
+use rdma_host_tests::tunables::TuneError;
+
+#[test]
+fn updates_build_new_snapshots() {
+    let config = DeviceConfig::default();
+    let updated = config
+        .with(ConfigKey::Mtu, "1024\n")
+        .and_then(|c| c.with(ConfigKey::CrcMode, "generate"))
+        .and_then(|c| c.with(ConfigKey::PacingRate, "50000"))
+        .unwrap();
+    // The original is left alone.
+    assert_eq!(config, DeviceConfig::DEFAULT);
+    assert_eq!(
+        updated,
+        DeviceConfig {
+            mtu_override: 1024,
+            crc_mode: CrcMode::GenerateOnly,
+            pacing_rate: 50000,
+        }
+    );
+    assert!(updated.crc_mode.generates() && !updated.crc_mode.checks());
+    assert_eq!(updated.path_mtu(4096), 1024);
+    assert_eq!(updated.path_mtu(512), 512);
+    assert_eq!(config.path_mtu(4096), 4096);
+
+    assert_eq!(
+        config.with(ConfigKey::Mtu, "1000"),
+        Err(TuneError::OutOfRange)
+    );
+    assert_eq!(
+        config.with(ConfigKey::Mtu, "8192"),
+        Err(TuneError::OutOfRange)
+    );
+    assert_eq!(
+        config.with(ConfigKey::CrcMode, "fast"),
+        Err(TuneError::Parse)
+    );
+    assert_eq!(
+        config.with(ConfigKey::PacingRate, "-1"),
+        Err(TuneError::Parse)
+    );
+    assert_eq!(config.with(ConfigKey::Mtu, "0"), Ok(config));
+}
+
+#[test]
+fn values_are_shown_like_sysfs() {
+    let config = DeviceConfig::default()
+        .with(ConfigKey::CrcMode, "off")
+        .unwrap();
+    let mut out = String::new();
+    for key in ConfigKey::ALL {
+        assert_eq!(ConfigKey::from_name(key.name()), Some(key));
+        config.show(key, &mut out).unwrap();
+    }
+    assert_eq!(out, "0\ncheck generate [off]\n0\n");
+    assert_eq!(ConfigKey::from_name("mtu"), None);
+}
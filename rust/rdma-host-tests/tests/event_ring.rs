@@ -0,0 +1,83 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::event_ring::EventRing;
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn fifo_order() {
+    let ring = EventRing::<u32, 4>::new();
+    assert!(ring.is_empty());
+    for i in 0..3 {
+        ring.push(i).unwrap();
+    }
+    assert_eq!(ring.pop(), Some(0));
+    ring.push(3).unwrap();
+    ring.push(4).unwrap();
+    assert_eq!(
+        (1..5).map(|_| ring.pop().unwrap()).collect::<Vec<_>>(),
+        [1, 2, 3, 4]
+    );
+    assert_eq!(ring.pop(), None);
+}
+
+#[test]
+fn full_ring_drops() {
+    let ring = EventRing::<u8, 2>::new();
+    ring.push(1).unwrap();
+    ring.push(2).unwrap();
+    assert_eq!(ring.push(3), Err(3));
+    assert_eq!(ring.dropped(), 1);
+    assert_eq!(ring.pop(), Some(1));
+    ring.push(3).unwrap();
+    assert_eq!(ring.dropped(), 1);
+}
+
+#[test]
+fn wraps_many_laps() {
+    let ring = EventRing::<usize, 8>::new();
+    for i in 0..1000 {
+        ring.push(i).unwrap();
+        assert_eq!(ring.pop(), Some(i));
+    }
+    assert!(ring.is_empty());
+}
+
+#[test]
+fn concurrent_producers() {
+    const PER_THREAD: u64 = 10_000;
+    let ring = Arc::new(EventRing::<u64, 64>::new());
+    let producers: Vec<_> = (0..4u64)
+        .map(|t| {
+            let ring = ring.clone();
+            thread::spawn(move || {
+                for i in 0..PER_THREAD {
+                    let mut event = t << 32 | i;
+                    while let Err(e) = ring.push(event) {
+                        event = e;
+                        thread::yield_now();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let mut next = [0u64; 4];
+    let mut received = 0;
+    while received < 4 * PER_THREAD {
+        match ring.pop() {
+            Some(event) => {
+                let (t, i) = ((event >> 32) as usize, event & 0xffff_ffff);
+                // Events of one producer come out in the order it pushed them.
+                assert_eq!(i, next[t]);
+                next[t] += 1;
+                received += 1;
+            }
+            None => thread::yield_now(),
+        }
+    }
+    for p in producers {
+        p.join().unwrap();
+    }
+    assert_eq!(next, [PER_THREAD; 4]);
+}
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::fault::{FaultInjector, FaultKnob, TxFault, MAX_DELAY_US, PPM};
+use rdma_host_tests::tunables::TuneError;
+
+#[test]
+fn inactive_by_default() {
+    let faults = FaultInjector::new();
+    assert!(!faults.is_active());
+    assert!((0..1000).all(|r| faults.decide(r * 4099) == TxFault::Pass));
+    // A delay fraction without a delay does nothing.
+    faults.set(FaultKnob::DelayPpm, PPM).unwrap();
+    assert!(!faults.is_active());
+    assert_eq!(faults.decide(0), TxFault::Pass);
+}
+
+#[test]
+fn fractions_are_honoured() {
+    let faults = FaultInjector::new();
+    faults.store(FaultKnob::DropPpm, "100000\n").unwrap();
+    faults.set(FaultKnob::DelayPpm, 200_000).unwrap();
+    faults.set(FaultKnob::DelayUs, 500).unwrap();
+    assert!(faults.is_active());
+
+    assert_eq!(faults.decide(99_999), TxFault::Drop);
+    assert_eq!(faults.decide(100_000), TxFault::Delay(500));
+    assert_eq!(faults.decide(299_999), TxFault::Delay(500));
+    assert_eq!(faults.decide(300_000), TxFault::Pass);
+    assert_eq!(faults.decide(PPM + 5), TxFault::Drop);
+
+    for r in 0..PPM / 100 {
+        faults.decide(r * 100);
+    }
+    assert_eq!(faults.dropped(), 2 + 1000);
+    assert_eq!(faults.delayed(), 2 + 2000);
+
+    let mut out = String::new();
+    faults.show(FaultKnob::DelayUs, &mut out).unwrap();
+    assert_eq!(out, "500\n");
+}
+
+#[test]
+fn knobs_are_bounded() {
+    let faults = FaultInjector::new();
+    faults.set(FaultKnob::DropPpm, 600_000).unwrap();
+    assert_eq!(
+        faults.set(FaultKnob::DelayPpm, 500_000),
+        Err(TuneError::OutOfRange)
+    );
+    faults.set(FaultKnob::DelayPpm, 400_000).unwrap();
+    assert_eq!(
+        faults.set(FaultKnob::DelayUs, MAX_DELAY_US + 1),
+        Err(TuneError::OutOfRange)
+    );
+    assert_eq!(
+        faults.store(FaultKnob::DelayUs, "soon"),
+        Err(TuneError::Parse)
+    );
+    assert_eq!(
+        FaultKnob::from_name("fault_drop_ppm"),
+        Some(FaultKnob::DropPpm)
+    );
+}
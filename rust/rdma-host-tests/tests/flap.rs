@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::flap::{FlapDebouncer, FlapStats, FlapVerdict, LinkEvent};
+
+#[test]
+fn bouncing_link_settles_before_being_reported() {
+    let mut flaps = FlapDebouncer::<4>::new(10);
+    let mut reported = [(0, LinkEvent::Up); 4];
+    let mut n = 0;
+
+    assert_eq!(
+        flaps.event(3, LinkEvent::Down, 100),
+        FlapVerdict::Deferred(110)
+    );
+    // Every event restarts the holdoff.
+    assert_eq!(
+        flaps.event(3, LinkEvent::Up, 105),
+        FlapVerdict::Deferred(115)
+    );
+    assert_eq!(
+        flaps.event(3, LinkEvent::Down, 108),
+        FlapVerdict::Deferred(118)
+    );
+    assert_eq!(flaps.next_deadline(108), Some(118));
+    flaps.expire(117, |i, e| {
+        reported[n] = (i, e);
+        n += 1;
+    });
+    assert_eq!(n, 0);
+    assert!(flaps.is_pending(3));
+
+    flaps.expire(118, |i, e| {
+        reported[n] = (i, e);
+        n += 1;
+    });
+    assert_eq!(&reported[..n], &[(3, LinkEvent::Down)]);
+    assert!(!flaps.is_pending(3));
+    assert_eq!(flaps.event(3, LinkEvent::Down, 120), FlapVerdict::Ignored);
+
+    // Down and up again within the holdoff: nothing to report.
+    flaps.event(3, LinkEvent::Up, 130);
+    flaps.event(3, LinkEvent::Down, 132);
+    flaps.expire(200, |i, e| {
+        reported[n] = (i, e);
+        n += 1;
+    });
+    assert_eq!(n, 1);
+    assert_eq!(flaps.next_deadline(200), None);
+    assert_eq!(
+        flaps.stats(),
+        FlapStats {
+            events: 6,
+            propagated: 1,
+        }
+    );
+    assert_eq!(flaps.stats().absorbed(), 5);
+}
+
+#[test]
+fn untracked_links_propagate_right_away() {
+    let mut off = FlapDebouncer::<2>::new(0);
+    assert_eq!(
+        off.event(1, LinkEvent::Down, 0),
+        FlapVerdict::Propagate(LinkEvent::Down)
+    );
+
+    // A full table does not lose events, and forgetting a link frees its slot.
+    let mut flaps = FlapDebouncer::<2>::new(5);
+    flaps.event(1, LinkEvent::Down, u64::MAX - 1);
+    flaps.event(2, LinkEvent::Down, u64::MAX - 1);
+    assert_eq!(
+        flaps.event(3, LinkEvent::Up, u64::MAX),
+        FlapVerdict::Propagate(LinkEvent::Up)
+    );
+    assert_eq!(flaps.next_deadline(u64::MAX), Some(3));
+    flaps.forget(2);
+    assert!(!flaps.is_pending(2));
+    assert_eq!(flaps.event(3, LinkEvent::Up, 0), FlapVerdict::Deferred(5));
+
+    // Deadlines wrap with the clock.
+    let mut seen = 0;
+    flaps.expire(3, |i, e| {
+        assert_eq!((i, e), (1, LinkEvent::Down));
+        seen += 1;
+    });
+    assert_eq!(seen, 1);
+    assert_eq!(flaps.next_deadline(3), Some(5));
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use core::fmt::Write;
+use rdma_host_tests::fw_str::FwStr;
+
+#[test]
+fn nul_terminated() {
+    let mut buf = [0xffu8; 16];
+    let mut fw = FwStr::new(&mut buf).unwrap();
+    write!(fw, "{}.{}.{}", 2, 42, 5000).unwrap();
+    assert_eq!(fw.as_str(), "2.42.5000");
+    assert!(!fw.is_truncated());
+    assert_eq!(&buf[..10], b"2.42.5000\0");
+}
+
+#[test]
+fn truncates_like_snprintf() {
+    let mut buf = [0u8; 8];
+    let mut fw = FwStr::new(&mut buf).unwrap();
+    assert_eq!(fw.capacity(), 7);
+    let version = "1.0";
+    write!(fw, "rust_rxe {version}").unwrap();
+    assert_eq!(fw.as_str(), "rust_rx");
+    assert!(fw.is_truncated());
+    assert_eq!(buf[7], 0);
+}
+
+#[test]
+fn keeps_whole_characters() {
+    let mut buf = [0u8; 5];
+    let mut fw = FwStr::new(&mut buf).unwrap();
+    fw.write_str("ab\u{e9}\u{e9}").unwrap();
+    assert_eq!(fw.as_str(), "ab\u{e9}");
+    assert!(FwStr::new(&mut []).is_none());
+}
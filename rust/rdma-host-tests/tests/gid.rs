@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::gid::{eui64, Gid};
+use rdma_host_tests::ip_filter::PeerAddr;
+
+#[test]
+fn ipv4_gids_are_mapped() {
+    let gid = Gid::from_ipv4([192, 168, 1, 2]);
+    assert!(gid.is_v4_mapped());
+    assert_eq!(gid.to_addr(), PeerAddr::from_v4([192, 168, 1, 2]));
+    assert_eq!("192.168.1.2".parse::<Gid>().unwrap(), gid);
+    assert_eq!(gid.to_string(), "0000:0000:0000:0000:0000:ffff:c0a8:0102");
+}
+
+#[test]
+fn ipv6_gids_are_verbatim() {
+    let gid: Gid = "fe80::1".parse().unwrap();
+    assert!(!gid.is_v4_mapped());
+    assert_eq!(gid.subnet_prefix(), 0xfe80_0000_0000_0000);
+    assert_eq!(gid.interface_id(), 1);
+}
+
+#[test]
+fn link_local_from_mac() {
+    let mac = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+    assert_eq!(eui64(mac), [0x50, 0x54, 0x00, 0xff, 0xfe, 0x12, 0x34, 0x56]);
+    let gid = Gid::link_local(mac);
+    assert_eq!(gid.to_string(), "fe80:0000:0000:0000:5054:00ff:fe12:3456");
+    assert!(!gid.is_zero());
+    assert!(Gid::ZERO.is_zero());
+}
+
+#[test]
+fn multicast_gids_map_to_ethernet() {
+    let v4 = Gid::from_ipv4([239, 129, 2, 3]);
+    assert!(v4.is_multicast());
+    assert_eq!(v4.multicast_mac(), Some([0x01, 0x00, 0x5e, 0x01, 0x02, 0x03]));
+    let v6: Gid = "ff12::8000:1:2".parse().unwrap();
+    assert!(v6.is_multicast());
+    assert_eq!(v6.multicast_mac(), Some([0x33, 0x33, 0x00, 0x01, 0x00, 0x02]));
+    for unicast in ["192.168.1.2", "fe80::1", "::ffff:0:0"] {
+        let gid: Gid = unicast.parse().unwrap();
+        assert!(!gid.is_multicast());
+        assert_eq!(gid.multicast_mac(), None);
+    }
+}
@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::hdr::{Aeth, Bth, Packet, PacketMut, ParseError, ICRC_SIZE};
+use rdma_host_tests::opcode;
+use rdma_host_tests::psn::Psn;
+
+#[test]
+fn bth_fields() {
+    let mut buf = [0u8; 12];
+    let mut bth = Bth::new(&mut buf[..]).unwrap();
+    bth.set_opcode(opcode::RC_SEND_ONLY);
+    bth.set_solicited(true);
+    bth.set_pad(3);
+    bth.set_pkey(0xffff);
+    bth.set_becn(true);
+    bth.set_dest_qp(0x1234_5678);
+    bth.set_ack_req(true);
+    bth.set_psn(Psn::new(0xab_cdef));
+    assert_eq!(
+        buf,
+        [0x04, 0xb0, 0xff, 0xff, 0x40, 0x34, 0x56, 0x78, 0x80, 0xab, 0xcd, 0xef]
+    );
+
+    let bth = Bth::new(&buf[..]).unwrap();
+    assert!(bth.solicited() && !bth.migreq() && !bth.fecn() && bth.becn() && bth.ack_req());
+    assert_eq!((bth.pad(), bth.tver(), bth.pkey()), (3, 0, 0xffff));
+    assert_eq!((bth.dest_qp(), bth.psn()), (0x34_5678, Psn::new(0xab_cdef)));
+    assert!(Bth::new(&buf[..11]).is_none());
+}
+
+#[test]
+fn aeth_keeps_syndrome() {
+    let mut buf = [0u8; 4];
+    let mut aeth = Aeth::new(&mut buf[..]).unwrap();
+    aeth.set_syndrome(0x1f);
+    aeth.set_msn(0x100_0001);
+    assert_eq!((aeth.syndrome(), aeth.msn()), (0x1f, 1));
+}
+
+#[test]
+fn write_first_round_trip() {
+    let mut buf = [0xaau8; 64];
+    let mut pkt = PacketMut::init(&mut buf, opcode::RC_RDMA_WRITE_FIRST, 6).unwrap();
+    assert_eq!(pkt.len(), 12 + 16 + 6 + 2 + ICRC_SIZE);
+    pkt.bth().set_dest_qp(17);
+    let mut reth = pkt.reth().unwrap();
+    reth.set_va(0x1000);
+    reth.set_rkey(0x42);
+    reth.set_len(4096);
+    assert!(pkt.aeth().is_none());
+    pkt.payload_mut().copy_from_slice(b"abcdef");
+    pkt.set_icrc([1, 2, 3, 4]);
+    let len = pkt.len();
+
+    let pkt = Packet::parse(&buf[..len]).unwrap();
+    assert_eq!(pkt.info().name, "IB_OPCODE_RC_RDMA_WRITE_FIRST");
+    assert_eq!((pkt.bth().dest_qp(), pkt.bth().pad()), (17, 2));
+    let reth = pkt.reth().unwrap();
+    assert_eq!((reth.va(), reth.rkey(), reth.len()), (0x1000, 0x42, 4096));
+    assert_eq!(pkt.payload(), b"abcdef");
+    assert_eq!(pkt.icrc(), [1, 2, 3, 4]);
+    assert_eq!(pkt.check_mtu(6), Ok(()));
+    assert_eq!(pkt.check_mtu(256), Err(ParseError::PayloadLength));
+}
+
+#[test]
+fn ud_send_with_immediate() {
+    let mut buf = [0u8; 64];
+    let mut pkt = PacketMut::init(&mut buf, opcode::UD_SEND_ONLY_WITH_IMMEDIATE, 4).unwrap();
+    let mut deth = pkt.deth().unwrap();
+    deth.set_qkey(0x8001_0000);
+    deth.set_src_qp(0xff_0005);
+    pkt.immdt().unwrap().set_imm(u32::from_be(7));
+    let pkt = pkt.into_packet();
+
+    assert_eq!(pkt.deth().unwrap().qkey(), 0x8001_0000);
+    assert_eq!(pkt.deth().unwrap().src_qp(), 0xff_0005);
+    assert_eq!(u32::from_be(pkt.immdt().unwrap().imm()), 7);
+    assert_eq!(pkt.payload().len(), 4);
+    assert_eq!(pkt.check_mtu(1024), Ok(()));
+}
+
+#[test]
+fn atomic_request() {
+    let mut buf = [0u8; 64];
+    let mut pkt = PacketMut::init(&mut buf, opcode::RC_COMPARE_SWAP, 0).unwrap();
+    let mut atmeth = pkt.atmeth().unwrap();
+    atmeth.set_va(0x2000);
+    atmeth.set_rkey(9);
+    atmeth.set_swap_add(u64::MAX);
+    atmeth.set_compare(1);
+    let pkt = pkt.into_packet();
+
+    assert_eq!(pkt.len(), 12 + 28 + ICRC_SIZE);
+    let atmeth = pkt.atmeth().unwrap();
+    assert_eq!((atmeth.va(), atmeth.rkey()), (0x2000, 9));
+    assert_eq!((atmeth.swap_add(), atmeth.compare()), (u64::MAX, 1));
+    assert!(pkt.payload().is_empty());
+    assert_eq!(
+        PacketMut::init(&mut [0u8; 64], opcode::RC_FETCH_ADD, 8).unwrap_err(),
+        ParseError::UnexpectedPayload
+    );
+}
+
+#[test]
+fn malformed_packets() {
+    assert_eq!(Packet::parse(&[0; 8]).unwrap_err(), ParseError::Truncated);
+    assert_eq!(
+        Packet::parse(&[0x1f; 16]).unwrap_err(),
+        ParseError::UnknownOpcode(0x1f)
+    );
+
+    let mut ack = [0u8; 12 + 4 + ICRC_SIZE];
+    PacketMut::init(&mut ack, opcode::RC_ACKNOWLEDGE, 0).unwrap();
+    assert!(Packet::parse(&ack).is_ok());
+    // Headers cut short.
+    assert_eq!(
+        Packet::parse(&ack[..ack.len() - 1]).unwrap_err(),
+        ParseError::Truncated
+    );
+    // ACKs carry no payload.
+    let mut long = [0u8; 12 + 4 + 4 + ICRC_SIZE];
+    long[..ack.len()].copy_from_slice(&ack);
+    assert_eq!(
+        Packet::parse(&long).unwrap_err(),
+        ParseError::UnexpectedPayload
+    );
+    ack[1] = 0x01;
+    assert_eq!(Packet::parse(&ack).unwrap_err(), ParseError::BadVersion(1));
+
+    // A middle packet must fill the MTU.
+    let mut mid = [0u8; 12 + 256 + ICRC_SIZE];
+    let mid = PacketMut::init(&mut mid, opcode::RC_SEND_MIDDLE, 256)
+        .unwrap()
+        .into_packet();
+    assert_eq!(mid.check_mtu(256), Ok(()));
+    assert_eq!(mid.check_mtu(512), Err(ParseError::PayloadLength));
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::crc::crc32_le;
+use rdma_host_tests::hdr::PacketMut;
+use rdma_host_tests::icrc::{self, IcrcError, SEED};
+use rdma_host_tests::opcode;
+
+fn ipv4_udp() -> [u8; 28] {
+    let mut hdr = [0u8; 28];
+    hdr[0] = 0x45;
+    hdr[1] = 0x02;
+    hdr[8] = 64;
+    hdr[10..12].copy_from_slice(&[0x12, 0x34]);
+    hdr[20..22].copy_from_slice(&4791u16.to_be_bytes());
+    hdr[26..28].copy_from_slice(&[0x56, 0x78]);
+    hdr
+}
+
+fn send_only(buf: &mut [u8]) -> usize {
+    let mut pkt = PacketMut::init(buf, opcode::RC_SEND_ONLY, 5).unwrap();
+    pkt.bth().set_dest_qp(0x11);
+    pkt.payload_mut().copy_from_slice(b"hello");
+    let icrc = icrc::compute(crc32_le, &ipv4_udp(), pkt.without_icrc()).unwrap();
+    pkt.set_icrc(icrc::to_wire(icrc));
+    pkt.len()
+}
+
+#[test]
+fn seed_is_masked_lrh() {
+    assert_eq!(crc32_le(!0, &[0xff; 8]), SEED);
+}
+
+#[test]
+fn round_trip() {
+    let mut buf = [0u8; 64];
+    let len = send_only(&mut buf);
+    assert_eq!(icrc::verify(crc32_le, &ipv4_udp(), &buf[..len]), Ok(()));
+
+    buf[12] ^= 1;
+    assert!(matches!(
+        icrc::verify(crc32_le, &ipv4_udp(), &buf[..len]),
+        Err(IcrcError::Mismatch { .. })
+    ));
+}
+
+#[test]
+fn variant_fields_are_masked() {
+    let mut buf = [0u8; 64];
+    let len = send_only(&mut buf);
+
+    let mut ip_udp = ipv4_udp();
+    // Type of service, TTL, IP and UDP checksums.
+    ip_udp[1] = 0xb8;
+    ip_udp[8] = 1;
+    ip_udp[10] = 0;
+    ip_udp[27] = 0;
+    // BTH FECN and BECN.
+    buf[4] |= 0xc0;
+    assert_eq!(icrc::verify(crc32_le, &ip_udp, &buf[..len]), Ok(()));
+
+    // The destination address is invariant.
+    ip_udp[19] = 1;
+    assert!(icrc::verify(crc32_le, &ip_udp, &buf[..len]).is_err());
+}
+
+#[test]
+fn ipv6_headers() {
+    let mut ip_udp = [0u8; 48];
+    ip_udp[0] = 0x60;
+    ip_udp[7] = 64;
+    let mut buf = [0u8; 64];
+    let mut pkt = PacketMut::init(&mut buf, opcode::RC_ACKNOWLEDGE, 0).unwrap();
+    let icrc = icrc::compute(crc32_le, &ip_udp, pkt.without_icrc()).unwrap();
+    pkt.set_icrc(icrc::to_wire(icrc));
+    let len = pkt.len();
+
+    // Traffic class, flow label and hop limit.
+    ip_udp[0..4].copy_from_slice(&[0x6a, 0xbc, 0xde, 0xf0]);
+    ip_udp[7] = 3;
+    assert_eq!(icrc::verify(crc32_le, &ip_udp, &buf[..len]), Ok(()));
+}
+
+#[test]
+fn malformed_headers() {
+    let packet = [0u8; 16];
+    let mut ip_udp = ipv4_udp();
+    assert_eq!(
+        icrc::verify(crc32_le, &ip_udp[..27], &packet),
+        Err(IcrcError::Malformed)
+    );
+    ip_udp[0] = 0x55;
+    assert_eq!(icrc::compute(crc32_le, &ip_udp, &packet[..12]), None);
+    assert_eq!(
+        icrc::verify(crc32_le, &ipv4_udp(), &packet[..3]),
+        Err(IcrcError::Malformed)
+    );
+}
@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::id_alloc::{words_for, IdAllocator};
+use rdma_host_tests::pool::IndexRange;
+
+#[test]
+fn allocation_is_cyclic_and_honours_reservations() {
+    let range = IndexRange { min: 100, max: 229 };
+    assert_eq!(words_for(range.size()), 3);
+    assert!(IdAllocator::new(range, [0u64; 2]).is_none());
+
+    let mut ids = IdAllocator::new(range, [u64::MAX; 3]).unwrap();
+    assert_eq!(ids.used(), 0);
+    assert!(ids.reserve(IndexRange { min: 100, max: 101 }));
+    assert!(!ids.reserve(IndexRange { min: 220, max: 230 }));
+    assert_eq!(ids.alloc(), Some(102));
+    assert_eq!(ids.alloc(), Some(103));
+    // A freed ID is not handed out again right away.
+    assert!(ids.free(102));
+    assert!(!ids.free(102));
+    assert_eq!(ids.alloc(), Some(104));
+
+    assert!(ids.alloc_at(229));
+    assert!(!ids.alloc_at(229));
+    assert!(!ids.alloc_at(99));
+    while ids.alloc().is_some() {}
+    assert!(ids.is_full());
+    assert_eq!(ids.used(), range.size());
+
+    // Once full, the freed IDs are found by wrapping around.
+    assert!(ids.free(102));
+    assert!(ids.free(200));
+    assert_eq!(ids.alloc(), Some(200));
+    assert_eq!(ids.alloc(), Some(102));
+    assert_eq!(ids.alloc(), None);
+}
+
+#[test]
+fn blocks_are_aligned_and_contiguous() {
+    let range = IndexRange {
+        min: 0x40,
+        max: 0x7f,
+    };
+    let mut qpns = IdAllocator::new(range, [0u64; 1]).unwrap();
+    assert!(qpns.alloc_at(0x41));
+    assert_eq!(qpns.alloc_block(8, 8), Some(0x48));
+    assert_eq!(qpns.alloc_block(3, 0), Some(0x42));
+    assert_eq!(qpns.alloc_block(16, 16), Some(0x50));
+    assert_eq!(qpns.alloc_block(32, 32), Some(0x60));
+    assert_eq!(qpns.alloc_block(32, 32), None);
+    assert_eq!(qpns.alloc_block(4, 3), None);
+    assert_eq!(qpns.used(), 60);
+    assert!(qpns.is_used(0x7f));
+
+    assert_eq!(qpns.free_block(0x48, 8), 8);
+    assert_eq!(qpns.free_block(0x48, 8), 0);
+    assert_eq!(qpns.alloc_block(8, 4), Some(0x48));
+    // Only three IDs are left between the blocks.
+    assert_eq!(qpns.alloc_block(4, 1), None);
+    assert_eq!(qpns.alloc_block(3, 1), Some(0x45));
+}
@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::init_once::InitOnce;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn failed_init_is_retried() {
+    let once = InitOnce::new();
+    assert!(!once.is_done());
+    assert_eq!(once.call(|| Err(-16), || {}), Err(-16));
+    assert!(!once.is_done());
+    assert_eq!(once.call(|| Ok::<(), i32>(()), || {}), Ok(()));
+    assert!(once.is_done());
+    assert_eq!(once.call(|| Err(-16), || {}), Ok(()));
+
+    once.reset();
+    assert!(!once.is_done());
+    assert_eq!(once.call(|| Err(-12), || {}), Err(-12));
+}
+
+#[test]
+fn concurrent_callers_share_one_init() {
+    let once = Arc::new(InitOnce::new());
+    let runs = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..8)
+        .map(|_| {
+            let (once, runs) = (once.clone(), runs.clone());
+            thread::spawn(move || {
+                once.call(
+                    || {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        thread::yield_now();
+                        Ok::<(), ()>(())
+                    },
+                    thread::yield_now,
+                )
+                .unwrap();
+                assert!(once.is_done());
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::ip_filter::{FilterError, IpPrefix, PeerAddr, PrefixList};
+
+fn addr(s: &str) -> PeerAddr {
+    s.parse().unwrap()
+}
+
+fn prefix(s: &str) -> IpPrefix {
+    s.parse().unwrap()
+}
+
+#[test]
+fn parses_addresses() {
+    assert_eq!(addr("10.1.2.3"), PeerAddr::from_v4([10, 1, 2, 3]));
+    assert!(addr("10.1.2.3").is_v4());
+    let mut v6 = [0; 16];
+    v6[0] = 0xfe;
+    v6[1] = 0x80;
+    v6[15] = 1;
+    assert_eq!(addr("fe80::1"), PeerAddr::from_v6(v6));
+    assert_eq!(addr("::"), PeerAddr::from_v6([0; 16]));
+    assert!("10.1.2".parse::<PeerAddr>().is_err());
+    assert!("10.1.2.256".parse::<PeerAddr>().is_err());
+    assert!("1::2::3".parse::<PeerAddr>().is_err());
+    assert!("1:2:3:4:5:6:7".parse::<PeerAddr>().is_err());
+}
+
+#[test]
+fn prefixes_match_and_format() {
+    let p = prefix("10.1.2.3/8");
+    assert_eq!(p.len(), 8);
+    assert_eq!(p.to_string(), "10.0.0.0/8");
+    assert!(p.contains(addr("10.200.0.1")));
+    assert!(!p.contains(addr("11.0.0.1")));
+    assert!(!p.contains(addr("fe80::1")));
+
+    let p = prefix("fd00::/8");
+    assert!(p.contains(addr("fd12:3456::1")));
+    assert!(!p.contains(addr("10.0.0.1")));
+    assert_eq!(p.to_string(), "fd00:0:0:0:0:0:0:0/8");
+
+    assert_eq!(prefix("192.168.0.1").len(), 32);
+    assert!("10.0.0.0/33".parse::<IpPrefix>().is_err());
+}
+
+#[test]
+fn empty_list_accepts_everyone() {
+    let list = PrefixList::<4>::new();
+    assert!(list.is_empty());
+    assert!(list.permits(addr("1.2.3.4")));
+    assert!(list.permits(addr("fe80::1")));
+}
+
+#[test]
+fn allowlist_rejects_other_peers() {
+    let mut list = PrefixList::<4>::new();
+    list.add(prefix("10.0.0.0/8")).unwrap();
+    list.add(prefix("fd00::/8")).unwrap();
+    assert!(list.permits(addr("10.0.0.7")));
+    assert!(list.permits(addr("fd00::7")));
+    assert!(!list.permits(addr("192.168.1.1")));
+    assert!(!list.permits(addr("fe80::1")));
+
+    assert!(list.remove(prefix("10.0.0.0/8")));
+    assert!(!list.remove(prefix("10.0.0.0/8")));
+    assert!(!list.permits(addr("10.0.0.7")));
+    assert_eq!(list.iter().copied().collect::<Vec<_>>(), [prefix("fd00::/8")]);
+    list.clear();
+    assert!(list.permits(addr("10.0.0.7")));
+}
+
+#[test]
+fn capacity_is_enforced() {
+    let mut list = PrefixList::<1>::new();
+    list.add(prefix("10.0.0.0/8")).unwrap();
+    list.add(prefix("10.0.0.0/8")).unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list.add(prefix("11.0.0.0/8")), Err(FilterError::Full));
+}
+
+#[test]
+fn store_and_show_round_trip() {
+    let mut list = PrefixList::<4>::new();
+    list.store("10.0.0.0/8, 192.168.1.0/24\n").unwrap();
+    let mut out = String::new();
+    list.show(&mut out).unwrap();
+    assert_eq!(out, "10.0.0.0/8\n192.168.1.0/24\n");
+
+    assert_eq!(list.store("10.0.0.0/8 bogus"), Err(FilterError::Parse));
+    assert_eq!(
+        list.store("1.0.0.0/8 2.0.0.0/8 3.0.0.0/8 4.0.0.0/8 5.0.0.0/8"),
+        Err(FilterError::Full)
+    );
+    let mut unchanged = String::new();
+    list.show(&mut unchanged).unwrap();
+    assert_eq!(unchanged, out);
+
+    list.store("").unwrap();
+    assert!(list.is_empty());
+}
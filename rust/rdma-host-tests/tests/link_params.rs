@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use std::fmt::Write;
+
+use rdma_host_tests::dev_config::CrcMode;
+use rdma_host_tests::link_params::{attr, LinkParamError, LinkParams};
+
+#[test]
+fn overrides_parse_and_print_back() {
+    let params = LinkParams::parse("max_qp=256, udp_port=4792 crc_mode=off").unwrap();
+    assert_eq!(params.max_qp, Some(256));
+    assert_eq!(params.udp_port, Some(4792));
+    assert_eq!(params.config.crc_mode, CrcMode::Off);
+    assert_eq!(params.max_cq, None);
+    assert!(!params.is_default());
+
+    let mut text = String::new();
+    write!(text, "{}", params).unwrap();
+    assert_eq!(text, "udp_port=4792 max_qp=256 crc_mode=off");
+    assert_eq!(LinkParams::parse(&text), Ok(params));
+
+    assert!(LinkParams::parse("").unwrap().is_default());
+    assert_eq!(
+        LinkParams::parse("max_qp=0"),
+        Err(LinkParamError::OutOfRange)
+    );
+    assert_eq!(
+        LinkParams::parse("mtu_override=1000"),
+        Err(LinkParamError::OutOfRange)
+    );
+    assert_eq!(LinkParams::parse("max_qp"), Err(LinkParamError::Parse));
+    assert_eq!(
+        LinkParams::parse("ports=2"),
+        Err(LinkParamError::UnknownKey)
+    );
+}
+
+#[test]
+fn newlink_attributes_override_the_defaults() {
+    let params = LinkParams::DEFAULT
+        .with_attr(attr::UDP_PORT, &4792u16.to_ne_bytes())
+        .and_then(|p| p.with_attr(attr::MAX_QP, &256u32.to_ne_bytes()))
+        .and_then(|p| p.with_attr(attr::CONFIG, b"crc_mode=off,mtu_override=1024\0"))
+        .unwrap();
+    assert_eq!(params.udp_port, Some(4792));
+    assert_eq!(params.max_qp, Some(256));
+    assert_eq!(params.max_cq, None);
+    assert_eq!(params.config.crc_mode, CrcMode::Off);
+    assert_eq!(params.config.mtu_override, 1024);
+    assert_eq!(
+        LinkParams::parse("udp_port=4792 max_qp=256 crc_mode=off mtu_override=1024"),
+        Ok(params)
+    );
+
+    let default = LinkParams::DEFAULT;
+    assert_eq!(
+        default.with_attr(attr::MAX_CQ, &0u32.to_ne_bytes()),
+        Err(LinkParamError::OutOfRange)
+    );
+    assert_eq!(
+        default.with_attr(attr::MAX_MR, &1u16.to_ne_bytes()),
+        Err(LinkParamError::Parse)
+    );
+    assert_eq!(
+        default.with_attr(attr::CONFIG, b"max_qp=8\0"),
+        Err(LinkParamError::UnknownKey)
+    );
+    assert_eq!(
+        default.with_attr(attr::IBDEV_NAME, b"rxe1\0"),
+        Err(LinkParamError::UnknownKey)
+    );
+    assert_eq!(
+        default.with_attr(attr::MAX + 1, &[]),
+        Err(LinkParamError::UnknownKey)
+    );
+}
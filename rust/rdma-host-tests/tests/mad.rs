@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::mad::{
+    class, method, rmpp_type, MadHdr, MadResult, RmppFlags, RmppHdr, SmpHdr, MAD_HDR_SIZE,
+    MAD_SIZE, RMPP_HDR_SIZE, RMPP_VERSION, STATUS_UNSUPPORTED_METHOD,
+};
+
+#[test]
+fn headers_round_trip() {
+    let hdr = MadHdr::new(class::PERF_MGMT, 1, method::GET, 0x0102_0304_0506_0708)
+        .with_attr(0x0012, 0xaabb_ccdd);
+    let mut buf = [0xffu8; MAD_SIZE];
+    assert!(hdr.write(&mut buf));
+    assert_eq!(&buf[..4], &[1, class::PERF_MGMT, 1, method::GET]);
+    assert_eq!(&buf[8..16], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    assert_eq!(&buf[16..24], &[0x00, 0x12, 0, 0, 0xaa, 0xbb, 0xcc, 0xdd]);
+    assert_eq!(MadHdr::parse(&buf), Some(hdr));
+    assert!(!hdr.is_smp());
+    assert!(!hdr.is_response());
+
+    assert_eq!(MadHdr::parse(&buf[..MAD_HDR_SIZE - 1]), None);
+    assert!(!hdr.write(&mut buf[..MAD_HDR_SIZE - 1]));
+    // A GMP is not an SMP.
+    assert_eq!(SmpHdr::parse(&buf), None);
+
+    let resp = hdr.response(STATUS_UNSUPPORTED_METHOD);
+    assert_eq!(resp.method, method::GET_RESP);
+    assert_eq!(resp.tid, hdr.tid);
+    assert!(resp.is_response());
+    let trap = MadHdr::new(class::SUBN_LID_ROUTED, 1, method::TRAP, 7).response(0);
+    assert_eq!(trap.method, method::TRAP_REPRESS);
+}
+
+#[test]
+fn directed_route_smps() {
+    let mut mad = MadHdr::new(class::SUBN_DIRECTED_ROUTE, 1, method::GET, 42).with_attr(0x15, 1);
+    mad.class_specific = 0x0102;
+    let smp = SmpHdr {
+        mad,
+        mkey: 0xdead_beef,
+        dr_slid: 0xffff,
+        dr_dlid: 0xffff,
+    };
+    let mut buf = [0u8; MAD_SIZE];
+    assert!(smp.write(&mut buf));
+    let parsed = SmpHdr::parse(&buf).unwrap();
+    assert_eq!(parsed, smp);
+    assert!(parsed.is_directed_route());
+    assert_eq!((parsed.hop_ptr(), parsed.hop_cnt()), (1, 2));
+    assert!(!parsed.is_returning());
+
+    let resp = parsed.response(STATUS_UNSUPPORTED_METHOD);
+    assert!(resp.is_returning());
+    assert_eq!(resp.status(), STATUS_UNSUPPORTED_METHOD);
+    assert_eq!(resp.mad.status, 0x8000 | STATUS_UNSUPPORTED_METHOD);
+    assert_eq!(resp.mkey, smp.mkey);
+}
+
+#[test]
+fn results_combine() {
+    let res = MadResult::SUCCESS | MadResult::REPLY;
+    assert_eq!(res.bits(), 3);
+    assert!(res.contains(MadResult::REPLY));
+    assert!(!res.contains(MadResult::CONSUMED));
+    assert_eq!(MadResult::default(), MadResult::FAILURE);
+}
+
+#[test]
+fn rmpp_segments() {
+    let first = RmppHdr::data(1, 3, 500, 20);
+    assert!(first.is_active());
+    assert_eq!(first.flags, RmppFlags::ACTIVE | RmppFlags::FIRST);
+    assert_eq!(first.paylen_newwin, 500);
+    let middle = RmppHdr::data(2, 3, 500, 20);
+    assert_eq!(middle.flags, RmppFlags::ACTIVE);
+    assert_eq!(middle.paylen_newwin, 0);
+    let only = RmppHdr::data(1, 1, 20, 20);
+    assert!(only.flags.contains(RmppFlags::FIRST | RmppFlags::LAST));
+    assert_eq!(only.paylen_newwin, 20);
+
+    let mut last = RmppHdr::data(3, 3, 500, 20);
+    last.rtime = 0x1f;
+    let mut buf = [0u8; MAD_SIZE];
+    MadHdr::new(class::SUBN_ADM, 2, method::GET_RESP, 9).write(&mut buf);
+    assert!(last.write(&mut buf));
+    assert_eq!(
+        &buf[MAD_HDR_SIZE..MAD_HDR_SIZE + 4],
+        &[RMPP_VERSION, rmpp_type::DATA, 0xfd, 0]
+    );
+    assert_eq!(RmppHdr::parse(&buf), Some(last));
+    // The common header is left alone.
+    assert_eq!(MadHdr::parse(&buf).unwrap().tid, 9);
+
+    assert_eq!(RmppHdr::parse(&buf[..RMPP_HDR_SIZE - 1]), None);
+    assert!(!RmppHdr::INACTIVE.is_active());
+    assert_eq!(RmppFlags::from_bits(0xff).bits(), 7);
+}
+
+#[test]
+fn rmpp_last_segment_carries_its_own_length() {
+    let last = RmppHdr::data(3, 3, 500, 20);
+    assert_eq!(last.flags, RmppFlags::ACTIVE | RmppFlags::LAST);
+    assert_eq!(last.paylen_newwin, 20);
+
+    let mut buf = [0u8; MAD_SIZE];
+    assert!(last.write(&mut buf));
+    assert_eq!(&buf[MAD_HDR_SIZE + 8..RMPP_HDR_SIZE], &20u32.to_be_bytes());
+}
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::mcg::{
+    McgAction, McgGroup, McgRequest, McgState, McgStatus, JOIN_FULL, JOIN_NON_MEMBER, MAX_PENDING,
+};
+
+fn run(f: impl FnOnce(&mut dyn FnMut(McgAction))) -> Vec<McgAction> {
+    let mut actions = Vec::new();
+    f(&mut |a| actions.push(a));
+    actions
+}
+
+fn reply(slave: u8, status: McgStatus) -> McgAction {
+    McgAction::Reply { slave, status }
+}
+
+fn join(slave: u8, mask: u8) -> McgRequest {
+    McgRequest::Join { slave, mask }
+}
+
+fn leave(slave: u8, mask: u8) -> McgRequest {
+    McgRequest::Leave { slave, mask }
+}
+
+#[test]
+fn join_once_and_leave_with_last_member() {
+    let mut g = McgGroup::new(10);
+    let a = run(|mut out| g.request(join(1, JOIN_FULL), 0, &mut out));
+    assert_eq!(a, [McgAction::SendJoin { mask: JOIN_FULL }]);
+    assert_eq!(g.state(), McgState::JoinSent);
+    assert_eq!(g.deadline(), Some(10));
+
+    // A second slave waits for the join in flight, then rides on it.
+    let a = run(|mut out| g.request(join(2, JOIN_FULL), 1, &mut out));
+    assert!(a.is_empty());
+    assert_eq!(g.pending(), 1);
+    let a = run(|mut out| g.response(true, 2, &mut out));
+    assert_eq!(a, [reply(1, McgStatus::Ok), reply(2, McgStatus::Ok)]);
+    assert_eq!(g.joined(), JOIN_FULL);
+    assert_eq!(g.state(), McgState::Idle);
+
+    // Leaves are acknowledged right away, the SA only hears of the last one.
+    let a = run(|mut out| g.request(leave(1, JOIN_FULL), 3, &mut out));
+    assert_eq!(a, [reply(1, McgStatus::Ok)]);
+    let a = run(|mut out| g.request(leave(2, JOIN_FULL), 4, &mut out));
+    assert_eq!(
+        a,
+        [
+            reply(2, McgStatus::Ok),
+            McgAction::SendLeave { mask: JOIN_FULL }
+        ]
+    );
+    assert_eq!(g.state(), McgState::LeaveSent);
+    let a = run(|mut out| g.response(true, 5, &mut out));
+    assert_eq!(a, [McgAction::Destroy]);
+    assert_eq!(g.state(), McgState::Dead);
+}
+
+#[test]
+fn join_extends_the_held_join_states() {
+    let mut g = McgGroup::new(10);
+    run(|mut out| g.request(join(0, JOIN_FULL), 0, &mut out));
+    run(|mut out| g.response(true, 1, &mut out));
+    let a = run(|mut out| g.request(join(3, JOIN_NON_MEMBER), 2, &mut out));
+    assert_eq!(
+        a,
+        [McgAction::SendJoin {
+            mask: JOIN_FULL | JOIN_NON_MEMBER
+        }]
+    );
+    run(|mut out| g.response(true, 3, &mut out));
+    assert_eq!(g.member(0), JOIN_FULL);
+    assert_eq!(g.member(3), JOIN_NON_MEMBER);
+
+    // Only the join state nobody needs anymore is left.
+    let a = run(|mut out| g.request(leave(3, JOIN_NON_MEMBER), 4, &mut out));
+    assert_eq!(
+        a,
+        [
+            reply(3, McgStatus::Ok),
+            McgAction::SendLeave {
+                mask: JOIN_NON_MEMBER
+            }
+        ]
+    );
+    // An error answer to a leave still drops the join state.
+    run(|mut out| g.response(false, 5, &mut out));
+    assert_eq!(g.joined(), JOIN_FULL);
+    assert_eq!(g.state(), McgState::Idle);
+}
+
+#[test]
+fn timeouts_and_rejections() {
+    let mut g = McgGroup::new(10);
+    run(|mut out| g.request(join(1, JOIN_FULL), 100, &mut out));
+    run(|mut out| g.request(join(2, JOIN_FULL), 101, &mut out));
+    let a = run(|mut out| {
+        assert!(!g.timeout(109, &mut out));
+    });
+    assert!(a.is_empty());
+
+    // The first join times out, the queued one is sent in turn.
+    let a = run(|mut out| {
+        assert!(g.timeout(110, &mut out));
+    });
+    assert_eq!(
+        a,
+        [
+            reply(1, McgStatus::Timeout),
+            McgAction::SendJoin { mask: JOIN_FULL }
+        ]
+    );
+    assert_eq!(g.deadline(), Some(120));
+
+    // It is rejected, and as nobody uses the group it goes away.
+    let a = run(|mut out| g.response(false, 111, &mut out));
+    assert_eq!(a, [reply(2, McgStatus::Rejected), McgAction::Destroy]);
+
+    // Late answers and malformed requests are harmless.
+    assert!(run(|mut out| g.response(true, 112, &mut out)).is_empty());
+    let mut g = McgGroup::new(10);
+    let a = run(|mut out| g.request(join(200, JOIN_FULL), 0, &mut out));
+    assert_eq!(a, [reply(200, McgStatus::Invalid)]);
+    let a = run(|mut out| g.request(join(1, 0), 0, &mut out));
+    assert_eq!(a, [reply(1, McgStatus::Invalid)]);
+}
+
+#[test]
+fn teardown_fails_pending_requests() {
+    let mut g = McgGroup::new(10);
+    run(|mut out| g.request(join(0, JOIN_FULL), 0, &mut out));
+    run(|mut out| g.response(true, 1, &mut out));
+    run(|mut out| g.request(join(1, JOIN_NON_MEMBER), 2, &mut out));
+    for slave in 0..MAX_PENDING as u8 {
+        run(|mut out| g.request(leave(slave, JOIN_FULL), 3, &mut out));
+    }
+    let a = run(|mut out| g.request(join(9, JOIN_FULL), 3, &mut out));
+    assert_eq!(a, [reply(9, McgStatus::Busy)]);
+
+    let a = run(|mut out| g.teardown(&mut out));
+    assert_eq!(a.len(), 1 + MAX_PENDING + 2);
+    assert_eq!(a[0], reply(1, McgStatus::Removed));
+    assert_eq!(a[MAX_PENDING], reply(7, McgStatus::Removed));
+    assert_eq!(a[MAX_PENDING + 1], McgAction::SendLeave { mask: JOIN_FULL });
+    assert_eq!(a[MAX_PENDING + 2], McgAction::Destroy);
+    assert_eq!(g.state(), McgState::Dead);
+
+    let a = run(|mut out| g.request(join(0, JOIN_FULL), 4, &mut out));
+    assert_eq!(a, [reply(0, McgStatus::Removed)]);
+    assert!(run(|mut out| g.teardown(&mut out)).is_empty());
+}
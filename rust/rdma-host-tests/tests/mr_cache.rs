@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::mr_cache::{MrCache, MrGeneration};
+
+#[test]
+fn repeated_lookups_hit() {
+    let generation = MrGeneration::new();
+    let mut cache = MrCache::<u32, 4>::new();
+    let mut resolved = 0;
+    for _ in 0..3 {
+        let mr = cache.lookup(&generation, 0x1234, |key| {
+            resolved += 1;
+            Some(key + 1)
+        });
+        assert_eq!(mr, Some(0x1235));
+    }
+    assert_eq!(resolved, 1);
+    assert_eq!((cache.hits(), cache.misses()), (2, 1));
+}
+
+#[test]
+fn bump_makes_entries_stale() {
+    let generation = MrGeneration::new();
+    let mut cache = MrCache::<u32, 4>::new();
+    cache.lookup(&generation, 7, Some);
+    generation.bump();
+    assert_eq!(cache.get(generation.current(), 7), None);
+    assert_eq!(cache.lookup(&generation, 7, |_| None), None);
+}
+
+#[test]
+fn failed_resolution_is_not_cached() {
+    let generation = MrGeneration::new();
+    let mut cache = MrCache::<u32, 2>::new();
+    assert_eq!(cache.lookup(&generation, 1, |_| None), None);
+    assert_eq!(cache.lookup(&generation, 1, Some), Some(1));
+}
+
+#[test]
+fn full_cache_evicts_least_recently_used() {
+    let mut cache = MrCache::<u32, 2>::new();
+    cache.insert(0, 1, 10);
+    cache.insert(0, 2, 20);
+    assert_eq!(cache.get(0, 1), Some(&10));
+    cache.insert(0, 3, 30);
+    assert_eq!(cache.get(0, 2), None);
+    assert_eq!(cache.get(0, 1), Some(&10));
+    assert_eq!(cache.get(0, 3), Some(&30));
+}
+
+#[test]
+fn full_cache_evicts_oldest() {
+    let mut cache = MrCache::<u32, 2>::new();
+    cache.insert(0, 1, 10);
+    cache.insert(0, 2, 20);
+    cache.insert(0, 3, 30);
+    assert_eq!(cache.get(0, 1), None);
+    assert_eq!(cache.get(0, 2), Some(&20));
+    assert_eq!(cache.get(0, 3), Some(&30));
+}
+
+#[test]
+fn invalidate_and_clear() {
+    let mut cache = MrCache::<u32, 4>::new();
+    cache.insert(0, 1, 10);
+    cache.insert(0, 2, 20);
+    cache.invalidate(1);
+    assert_eq!(cache.get(0, 1), None);
+    assert_eq!(cache.get(0, 2), Some(&20));
+    cache.clear();
+    assert_eq!(cache.get(0, 2), None);
+}
+
+#[test]
+fn reinsert_replaces_entry() {
+    let mut cache = MrCache::<u32, 2>::new();
+    cache.insert(0, 1, 10);
+    cache.insert(1, 1, 11);
+    cache.insert(1, 2, 20);
+    assert_eq!(cache.get(1, 1), Some(&11));
+    assert_eq!(cache.get(1, 2), Some(&20));
+}
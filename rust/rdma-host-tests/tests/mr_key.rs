@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::mr_key::{MrKey, MAX_INDEX};
+
+#[test]
+fn packs_index_and_variant() {
+    let key = MrKey::new(0x12_3456, 0x78);
+    assert_eq!(key.raw(), 0x1234_5678);
+    assert_eq!(key.index(), 0x12_3456);
+    assert_eq!(key.variant(), 0x78);
+    assert_eq!(MrKey::from_raw(0x1234_5678), key);
+}
+
+#[test]
+fn variant_changes_keep_the_slot() {
+    let key = MrKey::new(MAX_INDEX, 0xff);
+    let next = key.next_variant();
+    assert_eq!(next.index(), MAX_INDEX);
+    assert_eq!(next.variant(), 0);
+    assert_ne!(next, key);
+    assert_eq!(key.with_variant(3).raw(), 0xffff_ff03);
+}
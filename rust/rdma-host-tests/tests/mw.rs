@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::mr_key::MrKey;
+use rdma_host_tests::mw::{
+    BindError, BindMw, BoundWindows, InvalidateError, MrView, MwState, MwType, Window,
+    ACCESS_LOCAL_WRITE, ACCESS_MW_BIND, ACCESS_REMOTE_READ, ACCESS_REMOTE_WRITE, ZERO_BASED,
+};
+
+const PD: usize = 0x1000;
+
+fn mr(access: u32) -> MrView {
+    MrView {
+        lkey: MrKey::new(7, 0),
+        pd: PD,
+        iova: 0x10_000,
+        length: 0x4000,
+        access: access | ACCESS_MW_BIND,
+    }
+}
+
+fn bind(addr: u64, length: u64, access: u32, mr: MrView) -> BindMw {
+    BindMw {
+        key: 0x5a,
+        addr,
+        length,
+        access,
+        mr: Some(mr),
+    }
+}
+
+#[test]
+fn type2_bind_access_and_invalidate() {
+    let mut mw = Window::new(MwType::Type2, MrKey::new(3, 0), PD);
+    assert_eq!(mw.state(), MwState::Free);
+    let req = bind(0x11_000, 0x1000, ACCESS_REMOTE_READ, mr(0));
+    assert_eq!(mw.bind(PD, 17, &req), Ok(None));
+    let rkey = mw.rkey();
+    assert_eq!(rkey, MrKey::new(3, 0x5a));
+    assert_eq!(mw.state(), MwState::Valid);
+
+    let hit = mw.check_access(rkey, 17, ACCESS_REMOTE_READ, 0x11_800, 0x800);
+    assert_eq!(hit, Some((MrKey::new(7, 0), 0x11_800)));
+    // Wrong QP, access beyond the window, ungranted access and a stale key all miss.
+    assert_eq!(
+        mw.check_access(rkey, 18, ACCESS_REMOTE_READ, 0x11_000, 8),
+        None
+    );
+    assert_eq!(
+        mw.check_access(rkey, 17, ACCESS_REMOTE_READ, 0x11_800, 0x801),
+        None
+    );
+    assert_eq!(
+        mw.check_access(rkey, 17, ACCESS_REMOTE_WRITE, 0x11_000, 8),
+        None
+    );
+    assert_eq!(
+        mw.check_access(MrKey::new(3, 0), 17, ACCESS_REMOTE_READ, 0x11_000, 8),
+        None
+    );
+
+    // A bound type 2 window must be invalidated before it is bound again.
+    assert_eq!(mw.bind(PD, 17, &req), Err(BindError::State));
+    assert_eq!(mw.invalidate(MrKey::new(3, 1)), Err(InvalidateError::Key));
+    assert_eq!(mw.invalidate(rkey), Ok(Some(MrKey::new(7, 0))));
+    assert_eq!(mw.state(), MwState::Free);
+    assert_eq!(
+        mw.check_access(rkey, 17, ACCESS_REMOTE_READ, 0x11_000, 8),
+        None
+    );
+}
+
+#[test]
+fn zero_based_window() {
+    let mut mw = Window::new(MwType::Type2, MrKey::new(3, 0), PD);
+    let req = bind(0x2000, 0x2000, ACCESS_REMOTE_READ | ZERO_BASED, mr(0));
+    mw.bind(PD, 1, &req).unwrap();
+    let rkey = mw.rkey();
+    assert_eq!(
+        mw.check_access(rkey, 1, ACCESS_REMOTE_READ, 0x100, 0x100),
+        Some((MrKey::new(7, 0), 0x2100))
+    );
+    assert_eq!(
+        mw.check_access(rkey, 1, ACCESS_REMOTE_READ, 0x1f00, 0x200),
+        None
+    );
+}
+
+#[test]
+fn bind_checks() {
+    let mut t2 = Window::new(MwType::Type2, MrKey::new(3, 0), PD);
+    let read = bind(0x10_000, 0x1000, ACCESS_REMOTE_READ, mr(0));
+    assert_eq!(t2.bind(PD + 1, 1, &read), Err(BindError::PdMismatch));
+    let mut other = mr(0);
+    other.pd = PD + 1;
+    let req = bind(0x10_000, 0x1000, ACCESS_REMOTE_READ, other);
+    assert_eq!(t2.bind(PD, 1, &req), Err(BindError::PdMismatch));
+    let mut unbindable = mr(0);
+    unbindable.access = 0;
+    let req = bind(0x10_000, 0x1000, ACCESS_REMOTE_READ, unbindable);
+    assert_eq!(t2.bind(PD, 1, &req), Err(BindError::NotBindable));
+    let req = bind(0x10_000, 0x1000, ACCESS_REMOTE_READ, mr(ZERO_BASED));
+    assert_eq!(t2.bind(PD, 1, &req), Err(BindError::ZeroBasedMr));
+    let req = bind(0x10_000, 0x1000, ACCESS_REMOTE_WRITE, mr(0));
+    assert_eq!(t2.bind(PD, 1, &req), Err(BindError::NoLocalWrite));
+    let req = bind(0x13_800, 0x1000, ACCESS_REMOTE_READ, mr(0));
+    assert_eq!(t2.bind(PD, 1, &req), Err(BindError::OutOfBounds));
+    let req = bind(0xf_000, 0x1000, ACCESS_REMOTE_READ, mr(0));
+    assert_eq!(t2.bind(PD, 1, &req), Err(BindError::OutOfBounds));
+    let req = bind(
+        0x10_000,
+        0x1000,
+        ACCESS_REMOTE_WRITE,
+        mr(ACCESS_LOCAL_WRITE),
+    );
+    assert!(t2.bind(PD, 1, &req).is_ok());
+
+    // Type 1 windows rebind freely, are usable by any QP and cannot be invalidated.
+    let mut t1 = Window::new(MwType::Type1, MrKey::new(4, 0), PD);
+    let req = bind(0, 0x1000, ACCESS_REMOTE_READ | ZERO_BASED, mr(0));
+    assert_eq!(t1.bind(PD, 1, &req), Err(BindError::ZeroBasedType1));
+    assert_eq!(t1.bind(PD, 1, &read), Ok(None));
+    assert_eq!(t1.bind(PD, 2, &read), Ok(Some(MrKey::new(7, 0))));
+    assert!(t1
+        .check_access(t1.rkey(), 9, ACCESS_REMOTE_READ, 0x10_000, 8)
+        .is_some());
+    assert_eq!(t1.invalidate(t1.rkey()), Err(InvalidateError::State));
+    assert_eq!(t1.dealloc(), Some(MrKey::new(7, 0)));
+    assert_eq!(t1.bind(PD, 1, &read), Err(BindError::State));
+}
+
+#[test]
+fn bound_count() {
+    let mut mw = Window::new(MwType::Type2, MrKey::new(3, 0), PD);
+    let bound = BoundWindows::new();
+    let req = bind(0x10_000, 0x1000, ACCESS_REMOTE_READ, mr(0));
+    assert!(bound.bind());
+    assert_eq!(mw.bind(PD, 1, &req), Ok(None));
+    assert!(bound.is_bound());
+    assert!(!bound.retire());
+
+    let rkey = mw.rkey();
+    assert_eq!(mw.invalidate(rkey), Ok(Some(MrKey::new(7, 0))));
+    bound.unbind();
+    assert!(!bound.is_bound());
+    // Unbalanced unbinds do not wrap.
+    bound.unbind();
+    assert!(!bound.is_bound());
+
+    // Once retired, the region cannot be bound anymore.
+    assert!(bound.retire());
+    assert!(!bound.is_bound());
+    assert!(!bound.bind());
+    bound.unbind();
+    assert!(!bound.bind());
+}
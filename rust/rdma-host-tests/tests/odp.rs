@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::odp::{OdpCaps, OdpSpan, OdpTransportCaps, ODP_SUPPORT, ODP_SUPPORT_IMPLICIT};
+
+#[test]
+fn caps_advertise_support() {
+    let mut caps = OdpCaps::new();
+    assert!(!caps.is_supported());
+    assert_eq!(caps.general_caps(), 0);
+    // Implicit regions mean nothing without a transport that faults.
+    caps.implicit = true;
+    assert_eq!(caps.general_caps(), 0);
+    caps.rc = OdpTransportCaps::SEND | OdpTransportCaps::RECV | OdpTransportCaps::WRITE;
+    assert!(caps.rc.contains(OdpTransportCaps::WRITE));
+    assert!(!caps.rc.contains(OdpTransportCaps::READ));
+    assert_eq!(caps.general_caps(), ODP_SUPPORT | ODP_SUPPORT_IMPLICIT);
+    caps.implicit = false;
+    assert_eq!(caps.general_caps(), ODP_SUPPORT);
+    assert_eq!(caps.rc.bits(), 0b111);
+}
+
+#[test]
+fn spans_clamp_to_whole_pages() {
+    let (start, end) = (0x10000, 0x20000);
+    // A range inside the region, unaligned on both ends.
+    assert_eq!(
+        OdpSpan::of(start, end, 12, 0x11800, 0x13001),
+        Some(OdpSpan {
+            start: 0x11000,
+            end: 0x14000,
+            first_page: 1,
+            npages: 3,
+        })
+    );
+    // A range covering the whole address space, as `munmap` of everything gives.
+    assert_eq!(
+        OdpSpan::of(start, end, 12, 0, u64::MAX),
+        Some(OdpSpan {
+            start,
+            end,
+            first_page: 0,
+            npages: 16,
+        })
+    );
+    assert_eq!(OdpSpan::of(start, end, 12, 0x20000, 0x30000), None);
+    assert_eq!(OdpSpan::of(start, end, 12, 0, 0x10000), None);
+    // Huge pages.
+    let span = OdpSpan::of(0, 1 << 22, 21, 0x300000, 0x300001).unwrap();
+    assert_eq!((span.first_page, span.npages), (1, 1));
+}
@@ -0,0 +1,71 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::opcode::{self, Header, OpcodeMask, Transport};
+
+#[test]
+fn header_lengths() {
+    assert_eq!(opcode::info(opcode::RC_SEND_ONLY).unwrap().header_len(), 12);
+    assert_eq!(
+        opcode::info(opcode::RC_RDMA_WRITE_FIRST)
+            .unwrap()
+            .header_len(),
+        28
+    );
+    assert_eq!(
+        opcode::info(opcode::RC_RDMA_WRITE_ONLY_WITH_IMMEDIATE)
+            .unwrap()
+            .header_len(),
+        32
+    );
+    assert_eq!(
+        opcode::info(opcode::RC_COMPARE_SWAP).unwrap().header_len(),
+        40
+    );
+    assert_eq!(
+        opcode::info(opcode::RC_ATOMIC_ACKNOWLEDGE)
+            .unwrap()
+            .header_len(),
+        24
+    );
+    assert_eq!(
+        opcode::info(opcode::UD_SEND_ONLY_WITH_IMMEDIATE)
+            .unwrap()
+            .header_len(),
+        24
+    );
+}
+
+#[test]
+fn header_offsets_follow_wire_order() {
+    let info = opcode::info(opcode::UD_SEND_ONLY_WITH_IMMEDIATE).unwrap();
+    assert_eq!(info.offset(Header::Bth), Some(0));
+    assert_eq!(info.offset(Header::Deth), Some(12));
+    assert_eq!(info.offset(Header::Immdt), Some(20));
+    assert_eq!(info.offset(Header::Reth), None);
+
+    let info = opcode::info(opcode::RC_ATOMIC_ACKNOWLEDGE).unwrap();
+    assert_eq!(info.offset(Header::Aeth), Some(12));
+    assert_eq!(info.offset(Header::Atmack), Some(16));
+}
+
+#[test]
+fn unsupported_opcodes() {
+    assert!(opcode::info(0x15).is_none());
+    assert!(opcode::info(0x2c).is_none());
+    assert!(opcode::info(0x40).is_none());
+    assert!(opcode::info(0xff).is_none());
+}
+
+#[test]
+fn every_opcode_is_either_request_or_ack() {
+    for op in 0..=u8::MAX {
+        if let Some(info) = opcode::info(op) {
+            assert!(
+                info.mask.contains(OpcodeMask::REQ) != info.mask.contains(OpcodeMask::ACK),
+                "{}",
+                info.name
+            );
+            assert!(Transport::of(op).is_some());
+        }
+    }
+}
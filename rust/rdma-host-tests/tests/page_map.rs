@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::page_map::{collapse_shift, PageMap};
+use rdma_host_tests::page_size::PageSizeCap;
+
+const SZ_4K: u64 = 1 << 12;
+const SZ_2M: u64 = 1 << 21;
+
+fn pages_of(runs: &[(u64, u64)]) -> Vec<u64> {
+    runs.iter()
+        .flat_map(|&(addr, len)| (0..len / SZ_4K).map(move |i| addr + i * SZ_4K))
+        .collect()
+}
+
+#[test]
+fn huge_pages_collapse_to_2m_blocks() {
+    let cap = PageSizeCap::range(12, 21);
+    let mut pages = pages_of(&[(0x4000_0000, SZ_2M), (0x8020_0000, SZ_2M)]);
+    let map = PageMap::collapse(cap, 0x7f00_0000_0000, 2 * SZ_2M, 12, &mut pages).unwrap();
+    assert_eq!(map.block_shift(), 21);
+    assert_eq!(map.blocks(), &[0x4000_0000, 0x8020_0000]);
+    assert_eq!(map.translate(0x7f00_0000_1234), Some(0x4000_1234));
+    assert_eq!(map.translate(0x7f00_0020_0010), Some(0x8020_0010));
+}
+
+#[test]
+fn cap_bounds_block_size() {
+    let cap = PageSizeCap::range(12, 16);
+    let pages = pages_of(&[(0x4000_0000, SZ_2M)]);
+    assert_eq!(collapse_shift(cap, 0x1000_0000, 12, &pages), 16);
+}
+
+#[test]
+fn scattered_pages_stay_at_page_size() {
+    let cap = PageSizeCap::range(12, 21);
+    let mut pages = vec![0x1000, 0x9000, 0x3000];
+    let map = PageMap::collapse(cap, 0x10_0000, 3 * SZ_4K, 12, &mut pages).unwrap();
+    assert_eq!(map.block_shift(), 12);
+    assert_eq!(map.blocks().len(), 3);
+    assert_eq!(map.translate(0x10_1008), Some(0x9008));
+}
+
+#[test]
+fn discontinuity_limits_block_size() {
+    let cap = PageSizeCap::range(12, 21);
+    // Two contiguous 64 KiB runs starting on 64 KiB boundaries.
+    let pages = pages_of(&[(0x20_0000, 0x1_0000), (0x50_0000, 0x1_0000)]);
+    assert_eq!(collapse_shift(cap, 0x100_0000, 12, &pages), 16);
+}
+
+#[test]
+fn misaligned_iova_collapses_by_offset() {
+    let cap = PageSizeCap::range(12, 21);
+    let mut pages = pages_of(&[(0x4000_0000, SZ_2M)]);
+    // The region starts 0x800 bytes into its first page.
+    let map = PageMap::collapse(cap, 0x7f00_0000_0800, SZ_2M - 0x800, 12, &mut pages).unwrap();
+    assert_eq!(map.block_shift(), 21);
+    assert_eq!(map.translate(0x7f00_0000_0800), Some(0x4000_0800));
+}
+
+#[test]
+fn chunk_stops_at_block_and_region_end() {
+    let blocks = [0x1_0000, 0x5_0000];
+    let map = PageMap::new(0x2000, 0x1800, 12, &blocks).unwrap();
+    assert_eq!(map.chunk(0x2100), Some((0x1_0100, 0xf00)));
+    assert_eq!(map.chunk(0x3100), Some((0x5_0100, 0x700)));
+    assert_eq!(map.chunk(0x3800), None);
+    assert_eq!(map.translate(0x1fff), None);
+}
+
+#[test]
+fn short_block_list_is_rejected() {
+    let blocks = [0x1_0000];
+    assert!(PageMap::new(0x2000, 0x1001, 12, &blocks).is_none());
+    assert!(PageMap::new(0x2000, 0x1000, 12, &blocks).is_some());
+}
+
+#[test]
+fn contains_checks_bounds() {
+    let blocks = [0];
+    let map = PageMap::new(0x1000, 0x1000, 12, &blocks).unwrap();
+    assert!(map.contains(0x1000, 0x1000));
+    assert!(!map.contains(0x1800, 0x801));
+    assert!(!map.contains(0xfff, 1));
+}
@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::page_size::{best_page_size, MrLimits, PageSizeCap};
+
+const SZ_4K: u64 = 1 << 12;
+const SZ_2M: u64 = 1 << 21;
+
+#[test]
+fn cap_range() {
+    let cap = PageSizeCap::range(12, 21);
+    assert_eq!(cap.min(), Some(SZ_4K));
+    assert!(cap.supports(SZ_2M));
+    assert!(!cap.supports(1 << 22));
+    assert!(!cap.supports(3 * SZ_4K));
+}
+
+#[test]
+fn contiguous_huge_pages_use_large_blocks() {
+    let cap = PageSizeCap::range(12, 21);
+    let segs = [(0x4000_0000, SZ_2M), (0x4020_0000, SZ_2M)];
+    assert_eq!(
+        best_page_size(cap, 0x7f00_0000_0000, 2 * SZ_2M, 0, segs.into_iter()),
+        Some(SZ_2M)
+    );
+}
+
+#[test]
+fn misaligned_iova_falls_back_to_small_pages() {
+    let cap = PageSizeCap::range(12, 21);
+    let segs = [(0x4000_0000, SZ_2M)];
+    assert_eq!(
+        best_page_size(cap, 0x7f00_0000_1000, SZ_2M, 0, segs.into_iter()),
+        Some(SZ_4K)
+    );
+}
+
+#[test]
+fn scattered_pages() {
+    let cap = PageSizeCap::range(12, 21);
+    let segs = [(0x1000, SZ_4K), (0x9000, SZ_4K), (0x3000, SZ_4K)];
+    assert_eq!(
+        best_page_size(cap, 0x10_0000, 3 * SZ_4K, 0, segs.into_iter()),
+        Some(SZ_4K)
+    );
+    // Nothing fits if the device cannot map 4 KiB pages.
+    let segs = [(0x1000, SZ_4K), (0x9000, SZ_4K)];
+    assert_eq!(
+        best_page_size(
+            PageSizeCap::range(16, 21),
+            0x10_0000,
+            2 * SZ_4K,
+            0,
+            segs.into_iter()
+        ),
+        None
+    );
+}
+
+#[test]
+fn overflowing_regions_are_refused() {
+    let cap = PageSizeCap::range(12, 21);
+    let segs = [(0x4000_0000, SZ_2M)];
+    assert_eq!(
+        best_page_size(cap, u64::MAX - SZ_4K + 1, SZ_2M, 0, segs.into_iter()),
+        None
+    );
+    // A region ending at the top of the address space still fits.
+    let segs = [(0x4000_0000, SZ_4K)];
+    assert_eq!(
+        best_page_size(cap, u64::MAX - SZ_4K + 1, SZ_4K, 0, segs.into_iter()),
+        Some(SZ_4K)
+    );
+    let segs = [(u64::MAX - SZ_4K + 1, SZ_4K)];
+    assert_eq!(
+        best_page_size(cap, 0x1000, SZ_4K, SZ_4K, segs.into_iter()),
+        None
+    );
+    // The offset must lie within the first segment.
+    let segs = [(0x1000, SZ_4K), (0x2000, SZ_4K)];
+    assert_eq!(
+        best_page_size(cap, 0x1000, SZ_4K, 2 * SZ_4K, segs.into_iter()),
+        None
+    );
+    // A segment must not take the IOVA past the top of the address space.
+    let segs = [(0x1000, SZ_2M), (0x40_0000, SZ_4K)];
+    assert_eq!(
+        best_page_size(cap, u64::MAX - SZ_4K + 1, SZ_4K, 0, segs.into_iter()),
+        None
+    );
+}
+
+#[test]
+fn limits() {
+    let limits = MrLimits::new().with_max_mr_size(SZ_2M);
+    assert!(limits.allows(SZ_2M));
+    assert!(!limits.allows(SZ_2M + 1));
+    assert!(!limits.allows(0));
+}
@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::pool::{IndexRange, RefCount, SlotIndices, MAX_INDEX};
+use rdma_host_tests::tracker::ResourceKind;
+
+#[test]
+fn mr_and_mw_indices_are_disjoint() {
+    let mr = IndexRange::of(ResourceKind::Mr);
+    let mw = IndexRange::of(ResourceKind::Mw);
+    assert_eq!(mr.max + 1, mw.min);
+    assert_eq!(mw.max, MAX_INDEX);
+    assert!(!mr.contains(0));
+    assert!(!IndexRange::of(ResourceKind::Qp).contains(1));
+    assert_eq!(mw.slot(mw.min + 5, 4), Some(1));
+    assert_eq!(mw.slot(mr.max, 4), None);
+    assert_eq!(mr.slot(1, 0), None);
+}
+
+#[test]
+fn indices_follow_their_slots_cyclically() {
+    let range = IndexRange { min: 10, max: 19 };
+    assert_eq!(range.index_of_slot(10, 0, 4), 10);
+    assert_eq!(range.index_of_slot(11, 0, 4), 14);
+    assert_eq!(range.index_of_slot(13, 1, 4), 15);
+    assert_eq!(range.index_of_slot(17, 0, 4), 18);
+    assert_eq!(range.index_of_slot(19, 1, 4), 19);
+    // Past the end of the range, the first index of the slot.
+    assert_eq!(range.index_of_slot(19, 0, 4), 10);
+    assert_eq!(range.index_of_slot(19, 2, 4), 12);
+}
+
+#[test]
+fn allocation_is_cyclic_and_skips_taken_slots() {
+    let range = IndexRange { min: 10, max: 19 };
+    let mut indices = SlotIndices::new(range, 4, [0u64; 1]).unwrap();
+    let mut take = || {
+        let (index, slot) = indices.alloc()?;
+        assert_eq!(range.slot(index, 4), Some(slot));
+        Some(index)
+    };
+    assert_eq!(take(), Some(10));
+    assert_eq!(take(), Some(11));
+    assert_eq!(take(), Some(12));
+    // A freed index is not handed out again right away.
+    assert!(indices.free(0));
+    let mut take = || indices.alloc().map(|(index, _)| index);
+    assert_eq!(take(), Some(13));
+    assert_eq!(take(), Some(14));
+    assert_eq!(take(), None);
+
+    // The slots are taken cyclically, and the indices wrap around the range.
+    assert!(indices.free(2));
+    assert!(indices.free(3));
+    assert_eq!(indices.alloc(), Some((16, 2)));
+    assert_eq!(indices.alloc(), Some((17, 3)));
+    assert!(indices.free(1));
+    assert_eq!(indices.alloc(), Some((19, 1)));
+    assert!(indices.free(0));
+    assert_eq!(indices.alloc(), Some((10, 0)));
+    assert!(!indices.free(4));
+}
+
+#[test]
+fn allocation_at_an_index_takes_its_slot() {
+    let range = IndexRange { min: 10, max: 19 };
+    let mut indices = SlotIndices::new(range, 4, [0u64; 1]).unwrap();
+    assert_eq!(indices.alloc_at(15), Some(1));
+    // 11 and 19 share the slot of 15.
+    assert_eq!(indices.alloc_at(11), None);
+    assert_eq!(indices.alloc_at(19), None);
+    assert_eq!(indices.alloc_at(20), None);
+    assert_eq!(indices.alloc(), Some((10, 0)));
+    assert_eq!(indices.alloc(), Some((12, 2)));
+    assert!(indices.free(1));
+    assert_eq!(indices.alloc_at(19), Some(1));
+
+    assert!(SlotIndices::new(range, 0, [0u64; 1]).is_none());
+    assert!(SlotIndices::new(range, 11, [0u64; 1]).is_none());
+    assert!(SlotIndices::new(range, 4, [0u64; 0]).is_none());
+}
+
+#[test]
+fn refcount_refuses_dead_objects() {
+    let refs = RefCount::new();
+    assert!(refs.get_unless_zero());
+    refs.get();
+    assert_eq!(refs.count(), 3);
+    assert!(!refs.put());
+    assert!(!refs.put());
+    assert!(refs.put());
+    assert!(!refs.get_unless_zero());
+    assert_eq!(refs.count(), 0);
+}
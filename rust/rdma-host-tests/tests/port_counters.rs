@@ -0,0 +1,128 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::mad::{class, method, MadHdr, MAD_SIZE};
+use rdma_host_tests::port_counters::{
+    parse_select, select, CounterExtender, PortCounterAging, PortTotals, ATTR_PORT_COUNTERS,
+    ATTR_PORT_COUNTERS_EXT, PMA_DATA_OFFSET, PORT_COUNTERS_EXT_SIZE, PORT_COUNTERS_SIZE,
+};
+
+fn be32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn counters_are_reported_since_their_reset_and_saturate() {
+    let mut aging = PortCounterAging::new();
+    let mut now = PortTotals {
+        xmit_bytes: 4000,
+        rcv_bytes: 8 << 32,
+        xmit_pkts: 10,
+        rcv_pkts: u64::MAX - 5,
+        rcv_errors: 70_000,
+        xmit_discards: 3,
+        link_downed: 300,
+    };
+    let mut buf = [0xffu8; PORT_COUNTERS_SIZE];
+    assert!(aging.write(&now, 1, &mut buf));
+    assert_eq!(buf[1], 1);
+    assert_eq!(buf[7], u8::MAX);
+    assert_eq!(&buf[8..10], &u16::MAX.to_be_bytes());
+    assert_eq!(&buf[14..16], &[0, 3]);
+    // Data is counted in units of 4 bytes.
+    assert_eq!(be32(&buf, 24), 1000);
+    assert_eq!(be32(&buf, 28), u32::MAX);
+    assert_eq!(be32(&buf, 32), 10);
+    assert_eq!(be32(&buf, 36), u32::MAX);
+
+    // Resetting moves the baseline, and the 64-bit counters may wrap past it.
+    aging.reset(&now, select::RCV_PKTS | select::LINK_DOWNED);
+    now.rcv_pkts = now.rcv_pkts.wrapping_add(20);
+    now.link_downed += 1;
+    let deltas = aging.deltas(&now);
+    assert_eq!(deltas.rcv_pkts, 20);
+    assert_eq!(deltas.link_downed, 1);
+    assert_eq!(deltas.xmit_pkts, 10);
+
+    aging.reset(&now, select::ALL);
+    assert_eq!(aging.deltas(&now), PortTotals::ZERO);
+    assert!(!aging.write(&now, 1, &mut buf[..PORT_COUNTERS_SIZE - 1]));
+}
+
+#[test]
+fn extended_counters_and_32_bit_registers_widen_to_64_bits() {
+    let mut aging = PortCounterAging::new();
+    let now = PortTotals {
+        xmit_bytes: 1 << 40,
+        rcv_pkts: 1 << 33,
+        ..PortTotals::default()
+    };
+    let mut buf = [0u8; PORT_COUNTERS_EXT_SIZE];
+    assert!(aging.write_ext(&now, 2, &mut buf));
+    assert_eq!(&buf[8..16], &(1u64 << 38).to_be_bytes());
+    assert_eq!(&buf[32..40], &(1u64 << 33).to_be_bytes());
+    // Bit 3 resets PortRcvPkts only.
+    aging.reset_ext(&now, 1 << 3);
+    assert!(aging.write_ext(&now, 2, &mut buf));
+    assert_eq!(&buf[32..40], &[0; 8]);
+    assert_eq!(&buf[8..16], &(1u64 << 38).to_be_bytes());
+
+    let mut reg = CounterExtender::new(u32::MAX - 1);
+    assert_eq!(reg.update(u32::MAX), 1);
+    // The register wrapped.
+    assert_eq!(reg.update(3), 5);
+    assert_eq!(reg.total(), 5);
+
+    assert_eq!(parse_select(&[0, 1, 0x80, 0x04]), Some((1, 0x8004)));
+    assert_eq!(parse_select(&[0, 1]), None);
+}
+
+fn pma(method: u8, attr_id: u16, counter_select: u16) -> [u8; MAD_SIZE] {
+    let mut mad = [0; MAD_SIZE];
+    MadHdr::new(class::PERF_MGMT, 1, method, 7)
+        .with_attr(attr_id, 0)
+        .write(&mut mad);
+    mad[PMA_DATA_OFFSET + 1] = 1;
+    mad[PMA_DATA_OFFSET + 2..PMA_DATA_OFFSET + 4].copy_from_slice(&counter_select.to_be_bytes());
+    mad
+}
+
+#[test]
+fn pma_gets_and_sets_are_answered_from_the_baselines() {
+    let mut aging = PortCounterAging::new();
+    let now = PortTotals {
+        xmit_pkts: 5,
+        rcv_pkts: 9,
+        ..PortTotals::default()
+    };
+    let mut out = [0u8; MAD_SIZE];
+    let get = pma(method::GET, ATTR_PORT_COUNTERS, 0);
+    assert!(aging.process(&now, &get, &mut out));
+    let hdr = MadHdr::parse(&out).unwrap();
+    assert_eq!((hdr.method, hdr.status, hdr.tid), (method::GET_RESP, 0, 7));
+    assert_eq!(out[PMA_DATA_OFFSET + 1], 1);
+    assert_eq!(be32(&out, PMA_DATA_OFFSET + 32), 5);
+    assert_eq!(be32(&out, PMA_DATA_OFFSET + 36), 9);
+
+    // A `Set` resets what it selects, and reports the counters from there.
+    let set = pma(method::SET, ATTR_PORT_COUNTERS, select::RCV_PKTS);
+    assert!(aging.process(&now, &set, &mut out));
+    assert_eq!(be32(&out, PMA_DATA_OFFSET + 32), 5);
+    assert_eq!(be32(&out, PMA_DATA_OFFSET + 36), 0);
+
+    // The extended counters keep their own baselines.
+    let get = pma(method::GET, ATTR_PORT_COUNTERS_EXT, 0);
+    assert!(aging.process(&now, &get, &mut out));
+    assert_eq!(
+        &out[PMA_DATA_OFFSET + 32..PMA_DATA_OFFSET + 40],
+        &9u64.to_be_bytes()
+    );
+
+    // Other attributes and classes go to the agents.
+    let mut untouched = [0xaau8; MAD_SIZE];
+    let other = pma(method::GET, 0x10, 0);
+    assert!(!aging.process(&now, &other, &mut untouched));
+    let mut sa = pma(method::GET, ATTR_PORT_COUNTERS, 0);
+    sa[1] = class::SUBN_ADM;
+    assert!(!aging.process(&now, &sa, &mut untouched));
+    assert_eq!(untouched, [0xaa; MAD_SIZE]);
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use core::cmp::Ordering;
+use rdma_host_tests::psn::{Psn, PsnClass, PsnWindow, PSN_MASK, PSN_WINDOW_MAX};
+
+#[test]
+fn new_masks_to_24_bits() {
+    assert_eq!(Psn::new(0x0100_0005).value(), 5);
+    assert_eq!(Psn::new(PSN_MASK).value(), PSN_MASK);
+}
+
+#[test]
+fn add_and_sub_wrap() {
+    assert_eq!(Psn::new(PSN_MASK).next(), Psn::new(0));
+    assert_eq!(Psn::new(0).sub(1), Psn::new(PSN_MASK));
+    assert_eq!(Psn::new(PSN_MASK - 1).add(3), Psn::new(1));
+}
+
+#[test]
+fn compare_across_wrap() {
+    let before_wrap = Psn::new(PSN_MASK - 2);
+    let after_wrap = Psn::new(3);
+    assert_eq!(after_wrap.diff(before_wrap), 6);
+    assert_eq!(before_wrap.diff(after_wrap), -6);
+    assert!(before_wrap.before(after_wrap));
+    assert!(after_wrap.after(before_wrap));
+    assert_eq!(after_wrap.compare(after_wrap), Ordering::Equal);
+}
+
+#[test]
+fn responder_window_classifies_across_wrap() {
+    let mut expected = PsnWindow::new(Psn::new(PSN_MASK), 1);
+    assert_eq!(expected.classify(Psn::new(PSN_MASK)), PsnClass::InWindow(0));
+    assert_eq!(
+        expected.classify(Psn::new(PSN_MASK - 1)),
+        PsnClass::Duplicate
+    );
+    assert_eq!(expected.classify(Psn::new(0)), PsnClass::Ahead);
+
+    expected.slide(1);
+    assert_eq!(expected.start(), Psn::new(0));
+    assert_eq!(expected.classify(Psn::new(PSN_MASK)), PsnClass::Duplicate);
+    assert_eq!(expected.classify(Psn::new(0)), PsnClass::InWindow(0));
+    // Half of the space behind is duplicates, the other half is ahead.
+    assert_eq!(
+        expected.classify(Psn::new(0).sub(PSN_WINDOW_MAX)),
+        PsnClass::Duplicate
+    );
+    assert_eq!(
+        expected.classify(Psn::new(PSN_WINDOW_MAX - 1)),
+        PsnClass::Ahead
+    );
+}
+
+#[test]
+fn requester_window_acknowledges() {
+    let mut outstanding = PsnWindow::new(Psn::new(PSN_MASK - 1), 0);
+    assert!(outstanding.is_empty());
+    assert!(outstanding.extend(4));
+    assert_eq!(outstanding.end(), Psn::new(2));
+    assert!(outstanding.contains(Psn::new(1)));
+    assert_eq!(outstanding.classify(Psn::new(1)), PsnClass::InWindow(3));
+    assert_eq!(outstanding.classify(Psn::new(2)), PsnClass::Ahead);
+
+    // Coalesced ACK of the first three requests, across the wrap.
+    assert_eq!(outstanding.acknowledge(Psn::new(0)), 3);
+    assert_eq!(outstanding.start(), Psn::new(1));
+    assert_eq!(outstanding.len(), 1);
+    // A late ACK for them changes nothing.
+    assert_eq!(outstanding.acknowledge(Psn::new(PSN_MASK)), 0);
+    assert_eq!(outstanding.classify(Psn::new(0)), PsnClass::Duplicate);
+    // Nor does one for a PSN that was never sent.
+    assert_eq!(outstanding.acknowledge(Psn::new(5)), 0);
+    assert_eq!(outstanding.acknowledge(Psn::new(1)), 1);
+    assert!(outstanding.is_empty());
+    assert_eq!(outstanding.start(), Psn::new(2));
+}
+
+#[test]
+fn window_size_is_bounded() {
+    let mut window = PsnWindow::new(Psn::new(0), u32::MAX);
+    assert_eq!(window.len(), PSN_WINDOW_MAX);
+    assert!(!window.extend(1));
+    assert_eq!(window.len(), PSN_WINDOW_MAX);
+}
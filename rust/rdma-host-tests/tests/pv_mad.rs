@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::cm::{self, attr};
+use rdma_host_tests::cm_proxy::PvCmIds;
+use rdma_host_tests::mad::{class, method, MadHdr, MAD_SIZE};
+use rdma_host_tests::pv_mad::{demux, multiplex, Egress, Ingress, MASTER_TID};
+use rdma_host_tests::sa::{self, ATTR_MC_MEMBER_REC};
+
+const MASTER: u8 = 0;
+const NUM_FUNCS: u32 = 4;
+
+fn mad(mgmt_class: u8, method: u8, tid: u64, attr_id: u16) -> [u8; MAD_SIZE] {
+    let mut mad = [0; MAD_SIZE];
+    MadHdr::new(mgmt_class, 2, method, tid)
+        .with_attr(attr_id, 0)
+        .write(&mut mad);
+    mad
+}
+
+fn tid(mad: &[u8]) -> u64 {
+    MadHdr::parse(mad).unwrap().tid
+}
+
+fn no_cm(_: &mut [u8]) -> bool {
+    panic!("not a CM MAD")
+}
+
+#[test]
+fn requests_are_tagged_and_responses_go_back() {
+    let mut get = mad(class::SUBN_ADM, method::GET, 0x1234, 0x35);
+    assert_eq!(multiplex(&mut get, 2, MASTER, false, no_cm), Egress::Send);
+    assert_eq!(tid(&get), 0x0200_0000_0000_1234);
+
+    let mut resp = mad(class::SUBN_ADM, method::GET_RESP, tid(&get), 0x35);
+    let ingress = demux(&mut resp, MASTER, NUM_FUNCS, None, |_| false, |_| None);
+    assert_eq!(ingress, Ingress::Slave(2));
+    assert_eq!(tid(&resp), 0x1234);
+
+    // Slaves must leave the byte to the master.
+    let mut tagged = mad(class::SUBN_ADM, method::GET, 0x0100_0000_0000_0001, 0x35);
+    assert_eq!(
+        multiplex(&mut tagged, 1, MASTER, false, no_cm),
+        Egress::Drop
+    );
+}
+
+#[test]
+fn classes_are_filtered_for_the_slaves() {
+    let mut smp = mad(class::SUBN_LID_ROUTED, method::GET, 1, 0x15);
+    assert_eq!(multiplex(&mut smp, 1, MASTER, false, no_cm), Egress::Drop);
+    let mut smp = mad(class::SUBN_LID_ROUTED, method::GET, 1, 0x15);
+    assert_eq!(multiplex(&mut smp, 1, MASTER, true, no_cm), Egress::Send);
+    let mut smp = mad(class::SUBN_LID_ROUTED, method::GET, 1, 0x15);
+    assert_eq!(
+        multiplex(&mut smp, MASTER, MASTER, false, no_cm),
+        Egress::Send
+    );
+
+    let mut trap = mad(class::DEVICE_MGMT, method::TRAP, 1, 0x10);
+    assert_eq!(multiplex(&mut trap, 1, MASTER, false, no_cm), Egress::Drop);
+    let mut vendor = mad(0x09, method::GET, 1, 0x10);
+    assert_eq!(
+        multiplex(&mut vendor, 1, MASTER, false, no_cm),
+        Egress::Drop
+    );
+    let mut vendor = mad(0x09, method::GET, 1, 0x10);
+    assert_eq!(
+        multiplex(&mut vendor, MASTER, MASTER, false, no_cm),
+        Egress::Send
+    );
+
+    // Unsolicited SMPs are not for the slaves.
+    let mut smp = mad(class::SUBN_LID_ROUTED, method::SET, 1, 0x15);
+    let ingress = demux(&mut smp, MASTER, NUM_FUNCS, Some(1), |_| true, |_| None);
+    assert_eq!(ingress, Ingress::Drop);
+    let mut smp = mad(
+        class::SUBN_LID_ROUTED,
+        method::GET_RESP,
+        0x0100_0000_0000_0001,
+        0x15,
+    );
+    let ingress = demux(&mut smp, MASTER, NUM_FUNCS, None, |_| false, |_| None);
+    assert_eq!(ingress, Ingress::Drop);
+    let mut smp = mad(
+        class::SUBN_LID_ROUTED,
+        method::GET_RESP,
+        0x0100_0000_0000_0001,
+        0x15,
+    );
+    let ingress = demux(&mut smp, MASTER, NUM_FUNCS, None, |_| true, |_| None);
+    assert_eq!(ingress, Ingress::Slave(1));
+}
+
+#[test]
+fn unknown_functions_and_master_agents_are_dropped() {
+    let mut resp = mad(
+        class::PERF_MGMT,
+        method::GET_RESP,
+        0x0900_0000_0000_0001,
+        0x12,
+    );
+    let ingress = demux(&mut resp, 9, NUM_FUNCS, None, |_| false, |_| None);
+    assert_eq!(ingress, Ingress::Drop);
+
+    let raw = u64::from(MASTER_TID) << 56 | 1;
+    let mut resp = mad(class::SUBN_ADM, method::GET_RESP, raw, 0x35);
+    let ingress = demux(&mut resp, MASTER, NUM_FUNCS, None, |_| false, |_| None);
+    assert_eq!(ingress, Ingress::Drop);
+    assert_eq!(tid(&resp), raw);
+
+    // Requests from the fabric go to the master, unless the GRH names a slave.
+    let mut get = mad(class::SUBN_ADM, method::GET, 1, 0x35);
+    let ingress = demux(&mut get, MASTER, NUM_FUNCS, None, |_| false, |_| None);
+    assert_eq!(ingress, Ingress::Slave(MASTER));
+    let mut get = mad(class::PERF_MGMT, method::GET, 1, 0x12);
+    let ingress = demux(&mut get, MASTER, NUM_FUNCS, Some(3), |_| false, |_| None);
+    assert_eq!(ingress, Ingress::Drop);
+}
+
+#[test]
+fn multicast_members_go_to_the_proxy() {
+    let mut join = mad(class::SUBN_ADM, method::SET, 1, ATTR_MC_MEMBER_REC);
+    assert_eq!(
+        multiplex(&mut join, 1, MASTER, false, no_cm),
+        Egress::McMember
+    );
+    let mut leave = mad(class::SUBN_ADM, sa::method::DELETE, 1, ATTR_MC_MEMBER_REC);
+    assert_eq!(
+        multiplex(&mut leave, 1, MASTER, false, no_cm),
+        Egress::McMember
+    );
+    let mut query = mad(class::SUBN_ADM, method::GET, 1, ATTR_MC_MEMBER_REC);
+    assert_eq!(multiplex(&mut query, 1, MASTER, false, no_cm), Egress::Send);
+
+    let mut resp = mad(class::SUBN_ADM, method::GET_RESP, 1, ATTR_MC_MEMBER_REC);
+    let ingress = demux(&mut resp, MASTER, NUM_FUNCS, None, |_| false, |_| None);
+    assert_eq!(ingress, Ingress::McMember);
+}
+
+#[test]
+fn cm_mads_go_through_the_cm_proxy() {
+    let mut ids = PvCmIds::<u8, 4>::new();
+    let mut req = mad(class::CM, method::SEND, 0, attr::REQ);
+    req[24..28].copy_from_slice(&5u32.to_be_bytes());
+    let egress = multiplex(&mut req, 2, MASTER, false, |m| {
+        ids.multiplex(2, m, 100).is_ok()
+    });
+    assert_eq!(egress, Egress::Send);
+    let pv = cm::local_comm_id(&req).unwrap();
+    assert_ne!(pv, 5);
+
+    let mut rep = mad(class::CM, method::SEND, 0, attr::REP);
+    rep[28..32].copy_from_slice(&pv.to_be_bytes());
+    let ingress = demux(
+        &mut rep,
+        MASTER,
+        NUM_FUNCS,
+        None,
+        |_| false,
+        |m| ids.demux(m, 100, |_| None).ok(),
+    );
+    assert_eq!(ingress, Ingress::Slave(2));
+    assert_eq!(cm::remote_comm_id(&rep), Some(5));
+
+    // A DREQ for a connection without an ID is dropped.
+    let mut dreq = mad(class::CM, method::SEND, 0, attr::DREQ);
+    let egress = multiplex(&mut dreq, 1, MASTER, false, |m| {
+        ids.multiplex(1, m, 100).is_ok()
+    });
+    assert_eq!(egress, Egress::Drop);
+}
@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::qp_fault::{QpFaultCommand, QpFaultInjector, QpFaultRule, MAX_RULES};
+use rdma_host_tests::qp_state::QpState;
+use rdma_host_tests::tunables::TuneError;
+
+#[test]
+fn rules_fail_matching_transitions() {
+    let faults = QpFaultInjector::new();
+    assert!(!faults.is_active());
+    faults.store("fail rtr rts qpn=17 count=2\n").unwrap();
+    faults.store("fail any err").unwrap();
+    assert!(faults.is_active());
+
+    // Other QPs and transitions are left alone.
+    assert!(!faults.fail_modify(18, QpState::Rtr, QpState::Rts));
+    assert!(!faults.fail_modify(17, QpState::Init, QpState::Rtr));
+    assert!(faults.fail_modify(17, QpState::Rtr, QpState::Rts));
+    assert!(faults.fail_modify(17, QpState::Rtr, QpState::Rts));
+    // The count is used up.
+    assert!(!faults.fail_modify(17, QpState::Rtr, QpState::Rts));
+    for _ in 0..3 {
+        assert!(faults.fail_modify(5, QpState::Rts, QpState::Err));
+    }
+    assert_eq!(faults.failed(), 5);
+
+    for _ in 1..MAX_RULES {
+        faults
+            .add_rule(QpFaultRule::new(Some(QpState::Reset), QpState::Init).with_count(1))
+            .unwrap();
+    }
+    assert_eq!(
+        faults.add_rule(QpFaultRule::new(None, QpState::Reset)),
+        Err(TuneError::OutOfRange)
+    );
+    faults.store("clear").unwrap();
+    assert!(!faults.is_active());
+    assert!(!faults.fail_modify(5, QpState::Rts, QpState::Err));
+}
+
+#[test]
+fn errors_are_taken_once_and_commands_round_trip() {
+    let faults = QpFaultInjector::new();
+    faults.store("error 12").unwrap();
+    assert!(!faults.take_error(13));
+    assert!(faults.take_error(12));
+    assert!(!faults.take_error(12));
+    assert_eq!(faults.forced(), 1);
+
+    assert_eq!(QpFaultCommand::parse("error"), Err(TuneError::Parse));
+    assert_eq!(
+        QpFaultCommand::parse("error 16777216"),
+        Err(TuneError::OutOfRange)
+    );
+    assert_eq!(QpFaultCommand::parse("fail rtr"), Err(TuneError::Parse));
+    assert_eq!(
+        QpFaultCommand::parse("fail any rts speed=1"),
+        Err(TuneError::Parse)
+    );
+    assert_eq!(QpFaultCommand::parse("clear all"), Err(TuneError::Parse));
+
+    faults.store("fail init rtr count=1").unwrap();
+    faults.store("fail any sqd qpn=3").unwrap();
+    faults.store("error 7").unwrap();
+    let mut out = String::new();
+    faults.show(&mut out).unwrap();
+    assert_eq!(
+        out,
+        "fail init rtr count=1\nfail any sqd qpn=3\nerror 7\nfailed 0\nforced 1\n"
+    );
+    for line in out.lines().take(2) {
+        let QpFaultCommand::Fail(rule) = QpFaultCommand::parse(line).unwrap() else {
+            panic!("not a rule: {}", line);
+        };
+        assert_eq!(rule.to_string(), line);
+    }
+}
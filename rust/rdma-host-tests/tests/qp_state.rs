@@ -0,0 +1,40 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::qp_state::QpState;
+
+const ALL: [QpState; 7] = [
+    QpState::Reset,
+    QpState::Init,
+    QpState::Rtr,
+    QpState::Rts,
+    QpState::Sqd,
+    QpState::Sqe,
+    QpState::Err,
+];
+
+#[test]
+fn raw_round_trip() {
+    for state in ALL {
+        assert_eq!(QpState::from_raw(state.to_raw()), Some(state));
+    }
+    assert_eq!(QpState::from_raw(7), None);
+}
+
+#[test]
+fn reset_and_error_always_reachable() {
+    for state in ALL {
+        assert!(state.can_transition_to(QpState::Reset));
+        assert!(state.can_transition_to(QpState::Err));
+    }
+}
+
+#[test]
+fn connection_path() {
+    assert!(QpState::Reset.can_transition_to(QpState::Init));
+    assert!(QpState::Init.can_transition_to(QpState::Rtr));
+    assert!(QpState::Rtr.can_transition_to(QpState::Rts));
+    assert!(!QpState::Reset.can_transition_to(QpState::Rtr));
+    assert!(!QpState::Init.can_transition_to(QpState::Rts));
+    assert!(!QpState::Rtr.can_transition_to(QpState::Rtr));
+    assert!(!QpState::Err.can_transition_to(QpState::Rts));
+}
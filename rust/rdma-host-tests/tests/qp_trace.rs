@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::qp_state::QpState;
+use rdma_host_tests::qp_trace::{QpEvent, QpTrace, TraceRecord};
+
+fn records<const N: usize>(trace: &QpTrace<N>) -> Vec<TraceRecord> {
+    let mut out = Vec::new();
+    trace.for_each(|r| out.push(r));
+    out
+}
+
+#[test]
+fn events_round_trip() {
+    let events = [
+        QpEvent::State {
+            from: QpState::Rtr,
+            to: QpState::Rts,
+        },
+        QpEvent::Ack { psn: 0xff_ffff },
+        QpEvent::Nak {
+            psn: 12,
+            syndrome: 0x61,
+        },
+        QpEvent::Retry {
+            psn: 0x80_0000,
+            retries_left: 6,
+        },
+        QpEvent::RnrRetry {
+            psn: 1,
+            retries_left: 7,
+        },
+        QpEvent::AckTimeout,
+        QpEvent::RnrTimeout,
+    ];
+    let trace = QpTrace::<8>::new();
+    for (i, event) in events.iter().enumerate() {
+        trace.record(100 + i as u64, *event);
+    }
+    let got = records(&trace);
+    assert_eq!(got.len(), events.len());
+    for (i, r) in got.iter().enumerate() {
+        assert_eq!(r.seq, i as u64);
+        assert_eq!(r.stamp, 100 + i as u64);
+        assert_eq!(r.event, events[i]);
+    }
+}
+
+#[test]
+fn oldest_records_are_overwritten() {
+    let trace = QpTrace::<4>::new();
+    assert!(records(&trace).is_empty());
+    for psn in 0..10 {
+        trace.record(psn.into(), QpEvent::Ack { psn });
+    }
+    assert_eq!(trace.recorded(), 10);
+    let got = records(&trace);
+    assert_eq!(
+        got.iter().map(|r| r.event).collect::<Vec<_>>(),
+        (6..10).map(|psn| QpEvent::Ack { psn }).collect::<Vec<_>>()
+    );
+    assert_eq!(got[0].seq, 6);
+}
+
+#[test]
+fn dump_format() {
+    let trace = QpTrace::<2>::new();
+    trace.record(5, QpEvent::AckTimeout);
+    trace.record(
+        6,
+        QpEvent::Retry {
+            psn: 0x1234,
+            retries_left: 3,
+        },
+    );
+    trace.record(
+        7,
+        QpEvent::State {
+            from: QpState::Rts,
+            to: QpState::Err,
+        },
+    );
+    let mut out = String::new();
+    trace.dump(&mut out).unwrap();
+    assert_eq!(
+        out,
+        "recorded 3 lost 1\n       1            6 retry psn 0x001234 left 3\n       2            7 \
+         state Rts -> Err\n"
+    );
+}
+
+#[test]
+fn concurrent_recording() {
+    let trace = QpTrace::<64>::new();
+    std::thread::scope(|s| {
+        for t in 0..4u32 {
+            let trace = &trace;
+            s.spawn(move || {
+                for i in 0..1000 {
+                    trace.record(
+                        i,
+                        QpEvent::Ack {
+                            psn: t << 16 | i as u32,
+                        },
+                    );
+                }
+            });
+        }
+        s.spawn(|| {
+            for _ in 0..100 {
+                let got = records(&trace);
+                assert!(got.len() <= 64);
+                assert!(got.windows(2).all(|w| w[0].seq < w[1].seq));
+            }
+        });
+    });
+    assert_eq!(trace.recorded(), 4000);
+    assert_eq!(records(&trace).len(), 64);
+}
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::queue::{Geometry, Queue, HEADER_SIZE};
+use rdma_host_tests::scrub::{Scrub, Scrubber};
+use std::thread;
+
+fn buffer(geometry: Geometry) -> Vec<u64> {
+    vec![0; geometry.buf_size() / 8]
+}
+
+fn index(buf: &[u64], offset: usize) -> u32 {
+    let word = buf[offset / 8];
+    (word >> ((offset % 8) * 8)) as u32
+}
+
+#[test]
+fn geometry_rounds_up() {
+    let geometry = Geometry::new(100, 40).unwrap();
+    assert_eq!(geometry.capacity(), 127);
+    assert_eq!(geometry.elem_size(), 64);
+    assert_eq!(geometry.buf_size(), HEADER_SIZE + 128 * 64);
+    // One slot stays free, so a power of two doubles.
+    assert_eq!(Geometry::new(128, 8).unwrap().capacity(), 255);
+    assert_eq!(Geometry::new(1, 0), None);
+    assert_eq!(Geometry::new(u32::MAX, 8), None);
+}
+
+#[test]
+fn header_matches_rxe_queue_buf() {
+    let geometry = Geometry::new(3, 16).unwrap();
+    let mut buf = buffer(geometry);
+    {
+        // SAFETY: The buffer is large enough and aligned, and only used through the queue.
+        let mut queue = unsafe { Queue::init(buf.as_mut_ptr().cast(), geometry) };
+        let (mut producer, _) = queue.split();
+        assert!(producer.push(&[0xaa; 16]));
+        assert!(producer.push(&[0xbb; 4]));
+    }
+
+    // log2_elem_size, index_mask, then the indices on their own 128-byte lines.
+    assert_eq!(index(&buf, 0), 4);
+    assert_eq!(index(&buf, 4), 3);
+    assert_eq!(index(&buf, 128), 2);
+    assert_eq!(index(&buf, 256), 0);
+    assert_eq!(buf[HEADER_SIZE / 8], 0xaaaa_aaaa_aaaa_aaaa);
+    assert_eq!(buf[HEADER_SIZE / 8 + 2], 0xbbbb_bbbb);
+}
+
+#[test]
+fn full_and_empty() {
+    let geometry = Geometry::new(3, 8).unwrap();
+    let mut buf = buffer(geometry);
+    // SAFETY: The buffer is large enough and aligned, and only used through the queue.
+    let mut queue = unsafe { Queue::init(buf.as_mut_ptr().cast(), geometry) };
+    let (mut producer, mut consumer) = queue.split();
+    assert!(consumer.peek().is_none());
+    for i in 0..10u8 {
+        for j in 0..3 {
+            assert!(producer.push(&[i * 3 + j]));
+        }
+        assert!(producer.is_full());
+        assert!(!producer.push(&[0xff]));
+        assert_eq!(consumer.count(), 3);
+        for j in 0..3 {
+            assert_eq!(consumer.peek().unwrap()[0], i * 3 + j);
+            consumer.advance();
+        }
+        assert!(consumer.is_empty());
+    }
+    assert!(!producer.push(&[0; 9]));
+}
+
+#[test]
+fn reattach_keeps_indices() {
+    let geometry = Geometry::new(7, 8).unwrap();
+    let mut buf = buffer(geometry);
+    let base = buf.as_mut_ptr().cast();
+    // SAFETY: The buffer is large enough and aligned, and only used through the queue.
+    let mut queue = unsafe { Queue::init(base, geometry) };
+    queue.split().0.push(&[7]);
+    // SAFETY: Same buffer, initialised with the same geometry, and the first queue is unused.
+    let mut queue = unsafe { Queue::attach(base, geometry) };
+    let indices = queue.indices();
+    assert_eq!(
+        (indices.slots, indices.producer, indices.consumer),
+        (8, 1, 0)
+    );
+    assert_eq!(indices.count(), 1);
+    let (_, mut consumer) = queue.split();
+    assert_eq!(consumer.peek().unwrap()[0], 7);
+}
+
+#[test]
+fn producer_and_consumer_threads() {
+    let geometry = Geometry::new(15, 8).unwrap();
+    let mut buf = buffer(geometry);
+    // SAFETY: The buffer is large enough and aligned, and only used through the queue.
+    let mut queue = unsafe { Queue::init(buf.as_mut_ptr().cast(), geometry) };
+    let (mut producer, mut consumer) = queue.split();
+    // Enough rounds to wrap the indices many times; waiting threads yield, so that the test does
+    // not crawl when both share a CPU.
+    const ROUNDS: u64 = 20_000;
+    thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..ROUNDS {
+                while !producer.push(&i.to_le_bytes()) {
+                    thread::yield_now();
+                }
+            }
+        });
+        s.spawn(move || {
+            for i in 0..ROUNDS {
+                let value = loop {
+                    if let Some(slot) = consumer.peek() {
+                        break u64::from_le_bytes(slot[..8].try_into().unwrap());
+                    }
+                    thread::yield_now();
+                };
+                assert_eq!(value, i);
+                consumer.advance();
+            }
+        });
+    });
+}
+
+#[test]
+fn released_buffer_comes_back_zeroed() {
+    let geometry = Geometry::new(3, 16).unwrap();
+    let mut buf = buffer(geometry);
+    let scrubber = Scrubber::new();
+    scrubber.set_enabled(true);
+    {
+        // SAFETY: The buffer is large enough and aligned, and only used through the queue.
+        let mut queue = unsafe { Queue::init(buf.as_mut_ptr().cast(), geometry) };
+        let (mut producer, _) = queue.split();
+        assert!(producer.push(&[0xaa; 16]));
+        assert!(producer.push(&[0xbb; 16]));
+        queue.scrub(&scrubber);
+    }
+    assert_eq!(
+        scrubber.scrubbed(),
+        (geometry.buf_size() - HEADER_SIZE) as u64
+    );
+    assert!(buf[HEADER_SIZE / 8..].iter().all(|&word| word == 0));
+
+    // The next user of the buffer finds none of the payloads.
+    // SAFETY: As above.
+    let mut queue = unsafe { Queue::init(buf.as_mut_ptr().cast(), geometry) };
+    let (mut producer, _) = queue.split();
+    assert_eq!(producer.next_slot().unwrap(), &[0; 16]);
+}
@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::ring::Ring;
+use std::rc::Rc;
+
+#[test]
+fn fifo_order_and_capacity() {
+    let mut ring: Ring<u32, 4> = Ring::new();
+    assert!(ring.is_empty());
+    for i in 0..4 {
+        ring.push(i).unwrap();
+    }
+    assert!(ring.is_full());
+    assert_eq!(ring.push(4), Err(4));
+    assert_eq!(ring.front(), Some(&0));
+    assert_eq!(ring.pop(), Some(0));
+    ring.push(4).unwrap();
+    assert_eq!(ring.iter().copied().collect::<Vec<_>>(), [1, 2, 3, 4]);
+    assert_eq!(ring.get(3), Some(&4));
+    assert_eq!(ring.get(4), None);
+}
+
+#[test]
+fn indices_wrap() {
+    let mut ring: Ring<usize, 2> = Ring::new();
+    for i in 0..1000 {
+        ring.push(i).unwrap();
+        assert_eq!(ring.pop(), Some(i));
+    }
+    assert_eq!(ring.len(), 0);
+}
+
+#[test]
+fn drops_remaining_elements() {
+    let value = Rc::new(());
+    {
+        let mut ring: Ring<Rc<()>, 8> = Ring::new();
+        ring.push(value.clone()).unwrap();
+        ring.push(value.clone()).unwrap();
+        assert_eq!(Rc::strong_count(&value), 3);
+    }
+    assert_eq!(Rc::strong_count(&value), 1);
+}
@@ -0,0 +1,21 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::sa::SaCompMask;
+
+#[test]
+fn masks_combine() {
+    let mask = SaCompMask::PATH_DGID | SaCompMask::PATH_SGID | SaCompMask::PATH_NUMB_PATH;
+    assert_eq!(mask.bits(), 0x100c);
+    assert!(mask.contains(SaCompMask::PATH_SGID));
+    assert!(!mask.contains(SaCompMask::PATH_PKEY));
+    assert_eq!(SaCompMask::default(), SaCompMask::NONE);
+    assert!(SaCompMask::MCMEMBER_JOIN.contains(SaCompMask::MCMEMBER_JOIN_STATE));
+    assert_eq!(SaCompMask::PATH_SERVICE_ID.bits().count_ones(), 2);
+}
+
+#[test]
+fn masks_go_out_in_network_order() {
+    let mask = SaCompMask::MCMEMBER_MGID | SaCompMask::MCMEMBER_JOIN_STATE;
+    assert_eq!(mask.to_be().to_ne_bytes(), [0, 0, 0, 0, 0, 1, 0, 0x01]);
+    assert_eq!(SaCompMask::from_bits(mask.bits()), mask);
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::scrub::{scrub, Scrubber};
+
+#[test]
+fn scrub_zeroes() {
+    let mut buf = [0xa5u8; 64];
+    scrub(&mut buf);
+    assert_eq!(buf, [0; 64]);
+}
+
+#[test]
+fn disabled_scrubber_leaves_buffers() {
+    let scrubber = Scrubber::new();
+    let mut buf = [1u8; 16];
+    scrubber.release(&mut buf);
+    assert_eq!(buf, [1; 16]);
+    assert_eq!(scrubber.scrubbed(), 0);
+}
+
+#[test]
+fn enabled_scrubber_zeroes_and_counts() {
+    let scrubber = Scrubber::new();
+    scrubber.set_enabled(true);
+    let mut a = [1u8; 16];
+    let mut b = [2u8; 8];
+    scrubber.release(&mut a);
+    scrubber.release(&mut b);
+    assert_eq!((a, b), ([0; 16], [0; 8]));
+    assert_eq!(scrubber.scrubbed(), 24);
+}
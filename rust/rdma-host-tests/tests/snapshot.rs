@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::queue::QueueIndices;
+use rdma_host_tests::snapshot::Snapshot;
+use rdma_host_tests::tracker::{LiveResource, LiveResources, ResourceKind, ResourceTracker};
+
+struct Qps(Vec<u32>);
+
+impl LiveResources for Qps {
+    fn for_each_live(&self, f: &mut dyn FnMut(LiveResource)) {
+        for &index in &self.0 {
+            f(LiveResource {
+                kind: ResourceKind::Qp,
+                index,
+                owner: 42,
+            });
+        }
+    }
+}
+
+#[test]
+fn records_are_json_lines() {
+    let mut out = String::new();
+    let mut snap = Snapshot::new(&mut out, 1000);
+    snap.pool(ResourceKind::Qp, 64, &Qps(vec![17, 18]));
+    snap.queue(
+        17,
+        "sq",
+        QueueIndices {
+            slots: 8,
+            producer: 1,
+            consumer: 6,
+        },
+    );
+    snap.record("qp")
+        .u64("qpn", 17)
+        .str("state", "RTS")
+        .bool("traced", false)
+        .finish();
+    assert_eq!(snap.records(), 6);
+    snap.finish().unwrap();
+
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            r#"{"type":"snapshot","version":1,"stamp":1000}"#,
+            r#"{"type":"pool","kind":"QP","len":2,"capacity":64}"#,
+            r#"{"type":"object","kind":"QP","index":17,"owner":42}"#,
+            r#"{"type":"object","kind":"QP","index":18,"owner":42}"#,
+            r#"{"type":"queue","owner":17,"name":"sq","slots":8,"producer":1,"consumer":6,"count":3}"#,
+            r#"{"type":"qp","qpn":17,"state":"RTS","traced":false}"#,
+        ]
+    );
+}
+
+#[test]
+fn strings_are_escaped_and_resources_listed() {
+    let tracker = ResourceTracker::new();
+    tracker.add(ResourceKind::Mr);
+    let mut out = String::new();
+    let mut snap = Snapshot::new(&mut out, 0);
+    snap.record("note").str("text", "a \"b\"\\\n").finish();
+    snap.resources(&tracker);
+    snap.finish().unwrap();
+
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[1], r#"{"type":"note","text":"a \"b\"\\\u000a"}"#);
+    assert_eq!(lines.len(), 2 + ResourceKind::ALL.len());
+    assert!(lines.contains(&r#"{"type":"resources","kind":"MR","live":1}"#));
+    assert!(lines.contains(&r#"{"type":"resources","kind":"PD","live":0}"#));
+}
+
+#[test]
+fn objects_are_listed_on_their_own() {
+    let mut out = String::new();
+    let mut snap = Snapshot::new(&mut out, 0);
+    snap.objects(&Qps(vec![20]));
+    snap.objects(&Qps(vec![]));
+    assert_eq!(snap.records(), 2);
+    snap.finish().unwrap();
+    assert_eq!(
+        out.lines().nth(1),
+        Some(r#"{"type":"object","kind":"QP","index":20,"owner":42}"#)
+    );
+}
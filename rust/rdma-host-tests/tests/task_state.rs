@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::task_state::{Drive, Finish, Pass, TaskState};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn request_while_running_reruns() {
+    let task = TaskState::new();
+    assert!(task.reserve());
+    assert!(task.is_busy());
+    assert!(!task.reserve());
+    assert!(task.is_armed());
+    assert_eq!(task.finish(), Finish::Rerun);
+    assert!(task.is_busy());
+    assert_eq!(task.finish(), Finish::Idle);
+    assert!(!task.is_busy());
+    assert!(task.reserve());
+}
+
+#[test]
+fn drive_yields_after_budget() {
+    let task = TaskState::new();
+    assert!(task.reserve());
+    let mut passes = 0;
+    assert_eq!(
+        task.drive(4, || {
+            passes += 1;
+            Pass::More
+        }),
+        Drive::Yield
+    );
+    assert_eq!(passes, 4);
+    assert!(task.is_busy());
+    assert_eq!(task.drive(4, || Pass::Done), Drive::Idle);
+    assert!(!task.is_busy());
+}
+
+#[test]
+fn disabled_task_runs_once_enabled() {
+    let task = TaskState::new();
+    assert!(task.reserve());
+    let drive = task.drive(16, || {
+        task.disable();
+        Pass::More
+    });
+    assert_eq!(drive, Drive::Paused);
+    assert!(!task.is_busy());
+    assert!(task.is_disabled());
+    assert!(!task.reserve());
+    assert!(task.enable());
+    assert!(task.is_busy());
+    assert_eq!(task.drive(16, || Pass::Done), Drive::Idle);
+
+    // Nothing was requested while disabled.
+    task.disable();
+    assert!(!task.enable());
+    assert!(!task.is_busy());
+}
+
+#[test]
+fn killed_task_never_runs() {
+    let task = TaskState::new();
+    assert!(task.reserve());
+    task.kill();
+    assert_eq!(task.drive(16, || Pass::More), Drive::Paused);
+    assert!(!task.reserve());
+    assert!(!task.is_armed());
+    assert!(!task.enable());
+    assert!(task.is_disabled());
+}
+
+#[test]
+fn concurrent_requests_run_serially() {
+    let task = Arc::new(TaskState::new());
+    let running = Arc::new(AtomicUsize::new(0));
+    let work = Arc::new(AtomicUsize::new(0));
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let (task, running, work) = (task.clone(), running.clone(), work.clone());
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    work.fetch_add(1, Ordering::SeqCst);
+                    if !task.reserve() {
+                        continue;
+                    }
+                    let drive = task.drive(usize::MAX, || {
+                        assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                        let pending = work.swap(0, Ordering::SeqCst);
+                        running.fetch_sub(1, Ordering::SeqCst);
+                        if pending == 0 {
+                            Pass::Done
+                        } else {
+                            Pass::More
+                        }
+                    });
+                    assert_eq!(drive, Drive::Idle);
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    // Every request was either served or armed a pass that served it.
+    assert!(!task.is_busy());
+    assert_eq!(work.load(Ordering::SeqCst), 0);
+}
@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::timeout::{ack_timeout_usecs, rnr_nak_usecs, MAX_ACK_TIMEOUT};
+
+#[test]
+fn ack_timeout() {
+    assert_eq!(ack_timeout_usecs(0), None);
+    assert_eq!(ack_timeout_usecs(1), Some(8));
+    assert_eq!(ack_timeout_usecs(14), Some(67_108));
+    assert_eq!(
+        ack_timeout_usecs(MAX_ACK_TIMEOUT + 1),
+        ack_timeout_usecs(MAX_ACK_TIMEOUT)
+    );
+}
+
+#[test]
+fn rnr_nak_codes() {
+    assert_eq!(rnr_nak_usecs(0), 655_360);
+    assert_eq!(rnr_nak_usecs(1), 10);
+    assert_eq!(rnr_nak_usecs(25), 61_440);
+    assert_eq!(rnr_nak_usecs(31), 491_520);
+    // The syndrome bits above the timer are ignored.
+    assert_eq!(rnr_nak_usecs(0x20 | 14), rnr_nak_usecs(14));
+    // Codes from 1 up get longer.
+    assert!((2..32).all(|code| rnr_nak_usecs(code) > rnr_nak_usecs(code - 1)));
+}
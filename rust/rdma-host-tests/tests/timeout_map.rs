@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::timeout_map::{reached, TimeoutMap};
+
+#[test]
+fn insert_lookup_remove() {
+    let mut map = TimeoutMap::<u32, &str, 2>::new();
+    assert_eq!(map.insert(1, "a"), Ok(None));
+    assert_eq!(map.insert(2, "b"), Ok(None));
+    assert_eq!(map.insert(3, "c"), Err((3, "c")));
+    assert_eq!(map.insert(1, "A"), Ok(Some("a")));
+    assert_eq!(map.get(&1), Some(&"A"));
+    assert_eq!(map.find(|_, v| *v == "b"), Some((&2, &"b")));
+    assert_eq!(map.remove(&2), Some("b"));
+    assert_eq!(map.remove(&2), None);
+    assert_eq!(map.len(), 1);
+    map.retain(|k, _| *k != 1);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn only_scheduled_entries_expire() {
+    let mut map = TimeoutMap::<u32, u32, 4>::new();
+    for k in 0..4 {
+        map.insert(k, k * 10).unwrap();
+    }
+    assert!(map.schedule(&1, 100));
+    assert!(map.schedule(&2, 50));
+    // The first deadline sticks.
+    assert!(map.schedule(&2, 500));
+    assert!(map.schedule(&3, 80));
+    assert!(!map.schedule(&9, 10));
+    assert!(map.cancel(&3));
+    assert!(!map.cancel(&0));
+    assert_eq!(map.next_deadline(0), Some(50));
+
+    let mut gone = Vec::new();
+    assert_eq!(map.expire(60, |k, v| gone.push((k, v))), 1);
+    assert_eq!(gone, [(2, 20)]);
+    assert_eq!(map.next_deadline(60), Some(100));
+    assert_eq!(map.expire(1000, |_, _| {}), 1);
+    assert_eq!(map.next_deadline(1000), None);
+    assert_eq!(map.len(), 2);
+
+    // Re-inserting clears the schedule.
+    map.schedule(&0, 5);
+    map.insert(0, 1).unwrap();
+    assert_eq!(map.expire(10, |_, _| {}), 0);
+}
+
+#[test]
+fn deadlines_wrap() {
+    assert!(reached(5, u64::MAX - 5));
+    assert!(!reached(u64::MAX - 5, 5));
+    let mut map = TimeoutMap::<u8, (), 2>::new();
+    map.insert(1, ()).unwrap();
+    map.insert(2, ()).unwrap();
+    map.schedule(&1, 3);
+    map.schedule(&2, u64::MAX - 1);
+    assert_eq!(map.next_deadline(u64::MAX - 10), Some(u64::MAX - 1));
+    assert_eq!(map.expire(0, |_, _| {}), 1);
+    assert_eq!(map.get(&1), Some(&()));
+}
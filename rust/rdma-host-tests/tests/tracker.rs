@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::tracker::{ResourceKind, ResourceTracker};
+
+#[test]
+fn summary_reports_leaked_kinds() {
+    let tracker = ResourceTracker::new();
+    assert!(tracker.summary().is_clean());
+
+    tracker.add(ResourceKind::Qp);
+    tracker.add(ResourceKind::Qp);
+    tracker.add(ResourceKind::Mr);
+    tracker.add(ResourceKind::Cq);
+    tracker.remove(ResourceKind::Cq);
+
+    let summary = tracker.summary();
+    assert_eq!(summary.total(), 3);
+    assert_eq!(
+        summary.leaked().collect::<Vec<_>>(),
+        [(ResourceKind::Qp, 2), (ResourceKind::Mr, 1)]
+    );
+}
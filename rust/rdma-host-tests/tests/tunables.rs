@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use rdma_host_tests::tunables::{QpLimits, Tunable, TunableLimits, TuneError};
+use std::sync::Arc;
+use std::thread;
+
+const CEILING: QpLimits = QpLimits {
+    max_qp_wr: 1024,
+    max_inline_data: 256,
+    pacing_rate: 0,
+};
+
+#[test]
+fn set_within_ceiling() {
+    let limits = TunableLimits::new(CEILING);
+    assert_eq!(limits.snapshot(), CEILING);
+    limits.set(Tunable::MaxQpWr, 128).unwrap();
+    limits.store(Tunable::PacingRate, "10000\n").unwrap();
+    assert_eq!(
+        limits.snapshot(),
+        QpLimits {
+            max_qp_wr: 128,
+            max_inline_data: 256,
+            pacing_rate: 10000,
+        }
+    );
+    assert!(limits.snapshot().allows(128, 1, 0));
+    assert!(!limits.snapshot().allows(129, 1, 0));
+
+    assert_eq!(limits.set(Tunable::MaxQpWr, 0), Err(TuneError::OutOfRange));
+    assert_eq!(
+        limits.set(Tunable::MaxQpWr, 2048),
+        Err(TuneError::OutOfRange)
+    );
+    assert_eq!(
+        limits.store(Tunable::MaxInlineData, "lots"),
+        Err(TuneError::Parse)
+    );
+    assert_eq!(limits.get(Tunable::MaxQpWr), 128);
+
+    limits.reset();
+    assert_eq!(limits.snapshot(), CEILING);
+}
+
+#[test]
+fn names_and_show() {
+    for t in Tunable::ALL {
+        assert_eq!(Tunable::from_name(t.name()), Some(t));
+    }
+    assert_eq!(Tunable::from_name("max_cqe"), None);
+
+    let limits = TunableLimits::new(CEILING);
+    let mut out = String::new();
+    limits.show(Tunable::MaxInlineData, &mut out).unwrap();
+    assert_eq!(out, "256\n");
+}
+
+#[test]
+fn snapshots_are_consistent() {
+    let limits = Arc::new(TunableLimits::new(QpLimits {
+        max_qp_wr: 1 << 16,
+        max_inline_data: 1 << 16,
+        pacing_rate: 0,
+    }));
+    limits.set(Tunable::MaxQpWr, 1).unwrap();
+    limits.set(Tunable::MaxInlineData, 1).unwrap();
+    let writer = {
+        let limits = limits.clone();
+        thread::spawn(move || {
+            for v in 2..20_000 {
+                // Values are only ever changed together, so snapshots must see them equal.
+                limits.set(Tunable::MaxQpWr, v).unwrap();
+                limits.set(Tunable::MaxInlineData, v).unwrap();
+            }
+        })
+    };
+    for _ in 0..20_000 {
+        let s = limits.snapshot();
+        assert!(s.max_inline_data == s.max_qp_wr || s.max_inline_data + 1 == s.max_qp_wr);
+    }
+    writer.join().unwrap();
+}
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: GPL-2.0
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use rdma_host_tests::violation::{
+    expected_qkey, pkey_match, KeyViolations, Violation, ViolationKind, GSI_QKEY,
+};
+
+#[test]
+fn pkey_matching_rules() {
+    assert!(pkey_match(0xffff, 0xffff));
+    assert!(pkey_match(0x7fff, 0xffff));
+    assert!(!pkey_match(0x7fff, 0x7fff));
+    assert!(!pkey_match(0x8001, 0x8002));
+    assert!(!pkey_match(0x8000, 0x8000));
+}
+
+#[test]
+fn gsi_uses_the_well_known_qkey() {
+    assert_eq!(expected_qkey(1, 0x1234), GSI_QKEY);
+    assert_eq!(expected_qkey(17, 0x1234), 0x1234);
+}
+
+#[test]
+fn violations_are_counted() {
+    let v = KeyViolations::new();
+    assert!(v.check_pkey(0xffff, 0x8001, 17, 3).is_err());
+    assert!(v.check_pkey(0xffff, 0xffff, 17, 3).is_ok());
+    let err = v.check_qkey(0x11, 0x22, 17, 3).unwrap_err();
+    assert_eq!(
+        err,
+        Violation {
+            kind: ViolationKind::QKey,
+            expected: 0x11,
+            received: 0x22,
+            dest_qp: 17,
+            src_qp: 3,
+        }
+    );
+    assert!(v.check_qkey(0, GSI_QKEY, 1, 3).is_ok());
+    assert_eq!((v.pkey_violations(), v.qkey_violations()), (1, 1));
+    v.reset();
+    assert_eq!((v.pkey_violations(), v.qkey_violations()), (0, 0));
+}
+
+static HOOK_CALLS: AtomicU32 = AtomicU32::new(0);
+
+fn hook(violation: &Violation) {
+    assert_eq!(violation.kind, ViolationKind::PKey);
+    HOOK_CALLS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[test]
+fn hook_is_called() {
+    let v = KeyViolations::new().with_hook(hook);
+    let _ = v.check_pkey(0xffff, 0x0001, 2, 3);
+    let _ = v.check_pkey(0xffff, 0xffff, 2, 3);
+    assert_eq!(HOOK_CALLS.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Provider operations shared by the RDMA smoke test and capability probe.
+//!
+//! Both samples register every enabled provider type only to exercise the registration paths, so
+//! the operations below create no device and bind to nothing. Included with `#[path]`, this file
+//! is not a module of its own.
+
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+use kernel::ib::cq::{CqInitAttr, CqNotify};
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+use kernel::ib::device::{DeviceAttr, PortAttr, PortImmutable};
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+use kernel::ib::qp::QpInitAttr;
+use kernel::rdma::prelude::*;
+
+/// Soft-RoCE operations refusing every link and every packet.
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+pub(crate) struct StubRxe;
+
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+#[vtable]
+impl rxe::RxeOperation for StubRxe {
+    type Device = StubRxeDev;
+
+    fn notify() -> Result {
+        Ok(())
+    }
+    fn newlink(_ibdev_name: &CStr, _ndev: &NetDevice, _params: &LinkParams) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn udp_recv(_skb: &SkBuff) -> UdpRecvVerdict {
+        UdpRecvVerdict::Refused
+    }
+}
+
+/// Devices of [`StubRxe`], which never creates any.
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+pub(crate) struct StubRxeDev;
+
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+#[vtable]
+impl IbDeviceOperations for StubRxeDev {
+    type Data = ();
+
+    const DRIVER_ID: DriverId = DriverId::Rxe;
+
+    fn query_device(_dev: &DeviceRef<Self>, _attr: &mut DeviceAttr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn query_port(_dev: &DeviceRef<Self>, _port: u32, _attr: &mut PortAttr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn get_port_immutable(_dev: &DeviceRef<Self>, _port: u32, _imm: &mut PortImmutable) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn alloc_pd(_dev: &DeviceRef<Self>) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn create_cq(_dev: &DeviceRef<Self>, _attr: &mut CqInitAttr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn poll_cq(_cq: &CompletionQueue<Self>) -> Option<WorkCompletion> {
+        None
+    }
+    fn req_notify_cq(_cq: &CompletionQueue<Self>, _notify: CqNotify) -> Result<bool> {
+        Err(EOPNOTSUPP)
+    }
+    fn create_qp(_dev: &DeviceRef<Self>, _init: &QpInitAttr) -> Result<(u32, ())> {
+        Err(EOPNOTSUPP)
+    }
+    fn modify_qp(_qp: &QueuePair<Self>, _attr: &QpAttr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn post_send(_qp: &QueuePair<Self>, _wr: &SendWr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn post_recv(_qp: &QueuePair<Self>, _wr: &RecvWr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+}
+
+/// mlx4 interface keeping no state per device and ignoring events.
+#[cfg(CONFIG_RUST_RDMA_MLX4)]
+pub(crate) struct StubMlx4;
+
+#[cfg(CONFIG_RUST_RDMA_MLX4)]
+#[vtable]
+impl mlx4::Mlx4Operation for StubMlx4 {
+    type Context = ();
+
+    fn add(_dev: &mlx4::Mlx4Device) -> Result<Pin<Box<()>>> {
+        Ok(Pin::from(Box::try_new(())?))
+    }
+    fn remove(_dev: &mlx4::Mlx4Device, _context: Pin<Box<()>>) {}
+}
+
+/// Auxiliary driver matching no device.
+#[cfg(CONFIG_RUST_RDMA_AUXILIARY)]
+pub(crate) struct StubAuxiliary;
+
+#[cfg(CONFIG_RUST_RDMA_AUXILIARY)]
+#[vtable]
+impl auxiliary::Driver for StubAuxiliary {
+    type Context = ();
+
+    // No core driver creates such a device, so nothing is probed.
+    const ID_TABLE: &'static [auxiliary::DeviceId] =
+        &[auxiliary::DeviceId::new("rdma_stubs.none", 0)];
+
+    fn probe(_dev: &auxiliary::Device, _id: &auxiliary::DeviceId) -> Result<Pin<Box<()>>> {
+        Err(ENODEV)
+    }
+    fn remove(_dev: &auxiliary::Device, _context: Pin<Box<()>>) {}
+}
@@ -2,8 +2,7 @@
 
 //! Rust infiniband mls4 device sample.
 
-use kernel::mlx4;
-use kernel::prelude::*;
+use kernel::rdma::prelude::*;
 
 module! {
     type: RustMlx4,
@@ -15,16 +14,86 @@ module! {
 
 struct RustMlx4Ops;
 
+struct RustMlx4Context {
+    num_ports: u32,
+    _eq: mlx4::EqVector,
+    _qpns: mlx4::QpnAllocator,
+    sriov: mlx4::Sriov,
+}
+
 #[vtable]
 impl mlx4::Mlx4Operation for RustMlx4Ops {
-    fn add() -> Result {
-        Ok(())
+    type Context = RustMlx4Context;
+
+    fn add(dev: &mlx4::Mlx4Device) -> Result<Pin<Box<RustMlx4Context>>> {
+        let caps = dev.caps();
+        pr_info!(
+            "mlx4 board {}, firmware {}, {} ports, {} QPs, {} CQs\n",
+            dev.board_id(),
+            dev.fw_version(),
+            caps.num_ports,
+            caps.num_qps - caps.reserved_qps,
+            caps.num_cqs - caps.reserved_cqs
+        );
+        for port in 1..=caps.num_ports {
+            let port = dev.query_port(port)?;
+            pr_info!(
+                "mlx4 port {}: {:?}, MTU {:?}, {} GIDs\n",
+                port.port,
+                port.port_type,
+                port.mtu,
+                port.gid_table_len
+            );
+        }
+        // Ask the firmware directly too; the mailbox is freed at the end of the block.
+        {
+            let mut outbox = mlx4::Mailbox::try_new(dev)?;
+            mlx4::Mlx4Cmd::query_port(1).run_box(dev, mlx4::CmdInput::none(), &mut outbox)?;
+            pr_info!("mlx4 QUERY_PORT 1: {:#010x}\n", outbox.read_be32(0)?);
+        }
+        // One completion vector is enough for the sample.
+        // SAFETY: The vector is kept in the context, which `remove` drops before the device goes
+        // away.
+        let eq = unsafe { mlx4::EqVector::try_new(dev, 1, 0)? };
+        pr_info!(
+            "mlx4 completion EQ {} of {} for port 1\n",
+            eq.vector(),
+            dev.eqs_per_port(1)
+        );
+        // A small aligned block of QP numbers, sub-allocated like the steerable UD QPs.
+        // SAFETY: The range is kept in the context, which `remove` drops before the device goes
+        // away.
+        let range = unsafe { mlx4::QpRange::try_new(dev, 8, 8)? };
+        let mut qpns = mlx4::QpnAllocator::try_new(range)?;
+        let qpn = qpns.alloc()?;
+        pr_info!(
+            "mlx4 QPN {:#x} from range {:#x}+{}\n",
+            qpn,
+            qpns.qp_range().base(),
+            qpns.qp_range().count()
+        );
+        qpns.free(qpn)?;
+        Ok(Pin::from(Box::try_new(RustMlx4Context {
+            num_ports: caps.num_ports,
+            _eq: eq,
+            _qpns: qpns,
+            sriov: mlx4::Sriov::try_new()?,
+        })?))
+    }
+    fn remove(_dev: &mlx4::Mlx4Device, context: Pin<Box<RustMlx4Context>>) {
+        pr_info!("mlx4 device with {} ports removed\n", context.num_ports);
+    }
+    fn sriov(context: &RustMlx4Context) -> Option<&mlx4::Sriov> {
+        Some(&context.sriov)
+    }
+    fn port_event(_dev: &mlx4::Mlx4Device, port: u8, event: mlx4::PortEvent) {
+        pr_info!("mlx4 port {}: {:?}\n", port, event);
     }
-    fn remove() -> Result {
-        Ok(())
+    fn slave_init(dev: &mlx4::Mlx4Device, slave: mlx4::SlaveId) {
+        pr_info!("mlx4 slave {} of {} up\n", slave, dev.num_slaves());
     }
-    fn event() -> Result {
-        Ok(())
+    fn slave_shutdown(_dev: &mlx4::Mlx4Device, slave: mlx4::SlaveId) {
+        pr_info!("mlx4 slave {} down\n", slave);
     }
 }
 
@@ -36,9 +105,12 @@ impl kernel::Module for RustMlx4 {
     fn init(name: &'static CStr, _module: &'static ThisModule) -> Result<Self> {
         pr_info!("Rust infiniband mlx4 driver sample (init)\n");
 
-        Ok(RustMlx4 {
-            _dev: mlx4::Registration::new_pinned(name)?,
-        })
+        let dev = mlx4::Registration::<RustMlx4Ops>::new_pinned(
+            name,
+            mlx4::Registration::<RustMlx4Ops>::example_interface_config(),
+        )?;
+        dev.set_event_mask(mlx4::Registration::<RustMlx4Ops>::example_config());
+        Ok(RustMlx4 { _dev: dev })
     }
 }
 
@@ -0,0 +1,197 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Rust RDMA capability probe.
+//!
+//! Registers every provider type enabled in Kconfig, reads back the configuration each
+//! registration ended up with, logs it and unregisters again, so CI can load the module and
+//! check the kernel log. Every line is `<provider> key=value ...`, with `enabled=0` for the
+//! providers left out of the build, and a final `done` line once all of them went through.
+//! Before that, the devices of the Rust providers already registered are logged with the limits
+//! they reported and the state of each of their ports, one `device` and one `port` line each, and
+//! the soft-RoCE ones with the data path settings they ended up with, in a `config` line.
+//! Loading fails with the error of the first registration that did not.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+use kernel::rdma::dev_config::ConfigKey;
+use kernel::rdma::prelude::*;
+
+module! {
+    type: RustRdmaProbe,
+    name: "rust_rdma_probe",
+    author: "Rust for Linux Contributors",
+    description: "Rust RDMA capability probe",
+    license: "GPL",
+}
+
+#[path = "rdma_stubs.rs"]
+mod stubs;
+
+use stubs::*;
+
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+fn probe_rxe(name: &'static CStr) -> Result<u32> {
+    let config = rxe::Registration::<StubRxe>::example_config();
+    let dev = rxe::Registration::<StubRxe>::new_pinned(name, config)?;
+    dev.prewarm()?;
+    pr_info!(
+        "rxe enabled=1 port={} legacy_port={} per_netns={} flap_holdoff_ms={} warm={}\n",
+        config.port(),
+        config.legacy_port().unwrap_or(0),
+        config.per_netns() as u8,
+        config.flap_holdoff(),
+        dev.is_warm() as u8,
+    );
+    Ok(1)
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_RXE))]
+fn probe_rxe(_name: &'static CStr) -> Result<u32> {
+    pr_info!("rxe enabled=0\n");
+    Ok(0)
+}
+
+#[cfg(CONFIG_RUST_RDMA_MLX4)]
+fn probe_mlx4(name: &'static CStr) -> Result<u32> {
+    let config = mlx4::Registration::<StubMlx4>::example_interface_config();
+    let dev = mlx4::Registration::<StubMlx4>::new_pinned(name, config)?;
+    let requested = mlx4::Registration::<StubMlx4>::example_config();
+    dev.set_event_mask(requested);
+    pr_info!(
+        "mlx4 enabled=1 protocol={:?} flags={:#x} event_mask_requested={:#x} event_mask={:#x}\n",
+        config.protocol(),
+        config.flags(),
+        requested.bits(),
+        dev.event_mask().bits(),
+    );
+    Ok(1)
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_MLX4))]
+fn probe_mlx4(_name: &'static CStr) -> Result<u32> {
+    pr_info!("mlx4 enabled=0\n");
+    Ok(0)
+}
+
+#[cfg(CONFIG_RUST_RDMA_AUXILIARY)]
+fn probe_auxiliary(name: &'static CStr, module: &'static ThisModule) -> Result<u32> {
+    drop(auxiliary::Registration::<StubAuxiliary>::new_pinned(
+        name, module,
+    )?);
+    pr_info!(
+        "auxiliary enabled=1 ids={}\n",
+        <StubAuxiliary as auxiliary::Driver>::ID_TABLE.len()
+    );
+    Ok(1)
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_AUXILIARY))]
+fn probe_auxiliary(_name: &'static CStr, _module: &'static ThisModule) -> Result<u32> {
+    pr_info!("auxiliary enabled=0\n");
+    Ok(0)
+}
+
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+struct ProbeObserver {
+    devices: AtomicU32,
+}
+
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+impl ib::DeviceObserver for ProbeObserver {
+    fn added(&self, dev: &ib::ObservedDevice) {
+        self.devices.fetch_add(1, Ordering::Relaxed);
+        let driver = dev.driver_name().unwrap_or(kernel::c_str!("builtin"));
+        let attrs = dev.attrs();
+        pr_info!(
+            "device name={} driver={} ports={} fw_ver={:#x} max_qp={} max_qp_wr={} max_cq={} max_cqe={} max_mr={} max_mr_size={:#x} max_pd={} max_srq={}\n",
+            dev.name(),
+            driver,
+            dev.phys_port_cnt(),
+            attrs.fw_ver(),
+            attrs.max_qp(),
+            attrs.max_qp_wr(),
+            attrs.max_cq(),
+            attrs.max_cqe(),
+            attrs.max_mr(),
+            attrs.max_mr_size(),
+            attrs.max_pd(),
+            attrs.max_srq(),
+        );
+        for port in 1..=dev.phys_port_cnt() {
+            match dev.query_port(port) {
+                Ok(attr) => pr_info!(
+                    "port device={} port={} state={:?} phys_state={} active_mtu={} width={} speed={} gids={}\n",
+                    dev.name(),
+                    port,
+                    attr.state(),
+                    attr.phys_state(),
+                    attr.active_mtu().map_or(0, |mtu| mtu.bytes()),
+                    attr.active_width(),
+                    attr.active_speed(),
+                    attr.gid_tbl_len(),
+                ),
+                Err(e) => pr_info!(
+                    "port device={} port={} errno={}\n",
+                    dev.name(),
+                    port,
+                    e.to_kernel_errno()
+                ),
+            }
+        }
+        if dev.belongs_to(DriverId::Rxe) {
+            probe_config(dev);
+        }
+    }
+    fn removed(&self, _dev: &ib::ObservedDevice) {}
+}
+
+/// Logs the data path settings of the soft-RoCE device `dev`, as its sysfs attributes show them.
+///
+/// Devices without these attributes, e.g. of providers that do not expose them, are skipped.
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+fn probe_config(dev: &ib::ObservedDevice) {
+    let mut bufs = [[0u8; 32]; ConfigKey::ALL.len()];
+    let mut values = [""; ConfigKey::ALL.len()];
+    for ((key, buf), value) in ConfigKey::ALL.iter().zip(&mut bufs).zip(&mut values) {
+        match dev.show_attr(key.name(), buf) {
+            Ok(text) => *value = text,
+            Err(_) => return,
+        }
+    }
+    pr_info!(
+        "config device={} mtu_override={} crc_mode={} tx_pacing_kbps={}\n",
+        dev.name(),
+        values[0],
+        values[1],
+        values[2],
+    );
+}
+
+/// Logs the devices registered so far, of Rust providers, and returns their number.
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+fn probe_devices() -> Result<u32> {
+    let reg = ib::ObserverRegistration::try_new(ProbeObserver {
+        devices: AtomicU32::new(0),
+    })?;
+    let devices = reg.observer().devices.load(Ordering::Relaxed);
+    pr_info!("verbs enabled=1 devices={}\n", devices);
+    Ok(devices)
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_CORE_VERBS))]
+fn probe_devices() -> Result<u32> {
+    pr_info!("verbs enabled=0\n");
+    Ok(0)
+}
+
+struct RustRdmaProbe;
+
+impl kernel::Module for RustRdmaProbe {
+    fn init(name: &'static CStr, module: &'static ThisModule) -> Result<Self> {
+        let devices = probe_devices()?;
+        let providers = probe_rxe(name)? + probe_mlx4(name)? + probe_auxiliary(name, module)?;
+        pr_info!("done providers={} devices={}\n", providers, devices);
+        Ok(RustRdmaProbe)
+    }
+}
@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: GPL-2.0
+
+//! Rust RDMA registration smoke test.
+//!
+//! Registers every provider type enabled in Kconfig with its example configuration and
+//! unregisters it right away, so that loading the module exercises all registration paths.
+//! Loading fails with the error of the first registration that did not go through.
+
+use kernel::rdma::prelude::*;
+
+module! {
+    type: RustRdmaSmoke,
+    name: "rust_rdma_smoke",
+    author: "Rust for Linux Contributors",
+    description: "Rust RDMA registration smoke test",
+    license: "GPL",
+}
+
+#[path = "rdma_stubs.rs"]
+mod stubs;
+
+use stubs::*;
+
+#[cfg(CONFIG_RUST_RDMA_RXE)]
+fn smoke_rxe(name: &'static CStr) -> Result {
+    let config = rxe::Registration::<StubRxe>::example_config();
+    let dev = rxe::Registration::<StubRxe>::new_pinned(name, config)?;
+    // Sockets are only created on the first link unless asked for.
+    dev.prewarm()?;
+    drop(dev);
+    pr_info!("rxe registration: ok\n");
+    Ok(())
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_RXE))]
+fn smoke_rxe(_name: &'static CStr) -> Result {
+    Ok(())
+}
+
+#[cfg(CONFIG_RUST_RDMA_MLX4)]
+fn smoke_mlx4(name: &'static CStr) -> Result {
+    let config = mlx4::Registration::<StubMlx4>::example_interface_config();
+    let dev = mlx4::Registration::<StubMlx4>::new_pinned(name, config)?;
+    dev.set_event_mask(mlx4::Registration::<StubMlx4>::example_config());
+    drop(dev);
+    pr_info!("mlx4 registration: ok\n");
+    Ok(())
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_MLX4))]
+fn smoke_mlx4(_name: &'static CStr) -> Result {
+    Ok(())
+}
+
+#[cfg(CONFIG_RUST_RDMA_AUXILIARY)]
+fn smoke_auxiliary(name: &'static CStr, module: &'static ThisModule) -> Result {
+    drop(auxiliary::Registration::<StubAuxiliary>::new_pinned(
+        name, module,
+    )?);
+    pr_info!("auxiliary registration: ok\n");
+    Ok(())
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_AUXILIARY))]
+fn smoke_auxiliary(_name: &'static CStr, _module: &'static ThisModule) -> Result {
+    Ok(())
+}
+
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+struct SmokeObserver;
+
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+impl ib::DeviceObserver for SmokeObserver {
+    fn added(&self, dev: &ib::ObservedDevice) {
+        pr_info!("observed {}\n", dev.name());
+    }
+    fn removed(&self, _dev: &ib::ObservedDevice) {}
+}
+
+#[cfg(CONFIG_RUST_RDMA_CORE_VERBS)]
+fn smoke_observer() -> Result {
+    drop(ib::ObserverRegistration::try_new(SmokeObserver)?);
+    pr_info!("observer registration: ok\n");
+    Ok(())
+}
+
+#[cfg(not(CONFIG_RUST_RDMA_CORE_VERBS))]
+fn smoke_observer() -> Result {
+    Ok(())
+}
+
+struct RustRdmaSmoke;
+
+impl kernel::Module for RustRdmaSmoke {
+    fn init(name: &'static CStr, module: &'static ThisModule) -> Result<Self> {
+        smoke_observer()?;
+        smoke_rxe(name)?;
+        smoke_mlx4(name)?;
+        smoke_auxiliary(name, module)?;
+        Ok(RustRdmaSmoke)
+    }
+}
@@ -1,9 +1,44 @@
 // SPDX-License-Identifier: GPL-2.0
 
 //! Rust infiniband Soft-RoCE driver sample.
+//!
+//! Each `rdma link add` creates a device with an allowlist of peers, empty at first, which is
+//! read and written through its sysfs attribute `peers`, e.g.
+//! `echo 10.0.0.0/8 > /sys/class/infiniband/rxe0/peers`. Packets from other peers are dropped
+//! and counted in `peer_drops`. The accepted packets are counted in `rcvd_pkts`, which
+//! `rdma statistic show` reports along with the other counters of the device, and the PMA as
+//! its `PortCounters` and `PortCountersExtended`. Packets sent to a multicast group are only
+//! accepted while a QP is attached to it.
+//!
+//! The QP limits `max_qp_wr`, `max_inline_data` and `pacing_rate_kbps` are sysfs attributes too,
+//! which may be lowered at runtime, e.g. `echo 256 > /sys/class/infiniband/rxe0/max_qp_wr`. They
+//! apply to the QPs created afterwards, and `query_device` reports them.
+//!
+//! The data path settings `mtu_override`, `crc_mode` and `tx_pacing_kbps` start at the overrides
+//! of the device, and are swapped under RCU when written. Received packets have their ICRC
+//! checked unless `crc_mode` says otherwise, and are dropped if it does not match. Packets whose
+//! P_Key does not match the default one of the port are dropped too, and counted in
+//! `pkey_violations`.
+//!
+//! The devices report `rust_rxe` and [`DRIVER_VERSION`] as their firmware version.
+//!
+//! Devices created with the generic netlink `newlink` command of the `rdma_rxe` family may
+//! override their limits, which `query_device` reports, and their UDP destination port, which
+//! the packets they send would use through [`rxe::net::TxParams::for_link`].
 
-use kernel::prelude::*;
-use kernel::rxe;
+use core::fmt::{self, Write};
+
+use kernel::bindings;
+use kernel::c_str;
+use kernel::ib::cq::{CqInitAttr, CqNotify};
+use kernel::ib::device::{DeviceAttr, PortAttr, PortImmutable, PortProtocol, PortState};
+use kernel::ib::qp::QpInitAttr;
+use kernel::ib::MadRequest;
+use kernel::rdma::port_counters::PortCounterAging;
+use kernel::rdma::prelude::*;
+use kernel::rdma::tunables::{QpLimits, Tunable, TunableLimits};
+use kernel::rdma::violation::DEFAULT_PKEY_FULL;
+use kernel::sync::smutex::Mutex;
 
 module! {
     type: RustRxe,
@@ -13,18 +48,270 @@ module! {
     license: "GPL",
 }
 
+/// Version reported after the module name as the firmware version of the devices, e.g. by
+/// `ibv_devinfo`.
+const DRIVER_VERSION: &str = "0.1";
+
+/// Largest number of peer prefixes allowed on a device.
+const MAX_PEERS: usize = 8;
+
+/// Default ceiling of the QP limits, like `RXE_MAX_QP_WR` and `RXE_MAX_INLINE_DATA`.
+const QP_LIMITS: QpLimits = QpLimits {
+    max_qp_wr: 0x4000,
+    max_inline_data: 400,
+    pacing_rate: 0,
+};
+
+/// `modify_qp` failures injected through debugfs.
+static QP_FAULTS: rxe::QpFaultInjector = rxe::QpFaultInjector::new();
+
+/// The sysfs attributes of the sample's devices.
+static ATTRS: AttributeGroup<RustRxeDev, 8> = AttributeGroup::new(
+    None,
+    [
+        DeviceAttribute::new(c_str!("peers"), show_peers).with_store(store_peers),
+        DeviceAttribute::new(c_str!("peer_drops"), show_peer_drops),
+        DeviceAttribute::new(c_str!("max_qp_wr"), show_max_qp_wr).with_store(store_max_qp_wr),
+        DeviceAttribute::new(c_str!("max_inline_data"), show_max_inline_data)
+            .with_store(store_max_inline_data),
+        DeviceAttribute::new(c_str!("pacing_rate_kbps"), show_pacing_rate)
+            .with_store(store_pacing_rate),
+        DeviceAttribute::new(c_str!("mtu_override"), show_mtu_override)
+            .with_store(store_mtu_override),
+        DeviceAttribute::new(c_str!("crc_mode"), show_crc_mode).with_store(store_crc_mode),
+        DeviceAttribute::new(c_str!("tx_pacing_kbps"), show_tx_pacing).with_store(store_tx_pacing),
+    ],
+);
+
+fn show_peers(dev: &DeviceRef<RustRxeDev>, w: &mut dyn Write) -> fmt::Result {
+    dev.data().peers.show(w)
+}
+
+fn store_peers(dev: &DeviceRef<RustRxeDev>, text: &str) -> Result {
+    dev.data().peers.store(text)
+}
+
+fn show_peer_drops(dev: &DeviceRef<RustRxeDev>, w: &mut dyn Write) -> fmt::Result {
+    writeln!(w, "{}", dev.data().peers.dropped())
+}
+
+/// Defines the `show` and `store` functions of the attribute of a [`Tunable`].
+macro_rules! tunable_attr {
+    ($show:ident, $store:ident, $tunable:expr) => {
+        fn $show(dev: &DeviceRef<RustRxeDev>, w: &mut dyn Write) -> fmt::Result {
+            dev.data().limits.show($tunable, w)
+        }
+
+        fn $store(dev: &DeviceRef<RustRxeDev>, text: &str) -> Result {
+            dev.data().limits.store($tunable, text).map_err(|_| EINVAL)
+        }
+    };
+}
+
+tunable_attr!(show_max_qp_wr, store_max_qp_wr, Tunable::MaxQpWr);
+tunable_attr!(
+    show_max_inline_data,
+    store_max_inline_data,
+    Tunable::MaxInlineData
+);
+tunable_attr!(show_pacing_rate, store_pacing_rate, Tunable::PacingRate);
+
+/// Defines the `show` and `store` functions of the attribute of a [`rxe::ConfigKey`].
+macro_rules! config_attr {
+    ($show:ident, $store:ident, $key:expr) => {
+        fn $show(dev: &DeviceRef<RustRxeDev>, w: &mut dyn Write) -> fmt::Result {
+            dev.data().config.show($key, w)
+        }
+
+        fn $store(dev: &DeviceRef<RustRxeDev>, text: &str) -> Result {
+            dev.data().config.store($key, text)
+        }
+    };
+}
+
+config_attr!(show_mtu_override, store_mtu_override, rxe::ConfigKey::Mtu);
+config_attr!(show_crc_mode, store_crc_mode, rxe::ConfigKey::CrcMode);
+config_attr!(show_tx_pacing, store_tx_pacing, rxe::ConfigKey::PacingRate);
+
+/// State of each device.
+struct RustRxeData {
+    peers: rxe::SourceFilter<MAX_PEERS>,
+    counters: rxe::Counters,
+    /// Baselines of the counters reported to the PMA.
+    pma: Mutex<PortCounterAging>,
+    mcast: rxe::McastTable,
+    /// Overrides of the defaults the device was created with.
+    params: LinkParams,
+    /// QP limits, tunable up to the defaults or their overrides.
+    limits: TunableLimits,
+    /// Settings of the data path, read once per packet.
+    config: rxe::RcuConfig<rxe::DeviceConfig>,
+}
+
+/// The sample's devices, which only filter the packets they receive.
+struct RustRxeDev;
+
+#[vtable]
+impl IbDeviceOperations for RustRxeDev {
+    type Data = RustRxeData;
+
+    const DRIVER_ID: DriverId = DriverId::Rxe;
+    const DRIVER_VERSION: &'static str = DRIVER_VERSION;
+
+    fn query_device(dev: &DeviceRef<Self>, attr: &mut DeviceAttr) -> Result {
+        attr.set_max_mcast_grp(rxe::mcast::MAX_MCAST_GRP)
+            .set_max_mcast_qp_attach(rxe::mcast::MAX_MCAST_QP_ATTACH as u32)
+            .set_max_total_mcast_qp_attach(rxe::mcast::MAX_TOTAL_MCAST_QP_ATTACH)
+            .set_link_params(&dev.data().params)
+            // The tuned limits never exceed the overrides.
+            .set_qp_limits(&dev.data().limits.snapshot());
+        Ok(())
+    }
+    fn query_port(_dev: &DeviceRef<Self>, _port: u32, attr: &mut PortAttr) -> Result {
+        attr.set_state(PortState::Active);
+        Ok(())
+    }
+    fn get_port_immutable(_dev: &DeviceRef<Self>, _port: u32, imm: &mut PortImmutable) -> Result {
+        imm.set_protocol(PortProtocol::RoceUdpEncap)
+            .set_pkey_tbl_len(1)
+            .set_gid_tbl_len(16)
+            .set_max_mad_size(kernel::rdma::mad::MAD_SIZE as u32);
+        Ok(())
+    }
+    fn alloc_pd(_dev: &DeviceRef<Self>) -> Result {
+        Ok(())
+    }
+    fn create_cq(_dev: &DeviceRef<Self>, _attr: &mut CqInitAttr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn poll_cq(_cq: &CompletionQueue<Self>) -> Option<WorkCompletion> {
+        None
+    }
+    fn req_notify_cq(_cq: &CompletionQueue<Self>, _notify: CqNotify) -> Result<bool> {
+        Ok(false)
+    }
+    fn create_qp(dev: &DeviceRef<Self>, init: &QpInitAttr) -> Result<(u32, ())> {
+        init.check_limits(&dev.data().limits.snapshot())?;
+        Err(EOPNOTSUPP)
+    }
+    fn modify_qp(_qp: &QueuePair<Self>, _attr: &QpAttr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn post_send(_qp: &QueuePair<Self>, _wr: &SendWr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn post_recv(_qp: &QueuePair<Self>, _wr: &RecvWr) -> Result {
+        Err(EOPNOTSUPP)
+    }
+    fn attach_mcast(qp: &QueuePair<Self>, mgid: &Gid, _mlid: u16) -> Result {
+        qp.device().data().mcast.attach(mgid, qp.qp_num())
+    }
+    fn detach_mcast(qp: &QueuePair<Self>, mgid: &Gid, _mlid: u16) -> Result {
+        qp.device().data().mcast.detach(mgid, qp.qp_num())
+    }
+    fn process_mad(
+        dev: &DeviceRef<Self>,
+        _port: u32,
+        req: &MadRequest<'_>,
+        out_mad: &mut [u8],
+    ) -> Result<MadResult> {
+        let data = dev.data();
+        let now = data.counters.values().port_totals();
+        if data.pma.lock().process(&now, req.mad, out_mad) {
+            return Ok(MadResult::SUCCESS.union(MadResult::REPLY));
+        }
+        Ok(MadResult::SUCCESS)
+    }
+    fn qp_faults(_dev: &DeviceRef<Self>) -> Option<&rxe::QpFaultInjector> {
+        Some(&QP_FAULTS)
+    }
+    fn alloc_hw_port_stats(_dev: &DeviceRef<Self>, _port: u32) -> Result<HwStats> {
+        Ok(rxe::Counters::HW_STATS)
+    }
+    fn get_hw_stats(dev: &DeviceRef<Self>, _port: u32, values: &mut [u64]) -> Result<usize> {
+        Ok(dev.data().counters.fill(values))
+    }
+}
+
 struct RustRxeOps;
 
 #[vtable]
 impl rxe::RxeOperation for RustRxeOps {
+    type Device = RustRxeDev;
+
     fn notify() -> Result {
         Ok(())
     }
-    fn newlink() -> Result {
-        Ok(())
+    fn newlink(ibdev_name: &CStr, ndev: &NetDevice, params: &LinkParams) -> Result {
+        pr_info!("newlink {} on {}\n", ibdev_name, ndev.name());
+        if !params.is_default() {
+            pr_info!("{} overrides: {}\n", ibdev_name, params);
+        }
+        let data = RustRxeData {
+            peers: rxe::SourceFilter::try_new()?,
+            counters: rxe::Counters::try_new()?,
+            pma: Mutex::new(PortCounterAging::new()),
+            mcast: rxe::McastTable::new(ndev),
+            params: *params,
+            limits: TunableLimits::new(QpLimits {
+                max_qp_wr: params.max_qp_wr.unwrap_or(QP_LIMITS.max_qp_wr),
+                ..QP_LIMITS
+            }),
+            config: rxe::RcuConfig::try_new(params.config)?,
+        };
+        let mut dev = Device::<RustRxeDev>::try_new(&THIS_MODULE, data)?;
+        dev.set_node_type(bindings::rdma_node_type_RDMA_NODE_IB_CA)
+            .set_phys_port_cnt(1)
+            .set_num_comp_vectors(1)
+            .set_sysfs_group(&ATTRS);
+        dev.set_netdev(ndev, 1)?;
+        // The device is torn down with the registration, or by `rdma link delete`.
+        dev.register(ibdev_name)
     }
-    fn udp_recv() -> Result {
-        Ok(())
+    fn udp_recv(skb: &SkBuff) -> UdpRecvVerdict {
+        let verdict = skb.dev().and_then(|ndev| {
+            DeviceRef::<RustRxeDev>::with_netdev(ndev, |dev| {
+                let data = dev.data();
+                if let Some(addr) = skb.source_addr() {
+                    if !data.peers.permits(addr) {
+                        return UdpRecvVerdict::Dropped;
+                    }
+                }
+                if data.config.read(|config| config.crc_mode.checks())
+                    && rxe::icrc::verify(skb).is_err()
+                {
+                    return UdpRecvVerdict::Dropped;
+                }
+                if let Ok(pkt) = skb.roce_packet() {
+                    let src_qp = pkt.deth().map_or(0, |deth| deth.src_qp());
+                    let violations = data.counters.violations();
+                    let bth = pkt.bth();
+                    if violations
+                        .check_pkey(DEFAULT_PKEY_FULL, bth.pkey(), bth.dest_qp(), src_qp)
+                        .is_err()
+                    {
+                        return UdpRecvVerdict::Dropped;
+                    }
+                }
+                // Packets to a group are for the QPs attached to it, and dropped if there is
+                // none. The sample has no QPs to hand them to.
+                if let Some(mgid) = skb.dest_addr().map(Gid::from).filter(Gid::is_multicast) {
+                    if data.mcast.for_each_member(&mgid, |_qpn| ()) == 0 {
+                        return UdpRecvVerdict::Dropped;
+                    }
+                }
+                rxe_inc!(data.counters, RcvdPkts);
+                UdpRecvVerdict::Consumed
+            })
+        });
+        // No device of ours is bound to the interface.
+        verdict.unwrap_or(UdpRecvVerdict::Refused)
+    }
+    fn port_event(ndev: &NetDevice, event: LinkEvent) {
+        pr_info!("{} settled {:?}\n", ndev.name(), event);
+    }
+    fn qp_faults() -> Option<&'static rxe::QpFaultInjector> {
+        Some(&QP_FAULTS)
     }
 }
 
@@ -37,7 +324,10 @@ impl kernel::Module for RustRxe {
         pr_info!("Rust Soft-RoCE driver sample (init)\n");
 
         Ok(RustRxe {
-            _dev: rxe::Registration::<RustRxeOps>::new_pinned(name)?,
+            _dev: rxe::Registration::<RustRxeOps>::new_pinned(
+                name,
+                rxe::Registration::<RustRxeOps>::example_config(),
+            )?,
         })
     }
 }